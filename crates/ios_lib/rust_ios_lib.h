@@ -5,15 +5,243 @@
 #include <stddef.h>
 #include <stdint.h>
 
-// Callback function type for real-time price updates
-typedef void (*PriceUpdateCallback)(const void* context);
+// Callback function type for real-time price updates. Receives a JSON object
+// {"updated":[...coins whose quote changed...],"removed":["SYM",...],"total":100}
+// as data + len (not NUL-terminated). The callee owns the buffer and must release it with
+// free_price_update, which may happen later on another thread.
+typedef void (*PriceUpdateCallback)(uint8_t* data, size_t len);
+void free_price_update(uint8_t* data, size_t len);
+
+// Original callback signature: only signals that prices changed (context is always NULL);
+// read them with get_crypto_data
+typedef void (*PriceNotifyCallback)(const void* context);
+
+// Broker to connect to instead of the one in .env.client (or the built-in default), e.g. to
+// switch between staging and production from the app. host and client_id may be NULL to keep
+// the configured ones; port 0 means 8883 with TLS and 1883 without. TLS uses the platform's root
+// certificates unless .env.client names a CA. Call before the first request; if the client is
+// already connected it reconnects to the new broker right away. Returns a COIN_CRAB_* status:
+// COIN_CRAB_INVALID_ARGUMENT for a malformed host or client id, COIN_CRAB_NOT_CONNECTED if the
+// new broker can't be reached (the settings are kept and the next request tries again).
+int32_t configure_client(const char* host, uint16_t port, bool use_tls, const char* client_id);
+
+// Releases the process-wide client, e.g. when backgrounded or on logout: unsubscribes,
+// disconnects cleanly and stops its threads (the worker threads only once no client handle
+// uses them). Any later call connects again. Blocks for up to 2s each for the disconnect and
+// the worker threads; don't call it from a callback (COIN_CRAB_INVALID_ARGUMENT).
+// COIN_CRAB_TIMEOUT if the event loop was still waiting to reconnect; it stops on its own.
+int32_t shutdown_mqtt_client(void);
+
+// App lifecycle hooks. on_app_background (from applicationDidEnterBackground) disconnects and
+// holds the connection's event loop so iOS can suspend the app without a busy socket; prices,
+// callbacks and subscribe_symbol subscriptions are kept. on_app_foreground (from
+// applicationWillEnterForeground) reconnects, resubscribes - which brings the retained prices
+// again - and asks the server for fresh listings. Neither blocks; both return COIN_CRAB_OK and
+// do nothing if no connection has been made yet. Data calls made in the background only see what
+// is already cached (or the offline copy). Unlike shutdown_mqtt_client, nothing has to be set up
+// again.
+int32_t on_app_background(void);
+int32_t on_app_foreground(void);
+
+// Network hint, e.g. from NWPathMonitor: reachable = (path.status == .satisfied). A lost
+// connection retries forever with a growing delay (MQTT_RECONNECT_MAX_DELAY_MS); when the network
+// comes back the delay starts over and the connection retries right away. While it is gone no
+// reconnect is attempted, to save battery, and data calls return the offline copy without trying
+// to connect. Doesn't block; returns COIN_CRAB_OK.
+int32_t notify_network_changed(bool reachable);
+
+// Deprecated: the functions that take no handle all work on one process-wide client.
+// Prefer the client_* functions at the end of this file.
 
 // Generic data fetching functions (used by Swift)
+// Without a broker connection both fall back to the last data saved on the device (under
+// Documents/coin-crab). get_crypto_data then returns {"success":true,"cached":true,"age_seconds":N,...}.
+// get_crypto_data results also carry "coin_count", "schema_version" and, when the server sent
+// them, "data_timestamp" (RFC 3339) and "source" (e.g. "CoinMarketCap").
+// If the server reports that fetching a series failed, get_historical_data returns its error
+// ({"success":false,"error":"..."}) right away instead of waiting out the timeout. While the
+// server's data provider is rate limiting it, the error ends with " - try again in Ns".
+// get_historical_data results carry the server's payload "schema_version" (0 from servers that
+// predate it).
 char* get_crypto_data(void);
 char* get_historical_data(const char* symbol, const char* timeframe);
 
-// Real-time callback registration
-void register_price_update_callback(PriceUpdateCallback callback);
+// The same JSON as get_crypto_data / get_historical_data, returned as ptr + len (not
+// NUL-terminated) so large listings aren't copied into a C string. Wrap it without copying,
+// e.g. Data(bytesNoCopy: ptr, count: len, deallocator: .none), and release it with
+// free_byte_buffer once done; ptr is NULL when len is 0.
+typedef struct ByteBuffer {
+    uint8_t* ptr;
+    size_t len;
+} ByteBuffer;
+ByteBuffer get_crypto_data_buffer(void);
+ByteBuffer get_historical_data_buffer(const char* symbol, const char* timeframe);
+void free_byte_buffer(ByteBuffer buffer);
+
+// One coin's quote as a CryptoCurrency JSON object, for a detail screen, instead of the whole
+// listing from get_crypto_data. Coins missing from the cached listing (e.g. outside the top
+// 100) are fetched from crypto/prices/{symbol}, blocking up to the data wait timeout; without a
+// broker connection the offline copy is used. NULL if there is no quote (see
+// get_last_error_message). Free with free_string.
+char* get_symbol_quote(const char* symbol);
+
+// The first n coins of the get_crypto_data listing, sorted highest first in Rust so list
+// screens don't sort the whole listing in Swift on every refresh. sort_key is "market_cap",
+// "percent_change_24h" or "volume_24h". Same JSON shape as get_crypto_data (including the
+// offline fallback), with "coin_count" the number returned. n of 0 or an unknown sort_key
+// gives {"success":false,...} and COIN_CRAB_INVALID_ARGUMENT. Free with free_string.
+char* get_top_coins(uint32_t n, const char* sort_key);
+
+// Non-blocking historical fetch, safe to call from the main thread. Returns immediately;
+// the callback later runs on a background thread with the same JSON get_historical_data returns.
+// The JSON string is only valid during the callback - copy it, and dispatch to the main queue for UI work.
+// Returns false (and never calls back) if an argument is NULL or invalid.
+typedef void (*HistoricalDataCallback)(void* context, const char* json);
+bool request_historical_data_async(const char* symbol, const char* timeframe,
+                                   HistoricalDataCallback callback, void* context);
+
+// For BGAppRefreshTask: connects, takes the retained latest prices (falling back to HTTP_API_URL),
+// saves them for the next launch and disconnects within max_duration_ms. Returns immediately; the
+// callback runs on a background thread with
+// {"success":true,"source":"mqtt","coin_count":100,"elapsed_ms":840,"error":null}
+// source is mqtt or http (null on failure). Call setTaskCompleted(success:) from the callback.
+// Returns false (and never calls back) if callback is NULL.
+typedef void (*BackgroundRefreshCallback)(void* context, const char* json);
+bool perform_background_refresh(uint64_t max_duration_ms, BackgroundRefreshCallback callback, void* context);
+
+// Incremental chart refresh: asks the server only for points newer than the cached series.
+// Returns false if the client is not connected; poll get_historical_data for the merged result.
+bool request_historical_update(const char* symbol, const char* timeframe);
+
+// Pull-to-refresh: asks the server to fetch prices now rather than re-reading the client cache.
+// Changed prices arrive through the price update callback. Returns false if the client is not
+// initialized or the server is rate limiting this client.
+bool request_price_refresh(void);
+
+// Real-time callback registration. Only one price callback is active; registering either
+// kind replaces the other.
+void register_price_update_callback(PriceNotifyCallback callback);
+void register_price_payload_callback(PriceUpdateCallback callback);
+
+// Per-symbol live prices (crypto/prices/{symbol}), e.g. for the coin detail view.
+// The callback receives one coin's JSON on a background thread; the string is only valid during the call.
+// Subscriptions are reference counted - pair every subscribe_symbol with an unsubscribe_symbol.
+// Both return false for a NULL/empty symbol or if the client is not initialized.
+typedef void (*SymbolPriceCallback)(const char* json);
+void register_symbol_price_callback(SymbolPriceCallback callback); // NULL clears it
+bool subscribe_symbol(const char* symbol);
+bool unsubscribe_symbol(const char* symbol);
+
+// Prefetch hints published by the server (JSON array) and idle-time cache warming.
+// warm_prefetch_cache returns the number of requests sent, or -1 if not connected.
+char* get_prefetch_hints(void);
+int32_t warm_prefetch_cache(void);
+
+// On-disk cache of the CMC symbol->id mapping and logos for cold launches.
+// Mapping entries expire after 7 days, logos after 30 days; only recently used logos are kept.
+bool cache_cmc_mapping(const char* mapping_json);
+char* get_cached_cmc_mapping(void);          // JSON object, "{}" if missing or expired
+int64_t get_cached_cmc_id(const char* symbol); // -1 if unknown
+bool cache_logo(const char* symbol, const uint8_t* data, size_t len);
+char* get_cached_logo_path(const char* symbol); // NULL if not cached
+// Loads missing logos over MQTT (comma-separated symbols) into the logo cache as they arrive.
+// Returns the number requested, 0 if all are cached, or -1 if not connected.
+int32_t request_logos(const char* symbols);
+
+// Parse-failure counters per topic kind plus the most recent failure (field path, expected type).
+// JSON object: {"parse_failures":{...},"last_failure":null,"unsupported_schema_version":null}
+// unsupported_schema_version is set once the server sends payloads in a newer schema than this
+// build reads - prompt the user to update.
+char* get_client_diagnostics(void);
+
+// Connection quality from keepalive ping and historical request round trips.
+// JSON object: {"score":82,"rating":"good","recommendation":"none","ping_rtt_ms":95.0,...}
+// score is 0-100 (null until measured); rating is good/fair/poor/offline/unknown;
+// recommendation is none/switch_network/use_http. "{}" if the client is not initialized.
+char* get_connection_quality(void);
+
+// The broker's answer to each subscription. JSON object:
+// {"subscriptions":[{"topic":"crypto/prices/latest","requested_qos":1,"status":"granted","granted_qos":1}],
+//  "pending":0,"rejected":[]}
+// status is pending/granted/rejected; a rejected topic (e.g. an ACL denial) never delivers data.
+// "{}" if the client is not initialized.
+char* get_subscription_status(void);
+
+// Client log file (Documents/debug.log), off unless LOG_FILE_ENABLED=true in .env.client.
+// It is rotated at LOG_FILE_MAX_BYTES (default 1 MB), keeping LOG_FILE_ROTATIONS old files
+// (default 3) as debug.log.1, debug.log.2, ... set_file_logging_enabled returns false if
+// logging couldn't be set up.
+bool set_file_logging_enabled(bool enabled);
+char* get_log_file_path(void); // free with free_string
+
+// Client handles. Each handle has its own broker connection (opened on first use), cache and
+// callbacks, so e.g. the app and a widget extension don't share state or contend on one lock.
+// Handles after the first in a process connect as "<MQTT_CLIENT_ID>-<n>". client_destroy
+// closes the connection once pending async requests have called back; NULL is ignored.
+// The client_* functions behave like the functions of the same name above and treat a NULL
+// handle like a client that isn't connected.
+typedef struct CoinCrabClient CoinCrabClient;
+CoinCrabClient* client_create(void);
+void client_destroy(CoinCrabClient* client);
+// configure_client for one handle; an explicit client_id is used as given for this handle
+int32_t client_configure(CoinCrabClient* client, const char* host, uint16_t port, bool use_tls, const char* client_id);
+int32_t client_on_app_background(CoinCrabClient* client);
+int32_t client_on_app_foreground(CoinCrabClient* client);
+int32_t client_notify_network_changed(CoinCrabClient* client, bool reachable);
+char* client_get_crypto_data(CoinCrabClient* client);
+char* client_get_historical_data(CoinCrabClient* client, const char* symbol, const char* timeframe);
+ByteBuffer client_get_crypto_data_buffer(CoinCrabClient* client);
+ByteBuffer client_get_historical_data_buffer(CoinCrabClient* client, const char* symbol, const char* timeframe);
+char* client_get_symbol_quote(CoinCrabClient* client, const char* symbol);
+char* client_get_top_coins(CoinCrabClient* client, uint32_t n, const char* sort_key);
+bool client_request_historical_data_async(CoinCrabClient* client, const char* symbol, const char* timeframe,
+                                          HistoricalDataCallback callback, void* context);
+bool client_request_historical_update(CoinCrabClient* client, const char* symbol, const char* timeframe);
+bool client_request_price_refresh(CoinCrabClient* client);
+void client_register_price_update_callback(CoinCrabClient* client, PriceNotifyCallback callback);
+void client_register_price_payload_callback(CoinCrabClient* client, PriceUpdateCallback callback);
+void client_register_symbol_price_callback(CoinCrabClient* client, SymbolPriceCallback callback);
+bool client_subscribe_symbol(CoinCrabClient* client, const char* symbol);
+bool client_unsubscribe_symbol(CoinCrabClient* client, const char* symbol);
+char* client_get_prefetch_hints(CoinCrabClient* client);
+int32_t client_warm_prefetch_cache(CoinCrabClient* client);
+int32_t client_request_logos(CoinCrabClient* client, const char* symbols);
+char* client_get_diagnostics(CoinCrabClient* client);
+char* client_get_connection_quality(CoinCrabClient* client);
+char* client_get_subscription_status(CoinCrabClient* client);
+
+// Server presence from the retained crypto/server/status, which the broker sets to offline
+// when the server dies. JSON object:
+// {"available":true,"status":"online","version":"0.1.0","started_at":1700000000,"status_age_seconds":42}
+// status is online/offline, or null until received; available also requires the broker connection.
+char* client_get_server_status(CoinCrabClient* client);
+
+// Status codes returned by configure_client and the int32_t client_load_* functions
+#define COIN_CRAB_OK                0
+#define COIN_CRAB_INVALID_ARGUMENT  1 // NULL or non-UTF-8 argument, or a NULL handle
+#define COIN_CRAB_NOT_CONNECTED     2 // the broker can't be reached
+#define COIN_CRAB_TIMEOUT           3 // connected, but the data didn't arrive in time
+#define COIN_CRAB_INVALID_SYMBOL    4 // empty, or contains whitespace, '/', '+' or '#'
+#define COIN_CRAB_RATE_LIMITED      5 // the server's data provider is rate limiting it; retry later
+#define COIN_CRAB_SERVER_ERROR      6 // the server answered that the request failed
+#define COIN_CRAB_PARSE_ERROR       7
+#define COIN_CRAB_INTERNAL          8 // also: the call panicked (see below)
+
+// No function unwinds a Rust panic into the caller. A call that panics returns its usual failure
+// value instead: COIN_CRAB_INTERNAL for status codes, -1 for counts, false, NULL, or the
+// {"success":false,"error":"..."} result for get_crypto_data, get_top_coins, get_historical_data
+// and their buffer variants. get_last_error_message then returns "<function> panicked: <message>".
+// Async calls still deliver a callback with the error.
+
+// Like client_get_crypto_data/client_get_historical_data, but return a status code and write the
+// JSON to *out_json (free with free_string). On failure *out_json is the offline copy saved on the
+// device ("cached":true for prices) if there is one, otherwise NULL.
+int32_t client_load_crypto_data(CoinCrabClient* client, char** out_json);
+int32_t client_load_historical_data(CoinCrabClient* client, const char* symbol, const char* timeframe,
+                                    char** out_json);
+// The message behind the last non-zero status on the calling thread, or NULL after a success.
+// Free with free_string.
+char* get_last_error_message(void);
 
 // Memory management
 void free_string(char* s);
@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+use rumqttc::{Event, Outgoing, Packet, QoS};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::globals::DEFAULT_CLIENT;
+use crate::mqtt::connection::ConnectionManager;
+use crate::offline::OfflineStore;
+use crate::runtime::shared_runtime;
+use crate::types::{ApiResponse, CryptoCurrency, PriceEnvelope};
+use shared::{CoinCrabError, CoinCrabResult, PayloadCodec};
+
+// BGAppRefreshTask support: one short-lived connection takes the retained price snapshot,
+// saves it where the next launch reads offline prices from and disconnects, all within the
+// time iOS grants the task. When the broker doesn't deliver in time the server's HTTP API
+// (HTTP_API_URL) is tried with whatever time is left.
+
+// Leaves room to save the snapshot and report back before iOS expires the task
+const SAVE_RESERVE: Duration = Duration::from_millis(250);
+const DISCONNECT_GRACE: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshSource {
+    Mqtt,
+    Http,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackgroundRefreshResult {
+    pub success: bool,
+    pub source: Option<RefreshSource>,
+    pub coin_count: usize,
+    pub elapsed_ms: u64,
+    pub error: Option<String>,
+}
+
+pub fn perform_background_refresh(max_duration: Duration) -> BackgroundRefreshResult {
+    let started = Instant::now();
+    let outcome = refresh(started + max_duration.saturating_sub(SAVE_RESERVE)).and_then(|(source, prices)| {
+        OfflineStore::default_location().store_prices(&prices, Instant::now())?;
+        Ok((source, prices.len()))
+    });
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    match outcome {
+        Ok((source, coin_count)) => {
+            debug!("Background refresh: Saved {} prices from {:?} in {}ms", coin_count, source, elapsed_ms);
+            BackgroundRefreshResult { success: true, source: Some(source), coin_count, elapsed_ms, error: None }
+        }
+        Err(e) => {
+            warn!("Background refresh: Failed after {}ms: {}", elapsed_ms, e);
+            BackgroundRefreshResult { success: false, source: None, coin_count: 0, elapsed_ms, error: Some(e.to_string()) }
+        }
+    }
+}
+
+fn refresh(deadline: Instant) -> CoinCrabResult<(RefreshSource, Vec<CryptoCurrency>)> {
+    let mut config = DEFAULT_CLIENT.config()?;
+    // Its own client id, so a foreground client using the configured one isn't disconnected
+    config.client_id = format!("{}-bg", config.client_id);
+    shared_runtime()?.block_on(async {
+        let deadline = tokio::time::Instant::from_std(deadline);
+        let mqtt_error = match tokio::time::timeout_at(deadline, fetch_over_mqtt(&config)).await {
+            Ok(Ok(prices)) => return Ok((RefreshSource::Mqtt, prices)),
+            Ok(Err(e)) => e,
+            Err(_) => CoinCrabError::Timeout("No prices from the broker within the time budget".to_string()),
+        };
+        let Some(url) = &config.http_api_url else {
+            return Err(mqtt_error);
+        };
+        debug!("Background refresh: {} - trying {}", mqtt_error, url);
+        match tokio::time::timeout_at(deadline, fetch_over_http(url)).await {
+            Ok(result) => result.map(|prices| (RefreshSource::Http, prices)),
+            Err(_) => Err(CoinCrabError::Timeout(format!("{}; HTTP fallback timed out", mqtt_error))),
+        }
+    })
+}
+
+async fn fetch_over_mqtt(config: &Config) -> CoinCrabResult<Vec<CryptoCurrency>> {
+    let (client, mut eventloop) = ConnectionManager::new(config)?.create_client()?;
+    let topic = shared::with_topic_prefix(&config.topic_prefix, &config.payload_codec.topic("crypto/prices/latest"));
+    client
+        .subscribe(&topic, QoS::AtLeastOnce)
+        .await
+        .map_err(|e| CoinCrabError::Mqtt(format!("Failed to subscribe to {}: {}", topic, e)))?;
+
+    let result = loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == topic => {
+                break decode_latest_prices(config.payload_codec, &publish.payload);
+            }
+            Ok(_) => {}
+            Err(e) => break Err(CoinCrabError::Mqtt(format!("Connection failed: {}", e))),
+        }
+    };
+
+    // Let the DISCONNECT go out rather than dropping the socket
+    if client.try_disconnect().is_ok() {
+        let _ = tokio::time::timeout(DISCONNECT_GRACE, async {
+            while !matches!(eventloop.poll().await, Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_)) {}
+        })
+        .await;
+    }
+    result
+}
+
+fn decode_latest_prices(codec: PayloadCodec, payload: &[u8]) -> CoinCrabResult<Vec<CryptoCurrency>> {
+    let json = codec.to_json(payload)?;
+    let envelope: PriceEnvelope<Vec<CryptoCurrency>> = serde_json::from_str(&json)?;
+    if envelope.is_expired() {
+        return Err(CoinCrabError::Parse(format!("Retained prices expired at {}", envelope.expires_at)));
+    }
+    if envelope.data.is_empty() {
+        return Err(CoinCrabError::Parse("Retained price snapshot is empty".to_string()));
+    }
+    Ok(envelope.data)
+}
+
+async fn fetch_over_http(base_url: &str) -> CoinCrabResult<Vec<CryptoCurrency>> {
+    let url = format!("{}/api/crypto-prices", base_url);
+    let response = reqwest::get(&url)
+        .await
+        .map_err(|e| CoinCrabError::Http(format!("Request to {} failed: {}", url, e)))?;
+    if !response.status().is_success() {
+        return Err(CoinCrabError::Http(format!("{} returned {}", url, response.status())));
+    }
+    let body: ApiResponse = response
+        .json()
+        .await
+        .map_err(|e| CoinCrabError::Parse(format!("Failed to parse prices from {}: {}", url, e)))?;
+    if body.data.is_empty() {
+        return Err(CoinCrabError::Http("Server has no prices yet".to_string()));
+    }
+    Ok(body.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_latest_prices() {
+        let coin: CryptoCurrency = serde_json::from_value(serde_json::json!({
+            "id": 1, "name": "Bitcoin", "symbol": "BTC",
+            "quote": { "USD": { "price": 50000.0, "percent_change_1h": 0.0, "percent_change_24h": 0.0,
+                "percent_change_7d": 0.0, "market_cap": 0.0, "volume_24h": 0.0, "last_updated": "2024-01-01T00:00:00Z" } }
+        }))
+        .unwrap();
+        let envelope = |expires_at: i64, data: Vec<CryptoCurrency>| {
+            serde_json::to_vec(&PriceEnvelope { expires_at, data, fetched_at: None, source: None, fx: None, schema_version: shared::PAYLOAD_SCHEMA_VERSION }).unwrap()
+        };
+        let future = chrono::Utc::now().timestamp() + 60;
+
+        let prices = decode_latest_prices(PayloadCodec::Json, &envelope(future, vec![coin.clone()])).unwrap();
+        assert_eq!(prices[0].symbol, "BTC");
+        assert!(decode_latest_prices(PayloadCodec::Json, &envelope(1, vec![coin])).is_err());
+        assert!(decode_latest_prices(PayloadCodec::Json, &envelope(future, Vec::new())).is_err());
+        assert!(decode_latest_prices(PayloadCodec::Json, b"not json").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use crate::mqtt::price_update::{free_raw_buffer, into_raw_buffer};
+
+// Owned bytes handed to C by value together with their length. Unlike a CString the payload
+// isn't NUL-terminated, so it can hold any bytes and isn't scanned or copied again on the way
+// out. Released with free_byte_buffer; an empty buffer has a NULL ptr.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ByteBuffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+
+impl ByteBuffer {
+    pub fn empty() -> Self {
+        ByteBuffer { ptr: std::ptr::null_mut(), len: 0 }
+    }
+
+    // SAFETY: the buffer must come from ByteBuffer::from and not have been freed yet
+    pub(crate) unsafe fn free(self) {
+        free_raw_buffer(self.ptr, self.len);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl From<Vec<u8>> for ByteBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            return ByteBuffer::empty();
+        }
+        let (ptr, len) = into_raw_buffer(bytes);
+        ByteBuffer { ptr, len }
+    }
+}
+
+impl From<String> for ByteBuffer {
+    fn from(json: String) -> Self {
+        ByteBuffer::from(json.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_buffer_round_trip() {
+        // NULs are fine, unlike with CString
+        let buffer = ByteBuffer::from(b"{\"a\":\0}".to_vec());
+        assert_eq!(buffer.len, 7);
+        assert_eq!(buffer.as_slice(), b"{\"a\":\0}");
+        unsafe { buffer.free() };
+
+        let empty = ByteBuffer::from(String::new());
+        assert!(empty.ptr.is_null());
+        unsafe { empty.free() };
+    }
+}
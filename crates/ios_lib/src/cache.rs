@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use shared::{CoinCrabError, CoinCrabResult};
+
+// On-disk cache for the CMC symbol→id mapping and coin logos, so a cold app launch can
+// resolve ids and render icons without any network round trips.
+// Lives under Library/Caches on iOS, which the OS may purge when storage runs low.
+
+// The mapping changes rarely (new listings); logos almost never
+pub const MAPPING_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const LOGO_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// Only the most recently used logos are kept
+pub const MAX_CACHED_LOGOS: usize = 200;
+
+const MAPPING_FILE: &str = "cmc_mapping.json";
+const LOGO_DIR: &str = "logos";
+const LOGO_INDEX_FILE: &str = "logo_index.json";
+
+// Logos arrive over MQTT and through cache_logo at the same time; updates to the index are
+// serialized so neither loses the other's fetch time
+static LOGO_INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize)]
+struct MappingFile {
+    saved_at: u64,
+    mapping: HashMap<String, u32>,
+}
+
+// When each cached logo was fetched, by symbol. A logo file's modification time is bumped on
+// every read for LRU eviction, so it can't say how old the image is.
+#[derive(Serialize, Deserialize, Default)]
+struct LogoIndex {
+    fetched_at: HashMap<String, u64>,
+}
+
+pub struct DiskCache {
+    root: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DiskCache { root: root.into() }
+    }
+
+    // Library/Caches/coin-crab inside the app sandbox on iOS, a temp directory elsewhere
+    pub fn default_location() -> Self {
+        let root = if cfg!(target_os = "ios") {
+            let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+            PathBuf::from(home).join("Library/Caches/coin-crab")
+        } else {
+            std::env::temp_dir().join("coin-crab-cache")
+        };
+        DiskCache::new(root)
+    }
+
+    pub fn store_mapping(&self, mapping: &HashMap<String, u32>) -> CoinCrabResult<()> {
+        let file = MappingFile {
+            saved_at: unix_now(),
+            mapping: mapping.clone(),
+        };
+        let json = serde_json::to_string(&file).map_err(|e| CoinCrabError::Parse(format!("Failed to serialize mapping: {}", e)))?;
+        fs::create_dir_all(&self.root).map_err(|e| CoinCrabError::Io(format!("Failed to create cache dir: {}", e)))?;
+        write_atomic(&self.root.join(MAPPING_FILE), json.as_bytes())?;
+        debug!("Cache: Stored CMC mapping with {} symbols", mapping.len());
+        Ok(())
+    }
+
+    // Returns the mapping if it was saved within the TTL
+    pub fn load_mapping(&self) -> Option<HashMap<String, u32>> {
+        let contents = fs::read_to_string(self.root.join(MAPPING_FILE)).ok()?;
+        let file: MappingFile = serde_json::from_str(&contents).ok()?;
+        if unix_now().saturating_sub(file.saved_at) > MAPPING_TTL.as_secs() {
+            debug!("Cache: CMC mapping expired");
+            return None;
+        }
+        Some(file.mapping)
+    }
+
+    pub fn lookup_cmc_id(&self, symbol: &str) -> Option<u32> {
+        self.load_mapping()?.get(&symbol.to_uppercase()).copied()
+    }
+
+    pub fn store_logo(&self, symbol: &str, bytes: &[u8]) -> CoinCrabResult<PathBuf> {
+        let path = self.logo_file(symbol).ok_or_else(|| CoinCrabError::Config(format!("Invalid symbol: {}", symbol)))?;
+        fs::create_dir_all(self.root.join(LOGO_DIR)).map_err(|e| CoinCrabError::Io(format!("Failed to create logo dir: {}", e)))?;
+        write_atomic(&path, bytes)?;
+        let _guard = LOGO_INDEX_LOCK.lock().unwrap();
+        let mut index = self.load_logo_index();
+        index.fetched_at.insert(symbol.to_uppercase(), unix_now());
+        self.prune_logos(MAX_CACHED_LOGOS, &mut index);
+        self.save_logo_index(&index);
+        Ok(path)
+    }
+
+    // Path of a logo fetched within the TTL. Reading a logo marks it as recently used.
+    pub fn logo_path(&self, symbol: &str) -> Option<PathBuf> {
+        let path = self.logo_file(symbol)?;
+        if !path.exists() {
+            return None;
+        }
+        let key = symbol.to_uppercase();
+        let _guard = LOGO_INDEX_LOCK.lock().unwrap();
+        let mut index = self.load_logo_index();
+        // Logos without a fetch time (e.g. cached before the index existed) count as expired
+        let expired = index
+            .fetched_at
+            .get(&key)
+            .is_none_or(|fetched_at| unix_now().saturating_sub(*fetched_at) > LOGO_TTL.as_secs());
+        if expired {
+            let _ = fs::remove_file(&path);
+            if index.fetched_at.remove(&key).is_some() {
+                self.save_logo_index(&index);
+            }
+            return None;
+        }
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(path)
+    }
+
+    fn load_logo_index(&self) -> LogoIndex {
+        fs::read_to_string(self.root.join(LOGO_INDEX_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_logo_index(&self, index: &LogoIndex) {
+        let result = serde_json::to_vec(index)
+            .map_err(|e| CoinCrabError::Parse(format!("Failed to serialize logo index: {}", e)))
+            .and_then(|json| write_atomic(&self.root.join(LOGO_INDEX_FILE), &json));
+        if let Err(e) = result {
+            debug!("Cache: Failed to save logo index: {}", e);
+        }
+    }
+
+    // Evict least recently used logos beyond the limit, along with their fetch times
+    fn prune_logos(&self, max: usize, index: &mut LogoIndex) {
+        let Ok(entries) = fs::read_dir(self.root.join(LOGO_DIR)) else {
+            return;
+        };
+        let mut logos: Vec<(SystemTime, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+                Some((modified, entry.path()))
+            })
+            .collect();
+        if logos.len() <= max {
+            return;
+        }
+        logos.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        for (_, path) in logos.drain(max..) {
+            if let Some(symbol) = path.file_stem() {
+                index.fetched_at.remove(&*symbol.to_string_lossy());
+            }
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    // Symbols become file names, so only plain alphanumeric symbols are accepted
+    fn logo_file(&self, symbol: &str) -> Option<PathBuf> {
+        if symbol.is_empty() || !symbol.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        Some(self.root.join(LOGO_DIR).join(format!("{}.png", symbol.to_uppercase())))
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Write via a temp file so a crash never leaves a truncated cache entry behind
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> CoinCrabResult<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes).map_err(|e| CoinCrabError::Io(format!("Failed to write {}: {}", tmp_path.display(), e)))?;
+    fs::rename(&tmp_path, path).map_err(|e| CoinCrabError::Io(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cache(name: &str) -> DiskCache {
+        let root = std::env::temp_dir().join(format!("coin-crab-cache-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        DiskCache::new(root)
+    }
+
+    #[test]
+    fn test_mapping_round_trip() {
+        let cache = test_cache("mapping");
+        assert!(cache.load_mapping().is_none());
+
+        let mut mapping = HashMap::new();
+        mapping.insert("BTC".to_string(), 1);
+        mapping.insert("ETH".to_string(), 1027);
+        cache.store_mapping(&mapping).unwrap();
+
+        assert_eq!(cache.load_mapping(), Some(mapping));
+        assert_eq!(cache.lookup_cmc_id("eth"), Some(1027));
+        assert_eq!(cache.lookup_cmc_id("DOGE"), None);
+
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn test_expired_mapping_is_ignored() {
+        let cache = test_cache("expired");
+        fs::create_dir_all(&cache.root).unwrap();
+        let stale = format!(r#"{{"saved_at":{},"mapping":{{"BTC":1}}}}"#, unix_now() - MAPPING_TTL.as_secs() - 1);
+        fs::write(cache.root.join(MAPPING_FILE), stale).unwrap();
+
+        assert!(cache.load_mapping().is_none());
+
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn test_logo_store_and_lookup() {
+        let cache = test_cache("logos");
+        assert!(cache.logo_path("BTC").is_none());
+
+        let stored = cache.store_logo("btc", b"png-bytes").unwrap();
+        let found = cache.logo_path("BTC").unwrap();
+        assert_eq!(stored, found);
+        assert_eq!(fs::read(found).unwrap(), b"png-bytes");
+
+        // Path traversal and odd symbols are rejected
+        assert!(cache.store_logo("../etc", b"x").is_err());
+        assert!(cache.logo_path("").is_none());
+
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn test_logo_expires_by_fetch_time_despite_reads() {
+        let cache = test_cache("logo-ttl");
+        cache.store_logo("BTC", b"png-bytes").unwrap();
+        assert!(cache.logo_path("BTC").is_some());
+
+        // Fetched longer ago than the TTL; the read above refreshed the file's mtime
+        let mut index = cache.load_logo_index();
+        index.fetched_at.insert("BTC".to_string(), unix_now() - LOGO_TTL.as_secs() - 1);
+        cache.save_logo_index(&index);
+        assert!(cache.logo_path("BTC").is_none());
+        assert!(!cache.root.join(LOGO_DIR).join("BTC.png").exists());
+
+        // A logo with no recorded fetch time is treated as expired too
+        cache.store_logo("ETH", b"png-bytes").unwrap();
+        fs::remove_file(cache.root.join(LOGO_INDEX_FILE)).unwrap();
+        assert!(cache.logo_path("ETH").is_none());
+
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn test_prune_keeps_most_recent_logos() {
+        let cache = test_cache("prune");
+        for (i, symbol) in ["AAA", "BBB", "CCC"].iter().enumerate() {
+            let path = cache.store_logo(symbol, b"x").unwrap();
+            let file = File::options().write(true).open(&path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(1_000 + i as u64)).unwrap();
+        }
+
+        let mut index = cache.load_logo_index();
+        cache.prune_logos(2, &mut index);
+
+        let remaining = fs::read_dir(cache.root.join(LOGO_DIR)).unwrap().count();
+        assert_eq!(remaining, 2);
+        assert!(!cache.root.join(LOGO_DIR).join("AAA.png").exists());
+        assert!(!index.fetched_at.contains_key("AAA"));
+        assert!(index.fetched_at.contains_key("CCC"));
+
+        let _ = fs::remove_dir_all(&cache.root);
+    }
+}
@@ -1,90 +1,392 @@
-use dotenv;
-use shared::debug_log;
+use shared::{CoinCrabResult, LogFileConfig, PayloadCodec};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, warn};
 
 /// Default MQTT broker host (AWS EC2)
 const DEFAULT_BROKER_HOST: &str = "100.26.107.175";
 const DEFAULT_BROKER_PORT: u16 = 1883;
+const DEFAULT_TLS_BROKER_PORT: u16 = 8883;
+const DEFAULT_DATA_WAIT_TIMEOUT_MS: u64 = 3000;
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(2);
+const DEFAULT_RECONNECT_MAX_DELAY_MS: u64 = 60_000;
+const DEFAULT_RECONNECT_JITTER: f64 = 0.2;
+// Keeps a jittered delay at least half the backoff, so it never comes out near zero
+const MAX_RECONNECT_JITTER: f64 = 0.5;
 
 pub struct Config {
     pub broker_host: String,
     pub broker_port: u16,
     pub log_level: String,
+    // Per-module levels in RUST_LOG syntax, e.g. "rumqttc=warn,rust_ios_lib::mqtt=trace"
+    pub log_filter: String,
+    // Documents/debug.log on iOS; off unless LOG_FILE_ENABLED, toggled at runtime over FFI
+    pub log_file: LogFileConfig,
+    pub topic_prefix: String,
+    pub tls: Option<TlsSettings>,
+    pub report_parse_failures: bool,
+    // How long blocking FFI calls wait for data to arrive over MQTT
+    pub data_wait_timeout: Duration,
+    // Encoding requested for the full price payloads (the server must publish it too)
+    pub payload_codec: PayloadCodec,
+    // MQTT client id, also used in this client's request topics so the server can rate limit it
+    pub client_id: String,
+    // Server base URL (e.g. http://192.168.1.10:8080) used when MQTT can't deliver prices in time
+    pub http_api_url: Option<String>,
+    pub reconnect: ReconnectPolicy,
+}
+
+// Delay before each reconnect attempt. Attempts never stop: the delay doubles from 2s up to
+// max_delay, and is spread by up to +-jitter (a fraction of it, at most half) so clients that
+// lost the broker together don't all come back in the same second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub max_delay: Duration,
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_delay: Duration::from_millis(DEFAULT_RECONNECT_MAX_DELAY_MS),
+            jitter: DEFAULT_RECONNECT_JITTER,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn next_delay(&self, attempt: u32) -> Duration {
+        self.delay(attempt, rand::random::<f64>())
+    }
+
+    // The delay for the nth failed attempt (from 1), with random in [0, 1) picking the jitter
+    fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(16);
+        let base = RECONNECT_INITIAL_DELAY.saturating_mul(1 << doublings).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, MAX_RECONNECT_JITTER);
+        base.mul_f64(1.0 + jitter * (2.0 * random - 1.0))
+    }
+}
+
+// MQTTS settings. Without a CA the platform's root certificates are used; the embedded
+// server broker also requires a client certificate/key pair signed by its CA.
+#[derive(Clone, Debug, Default)]
+pub struct TlsSettings {
+    pub ca_path: Option<PathBuf>,
+    pub client_cert_path: Option<PathBuf>,
+    pub client_key_path: Option<PathBuf>,
+}
+
+// Broker settings set from the app at runtime (configure_client), taking precedence over
+// .env.client and the defaults. None/0 keeps the loaded value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BrokerOverride {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub use_tls: bool,
+    pub client_id: Option<String>,
+}
+
+impl BrokerOverride {
+    pub fn new(host: Option<&str>, port: u16, use_tls: bool, client_id: Option<&str>) -> Result<Self, String> {
+        let host = host.map(str::trim).filter(|host| !host.is_empty());
+        if host.is_some_and(|host| host.contains(char::is_whitespace)) {
+            return Err(format!("Invalid broker host '{}'", host.unwrap_or_default()));
+        }
+        let client_id = match client_id.map(str::trim).filter(|id| !id.is_empty()) {
+            Some(id) => match sanitize_client_id(id) {
+                sanitized if sanitized.is_empty() => return Err(format!("Invalid client id '{}'", id)),
+                sanitized => Some(sanitized),
+            },
+            None => None,
+        };
+        Ok(BrokerOverride {
+            host: host.map(str::to_string),
+            port: (port != 0).then_some(port),
+            use_tls,
+            client_id,
+        })
+    }
 }
 
 impl Config {
-    pub fn load() -> Result<Self, String> {
+    // Applies settings from the app. TLS keeps any certificates from .env.client; without them
+    // the platform's root certificates are used.
+    pub fn apply(&mut self, broker: &BrokerOverride) {
+        if let Some(host) = &broker.host {
+            self.broker_host = host.clone();
+        }
+        let default_port = |tls: bool| if tls { DEFAULT_TLS_BROKER_PORT } else { DEFAULT_BROKER_PORT };
+        if broker.use_tls != self.tls.is_some() && self.broker_port == default_port(self.tls.is_some()) {
+            // The port followed the TLS setting, so it follows it here too
+            self.broker_port = default_port(broker.use_tls);
+        }
+        self.tls = match (broker.use_tls, self.tls.take()) {
+            (true, tls) => Some(tls.unwrap_or_default()),
+            (false, _) => None,
+        };
+        if let Some(port) = broker.port {
+            self.broker_port = port;
+        }
+        if let Some(client_id) = &broker.client_id {
+            self.client_id = client_id.clone();
+        }
+        debug!(broker_host = %self.broker_host, broker_port = self.broker_port, tls = broker.use_tls, "Config: Broker set by the app");
+    }
+
+    pub fn load() -> CoinCrabResult<Self> {
         // Set default logging level if not specified
         if std::env::var("LOG_LEVEL").is_err() {
             std::env::set_var("LOG_LEVEL", "DEBUG");
         }
         
         // Load .env file from iOS bundle resources
-        debug_log("Config: Attempting to load .env.client from iOS bundle...");
+        debug!("Config: Attempting to load .env.client from iOS bundle...");
         
         let env_loaded = Self::load_env_file()?;
         
+        // Logging is configured by the same .env.client, so it starts once that's loaded
+        let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "DEBUG".to_string());
+        let log_filter = std::env::var("LOG_FILTER")
+            .or_else(|_| std::env::var("RUST_LOG"))
+            .unwrap_or_default();
+        let log_file = Self::load_log_file_settings();
+        shared::init_logging(&log_level, &log_filter, &log_file);
+        
         if !env_loaded {
-            debug_log("Config: No .env.client file found, using environment variables or defaults");
+            debug!("Config: No .env.client file found, using environment variables or defaults");
         }
         
         let broker_host = std::env::var("MQTT_BROKER_HOST").unwrap_or_else(|_| {
-            debug_log("Config: MQTT_BROKER_HOST not set, using AWS EC2 default");
+            debug!("Config: MQTT_BROKER_HOST not set, using AWS EC2 default");
             DEFAULT_BROKER_HOST.to_string()
         });
         
+        let tls = Self::load_tls_settings();
+        
         let broker_port = std::env::var("MQTT_BROKER_PORT")
             .and_then(|s| s.parse().map_err(|_| std::env::VarError::NotPresent))
             .unwrap_or_else(|_| {
-                debug_log("Config: MQTT_BROKER_PORT not set, using default (1883)");
-                DEFAULT_BROKER_PORT
+                let port = if tls.is_some() { DEFAULT_TLS_BROKER_PORT } else { DEFAULT_BROKER_PORT };
+                debug!("Config: MQTT_BROKER_PORT not set, using default ({})", port);
+                port
             });
         
-        let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "DEBUG".to_string());
+        // Must match the server's MQTT_TOPIC_PREFIX when sharing a broker between environments
+        let topic_prefix = std::env::var("MQTT_TOPIC_PREFIX")
+            .map(|p| shared::normalize_topic_prefix(&p))
+            .unwrap_or_default();
+        
+        // Opt-in: forward anonymized parse-failure reports to the server's diagnostics topic
+        let report_parse_failures = std::env::var("MQTT_REPORT_PARSE_FAILURES")
+            .map(|v| v.trim().eq_ignore_ascii_case("true") || v.trim() == "1")
+            .unwrap_or(false);
+        
+        let data_wait_timeout = std::env::var("MQTT_DATA_WAIT_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_millis(DEFAULT_DATA_WAIT_TIMEOUT_MS));
+        
+        // "msgpack" needs MQTT_MSGPACK_PAYLOADS=true on the server; JSON otherwise
+        let payload_codec = std::env::var("MQTT_PAYLOAD_CODEC")
+            .ok()
+            .and_then(|v| PayloadCodec::parse(&v))
+            .unwrap_or_default();
         
-        debug_log(&format!("Config: Loaded broker_host={}, port={}, log_level={}", 
-            broker_host, broker_port, log_level));
+        // Unique per client unless pinned; clients sharing an id would disconnect each other
+        let client_id = std::env::var("MQTT_CLIENT_ID")
+            .ok()
+            .map(|id| sanitize_client_id(&id))
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(generate_client_id);
+        
+        let http_api_url = std::env::var("HTTP_API_URL")
+            .ok()
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty());
+        
+        let defaults = ReconnectPolicy::default();
+        let reconnect = ReconnectPolicy {
+            max_delay: std::env::var("MQTT_RECONNECT_MAX_DELAY_MS")
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .map(Duration::from_millis)
+                .map_or(defaults.max_delay, |delay| delay.max(RECONNECT_INITIAL_DELAY)),
+            jitter: std::env::var("MQTT_RECONNECT_JITTER")
+                .ok()
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .filter(|jitter| jitter.is_finite())
+                .map_or(defaults.jitter, |jitter| jitter.clamp(0.0, MAX_RECONNECT_JITTER)),
+        };
+        
+        debug!(%broker_host, broker_port, %log_level, %log_filter, "Config: Loaded");
         
         Ok(Config {
             broker_host,
             broker_port,
             log_level,
+            log_filter,
+            log_file,
+            topic_prefix,
+            tls,
+            report_parse_failures,
+            data_wait_timeout,
+            payload_codec,
+            client_id,
+            http_api_url,
+            reconnect,
         })
     }
     
-    fn load_env_file() -> Result<bool, String> {
+    fn load_log_file_settings() -> LogFileConfig {
+        let defaults = LogFileConfig::default();
+        fn parsed<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+        }
+        LogFileConfig {
+            enabled: std::env::var("LOG_FILE_ENABLED")
+                .map(|v| v.trim().eq_ignore_ascii_case("true") || v.trim() == "1")
+                .unwrap_or(defaults.enabled),
+            max_bytes: parsed("LOG_FILE_MAX_BYTES").unwrap_or(defaults.max_bytes),
+            rotations: parsed("LOG_FILE_ROTATIONS").unwrap_or(defaults.rotations),
+        }
+    }
+    
+    fn load_tls_settings() -> Option<TlsSettings> {
+        let enabled = std::env::var("MQTT_TLS_ENABLED")
+            .map(|v| v.trim().eq_ignore_ascii_case("true") || v.trim() == "1")
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        
+        // Relative certificate paths are resolved against the app bundle
+        let bundle_dir = std::env::current_exe().ok().and_then(|p| p.parent().map(Path::to_path_buf));
+        let path_var = |name: &str| {
+            std::env::var(name).ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| resolve_path(bundle_dir.as_deref(), v.trim()))
+        };
+        
+        let settings = TlsSettings {
+            ca_path: path_var("MQTT_TLS_CA_PATH"),
+            client_cert_path: path_var("MQTT_TLS_CLIENT_CERT_PATH"),
+            client_key_path: path_var("MQTT_TLS_CLIENT_KEY_PATH"),
+        };
+        debug!("Config: MQTT TLS enabled (ca={:?}, client_cert={:?})",
+            settings.ca_path, settings.client_cert_path);
+        Some(settings)
+    }
+    
+    fn load_env_file() -> CoinCrabResult<bool> {
         // Try to find the .env.client file in the app bundle
         if let Ok(exe_path) = std::env::current_exe() {
             if let Some(bundle_dir) = exe_path.parent() {
                 let env_file_path = bundle_dir.join(".env.client");
-                debug_log(&format!("Config: Trying bundle path: {}", env_file_path.display()));
+                debug!("Config: Trying bundle path: {}", env_file_path.display());
                 
                 if env_file_path.exists() {
-                    debug_log("Config: .env.client file found in bundle");
-                    if let Ok(_) = dotenv::from_path(&env_file_path) {
-                        debug_log("Config: .env.client loaded from app bundle successfully");
+                    debug!("Config: .env.client file found in bundle");
+                    if dotenv::from_path(&env_file_path).is_ok() {
+                        debug!("Config: .env.client loaded from app bundle successfully");
                         return Ok(true);
                     } else {
-                        debug_log("Config: Failed to load .env.client from bundle");
+                        warn!("Config: Failed to load .env.client from bundle");
                         return Ok(false);
                     }
                 } else {
-                    debug_log("Config: .env.client file not found in bundle directory");
+                    debug!("Config: .env.client file not found in bundle directory");
                 }
             } else {
-                debug_log("Config: Could not get bundle directory from executable path");
+                warn!("Config: Could not get bundle directory from executable path");
             }
         } else {
-            debug_log("Config: Could not get current executable path");
+            warn!("Config: Could not get current executable path");
             // Fallback: try current directory
-            if let Ok(_) = dotenv::from_filename(".env.client") {
-                debug_log("Config: .env.client loaded from current directory");
+            if dotenv::from_filename(".env.client").is_ok() {
+                debug!("Config: .env.client loaded from current directory");
                 return Ok(true);
             } else {
-                debug_log("Config: .env.client not found in current directory either");
+                debug!("Config: .env.client not found in current directory either");
             }
         }
         
         Ok(false)
     }
    
+}
+
+// The id becomes a topic level, so separators and wildcards are removed
+fn sanitize_client_id(id: &str) -> String {
+    id.trim().chars().filter(|c| !matches!(c, '/' | '+' | '#')).collect()
+}
+
+fn generate_client_id() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    format!("rust-ios-client-{:x}", nanos ^ (u64::from(std::process::id()) << 32))
+}
+
+fn resolve_path(base: Option<&Path>, value: &str) -> PathBuf {
+    let path = PathBuf::from(value);
+    match base {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path() {
+        let base = Path::new("/app/Bundle");
+        assert_eq!(resolve_path(Some(base), "certs/ca.pem"), PathBuf::from("/app/Bundle/certs/ca.pem"));
+        assert_eq!(resolve_path(Some(base), "/etc/ca.pem"), PathBuf::from("/etc/ca.pem"));
+        assert_eq!(resolve_path(None, "ca.pem"), PathBuf::from("ca.pem"));
+    }
+
+    #[test]
+    fn test_broker_override() {
+        let mut config = Config::load().unwrap();
+        config.tls = None;
+        config.broker_port = DEFAULT_BROKER_PORT;
+        let staging = BrokerOverride::new(Some(" staging.example.com "), 0, true, Some("app/1")).unwrap();
+        config.apply(&staging);
+        assert_eq!((config.broker_host.as_str(), config.broker_port), ("staging.example.com", DEFAULT_TLS_BROKER_PORT));
+        assert!(config.tls.is_some());
+        assert_eq!(config.client_id, "app1");
+
+        config.apply(&BrokerOverride::new(None, 1884, false, None).unwrap());
+        assert_eq!((config.broker_host.as_str(), config.broker_port), ("staging.example.com", 1884));
+        assert!(config.tls.is_none());
+
+        assert!(BrokerOverride::new(Some("bad host"), 0, false, None).is_err());
+        assert!(BrokerOverride::new(None, 0, false, Some("#/+")).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_delays() {
+        let policy = ReconnectPolicy { max_delay: Duration::from_secs(30), jitter: 0.2 };
+        // random = 0.5 is the middle of the jitter range
+        let delays: Vec<u64> = (1..=6).map(|attempt| policy.delay(attempt, 0.5).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.delay(1000, 0.5), Duration::from_secs(30));
+        assert_eq!(policy.delay(5, 0.0), Duration::from_secs(24));
+        assert_eq!(policy.delay(5, 1.0), Duration::from_secs(36));
+
+        // Jitter past the cap can't take the delay near zero
+        let policy = ReconnectPolicy { max_delay: Duration::from_secs(30), jitter: 1.0 };
+        assert_eq!(policy.delay(5, 0.0), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn test_client_ids() {
+        assert_eq!(sanitize_client_id(" app/+#1 "), "app1");
+        assert!(generate_client_id().starts_with("rust-ios-client-"));
+    }
 }
\ No newline at end of file
@@ -1,209 +1,843 @@
+// FFI entry points take raw C pointers by design; null checks are done explicitly
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::runtime::Runtime;
+use tracing::{debug, instrument, warn};
+
+use crate::background;
+use crate::buffer::ByteBuffer;
+use crate::cache::DiskCache;
+use crate::config::{BrokerOverride, Config};
+use crate::offline::OfflineStore;
+use crate::runtime::{release_shared_runtime, shared_runtime};
+use crate::globals::DEFAULT_CLIENT;
+use crate::handle::CoinCrabClient;
+use crate::status::{self, check_symbol, FfiError, FfiStatus};
+use crate::ranking::{top_coins, SortKey};
+use crate::mqtt::{MQTTClient, client::{PriceListener, PriceNotifyCallback, PriceUpdateCallback}, price_update::free_raw_buffer, subscriptions::SymbolPriceCallback};
+use crate::types::{CryptoClientResult, HistoricalDataResult, RESULT_SCHEMA_VERSION};
+use shared::CoinCrabError;
 
-use crate::globals::MQTT_CLIENT;
-use crate::mqtt::{MQTTClient, client::PriceUpdateCallback};
-use crate::types::{CryptoClientResult, HistoricalDataResult};
-use shared::debug_log;
+// How long a freshly connected client waits for a retained historical series before requesting it
+const RETAINED_DATA_GRACE: Duration = Duration::from_millis(500);
+// How long shutdown_mqtt_client waits for the disconnect, and then for the worker threads
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
+// Every client_* function takes a handle from client_create. The functions without a handle
+// are deprecated and work on one process-wide client instead.
+
+// Creates a client handle. Its broker connection is opened on first use; release it with
+// client_destroy.
 #[no_mangle]
-pub extern "C" fn free_string(s: *mut c_char) {
-    unsafe {
-        if s.is_null() {
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_create"))]
+pub extern "C" fn client_create() -> *mut CoinCrabClient {
+    status::catch_panic("client_create", || Arc::into_raw(Arc::new(CoinCrabClient::new())) as *mut CoinCrabClient)
+}
+
+// Releases a handle from client_create; safe to call with NULL. The connection is closed once
+// async requests still running on the handle have delivered their callbacks.
+#[no_mangle]
+pub extern "C" fn client_destroy(client: *mut CoinCrabClient) {
+    status::catch_panic("client_destroy", || {
+        if client.is_null() {
             return;
         }
-        let _ = CString::from_raw(s);
+        drop(unsafe { Arc::from_raw(client) });
+    })
+}
+
+// Points the client at another broker, e.g. staging instead of production, overriding
+// .env.client. host and client_id may be NULL (keep the configured one), port 0 picks the
+// default for use_tls. Call before the first data request, or at any time to reconnect to the
+// new broker. Returns a COIN_CRAB_* status code.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "configure_client"))]
+pub extern "C" fn configure_client(host: *const c_char, port: u16, use_tls: bool, client_id: *const c_char) -> i32 {
+    status::catch_panic_or("configure_client", |error| error.status as i32, || {
+        configure(&DEFAULT_CLIENT, host, port, use_tls, client_id)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_configure"))]
+pub extern "C" fn client_configure(client: *mut CoinCrabClient, host: *const c_char, port: u16, use_tls: bool, client_id: *const c_char) -> i32 {
+    status::catch_panic_or("client_configure", |error| error.status as i32, || {
+        match client_arg(client) {
+            Some(client) => configure(client, host, port, use_tls, client_id),
+            None => status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle"))),
+        }
+    })
+}
+
+fn configure(handle: &CoinCrabClient, host: *const c_char, port: u16, use_tls: bool, client_id: *const c_char) -> i32 {
+    let result = configure_broker(handle, host, port, use_tls, client_id);
+    status::record(result.as_ref().map(|_| ()))
+}
+
+fn configure_broker(handle: &CoinCrabClient, host: *const c_char, port: u16, use_tls: bool, client_id: *const c_char) -> Result<(), FfiError> {
+    let optional_arg = |value: *const c_char, name: &str| {
+        if value.is_null() {
+            return Ok(None);
+        }
+        c_str_arg(value).map(Some).ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, format!("Invalid {} string", name)))
+    };
+    let (host, client_id) = (optional_arg(host, "host")?, optional_arg(client_id, "client id")?);
+    let broker = BrokerOverride::new(host, port, use_tls, client_id).map_err(|e| FfiError::new(FfiStatus::InvalidArgument, e))?;
+    handle.configure(broker).map_err(|e| {
+        warn!("configure_client: {}: {}", connect_error(&e), e);
+        FfiError::new(FfiStatus::from_error(&e), format!("{}: {}", connect_error(&e), e))
+    })
+}
+
+// Tears down the process-wide client when the app is backgrounded or the user logs out:
+// unsubscribes, disconnects cleanly, waits for the event loop to stop and releases the worker
+// threads unless client handles are still using them. Any later call connects again. Blocks for
+// up to SHUTDOWN_TIMEOUT; callbacks should not call it. Returns a COIN_CRAB_* status code.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "shutdown_mqtt_client"))]
+pub extern "C" fn shutdown_mqtt_client() -> i32 {
+    status::catch_panic_or("shutdown_mqtt_client", |error| error.status as i32, || {
+        // Blocking on the runtime from one of its own threads would panic
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "shutdown_mqtt_client can't be called from a callback")));
+        }
+        if !DEFAULT_CLIENT.shutdown(SHUTDOWN_TIMEOUT) {
+            return status::record(Err(&FfiError::new(FfiStatus::Timeout, "The event loop didn't stop in time; it stops on its own shortly")));
+        }
+        if !release_shared_runtime(SHUTDOWN_TIMEOUT) {
+            debug!("shutdown_mqtt_client: Runtime still used by client handles");
+        }
+        status::record(Ok(()))
+    })
+}
+
+// Call from applicationDidEnterBackground / sceneDidEnterBackground. Disconnects and holds the
+// event loop, keeping the caches, callbacks and symbol subscriptions, so iOS can suspend the app
+// without a socket kept busy. Doesn't block.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "on_app_background"))]
+pub extern "C" fn on_app_background() -> i32 {
+    status::catch_panic_or("on_app_background", |error| error.status as i32, || {
+        DEFAULT_CLIENT.background();
+        status::record(Ok(()))
+    })
+}
+
+// Call from applicationWillEnterForeground. Reconnects in the background, resubscribes (which
+// brings the retained prices again) and asks the server for fresh listings.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "on_app_foreground"))]
+pub extern "C" fn on_app_foreground() -> i32 {
+    status::catch_panic_or("on_app_foreground", |error| error.status as i32, || {
+        DEFAULT_CLIENT.foreground();
+        status::record(Ok(()))
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_on_app_background"))]
+pub extern "C" fn client_on_app_background(client: *mut CoinCrabClient) -> i32 {
+    status::catch_panic_or("client_on_app_background", |error| error.status as i32, || match client_arg(client) {
+        Some(client) => {
+            client.background();
+            status::record(Ok(()))
+        }
+        None => status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle"))),
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_on_app_foreground"))]
+pub extern "C" fn client_on_app_foreground(client: *mut CoinCrabClient) -> i32 {
+    status::catch_panic_or("client_on_app_foreground", |error| error.status as i32, || match client_arg(client) {
+        Some(client) => {
+            client.foreground();
+            status::record(Ok(()))
+        }
+        None => status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle"))),
+    })
+}
+
+// Call from an NWPathMonitor update handler with path.status == .satisfied. When the network
+// comes back the connection retries right away instead of waiting out its backoff; while it is
+// gone no reconnect is attempted and data calls go straight to the offline copy. Doesn't block.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "notify_network_changed"))]
+pub extern "C" fn notify_network_changed(reachable: bool) -> i32 {
+    status::catch_panic_or("notify_network_changed", |error| error.status as i32, || {
+        DEFAULT_CLIENT.network_changed(reachable);
+        status::record(Ok(()))
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_notify_network_changed"))]
+pub extern "C" fn client_notify_network_changed(client: *mut CoinCrabClient, reachable: bool) -> i32 {
+    status::catch_panic_or("client_notify_network_changed", |error| error.status as i32, || match client_arg(client) {
+        Some(client) => {
+            client.network_changed(reachable);
+            status::record(Ok(()))
+        }
+        None => status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle"))),
+    })
+}
+
+// The handle behind a client_* argument; None for NULL
+fn client_arg<'a>(client: *mut CoinCrabClient) -> Option<&'a CoinCrabClient> {
+    unsafe { client.as_ref() }
+}
+
+// A reference to the handle of its own, for work that outlives the call
+fn client_arc(client: *mut CoinCrabClient) -> Option<Arc<CoinCrabClient>> {
+    if client.is_null() {
+        return None;
+    }
+    unsafe {
+        Arc::increment_strong_count(client);
+        Some(Arc::from_raw(client))
+    }
+}
+
+// The handle's connection, if one has been made
+fn current_client(client: *mut CoinCrabClient) -> Option<Arc<MQTTClient>> {
+    client_arg(client).and_then(CoinCrabClient::current)
+}
+
+// What a caller is told when a handle can't get a connection
+fn connect_error(e: &CoinCrabError) -> &'static str {
+    match e {
+        CoinCrabError::Timeout(_) => "Failed to connect to MQTT broker",
+        _ => "Failed to initialize MQTT client",
     }
 }
 
-// Simplified single function for Swift to get crypto data
 #[no_mangle]
-pub extern "C" fn get_crypto_data() -> *mut c_char {
-    debug_log("get_crypto_data: Starting data fetch using MQTT");
-    
-    // Initialize MQTT client if needed (but only once)
-    {
-        let client_exists = MQTT_CLIENT.lock().unwrap().is_some();
-        if !client_exists {
-            debug_log("get_crypto_data: MQTT client not initialized, creating new client...");
-            match MQTTClient::new() {
-                Ok(client) => {
-                    debug_log("get_crypto_data: MQTT client created successfully");
-                    if let Err(e) = client.connect() {
-                        debug_log(&format!("get_crypto_data: Failed to connect to MQTT broker: {}", e));
-                        return return_mqtt_error("Failed to connect to MQTT broker");
-                    }
-                    *MQTT_CLIENT.lock().unwrap() = Some(client);
-                }
-                Err(e) => {
-                    debug_log(&format!("get_crypto_data: Failed to initialize MQTT client: {}", e));
-                    return return_mqtt_error("Failed to initialize MQTT client");
-                }
+pub extern "C" fn free_byte_buffer(buffer: ByteBuffer) {
+    status::catch_panic("free_byte_buffer", || {
+        // SAFETY: buffers only come from the *_buffer functions, which build them with ByteBuffer::from
+        unsafe { buffer.free() };
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_string(s: *mut c_char) {
+    status::catch_panic("free_string", || {
+        unsafe {
+            if s.is_null() {
+                return;
             }
-            
-            // Connection is now verified in connect() method, wait briefly for data to arrive
-            debug_log("get_crypto_data: Connection established, waiting for data...");
+            let _ = CString::from_raw(s);
+        }
+    })
+}
+
+// One coin's CryptoCurrency JSON for a detail screen, without the whole listing. Coins missing
+// from the cached listing are fetched from their own topic; without a broker connection the
+// offline copy is used. NULL if there is no quote; get_last_error_message says why.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_symbol_quote"))]
+pub extern "C" fn get_symbol_quote(symbol: *const c_char) -> *mut c_char {
+    status::catch_panic("get_symbol_quote", || symbol_quote(&DEFAULT_CLIENT, symbol))
+}
 
-            // Brief wait for retained messages to arrive
-            std::thread::sleep(Duration::from_millis(200));
-        } else {
-            debug_log("get_crypto_data: Using existing MQTT client");
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_symbol_quote"))]
+pub extern "C" fn client_get_symbol_quote(client: *mut CoinCrabClient, symbol: *const c_char) -> *mut c_char {
+    status::catch_panic("client_get_symbol_quote", || {
+        match client_arg(client) {
+            Some(client) => symbol_quote(client, symbol),
+            None => {
+                status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle")));
+                std::ptr::null_mut()
+            }
         }
+    })
+}
+
+fn symbol_quote(handle: &CoinCrabClient, symbol: *const c_char) -> *mut c_char {
+    let result = c_str_arg(symbol)
+        .ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, "Invalid symbol string"))
+        .and_then(check_symbol)
+        .and_then(|symbol| load_symbol_quote(handle, symbol));
+    status::record(result.as_ref().map(|_| ()));
+    match result {
+        Ok(json) => CString::new(json).map_or(std::ptr::null_mut(), CString::into_raw),
+        Err(_) => std::ptr::null_mut(),
     }
-    
-    // Try to get latest prices from MQTT client
-    if let Some(ref client) = *MQTT_CLIENT.lock().unwrap() {
-        if let Some(prices) = client.get_latest_prices() {
-            debug_log(&format!("get_crypto_data: Successfully got {} cryptocurrencies via MQTT", prices.len()));
-            
-            let result = CryptoClientResult {
-                success: true,
-                data: Some(prices),
-                error: None,
-                last_updated: Some(chrono::Utc::now().to_rfc3339()),
-                cached: true,
-            };
-            
-            match serde_json::to_string(&result) {
-                Ok(json) => {
-                    debug_log(&format!("get_crypto_data: Successfully returning {} bytes via MQTT", json.len()));
-                    return CString::new(json).unwrap().into_raw();
-                }
-                Err(e) => {
-                    debug_log(&format!("get_crypto_data: MQTT serialization error: {}", e));
-                }
+}
+
+fn load_symbol_quote(handle: &CoinCrabClient, symbol: &str) -> Result<String, FfiError> {
+    let error = match handle.connected(false) {
+        Ok((client, _)) => match client.get_symbol_quote(symbol) {
+            Ok(Some(quote)) => {
+                return serde_json::to_string(&quote)
+                    .map_err(|e| FfiError::new(FfiStatus::ParseError, format!("Failed to serialize quote: {}", e)));
             }
-        } else {
-            debug_log("get_crypto_data: MQTT client has no cached data");
+            Ok(None) => FfiError::new(FfiStatus::Timeout, format!("No quote for {} within {:?}", symbol.to_uppercase(), client.data_waiter().timeout())),
+            Err(e) => FfiError::new(FfiStatus::from_error(&e), e.to_string()),
+        },
+        Err(e) => {
+            warn!("get_symbol_quote: {}: {}", connect_error(&e), e);
+            FfiError::new(FfiStatus::NotConnected, connect_error(&e))
         }
-    } else {
-        debug_log("get_crypto_data: MQTT client not available");
+    };
+    let offline = OfflineStore::default_location()
+        .load_prices()
+        .and_then(|snapshot| snapshot.data.into_iter().find(|coin| coin.symbol.eq_ignore_ascii_case(symbol)));
+    match offline {
+        Some(quote) => {
+            debug!("get_symbol_quote: {} - returning the offline quote", error.message);
+            serde_json::to_string(&quote).map_err(|_| error)
+        }
+        None => Err(error),
     }
-    
-    debug_log("get_crypto_data: MQTT data not available");
-    return_mqtt_error("MQTT connection failed or no data available")
 }
 
-// Generic historical data function (no MQTT reference in name)
+// Simplified single function for Swift to get crypto data
 #[no_mangle]
-pub extern "C" fn get_historical_data(symbol: *const c_char, timeframe: *const c_char) -> *mut c_char {
-    debug_log("get_historical_data: Starting historical data fetch");
-    
-    let symbol_str = unsafe {
-        match CStr::from_ptr(symbol).to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                debug_log("get_historical_data: Invalid symbol string");
-                return CString::new("{\"success\":false,\"error\":\"Invalid symbol\",\"data\":[]}").unwrap().into_raw();
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_crypto_data"))]
+pub extern "C" fn get_crypto_data() -> *mut c_char {
+    status::catch_panic_or("get_crypto_data", |error| return_mqtt_error(&error.message), || {
+        crypto_data(&DEFAULT_CLIENT)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_crypto_data"))]
+pub extern "C" fn client_get_crypto_data(client: *mut CoinCrabClient) -> *mut c_char {
+    status::catch_panic_or("client_get_crypto_data", |error| return_mqtt_error(&error.message), || {
+        into_c_string(client_crypto_data_json(client))
+    })
+}
+
+// get_crypto_data as a length-prefixed buffer instead of a C string; free with free_byte_buffer
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_crypto_data_buffer"))]
+pub extern "C" fn get_crypto_data_buffer() -> ByteBuffer {
+    status::catch_panic_or("get_crypto_data_buffer", |error| ByteBuffer::from(mqtt_error_json(&error.message)), || {
+        ByteBuffer::from(crypto_data_json(&DEFAULT_CLIENT))
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_crypto_data_buffer"))]
+pub extern "C" fn client_get_crypto_data_buffer(client: *mut CoinCrabClient) -> ByteBuffer {
+    status::catch_panic_or("client_get_crypto_data_buffer", |error| ByteBuffer::from(mqtt_error_json(&error.message)), || {
+        ByteBuffer::from(client_crypto_data_json(client))
+    })
+}
+
+fn crypto_data(handle: &CoinCrabClient) -> *mut c_char {
+    into_c_string(crypto_data_json(handle))
+}
+
+fn client_crypto_data_json(client: *mut CoinCrabClient) -> String {
+    match client_arg(client) {
+        Some(client) => crypto_data_json(client),
+        None => mqtt_error_json("Invalid client handle"),
+    }
+}
+
+fn crypto_data_json(handle: &CoinCrabClient) -> String {
+    match load_crypto_data(handle, None) {
+        Ok(json) => json,
+        Err(error) => offline_prices_or_error(&error.message, None),
+    }
+}
+
+// Like get_crypto_data, but only the first n coins sorted (highest first) by sort_key:
+// "market_cap", "percent_change_24h" or "volume_24h". coin_count is the number returned.
+// Invalid arguments give an error result and COIN_CRAB_INVALID_ARGUMENT from get_last_error_message.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_top_coins"))]
+pub extern "C" fn get_top_coins(n: u32, sort_key: *const c_char) -> *mut c_char {
+    status::catch_panic_or("get_top_coins", |error| return_mqtt_error(&error.message), || {
+        top_coins_data(&DEFAULT_CLIENT, n, sort_key)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_top_coins"))]
+pub extern "C" fn client_get_top_coins(client: *mut CoinCrabClient, n: u32, sort_key: *const c_char) -> *mut c_char {
+    status::catch_panic_or("client_get_top_coins", |error| return_mqtt_error(&error.message), || {
+        match client_arg(client) {
+            Some(client) => top_coins_data(client, n, sort_key),
+            None => {
+                status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle")));
+                return_mqtt_error("Invalid client handle")
             }
         }
+    })
+}
+
+fn top_coins_data(handle: &CoinCrabClient, n: u32, sort_key: *const c_char) -> *mut c_char {
+    let top = match top_coins_args(n, sort_key) {
+        Ok(top) => top,
+        Err(error) => {
+            status::record(Err(&error));
+            return return_mqtt_error(&error.message);
+        }
     };
+    let result = load_crypto_data(handle, Some(top));
+    status::record(result.as_ref().map(|_| ()));
+    match result {
+        Ok(json) => into_c_string(json),
+        Err(error) => into_c_string(offline_prices_or_error(&error.message, Some(top))),
+    }
+}
+
+fn top_coins_args(n: u32, sort_key: *const c_char) -> Result<(usize, SortKey), FfiError> {
+    if n == 0 {
+        return Err(FfiError::new(FfiStatus::InvalidArgument, "n must be at least 1"));
+    }
+    let sort_key = c_str_arg(sort_key).ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, "Invalid sort key string"))?;
+    let key = SortKey::parse(sort_key)
+        .ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, format!("Unknown sort key '{}'", sort_key)))?;
+    Ok((n as usize, key))
+}
+
+// The latest prices as JSON from the broker, optionally only the top n by a key; failures
+// don't fall back to the offline copy
+fn load_crypto_data(handle: &CoinCrabClient, top: Option<(usize, SortKey)>) -> Result<String, FfiError> {
+    debug!("get_crypto_data: Starting data fetch using MQTT");
     
-    let timeframe_str = unsafe {
-        match CStr::from_ptr(timeframe).to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                debug_log("get_historical_data: Invalid timeframe string");
-                return CString::new("{\"success\":false,\"error\":\"Invalid timeframe\",\"data\":[]}").unwrap().into_raw();
-            }
+    // Connect if needed (but only once)
+    let client = match handle.connected(false) {
+        Ok((client, _)) => client,
+        Err(e) => {
+            warn!("get_crypto_data: {}: {}", connect_error(&e), e);
+            return Err(FfiError::new(FfiStatus::NotConnected, connect_error(&e)));
         }
     };
     
-    debug_log(&format!("get_historical_data: Fetching {} {} via MQTT", symbol_str, timeframe_str));
-    
-    // Initialize MQTT client if needed
-    let is_connected = if let Some(ref client) = *MQTT_CLIENT.lock().unwrap() {
-        client.is_connected()
-    } else {
-        false
+    // Wait until the retained prices have been cached
+    let waiter = client.data_waiter();
+    let prices = match top {
+        Some((n, key)) => waiter.wait_for_top_coins(n, key),
+        None => waiter.wait_for_latest_prices(),
     };
-    
-    if !is_connected {
-        debug_log("get_historical_data: MQTT not connected, initializing...");
-        match MQTTClient::new() {
-            Ok(client) => {
-                debug_log("get_historical_data: MQTT client created successfully");
-                if let Err(e) = client.connect() {
-                    debug_log(&format!("get_historical_data: Failed to connect to MQTT broker: {}", e));
-                    return CString::new("{\"success\":false,\"error\":\"Failed to connect to MQTT broker\",\"data\":[]}").unwrap().into_raw();
-                }
-                *MQTT_CLIENT.lock().unwrap() = Some(client);
-            }
-            Err(e) => {
-                debug_log(&format!("get_historical_data: Failed to initialize MQTT client: {}", e));
-                return CString::new("{\"success\":false,\"error\":\"Failed to initialize MQTT client\",\"data\":[]}").unwrap().into_raw();
-            }
+    let Some(prices) = prices else {
+        debug!("get_crypto_data: No prices received within {:?}", waiter.timeout());
+        return Err(FfiError::new(FfiStatus::Timeout, "MQTT connection failed or no data available"));
+    };
+    debug!("get_crypto_data: Successfully got {} cryptocurrencies via MQTT", prices.len());
+    let source = client.get_price_source();
+
+    let result = CryptoClientResult {
+        success: true,
+        coin_count: prices.len(),
+        data: Some(prices),
+        error: None,
+        last_updated: Some(chrono::Utc::now().to_rfc3339()),
+        cached: false,
+        age_seconds: None,
+        data_timestamp: source.fetched_at.and_then(|at| chrono::DateTime::from_timestamp(at, 0)).map(|time| time.to_rfc3339()),
+        source: source.provider,
+        schema_version: RESULT_SCHEMA_VERSION,
+    };
+    serde_json::to_string(&result).map_err(|e| {
+        warn!("get_crypto_data: MQTT serialization error: {}", e);
+        FfiError::new(FfiStatus::ParseError, format!("Failed to serialize prices: {}", e))
+    })
+}
+
+// Without a broker connection the last prices saved on the device are returned instead of the
+// error, flagged as cached with their age
+fn offline_prices_or_error(error_msg: &str, top: Option<(usize, SortKey)>) -> String {
+    offline_prices_json(error_msg, top).unwrap_or_else(|| mqtt_error_json(error_msg))
+}
+
+fn offline_prices_json(error_msg: &str, top: Option<(usize, SortKey)>) -> Option<String> {
+    let snapshot = OfflineStore::default_location().load_prices()?;
+    let age_seconds = snapshot.age_seconds();
+    let data = match top {
+        Some((n, key)) => top_coins(&snapshot.data, n, key),
+        None => snapshot.data,
+    };
+    debug!("get_crypto_data: {} - returning {} offline prices from {}s ago",
+        error_msg, data.len(), age_seconds);
+    let last_updated = chrono::DateTime::from_timestamp(snapshot.saved_at as i64, 0).map(|time| time.to_rfc3339());
+    let result = CryptoClientResult {
+        success: true,
+        age_seconds: Some(age_seconds),
+        coin_count: data.len(),
+        data: Some(data),
+        error: None,
+        data_timestamp: last_updated.clone(),
+        last_updated,
+        cached: true,
+        source: None,
+        schema_version: RESULT_SCHEMA_VERSION,
+    };
+    serde_json::to_string(&result).ok()
+}
+
+// The last series saved on the device, for when the broker can't provide it
+fn offline_series_json(symbol: &str, timeframe: &str) -> Option<String> {
+    let snapshot = OfflineStore::default_location().load_historical(symbol, timeframe)?;
+    debug!("load_historical_json: Returning offline {} {} from {}s ago", symbol, timeframe, snapshot.age_seconds());
+    serde_json::to_string(&snapshot.data).ok()
+}
+
+// Generic historical data function (no MQTT reference in name)
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_historical_data"))]
+pub extern "C" fn get_historical_data(symbol: *const c_char, timeframe: *const c_char) -> *mut c_char {
+    status::catch_panic_or("get_historical_data", |error| into_c_string(historical_panic_json(error)), || {
+        historical_data(&DEFAULT_CLIENT, symbol, timeframe)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_historical_data"))]
+pub extern "C" fn client_get_historical_data(client: *mut CoinCrabClient, symbol: *const c_char, timeframe: *const c_char) -> *mut c_char {
+    status::catch_panic_or("client_get_historical_data", |error| into_c_string(historical_panic_json(error)), || {
+        into_c_string(client_historical_data_json(client, symbol, timeframe))
+    })
+}
+
+// get_historical_data as a length-prefixed buffer instead of a C string; free with free_byte_buffer
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_historical_data_buffer"))]
+pub extern "C" fn get_historical_data_buffer(symbol: *const c_char, timeframe: *const c_char) -> ByteBuffer {
+    status::catch_panic_or("get_historical_data_buffer", |error| ByteBuffer::from(historical_panic_json(error)), || {
+        ByteBuffer::from(historical_data_json(&DEFAULT_CLIENT, symbol, timeframe))
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_historical_data_buffer"))]
+pub extern "C" fn client_get_historical_data_buffer(client: *mut CoinCrabClient, symbol: *const c_char, timeframe: *const c_char) -> ByteBuffer {
+    status::catch_panic_or("client_get_historical_data_buffer", |error| ByteBuffer::from(historical_panic_json(error)), || {
+        ByteBuffer::from(client_historical_data_json(client, symbol, timeframe))
+    })
+}
+
+fn historical_data(handle: &CoinCrabClient, symbol: *const c_char, timeframe: *const c_char) -> *mut c_char {
+    into_c_string(historical_data_json(handle, symbol, timeframe))
+}
+
+fn client_historical_data_json(client: *mut CoinCrabClient, symbol: *const c_char, timeframe: *const c_char) -> String {
+    match client_arg(client) {
+        Some(client) => historical_data_json(client, symbol, timeframe),
+        None => "{\"success\":false,\"error\":\"Invalid client handle\",\"data\":[]}".to_string(),
+    }
+}
+
+fn historical_data_json(handle: &CoinCrabClient, symbol: *const c_char, timeframe: *const c_char) -> String {
+    debug!("get_historical_data: Starting historical data fetch");
+
+    let Some(symbol_str) = c_str_arg(symbol) else {
+        warn!("get_historical_data: Invalid symbol string");
+        return "{\"success\":false,\"error\":\"Invalid symbol\",\"data\":[]}".to_string();
+    };
+    let Some(timeframe_str) = c_str_arg(timeframe) else {
+        warn!("get_historical_data: Invalid timeframe string");
+        return "{\"success\":false,\"error\":\"Invalid timeframe\",\"data\":[]}".to_string();
+    };
+
+    load_historical_json(handle, symbol_str, timeframe_str)
+}
+
+// Status-code variants of client_get_crypto_data and client_get_historical_data. They return a
+// COIN_CRAB_* code and write the JSON to *out_json (free with free_string). On failure *out_json
+// holds the offline copy if there is one, otherwise NULL; get_last_error_message has the detail.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_load_crypto_data"))]
+pub extern "C" fn client_load_crypto_data(client: *mut CoinCrabClient, out_json: *mut *mut c_char) -> i32 {
+    status::catch_panic_or("client_load_crypto_data", |error| error.status as i32, || {
+        if out_json.is_null() {
+            return status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "out_json is NULL")));
         }
-        
-        // Give MQTT time to connect and receive data
-        debug_log("get_historical_data: Waiting for MQTT connection and data...");
-        std::thread::sleep(Duration::from_millis(1000));
+        let result = client_arg(client)
+            .ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle"))
+            .and_then(|handle| load_crypto_data(handle, None));
+        deliver(out_json, result, || offline_prices_json("Prices unavailable", None))
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_load_historical_data"))]
+pub extern "C" fn client_load_historical_data(
+    client: *mut CoinCrabClient,
+    symbol: *const c_char,
+    timeframe: *const c_char,
+    out_json: *mut *mut c_char,
+) -> i32 {
+    status::catch_panic_or("client_load_historical_data", |error| error.status as i32, || {
+        if out_json.is_null() {
+            return status::record(Err(&FfiError::new(FfiStatus::InvalidArgument, "out_json is NULL")));
+        }
+        let args = historical_args(client, symbol, timeframe);
+        let fallback = args.as_ref().ok().map(|(_, symbol, timeframe)| (symbol.to_string(), timeframe.to_string()));
+        let result = args.and_then(|(handle, symbol, timeframe)| {
+            let hist_data = load_historical(handle, symbol, timeframe)?;
+            serde_json::to_string(&hist_data)
+                .map_err(|e| FfiError::new(FfiStatus::ParseError, format!("Failed to serialize series: {}", e)))
+        });
+        deliver(out_json, result, || fallback.and_then(|(symbol, timeframe)| offline_series_json(&symbol, &timeframe)))
+    })
+}
+
+fn historical_args<'a>(
+    client: *mut CoinCrabClient,
+    symbol: *const c_char,
+    timeframe: *const c_char,
+) -> Result<(&'a CoinCrabClient, &'a str, &'a str), FfiError> {
+    let handle = client_arg(client).ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, "Invalid client handle"))?;
+    let symbol = c_str_arg(symbol).ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, "Invalid symbol string"))?;
+    let timeframe = c_str_arg(timeframe).ok_or_else(|| FfiError::new(FfiStatus::InvalidArgument, "Invalid timeframe string"))?;
+    if timeframe.is_empty() || timeframe.contains(['/', '+', '#']) {
+        return Err(FfiError::new(FfiStatus::InvalidArgument, format!("Invalid timeframe '{}'", timeframe)));
     }
-    
-    // Try to get historical data from MQTT client
-    if let Some(ref client) = *MQTT_CLIENT.lock().unwrap() {
-        if let Some(hist_data) = client.get_historical_data(symbol_str, timeframe_str) {
-            debug_log(&format!("get_historical_data: Successfully got {} data points via MQTT", hist_data.data.len()));
-            let json = serde_json::to_string(&hist_data).unwrap();
-            return CString::new(json).unwrap().into_raw();
-        } else {
-            debug_log("get_historical_data: MQTT client has no cached historical data");
+    Ok((handle, check_symbol(symbol)?, timeframe))
+}
+
+// Writes the payload (or the fallback on failure) to *out_json and records the status
+fn deliver(out_json: *mut *mut c_char, result: Result<String, FfiError>, fallback: impl FnOnce() -> Option<String>) -> i32 {
+    let (json, code) = match result {
+        Ok(json) => (Some(json), status::record(Ok(()))),
+        Err(error) => {
+            let code = status::record(Err(&error));
+            // Malformed arguments have nothing to fall back to
+            let json = if error.status == FfiStatus::InvalidArgument { None } else { fallback() };
+            (json, code)
         }
-    } else {
-        debug_log("get_historical_data: MQTT client not available");
+    };
+    let raw = json.and_then(|json| CString::new(json).ok()).map_or(std::ptr::null_mut(), CString::into_raw);
+    unsafe { *out_json = raw };
+    code
+}
+
+// Receives the caller's context and the JSON result; the string is only valid during the call
+pub type HistoricalDataCallback = extern "C" fn(*mut c_void, *const c_char);
+
+// Caller-owned context pointer, handed back untouched to the callback on the worker thread
+struct CallbackContext(*mut c_void);
+
+// SAFETY: the pointer is never dereferenced here; the caller is responsible for its thread safety
+unsafe impl Send for CallbackContext {}
+
+impl CallbackContext {
+    fn as_ptr(&self) -> *mut c_void {
+        self.0
     }
+}
+
+// Non-blocking variant of get_historical_data, safe to call from the iOS main thread.
+// Returns immediately; the callback runs on a background thread with the same JSON that
+// get_historical_data would return (including error results). Returns false if the
+// arguments are invalid, in which case the callback is never called.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "request_historical_data_async"))]
+pub extern "C" fn request_historical_data_async(
+    symbol: *const c_char,
+    timeframe: *const c_char,
+    callback: Option<HistoricalDataCallback>,
+    context: *mut c_void,
+) -> bool {
+    status::catch_panic("request_historical_data_async", || {
+        historical_data_async(Some(DEFAULT_CLIENT.clone()), symbol, timeframe, callback, context)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_request_historical_data_async"))]
+pub extern "C" fn client_request_historical_data_async(
+    client: *mut CoinCrabClient,
+    symbol: *const c_char,
+    timeframe: *const c_char,
+    callback: Option<HistoricalDataCallback>,
+    context: *mut c_void,
+) -> bool {
+    status::catch_panic("client_request_historical_data_async", || {
+        historical_data_async(client_arc(client), symbol, timeframe, callback, context)
+    })
+}
+
+fn historical_data_async(
+    handle: Option<Arc<CoinCrabClient>>,
+    symbol: *const c_char,
+    timeframe: *const c_char,
+    callback: Option<HistoricalDataCallback>,
+    context: *mut c_void,
+) -> bool {
+    let (Some(handle), Some(symbol), Some(timeframe), Some(callback)) = (handle, c_str_arg(symbol), c_str_arg(timeframe), callback) else {
+        warn!("request_historical_data_async: Invalid client, symbol, timeframe or callback");
+        return false;
+    };
+    let (symbol, timeframe) = (symbol.to_string(), timeframe.to_string());
+    let context = CallbackContext(context);
+    let runtime = match shared_runtime() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            warn!("request_historical_data_async: {}", e);
+            return false;
+        }
+    };
     
-    // No MQTT data available - request from server and retry
-    debug_log(&format!("get_historical_data: Requesting {} {} from server via MQTT", symbol_str, timeframe_str));
-    
-    // Publish request to server
-    if let Some(ref client) = *MQTT_CLIENT.lock().unwrap() {
-        let request_payload = format!("{}:{}", symbol_str, timeframe_str);
-        debug_log(&format!("get_historical_data: Publishing request: {}", request_payload));
-        
-        // Use client runtime to publish request
-        let rt = Runtime::new().unwrap();
-        rt.block_on(async {
-            if let Err(e) = client.publish_message("crypto/requests/historical", &request_payload).await {
-                debug_log(&format!("get_historical_data: Failed to publish request: {}", e));
-            } else {
-                debug_log("get_historical_data: Request published successfully");
-            }
-        });
-        
-        // Wait and retry for data (server needs time to fetch from CMC API)
-        debug_log("get_historical_data: Waiting for server to populate data...");
-        std::thread::sleep(Duration::from_millis(2000)); // Wait 2 seconds
-        
-        // Retry getting the data
-        if let Some(hist_data) = client.get_historical_data(symbol_str, timeframe_str) {
-            debug_log(&format!("get_historical_data: Successfully got {} data points after retry", hist_data.data.len()));
-            let json = serde_json::to_string(&hist_data).unwrap();
-            return CString::new(json).unwrap().into_raw();
-        } else {
-            debug_log("get_historical_data: Still no data after retry - server may be busy");
+    // Loading blocks on the runtime while it waits for the broker, so it runs on the shared
+    // runtime's blocking pool rather than a worker or a thread of its own
+    runtime.spawn_blocking(move || {
+        // A panic still calls back, so the caller isn't left waiting
+        let json = status::catch_panic_or(
+            "request_historical_data_async",
+            |error| historical_error_json(&symbol, &timeframe, error.message.clone()),
+            || load_historical_json(&handle, &symbol, &timeframe),
+        );
+        let json = CString::new(json).unwrap_or_default();
+        debug!("request_historical_data_async: Delivering {} {} to callback", symbol, timeframe);
+        callback(context.as_ptr(), json.as_ptr());
+    });
+    true
+}
+
+// Receives the caller's context and the JSON BackgroundRefreshResult, valid only during the call
+pub type BackgroundRefreshCallback = extern "C" fn(*mut c_void, *const c_char);
+
+// For BGAppRefreshTask: connects, takes the retained price snapshot (or falls back to
+// HTTP_API_URL), saves it for the next launch and disconnects within max_duration_ms. Returns
+// immediately; the callback runs on a background thread with
+// {"success":true,"source":"mqtt","coin_count":100,"elapsed_ms":840,"error":null}.
+// Returns false (and never calls back) if the callback is NULL or the worker can't start.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "perform_background_refresh"))]
+pub extern "C" fn perform_background_refresh(
+    max_duration_ms: u64,
+    callback: Option<BackgroundRefreshCallback>,
+    context: *mut c_void,
+) -> bool {
+    status::catch_panic("perform_background_refresh", || {
+        let Some(callback) = callback else {
+            debug!("perform_background_refresh: Missing callback");
+            return false;
+        };
+        let context = CallbackContext(context);
+        let spawned = std::thread::Builder::new()
+            .name("background-refresh".to_string())
+            .spawn(move || {
+                let json = status::catch_panic_or(
+                    "perform_background_refresh",
+                    |error| serde_json::json!({"success": false, "error": error.message}).to_string(),
+                    || {
+                        let result = background::perform_background_refresh(Duration::from_millis(max_duration_ms));
+                        serde_json::to_string(&result).unwrap_or_else(|_| r#"{"success":false}"#.to_string())
+                    },
+                );
+                let json = CString::new(json).unwrap_or_default();
+                callback(context.as_ptr(), json.as_ptr());
+            });
+        if let Err(e) = spawned {
+            warn!("perform_background_refresh: Failed to spawn worker thread: {}", e);
+            return false;
         }
+        true
+    })
+}
+
+// Returns the cached series as JSON, requesting it from the server and waiting for the reply if needed.
+// Blocks for up to the configured data wait timeout; errors are returned as JSON too.
+fn load_historical_json(handle: &CoinCrabClient, symbol_str: &str, timeframe_str: &str) -> String {
+    match load_historical(handle, symbol_str, timeframe_str) {
+        Ok(hist_data) => serde_json::to_string(&hist_data).unwrap(),
+        Err(error) => offline_series_json(symbol_str, timeframe_str)
+            .unwrap_or_else(|| historical_error_json(symbol_str, timeframe_str, error.message)),
     }
-    
+}
+
+fn historical_error_json(symbol_str: &str, timeframe_str: &str, error: String) -> String {
     let error_result = HistoricalDataResult {
         success: false,
         data: vec![],
-        error: Some("MQTT data not available after request - server may be busy".to_string()),
+        error: Some(error),
         symbol: Some(symbol_str.to_string()),
         timeframe: Some(timeframe_str.to_string()),
+        gaps: Vec::new(),
+        buckets: Vec::new(),
+        schema_version: shared::PAYLOAD_SCHEMA_VERSION,
     };
     
-    let json = serde_json::to_string(&error_result).unwrap_or_else(|_| {
+    serde_json::to_string(&error_result).unwrap_or_else(|_| {
         r#"{"success":false,"error":"MQTT data not available after request","data":[]}"#.to_string()
-    });
-    CString::new(json).unwrap().into_raw()
+    })
+}
+
+// get_historical_data's result for a panic, when the symbol and timeframe may not be known
+fn historical_panic_json(error: &FfiError) -> String {
+    serde_json::json!({"success": false, "error": error.message, "data": []}).to_string()
+}
+
+// The series from the broker; failures don't fall back to the offline copy
+fn load_historical(handle: &CoinCrabClient, symbol_str: &str, timeframe_str: &str) -> Result<HistoricalDataResult, FfiError> {
+    debug!("load_historical_json: Fetching {} {} via MQTT", symbol_str, timeframe_str);
+    
+    // Reconnect if the connection was lost
+    let (client, fresh) = match handle.connected(true) {
+        Ok(connected) => connected,
+        Err(e) => {
+            warn!("load_historical_json: {}: {}", connect_error(&e), e);
+            return Err(FfiError::new(FfiStatus::NotConnected, connect_error(&e)));
+        }
+    };
+    
+    let waiter = client.data_waiter();
+    // A fresh connection may still be receiving the retained series; give it a short head start
+    let retained_wait = if fresh { RETAINED_DATA_GRACE.min(waiter.timeout()) } else { Duration::ZERO };
+    if let Some(hist_data) = waiter.wait_for_historical_data(symbol_str, timeframe_str, retained_wait) {
+        debug!("load_historical_json: Successfully got {} data points via MQTT", hist_data.data.len());
+        return Ok(hist_data);
+    }
+    debug!("load_historical_json: MQTT client has no cached historical data");
+
+    // No MQTT data available - request from server and wait for the reply
+    debug!("load_historical_json: Requesting {} {} from server via MQTT", symbol_str, timeframe_str);
+    let mut send_error = None;
+    let reply = match client.request_historical_data(symbol_str, timeframe_str) {
+        Ok(request_id) => {
+            debug!("load_historical_json: Request {} published successfully", request_id);
+            // Returns as soon as the series is cached (server needs time to fetch from CMC API),
+            // or as soon as the server answers that the fetch failed
+            debug!("load_historical_json: Waiting for server to populate data...");
+            waiter.wait_for_historical_response(symbol_str, timeframe_str, &request_id, waiter.timeout())
+        }
+        Err(e) => {
+            warn!("load_historical_json: Failed to publish request: {}", e);
+            let reply = waiter.wait_for_historical_data(symbol_str, timeframe_str, waiter.timeout()).map(Ok);
+            send_error = Some(e);
+            reply
+        }
+    };
+    match reply {
+        Some(Ok(hist_data)) => {
+            debug!("load_historical_json: Successfully got {} data points after request", hist_data.data.len());
+            Ok(hist_data)
+        }
+        Some(Err(fetch_error)) => {
+            let error = fetch_error.describe();
+            debug!("load_historical_json: Server could not fetch {} {}: {}", symbol_str, timeframe_str, error);
+            let status = if fetch_error.retry_after_seconds.is_some() { FfiStatus::RateLimited } else { FfiStatus::ServerError };
+            Err(FfiError::new(status, error))
+        }
+        None => {
+            debug!("load_historical_json: Still no data after {:?} - server may be busy", waiter.timeout());
+            Err(match send_error {
+                Some(e) => FfiError::new(FfiStatus::from_error(&e), e.to_string()),
+                None => FfiError::new(FfiStatus::Timeout, "MQTT data not available after request - server may be busy"),
+            })
+        }
+    }
 }
 
 // Helper function for returning MQTT errors
 fn return_mqtt_error(error_msg: &str) -> *mut c_char {
-    debug_log(&format!("return_mqtt_error: {}", error_msg));
+    into_c_string(mqtt_error_json(error_msg))
+}
+
+fn mqtt_error_json(error_msg: &str) -> String {
+    warn!("return_mqtt_error: {}", error_msg);
     
     let error_result = CryptoClientResult {
         success: false,
@@ -211,31 +845,527 @@ fn return_mqtt_error(error_msg: &str) -> *mut c_char {
         error: Some(error_msg.to_string()),
         last_updated: None,
         cached: false,
+        age_seconds: None,
+        data_timestamp: None,
+        source: None,
+        coin_count: 0,
+        schema_version: RESULT_SCHEMA_VERSION,
     };
     
-    let json = serde_json::to_string(&error_result).unwrap_or_else(|_| {
+    serde_json::to_string(&error_result).unwrap_or_else(|_| {
         r#"{"success":false,"error":"MQTT connection failed","data":null,"last_updated":null,"cached":false}"#.to_string()
-    });
-    CString::new(json).unwrap().into_raw()
+    })
 }
 
-// Function to register iOS callback for real-time price updates
+// The C string form of a JSON result. serde_json escapes NULs, so the error branch only guards
+// against hand-written payloads.
+fn into_c_string(json: String) -> *mut c_char {
+    match CString::new(json) {
+        Ok(json) => json.into_raw(),
+        Err(e) => {
+            warn!("into_c_string: payload has a NUL byte at {}", e.nul_position());
+            CString::new(mqtt_error_json("Payload contains a NUL byte")).unwrap_or_default().into_raw()
+        }
+    }
+}
+
+// Function to register iOS callback for real-time price updates. The callback only signals
+// that prices changed; register_price_payload_callback delivers the changes themselves.
 #[no_mangle]
-pub extern "C" fn register_price_update_callback(callback: PriceUpdateCallback) {
-    debug_log("register_price_update_callback: Registering iOS callback for real-time price updates");
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "register_price_update_callback"))]
+pub extern "C" fn register_price_update_callback(callback: PriceNotifyCallback) {
+    status::catch_panic("register_price_update_callback", || {
+        set_price_update_callback(DEFAULT_CLIENT.current(), PriceListener::Notify(callback));
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_register_price_update_callback"))]
+pub extern "C" fn client_register_price_update_callback(client: *mut CoinCrabClient, callback: PriceNotifyCallback) {
+    status::catch_panic("client_register_price_update_callback", || {
+        set_price_update_callback(current_client(client), PriceListener::Notify(callback));
+    })
+}
+
+// Registers a callback receiving each price update as JSON; replaces any callback registered
+// with register_price_update_callback
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "register_price_payload_callback"))]
+pub extern "C" fn register_price_payload_callback(callback: PriceUpdateCallback) {
+    status::catch_panic("register_price_payload_callback", || {
+        set_price_update_callback(DEFAULT_CLIENT.current(), PriceListener::Payload(callback));
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_register_price_payload_callback"))]
+pub extern "C" fn client_register_price_payload_callback(client: *mut CoinCrabClient, callback: PriceUpdateCallback) {
+    status::catch_panic("client_register_price_payload_callback", || {
+        set_price_update_callback(current_client(client), PriceListener::Payload(callback));
+    })
+}
+
+fn set_price_update_callback(client: Option<Arc<MQTTClient>>, listener: PriceListener) {
+    debug!("register_price_update_callback: Registering iOS callback for real-time price updates");
     
-    if let Some(ref client) = *MQTT_CLIENT.lock().unwrap() {
-        client.set_price_update_callback(callback);
-        debug_log("register_price_update_callback: Callback registered successfully");
+    if let Some(client) = client {
+        client.set_price_update_callback(listener);
+        debug!("register_price_update_callback: Callback registered successfully");
     } else {
-        debug_log("register_price_update_callback: MQTT client not initialized - callback will be lost");
+        debug!("register_price_update_callback: MQTT client not initialized - callback will be lost");
+    }
+}
+
+// Releases a payload passed to the price update callback; safe to call with NULL
+#[no_mangle]
+pub extern "C" fn free_price_update(data: *mut u8, len: usize) {
+    status::catch_panic("free_price_update", || {
+        unsafe { free_raw_buffer(data, len) };
+    })
+}
+
+// Registers (or clears, with NULL) the callback receiving crypto/prices/{symbol} updates
+// for symbols subscribed through subscribe_symbol
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "register_symbol_price_callback"))]
+pub extern "C" fn register_symbol_price_callback(callback: Option<SymbolPriceCallback>) {
+    status::catch_panic("register_symbol_price_callback", || {
+        set_symbol_price_callback(DEFAULT_CLIENT.current(), callback);
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_register_symbol_price_callback"))]
+pub extern "C" fn client_register_symbol_price_callback(client: *mut CoinCrabClient, callback: Option<SymbolPriceCallback>) {
+    status::catch_panic("client_register_symbol_price_callback", || {
+        set_symbol_price_callback(current_client(client), callback);
+    })
+}
+
+fn set_symbol_price_callback(client: Option<Arc<MQTTClient>>, callback: Option<SymbolPriceCallback>) {
+    if let Some(client) = client {
+        client.set_symbol_price_callback(callback);
+        debug!("register_symbol_price_callback: Callback registered successfully");
+    } else {
+        debug!("register_symbol_price_callback: MQTT client not initialized - callback will be lost");
+    }
+}
+
+// Live updates for one coin, e.g. while its detail view is on screen. Subscriptions are
+// reference counted: every subscribe_symbol needs a matching unsubscribe_symbol.
+// Returns false for an invalid symbol or if the MQTT client is not initialized.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "subscribe_symbol"))]
+pub extern "C" fn subscribe_symbol(symbol: *const c_char) -> bool {
+    status::catch_panic("subscribe_symbol", || {
+        update_symbol_subscription("subscribe_symbol", DEFAULT_CLIENT.current(), symbol, MQTTClient::subscribe_symbol)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "unsubscribe_symbol"))]
+pub extern "C" fn unsubscribe_symbol(symbol: *const c_char) -> bool {
+    status::catch_panic("unsubscribe_symbol", || {
+        update_symbol_subscription("unsubscribe_symbol", DEFAULT_CLIENT.current(), symbol, MQTTClient::unsubscribe_symbol)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_subscribe_symbol"))]
+pub extern "C" fn client_subscribe_symbol(client: *mut CoinCrabClient, symbol: *const c_char) -> bool {
+    status::catch_panic("client_subscribe_symbol", || {
+        update_symbol_subscription("client_subscribe_symbol", current_client(client), symbol, MQTTClient::subscribe_symbol)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_unsubscribe_symbol"))]
+pub extern "C" fn client_unsubscribe_symbol(client: *mut CoinCrabClient, symbol: *const c_char) -> bool {
+    status::catch_panic("client_unsubscribe_symbol", || {
+        update_symbol_subscription("client_unsubscribe_symbol", current_client(client), symbol, MQTTClient::unsubscribe_symbol)
+    })
+}
+
+fn update_symbol_subscription(
+    name: &str,
+    client: Option<Arc<MQTTClient>>,
+    symbol: *const c_char,
+    update: fn(&MQTTClient, &str) -> shared::CoinCrabResult<()>,
+) -> bool {
+    let Some(symbol) = c_str_arg(symbol).filter(|s| !s.trim().is_empty()) else {
+        warn!("{}: Invalid symbol string", name);
+        return false;
+    };
+    let Some(client) = client else {
+        debug!("{}: MQTT client not initialized", name);
+        return false;
+    };
+    match update(&client, symbol.trim()) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("{}: {}", name, e);
+            false
+        }
     }
 }
 
+// Returns the server's prefetch hints as a JSON array (empty if none received yet)
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_prefetch_hints"))]
+pub extern "C" fn get_prefetch_hints() -> *mut c_char {
+    status::catch_panic("get_prefetch_hints", || prefetch_hints(DEFAULT_CLIENT.current()))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_prefetch_hints"))]
+pub extern "C" fn client_get_prefetch_hints(client: *mut CoinCrabClient) -> *mut c_char {
+    status::catch_panic("client_get_prefetch_hints", || prefetch_hints(current_client(client)))
+}
+
+fn prefetch_hints(client: Option<Arc<MQTTClient>>) -> *mut c_char {
+    let hints = client.map(|client| client.get_prefetch_hints()).unwrap_or_default();
+
+    let json = serde_json::to_string(&hints).unwrap_or_else(|_| "[]".to_string());
+    CString::new(json).unwrap().into_raw()
+}
+
+// Returns parse-failure counters and the last failure as JSON (empty counters if not initialized)
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_client_diagnostics"))]
+pub extern "C" fn get_client_diagnostics() -> *mut c_char {
+    status::catch_panic("get_client_diagnostics", || client_diagnostics(DEFAULT_CLIENT.current()))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_diagnostics"))]
+pub extern "C" fn client_get_diagnostics(client: *mut CoinCrabClient) -> *mut c_char {
+    status::catch_panic("client_get_diagnostics", || client_diagnostics(current_client(client)))
+}
+
+fn client_diagnostics(client: Option<Arc<MQTTClient>>) -> *mut c_char {
+    let snapshot = client.map(|client| client.get_diagnostics()).unwrap_or_default();
+
+    let json = serde_json::to_string(&snapshot).unwrap_or_else(|_| "{}".to_string());
+    CString::new(json).unwrap().into_raw()
+}
+
+// Connection quality from keepalive and request round trips as JSON, e.g.
+// {"score":82,"rating":"good","recommendation":"none","ping_rtt_ms":95.0,...}. The app can suggest
+// switching networks or fall back to HTTP based on the recommendation. "{}" if not initialized.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_connection_quality"))]
+pub extern "C" fn get_connection_quality() -> *mut c_char {
+    status::catch_panic("get_connection_quality", || connection_quality(DEFAULT_CLIENT.current()))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_connection_quality"))]
+pub extern "C" fn client_get_connection_quality(client: *mut CoinCrabClient) -> *mut c_char {
+    status::catch_panic("client_get_connection_quality", || connection_quality(current_client(client)))
+}
+
+fn connection_quality(client: Option<Arc<MQTTClient>>) -> *mut c_char {
+    let json = client
+        .and_then(|client| serde_json::to_string(&client.get_connection_quality()).ok())
+        .unwrap_or_else(|| "{}".to_string());
+    CString::new(json).unwrap().into_raw()
+}
+
+// What the broker answered to each subscription as JSON, e.g.
+// {"subscriptions":[{"topic":"crypto/prices/latest","requested_qos":1,"status":"granted","granted_qos":1}],"pending":0,"rejected":[]}.
+// A rejected topic (e.g. denied by the broker's ACL) will never deliver data. "{}" if not initialized.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_subscription_status"))]
+pub extern "C" fn get_subscription_status() -> *mut c_char {
+    status::catch_panic("get_subscription_status", || subscription_status(DEFAULT_CLIENT.current()))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_subscription_status"))]
+pub extern "C" fn client_get_subscription_status(client: *mut CoinCrabClient) -> *mut c_char {
+    status::catch_panic("client_get_subscription_status", || subscription_status(current_client(client)))
+}
+
+fn subscription_status(client: Option<Arc<MQTTClient>>) -> *mut c_char {
+    let json = client
+        .and_then(|client| serde_json::to_string(&client.get_subscription_status()).ok())
+        .unwrap_or_else(|| "{}".to_string());
+    CString::new(json).unwrap().into_raw()
+}
+
+// Whether the server is up as JSON, e.g.
+// {"available":true,"status":"online","version":"0.1.0","started_at":1700000000,"status_age_seconds":42}.
+// status is online/offline, or null until the server's retained status arrives; available also
+// requires the broker connection. All fields are empty if the client is not initialized.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_get_server_status"))]
+pub extern "C" fn client_get_server_status(client: *mut CoinCrabClient) -> *mut c_char {
+    status::catch_panic("client_get_server_status", || {
+        let snapshot = current_client(client).map(|client| client.get_server_presence()).unwrap_or_default();
+        let json = serde_json::to_string(&snapshot).unwrap_or_else(|_| "{}".to_string());
+        CString::new(json).unwrap().into_raw()
+    })
+}
+
+// Requests every hinted series missing from the local cache; meant to be called while the app is idle.
+// Returns the number of requests sent, or -1 if the MQTT client is not initialized.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "warm_prefetch_cache"))]
+pub extern "C" fn warm_prefetch_cache() -> i32 {
+    status::catch_panic_or("warm_prefetch_cache", |_| -1, || warm_cache(DEFAULT_CLIENT.current()))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_warm_prefetch_cache"))]
+pub extern "C" fn client_warm_prefetch_cache(client: *mut CoinCrabClient) -> i32 {
+    status::catch_panic_or("client_warm_prefetch_cache", |_| -1, || warm_cache(current_client(client)))
+}
+
+fn warm_cache(client: Option<Arc<MQTTClient>>) -> i32 {
+    let Some(client) = client else {
+        debug!("warm_prefetch_cache: MQTT client not initialized");
+        return -1;
+    };
+    
+    let mut requested = 0;
+    for hint in client.missing_prefetch_hints() {
+        match client.request_background_historical_data(&hint.symbol, &hint.timeframe) {
+            Ok(_) => requested += 1,
+            Err(e) => warn!("warm_prefetch_cache: Failed to request {} {}: {}", hint.symbol, hint.timeframe, e),
+        }
+    }
+    debug!("warm_prefetch_cache: Requested {} hinted series", requested);
+    requested
+}
+
+// Incremental chart refresh: requests only points newer than the cached series (or the full
+// series if nothing is cached). The merged result is then available via get_historical_data.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "request_historical_update"))]
+pub extern "C" fn request_historical_update(symbol: *const c_char, timeframe: *const c_char) -> bool {
+    status::catch_panic("request_historical_update", || {
+        historical_update(DEFAULT_CLIENT.current(), symbol, timeframe)
+    })
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_request_historical_update"))]
+pub extern "C" fn client_request_historical_update(client: *mut CoinCrabClient, symbol: *const c_char, timeframe: *const c_char) -> bool {
+    status::catch_panic("client_request_historical_update", || {
+        historical_update(current_client(client), symbol, timeframe)
+    })
+}
+
+fn historical_update(client: Option<Arc<MQTTClient>>, symbol: *const c_char, timeframe: *const c_char) -> bool {
+    let (Some(symbol), Some(timeframe)) = (c_str_arg(symbol), c_str_arg(timeframe)) else {
+        warn!("request_historical_update: Invalid symbol or timeframe");
+        return false;
+    };
+    let Some(client) = client else {
+        debug!("request_historical_update: MQTT client not initialized");
+        return false;
+    };
+    match client.request_historical_update(symbol, timeframe) {
+        Ok(_) => true,
+        Err(e) => {
+            debug!("request_historical_update: {}", e);
+            false
+        }
+    }
+}
+
+// Pull-to-refresh: asks the server for a fresh price fetch rather than re-reading the local cache.
+// Returns false if the client is not initialized or the server has us rate limited.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "request_price_refresh"))]
+pub extern "C" fn request_price_refresh() -> bool {
+    status::catch_panic("request_price_refresh", || price_refresh(DEFAULT_CLIENT.current()))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_request_price_refresh"))]
+pub extern "C" fn client_request_price_refresh(client: *mut CoinCrabClient) -> bool {
+    status::catch_panic("client_request_price_refresh", || price_refresh(current_client(client)))
+}
+
+fn price_refresh(client: Option<Arc<MQTTClient>>) -> bool {
+    let Some(client) = client else {
+        debug!("request_price_refresh: MQTT client not initialized");
+        return false;
+    };
+    match client.request_price_refresh() {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("request_price_refresh: {}", e);
+            false
+        }
+    }
+}
+
+// Loads logos over MQTT instead of HTTP for a comma-separated list of symbols. Logos missing from
+// the disk cache are requested and appear in get_cached_logo_path as they arrive. Returns the
+// number requested (0 if all are cached), or -1 if the client is not initialized or the request failed.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "request_logos"))]
+pub extern "C" fn request_logos(symbols: *const c_char) -> i32 {
+    status::catch_panic_or("request_logos", |_| -1, || logos(DEFAULT_CLIENT.current(), symbols))
+}
+
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "client_request_logos"))]
+pub extern "C" fn client_request_logos(client: *mut CoinCrabClient, symbols: *const c_char) -> i32 {
+    status::catch_panic_or("client_request_logos", |_| -1, || logos(current_client(client), symbols))
+}
+
+fn logos(client: Option<Arc<MQTTClient>>, symbols: *const c_char) -> i32 {
+    let Some(symbols) = c_str_arg(symbols) else {
+        warn!("request_logos: Invalid symbols string");
+        return -1;
+    };
+    let Some(client) = client else {
+        debug!("request_logos: MQTT client not initialized");
+        return -1;
+    };
+    let symbols: Vec<&str> = symbols.split(',').collect();
+    match client.request_logos(&symbols) {
+        Ok(requested) => requested as i32,
+        Err(e) => {
+            debug!("request_logos: {}", e);
+            -1
+        }
+    }
+}
+
+// Reads a C string argument, returning None for null or invalid UTF-8
+fn c_str_arg<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(ptr).to_str().ok() }
+}
+
+// Persists the symbol->id mapping (JSON object, as served by /api/cmc-mapping) for cold launches
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "cache_cmc_mapping"))]
+pub extern "C" fn cache_cmc_mapping(mapping_json: *const c_char) -> bool {
+    status::catch_panic("cache_cmc_mapping", || {
+        let Some(json) = c_str_arg(mapping_json) else {
+            warn!("cache_cmc_mapping: Invalid mapping string");
+            return false;
+        };
+        let mapping = match serde_json::from_str(json) {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                warn!("cache_cmc_mapping: Failed to parse mapping: {}", e);
+                return false;
+            }
+        };
+        match DiskCache::default_location().store_mapping(&mapping) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("cache_cmc_mapping: {}", e);
+                false
+            }
+        }
+    })
+}
+
+// Returns the cached mapping as a JSON object, or "{}" if missing or expired
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_cached_cmc_mapping"))]
+pub extern "C" fn get_cached_cmc_mapping() -> *mut c_char {
+    status::catch_panic("get_cached_cmc_mapping", || {
+        let mapping = DiskCache::default_location().load_mapping().unwrap_or_default();
+        let json = serde_json::to_string(&mapping).unwrap_or_else(|_| "{}".to_string());
+        CString::new(json).unwrap().into_raw()
+    })
+}
+
+// Resolves a symbol to its CMC id from the cached mapping, or -1 if unknown
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_cached_cmc_id"))]
+pub extern "C" fn get_cached_cmc_id(symbol: *const c_char) -> i64 {
+    status::catch_panic("get_cached_cmc_id", || {
+        c_str_arg(symbol)
+            .and_then(|symbol| DiskCache::default_location().lookup_cmc_id(symbol))
+            .map(i64::from)
+            .unwrap_or(-1)
+    })
+}
+
+// Stores logo image bytes for a symbol; returns false on invalid input or I/O failure
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "cache_logo"))]
+pub extern "C" fn cache_logo(symbol: *const c_char, data: *const u8, len: usize) -> bool {
+    status::catch_panic("cache_logo", || {
+        let Some(symbol) = c_str_arg(symbol) else {
+            warn!("cache_logo: Invalid symbol string");
+            return false;
+        };
+        if data.is_null() || len == 0 {
+            debug!("cache_logo: Empty logo data");
+            return false;
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+        match DiskCache::default_location().store_logo(symbol, bytes) {
+            Ok(_) => true,
+            Err(e) => {
+                debug!("cache_logo: {}", e);
+                false
+            }
+        }
+    })
+}
+
+// Returns the file path of a fresh cached logo, or null if not cached
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_cached_logo_path"))]
+pub extern "C" fn get_cached_logo_path(symbol: *const c_char) -> *mut c_char {
+    status::catch_panic("get_cached_logo_path", || {
+        c_str_arg(symbol)
+            .and_then(|symbol| DiskCache::default_location().logo_path(symbol))
+            .and_then(|path| CString::new(path.to_string_lossy().into_owned()).ok())
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut())
+    })
+}
+
+// Switches the client log file on or off for this run; LOG_FILE_ENABLED sets it at launch.
+// Before any client exists, logging is first set up from the client configuration.
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "set_file_logging_enabled"))]
+pub extern "C" fn set_file_logging_enabled(enabled: bool) -> bool {
+    status::catch_panic("set_file_logging_enabled", || {
+        if shared::set_file_logging(enabled) {
+            return true;
+        }
+        match Config::load() {
+            Ok(_) => shared::set_file_logging(enabled),
+            Err(e) => {
+                warn!("set_file_logging_enabled: Failed to load config: {}", e);
+                false
+            }
+        }
+    })
+}
+
+// Where the log file is written (and rotated as <path>.1, <path>.2, ...), whether or not it is enabled
+#[no_mangle]
+#[instrument(level = "debug", name = "ffi", skip_all, fields(call = "get_log_file_path"))]
+pub extern "C" fn get_log_file_path() -> *mut c_char {
+    status::catch_panic("get_log_file_path", || {
+        CString::new(shared::log_file_path())
+            .map(CString::into_raw)
+            .unwrap_or(std::ptr::null_mut())
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::ffi::{CStr, CString};
+    use std::os::raw::c_void;
+    use crate::status::get_last_error_message;
     
     #[test]
     fn test_free_string_with_valid_pointer() {
@@ -244,19 +1374,122 @@ mod tests {
         let raw_ptr = test_string.into_raw();
         
         // This should not panic
-        unsafe { free_string(raw_ptr) };
+        free_string(raw_ptr);
         
         // If we reach here, the function worked correctly
-        assert!(true);
     }
-
+    
     #[test]
     fn test_free_string_with_null_pointer() {
         // Test that free_string handles null pointers safely
-        unsafe { free_string(std::ptr::null_mut()) };
+        free_string(std::ptr::null_mut());
         
         // If we reach here, the function handled null pointer correctly
-        assert!(true);
+    }
+    
+    #[test]
+    fn test_load_functions_reject_bad_arguments() {
+        let mut json: *mut c_char = std::ptr::null_mut();
+        assert_eq!(client_load_crypto_data(std::ptr::null_mut(), &mut json), FfiStatus::InvalidArgument as i32);
+        assert!(json.is_null());
+        let message = get_last_error_message();
+        assert_eq!(unsafe { CStr::from_ptr(message) }.to_str().unwrap(), "Invalid client handle");
+        free_string(message);
+
+        // Bad symbols are caught before any connection is made
+        let client = client_create();
+        let symbol = CString::new("BTC/USD").unwrap();
+        let timeframe = CString::new("24h").unwrap();
+        let code = client_load_historical_data(client, symbol.as_ptr(), timeframe.as_ptr(), &mut json);
+        assert_eq!(code, FfiStatus::InvalidSymbol as i32);
+        assert!(json.is_null());
+        let code = client_load_historical_data(client, symbol.as_ptr(), std::ptr::null(), &mut json);
+        assert_eq!(code, FfiStatus::InvalidArgument as i32);
+        client_destroy(client);
+    }
+
+    #[test]
+    fn test_symbol_quote_rejects_bad_symbols() {
+        let client = client_create();
+        let symbol = CString::new("BTC/USD").unwrap();
+        assert!(client_get_symbol_quote(client, symbol.as_ptr()).is_null());
+        let message = get_last_error_message();
+        assert_eq!(unsafe { CStr::from_ptr(message) }.to_str().unwrap(), "Invalid symbol 'BTC/USD'");
+        free_string(message);
+        assert!(client_get_symbol_quote(client, std::ptr::null()).is_null());
+        assert!(client_arg(client).unwrap().current().is_none());
+        client_destroy(client);
+    }
+
+    #[test]
+    fn test_buffer_functions_return_error_json() {
+        let buffer = client_get_crypto_data_buffer(std::ptr::null_mut());
+        let json: serde_json::Value = serde_json::from_slice(buffer.as_slice()).unwrap();
+        assert_eq!(json["success"], false);
+        assert_eq!(json["error"], "Invalid client handle");
+        free_byte_buffer(buffer);
+
+        // NULL arguments give an error result instead of being dereferenced
+        let client = client_create();
+        let buffer = client_get_historical_data_buffer(client, std::ptr::null(), std::ptr::null());
+        assert_eq!(buffer.as_slice(), br#"{"success":false,"error":"Invalid symbol","data":[]}"#);
+        free_byte_buffer(buffer);
+        client_destroy(client);
+    }
+
+    #[test]
+    fn test_top_coins_rejects_bad_arguments() {
+        let client = client_create();
+        let sort_key = CString::new("price").unwrap();
+        let json = client_get_top_coins(client, 10, sort_key.as_ptr());
+        assert!(unsafe { CStr::from_ptr(json) }.to_str().unwrap().contains("Unknown sort key 'price'"));
+        free_string(json);
+        let message = get_last_error_message();
+        assert_eq!(unsafe { CStr::from_ptr(message) }.to_str().unwrap(), "Unknown sort key 'price'");
+        free_string(message);
+
+        let sort_key = CString::new("market_cap").unwrap();
+        free_string(client_get_top_coins(client, 0, sort_key.as_ptr()));
+        free_string(client_get_top_coins(client, 10, std::ptr::null()));
+        assert!(client_arg(client).unwrap().current().is_none());
+        client_destroy(client);
+    }
+
+    #[test]
+    fn test_lifecycle_hooks_check_the_handle() {
+        assert_eq!(client_on_app_background(std::ptr::null_mut()), FfiStatus::InvalidArgument as i32);
+        let client = client_create();
+        assert_eq!(client_on_app_background(client), FfiStatus::Ok as i32);
+        assert_eq!(client_on_app_foreground(client), FfiStatus::Ok as i32);
+        // Nothing connects until the app asks for data
+        assert!(client_arg(client).unwrap().current().is_none());
+        client_destroy(client);
+    }
+
+    #[test]
+    fn test_network_hint_checks_the_handle() {
+        assert_eq!(client_notify_network_changed(std::ptr::null_mut(), true), FfiStatus::InvalidArgument as i32);
+        let client = client_create();
+        assert_eq!(client_notify_network_changed(client, false), FfiStatus::Ok as i32);
+        // Without a network the offline copy (or the error) comes back without trying to connect
+        free_string(client_get_crypto_data(client));
+        assert!(client_arg(client).unwrap().current().is_none());
+        assert_eq!(client_notify_network_changed(client, true), FfiStatus::Ok as i32);
+        client_destroy(client);
+    }
+
+    #[test]
+    fn test_configure_before_connecting() {
+        let host = CString::new("staging.example.com").unwrap();
+        assert_eq!(client_configure(std::ptr::null_mut(), host.as_ptr(), 0, true, std::ptr::null()), FfiStatus::InvalidArgument as i32);
+
+        // Nothing is connected yet, so the settings are only stored
+        let client = client_create();
+        assert_eq!(client_configure(client, host.as_ptr(), 0, true, std::ptr::null()), FfiStatus::Ok as i32);
+        let bad_host = CString::new("staging example").unwrap();
+        assert_eq!(client_configure(client, bad_host.as_ptr(), 1883, false, std::ptr::null()), FfiStatus::InvalidArgument as i32);
+        assert!(client_arg(client).unwrap().current().is_none());
+        client_destroy(client);
     }
 
     #[test]
@@ -274,9 +1507,9 @@ mod tests {
         assert!(error_str.contains("Test error message"));
         
         // Clean up the allocated string
-        unsafe { free_string(error_ptr) };
+        free_string(error_ptr);
     }
-
+    
     #[test]
     fn test_return_mqtt_error_serialization() {
         // Test that return_mqtt_error produces valid JSON
@@ -293,9 +1526,9 @@ mod tests {
         assert_eq!(parsed["cached"], false);
         
         // Clean up
-        unsafe { free_string(error_ptr) };
+        free_string(error_ptr);
     }
-
+    
     #[test]
     fn test_get_crypto_data_function_exists() {
         // Test that get_crypto_data function exists and has correct signature
@@ -303,9 +1536,8 @@ mod tests {
         let _get_crypto_fn: extern "C" fn() -> *mut c_char = get_crypto_data;
         
         // If this compiles, the function exists with the correct signature
-        assert!(true);
     }
-
+    
     #[test]
     fn test_get_historical_data_function_signature() {
         // Test that get_historical_data has the correct function signature
@@ -313,9 +1545,8 @@ mod tests {
         let _get_historical_fn: extern "C" fn(*const c_char, *const c_char) -> *mut c_char = get_historical_data;
         
         // If this compiles, the function exists with the correct signature
-        assert!(true);
     }
-
+    
     #[test]
     fn test_input_validation_logic() {
         // Test the UTF-8 validation logic used in get_historical_data
@@ -335,7 +1566,7 @@ mod tests {
         assert_eq!(symbol_result.unwrap(), "BTC");
         assert_eq!(timeframe_result.unwrap(), "24h");
     }
-
+    
     #[test]
     fn test_invalid_utf8_handling() {
         // Test that invalid UTF-8 sequences are properly detected
@@ -349,25 +1580,28 @@ mod tests {
         // Should return an error for invalid UTF-8
         assert!(result.is_err());
     }
-
+    
     #[test]
     fn test_register_price_update_callback_safety() {
         // Test that register_price_update_callback doesn't crash
         // We can't test the actual callback functionality without complex setup
         // but we can verify the function doesn't panic
         
-        // Create a dummy callback function
-        extern "C" fn dummy_callback(_data: *const c_void) {
+        // Create dummy callback functions
+        extern "C" fn dummy_callback(_context: *const c_void) {
             // Do nothing
         }
+        extern "C" fn dummy_payload_callback(data: *mut u8, len: usize) {
+            free_price_update(data, len);
+        }
         
         // This should not panic
         register_price_update_callback(dummy_callback);
+        register_price_payload_callback(dummy_payload_callback);
         
         // If we reach here, the function worked
-        assert!(true);
     }
-
+    
     #[test]
     fn test_ffi_function_signatures() {
         // Test that all FFI functions have the correct signatures and can be referenced
@@ -382,12 +1616,149 @@ mod tests {
         let _get_historical_fn: extern "C" fn(*const c_char, *const c_char) -> *mut c_char = get_historical_data;
         
         // Test register_price_update_callback signature
-        let _register_callback_fn: extern "C" fn(PriceUpdateCallback) = register_price_update_callback;
+        let _register_callback_fn: extern "C" fn(PriceNotifyCallback) = register_price_update_callback;
+        let _register_payload_fn: extern "C" fn(PriceUpdateCallback) = register_price_payload_callback;
+        let _free_update_fn: extern "C" fn(*mut u8, usize) = free_price_update;
+        
+        // Test prefetch hint function signatures
+        let _get_hints_fn: extern "C" fn() -> *mut c_char = get_prefetch_hints;
+        let _warm_fn: extern "C" fn() -> i32 = warm_prefetch_cache;
+        
+        let _update_fn: extern "C" fn(*const c_char, *const c_char) -> bool = request_historical_update;
+        let _refresh_fn: extern "C" fn() -> bool = request_price_refresh;
+        let _async_fn: extern "C" fn(*const c_char, *const c_char, Option<HistoricalDataCallback>, *mut c_void) -> bool = request_historical_data_async;
+        let _diagnostics_fn: extern "C" fn() -> *mut c_char = get_client_diagnostics;
+        let _quality_fn: extern "C" fn() -> *mut c_char = get_connection_quality;
+        let _subscription_status_fn: extern "C" fn() -> *mut c_char = get_subscription_status;
+        let _background_fn: extern "C" fn(u64, Option<BackgroundRefreshCallback>, *mut c_void) -> bool = perform_background_refresh;
+        let _subscribe_fn: extern "C" fn(*const c_char) -> bool = subscribe_symbol;
+        let _unsubscribe_fn: extern "C" fn(*const c_char) -> bool = unsubscribe_symbol;
+        let _symbol_callback_fn: extern "C" fn(Option<SymbolPriceCallback>) = register_symbol_price_callback;
+        
+        // Test client handle function signatures
+        let _create_fn: extern "C" fn() -> *mut CoinCrabClient = client_create;
+        let _destroy_fn: extern "C" fn(*mut CoinCrabClient) = client_destroy;
+        let _client_crypto_fn: extern "C" fn(*mut CoinCrabClient) -> *mut c_char = client_get_crypto_data;
+        let _client_historical_fn: extern "C" fn(*mut CoinCrabClient, *const c_char, *const c_char) -> *mut c_char = client_get_historical_data;
+        let _client_callback_fn: extern "C" fn(*mut CoinCrabClient, PriceNotifyCallback) = client_register_price_update_callback;
+        let _client_payload_fn: extern "C" fn(*mut CoinCrabClient, PriceUpdateCallback) = client_register_price_payload_callback;
+        let _client_symbol_callback_fn: extern "C" fn(*mut CoinCrabClient, Option<SymbolPriceCallback>) = client_register_symbol_price_callback;
+        let _client_unsubscribe_fn: extern "C" fn(*mut CoinCrabClient, *const c_char) -> bool = client_unsubscribe_symbol;
+        let _client_diagnostics_fn: extern "C" fn(*mut CoinCrabClient) -> *mut c_char = client_get_diagnostics;
+        let _client_quality_fn: extern "C" fn(*mut CoinCrabClient) -> *mut c_char = client_get_connection_quality;
+        let _client_status_fn: extern "C" fn(*mut CoinCrabClient) -> *mut c_char = client_get_subscription_status;
+        let _server_status_fn: extern "C" fn(*mut CoinCrabClient) -> *mut c_char = client_get_server_status;
+        
+        // Test disk cache function signatures
+        let _cache_mapping_fn: extern "C" fn(*const c_char) -> bool = cache_cmc_mapping;
+        let _get_mapping_fn: extern "C" fn() -> *mut c_char = get_cached_cmc_mapping;
+        let _get_id_fn: extern "C" fn(*const c_char) -> i64 = get_cached_cmc_id;
+        let _cache_logo_fn: extern "C" fn(*const c_char, *const u8, usize) -> bool = cache_logo;
+        let _get_logo_fn: extern "C" fn(*const c_char) -> *mut c_char = get_cached_logo_path;
         
         // If we reach here, all function signatures are correct
-        assert!(true);
+    }
+    
+    #[test]
+    fn test_header_declares_every_export() {
+        // The app's bridging header imports rust_ios_lib.h, so Swift can only call what it declares
+        let header = include_str!("../rust_ios_lib.h");
+        let sources = [include_str!("ffi.rs"), include_str!("status.rs")];
+        let missing: Vec<&str> = sources
+            .iter()
+            .flat_map(|source| source.split("pub extern \"C\" fn ").skip(1))
+            .filter_map(|rest| rest.split('(').next())
+            .filter(|name| !header.contains(&format!(" {}(", name)) && !header.contains(&format!("*{}(", name)))
+            .collect();
+        assert!(missing.is_empty(), "missing from rust_ios_lib.h: {:?}", missing);
+        assert!(header.contains("bool request_price_refresh(void);"));
+    }
+    
+    #[test]
+    fn test_get_prefetch_hints_returns_json_array() {
+        let hints_ptr = get_prefetch_hints();
+        let hints_str = unsafe { CStr::from_ptr(hints_ptr).to_string_lossy().into_owned() };
+        
+        let parsed: serde_json::Value = serde_json::from_str(&hints_str).unwrap();
+        assert!(parsed.is_array());
+        
+        free_string(hints_ptr);
+    }
+    
+    #[test]
+    fn test_request_historical_data_async_rejects_invalid_input() {
+        extern "C" fn unreachable_callback(_context: *mut c_void, _json: *const c_char) {
+            panic!("callback must not run for rejected requests");
+        }
+        let symbol = CString::new("BTC").unwrap();
+        let timeframe = CString::new("24h").unwrap();
+        
+        assert!(!request_historical_data_async(std::ptr::null(), timeframe.as_ptr(), Some(unreachable_callback), std::ptr::null_mut()));
+        assert!(!request_historical_data_async(symbol.as_ptr(), std::ptr::null(), Some(unreachable_callback), std::ptr::null_mut()));
+        assert!(!request_historical_data_async(symbol.as_ptr(), timeframe.as_ptr(), None, std::ptr::null_mut()));
+    }
+    
+    #[test]
+    fn test_symbol_subscription_rejects_invalid_symbol() {
+        let blank = CString::new("  ").unwrap();
+        assert!(!subscribe_symbol(std::ptr::null()));
+        assert!(!subscribe_symbol(blank.as_ptr()));
+        assert!(!unsubscribe_symbol(std::ptr::null()));
+    }
+    
+    #[test]
+    fn test_get_client_diagnostics_returns_counters() {
+        let diagnostics_ptr = get_client_diagnostics();
+        let diagnostics_str = unsafe { CStr::from_ptr(diagnostics_ptr).to_string_lossy().into_owned() };
+        
+        let parsed: serde_json::Value = serde_json::from_str(&diagnostics_str).unwrap();
+        assert!(parsed["parse_failures"].is_object());
+        
+        free_string(diagnostics_ptr);
+    }
+    
+    #[test]
+    fn test_client_handles() {
+        // A handle connects on first use, so creating and destroying one needs no broker
+        let client = client_create();
+        assert!(!client.is_null());
+        assert!(!client_request_price_refresh(client));
+        assert_eq!(client_warm_prefetch_cache(client), -1);
+        let hints_ptr = client_get_prefetch_hints(client);
+        assert_eq!(unsafe { CStr::from_ptr(hints_ptr) }.to_str().unwrap(), "[]");
+        free_string(hints_ptr);
+        client_destroy(client);
+        client_destroy(std::ptr::null_mut());
+
+        // NULL handles are rejected like missing connections
+        let symbol = CString::new("BTC").unwrap();
+        let null = std::ptr::null_mut();
+        assert!(!client_subscribe_symbol(null, symbol.as_ptr()));
+        assert!(!client_request_historical_update(null, symbol.as_ptr(), symbol.as_ptr()));
+        extern "C" fn unreachable_callback(_context: *mut c_void, _json: *const c_char) {
+            panic!("callback must not run for rejected requests");
+        }
+        assert!(!client_request_historical_data_async(null, symbol.as_ptr(), symbol.as_ptr(), Some(unreachable_callback), std::ptr::null_mut()));
+        let result_ptr = client_get_crypto_data(null);
+        let result: serde_json::Value = serde_json::from_str(&unsafe { CStr::from_ptr(result_ptr) }.to_string_lossy()).unwrap();
+        assert_eq!(result["error"], "Invalid client handle");
+        free_string(result_ptr);
     }
 
+    #[test]
+    fn test_cache_functions_reject_invalid_input() {
+        assert!(!cache_cmc_mapping(std::ptr::null()));
+        let not_json = CString::new("not json").unwrap();
+        assert!(!cache_cmc_mapping(not_json.as_ptr()));
+        
+        let symbol = CString::new("BTC").unwrap();
+        assert!(!cache_logo(symbol.as_ptr(), std::ptr::null(), 0));
+        assert!(!cache_logo(std::ptr::null(), b"x".as_ptr(), 1));
+        
+        assert_eq!(get_cached_cmc_id(std::ptr::null()), -1);
+        assert!(get_cached_logo_path(std::ptr::null()).is_null());
+    }
+    
     #[test]
     fn test_json_serialization_fallback() {
         // Test that JSON serialization errors are handled gracefully
@@ -414,12 +1785,11 @@ mod tests {
             let _parsed: serde_json::Value = serde_json::from_str(&error_str).unwrap();
             
             // Clean up
-            unsafe { free_string(error_ptr) };
+            free_string(error_ptr);
         }
         
-        assert!(true);
     }
-
+    
     #[test]
     fn test_cstring_memory_management() {
         // Test proper C string memory management patterns
@@ -437,9 +1807,8 @@ mod tests {
             assert_eq!(read_back, test_str);
             
             // Free it properly
-            unsafe { free_string(raw_ptr) };
+            free_string(raw_ptr);
         }
         
-        assert!(true);
     }
 }
\ No newline at end of file
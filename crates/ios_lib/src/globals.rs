@@ -1,16 +1,15 @@
-use std::sync::Mutex;
+use std::sync::{Arc, LazyLock};
+use crate::handle::CoinCrabClient;
 use crate::mqtt::MQTTClient;
+use shared::CoinCrabResult;
 
-// Global MQTT client instance
-pub static MQTT_CLIENT: Mutex<Option<MQTTClient>> = Mutex::new(None);
+// Deprecated: the client behind the FFI functions that take no handle, kept for apps written
+// before client_create. New code creates its own handle and calls the client_* functions.
+pub static DEFAULT_CLIENT: LazyLock<Arc<CoinCrabClient>> = LazyLock::new(|| Arc::new(CoinCrabClient::new()));
 
 /// Initialize or reinitialize the global MQTT client
-pub fn init_mqtt_client() -> Result<(), String> {
-    let client = MQTTClient::new()?;
-    client.connect()?;
-    
-    *MQTT_CLIENT.lock().unwrap() = Some(client);
-    Ok(())
+pub fn init_mqtt_client() -> CoinCrabResult<()> {
+    DEFAULT_CLIENT.reconnect().map(|_| ())
 }
 
 /// Get a reference to the global MQTT client if it exists
@@ -18,11 +17,7 @@ pub fn with_mqtt_client<T, F>(f: F) -> Option<T>
 where
     F: FnOnce(&MQTTClient) -> T,
 {
-    MQTT_CLIENT
-        .lock()
-        .unwrap()
-        .as_ref()
-        .map(f)
+    DEFAULT_CLIENT.current().map(|client| f(&client))
 }
 
 /// Check if the global MQTT client is connected
@@ -32,7 +27,7 @@ pub fn is_mqtt_connected() -> bool {
 
 /// Reset the global MQTT client's connection attempts counter
 pub fn reset_mqtt_connection_attempts() {
-    if let Some(ref client) = *MQTT_CLIENT.lock().unwrap() {
+    if let Some(client) = DEFAULT_CLIENT.current() {
         client.reset_connection_attempts();
     }
 }
@@ -40,17 +35,13 @@ pub fn reset_mqtt_connection_attempts() {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Mutex;
 
     #[test]
     fn test_mqtt_client_global_initialization() {
-        // Test that the global MQTT_CLIENT starts as None
-        let client_guard = MQTT_CLIENT.lock().unwrap();
-        // We can't assert it's None because other tests may have initialized it
-        // But we can verify the mutex works and the type is correct
-        let _is_some = client_guard.is_some();
+        // Test that the global DEFAULT_CLIENT is accessible
+        // We can't assert it has no connection because other tests may have initialized it
+        let _is_some = DEFAULT_CLIENT.current().is_some();
         // If we reach here, the global variable is accessible
-        assert!(true);
     }
 
     #[test]
@@ -59,7 +50,7 @@ mod tests {
         // We need to be careful here since global state may be modified by other tests
         let connected = is_mqtt_connected();
         // This should return false if no client, or actual connection status if client exists
-        assert!(connected == false || connected == true); // Just verify it returns a bool
+        let _: bool = connected; // Just verify it returns a bool
     }
 
     #[test]
@@ -67,7 +58,6 @@ mod tests {
         // Test that reset_mqtt_connection_attempts doesn't panic when no client exists
         reset_mqtt_connection_attempts();
         // If we reach here, the function didn't panic
-        assert!(true);
     }
 
     #[test]
@@ -79,7 +69,7 @@ mod tests {
         
         // Result should be None if no client, or Some(42) if client exists
         match result {
-            None => assert!(true), // No client case
+            None => {}, // No client case
             Some(value) => assert_eq!(value, 42), // Client exists case
         }
     }
@@ -99,31 +89,23 @@ mod tests {
 
     #[test]
     fn test_mutex_thread_safety() {
-        // Test that we can lock and unlock the mutex multiple times
-        {
-            let _guard1 = MQTT_CLIENT.lock().unwrap();
-            // Mutex acquired successfully
+        // Test that the current client can be taken from several threads at once
+        let handles: Vec<_> = (0..4).map(|_| std::thread::spawn(|| DEFAULT_CLIENT.current().is_some())).collect();
+        for handle in handles {
+            handle.join().unwrap();
         }
-        {
-            let _guard2 = MQTT_CLIENT.lock().unwrap();
-            // Mutex can be acquired again after previous release
-        }
-        assert!(true);
     }
 
     #[test]
     fn test_global_static_accessibility() {
         // Test that the global static is accessible and has correct type
-        let guard = MQTT_CLIENT.lock().unwrap();
-        match &*guard {
+        match DEFAULT_CLIENT.current() {
             Some(_client) => {
                 // Client exists, we can't test much without actually connecting
                 // but we can verify the type is correct
-                assert!(true);
             }
             None => {
                 // No client initialized, which is a valid state
-                assert!(true);
             }
         }
     }
@@ -137,6 +119,5 @@ mod tests {
         let _result = with_mqtt_client(|_| "test");
         
         // If we reach here, all functions handled the global state without panicking
-        assert!(true);
     }
 }
\ No newline at end of file
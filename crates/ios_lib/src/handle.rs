@@ -0,0 +1,199 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::config::{BrokerOverride, Config};
+use crate::mqtt::MQTTClient;
+use shared::{CoinCrabError, CoinCrabResult};
+
+// Handles after the first connect with "<MQTT_CLIENT_ID>-<n>", so two clients in one process
+// (e.g. the app and an embedded widget) don't take over each other's broker session
+static NEXT_INSTANCE: AtomicU32 = AtomicU32::new(0);
+
+// What client_create hands out (as a raw Arc) and every client_* FFI function takes. Each
+// handle owns its own connection, cache and callbacks; nothing is shared between handles.
+pub struct CoinCrabClient {
+    instance: u32,
+    // The current connection, replaced when a lost one is re-established. Calls work on a
+    // clone of the Arc, so the lock is only held long enough to copy it.
+    current: Mutex<Option<Arc<MQTTClient>>>,
+    // Held while connecting so concurrent calls don't each open a connection
+    connecting: Mutex<()>,
+    // Broker chosen by the app, applied on every connect
+    broker: Mutex<Option<BrokerOverride>>,
+    // Last network hint from the app; no connection is attempted while it says unreachable
+    network_reachable: AtomicBool,
+}
+
+impl Default for CoinCrabClient {
+    fn default() -> Self {
+        CoinCrabClient {
+            instance: NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed),
+            current: Mutex::new(None),
+            connecting: Mutex::new(()),
+            broker: Mutex::new(None),
+            network_reachable: AtomicBool::new(true),
+        }
+    }
+}
+
+impl CoinCrabClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn client_id(&self, configured: &str) -> String {
+        match self.instance {
+            0 => configured.to_string(),
+            instance => format!("{}-{}", configured, instance),
+        }
+    }
+
+    // The connection if one was made, connected or not
+    pub fn current(&self) -> Option<Arc<MQTTClient>> {
+        self.current.lock().unwrap().clone()
+    }
+
+    // The existing connection, or a new one if there is none yet. With reconnect, one that has
+    // lost its broker connection is replaced too. The flag is true for a fresh connection.
+    pub fn connected(&self, reconnect: bool) -> CoinCrabResult<(Arc<MQTTClient>, bool)> {
+        let _connecting = self.connecting.lock().unwrap();
+        match self.current() {
+            Some(client) if !reconnect || client.is_connected() => Ok((client, false)),
+            _ => self.open().map(|client| (client, true)),
+        }
+    }
+
+    // Always opens a new connection, closing the previous one once connected
+    pub fn reconnect(&self) -> CoinCrabResult<Arc<MQTTClient>> {
+        let _connecting = self.connecting.lock().unwrap();
+        self.open()
+    }
+
+    // Sets the broker for the next connection. An open connection is replaced right away, so
+    // the app can switch between staging and production without restarting.
+    pub fn configure(&self, broker: BrokerOverride) -> CoinCrabResult<()> {
+        let _connecting = self.connecting.lock().unwrap();
+        *self.broker.lock().unwrap() = Some(broker);
+        if self.current().is_none() {
+            return Ok(());
+        }
+        debug!("Client handle: Broker changed - reconnecting");
+        // Staying on the old broker would look like the switch worked; the next call retries
+        self.open().map(|_| ()).inspect_err(|_| self.close())
+    }
+
+    // The loaded config with this handle's client id and the broker chosen by the app
+    pub fn config(&self) -> CoinCrabResult<Config> {
+        let mut config = Config::load()?;
+        config.client_id = self.client_id(&config.client_id);
+        // A client id from the app is used as given, it already tells the handles apart
+        if let Some(broker) = self.broker.lock().unwrap().as_ref() {
+            config.apply(broker);
+        }
+        Ok(config)
+    }
+
+    fn open(&self) -> CoinCrabResult<Arc<MQTTClient>> {
+        if !self.network_reachable.load(Ordering::Relaxed) {
+            return Err(CoinCrabError::Mqtt("Network unreachable".to_string()));
+        }
+        let config = self.config()?;
+        debug!("Client handle: Connecting as {}", config.client_id);
+        let client = MQTTClient::with_config(config)?;
+        if let Err(e) = client.connect() {
+            client.close();
+            return Err(e);
+        }
+        let client = Arc::new(client);
+        // The old event loop would otherwise keep retrying, and take the session back
+        if let Some(previous) = self.current.lock().unwrap().replace(client.clone()) {
+            previous.close();
+        }
+        Ok(client)
+    }
+
+    // Takes the connection down cleanly and forgets it; the next call connects again. Returns
+    // false if its event loop didn't stop within the timeout.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let _connecting = self.connecting.lock().unwrap();
+        let Some(client) = self.current.lock().unwrap().take() else {
+            return true;
+        };
+        client.shutdown(timeout)
+    }
+
+    // App lifecycle hooks; without a connection there is nothing to pause and the next call
+    // connects as usual
+    pub fn background(&self) {
+        if let Some(client) = self.current() {
+            client.pause();
+        }
+    }
+
+    pub fn foreground(&self) {
+        if let Some(client) = self.current() {
+            client.resume();
+        }
+    }
+
+    // Network hint from the app, see MQTTClient::network_changed
+    pub fn network_changed(&self, reachable: bool) {
+        self.network_reachable.store(reachable, Ordering::Relaxed);
+        if let Some(client) = self.current() {
+            client.network_changed(reachable);
+        }
+    }
+
+    pub fn close(&self) {
+        if let Some(client) = self.current.lock().unwrap().take() {
+            client.close();
+        }
+    }
+}
+
+impl Drop for CoinCrabClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handles_get_distinct_client_ids() {
+        let first = CoinCrabClient::new();
+        let second = CoinCrabClient::new();
+        assert_ne!(first.client_id("ios-app"), second.client_id("ios-app"));
+        assert!(second.client_id("ios-app").starts_with("ios-app-"));
+        assert!(first.current().is_none());
+    }
+
+    #[test]
+    fn test_lifecycle_hooks_without_connection() {
+        let handle = CoinCrabClient::new();
+        handle.background();
+        handle.foreground();
+        assert!(handle.current().is_none());
+    }
+
+    #[test]
+    fn test_no_connection_while_network_unreachable() {
+        let handle = CoinCrabClient::new();
+        handle.network_changed(false);
+        assert!(matches!(handle.connected(false), Err(CoinCrabError::Mqtt(_))));
+        assert!(handle.current().is_none());
+        handle.network_changed(true);
+        assert!(handle.network_reachable.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_shutdown_without_connection() {
+        let handle = CoinCrabClient::new();
+        assert!(handle.shutdown(Duration::from_millis(100)));
+        assert!(handle.current().is_none());
+    }
+}
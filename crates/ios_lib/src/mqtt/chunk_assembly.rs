@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::types::HistoricalChunk;
+
+// A transfer with chunks still missing after this long is dropped; the waiting FFI call
+// times out and the app requests the series again
+pub const CHUNK_TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+// Bounds the memory a single transfer can claim; the server's largest series needs a handful
+const MAX_CHUNKS_PER_TRANSFER: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkProgress {
+    Pending { received: usize, total: usize },
+    // All chunks arrived; the joined series JSON
+    Complete(String),
+    // Chunk of a transfer that has since been replaced by a newer one
+    Superseded,
+    Rejected(String),
+}
+
+struct Transfer {
+    transfer_id: u64,
+    parts: Vec<Option<String>>,
+    received: usize,
+    started: Instant,
+}
+
+// Reassembles historical series the server split across chunk topics, one transfer per
+// series topic at a time. A newer transfer of the same series replaces a partial older one.
+pub struct ChunkAssembler {
+    timeout: Duration,
+    transfers: HashMap<String, Transfer>,
+}
+
+impl ChunkAssembler {
+    pub fn new(timeout: Duration) -> Self {
+        ChunkAssembler { timeout, transfers: HashMap::new() }
+    }
+
+    pub fn accept(&mut self, series_topic: &str, index: usize, total: usize, chunk: HistoricalChunk, now: Instant) -> ChunkProgress {
+        if total > MAX_CHUNKS_PER_TRANSFER || index >= total {
+            return ChunkProgress::Rejected(format!("chunk {}/{} out of range", index, total));
+        }
+        let transfer = match self.transfers.get_mut(series_topic) {
+            Some(transfer) if transfer.transfer_id > chunk.transfer_id => return ChunkProgress::Superseded,
+            Some(transfer) if transfer.transfer_id == chunk.transfer_id => {
+                if transfer.parts.len() != total {
+                    return ChunkProgress::Rejected(format!("chunk count changed from {} to {} mid-transfer", transfer.parts.len(), total));
+                }
+                transfer
+            }
+            _ => {
+                let transfer = Transfer {
+                    transfer_id: chunk.transfer_id,
+                    parts: vec![None; total],
+                    received: 0,
+                    started: now,
+                };
+                self.transfers.insert(series_topic.to_string(), transfer);
+                self.transfers.get_mut(series_topic).unwrap()
+            }
+        };
+
+        // QoS 1 may redeliver a chunk; only the first copy counts
+        if transfer.parts[index].is_none() {
+            transfer.parts[index] = Some(chunk.data);
+            transfer.received += 1;
+        }
+        if transfer.received < total {
+            return ChunkProgress::Pending { received: transfer.received, total };
+        }
+        let transfer = self.transfers.remove(series_topic).unwrap();
+        ChunkProgress::Complete(transfer.parts.into_iter().flatten().collect())
+    }
+
+    // Drop transfers that have been waiting for chunks too long; returns their series topics
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.transfers.retain(|topic, transfer| {
+            let alive = now.duration_since(transfer.started) < self.timeout;
+            if !alive {
+                expired.push(topic.clone());
+            }
+            alive
+        });
+        expired
+    }
+
+    pub fn pending_transfers(&self) -> usize {
+        self.transfers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERIES: &str = "crypto/historical/BTC/365d";
+
+    fn chunk(transfer_id: u64, data: &str) -> HistoricalChunk {
+        HistoricalChunk { transfer_id, data: data.to_string() }
+    }
+
+    #[test]
+    fn test_out_of_order_chunks_reassemble() {
+        let mut assembler = ChunkAssembler::new(CHUNK_TRANSFER_TIMEOUT);
+        let now = Instant::now();
+        assert_eq!(assembler.accept(SERIES, 2, 3, chunk(1, "ue}"), now), ChunkProgress::Pending { received: 1, total: 3 });
+        assert_eq!(assembler.accept(SERIES, 0, 3, chunk(1, "{\"ok\""), now), ChunkProgress::Pending { received: 2, total: 3 });
+        // Redelivered chunk
+        assert_eq!(assembler.accept(SERIES, 0, 3, chunk(1, "{\"ok\""), now), ChunkProgress::Pending { received: 2, total: 3 });
+        assert_eq!(assembler.accept(SERIES, 1, 3, chunk(1, ":tr"), now), ChunkProgress::Complete("{\"ok\":true}".to_string()));
+        assert_eq!(assembler.pending_transfers(), 0);
+    }
+
+    #[test]
+    fn test_partial_transfers_are_replaced_or_expired() {
+        let mut assembler = ChunkAssembler::new(CHUNK_TRANSFER_TIMEOUT);
+        let start = Instant::now();
+        assembler.accept(SERIES, 0, 2, chunk(1, "old"), start);
+
+        // A newer publish of the series replaces the partial one; stragglers of the old one are ignored
+        assembler.accept(SERIES, 0, 2, chunk(2, "ne"), start);
+        assert_eq!(assembler.accept(SERIES, 1, 2, chunk(1, "old"), start), ChunkProgress::Superseded);
+        assert!(matches!(assembler.accept(SERIES, 1, 3, chunk(2, "w"), start), ChunkProgress::Rejected(_)));
+        assert_eq!(assembler.accept(SERIES, 1, 2, chunk(2, "w"), start), ChunkProgress::Complete("new".to_string()));
+
+        assembler.accept(SERIES, 0, 2, chunk(3, "x"), start);
+        assembler.accept("crypto/historical/ETH/365d", 0, 2, chunk(3, "y"), start + Duration::from_secs(20));
+        assert_eq!(assembler.expire(start + CHUNK_TRANSFER_TIMEOUT), vec![SERIES.to_string()]);
+        assert_eq!(assembler.pending_transfers(), 1);
+        assert!(matches!(assembler.accept(SERIES, 0, 1000, chunk(4, "z"), start), ChunkProgress::Rejected(_)));
+    }
+}
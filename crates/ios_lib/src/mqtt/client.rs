@@ -1,82 +1,191 @@
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use arc_swap::ArcSwapOption;
 use std::collections::HashMap;
 use std::os::raw::c_void;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use rumqttc::{AsyncClient, QoS};
+use tracing::{debug, warn};
 
+use crate::cache::DiskCache;
 use crate::config::Config;
-use crate::types::{CryptoCurrency, HistoricalDataResult};
-use shared::debug_log;
+use crate::runtime::shared_runtime;
+use crate::types::{CryptoCurrency, DataSource, HistoricalDataResult, HistoricalFetchError, HistoricalRequestEnvelope, PrefetchHint};
+use crate::ranking::{top_coins, SortKey};
+use shared::{CoinCrabError, CoinCrabResult};
 use super::connection::ConnectionManager;
+use super::diagnostics::{DiagnosticsSnapshot, ParseDiagnostics};
+use super::data_signal::DataSignal;
+use super::subscriptions::{symbol_price_topic, SymbolPriceCallback, SymbolSubscriptions};
+use super::price_update::{diff_prices, into_raw_buffer, PriceUpdate};
+use super::request_throttle::RequestThrottle;
+use super::connection_quality::{ConnectionQuality, ConnectionQualitySnapshot};
+use super::subscription_acks::{SubscriptionAckSnapshot, SubscriptionAcks};
+use super::server_presence::{ServerPresence, ServerPresenceSnapshot};
+use super::pending_requests::PendingRequests;
+use super::historical_errors::HistoricalErrors;
+use super::lifecycle::AppLifecycle;
+use super::reachability::Reachability;
 
-// Callback function type for notifying iOS of price updates
-pub type PriceUpdateCallback = extern "C" fn(*const c_void);
+// Callback function type for notifying iOS of price updates. Receives a JSON PriceUpdate
+// (pointer + length, not NUL-terminated) that the callee owns and releases with free_price_update.
+pub type PriceUpdateCallback = extern "C" fn(*mut u8, usize);
+
+// Original register_price_update_callback signature: only signals that prices changed (always
+// with NULL), the app then reads them with get_crypto_data
+pub type PriceNotifyCallback = extern "C" fn(*const c_void);
+
+// The price callback the app registered, with either signature
+#[derive(Clone, Copy)]
+pub enum PriceListener {
+    Notify(PriceNotifyCallback),
+    Payload(PriceUpdateCallback),
+}
+
+impl PriceListener {
+    pub(crate) fn deliver(self, update: &PriceUpdate) {
+        match self {
+            PriceListener::Notify(callback) => callback(std::ptr::null()),
+            PriceListener::Payload(callback) => match serde_json::to_vec(update) {
+                Ok(bytes) => {
+                    debug!("MQTT: Delivering price update ({} bytes)", bytes.len());
+                    let (data, len) = into_raw_buffer(bytes);
+                    callback(data, len);
+                }
+                Err(e) => warn!("MQTT: Failed to serialize price update: {}", e),
+            },
+        }
+    }
+}
+
+// How long get_symbol_quote waits for the retained listings before asking for the coin alone
+const RETAINED_LISTINGS_GRACE: Duration = Duration::from_millis(500);
 
 // MQTT Client wrapper for thread-safe usage
 pub struct MQTTClient {
     pub(crate) client: Arc<AsyncClient>,
     pub(crate) runtime: Arc<Runtime>,
-    pub(crate) latest_prices: Arc<Mutex<Option<Vec<CryptoCurrency>>>>,
-    pub(crate) historical_data: Arc<Mutex<HashMap<String, HistoricalDataResult>>>,
+    pub(crate) latest_prices: Arc<ArcSwapOption<Vec<CryptoCurrency>>>,
+    pub(crate) price_source: Arc<Mutex<DataSource>>,
+    pub(crate) historical_data: Arc<RwLock<HashMap<String, HistoricalDataResult>>>,
     pub(crate) is_connected: Arc<Mutex<bool>>,
     pub(crate) connection_attempts: Arc<Mutex<u32>>,
-    pub(crate) max_retry_attempts: u32,
-    pub(crate) price_update_callback: Arc<Mutex<Option<PriceUpdateCallback>>>,
+    pub(crate) price_update_callback: Arc<Mutex<Option<PriceListener>>>,
+    pub(crate) prefetch_hints: Arc<Mutex<Vec<PrefetchHint>>>,
+    pub(crate) topic_prefix: String,
+    pub(crate) diagnostics: Arc<ParseDiagnostics>,
+    pub(crate) data_signal: Arc<DataSignal>,
+    pub(crate) data_wait_timeout: Duration,
+    pub(crate) subscriptions: Arc<SymbolSubscriptions>,
+    pub(crate) client_id: String,
+    pub(crate) request_throttle: Arc<RequestThrottle>,
+    pub(crate) quality: Arc<ConnectionQuality>,
+    pub(crate) subscription_acks: Arc<SubscriptionAcks>,
+    pub(crate) server_presence: Arc<ServerPresence>,
+    pub(crate) pending_requests: Arc<PendingRequests>,
+    pub(crate) historical_errors: Arc<HistoricalErrors>,
+    pub(crate) lifecycle: Arc<AppLifecycle>,
+    pub(crate) reachability: Arc<Reachability>,
+    // Set by close(); stops the event loop instead of reconnecting
+    pub(crate) closed: Arc<AtomicBool>,
+    // The event loop thread, joined by shutdown
+    pub(crate) event_loop: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl MQTTClient {
-    pub fn new() -> Result<Self, String> {
-        // Initialize logging first
-        shared::init_logging();
-        debug_log("MQTT: Creating new MQTTClient...");
-        
-        let rt = Runtime::new().map_err(|e| format!("Failed to create runtime: {}", e))?;
-        debug_log("MQTT: Runtime created successfully");
-        
-        // Load configuration
-        let config = Config::load()?;
-        debug_log(&format!("MQTT: Connecting to broker at {}:{}", config.broker_host, config.broker_port));
+    pub fn new() -> CoinCrabResult<Self> {
+        Self::with_config(Config::load()?)
+    }
+
+    pub(crate) fn with_config(config: Config) -> CoinCrabResult<Self> {
+        debug!("MQTT: Creating new MQTTClient...");
+        debug!("MQTT: Connecting to broker at {}:{}", config.broker_host, config.broker_port);
         
         // Create connection manager and get client
         let connection_manager = ConnectionManager::new(&config)?;
         let (client, eventloop) = connection_manager.create_client()?;
         
         let client_arc = Arc::new(client);
-        let runtime_arc = Arc::new(rt);
-        let latest_prices = Arc::new(Mutex::new(None));
-        let historical_data = Arc::new(Mutex::new(HashMap::new()));
+        let runtime_arc = shared_runtime()?;
+        let latest_prices = Arc::new(ArcSwapOption::empty());
+        let price_source = Arc::new(Mutex::new(DataSource::default()));
+        let historical_data = Arc::new(RwLock::new(HashMap::new()));
         let is_connected = Arc::new(Mutex::new(false));
         let connection_attempts = Arc::new(Mutex::new(0));
-        let max_retry_attempts = 5;  // Increased from 3 to handle slower connections
         let price_update_callback = Arc::new(Mutex::new(None));
+        let prefetch_hints = Arc::new(Mutex::new(Vec::new()));
+        let diagnostics = Arc::new(ParseDiagnostics::new());
+        let data_signal = Arc::new(DataSignal::new());
+        let subscriptions = Arc::new(SymbolSubscriptions::new());
+        let request_throttle = Arc::new(RequestThrottle::new());
+        let quality = Arc::new(ConnectionQuality::new());
+        let subscription_acks = Arc::new(SubscriptionAcks::new());
+        let server_presence = Arc::new(ServerPresence::new());
+        let pending_requests = Arc::new(PendingRequests::new());
+        let historical_errors = Arc::new(HistoricalErrors::new());
+        let lifecycle = Arc::new(AppLifecycle::new());
+        let reachability = Arc::new(Reachability::new());
+        let closed = Arc::new(AtomicBool::new(false));
         
         // Start the connection manager event loop
-        connection_manager.start_event_loop(
+        let event_loop = connection_manager.start_event_loop(
             eventloop,
             client_arc.clone(),
             runtime_arc.clone(),
             latest_prices.clone(),
+            price_source.clone(),
             historical_data.clone(),
             is_connected.clone(),
             connection_attempts.clone(),
             price_update_callback.clone(),
+            prefetch_hints.clone(),
+            diagnostics.clone(),
+            data_signal.clone(),
+            subscriptions.clone(),
+            request_throttle.clone(),
+            quality.clone(),
+            subscription_acks.clone(),
+            server_presence.clone(),
+            pending_requests.clone(),
+            historical_errors.clone(),
+            lifecycle.clone(),
+            reachability.clone(),
+            closed.clone(),
         );
         
-        debug_log("MQTT: MQTTClient creation completed successfully");
+        debug!("MQTT: MQTTClient creation completed successfully");
         Ok(MQTTClient {
             client: client_arc,
             runtime: runtime_arc,
             latest_prices,
+            price_source,
             historical_data,
             is_connected,
             connection_attempts,
-            max_retry_attempts,
             price_update_callback,
+            prefetch_hints,
+            topic_prefix: config.topic_prefix,
+            diagnostics,
+            data_signal,
+            data_wait_timeout: config.data_wait_timeout,
+            subscriptions,
+            client_id: config.client_id,
+            request_throttle,
+            quality,
+            subscription_acks,
+            server_presence,
+            pending_requests,
+            historical_errors,
+            lifecycle,
+            reachability,
+            closed,
+            event_loop: Mutex::new(Some(event_loop)),
         })
     }
     
-    pub fn connect(&self) -> Result<(), String> {
-        debug_log("MQTT: Starting synchronous connection...");
+    pub fn connect(&self) -> CoinCrabResult<()> {
+        debug!("MQTT: Starting synchronous connection...");
 
         // Wait for connection to be established with timeout
         let start_time = std::time::Instant::now();
@@ -84,7 +193,7 @@ impl MQTTClient {
 
         while start_time.elapsed() < timeout {
             if self.is_connected() {
-                debug_log("MQTT: Connection established successfully");
+                debug!("MQTT: Connection established successfully");
                 return Ok(());
             }
             // Small delay to avoid busy polling
@@ -95,19 +204,339 @@ impl MQTTClient {
         let attempts = self.get_connection_attempts();
         let error_msg = format!("MQTT connection timeout after {:.1}s ({} attempts)",
             timeout.as_secs_f32(), attempts);
-        debug_log(&error_msg);
-        Err(error_msg)
+        debug!("{}", error_msg);
+        Err(CoinCrabError::Timeout(error_msg))
     }
     
     pub fn get_latest_prices(&self) -> Option<Vec<CryptoCurrency>> {
-        self.latest_prices.lock().unwrap().clone()
+        self.latest_prices.load_full().map(|prices| prices.as_ref().clone())
+    }
+    
+    // As stamped on the last price snapshot received
+    pub fn get_price_source(&self) -> DataSource {
+        self.price_source.lock().unwrap().clone()
     }
     
     pub fn get_historical_data(&self, symbol: &str, timeframe: &str) -> Option<HistoricalDataResult> {
         let topic = format!("crypto/historical/{}/{}", symbol.to_uppercase(), timeframe);
-        self.historical_data.lock().unwrap().get(&topic).cloned()
+        self.historical_data.read().unwrap().get(&topic).cloned()
+    }
+    
+    // Handle for blocking until data arrives without holding the global client lock
+    pub fn data_waiter(&self) -> DataWaiter {
+        DataWaiter {
+            latest_prices: self.latest_prices.clone(),
+            historical_data: self.historical_data.clone(),
+            signal: self.data_signal.clone(),
+            pending_requests: self.pending_requests.clone(),
+            historical_errors: self.historical_errors.clone(),
+            timeout: self.data_wait_timeout,
+        }
+    }
+    
+    pub fn get_prefetch_hints(&self) -> Vec<PrefetchHint> {
+        self.prefetch_hints.lock().unwrap().clone()
+    }
+    
+    pub fn get_diagnostics(&self) -> DiagnosticsSnapshot {
+        self.diagnostics.snapshot()
+    }
+    
+    pub fn get_connection_quality(&self) -> ConnectionQualitySnapshot {
+        self.quality.snapshot(self.is_connected(), Instant::now())
+    }
+    
+    // Which subscriptions the broker granted; a rejected one never delivers data
+    pub fn get_subscription_status(&self) -> SubscriptionAckSnapshot {
+        self.subscription_acks.snapshot()
+    }
+    
+    // Whether the server is up, from its retained status and our broker connection
+    pub fn get_server_presence(&self) -> ServerPresenceSnapshot {
+        self.server_presence.snapshot(self.is_connected(), Instant::now())
+    }
+    
+    // Hinted series that are not yet in the local historical cache
+    pub fn missing_prefetch_hints(&self) -> Vec<PrefetchHint> {
+        let hints = self.get_prefetch_hints();
+        let hist_map = self.historical_data.read().unwrap();
+        filter_missing_hints(hints, &hist_map)
+    }
+    
+    // Ask the server to (re)publish one historical series. Returns the request id its
+    // response will carry; see DataWaiter::wait_for_historical_response.
+    pub fn request_historical_data(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<String> {
+        self.send_historical_request("requests/historical", symbol, timeframe, None, None)
+    }
+    
+    // Same, but the server downsamples the series to at most max_points points (e.g. one per
+    // pixel of the chart) and sends it to this client only
+    pub fn request_historical_data_sampled(&self, symbol: &str, timeframe: &str, max_points: usize) -> CoinCrabResult<String> {
+        self.send_historical_request("requests/historical", symbol, timeframe, None, Some(max_points))
+    }
+    
+    // Low-priority variant for cache warming; the server serves these after any interactive request
+    pub fn request_background_historical_data(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<String> {
+        self.send_historical_request("requests/historical/background", symbol, timeframe, None, None)
+    }
+    
+    // Pull-to-refresh: ask the server to fetch the listings now. The server ignores the request
+    // if its prices are already fresh; new prices arrive on crypto/prices/latest as usual.
+    pub fn request_price_refresh(&self) -> CoinCrabResult<()> {
+        self.send_request("requests/refresh-prices", "latest")
     }
     
+    // Logos arrive retained on crypto/assets/logos/{SYMBOL} and go into the disk cache. Subscribes
+    // to the symbols missing from it and asks the server for them in case it hasn't published
+    // them yet. Logo subscriptions aren't restored after a reconnect; call again for any still
+    // missing. Returns the number of logos requested.
+    pub fn request_logos(&self, symbols: &[&str]) -> CoinCrabResult<usize> {
+        let cache = DiskCache::default_location();
+        let mut missing: Vec<String> = Vec::new();
+        for symbol in symbols.iter().map(|symbol| symbol.trim().to_uppercase()) {
+            if !symbol.is_empty() && !missing.contains(&symbol) && cache.logo_path(&symbol).is_none() {
+                missing.push(symbol);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        for symbol in &missing {
+            let topic = shared::with_topic_prefix(&self.topic_prefix, &shared::logo_topic(symbol));
+            self.runtime.block_on(self.subscription_acks.subscribe(&self.client, &topic, QoS::AtLeastOnce))
+                .map_err(|e| CoinCrabError::Mqtt(format!("Failed to subscribe to {}: {}", topic, e)))?;
+        }
+        let payload = serde_json::to_string(&missing)
+            .map_err(|e| CoinCrabError::Parse(format!("Failed to serialize request: {}", e)))?;
+        self.send_request("requests/logos", &payload)?;
+        Ok(missing.len())
+    }
+    
+    // Requests go to this client's own topic so the server can rate limit per client.
+    // While the server has us throttled they fail locally without reaching the broker.
+    fn send_request(&self, request: &str, payload: &str) -> CoinCrabResult<()> {
+        if let Some(remaining) = self.request_throttle.remaining(Instant::now()) {
+            debug!("MQTT: Not sending {} - rate limited for another {:.1}s", payload, remaining.as_secs_f64());
+            return Err(CoinCrabError::RateLimited(format!("Retry after {}s", remaining.as_secs_f64().ceil())));
+        }
+        let topic = shared::client_topic(&self.client_id, request);
+        self.runtime.block_on(self.publish_message(&topic, payload))
+    }
+    
+    // Sent as a JSON envelope the server answers on crypto/responses/{client_id}/{request_id}.
+    // Sent requests are timed until their series arrives, for the connection quality score.
+    fn send_historical_request(&self, request: &str, symbol: &str, timeframe: &str, after: Option<f64>, max_points: Option<usize>) -> CoinCrabResult<String> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let envelope = HistoricalRequestEnvelope {
+            request_id: request_id.clone(),
+            symbol: symbol.to_string(),
+            timeframe: timeframe.to_string(),
+            after,
+            max_points,
+            start: None,
+            end: None,
+            interval: None,
+            reply_to: shared::response_topic(&self.client_id, &request_id),
+        };
+        let series_topic = format!("crypto/historical/{}/{}", symbol.to_uppercase(), timeframe);
+        self.send_envelope(request, &envelope, &series_topic)
+    }
+    
+    fn send_envelope(&self, request: &str, envelope: &HistoricalRequestEnvelope, series_topic: &str) -> CoinCrabResult<String> {
+        let payload = serde_json::to_string(envelope)
+            .map_err(|e| CoinCrabError::Parse(format!("Failed to serialize request: {}", e)))?;
+        self.pending_requests.sent(&envelope.request_id, Instant::now());
+        if let Err(e) = self.send_request(request, &payload) {
+            self.pending_requests.forget(&envelope.request_id);
+            return Err(e);
+        }
+        self.quality.request_sent(series_topic, Instant::now());
+        Ok(envelope.request_id.clone())
+    }
+    
+    // History of a custom window ("last March"): start and end as unix seconds or RFC 3339, end
+    // defaulting to now and the interval to one that fits the window. The series arrives for
+    // this client only and is collected with take_historical_range once the request succeeds.
+    pub fn request_historical_range(&self, symbol: &str, start: &str, end: Option<&str>, interval: Option<&str>) -> CoinCrabResult<String> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let envelope = HistoricalRequestEnvelope {
+            request_id: request_id.clone(),
+            symbol: symbol.to_string(),
+            timeframe: String::new(),
+            after: None,
+            max_points: None,
+            start: Some(start.to_string()),
+            end: end.map(str::to_string),
+            interval: interval.map(str::to_string),
+            reply_to: shared::response_topic(&self.client_id, &request_id),
+        };
+        let range_topic = shared::historical_range_topic(symbol, &self.client_id, &request_id);
+        self.send_envelope("requests/historical", &envelope, &range_topic)
+    }
+    
+    // The series of a range request; it isn't kept once taken
+    pub fn take_historical_range(&self, symbol: &str, request_id: &str) -> Option<HistoricalDataResult> {
+        let topic = shared::historical_range_topic(symbol, &self.client_id, request_id);
+        self.historical_data.write().unwrap().remove(&topic)
+    }
+    
+    // Ask the server only for points newer than the cached series; falls back to a full request
+    pub fn request_historical_update(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<String> {
+        let last_timestamp = self.get_historical_data(symbol, timeframe)
+            .filter(|data| data.success)
+            .and_then(|data| data.data.iter().map(|p| p.timestamp).reduce(f64::max));
+        self.send_historical_request("requests/historical", symbol, timeframe, last_timestamp, None)
+    }
+    
+    // Start receiving crypto/prices/{symbol} updates. Only the first subscriber for a symbol
+    // subscribes on the broker; the subscription is restored automatically after a reconnect.
+    pub fn subscribe_symbol(&self, symbol: &str) -> CoinCrabResult<()> {
+        if !self.subscriptions.add(symbol) {
+            debug!("MQTT: Already subscribed to {}, added a reference", symbol.to_uppercase());
+            return Ok(());
+        }
+        let topic = shared::with_topic_prefix(&self.topic_prefix, &symbol_price_topic(symbol));
+        debug!("MQTT: Subscribing to {}", topic);
+        self.runtime.block_on(self.subscription_acks.subscribe(&self.client, &topic, QoS::AtMostOnce)).map_err(|e| {
+            self.subscriptions.remove(symbol);
+            CoinCrabError::Mqtt(format!("Failed to subscribe to {}: {}", topic, e))
+        })
+    }
+    
+    // Drop one reference; the broker subscription goes once the last subscriber has left
+    pub fn unsubscribe_symbol(&self, symbol: &str) -> CoinCrabResult<()> {
+        if !self.subscriptions.remove(symbol) {
+            return Ok(());
+        }
+        let topic = shared::with_topic_prefix(&self.topic_prefix, &symbol_price_topic(symbol));
+        debug!("MQTT: Unsubscribing from {}", topic);
+        self.subscription_acks.unsubscribed(&topic);
+        self.runtime.block_on(self.client.unsubscribe(&topic))
+            .map_err(|e| CoinCrabError::Mqtt(format!("Failed to unsubscribe from {}: {}", topic, e)))
+    }
+    
+    pub fn get_symbol_price(&self, symbol: &str) -> Option<CryptoCurrency> {
+        self.subscriptions.latest(symbol)
+    }
+    
+    // One coin's quote from what this client already has: the per-symbol cache, then the listings
+    pub fn cached_symbol_quote(&self, symbol: &str) -> Option<CryptoCurrency> {
+        self.subscriptions.latest(symbol).or_else(|| {
+            let prices = self.latest_prices.load_full()?;
+            prices.iter().find(|coin| coin.symbol.eq_ignore_ascii_case(symbol)).cloned()
+        })
+    }
+
+    // Like cached_symbol_quote, but a coin missing from the cache (e.g. one outside the top 100)
+    // is fetched from its retained crypto/prices/{symbol} topic. None if nothing arrived within
+    // the data wait timeout.
+    pub fn get_symbol_quote(&self, symbol: &str) -> CoinCrabResult<Option<CryptoCurrency>> {
+        let waiter = self.data_waiter();
+        // A fresh connection may still be receiving the retained listings
+        let grace = if self.latest_prices.load().is_some() { Duration::ZERO } else { RETAINED_LISTINGS_GRACE.min(waiter.timeout()) };
+        if let Some(quote) = self.data_signal.wait_until(grace, || self.cached_symbol_quote(symbol)) {
+            return Ok(Some(quote));
+        }
+        debug!("MQTT: No cached quote for {}, subscribing to its topic", symbol.to_uppercase());
+        self.subscribe_symbol(symbol)?;
+        let quote = self.data_signal.wait_until(waiter.timeout(), || self.subscriptions.latest(symbol));
+        if let Err(e) = self.unsubscribe_symbol(symbol) {
+            warn!("MQTT: {}", e);
+        }
+        Ok(quote)
+    }
+
+    pub fn set_symbol_price_callback(&self, callback: Option<SymbolPriceCallback>) {
+        debug!("MQTT: Setting symbol price callback");
+        self.subscriptions.set_callback(callback);
+    }
+    
+    // Disconnects from the broker for good: the event loop stops instead of reconnecting
+    pub fn close(&self) {
+        if self.closed.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        debug!("MQTT: Closing client {}", self.client_id);
+        *self.is_connected.lock().unwrap() = false;
+        let _ = self.client.try_disconnect();
+        // A paused event loop, or one waiting for the network, only notices once woken
+        self.lifecycle.resume();
+        self.reachability.set(true);
+    }
+
+    // Hint from the app's network monitor. Coming back resets the attempts and retries right
+    // away instead of waiting out the backoff; going away stops retrying until it's back.
+    pub fn network_changed(&self, reachable: bool) {
+        debug!("MQTT: Network {}", if reachable { "reachable" } else { "unreachable" });
+        if reachable {
+            self.reset_connection_attempts();
+        }
+        self.reachability.set(reachable);
+    }
+
+    // The app went to the background: disconnect and hold the event loop until resume. Caches,
+    // callbacks and symbol subscriptions are kept for when the app comes back.
+    pub fn pause(&self) {
+        if self.closed.load(Ordering::Relaxed) || !self.lifecycle.pause() {
+            return;
+        }
+        debug!("MQTT: Pausing client {}", self.client_id);
+        *self.is_connected.lock().unwrap() = false;
+        if let Err(e) = self.client.try_disconnect() {
+            warn!("MQTT: Could not request the disconnect for pausing: {}", e);
+        }
+    }
+
+    // Back in the foreground: the event loop reconnects and resubscribes, which brings the
+    // retained prices again, and the server is asked for fresh listings on top
+    pub fn resume(&self) {
+        if !self.lifecycle.resume() {
+            return;
+        }
+        debug!("MQTT: Resuming client {}", self.client_id);
+        if let Err(e) = self.request_price_refresh() {
+            debug!("MQTT: Price refresh on resume not sent: {}", e);
+        }
+    }
+
+    // Unlike close, unsubscribes and disconnects cleanly, then waits for the event loop thread
+    // to finish. Returns false if it was still running when the timeout ran out (e.g. while
+    // waiting to reconnect); it stops on its own once it wakes up.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        // A paused client is already disconnected
+        if !self.closed.load(Ordering::Relaxed) && !self.lifecycle.is_paused() {
+            debug!("MQTT: Shutting down client {}", self.client_id);
+            let topics: Vec<String> = self.subscription_acks.snapshot().subscriptions.into_iter().map(|ack| ack.topic).collect();
+            let sent = self.runtime.block_on(tokio::time::timeout(timeout, async {
+                for topic in &topics {
+                    self.client.unsubscribe(topic).await?;
+                    self.subscription_acks.unsubscribed(topic);
+                }
+                self.client.disconnect().await
+            }));
+            if !matches!(sent, Ok(Ok(()))) {
+                warn!("MQTT: Could not unsubscribe and disconnect cleanly within {:?}", timeout);
+            }
+        }
+        let Some(event_loop) = self.event_loop.lock().unwrap().take() else {
+            self.close();
+            return true;
+        };
+        while !event_loop.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // Stops the event loop if the disconnect never went out
+        self.close();
+        if !event_loop.is_finished() {
+            warn!("MQTT: Event loop of {} still running after {:?}", self.client_id, timeout);
+            return false;
+        }
+        let _ = event_loop.join();
+        debug!("MQTT: Client {} shut down", self.client_id);
+        true
+    }
+
     pub fn is_connected(&self) -> bool {
         *self.is_connected.lock().unwrap()
     }
@@ -117,42 +546,124 @@ impl MQTTClient {
     }
     
     pub fn reset_connection_attempts(&self) {
-        debug_log("MQTT: Resetting connection attempts counter");
+        debug!("MQTT: Resetting connection attempts counter");
         *self.connection_attempts.lock().unwrap() = 0;
     }
     
-    pub fn has_exceeded_max_retries(&self) -> bool {
-        *self.connection_attempts.lock().unwrap() > self.max_retry_attempts
-    }
-    
-    pub async fn publish_message(&self, topic: &str, payload: &str) -> Result<(), String> {
-        debug_log(&format!("MQTT: Publishing to topic: {}", topic));
+    pub async fn publish_message(&self, topic: &str, payload: &str) -> CoinCrabResult<()> {
+        let topic = &shared::with_topic_prefix(&self.topic_prefix, topic);
+        debug!("MQTT: Publishing to topic: {}", topic);
         
         match self.client.publish(topic, QoS::AtLeastOnce, false, payload).await {
             Ok(_) => {
-                debug_log(&format!("MQTT: Successfully published to {}", topic));
+                debug!("MQTT: Successfully published to {}", topic);
                 Ok(())
             }
             Err(e) => {
-                debug_log(&format!("MQTT: Failed to publish to {}: {}", topic, e));
-                Err(format!("Failed to publish: {}", e))
+                warn!("MQTT: Failed to publish to {}: {}", topic, e);
+                Err(CoinCrabError::Mqtt(format!("Failed to publish: {}", e)))
             }
         }
     }
     
-    pub fn set_price_update_callback(&self, callback: PriceUpdateCallback) {
-        debug_log("MQTT: Setting price update callback");
-        *self.price_update_callback.lock().unwrap() = Some(callback);
+    pub fn set_price_update_callback(&self, listener: PriceListener) {
+        debug!("MQTT: Setting price update callback");
+        *self.price_update_callback.lock().unwrap() = Some(listener);
     }
     
+    // Deliver the full cached listing as one update, e.g. right after registering the callback
     pub fn trigger_price_update_callback(&self) {
-        if let Some(callback) = *self.price_update_callback.lock().unwrap() {
-            debug_log("MQTT: Triggering price update callback");
-            callback(std::ptr::null());
-        }
+        let Some(listener) = *self.price_update_callback.lock().unwrap() else {
+            return;
+        };
+        let Some(prices) = self.get_latest_prices() else {
+            return;
+        };
+        debug!("MQTT: Triggering price update callback");
+        listener.deliver(&diff_prices(None, &prices));
     }
 }
 
+// Blocks the calling thread until the event loop caches the wanted data or the configured timeout passes
+#[derive(Clone)]
+pub struct DataWaiter {
+    latest_prices: Arc<ArcSwapOption<Vec<CryptoCurrency>>>,
+    historical_data: Arc<RwLock<HashMap<String, HistoricalDataResult>>>,
+    signal: Arc<DataSignal>,
+    pending_requests: Arc<PendingRequests>,
+    historical_errors: Arc<HistoricalErrors>,
+    timeout: Duration,
+}
+
+impl DataWaiter {
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    
+    pub fn wait_for_latest_prices(&self) -> Option<Vec<CryptoCurrency>> {
+        self.signal.wait_until(self.timeout, || self.latest_prices.load_full().map(|prices| prices.as_ref().clone()))
+    }
+
+    // Like wait_for_latest_prices, but only the first n coins by the key
+    pub fn wait_for_top_coins(&self, n: usize, key: SortKey) -> Option<Vec<CryptoCurrency>> {
+        self.signal.wait_until(self.timeout, || self.latest_prices.load().as_ref().map(|prices| top_coins(prices, n, key)))
+    }
+    
+    pub fn wait_for_historical_data(&self, symbol: &str, timeframe: &str, timeout: Duration) -> Option<HistoricalDataResult> {
+        let topic = format!("crypto/historical/{}/{}", symbol.to_uppercase(), timeframe);
+        self.signal.wait_until(timeout, || self.historical_data.read().unwrap().get(&topic).cloned())
+    }
+    
+    // Like wait_for_historical_data, but stops early with the reason if the server answers the
+    // request with an error, or reports that fetching the series failed after it was sent.
+    // The request is forgotten either way.
+    pub fn wait_for_historical_response(&self, symbol: &str, timeframe: &str, request_id: &str, timeout: Duration) -> Option<Result<HistoricalDataResult, HistoricalFetchError>> {
+        let topic = format!("crypto/historical/{}/{}", symbol.to_uppercase(), timeframe);
+        let sent_at = self.pending_requests.sent_at(request_id).unwrap_or_else(Instant::now);
+        let result = self.signal.wait_until(timeout, || {
+            if let Some(data) = self.historical_data.read().unwrap().get(&topic).cloned() {
+                return Some(Ok(data));
+            }
+            if let Some(response) = self.pending_requests.response(request_id).filter(|response| !response.success) {
+                return Some(Err(HistoricalFetchError {
+                    symbol: symbol.to_uppercase(),
+                    timeframe: timeframe.to_string(),
+                    error: response.error.unwrap_or_else(|| "Server failed the request".to_string()),
+                    retry_after_seconds: response.retry_after_seconds,
+                    failed_at: chrono::Utc::now().timestamp(),
+                }));
+            }
+            self.historical_errors.since(&topic, sent_at).map(Err)
+        });
+        self.pending_requests.forget(request_id);
+        result
+    }
+}
+
+fn filter_missing_hints(
+    hints: Vec<PrefetchHint>,
+    hist_map: &HashMap<String, HistoricalDataResult>,
+) -> Vec<PrefetchHint> {
+    hints
+        .into_iter()
+        .filter(|hint| {
+            let topic = format!("crypto/historical/{}/{}", hint.symbol.to_uppercase(), hint.timeframe);
+            !hist_map.get(&topic).map(|data| data.success).unwrap_or(false)
+        })
+        .collect()
+}
+
+// Append points newer than the existing series; returns how many were added
+pub(crate) fn merge_historical_delta(existing: &mut HistoricalDataResult, delta: HistoricalDataResult) -> usize {
+    if !delta.success {
+        return 0;
+    }
+    let last = existing.data.iter().map(|p| p.timestamp).reduce(f64::max).unwrap_or(f64::MIN);
+    let before = existing.data.len();
+    existing.data.extend(delta.data.into_iter().filter(|p| p.timestamp > last));
+    existing.data.len() - before
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +685,7 @@ mod tests {
                     volume_24h: 50000000000.0,
                     last_updated: "2024-01-01T00:00:00Z".to_string(),
                 },
+                converted: Default::default(),
             },
         }
     }
@@ -181,12 +693,14 @@ mod tests {
     #[test]
     fn test_price_update_callback_type() {
         // Test that the PriceUpdateCallback type is correctly defined
-        extern "C" fn dummy_callback(_data: *const c_void) {
+        extern "C" fn dummy_callback(_data: *mut u8, _len: usize) {
             // Dummy callback for type testing
         }
         
         let _callback: PriceUpdateCallback = dummy_callback;
-        assert!(true); // If this compiles, the type is correct
+        
+        extern "C" fn dummy_notify(_context: *const c_void) {}
+        let _notify: PriceNotifyCallback = dummy_notify;
     }
 
     #[test]
@@ -196,14 +710,13 @@ mod tests {
         
         let _client_type = std::any::type_name::<Arc<AsyncClient>>();
         let _runtime_type = std::any::type_name::<Arc<Runtime>>();
-        let _prices_type = std::any::type_name::<Arc<Mutex<Option<Vec<CryptoCurrency>>>>>();
-        let _historical_type = std::any::type_name::<Arc<Mutex<HashMap<String, HistoricalDataResult>>>>();
+        let _prices_type = std::any::type_name::<Arc<ArcSwapOption<Vec<CryptoCurrency>>>>();
+        let _historical_type = std::any::type_name::<Arc<RwLock<HashMap<String, HistoricalDataResult>>>>();
         let _connected_type = std::any::type_name::<Arc<Mutex<bool>>>();
         let _attempts_type = std::any::type_name::<Arc<Mutex<u32>>>();
-        let _callback_type = std::any::type_name::<Arc<Mutex<Option<PriceUpdateCallback>>>>();
+        let _callback_type = std::any::type_name::<Arc<Mutex<Option<PriceListener>>>>();
         
         // If we reach here, all field types are correct
-        assert!(true);
     }
 
     #[test]
@@ -229,6 +742,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_filter_missing_hints() {
+        let hint = |symbol: &str, timeframe: &str| PrefetchHint {
+            symbol: symbol.to_string(),
+            timeframe: timeframe.to_string(),
+            cached_at: 1704067200,
+        };
+        let hints = vec![hint("BTC", "24h"), hint("ETH", "7d"), hint("SOL", "24h")];
+
+        let mut hist_map: HashMap<String, HistoricalDataResult> = HashMap::new();
+        hist_map.insert("crypto/historical/BTC/24h".to_string(), HistoricalDataResult {
+            success: true,
+            data: vec![],
+            error: None,
+            symbol: Some("BTC".to_string()),
+            timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        });
+        hist_map.insert("crypto/historical/SOL/24h".to_string(), HistoricalDataResult {
+            success: false,
+            data: vec![],
+            error: Some("No historical data points found".to_string()),
+            symbol: Some("SOL".to_string()),
+            timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        });
+
+        let missing = filter_missing_hints(hints, &hist_map);
+        let symbols: Vec<&str> = missing.iter().map(|h| h.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["ETH", "SOL"]);
+    }
+
+    #[test]
+    fn test_merge_historical_delta() {
+        let series = |timestamps: &[f64]| HistoricalDataResult {
+            success: true,
+            data: timestamps.iter().map(|&timestamp| shared::HistoricalDataPoint {
+                timestamp,
+                price: 1.0,
+                volume: None,
+            }).collect(),
+            error: None,
+            symbol: Some("BTC".to_string()),
+            timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        };
+
+        let mut existing = series(&[100.0, 200.0]);
+        // Overlapping points are skipped, newer ones appended
+        assert_eq!(merge_historical_delta(&mut existing, series(&[200.0, 300.0, 400.0])), 2);
+        assert_eq!(existing.data.len(), 4);
+        assert_eq!(existing.data[3].timestamp, 400.0);
+
+        let mut failed = series(&[500.0]);
+        failed.success = false;
+        assert_eq!(merge_historical_delta(&mut existing, failed), 0);
+    }
+
     #[test]
     fn test_max_retry_attempts_constant() {
         // Test that the max retry attempts constant is reasonable
@@ -248,14 +825,13 @@ mod tests {
         assert_eq!(crypto.quote.usd.price, 50000.0);
         
         // Test that we can put it in a Vec (as used by latest_prices)
-        let prices = vec![crypto.clone()];
+        let prices = [crypto.clone()];
         assert_eq!(prices.len(), 1);
         assert_eq!(prices[0].symbol, "BTC");
         
         // Test that we can put it in an Option
         let maybe_prices: Option<Vec<CryptoCurrency>> = Some(vec![crypto]);
-        assert!(maybe_prices.is_some());
-        assert_eq!(maybe_prices.unwrap()[0].symbol, "BTC");
+        assert_eq!(maybe_prices.map(|p| p[0].symbol.clone()), Some("BTC".to_string()));
     }
 
     #[test]
@@ -267,6 +843,9 @@ mod tests {
             error: None,
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
         };
         
         assert!(historical_data.success);
@@ -319,9 +898,9 @@ mod tests {
         
         let qos = QoS::AtLeastOnce;
         match qos {
-            QoS::AtMostOnce => assert!(false, "Should not use AtMostOnce"),
-            QoS::AtLeastOnce => assert!(true, "Correct QoS setting"),
-            QoS::ExactlyOnce => assert!(false, "Should not use ExactlyOnce for performance reasons"),
+            QoS::AtMostOnce => panic!("Should not use AtMostOnce"),
+            QoS::AtLeastOnce => {},
+            QoS::ExactlyOnce => panic!("Should not use ExactlyOnce for performance reasons"),
         }
     }
 
@@ -330,13 +909,12 @@ mod tests {
         // Test that debug logging doesn't panic when called
         // We can't easily test the actual output, but we can verify it doesn't crash
         
-        shared::debug_log("Test MQTT client creation");
-        shared::debug_log("Test MQTT connection");
-        shared::debug_log("Test MQTT publish");
-        shared::debug_log("Test MQTT callback registration");
+        debug!("Test MQTT client creation");
+        debug!(broker = "localhost:1883", "Test MQTT connection");
+        debug!(topic = "crypto/prices/latest", bytes = 128, "Test MQTT publish");
+        debug!("Test MQTT callback registration");
         
         // If we reach here, debug logging works
-        assert!(true);
     }
 
     #[test]
@@ -344,8 +922,9 @@ mod tests {
         // Test the callback management logic
         use std::sync::{Arc, Mutex};
         
-        extern "C" fn test_callback(_data: *const c_void) {
-            // Test callback function
+        extern "C" fn test_callback(data: *mut u8, len: usize) {
+            // Test callback function: takes ownership of the payload
+            unsafe { crate::mqtt::price_update::free_raw_buffer(data, len) };
         }
         
         let callback_storage: Arc<Mutex<Option<PriceUpdateCallback>>> = Arc::new(Mutex::new(None));
@@ -356,14 +935,14 @@ mod tests {
         
         // Test triggering callback (should not panic)
         if let Some(callback) = *callback_storage.lock().unwrap() {
-            callback(std::ptr::null());
+            let (data, len) = into_raw_buffer(b"{}".to_vec());
+            callback(data, len);
         }
         
         // Test clearing callback
         *callback_storage.lock().unwrap() = None;
         assert!(callback_storage.lock().unwrap().is_none());
         
-        assert!(true);
     }
 
     #[test]
@@ -373,13 +952,12 @@ mod tests {
         
         assert_send_sync::<Arc<AsyncClient>>();
         assert_send_sync::<Arc<Runtime>>();
-        assert_send_sync::<Arc<Mutex<Option<Vec<CryptoCurrency>>>>>();
-        assert_send_sync::<Arc<Mutex<HashMap<String, HistoricalDataResult>>>>();
+        assert_send_sync::<Arc<ArcSwapOption<Vec<CryptoCurrency>>>>();
+        assert_send_sync::<Arc<RwLock<HashMap<String, HistoricalDataResult>>>>();
         assert_send_sync::<Arc<Mutex<bool>>>();
         assert_send_sync::<Arc<Mutex<u32>>>();
         
         // If this compiles, all types are properly thread-safe
-        assert!(true);
     }
 
     #[test]
@@ -390,13 +968,13 @@ mod tests {
         let result: Result<String, String> = Ok("success".to_string());
         match result {
             Ok(value) => assert_eq!(value, "success"),
-            Err(_) => assert!(false, "Should not error"),
+            Err(_) => panic!("Should not error"),
         }
         
         // Test Result pattern for connection errors
         let error_result: Result<(), String> = Err("Connection failed".to_string());
         match error_result {
-            Ok(_) => assert!(false, "Should be an error"),
+            Ok(_) => panic!("Should be an error"),
             Err(e) => assert!(e.contains("Connection failed")),
         }
         
@@ -405,7 +983,6 @@ mod tests {
         assert!(maybe_data.is_none());
         
         let some_data: Option<Vec<CryptoCurrency>> = Some(vec![create_mock_crypto_currency()]);
-        assert!(some_data.is_some());
-        assert_eq!(some_data.unwrap().len(), 1);
+        assert_eq!(some_data.map(|d| d.len()), Some(1));
     }
 }
\ No newline at end of file
@@ -1,107 +1,237 @@
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use arc_swap::ArcSwapOption;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
-use rumqttc::{MqttOptions, AsyncClient, EventLoop, Event, Packet, QoS};
-use log::{info, warn, error};
+use rumqttc::{MqttOptions, AsyncClient, EventLoop, Event, Outgoing, Packet, QoS, Transport};
+use tracing::{info, warn, error, debug};
 
-use crate::config::Config;
-use crate::types::{CryptoCurrency, HistoricalDataResult};
-use shared::debug_log;
+use crate::config::{Config, ReconnectPolicy, TlsSettings};
+use crate::types::{CryptoCurrency, DataSource, HistoricalDataResult, PrefetchHint};
+use shared::{ClientCapabilities, CoinCrabError, CoinCrabResult, PayloadCodec, PAYLOAD_SCHEMA_VERSION};
 use super::message_handler::MessageHandler;
-use super::client::PriceUpdateCallback;
+use super::diagnostics::ParseDiagnostics;
+use super::data_signal::DataSignal;
+use super::subscriptions::{symbol_price_topic, SymbolSubscriptions};
+use super::client::PriceListener;
+use super::request_throttle::RequestThrottle;
+use super::connection_quality::ConnectionQuality;
+use super::subscription_acks::SubscriptionAcks;
+use super::server_presence::ServerPresence;
+use super::pending_requests::PendingRequests;
+use super::historical_errors::HistoricalErrors;
+use super::lifecycle::AppLifecycle;
+use super::reachability::Reachability;
+use crate::offline::OfflineStore;
 
 pub struct ConnectionManager {
     config: Config,
 }
 
 impl ConnectionManager {
-    pub fn new(config: &Config) -> Result<Self, String> {
+    pub fn new(config: &Config) -> CoinCrabResult<Self> {
         Ok(ConnectionManager {
             config: config.clone(),
         })
     }
     
-    pub fn create_client(&self) -> Result<(AsyncClient, EventLoop), String> {
-        let mut mqttoptions = MqttOptions::new("rust-ios-client", &self.config.broker_host, self.config.broker_port);
+    pub fn create_client(&self) -> CoinCrabResult<(AsyncClient, EventLoop)> {
+        let mut mqttoptions = MqttOptions::new(&self.config.client_id, &self.config.broker_host, self.config.broker_port);
         mqttoptions.set_keep_alive(Duration::from_secs(60));
         mqttoptions.set_clean_session(true); // Use clean session for faster connections
         mqttoptions.set_max_packet_size(102400, 102400); // Match broker config
-        debug_log(&format!("MQTT: Configured MQTT options for {}:{} (keep_alive=60s, clean_session=true, max_packet=102400)",
-            self.config.broker_host, self.config.broker_port));
+        if let Some(tls) = &self.config.tls {
+            mqttoptions.set_transport(build_tls_transport(tls)?);
+            debug!("MQTT: Using TLS transport");
+        }
+        debug!("MQTT: Configured MQTT options for {}:{} (keep_alive=60s, clean_session=true, max_packet=102400)",
+            self.config.broker_host, self.config.broker_port);
         
         let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
-        debug_log("MQTT: Created async client and event loop");
+        debug!("MQTT: Created async client and event loop");
         
         Ok((client, eventloop))
     }
     
+    #[allow(clippy::too_many_arguments)]
     pub fn start_event_loop(
         &self,
         mut eventloop: EventLoop,
         client: Arc<AsyncClient>,
         runtime: Arc<Runtime>,
-        latest_prices: Arc<Mutex<Option<Vec<CryptoCurrency>>>>,
-        historical_data: Arc<Mutex<HashMap<String, HistoricalDataResult>>>,
+        latest_prices: Arc<ArcSwapOption<Vec<CryptoCurrency>>>,
+        price_source: Arc<Mutex<DataSource>>,
+        historical_data: Arc<RwLock<HashMap<String, HistoricalDataResult>>>,
         is_connected: Arc<Mutex<bool>>,
         connection_attempts: Arc<Mutex<u32>>,
-        price_update_callback: Arc<Mutex<Option<PriceUpdateCallback>>>,
-    ) {
-        let message_handler = MessageHandler::new(latest_prices.clone(), historical_data.clone(), price_update_callback.clone());
+        price_update_callback: Arc<Mutex<Option<PriceListener>>>,
+        prefetch_hints: Arc<Mutex<Vec<PrefetchHint>>>,
+        diagnostics: Arc<ParseDiagnostics>,
+        data_signal: Arc<DataSignal>,
+        subscriptions: Arc<SymbolSubscriptions>,
+        request_throttle: Arc<RequestThrottle>,
+        quality: Arc<ConnectionQuality>,
+        subscription_acks: Arc<SubscriptionAcks>,
+        server_presence: Arc<ServerPresence>,
+        pending_requests: Arc<PendingRequests>,
+        historical_errors: Arc<HistoricalErrors>,
+        lifecycle: Arc<AppLifecycle>,
+        reachability: Arc<Reachability>,
+        closed: Arc<AtomicBool>,
+    ) -> std::thread::JoinHandle<()> {
+        let report_client = self.config.report_parse_failures.then(|| client.clone());
+        let message_handler = MessageHandler::new(latest_prices.clone(), price_source, historical_data.clone(), price_update_callback.clone(), prefetch_hints, self.config.topic_prefix.clone(), diagnostics, report_client, data_signal, subscriptions.clone(), request_throttle, quality.clone(), Arc::new(OfflineStore::default_location()), server_presence, pending_requests, historical_errors);
+        let topic_prefix = self.config.topic_prefix.clone();
+        let payload_codec = self.config.payload_codec;
+        let client_id = self.config.client_id.clone();
+        let reconnect_policy = self.config.reconnect;
         
         // Spawn event loop handling in the background
-        debug_log("MQTT: About to spawn event loop thread");
+        debug!("MQTT: About to spawn event loop thread");
         std::thread::spawn(move || {
-            debug_log("MQTT: Event loop thread started");
+            debug!("MQTT: Event loop thread started");
             runtime.block_on(async {
-                debug_log("MQTT: Starting event loop polling");
+                debug!("MQTT: Starting event loop polling");
                 loop {
-                    match eventloop.poll().await {
+                    let event = eventloop.poll().await;
+                    if closed.load(Ordering::Relaxed) {
+                        debug!("MQTT: Client closed, stopping event loop");
+                        break;
+                    }
+                    match event {
                         Ok(Event::Incoming(Packet::ConnAck(_))) => {
-                            Self::handle_connection_success(&client, &is_connected, &connection_attempts).await;
+                            Self::handle_connection_success(&client, &is_connected, &connection_attempts, &topic_prefix, payload_codec, &client_id, &subscriptions, &subscription_acks).await;
+                        }
+                        Ok(Event::Outgoing(Outgoing::Subscribe(pkid))) => {
+                            subscription_acks.sent(pkid);
+                        }
+                        Ok(Event::Incoming(Packet::SubAck(suback))) => {
+                            subscription_acks.acknowledged(&suback);
                         }
                         Ok(Event::Incoming(Packet::Publish(publish))) => {
                             message_handler.handle_message(&publish).await;
                         }
+                        // Keepalive round trips feed the connection quality score
+                        Ok(Event::Outgoing(Outgoing::PingReq)) => {
+                            quality.ping_sent(Instant::now());
+                        }
+                        Ok(Event::Incoming(Packet::PingResp)) => {
+                            quality.ping_response(Instant::now());
+                        }
+                        // Sent by pause: drop the socket and hold until the app is back in the
+                        // foreground, then reconnect and resubscribe as after any reconnect
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) if lifecycle.take_pause_disconnect() => {
+                            debug!("MQTT: Paused while the app is in the background");
+                            *is_connected.lock().unwrap() = false;
+                            subscription_acks.connection_lost();
+                            eventloop.clean();
+                            lifecycle.wait_until_resumed().await;
+                            if closed.load(Ordering::Relaxed) {
+                                debug!("MQTT: Client closed while paused, stopping event loop");
+                                break;
+                            }
+                            debug!("MQTT: Resuming - reconnecting");
+                        }
+                        // Sent by shutdown once the unsubscribes are out
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                            debug!("MQTT: Disconnected cleanly, stopping event loop");
+                            *is_connected.lock().unwrap() = false;
+                            break;
+                        }
                         Ok(Event::Incoming(Packet::Disconnect)) => {
+                            quality.disconnected(Instant::now());
+                            subscription_acks.connection_lost();
                             Self::handle_disconnect(&is_connected);
                         }
                         Err(e) => {
-                            if Self::handle_connection_error(&is_connected, &connection_attempts, e).await {
-                                break; // Exit the event loop after max retries
-                            }
+                            quality.disconnected(Instant::now());
+                            subscription_acks.connection_lost();
+                            Self::handle_connection_error(&is_connected, &connection_attempts, &reachability, &reconnect_policy, e).await;
                         }
                         _ => {}
                     }
                 }
             });
-        });
+        })
     }
     
+    #[allow(clippy::too_many_arguments)]
     async fn handle_connection_success(
         client: &Arc<AsyncClient>,
         is_connected: &Arc<Mutex<bool>>,
         connection_attempts: &Arc<Mutex<u32>>,
+        topic_prefix: &str,
+        payload_codec: PayloadCodec,
+        client_id: &str,
+        subscriptions: &SymbolSubscriptions,
+        subscription_acks: &SubscriptionAcks,
     ) {
-        debug_log("MQTT: *** CONNECTION SUCCESSFUL *** Connected to broker!");
+        debug!("MQTT: *** CONNECTION SUCCESSFUL *** Connected to broker!");
         info!("MQTT: Connected to broker");
         *is_connected.lock().unwrap() = true;
         *connection_attempts.lock().unwrap() = 0; // Reset retry counter on successful connection
         
         // Subscribe to topics
-        debug_log("MQTT: Subscribing to crypto/prices/latest");
-        if let Err(e) = client.subscribe("crypto/prices/latest", QoS::AtLeastOnce).await {
-            debug_log(&format!("MQTT: Failed to subscribe to latest prices: {}", e));
+        let base_subscriptions = [
+            (payload_codec.topic("crypto/prices/latest"), QoS::AtLeastOnce),
+            (payload_codec.topic("crypto/prices/delta"), QoS::AtLeastOnce),
+            ("crypto/historical/+/+".to_string(), QoS::AtMostOnce),
+            ("crypto/historical/+/+/since".to_string(), QoS::AtMostOnce),
+            // Downsampled series this client asked for
+            (format!("crypto/historical/+/+/sampled/{}", client_id), QoS::AtMostOnce),
+            // Custom windows this client asked for, whole or in chunks
+            (format!("crypto/historical/+/range/{}/+", client_id), QoS::AtLeastOnce),
+            (format!("crypto/historical/+/range/{}/+/chunk/+/+", client_id), QoS::AtLeastOnce),
+            // Series too large for one packet; QoS 1 since a lost chunk loses the series
+            ("crypto/historical/+/+/chunk/+/+".to_string(), QoS::AtLeastOnce),
+            ("crypto/prefetch/popular".to_string(), QoS::AtMostOnce),
+            // Rejections of this client's requests, e.g. rate limiting
+            (shared::client_topic(client_id, "errors"), QoS::AtLeastOnce),
+            ("crypto/server/status".to_string(), QoS::AtLeastOnce),
+            // Answers to this client's historical requests
+            (shared::response_topic(client_id, "+"), QoS::AtLeastOnce),
+            // Failed fetches of any series, whoever asked for it
+            ("crypto/errors/historical/+/+".to_string(), QoS::AtLeastOnce),
+        ];
+        // Individual prices only for the symbols the app is showing
+        let symbol_topics: Vec<(String, QoS)> = subscriptions
+            .symbols()
+            .iter()
+            .map(|symbol| (symbol_price_topic(symbol), QoS::AtMostOnce))
+            .collect();
+        let topics = base_subscriptions.into_iter().chain(symbol_topics);
+        for (topic, qos) in topics {
+            let topic = shared::with_topic_prefix(topic_prefix, &topic);
+            debug!("MQTT: Subscribing to {}", topic);
+            if let Err(e) = subscription_acks.subscribe(client, &topic, qos).await {
+                warn!("MQTT: Failed to subscribe to {}: {}", topic, e);
+            }
         }
-        debug_log("MQTT: Subscribing to crypto/historical/+/+");
-        if let Err(e) = client.subscribe("crypto/historical/+/+", QoS::AtMostOnce).await {
-            debug_log(&format!("MQTT: Failed to subscribe to historical data: {}", e));
+        debug!("MQTT: All subscription requests sent; see get_subscription_status for the broker's answers");
+
+        // Tell the server which payload schema this build reads, so it can see old builds before
+        // changing the payloads
+        let capabilities = ClientCapabilities {
+            schema_version: PAYLOAD_SCHEMA_VERSION,
+            client_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            codecs: payload_codec.suffix().map(str::to_string).into_iter().collect(),
+        };
+        let topic = shared::with_topic_prefix(topic_prefix, &shared::client_topic(client_id, "capabilities"));
+        match serde_json::to_string(&capabilities) {
+            Ok(json) => {
+                // try_publish: the event loop that would drain a full request queue is the one calling us
+                if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, false, json) {
+                    warn!("MQTT: Failed to announce capabilities: {}", e);
+                }
+            }
+            Err(e) => warn!("MQTT: Failed to serialize capabilities: {}", e),
         }
-        debug_log("MQTT: All subscription requests sent");
     }
     
     fn handle_disconnect(is_connected: &Arc<Mutex<bool>>) {
-        debug_log("MQTT: *** DISCONNECT RECEIVED *** Broker initiated disconnect");
+        debug!("MQTT: *** DISCONNECT RECEIVED *** Broker initiated disconnect");
         warn!("MQTT: Disconnected from broker");
         *is_connected.lock().unwrap() = false;
     }
@@ -109,26 +239,52 @@ impl ConnectionManager {
     async fn handle_connection_error(
         is_connected: &Arc<Mutex<bool>>,
         connection_attempts: &Arc<Mutex<u32>>,
+        reachability: &Reachability,
+        policy: &ReconnectPolicy,
         error: rumqttc::ConnectionError,
-    ) -> bool {
+    ) {
         error!("MQTT: Connection error: {}", error);
         *is_connected.lock().unwrap() = false;
+
+        // Attempts without a network would only fail; they count once it is back
+        if !reachability.is_reachable() {
+            debug!("MQTT: Network unreachable, retrying once it is back");
+            reachability.wait_until_reachable().await;
+            return;
+        }
         
-        let mut attempts = connection_attempts.lock().unwrap();
-        *attempts += 1;
+        // Release the lock before sleeping
+        let attempts = {
+            let mut attempts = connection_attempts.lock().unwrap();
+            *attempts = attempts.saturating_add(1);
+            *attempts
+        };
         
-        if *attempts <= 5 {
-            // Exponential backoff: 2^attempt seconds (2, 4, 8, 16, 32 seconds)
-            let delay_secs = 2u64.pow((*attempts - 1).min(5));  // Cap at 32 seconds
-            debug_log(&format!("MQTT: Connection attempt {} failed, retrying in {} seconds", attempts, delay_secs));
-            drop(attempts); // Release the lock before sleeping
-            tokio::time::sleep(Duration::from_secs(delay_secs)).await;
-            false // Continue trying
-        } else {
-            debug_log(&format!("MQTT: All {} connection attempts failed, giving up", attempts));
-            error!("MQTT: Maximum retry attempts exceeded, connection abandoned");
-            true // Exit the event loop
-        }
+        // Never gives up: a broker that is down for a while must not leave the app without
+        // prices until it is restarted
+        let delay = policy.next_delay(attempts);
+        warn!("MQTT: Connection attempt {} failed, retrying in {:.1}s", attempts, delay.as_secs_f64());
+        // Cut short when the app reports a network change
+        reachability.backoff(delay).await;
+        reachability.wait_until_reachable().await;
+    }
+}
+
+fn build_tls_transport(tls: &TlsSettings) -> CoinCrabResult<Transport> {
+    let read = |path: &PathBuf| {
+        std::fs::read(path).map_err(|e| CoinCrabError::Config(format!("Failed to read {}: {}", path.display(), e)))
+    };
+    
+    let client_auth = match (&tls.client_cert_path, &tls.client_key_path) {
+        (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+        (None, None) => None,
+        _ => return Err(CoinCrabError::Config("MQTT TLS client certificate and key must be configured together".to_string())),
+    };
+    
+    match &tls.ca_path {
+        Some(ca) => Ok(Transport::tls(read(ca)?, client_auth, None)),
+        None if client_auth.is_none() => Ok(Transport::tls_with_default_config()),
+        None => Err(CoinCrabError::Config("MQTT TLS client certificates require MQTT_TLS_CA_PATH".to_string())),
     }
 }
 
@@ -139,6 +295,38 @@ impl Clone for Config {
             broker_host: self.broker_host.clone(),
             broker_port: self.broker_port,
             log_level: self.log_level.clone(),
+            log_filter: self.log_filter.clone(),
+            log_file: self.log_file,
+            topic_prefix: self.topic_prefix.clone(),
+            tls: self.tls.clone(),
+            report_parse_failures: self.report_parse_failures,
+            data_wait_timeout: self.data_wait_timeout,
+            payload_codec: self.payload_codec,
+            client_id: self.client_id.clone(),
+            http_api_url: self.http_api_url.clone(),
+            reconnect: self.reconnect,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_transport_validation() {
+        // No CA and no client certificate: platform roots
+        assert!(build_tls_transport(&TlsSettings::default()).is_ok());
+
+        let half_configured = TlsSettings {
+            client_cert_path: Some(PathBuf::from("client.pem")),
+            ..TlsSettings::default()
+        };
+        assert!(matches!(build_tls_transport(&half_configured), Err(CoinCrabError::Config(_))));
+
+        let missing_ca = TlsSettings {
+            ca_path: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..TlsSettings::default()
+        };
+        assert!(matches!(build_tls_transport(&missing_ca), Err(CoinCrabError::Config(_))));
+    }
+}
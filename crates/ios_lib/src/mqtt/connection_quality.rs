@@ -0,0 +1,256 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+// Samples kept per measurement; keepalive pings are a minute apart, so this covers ~20 minutes
+const MAX_SAMPLES: usize = 20;
+// A request still unanswered after this long counts as lost
+pub const REQUEST_LOSS_TIMEOUT: Duration = Duration::from_secs(30);
+// Disconnects older than this no longer affect the score
+const DISCONNECT_WINDOW: Duration = Duration::from_secs(600);
+
+// Latencies at or below the first bound score 100, at or above the second 0.
+// Requests include the server's upstream fetch, so they get far more room than pings.
+const PING_RTT_BOUNDS_MS: (f64, f64) = (150.0, 2000.0);
+const REQUEST_LATENCY_BOUNDS_MS: (f64, f64) = (1000.0, 15000.0);
+const DISCONNECT_PENALTY: f64 = 15.0;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityRating {
+    Good,
+    Fair,
+    Poor,
+    Offline,
+    // Connected, but nothing measured yet
+    Unknown,
+}
+
+// What the app should suggest to the user
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityRecommendation {
+    None,
+    SwitchNetwork,
+    UseHttp,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ConnectionQualitySnapshot {
+    // 0-100, None until a ping or request has been measured
+    pub score: Option<u8>,
+    pub rating: QualityRating,
+    pub recommendation: QualityRecommendation,
+    pub ping_rtt_ms: Option<f64>,
+    pub request_latency_ms: Option<f64>,
+    pub ping_samples: usize,
+    pub request_samples: usize,
+    pub lost_requests: u64,
+    pub recent_disconnects: usize,
+}
+
+#[derive(Default)]
+struct QualityState {
+    ping_sent: Option<Instant>,
+    ping_rtts: VecDeque<Duration>,
+    // Series topic -> when it was requested
+    pending_requests: HashMap<String, Instant>,
+    request_latencies: VecDeque<Duration>,
+    // Lost requests among the last MAX_SAMPLES outcomes
+    request_outcomes: VecDeque<bool>,
+    lost_requests: u64,
+    disconnects: VecDeque<Instant>,
+}
+
+// Round-trip times of keepalive pings and historical requests, recorded by the event loop
+// and message handler and turned into a score the app can act on
+#[derive(Default)]
+pub struct ConnectionQuality {
+    state: Mutex<QualityState>,
+}
+
+fn push_sample<T>(samples: &mut VecDeque<T>, sample: T) {
+    if samples.len() == MAX_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn average_ms(samples: &VecDeque<Duration>) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    Some(samples.iter().map(|d| d.as_secs_f64() * 1000.0).sum::<f64>() / samples.len() as f64)
+}
+
+// Linear between the bounds, 100 when at or under the lower one
+fn latency_score(latency_ms: f64, (best, worst): (f64, f64)) -> f64 {
+    (100.0 * (worst - latency_ms) / (worst - best)).clamp(0.0, 100.0)
+}
+
+impl ConnectionQuality {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ping_sent(&self, now: Instant) {
+        self.state.lock().unwrap().ping_sent = Some(now);
+    }
+
+    pub fn ping_response(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(sent) = state.ping_sent.take() {
+            push_sample(&mut state.ping_rtts, now.saturating_duration_since(sent));
+        }
+    }
+
+    // Only the first request for a series is timed; repeats while waiting don't restart the clock
+    pub fn request_sent(&self, series_topic: &str, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        Self::expire_requests(&mut state, now);
+        state.pending_requests.entry(series_topic.to_string()).or_insert(now);
+    }
+
+    // Series pushed without a pending request (retained messages, other clients' requests) are ignored
+    pub fn response_received(&self, series_topic: &str, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(sent) = state.pending_requests.remove(series_topic) {
+            push_sample(&mut state.request_latencies, now.saturating_duration_since(sent));
+            push_sample(&mut state.request_outcomes, false);
+        }
+    }
+
+    // Pings in flight when the connection drops are never answered
+    pub fn disconnected(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.ping_sent = None;
+        push_sample(&mut state.disconnects, now);
+    }
+
+    fn expire_requests(state: &mut QualityState, now: Instant) {
+        let before = state.pending_requests.len();
+        state.pending_requests.retain(|_, sent| now.saturating_duration_since(*sent) < REQUEST_LOSS_TIMEOUT);
+        let lost = before - state.pending_requests.len();
+        for _ in 0..lost {
+            push_sample(&mut state.request_outcomes, true);
+        }
+        state.lost_requests += lost as u64;
+    }
+
+    pub fn snapshot(&self, connected: bool, now: Instant) -> ConnectionQualitySnapshot {
+        let mut state = self.state.lock().unwrap();
+        Self::expire_requests(&mut state, now);
+        let recent_disconnects = state
+            .disconnects
+            .iter()
+            .filter(|at| now.saturating_duration_since(**at) < DISCONNECT_WINDOW)
+            .count();
+        let ping_rtt_ms = average_ms(&state.ping_rtts);
+        let request_latency_ms = average_ms(&state.request_latencies);
+
+        let components: Vec<f64> = [
+            ping_rtt_ms.map(|ms| latency_score(ms, PING_RTT_BOUNDS_MS)),
+            request_latency_ms.map(|ms| latency_score(ms, REQUEST_LATENCY_BOUNDS_MS)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let lost_fraction = if state.request_outcomes.is_empty() {
+            0.0
+        } else {
+            state.request_outcomes.iter().filter(|lost| **lost).count() as f64 / state.request_outcomes.len() as f64
+        };
+        let score = if !connected {
+            Some(0)
+        } else if components.is_empty() && lost_fraction == 0.0 {
+            None
+        } else {
+            // With only lost requests measured, latency is taken as perfect and the loss decides
+            let latency = if components.is_empty() { 100.0 } else { components.iter().sum::<f64>() / components.len() as f64 };
+            let score = latency * (1.0 - lost_fraction) - DISCONNECT_PENALTY * recent_disconnects as f64;
+            Some(score.clamp(0.0, 100.0).round() as u8)
+        };
+
+        let rating = match score {
+            _ if !connected => QualityRating::Offline,
+            None => QualityRating::Unknown,
+            Some(70..) => QualityRating::Good,
+            Some(40..) => QualityRating::Fair,
+            Some(_) => QualityRating::Poor,
+        };
+        let recommendation = match rating {
+            QualityRating::Offline => QualityRecommendation::UseHttp,
+            QualityRating::Poor if score.unwrap_or(0) < 20 => QualityRecommendation::UseHttp,
+            QualityRating::Poor => QualityRecommendation::SwitchNetwork,
+            _ => QualityRecommendation::None,
+        };
+
+        ConnectionQualitySnapshot {
+            score,
+            rating,
+            recommendation,
+            ping_rtt_ms,
+            request_latency_ms,
+            ping_samples: state.ping_rtts.len(),
+            request_samples: state.request_latencies.len(),
+            lost_requests: state.lost_requests,
+            recent_disconnects,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_fast_connection_scores_good() {
+        let quality = ConnectionQuality::new();
+        let now = Instant::now();
+        assert_eq!(quality.snapshot(true, now).rating, QualityRating::Unknown);
+        assert_eq!(quality.snapshot(false, now).recommendation, QualityRecommendation::UseHttp);
+
+        quality.ping_sent(now);
+        quality.ping_response(now + 80 * MS);
+        // A response nobody asked for isn't a latency sample
+        quality.response_received("crypto/historical/ETH/7d", now + 100 * MS);
+        quality.request_sent("crypto/historical/BTC/24h", now);
+        quality.request_sent("crypto/historical/BTC/24h", now + 300 * MS);
+        quality.response_received("crypto/historical/BTC/24h", now + 600 * MS);
+
+        let snapshot = quality.snapshot(true, now + 700 * MS);
+        assert_eq!(snapshot.score, Some(100));
+        assert_eq!(snapshot.rating, QualityRating::Good);
+        assert_eq!(snapshot.ping_rtt_ms, Some(80.0));
+        assert_eq!(snapshot.request_latency_ms, Some(600.0));
+        assert_eq!(snapshot.request_samples, 1);
+    }
+
+    #[test]
+    fn test_slow_and_lossy_connection_suggests_switching() {
+        let quality = ConnectionQuality::new();
+        let now = Instant::now();
+        quality.ping_sent(now);
+        quality.ping_response(now + 1000 * MS);
+        quality.request_sent("crypto/historical/BTC/24h", now);
+        quality.request_sent("crypto/historical/ETH/24h", now);
+        quality.response_received("crypto/historical/ETH/24h", now + 4000 * MS);
+
+        // Half the requests lost: (54 + 79) / 2 * 0.5
+        let snapshot = quality.snapshot(true, now + REQUEST_LOSS_TIMEOUT);
+        assert_eq!(snapshot.lost_requests, 1);
+        assert_eq!(snapshot.score, Some(33));
+        assert_eq!(snapshot.recommendation, QualityRecommendation::SwitchNetwork);
+
+        // Repeated drops push it into HTTP fallback territory
+        quality.disconnected(now + REQUEST_LOSS_TIMEOUT);
+        let snapshot = quality.snapshot(true, now + REQUEST_LOSS_TIMEOUT);
+        assert_eq!(snapshot.recent_disconnects, 1);
+        assert_eq!(snapshot.score, Some(18));
+        assert_eq!(snapshot.recommendation, QualityRecommendation::UseHttp);
+        assert_eq!(quality.snapshot(true, now + DISCONNECT_WINDOW + REQUEST_LOSS_TIMEOUT).recent_disconnects, 0);
+    }
+}
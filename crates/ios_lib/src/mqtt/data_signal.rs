@@ -0,0 +1,81 @@
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// Wakes FFI calls blocked on data as soon as the MQTT event loop caches something,
+// instead of having them sleep for a fixed time and hope the data has arrived
+#[derive(Default)]
+pub struct DataSignal {
+    generation: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl DataSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Called by the message handler after it has stored new data
+    pub fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.condvar.notify_all();
+    }
+
+    // Re-check `ready` whenever data is cached until it yields a value or the timeout passes.
+    // The check runs under the signal lock, so a notify between check and wait is never missed.
+    pub fn wait_until<T>(&self, timeout: Duration, mut ready: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut generation = self.generation.lock().unwrap();
+        loop {
+            if let Some(value) = ready() {
+                return Some(value);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            generation = self.condvar.wait_timeout(generation, deadline - now).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_wait_returns_immediately_when_ready() {
+        let signal = DataSignal::new();
+        let start = Instant::now();
+        assert_eq!(signal.wait_until(Duration::from_secs(5), || Some(1)), Some(1));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_wait_wakes_on_notify() {
+        let signal = Arc::new(DataSignal::new());
+        let data = Arc::new(Mutex::new(None));
+
+        let writer = {
+            let (signal, data) = (signal.clone(), data.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                *data.lock().unwrap() = Some("BTC");
+                signal.notify();
+            })
+        };
+
+        let start = Instant::now();
+        let value = signal.wait_until(Duration::from_secs(5), || *data.lock().unwrap());
+        assert_eq!(value, Some("BTC"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_times_out() {
+        let signal = DataSignal::new();
+        let value: Option<u32> = signal.wait_until(Duration::from_millis(20), || None);
+        assert!(value.is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::warn;
+use shared::PAYLOAD_SCHEMA_VERSION;
+
+
+// Topic the client forwards parse-failure reports to when reporting is enabled
+pub const PARSE_FAILURE_TOPIC: &str = "crypto/diagnostics/parse_failures";
+
+// Anonymized description of a payload that failed to deserialize. Carries the field path
+// and error category only - never payload contents - so it is safe to forward to the server.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ParseFailureReport {
+    pub topic_kind: String,
+    pub expected_type: String,
+    pub field_path: String,
+    pub error_kind: String,
+    pub line: usize,
+    pub column: usize,
+    pub payload_bytes: usize,
+    pub client_version: String,
+    // schema_version of the payload, if it still has one; newer than PAYLOAD_SCHEMA_VERSION
+    // means the server changed the payloads and this build needs updating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct DiagnosticsSnapshot {
+    pub parse_failures: HashMap<String, u64>,
+    pub last_failure: Option<ParseFailureReport>,
+    // Newest payload schema seen in a payload this build couldn't read, e.g. to prompt for an update
+    pub unsupported_schema_version: Option<u32>,
+}
+
+// Parse-failure counters per topic kind, shared between the message handler and the FFI
+#[derive(Default)]
+pub struct ParseDiagnostics {
+    state: Mutex<DiagnosticsSnapshot>,
+}
+
+impl ParseDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Deserialize a payload, recording which field/type mismatched on failure
+    pub fn parse<T: DeserializeOwned>(
+        &self,
+        topic: &str,
+        payload: &str,
+        expected_type: &str,
+    ) -> Result<T, Box<ParseFailureReport>> {
+        let mut deserializer = serde_json::Deserializer::from_str(payload);
+        serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+            let inner = e.inner();
+            let report = ParseFailureReport {
+                topic_kind: topic_kind(topic),
+                expected_type: expected_type.to_string(),
+                field_path: e.path().to_string(),
+                error_kind: format!("{:?}", inner.classify()),
+                line: inner.line(),
+                column: inner.column(),
+                payload_bytes: payload.len(),
+                client_version: env!("CARGO_PKG_VERSION").to_string(),
+                schema_version: shared::payload_schema_version(payload),
+            };
+            if let Some(version) = report.schema_version.filter(|version| *version > PAYLOAD_SCHEMA_VERSION) {
+                warn!("MQTT: {} on {} uses payload schema {}; this build reads up to {}",
+                    expected_type, topic, version, PAYLOAD_SCHEMA_VERSION);
+            }
+            // The full serde message may quote payload values, so it is only logged locally
+            warn!("MQTT: Failed to parse {} on {} at field '{}': {}",
+                expected_type, topic, report.field_path, inner);
+            self.record(report.clone());
+            Box::new(report)
+        })
+    }
+
+    fn record(&self, report: ParseFailureReport) {
+        let mut state = self.state.lock().unwrap();
+        *state.parse_failures.entry(report.topic_kind.clone()).or_insert(0) += 1;
+        if let Some(version) = report.schema_version.filter(|version| *version > PAYLOAD_SCHEMA_VERSION) {
+            state.unsupported_schema_version = state.unsupported_schema_version.max(Some(version));
+        }
+        state.last_failure = Some(report);
+    }
+
+    pub fn snapshot(&self) -> DiagnosticsSnapshot {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn total_failures(&self) -> u64 {
+        self.state.lock().unwrap().parse_failures.values().sum()
+    }
+}
+
+// Collapse a topic to its kind so reports don't reveal which coins a user looks at,
+// e.g. "crypto/historical/BTC/24h" -> "crypto/historical/+/24h"
+pub fn topic_kind(topic: &str) -> String {
+    let parts: Vec<&str> = topic.split('/').collect();
+    match parts.as_slice() {
+        ["crypto", "historical", _, rest @ ..] => {
+            let mut kind = vec!["crypto", "historical", "+"];
+            kind.extend_from_slice(rest);
+            kind.join("/")
+        }
+        ["crypto", "prices", symbol] if *symbol != "latest" => "crypto/prices/+".to_string(),
+        _ => topic.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CryptoCurrency, HistoricalDataResult, PriceEnvelope};
+
+    #[test]
+    fn test_parse_reports_field_path() {
+        let diagnostics = ParseDiagnostics::new();
+        let payload = r#"{"success":true,"data":[{"timestamp":1.0,"price":"oops","volume":null}],"error":null,"symbol":"BTC","timeframe":"24h"}"#;
+
+        let report = diagnostics
+            .parse::<HistoricalDataResult>("crypto/historical/BTC/24h", payload, "HistoricalDataResult")
+            .unwrap_err();
+
+        assert_eq!(report.field_path, "data[0].price");
+        assert_eq!(report.error_kind, "Data");
+        assert_eq!(report.topic_kind, "crypto/historical/+/24h");
+        assert_eq!(report.payload_bytes, payload.len());
+        assert_eq!(diagnostics.total_failures(), 1);
+    }
+
+    #[test]
+    fn test_parse_counts_per_topic_kind() {
+        let diagnostics = ParseDiagnostics::new();
+        assert!(diagnostics.parse::<Vec<CryptoCurrency>>("crypto/prices/latest", "{", "Vec<CryptoCurrency>").is_err());
+        assert!(diagnostics.parse::<Vec<CryptoCurrency>>("crypto/prices/latest", "[]", "Vec<CryptoCurrency>").is_ok());
+        assert!(diagnostics.parse::<CryptoCurrency>("crypto/prices/ETH", "null", "CryptoCurrency").is_err());
+
+        let snapshot = diagnostics.snapshot();
+        assert_eq!(snapshot.parse_failures["crypto/prices/latest"], 1);
+        assert_eq!(snapshot.parse_failures["crypto/prices/+"], 1);
+        assert_eq!(snapshot.last_failure.unwrap().expected_type, "CryptoCurrency");
+    }
+
+    #[test]
+    fn test_parse_flags_newer_schema() {
+        let diagnostics = ParseDiagnostics::new();
+        let payload = r#"{"expires_at":0,"data":{"coins":[]},"schema_version":2}"#;
+        let report = diagnostics
+            .parse::<PriceEnvelope<Vec<CryptoCurrency>>>("crypto/prices/latest", payload, "PriceEnvelope<Vec<CryptoCurrency>>")
+            .unwrap_err();
+        assert_eq!(report.schema_version, Some(2));
+        assert_eq!(diagnostics.snapshot().unsupported_schema_version, Some(2));
+
+        // A corrupt payload of the current schema isn't an update prompt
+        assert!(diagnostics.parse::<CryptoCurrency>("crypto/prices/ETH", r#"{"schema_version":1}"#, "CryptoCurrency").is_err());
+        assert_eq!(diagnostics.snapshot().unsupported_schema_version, Some(2));
+    }
+
+    #[test]
+    fn test_topic_kind() {
+        assert_eq!(topic_kind("crypto/historical/ETH/7d/since"), "crypto/historical/+/7d/since");
+        assert_eq!(topic_kind("crypto/prices/latest"), "crypto/prices/latest");
+        assert_eq!(topic_kind("crypto/prices/SOL"), "crypto/prices/+");
+        assert_eq!(topic_kind("crypto/prefetch/popular"), "crypto/prefetch/popular");
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::warn;
+
+use crate::types::HistoricalFetchError;
+
+// The last failure the server reported on crypto/errors/historical/+/+ for each series, keyed
+// by series topic. Covers requests sent by any client, and ones without a request id.
+#[derive(Default)]
+pub struct HistoricalErrors {
+    errors: Mutex<HashMap<String, (HistoricalFetchError, Instant)>>,
+}
+
+impl HistoricalErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, series_topic: &str, error: HistoricalFetchError, now: Instant) {
+        warn!("MQTT: Server failed to fetch {}: {}", series_topic, error.describe());
+        self.errors.lock().unwrap().insert(series_topic.to_string(), (error, now));
+    }
+
+    // The series arrived after all
+    pub fn clear(&self, series_topic: &str) {
+        self.errors.lock().unwrap().remove(series_topic);
+    }
+
+    // A failure received after `since`, i.e. one that answers a request sent then
+    pub fn since(&self, series_topic: &str, since: Instant) -> Option<HistoricalFetchError> {
+        let errors = self.errors.lock().unwrap();
+        let (error, received_at) = errors.get(series_topic)?;
+        (*received_at >= since).then(|| error.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_only_errors_after_the_request_count() {
+        let errors = HistoricalErrors::new();
+        let start = Instant::now();
+        let error = HistoricalFetchError {
+            symbol: "BTC".to_string(),
+            timeframe: "24h".to_string(),
+            error: "rate limit reached".to_string(),
+            retry_after_seconds: Some(30),
+            failed_at: 1_700_000_000,
+        };
+        errors.record("crypto/historical/BTC/24h", error.clone(), start);
+
+        assert_eq!(errors.since("crypto/historical/BTC/24h", start), Some(error));
+        assert_eq!(errors.since("crypto/historical/BTC/24h", start + Duration::from_secs(1)), None);
+        assert_eq!(errors.since("crypto/historical/ETH/24h", start), None);
+
+        errors.clear("crypto/historical/BTC/24h");
+        assert_eq!(errors.since("crypto/historical/BTC/24h", start), None);
+    }
+}
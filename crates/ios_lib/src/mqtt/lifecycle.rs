@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+// Whether the app is in the background. While it is, the event loop has disconnected and waits
+// here instead of polling, so iOS can suspend the app without the library keeping a socket busy.
+#[derive(Default)]
+pub struct AppLifecycle {
+    paused: AtomicBool,
+    // Set with paused; tells the event loop that the next outgoing Disconnect is a pause rather
+    // than a shutdown, even if the app is back in the foreground by the time it goes out
+    pause_disconnect: AtomicBool,
+    resumed: Notify,
+}
+
+impl AppLifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // False if already paused
+    pub fn pause(&self) -> bool {
+        if self.paused.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        self.pause_disconnect.store(true, Ordering::SeqCst);
+        true
+    }
+
+    // False if it wasn't paused. Also wakes the event loop so it can see that it was closed.
+    pub fn resume(&self) -> bool {
+        let was_paused = self.paused.swap(false, Ordering::SeqCst);
+        self.resumed.notify_one();
+        was_paused
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // Whether a Disconnect the event loop just sent was the one requested by pause
+    pub fn take_pause_disconnect(&self) -> bool {
+        self.pause_disconnect.swap(false, Ordering::SeqCst)
+    }
+
+    // notify_one keeps a permit if nobody is waiting yet, so a resume racing this is never missed
+    pub async fn wait_until_resumed(&self) {
+        while self.is_paused() {
+            self.resumed.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pause_until_resumed() {
+        let lifecycle = Arc::new(AppLifecycle::new());
+        assert!(!lifecycle.resume());
+        assert!(lifecycle.pause());
+        assert!(!lifecycle.pause());
+        assert!(lifecycle.take_pause_disconnect());
+        // Only the first Disconnect after a pause belongs to it
+        assert!(!lifecycle.take_pause_disconnect());
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            let waiter = tokio::spawn({
+                let lifecycle = lifecycle.clone();
+                async move { lifecycle.wait_until_resumed().await }
+            });
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!waiter.is_finished());
+            assert!(lifecycle.resume());
+            tokio::time::timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap();
+            // Not paused: returns right away, even with the permit left by resume used up
+            lifecycle.wait_until_resumed().await;
+        });
+    }
+}
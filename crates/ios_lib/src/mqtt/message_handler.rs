@@ -1,64 +1,181 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use arc_swap::ArcSwapOption;
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
-use rumqttc::Publish;
-use log::info;
+use rumqttc::{AsyncClient, Publish, QoS};
+use tracing::{info, debug, warn};
 
-use crate::types::{CryptoCurrency, HistoricalDataResult};
-use shared::debug_log;
-use super::client::PriceUpdateCallback;
+use crate::types::{CryptoCurrency, DataSource, HistoricalChunk, HistoricalDataResult, PrefetchHint, PriceDelta, PriceEnvelope, RequestError, RequestResponse, HistoricalFetchError, ServerStatus};
+use shared::PayloadCodec;
+use super::client::{PriceListener, merge_historical_delta};
+use super::data_signal::DataSignal;
+use super::diagnostics::{ParseDiagnostics, ParseFailureReport, PARSE_FAILURE_TOPIC};
+use super::subscriptions::SymbolSubscriptions;
+use super::request_throttle::RequestThrottle;
+use super::connection_quality::ConnectionQuality;
+use super::server_presence::ServerPresence;
+use super::pending_requests::PendingRequests;
+use super::historical_errors::HistoricalErrors;
+use crate::cache::DiskCache;
+use crate::offline::OfflineStore;
+use super::chunk_assembly::{ChunkAssembler, ChunkProgress, CHUNK_TRANSFER_TIMEOUT};
+use super::price_update::{apply_delta, diff_prices, DeltaOrder, DeltaSequence, PriceUpdate};
 
 pub struct MessageHandler {
-    latest_prices: Arc<Mutex<Option<Vec<CryptoCurrency>>>>,
-    historical_data: Arc<Mutex<HashMap<String, HistoricalDataResult>>>,
-    price_update_callback: Arc<Mutex<Option<PriceUpdateCallback>>>,
+    // Replaced wholesale by the event loop, its only writer; readers take the current
+    // snapshot without blocking it
+    latest_prices: Arc<ArcSwapOption<Vec<CryptoCurrency>>>,
+    // Fetch time and provider from the snapshot latest_prices was last replaced with
+    price_source: Arc<Mutex<DataSource>>,
+    historical_data: Arc<RwLock<HashMap<String, HistoricalDataResult>>>,
+    price_update_callback: Arc<Mutex<Option<PriceListener>>>,
+    prefetch_hints: Arc<Mutex<Vec<PrefetchHint>>>,
+    topic_prefix: String,
+    diagnostics: Arc<ParseDiagnostics>,
+    // Set only when MQTT_REPORT_PARSE_FAILURES is enabled
+    report_client: Option<Arc<AsyncClient>>,
+    // Wakes FFI calls waiting for prices or historical data
+    data_signal: Arc<DataSignal>,
+    // Per-symbol price subscriptions made through subscribe_symbol
+    subscriptions: Arc<SymbolSubscriptions>,
+    // Set from rate limit errors the server sends to this client
+    request_throttle: Arc<RequestThrottle>,
+    // Times replies to this client's historical requests
+    quality: Arc<ConnectionQuality>,
+    // On-device copy of prices and series for launches without connectivity
+    offline: Arc<OfflineStore>,
+    // From the server's retained crypto/server/status
+    server_presence: Arc<ServerPresence>,
+    // Requests waiting for the server's answer on crypto/responses/{client_id}/+
+    pending_requests: Arc<PendingRequests>,
+    // Failures the server reported on crypto/errors/historical/+/+
+    historical_errors: Arc<HistoricalErrors>,
+    // Last crypto/prices/delta merged into latest_prices
+    delta_sequence: Mutex<DeltaSequence>,
+    // Historical series arriving in chunks
+    chunks: Mutex<ChunkAssembler>,
     last_update_time: Arc<Mutex<Option<Instant>>>,
     debounce_duration: Duration,
 }
 
 impl MessageHandler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        latest_prices: Arc<Mutex<Option<Vec<CryptoCurrency>>>>,
-        historical_data: Arc<Mutex<HashMap<String, HistoricalDataResult>>>,
-        price_update_callback: Arc<Mutex<Option<PriceUpdateCallback>>>,
+        latest_prices: Arc<ArcSwapOption<Vec<CryptoCurrency>>>,
+        price_source: Arc<Mutex<DataSource>>,
+        historical_data: Arc<RwLock<HashMap<String, HistoricalDataResult>>>,
+        price_update_callback: Arc<Mutex<Option<PriceListener>>>,
+        prefetch_hints: Arc<Mutex<Vec<PrefetchHint>>>,
+        topic_prefix: String,
+        diagnostics: Arc<ParseDiagnostics>,
+        report_client: Option<Arc<AsyncClient>>,
+        data_signal: Arc<DataSignal>,
+        subscriptions: Arc<SymbolSubscriptions>,
+        request_throttle: Arc<RequestThrottle>,
+        quality: Arc<ConnectionQuality>,
+        offline: Arc<OfflineStore>,
+        server_presence: Arc<ServerPresence>,
+        pending_requests: Arc<PendingRequests>,
+        historical_errors: Arc<HistoricalErrors>,
     ) -> Self {
         Self {
             latest_prices,
+            price_source,
             historical_data,
             price_update_callback,
+            prefetch_hints,
+            topic_prefix,
+            diagnostics,
+            report_client,
+            data_signal,
+            subscriptions,
+            request_throttle,
+            quality,
+            offline,
+            server_presence,
+            pending_requests,
+            historical_errors,
+            delta_sequence: Mutex::new(DeltaSequence::default()),
+            chunks: Mutex::new(ChunkAssembler::new(CHUNK_TRANSFER_TIMEOUT)),
             last_update_time: Arc::new(Mutex::new(None)),
             debounce_duration: Duration::from_millis(500), // Debounce rapid updates within 500ms
         }
     }
     
     pub async fn handle_message(&self, publish: &Publish) {
-        let topic = &publish.topic;
-        let payload = String::from_utf8_lossy(&publish.payload);
-        debug_log(&format!("MQTT: *** MESSAGE RECEIVED *** Topic: {}, Size: {} bytes", topic, payload.len()));
-        debug_log(&format!("MQTT: First 300 chars: {}", &payload[..payload.len().min(300)]));
+        // Topics are handled and cached without the namespace prefix
+        let Some(topic) = shared::strip_topic_prefix(&self.topic_prefix, &publish.topic) else {
+            debug!("MQTT: Ignoring message outside topic namespace: {}", publish.topic);
+            return;
+        };
+        // Logos are image bytes, stored as they arrive
+        if let Some(symbol) = shared::split_logo_topic(topic) {
+            self.handle_logo(symbol, &publish.payload);
+            return;
+        }
+        let (topic, codec) = PayloadCodec::from_topic(topic);
+        let payload = if codec == PayloadCodec::Json || publish.payload.is_empty() {
+            String::from_utf8_lossy(&publish.payload).into_owned()
+        } else {
+            // Binary payloads are re-encoded so every topic keeps one JSON parsing path
+            match codec.to_json(&publish.payload) {
+                Ok(json) => json,
+                Err(e) => {
+                    warn!("MQTT: Failed to decode {:?} payload on {}: {}", codec, topic, e);
+                    return;
+                }
+            }
+        };
+        debug!("MQTT: *** MESSAGE RECEIVED *** Topic: {}, Size: {} bytes", topic, payload.len());
+        debug!("MQTT: First 300 chars: {}", &payload[..payload.len().min(300)]);
         
         if topic == "crypto/prices/latest" {
             self.handle_latest_prices(&payload).await;
+        } else if topic == "crypto/prices/delta" {
+            self.handle_price_delta(&payload).await;
+        } else if shared::split_client_topic(topic).is_some_and(|(_, rest)| rest == "errors") {
+            self.handle_request_error(&payload);
+        } else if shared::split_response_topic(topic).is_some() {
+            self.handle_request_response(&payload);
+        } else if let Some(series_topic) = shared::split_historical_error_topic(topic) {
+            self.handle_historical_error(&series_topic, &payload);
+        } else if topic == "crypto/server/status" {
+            self.handle_server_status(&payload);
+        } else if topic == "crypto/prefetch/popular" {
+            self.handle_prefetch_hints(&payload).await;
+        } else if payload.is_empty() {
+            // The server clears retained series with empty payloads; nothing to parse
+            debug!("MQTT: Retained message cleared for {}", topic);
+        } else if let Some((series_topic, index, total)) = shared::split_historical_chunk_topic(topic) {
+            self.handle_historical_chunk(topic, series_topic, index, total, &payload).await;
+        } else if topic.starts_with("crypto/historical/") && topic.ends_with("/since") {
+            self.handle_historical_delta(topic, &payload).await;
+        } else if let Some(series_topic) = shared::split_sampled_historical_topic(topic) {
+            // A downsampled copy we asked for stands in for the full series
+            self.handle_historical_data(series_topic, &payload).await;
         } else if topic.starts_with("crypto/historical/") {
             self.handle_historical_data(topic, &payload).await;
         } else if topic.starts_with("crypto/prices/") {
             self.handle_individual_price(topic, &payload).await;
         } else {
-            debug_log(&format!("MQTT: *** UNHANDLED TOPIC *** {}, payload: {}", topic, &payload[..payload.len().min(200)]));
+            debug!("MQTT: *** UNHANDLED TOPIC *** {}, payload: {}", topic, &payload[..payload.len().min(200)]);
         }
     }
     
     async fn handle_latest_prices(&self, payload: &str) {
-        debug_log("MQTT: Processing crypto/prices/latest payload...");
-        match serde_json::from_str::<Vec<CryptoCurrency>>(payload) {
-            Ok(crypto_data) => {
-                debug_log(&format!("MQTT: *** SUCCESS *** Parsed {} cryptocurrencies from latest prices", crypto_data.len()));
+        debug!("MQTT: Processing crypto/prices/latest payload...");
+        match self.diagnostics.parse::<PriceEnvelope<Vec<CryptoCurrency>>>("crypto/prices/latest", payload, "PriceEnvelope<Vec<CryptoCurrency>>") {
+            Ok(envelope) if envelope.is_expired() => {
+                debug!("MQTT: Dropping expired latest prices (expired at {})", envelope.expires_at);
+            }
+            Ok(PriceEnvelope { data: crypto_data, fetched_at, source, .. }) => {
+                debug!("MQTT: *** SUCCESS *** Parsed {} cryptocurrencies from latest prices", crypto_data.len());
                 if !crypto_data.is_empty() {
-                    debug_log(&format!("MQTT: Sample crypto: {} ({}) - Price: ${:.2}", 
+                    debug!("MQTT: Sample crypto: {} ({}) - Price: ${:.2}", 
                         crypto_data[0].name, 
                         crypto_data[0].symbol,
                         crypto_data[0].quote.usd.price
-                    ));
+                    );
                 }
                 
                 // Check if we should debounce this update
@@ -68,7 +185,7 @@ impl MessageHandler {
                     
                     if let Some(last) = *last_time {
                         if now.duration_since(last) < self.debounce_duration {
-                            debug_log("MQTT: Debouncing price update - too soon since last update");
+                            debug!("MQTT: Debouncing price update - too soon since last update");
                             false
                         } else {
                             *last_time = Some(now);
@@ -81,59 +198,338 @@ impl MessageHandler {
                 };
                 
                 if should_update {
-                    *self.latest_prices.lock().unwrap() = Some(crypto_data.clone());
-                    debug_log(&format!("MQTT: *** CACHED {} CRYPTOCURRENCIES ***", crypto_data.len()));
+                    let update = {
+                        let previous = self.latest_prices.swap(Some(Arc::new(crypto_data.clone())));
+                        let update = diff_prices(previous.as_deref().map(Vec::as_slice), &crypto_data);
+                        *self.price_source.lock().unwrap() = DataSource { fetched_at, provider: source };
+                        // Deltas from here on apply on top of this snapshot
+                        self.delta_sequence.lock().unwrap().reset();
+                        update
+                    };
+                    self.data_signal.notify();
+                    debug!("MQTT: *** CACHED {} CRYPTOCURRENCIES *** ({} updated, {} removed)",
+                        crypto_data.len(), update.updated.len(), update.removed.len());
                     info!("MQTT: Updated latest prices from broker");
-                    
-                    // Trigger callback to notify iOS of price update
-                    if let Some(callback) = *self.price_update_callback.lock().unwrap() {
-                        debug_log("MQTT: Triggering iOS callback for price update");
-                        callback(std::ptr::null());
-                    } else {
-                        debug_log("MQTT: No callback registered, price update not sent to iOS");
-                    }
+                    self.notify_price_update(&update);
+                    self.save_offline_prices();
                 } else {
-                    debug_log("MQTT: Skipped price update due to debouncing");
+                    debug!("MQTT: Skipped price update due to debouncing");
                 }
             }
-            Err(e) => {
-                debug_log(&format!("MQTT: Failed to parse crypto/prices/latest - Error: {}", e));
-                debug_log(&format!("MQTT: Full payload (first 1000 chars): {}", &payload[..payload.len().min(1000)]));
+            Err(report) => {
+                debug!("MQTT: Full payload (first 1000 chars): {}", &payload[..payload.len().min(1000)]);
+                self.report_parse_failure(report);
             }
         }
     }
     
+    // Coins that moved since the last snapshot, merged into the cached listing. Deltas are
+    // never debounced: each one carries changes the next may not repeat.
+    async fn handle_price_delta(&self, payload: &str) {
+        let delta = match self.diagnostics.parse::<PriceEnvelope<PriceDelta>>("crypto/prices/delta", payload, "PriceEnvelope<PriceDelta>") {
+            Ok(envelope) if envelope.is_expired() => {
+                debug!("MQTT: Dropping expired price delta #{}", envelope.data.seq);
+                return;
+            }
+            Ok(envelope) => envelope.data,
+            Err(report) => {
+                self.report_parse_failure(report);
+                return;
+            }
+        };
+        let update = {
+            let Some(current) = self.latest_prices.load_full() else {
+                debug!("MQTT: Ignoring price delta #{} - no snapshot to apply it to yet", delta.seq);
+                return;
+            };
+            match self.delta_sequence.lock().unwrap().accept(&delta) {
+                DeltaOrder::Stale => {
+                    debug!("MQTT: Ignoring stale price delta #{} (epoch {})", delta.seq, delta.epoch);
+                    return;
+                }
+                DeltaOrder::Gap { missed } => debug!(
+                    "MQTT: Missed {} price deltas before #{} - some prices stale until the next snapshot", missed, delta.seq),
+                DeltaOrder::InOrder => {}
+            }
+            let mut latest = current.as_ref().clone();
+            let update = apply_delta(&mut latest, &delta.coins);
+            self.latest_prices.store(Some(Arc::new(latest)));
+            update
+        };
+        self.data_signal.notify();
+        debug!("MQTT: Applied price delta #{} ({} coins updated)", delta.seq, update.updated.len());
+        self.notify_price_update(&update);
+        self.save_offline_prices();
+    }
+    
+    fn save_offline_prices(&self) {
+        let Some(prices) = self.latest_prices.load_full() else {
+            return;
+        };
+        if let Err(e) = self.offline.store_prices(&prices, Instant::now()) {
+            warn!("MQTT: Failed to save prices for offline use: {}", e);
+        }
+    }
+    
+    fn save_offline_series(&self, series_topic: &str, result: &HistoricalDataResult) {
+        // Custom windows are one-off answers, not series to show offline
+        if shared::split_historical_range_topic(series_topic).is_some() {
+            return;
+        }
+        let Some((symbol, timeframe)) = series_topic.strip_prefix("crypto/historical/").and_then(|rest| rest.split_once('/')) else {
+            return;
+        };
+        if let Err(e) = self.offline.store_historical(symbol, timeframe, result) {
+            warn!("MQTT: Failed to save {} for offline use: {}", series_topic, e);
+        }
+    }
+    
+    fn handle_request_error(&self, payload: &str) {
+        match self.diagnostics.parse::<RequestError>("crypto/clients/+/errors", payload, "RequestError") {
+            Ok(request_error) => {
+                debug!("MQTT: Server rejected request {}: {}", request_error.request, request_error.error);
+                if let Some(seconds) = request_error.retry_after_seconds {
+                    debug!("MQTT: Holding back requests for {}s", seconds);
+                    self.request_throttle.throttle_for(Duration::from_secs(seconds), Instant::now());
+                }
+            }
+            Err(report) => self.report_parse_failure(report),
+        }
+    }
+    
+    fn handle_request_response(&self, payload: &str) {
+        match self.diagnostics.parse::<RequestResponse>("crypto/responses/+/+", payload, "RequestResponse") {
+            Ok(response) => {
+                match &response.error {
+                    Some(error) => debug!("MQTT: Request {} failed: {}", response.request_id, error),
+                    None => debug!("MQTT: Request {} completed", response.request_id),
+                }
+                if self.pending_requests.respond(response) {
+                    self.data_signal.notify();
+                }
+            }
+            Err(report) => self.report_parse_failure(report),
+        }
+    }
+    
+    fn handle_historical_error(&self, series_topic: &str, payload: &str) {
+        match self.diagnostics.parse::<HistoricalFetchError>("crypto/errors/historical/+/+", payload, "HistoricalFetchError") {
+            Ok(fetch_error) => {
+                self.historical_errors.record(series_topic, fetch_error, Instant::now());
+                self.data_signal.notify();
+            }
+            Err(report) => self.report_parse_failure(report),
+        }
+    }
+    
+    // Hand the changes to iOS so it doesn't need to fetch the full list
+    fn notify_price_update(&self, update: &PriceUpdate) {
+        let listener = *self.price_update_callback.lock().unwrap();
+        match listener {
+            Some(_) if update.is_empty() => debug!("MQTT: Prices unchanged, skipping iOS callback"),
+            Some(listener) => {
+                debug!("MQTT: Triggering iOS callback for price update");
+                listener.deliver(update);
+            }
+            None => debug!("MQTT: No callback registered, price update not sent to iOS"),
+        }
+    }
+    
     async fn handle_historical_data(&self, topic: &str, payload: &str) {
-        debug_log(&format!("MQTT: Processing historical data for topic: {}", topic));
-        match serde_json::from_str::<HistoricalDataResult>(payload) {
+        debug!("MQTT: Processing historical data for topic: {}", topic);
+        match self.diagnostics.parse::<HistoricalDataResult>(topic, payload, "HistoricalDataResult") {
             Ok(hist_data) => {
-                debug_log(&format!("MQTT: *** SUCCESS *** Parsed {} historical data points for {}", hist_data.data.len(), topic));
-                let mut hist_map = self.historical_data.lock().unwrap();
-                hist_map.insert(topic.to_string(), hist_data);
-                debug_log(&format!("MQTT: *** CACHED HISTORICAL DATA *** for topic: {}", topic));
+                debug!("MQTT: *** SUCCESS *** Parsed {} historical data points for {}", hist_data.data.len(), topic);
+                self.save_offline_series(topic, &hist_data);
+                self.historical_data.write().unwrap().insert(topic.to_string(), hist_data);
+                self.historical_errors.clear(topic);
+                self.quality.response_received(topic, Instant::now());
+                self.data_signal.notify();
+                debug!("MQTT: *** CACHED HISTORICAL DATA *** for topic: {}", topic);
                 info!("MQTT: Updated historical data for topic: {}", topic);
             }
-            Err(e) => {
-                debug_log(&format!("MQTT: Failed to parse historical data for topic {} - Error: {}", topic, e));
-                debug_log(&format!("MQTT: Historical payload (first 800 chars): {}", &payload[..payload.len().min(800)]));
+            Err(report) => {
+                debug!("MQTT: Historical payload (first 800 chars): {}", &payload[..payload.len().min(800)]);
+                self.report_parse_failure(report);
             }
         }
     }
     
+    // One piece of a series too large for a single packet; the series is handled like any
+    // other once every piece has arrived
+    async fn handle_historical_chunk(&self, topic: &str, series_topic: &str, index: usize, total: usize, payload: &str) {
+        let chunk = match self.diagnostics.parse::<HistoricalChunk>(topic, payload, "HistoricalChunk") {
+            Ok(chunk) => chunk,
+            Err(report) => {
+                self.report_parse_failure(report);
+                return;
+            }
+        };
+        let (progress, pending_transfers) = {
+            let now = Instant::now();
+            let mut chunks = self.chunks.lock().unwrap();
+            for expired in chunks.expire(now) {
+                debug!("MQTT: Gave up on chunked transfer for {} - chunks missing after {:?}", expired, CHUNK_TRANSFER_TIMEOUT);
+            }
+            let progress = chunks.accept(series_topic, index, total, chunk, now);
+            (progress, chunks.pending_transfers())
+        };
+        match progress {
+            ChunkProgress::Pending { received, total } => {
+                debug!("MQTT: Received chunk {} for {} ({}/{}, {} transfers in progress)",
+                    index, series_topic, received, total, pending_transfers);
+            }
+            ChunkProgress::Complete(json) => {
+                debug!("MQTT: Reassembled {} bytes for {} from {} chunks", json.len(), series_topic, total);
+                self.handle_historical_data(series_topic, &json).await;
+            }
+            ChunkProgress::Superseded => {
+                debug!("MQTT: Ignoring chunk {} of a superseded transfer for {}", index, series_topic);
+            }
+            ChunkProgress::Rejected(reason) => {
+                debug!("MQTT: Rejected chunk for {}: {}", series_topic, reason);
+            }
+        }
+    }
+    
+    // Incremental points for a series we already hold; merged into the cached series
+    async fn handle_historical_delta(&self, topic: &str, payload: &str) {
+        let series_topic = topic.trim_end_matches("/since");
+        match self.diagnostics.parse::<HistoricalDataResult>(topic, payload, "HistoricalDataResult") {
+            Ok(delta) => {
+                self.quality.response_received(series_topic, Instant::now());
+                let mut hist_map = self.historical_data.write().unwrap();
+                match hist_map.get_mut(series_topic) {
+                    Some(existing) => {
+                        let added = merge_historical_delta(existing, delta);
+                        let merged = existing.clone();
+                        drop(hist_map);
+                        self.save_offline_series(series_topic, &merged);
+                        self.data_signal.notify();
+                        debug!("MQTT: Merged {} new historical points into {}", added, series_topic);
+                    }
+                    None => {
+                        debug!("MQTT: Ignoring delta for {} - no cached series to merge into", series_topic);
+                    }
+                }
+            }
+            Err(report) => self.report_parse_failure(report),
+        }
+    }
+    
+    fn handle_server_status(&self, payload: &str) {
+        if payload.is_empty() {
+            self.server_presence.clear();
+            return;
+        }
+        match self.diagnostics.parse::<ServerStatus>("crypto/server/status", payload, "ServerStatus") {
+            Ok(status) => self.server_presence.update(status, Instant::now()),
+            Err(report) => self.report_parse_failure(report),
+        }
+    }
+    
+    // Into the disk cache, where get_cached_logo_path finds them
+    fn handle_logo(&self, symbol: &str, image: &[u8]) {
+        if image.is_empty() {
+            debug!("MQTT: Logo for {} cleared", symbol);
+            return;
+        }
+        match DiskCache::default_location().store_logo(symbol, image) {
+            Ok(_) => debug!("MQTT: Cached logo for {} ({} bytes)", symbol, image.len()),
+            Err(e) => warn!("MQTT: Failed to cache logo for {}: {}", symbol, e),
+        }
+    }
+    
+    async fn handle_prefetch_hints(&self, payload: &str) {
+        // An empty payload means the server cleared the retained hint list
+        if payload.is_empty() {
+            self.prefetch_hints.lock().unwrap().clear();
+            return;
+        }
+        match self.diagnostics.parse::<Vec<PrefetchHint>>("crypto/prefetch/popular", payload, "Vec<PrefetchHint>") {
+            Ok(hints) => {
+                debug!("MQTT: Received {} prefetch hints", hints.len());
+                *self.prefetch_hints.lock().unwrap() = hints;
+            }
+            Err(report) => self.report_parse_failure(report),
+        }
+    }
+    
     async fn handle_individual_price(&self, topic: &str, payload: &str) {
-        debug_log(&format!("MQTT: Processing individual crypto price for topic: {}", topic));
-        match serde_json::from_str::<CryptoCurrency>(payload) {
-            Ok(crypto_data) => {
-                debug_log(&format!("MQTT: *** SUCCESS *** Individual crypto: {} ({}) - Price: ${:.2}", 
+        debug!("MQTT: Processing individual crypto price for topic: {}", topic);
+        match self.diagnostics.parse::<PriceEnvelope<CryptoCurrency>>(topic, payload, "PriceEnvelope<CryptoCurrency>") {
+            Ok(envelope) if envelope.is_expired() => {
+                debug!("MQTT: Dropping expired price for {} (expired at {})", topic, envelope.expires_at);
+            }
+            Ok(PriceEnvelope { data: crypto_data, .. }) => {
+                debug!("MQTT: *** SUCCESS *** Individual crypto: {} ({}) - Price: ${:.2}", 
                     crypto_data.name, 
                     crypto_data.symbol,
                     crypto_data.quote.usd.price
-                ));
+                );
+                let symbol = topic.rsplit('/').next().unwrap_or(&crypto_data.symbol).to_string();
+                if self.subscriptions.deliver(&symbol, crypto_data) {
+                    self.data_signal.notify();
+                }
             }
-            Err(e) => {
-                debug_log(&format!("MQTT: Failed to parse individual crypto for topic {} - Error: {}", topic, e));
-                debug_log(&format!("MQTT: Individual crypto payload: {}", &payload[..payload.len().min(500)]));
+            Err(report) => {
+                debug!("MQTT: Individual crypto payload: {}", &payload[..payload.len().min(500)]);
+                self.report_parse_failure(report);
             }
         }
     }
-}
\ No newline at end of file
+    
+    // Forward the anonymized report so schema drift between server and client shows up server-side
+    fn report_parse_failure(&self, report: Box<ParseFailureReport>) {
+        debug!("MQTT: {} payload parse failures so far", self.diagnostics.total_failures());
+        let Some(client) = &self.report_client else {
+            return;
+        };
+        let Ok(json) = serde_json::to_string(&report) else {
+            return;
+        };
+        let topic = shared::with_topic_prefix(&self.topic_prefix, PARSE_FAILURE_TOPIC);
+        // try_publish: the event loop that would drain a full request queue is the one calling us
+        if let Err(e) = client.try_publish(topic, QoS::AtMostOnce, false, json) {
+            warn!("MQTT: Failed to send parse failure report: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleared_retained_message_is_not_a_parse_failure() {
+        let historical_data = Arc::new(RwLock::new(HashMap::new()));
+        let diagnostics = Arc::new(ParseDiagnostics::new());
+        let handler = MessageHandler::new(
+            Arc::new(ArcSwapOption::empty()),
+            Arc::new(Mutex::new(DataSource::default())),
+            historical_data.clone(),
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(Vec::new())),
+            String::new(),
+            diagnostics.clone(),
+            None,
+            Arc::new(DataSignal::new()),
+            Arc::new(SymbolSubscriptions::new()),
+            Arc::new(RequestThrottle::new()),
+            Arc::new(ConnectionQuality::new()),
+            Arc::new(OfflineStore::new(std::env::temp_dir().join("coin-crab-message-handler-test"))),
+            Arc::new(ServerPresence::new()),
+            Arc::new(PendingRequests::new()),
+            Arc::new(HistoricalErrors::new()),
+        );
+        // What the server publishes to clear a retained series
+        let mut cleared = Publish::new("crypto/historical/BTC/24h", QoS::AtLeastOnce, Vec::new());
+        cleared.retain = true;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(handler.handle_message(&cleared));
+
+        assert_eq!(diagnostics.total_failures(), 0);
+        assert!(historical_data.read().unwrap().is_empty());
+    }
+}
@@ -1,6 +1,19 @@
 pub mod client;
 pub mod connection;
 pub mod message_handler;
+pub mod diagnostics;
+pub mod data_signal;
+pub mod subscriptions;
+pub mod price_update;
+pub mod request_throttle;
+pub mod chunk_assembly;
+pub mod connection_quality;
+pub mod subscription_acks;
+pub mod server_presence;
+pub mod pending_requests;
+pub mod historical_errors;
+pub mod lifecycle;
+pub mod reachability;
 
 // Re-export main types for convenience
 pub use client::MQTTClient;
@@ -24,6 +37,5 @@ mod tests {
         
         // We can't directly test module existence at runtime, but we can test
         // that types from each module are accessible through the module structure
-        assert!(true); // If this compiles, the module structure is correct
     }
 }
\ No newline at end of file
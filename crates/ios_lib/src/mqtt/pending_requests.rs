@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::types::RequestResponse;
+
+// Requests nobody waits for (cache warming, incremental refreshes) are forgotten after this
+const PENDING_TTL: Duration = Duration::from_secs(300);
+
+struct PendingRequest {
+    sent_at: Instant,
+    response: Option<RequestResponse>,
+}
+
+// Requests sent with a request id, matched with the server's answers on
+// crypto/responses/{client_id}/+ so a caller waiting for a series can stop on an error
+#[derive(Default)]
+pub struct PendingRequests {
+    requests: Mutex<HashMap<String, PendingRequest>>,
+}
+
+impl PendingRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registered before the request is published, so a fast answer is never dropped
+    pub fn sent(&self, request_id: &str, now: Instant) {
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|_, request| now.saturating_duration_since(request.sent_at) < PENDING_TTL);
+        requests.insert(request_id.to_string(), PendingRequest { sent_at: now, response: None });
+    }
+
+    // Store the server's answer; false if the request is unknown or was forgotten
+    pub fn respond(&self, response: RequestResponse) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let Some(request) = requests.get_mut(&response.request_id) else {
+            debug!("MQTT: Ignoring response to unknown request {}", response.request_id);
+            return false;
+        };
+        request.response = Some(response);
+        true
+    }
+
+    pub fn sent_at(&self, request_id: &str) -> Option<Instant> {
+        self.requests.lock().unwrap().get(request_id).map(|request| request.sent_at)
+    }
+
+    pub fn response(&self, request_id: &str) -> Option<RequestResponse> {
+        self.requests.lock().unwrap().get(request_id).and_then(|request| request.response.clone())
+    }
+
+    pub fn forget(&self, request_id: &str) {
+        self.requests.lock().unwrap().remove(request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responses_are_matched_by_request_id() {
+        let pending = PendingRequests::new();
+        let start = Instant::now();
+        pending.sent("a", start);
+        assert_eq!(pending.sent_at("a"), Some(start));
+        assert_eq!(pending.response("a"), None);
+
+        assert!(pending.respond(RequestResponse::failed("a", "unknown symbol")));
+        assert!(!pending.respond(RequestResponse::succeeded("b")));
+        assert_eq!(pending.response("a").unwrap().error.as_deref(), Some("unknown symbol"));
+
+        pending.forget("a");
+        assert!(!pending.respond(RequestResponse::succeeded("a")));
+
+        // Requests nobody waited for expire when the next one is sent
+        pending.sent("c", start);
+        pending.sent("d", start + PENDING_TTL);
+        assert!(!pending.respond(RequestResponse::succeeded("c")));
+        assert!(pending.respond(RequestResponse::succeeded("d")));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+use serde::Serialize;
+
+use crate::types::{CryptoCurrency, PriceDelta};
+
+// What changed between two crypto/prices/latest snapshots, delivered to the price update
+// callback so the app doesn't have to call back into get_crypto_data to find out
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PriceUpdate {
+    // New coins and coins whose quote changed
+    pub updated: Vec<CryptoCurrency>,
+    // Symbols that dropped out of the listing
+    pub removed: Vec<String>,
+    // Coins in the full listing after this update
+    pub total: usize,
+}
+
+impl PriceUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+// With no previous snapshot every coin counts as updated
+pub fn diff_prices(previous: Option<&[CryptoCurrency]>, current: &[CryptoCurrency]) -> PriceUpdate {
+    let previous = previous.unwrap_or_default();
+    let updated = current
+        .iter()
+        .filter(|coin| !previous.iter().any(|old| old.id == coin.id && old == *coin))
+        .cloned()
+        .collect();
+    let current_ids: HashSet<i32> = current.iter().map(|coin| coin.id).collect();
+    let removed = previous
+        .iter()
+        .filter(|old| !current_ids.contains(&old.id))
+        .map(|old| old.symbol.clone())
+        .collect();
+    PriceUpdate { updated, removed, total: current.len() }
+}
+
+// Merge a delta's coins into the cached listing by id; coins not seen before are appended
+pub fn apply_delta(latest: &mut Vec<CryptoCurrency>, coins: &[CryptoCurrency]) -> PriceUpdate {
+    let mut updated = Vec::new();
+    for coin in coins {
+        match latest.iter_mut().find(|cached| cached.id == coin.id) {
+            Some(cached) if cached == coin => continue,
+            Some(cached) => *cached = coin.clone(),
+            None => latest.push(coin.clone()),
+        }
+        updated.push(coin.clone());
+    }
+    PriceUpdate { updated, removed: Vec::new(), total: latest.len() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeltaOrder {
+    // The next delta after the last one applied (or the first since a snapshot)
+    InOrder,
+    // Newer, but some deltas in between were missed; prices of the coins they carried
+    // stay stale until the next snapshot
+    Gap { missed: u64 },
+    // Already applied or older than the last applied delta
+    Stale,
+}
+
+// Position of the last applied delta. Reset on every snapshot, since the snapshot doesn't
+// say which delta it follows.
+#[derive(Debug, Default)]
+pub struct DeltaSequence {
+    last: Option<(u64, u64)>,
+}
+
+impl DeltaSequence {
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    // Classify a delta and, unless stale, record it as the latest applied
+    pub fn accept(&mut self, delta: &PriceDelta) -> DeltaOrder {
+        let order = match self.last {
+            Some((epoch, seq)) if epoch == delta.epoch && delta.seq <= seq => return DeltaOrder::Stale,
+            Some((epoch, seq)) if epoch == delta.epoch && delta.seq > seq + 1 => DeltaOrder::Gap { missed: delta.seq - seq - 1 },
+            _ => DeltaOrder::InOrder,
+        };
+        self.last = Some((delta.epoch, delta.seq));
+        order
+    }
+}
+
+// Hand a serialized update to C as an owned buffer; it must be released with free_price_update
+pub fn into_raw_buffer(bytes: Vec<u8>) -> (*mut u8, usize) {
+    let len = bytes.len();
+    (Box::into_raw(bytes.into_boxed_slice()) as *mut u8, len)
+}
+
+// SAFETY: ptr and len must come from one into_raw_buffer call and not have been freed yet
+pub unsafe fn free_raw_buffer(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(id: i32, symbol: &str, price: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: shared::Quote {
+                usd: shared::UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_changed_new_and_removed_coins() {
+        let previous = vec![coin(1, "BTC", 50000.0), coin(1027, "ETH", 3000.0), coin(2, "LTC", 70.0)];
+        let current = vec![coin(1, "BTC", 50100.0), coin(1027, "ETH", 3000.0), coin(5426, "SOL", 150.0)];
+
+        let update = diff_prices(Some(&previous), &current);
+        let symbols: Vec<&str> = update.updated.iter().map(|c| c.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["BTC", "SOL"]);
+        assert_eq!(update.removed, vec!["LTC"]);
+        assert_eq!(update.total, 3);
+
+        assert!(diff_prices(Some(&current), &current).is_empty());
+        assert_eq!(diff_prices(None, &current).updated.len(), 3);
+    }
+
+    #[test]
+    fn test_apply_delta_merges_by_id() {
+        let mut latest = vec![coin(1, "BTC", 50000.0), coin(1027, "ETH", 3000.0)];
+        let update = apply_delta(&mut latest, &[coin(1027, "ETH", 3100.0), coin(1, "BTC", 50000.0), coin(5426, "SOL", 150.0)]);
+
+        let symbols: Vec<&str> = update.updated.iter().map(|c| c.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["ETH", "SOL"]);
+        assert_eq!(update.total, 3);
+        assert_eq!(latest[1].quote.usd.price, 3100.0);
+        assert_eq!(latest[2].symbol, "SOL");
+    }
+
+    #[test]
+    fn test_delta_sequence_ordering() {
+        let delta = |epoch, seq| PriceDelta { epoch, seq, coins: Vec::new() };
+        let mut sequence = DeltaSequence::default();
+        assert_eq!(sequence.accept(&delta(7, 4)), DeltaOrder::InOrder);
+        assert_eq!(sequence.accept(&delta(7, 5)), DeltaOrder::InOrder);
+        assert_eq!(sequence.accept(&delta(7, 5)), DeltaOrder::Stale);
+        assert_eq!(sequence.accept(&delta(7, 3)), DeltaOrder::Stale);
+        assert_eq!(sequence.accept(&delta(7, 8)), DeltaOrder::Gap { missed: 2 });
+        // A server restart starts a new epoch
+        assert_eq!(sequence.accept(&delta(9, 1)), DeltaOrder::InOrder);
+        sequence.reset();
+        assert_eq!(sequence.accept(&delta(9, 5)), DeltaOrder::InOrder);
+    }
+
+    #[test]
+    fn test_raw_buffer_round_trip() {
+        let json = serde_json::to_vec(&diff_prices(None, &[coin(1, "BTC", 1.0)])).unwrap();
+        let expected = json.clone();
+        let (ptr, len) = into_raw_buffer(json);
+        let received = unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec();
+        assert_eq!(received, expected);
+        unsafe { free_raw_buffer(ptr, len) };
+        unsafe { free_raw_buffer(std::ptr::null_mut(), 0) };
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// Network reachability as reported by the app (NWPathMonitor). While the network is down the
+// event loop waits for it instead of spending reconnect attempts, and a change cuts the backoff
+// between attempts short.
+#[derive(Default)]
+pub struct Reachability {
+    unreachable: AtomicBool,
+    changed: Notify,
+}
+
+impl Reachability {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, reachable: bool) {
+        self.unreachable.store(!reachable, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        !self.unreachable.load(Ordering::SeqCst)
+    }
+
+    // Sleeps for the delay, or until the reachability changes
+    pub async fn backoff(&self, delay: Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = self.changed.notified() => {}
+        }
+    }
+
+    pub async fn wait_until_reachable(&self) {
+        loop {
+            // Registered before the check, so a change in between isn't missed
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if self.is_reachable() {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_waits_for_the_network() {
+        let reachability = Arc::new(Reachability::new());
+        assert!(reachability.is_reachable());
+        reachability.set(false);
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            let waiter = tokio::spawn({
+                let reachability = reachability.clone();
+                async move { reachability.wait_until_reachable().await }
+            });
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!waiter.is_finished());
+            reachability.set(true);
+            tokio::time::timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap();
+
+            // The network coming back ends the backoff early
+            let start = Instant::now();
+            let backoff = tokio::spawn({
+                let reachability = reachability.clone();
+                async move { reachability.backoff(Duration::from_secs(30)).await }
+            });
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            reachability.set(true);
+            tokio::time::timeout(Duration::from_secs(5), backoff).await.unwrap().unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+    }
+}
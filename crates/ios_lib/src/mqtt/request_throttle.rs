@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Set when the server reports this client as rate limited; requests are held back locally
+// until the retry-after passes instead of adding to the load that got us throttled
+#[derive(Default)]
+pub struct RequestThrottle {
+    until: Mutex<Option<Instant>>,
+}
+
+impl RequestThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn throttle_for(&self, retry_after: Duration, now: Instant) {
+        let until = now + retry_after;
+        let mut current = self.until.lock().unwrap();
+        // Keep the later deadline if several rejections arrive out of order
+        if current.is_none_or(|current| current < until) {
+            *current = Some(until);
+        }
+    }
+
+    // Time left before requests may be sent again, None if not throttled
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        let until = (*self.until.lock().unwrap())?;
+        (until > now).then(|| until - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_window() {
+        let throttle = RequestThrottle::new();
+        let now = Instant::now();
+        assert!(throttle.remaining(now).is_none());
+
+        throttle.throttle_for(Duration::from_secs(10), now);
+        throttle.throttle_for(Duration::from_secs(2), now);
+        assert_eq!(throttle.remaining(now + Duration::from_secs(4)), Some(Duration::from_secs(6)));
+        assert!(throttle.remaining(now + Duration::from_secs(10)).is_none());
+    }
+}
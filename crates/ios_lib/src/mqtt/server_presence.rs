@@ -0,0 +1,94 @@
+use std::sync::Mutex;
+use std::time::Instant;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::types::{ServerState, ServerStatus};
+
+// The server's retained presence on crypto/server/status. "offline" is the server's Last
+// Will, so it arrives even when the server dies without shutting down.
+#[derive(Default)]
+pub struct ServerPresence {
+    last: Mutex<Option<(ServerStatus, Instant)>>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Default)]
+pub struct ServerPresenceSnapshot {
+    // The broker connection is up and the server says it is online
+    pub available: bool,
+    // null until the retained status has been received
+    pub status: Option<ServerState>,
+    pub version: Option<String>,
+    pub started_at: Option<i64>,
+    // Seconds since the status was received
+    pub status_age_seconds: Option<u64>,
+}
+
+impl ServerPresence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&self, status: ServerStatus, now: Instant) {
+        let mut last = self.last.lock().unwrap();
+        let changed = last.as_ref().is_none_or(|(previous, _)| previous.status != status.status);
+        if changed {
+            match status.status {
+                ServerState::Online => info!("MQTT: Server {} is online (started at {})", status.version, status.started_at),
+                ServerState::Offline => warn!("MQTT: Server {} went offline", status.version),
+            }
+        }
+        *last = Some((status, now));
+    }
+
+    // The retained status was cleared
+    pub fn clear(&self) {
+        *self.last.lock().unwrap() = None;
+    }
+
+    pub fn snapshot(&self, connected: bool, now: Instant) -> ServerPresenceSnapshot {
+        let last = self.last.lock().unwrap();
+        let Some((status, received_at)) = last.as_ref() else {
+            return ServerPresenceSnapshot::default();
+        };
+        ServerPresenceSnapshot {
+            available: connected && status.status == ServerState::Online,
+            status: Some(status.status),
+            version: Some(status.version.clone()),
+            started_at: Some(status.started_at),
+            status_age_seconds: Some(now.saturating_duration_since(*received_at).as_secs()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn status(status: ServerState) -> ServerStatus {
+        ServerStatus { status, version: "0.1.0".to_string(), started_at: 1_700_000_000 }
+    }
+
+    #[test]
+    fn test_server_presence() {
+        let presence = ServerPresence::new();
+        let start = Instant::now();
+        assert_eq!(presence.snapshot(true, start), ServerPresenceSnapshot::default());
+
+        presence.update(status(ServerState::Online), start);
+        let snapshot = presence.snapshot(true, start + Duration::from_secs(30));
+        assert!(snapshot.available);
+        assert_eq!(snapshot.status_age_seconds, Some(30));
+        // Online but unreachable is not available
+        assert!(!presence.snapshot(false, start).available);
+
+        presence.update(status(ServerState::Offline), start);
+        let snapshot = presence.snapshot(true, start);
+        assert!(!snapshot.available);
+        assert_eq!(serde_json::to_value(&snapshot).unwrap()["status"], "offline");
+
+        presence.clear();
+        assert_eq!(presence.snapshot(true, start).status, None);
+    }
+}
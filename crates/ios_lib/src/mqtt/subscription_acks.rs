@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use tracing::{warn, debug};
+use rumqttc::{AsyncClient, ClientError, QoS, SubAck, SubscribeReasonCode};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    // Sent or queued, no SubAck yet
+    Pending,
+    Granted,
+    // The broker refused the filter, e.g. an ACL denial; no data will arrive on it
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SubscriptionAck {
+    // The filter as sent, including any topic prefix
+    pub topic: String,
+    pub requested_qos: u8,
+    pub status: SubscriptionStatus,
+    // May be lower than requested
+    pub granted_qos: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Default)]
+pub struct SubscriptionAckSnapshot {
+    pub subscriptions: Vec<SubscriptionAck>,
+    pub pending: usize,
+    pub rejected: Vec<String>,
+}
+
+#[derive(Default)]
+struct AckState {
+    // Filters handed to the client but not yet sent by the event loop, in request order
+    unsent: VecDeque<String>,
+    // Packet id -> filter, sent and waiting for the SubAck
+    in_flight: HashMap<u16, String>,
+    acks: BTreeMap<String, SubscriptionAck>,
+}
+
+// What the broker answered to each subscription. rumqttc doesn't say which packet id a
+// subscribe request got, but the event loop sends requests in order and reports each one as
+// Outgoing::Subscribe(pkid), so queuing filters in the order they are requested pairs them up.
+#[derive(Default)]
+pub struct SubscriptionAcks {
+    state: Mutex<AckState>,
+    // Keeps queue order and channel order the same when several threads subscribe
+    send_lock: tokio::sync::Mutex<()>,
+}
+
+impl SubscriptionAcks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every subscription goes through here so its SubAck can be matched
+    pub async fn subscribe(&self, client: &AsyncClient, topic: &str, qos: QoS) -> Result<(), ClientError> {
+        let _send = self.send_lock.lock().await;
+        self.requested(topic, qos);
+        let result = client.subscribe(topic, qos).await;
+        if result.is_err() {
+            // Never reached the event loop; still last in the queue while we hold the lock
+            let mut state = self.state.lock().unwrap();
+            state.unsent.pop_back();
+            state.acks.remove(topic);
+        }
+        result
+    }
+
+    fn requested(&self, topic: &str, qos: QoS) {
+        let mut state = self.state.lock().unwrap();
+        state.unsent.push_back(topic.to_string());
+        state.acks.insert(topic.to_string(), SubscriptionAck {
+            topic: topic.to_string(),
+            requested_qos: qos as u8,
+            status: SubscriptionStatus::Pending,
+            granted_qos: None,
+        });
+    }
+
+    // Outgoing::Subscribe from the event loop
+    pub fn sent(&self, pkid: u16) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(topic) = state.unsent.pop_front() {
+            state.in_flight.insert(pkid, topic);
+        }
+    }
+
+    pub fn acknowledged(&self, suback: &SubAck) {
+        let mut state = self.state.lock().unwrap();
+        let Some(topic) = state.in_flight.remove(&suback.pkid) else {
+            return;
+        };
+        // Unsubscribed while the request was in flight
+        let Some(ack) = state.acks.get_mut(&topic) else {
+            return;
+        };
+        // One filter per subscribe request
+        match suback.return_codes.first() {
+            Some(SubscribeReasonCode::Success(qos)) => {
+                ack.status = SubscriptionStatus::Granted;
+                ack.granted_qos = Some(*qos as u8);
+            }
+            _ => {
+                ack.status = SubscriptionStatus::Rejected;
+                ack.granted_qos = None;
+                warn!("MQTT: Broker rejected subscription to {}", topic);
+                debug!("MQTT: Broker rejected subscription to {}", topic);
+            }
+        }
+    }
+
+    pub fn unsubscribed(&self, topic: &str) {
+        self.state.lock().unwrap().acks.remove(topic);
+    }
+
+    // SubAcks for requests sent before a disconnect never arrive; those filters stay pending
+    // until they are subscribed again on reconnect
+    pub fn connection_lost(&self) {
+        self.state.lock().unwrap().in_flight.clear();
+    }
+
+    pub fn snapshot(&self) -> SubscriptionAckSnapshot {
+        let state = self.state.lock().unwrap();
+        let subscriptions: Vec<SubscriptionAck> = state.acks.values().cloned().collect();
+        SubscriptionAckSnapshot {
+            pending: subscriptions.iter().filter(|ack| ack.status == SubscriptionStatus::Pending).count(),
+            rejected: subscriptions
+                .iter()
+                .filter(|ack| ack.status == SubscriptionStatus::Rejected)
+                .map(|ack| ack.topic.clone())
+                .collect(),
+            subscriptions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suback(pkid: u16, code: SubscribeReasonCode) -> SubAck {
+        SubAck { pkid, return_codes: vec![code] }
+    }
+
+    #[test]
+    fn test_acks_match_requests_in_order() {
+        let acks = SubscriptionAcks::new();
+        acks.requested("crypto/prices/latest", QoS::AtLeastOnce);
+        acks.requested("crypto/historical/+/+", QoS::AtMostOnce);
+        acks.requested("crypto/prices/BTC", QoS::AtMostOnce);
+        acks.sent(7);
+        acks.sent(8);
+        acks.acknowledged(&suback(8, SubscribeReasonCode::Failure));
+        acks.acknowledged(&suback(7, SubscribeReasonCode::Success(QoS::AtMostOnce)));
+
+        let snapshot = acks.snapshot();
+        assert_eq!(snapshot.pending, 1);
+        assert_eq!(snapshot.rejected, vec!["crypto/historical/+/+"]);
+        let latest = snapshot.subscriptions.iter().find(|ack| ack.topic == "crypto/prices/latest").unwrap();
+        assert_eq!((latest.status, latest.requested_qos, latest.granted_qos), (SubscriptionStatus::Granted, 1, Some(0)));
+
+        // The in-flight request is lost with the connection and resubscribed on reconnect
+        acks.sent(9);
+        acks.connection_lost();
+        acks.acknowledged(&suback(9, SubscribeReasonCode::Success(QoS::AtMostOnce)));
+        assert_eq!(acks.snapshot().pending, 1);
+
+        acks.unsubscribed("crypto/prices/BTC");
+        assert_eq!(acks.snapshot().subscriptions.len(), 2);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::types::CryptoCurrency;
+
+// Receives the JSON of one coin's price update; the string is only valid during the call
+pub type SymbolPriceCallback = extern "C" fn(*const c_char);
+
+// Per-symbol price topic, e.g. "crypto/prices/BTC"
+pub fn symbol_price_topic(symbol: &str) -> String {
+    format!("crypto/prices/{}", symbol.to_uppercase())
+}
+
+// Symbols the app currently displays, reference counted so two views showing the same coin
+// can subscribe and unsubscribe independently. Shared with the event loop so subscriptions
+// are restored after a reconnect (sessions are clean, so the broker forgets them).
+#[derive(Default)]
+pub struct SymbolSubscriptions {
+    counts: Mutex<HashMap<String, usize>>,
+    latest: Mutex<HashMap<String, CryptoCurrency>>,
+    callback: Mutex<Option<SymbolPriceCallback>>,
+}
+
+impl SymbolSubscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns true for the first subscriber, i.e. when the MQTT subscription is needed
+    pub fn add(&self, symbol: &str) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(symbol.to_uppercase()).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    // Returns true when the last subscriber left, i.e. when the MQTT subscription can go.
+    // Unknown symbols return false.
+    pub fn remove(&self, symbol: &str) -> bool {
+        let symbol = symbol.to_uppercase();
+        let mut counts = self.counts.lock().unwrap();
+        let Some(count) = counts.get_mut(&symbol) else {
+            return false;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return false;
+        }
+        counts.remove(&symbol);
+        self.latest.lock().unwrap().remove(&symbol);
+        true
+    }
+
+    pub fn is_subscribed(&self, symbol: &str) -> bool {
+        self.counts.lock().unwrap().contains_key(&symbol.to_uppercase())
+    }
+
+    pub fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.counts.lock().unwrap().keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    pub fn latest(&self, symbol: &str) -> Option<CryptoCurrency> {
+        self.latest.lock().unwrap().get(&symbol.to_uppercase()).cloned()
+    }
+
+    pub fn set_callback(&self, callback: Option<SymbolPriceCallback>) {
+        *self.callback.lock().unwrap() = callback;
+    }
+
+    // Cache the update and hand it to the app. Updates for symbols nobody subscribed to
+    // (e.g. after an unsubscribe raced with a message in flight) are dropped.
+    pub fn deliver(&self, symbol: &str, crypto: CryptoCurrency) -> bool {
+        let symbol = symbol.to_uppercase();
+        if !self.is_subscribed(&symbol) {
+            debug!("MQTT: Dropping price update for unsubscribed symbol {}", symbol);
+            return false;
+        }
+        let json = serde_json::to_string(&crypto).ok().and_then(|json| CString::new(json).ok());
+        self.latest.lock().unwrap().insert(symbol.clone(), crypto);
+
+        let callback = *self.callback.lock().unwrap();
+        match (callback, json) {
+            (Some(callback), Some(json)) => {
+                debug!("MQTT: Triggering symbol price callback for {}", symbol);
+                callback(json.as_ptr());
+            }
+            (None, _) => debug!("MQTT: No symbol price callback registered, {} update cached only", symbol),
+            (_, None) => warn!("MQTT: Failed to serialize price update for {}", symbol),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn crypto(symbol: &str, price: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: shared::Quote {
+                usd: shared::UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_reference_counted_subscriptions() {
+        let subscriptions = SymbolSubscriptions::new();
+        assert!(subscriptions.add("btc"));
+        assert!(!subscriptions.add("BTC"));
+        assert!(subscriptions.add("ETH"));
+        assert_eq!(subscriptions.symbols(), vec!["BTC", "ETH"]);
+
+        assert!(!subscriptions.remove("BTC"));
+        assert!(subscriptions.is_subscribed("btc"));
+        assert!(subscriptions.remove("btc"));
+        assert!(!subscriptions.is_subscribed("BTC"));
+        assert!(!subscriptions.remove("BTC"));
+        assert_eq!(symbol_price_topic("sol"), "crypto/prices/SOL");
+    }
+
+    static DELIVERED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn count_btc(json: *const c_char) {
+        let json = unsafe { CStr::from_ptr(json) }.to_str().unwrap();
+        let crypto: CryptoCurrency = serde_json::from_str(json).unwrap();
+        assert_eq!(crypto.symbol, "BTC");
+        DELIVERED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_deliver_only_subscribed_symbols() {
+        let subscriptions = SymbolSubscriptions::new();
+        subscriptions.set_callback(Some(count_btc));
+        subscriptions.add("BTC");
+
+        assert!(subscriptions.deliver("BTC", crypto("BTC", 50000.0)));
+        assert!(!subscriptions.deliver("ETH", crypto("ETH", 3000.0)));
+        assert_eq!(DELIVERED.load(Ordering::SeqCst), 1);
+        assert_eq!(subscriptions.latest("btc").unwrap().quote.usd.price, 50000.0);
+
+        // Unsubscribing drops the cached quote too
+        subscriptions.remove("BTC");
+        assert!(subscriptions.latest("BTC").is_none());
+    }
+}
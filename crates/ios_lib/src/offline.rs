@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::cache::{unix_now, write_atomic};
+use crate::types::{CryptoCurrency, HistoricalDataResult};
+use shared::{CoinCrabError, CoinCrabResult};
+
+// Last prices and historical series received from the broker, kept on disk so a launch
+// without connectivity can still show (stale) data. Lives under Documents on iOS, which the
+// OS never purges, unlike the Library/Caches DiskCache.
+
+// Streamed prices can change every second; the snapshot on disk only needs to be recent
+pub const PRICE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const PRICES_FILE: &str = "latest_prices.json";
+const HISTORICAL_DIR: &str = "historical";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfflineSnapshot<T> {
+    // Unix seconds when the data was received
+    pub saved_at: u64,
+    pub data: T,
+}
+
+impl<T> OfflineSnapshot<T> {
+    pub fn age_seconds(&self) -> u64 {
+        unix_now().saturating_sub(self.saved_at)
+    }
+}
+
+pub struct OfflineStore {
+    root: PathBuf,
+    last_price_save: Mutex<Option<Instant>>,
+}
+
+impl OfflineStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        OfflineStore { root: root.into(), last_price_save: Mutex::new(None) }
+    }
+
+    // Documents/coin-crab inside the app sandbox on iOS, a temp directory elsewhere
+    pub fn default_location() -> Self {
+        let root = if cfg!(target_os = "ios") {
+            let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+            PathBuf::from(home).join("Documents/coin-crab")
+        } else {
+            std::env::temp_dir().join("coin-crab-offline")
+        };
+        OfflineStore::new(root)
+    }
+
+    // Saves at most once per PRICE_SAVE_INTERVAL; returns whether the snapshot was written
+    pub fn store_prices(&self, prices: &[CryptoCurrency], now: Instant) -> CoinCrabResult<bool> {
+        {
+            let mut last_save = self.last_price_save.lock().unwrap();
+            if last_save.is_some_and(|last| now.duration_since(last) < PRICE_SAVE_INTERVAL) {
+                return Ok(false);
+            }
+            *last_save = Some(now);
+        }
+        self.write(&self.root.join(PRICES_FILE), prices)?;
+        debug!("Offline: Saved {} prices", prices.len());
+        Ok(true)
+    }
+
+    pub fn load_prices(&self) -> Option<OfflineSnapshot<Vec<CryptoCurrency>>> {
+        read(&self.root.join(PRICES_FILE))
+    }
+
+    // Only successful series are worth showing offline
+    pub fn store_historical(&self, symbol: &str, timeframe: &str, result: &HistoricalDataResult) -> CoinCrabResult<()> {
+        if !result.success {
+            return Ok(());
+        }
+        let path = self
+            .historical_file(symbol, timeframe)
+            .ok_or_else(|| CoinCrabError::Config(format!("Invalid series: {} {}", symbol, timeframe)))?;
+        self.write(&path, result)
+    }
+
+    pub fn load_historical(&self, symbol: &str, timeframe: &str) -> Option<OfflineSnapshot<HistoricalDataResult>> {
+        read(&self.historical_file(symbol, timeframe)?)
+    }
+
+    fn write<T: Serialize + ?Sized>(&self, path: &Path, data: &T) -> CoinCrabResult<()> {
+        let snapshot = OfflineSnapshot { saved_at: unix_now(), data };
+        let json = serde_json::to_vec(&snapshot).map_err(|e| CoinCrabError::Parse(format!("Failed to serialize offline data: {}", e)))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| CoinCrabError::Io(format!("Failed to create offline dir: {}", e)))?;
+        }
+        write_atomic(path, &json)
+    }
+
+    // Symbols and timeframes become file names, so only plain alphanumeric ones are accepted
+    fn historical_file(&self, symbol: &str, timeframe: &str) -> Option<PathBuf> {
+        let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid(symbol) || !valid(timeframe) {
+            return None;
+        }
+        Some(self.root.join(HISTORICAL_DIR).join(format!("{}_{}.json", symbol.to_uppercase(), timeframe)))
+    }
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> Option<OfflineSnapshot<T>> {
+    let contents = fs::read(path).ok()?;
+    serde_json::from_slice(&contents)
+        .inspect_err(|e| debug!("Offline: Ignoring unreadable {}: {}", path.display(), e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_store(name: &str) -> OfflineStore {
+        let root = std::env::temp_dir().join(format!("coin-crab-offline-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        OfflineStore::new(root)
+    }
+
+    fn coin(symbol: &str) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: shared::Quote {
+                usd: shared::UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_price_snapshot_is_throttled() {
+        let store = test_store("prices");
+        assert!(store.load_prices().is_none());
+
+        let now = Instant::now();
+        assert!(store.store_prices(&[coin("BTC")], now).unwrap());
+        assert!(!store.store_prices(&[coin("ETH")], now + Duration::from_secs(5)).unwrap());
+        let snapshot = store.load_prices().unwrap();
+        assert_eq!(snapshot.data[0].symbol, "BTC");
+        assert!(snapshot.age_seconds() < 5);
+
+        assert!(store.store_prices(&[coin("ETH")], now + PRICE_SAVE_INTERVAL).unwrap());
+        assert_eq!(store.load_prices().unwrap().data[0].symbol, "ETH");
+
+        let _ = fs::remove_dir_all(&store.root);
+    }
+
+    #[test]
+    fn test_historical_round_trip() {
+        let store = test_store("historical");
+        let mut result = HistoricalDataResult {
+            success: true,
+            data: vec![],
+            error: None,
+            symbol: Some("BTC".to_string()),
+            timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        };
+        store.store_historical("btc", "24h", &result).unwrap();
+        assert_eq!(store.load_historical("BTC", "24h").unwrap().data.symbol.as_deref(), Some("BTC"));
+        assert!(store.load_historical("BTC", "7d").is_none());
+
+        // Failures don't replace a usable series; odd names are rejected
+        result.success = false;
+        store.store_historical("BTC", "24h", &result).unwrap();
+        assert!(store.load_historical("BTC", "24h").unwrap().data.success);
+        assert!(store.store_historical("../BTC", "24h", &HistoricalDataResult { success: true, ..result }).is_err());
+
+        let _ = fs::remove_dir_all(&store.root);
+    }
+}
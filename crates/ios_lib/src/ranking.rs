@@ -0,0 +1,84 @@
+use crate::types::CryptoCurrency;
+
+// Orderings offered to list screens by get_top_coins. The names match the quote fields they
+// sort by, highest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    MarketCap,
+    PercentChange24h,
+    Volume24h,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> Option<Self> {
+        match key {
+            "market_cap" => Some(SortKey::MarketCap),
+            "percent_change_24h" => Some(SortKey::PercentChange24h),
+            "volume_24h" => Some(SortKey::Volume24h),
+            _ => None,
+        }
+    }
+
+    fn value(self, coin: &CryptoCurrency) -> f64 {
+        let usd = &coin.quote.usd;
+        let value = match self {
+            SortKey::MarketCap => usd.market_cap,
+            SortKey::PercentChange24h => usd.percent_change_24h,
+            SortKey::Volume24h => usd.volume_24h,
+        };
+        // Missing values sort last rather than first
+        if value.is_nan() { f64::NEG_INFINITY } else { value }
+    }
+}
+
+// The n coins with the highest value for the key. Ties keep the listing's order.
+pub fn top_coins(prices: &[CryptoCurrency], n: usize, key: SortKey) -> Vec<CryptoCurrency> {
+    let mut ranked: Vec<&CryptoCurrency> = prices.iter().collect();
+    ranked.sort_by(|a, b| key.value(b).total_cmp(&key.value(a)));
+    ranked.into_iter().take(n).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(symbol: &str, market_cap: f64, percent_change_24h: f64, volume_24h: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: shared::Quote {
+                usd: shared::UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: 0.0,
+                    percent_change_24h,
+                    percent_change_7d: 0.0,
+                    market_cap,
+                    volume_24h,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    fn symbols(coins: &[CryptoCurrency]) -> Vec<&str> {
+        coins.iter().map(|coin| coin.symbol.as_str()).collect()
+    }
+
+    #[test]
+    fn test_top_coins_sorts_by_key() {
+        let prices = vec![
+            coin("ETH", 400.0, 5.0, 20.0),
+            coin("BTC", 900.0, -1.0, 30.0),
+            coin("DOGE", 10.0, f64::NAN, 20.0),
+            coin("SOL", 80.0, 12.0, 5.0),
+        ];
+        assert_eq!(symbols(&top_coins(&prices, 2, SortKey::MarketCap)), ["BTC", "ETH"]);
+        assert_eq!(symbols(&top_coins(&prices, 10, SortKey::PercentChange24h)), ["SOL", "ETH", "BTC", "DOGE"]);
+        assert_eq!(symbols(&top_coins(&prices, 3, SortKey::Volume24h)), ["BTC", "ETH", "DOGE"]);
+
+        assert_eq!(SortKey::parse("volume_24h"), Some(SortKey::Volume24h));
+        assert_eq!(SortKey::parse("price"), None);
+    }
+}
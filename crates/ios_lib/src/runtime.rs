@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+use tracing::debug;
+
+use shared::{CoinCrabError, CoinCrabResult};
+
+// One tokio runtime for the whole library. Every MQTTClient (one is created per reconnect),
+// its event loop, the blocking publish/subscribe calls behind the FFI and background refreshes
+// all run on it instead of each building and tearing down a thread pool of their own.
+// It lives until shutdown_mqtt_client releases it, which only happens once nothing else holds
+// it, so it is never dropped from inside an async context.
+static RUNTIME: Mutex<Option<Arc<Runtime>>> = Mutex::new(None);
+
+// The work is a handful of MQTT connections and small payloads; a phone doesn't need a
+// worker per core for that
+const WORKER_THREADS: usize = 2;
+
+pub(crate) fn shared_runtime() -> CoinCrabResult<Arc<Runtime>> {
+    let mut slot = RUNTIME.lock().unwrap();
+    if let Some(runtime) = slot.as_ref() {
+        return Ok(runtime.clone());
+    }
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .thread_name("coin-crab-runtime")
+        .enable_all()
+        .build()
+        .map_err(|e| CoinCrabError::Config(format!("Failed to create runtime: {}", e)))?;
+    debug!("Runtime: Created shared runtime with {} worker threads", WORKER_THREADS);
+    Ok(slot.insert(Arc::new(runtime)).clone())
+}
+
+// Stops the worker threads if no client or refresh is using the runtime any more; the next
+// shared_runtime call starts a new one. Returns false if it is still in use and was kept.
+// Must not be called from inside the runtime.
+pub(crate) fn release_shared_runtime(timeout: Duration) -> bool {
+    let mut slot = RUNTIME.lock().unwrap();
+    let Some(runtime) = slot.take() else {
+        return true;
+    };
+    match Arc::try_unwrap(runtime) {
+        Ok(runtime) => {
+            drop(slot);
+            runtime.shutdown_timeout(timeout);
+            debug!("Runtime: Shut down shared runtime");
+            true
+        }
+        Err(runtime) => {
+            debug!("Runtime: Still in use ({} references), keeping it", Arc::strong_count(&runtime) - 1);
+            *slot = Some(runtime);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_is_shared() {
+        let first = shared_runtime().unwrap();
+        let second = std::thread::spawn(|| shared_runtime().unwrap()).join().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.block_on(async { 1 + 1 }), 2);
+    }
+}
@@ -0,0 +1,190 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use tracing::error;
+use shared::CoinCrabError;
+
+// Status codes of the FFI functions that return int32_t, mirrored as COIN_CRAB_* in
+// rust_ios_lib.h. The detail of the last failure on the calling thread is kept for
+// get_last_error_message, like errno.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum FfiStatus {
+    Ok = 0,
+    // NULL or non-UTF-8 argument, or an invalid client handle
+    InvalidArgument = 1,
+    // The broker can't be reached
+    NotConnected = 2,
+    // Connected, but the data didn't arrive in time
+    Timeout = 3,
+    // A symbol that can't name a coin (empty, whitespace or topic characters)
+    InvalidSymbol = 4,
+    // The server or its data provider is rate limiting; retry later
+    RateLimited = 5,
+    // The server answered that the request failed
+    ServerError = 6,
+    // Data arrived but couldn't be read or written as JSON
+    ParseError = 7,
+    Internal = 8,
+}
+
+impl FfiStatus {
+    pub fn from_error(error: &CoinCrabError) -> Self {
+        match error {
+            CoinCrabError::Mqtt(_) => FfiStatus::NotConnected,
+            CoinCrabError::Timeout(_) => FfiStatus::Timeout,
+            CoinCrabError::RateLimited(_) => FfiStatus::RateLimited,
+            CoinCrabError::Http(_) => FfiStatus::ServerError,
+            CoinCrabError::Parse(_) => FfiStatus::ParseError,
+            CoinCrabError::Config(_) | CoinCrabError::Io(_) => FfiStatus::Internal,
+        }
+    }
+}
+
+// A failed FFI call: the status returned and the message behind it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FfiError {
+    pub status: FfiStatus,
+    pub message: String,
+}
+
+impl FfiError {
+    pub fn new(status: FfiStatus, message: impl Into<String>) -> Self {
+        FfiError { status, message: message.into() }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Record the outcome of a call on this thread and return its status code
+pub fn record(result: Result<(), &FfiError>) -> i32 {
+    let (status, message) = match result {
+        Ok(()) => (FfiStatus::Ok, None),
+        Err(error) => (error.status, Some(error.message.clone())),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status as i32
+}
+
+pub fn last_error_message() -> Option<String> {
+    LAST_ERROR.with(|last| last.borrow().clone())
+}
+
+// What an FFI function returns when its body panicked: the same value as its ordinary failures
+pub trait PanicValue {
+    fn panic_value() -> Self;
+}
+
+impl PanicValue for () {
+    fn panic_value() {}
+}
+
+impl PanicValue for bool {
+    fn panic_value() -> Self {
+        false
+    }
+}
+
+// get_cached_cmc_id's "not found"
+impl PanicValue for i64 {
+    fn panic_value() -> Self {
+        -1
+    }
+}
+
+impl<T> PanicValue for *mut T {
+    fn panic_value() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+// Runs the body of an FFI function so a panic returns its failure value instead of unwinding
+// into Swift, which aborts the app. The panic message is recorded as COIN_CRAB_INTERNAL.
+pub fn catch_panic<T: PanicValue>(call: &str, body: impl FnOnce() -> T) -> T {
+    catch_panic_or(call, |_| T::panic_value(), body)
+}
+
+// catch_panic for functions whose failure value carries the error, e.g. a JSON error result
+pub fn catch_panic_or<T>(call: &str, on_panic: impl FnOnce(&FfiError) -> T, body: impl FnOnce() -> T) -> T {
+    // Shared state is behind mutexes, so what a panic leaves half-updated shows up as poisoning
+    // (and another caught panic) on the next call rather than as silently broken data
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let error = FfiError::new(FfiStatus::Internal, format!("{} panicked: {}", call, panic_message(payload.as_ref())));
+            error!("{}", error.message);
+            record(Err(&error));
+            on_panic(&error)
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+// Symbols end up in topic names, so ones that would change the topic are rejected up front
+pub fn check_symbol(symbol: &str) -> Result<&str, FfiError> {
+    let symbol = symbol.trim();
+    if symbol.is_empty() || symbol.chars().any(|c| c.is_whitespace() || matches!(c, '/' | '+' | '#')) {
+        return Err(FfiError::new(FfiStatus::InvalidSymbol, format!("Invalid symbol '{}'", symbol)));
+    }
+    Ok(symbol)
+}
+
+// The message of the last failed call on this thread, or NULL if it succeeded.
+// Free with free_string.
+#[no_mangle]
+pub extern "C" fn get_last_error_message() -> *mut c_char {
+    catch_panic("get_last_error_message", || match last_error_message().and_then(|message| CString::new(message).ok()) {
+        Some(message) => message.into_raw(),
+        None => std::ptr::null_mut(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_error_follows_each_call() {
+        let error = FfiError::new(FfiStatus::Timeout, "No data after 10s");
+        assert_eq!(record(Err(&error)), 3);
+        assert_eq!(last_error_message().as_deref(), Some("No data after 10s"));
+        // The message is per thread
+        assert_eq!(std::thread::spawn(last_error_message).join().unwrap(), None);
+        assert_eq!(record(Ok(())), 0);
+        assert!(get_last_error_message().is_null());
+    }
+
+    #[test]
+    fn test_panics_become_internal_errors() {
+        assert!(!catch_panic("subscribe_symbol", || -> bool { panic!("lock poisoned") }));
+        assert_eq!(last_error_message().as_deref(), Some("subscribe_symbol panicked: lock poisoned"));
+        let code = catch_panic_or("client_configure", |error| error.status as i32, || -> i32 { panic!("{} is not a port", "x") });
+        assert_eq!(code, FfiStatus::Internal as i32);
+        assert_eq!(last_error_message().as_deref(), Some("client_configure panicked: x is not a port"));
+        // Calls that don't panic leave the status to the function
+        assert!(catch_panic("subscribe_symbol", || true));
+    }
+
+    #[test]
+    fn test_status_of_errors_and_symbols() {
+        assert_eq!(FfiStatus::from_error(&CoinCrabError::Mqtt("refused".to_string())), FfiStatus::NotConnected);
+        assert_eq!(FfiStatus::from_error(&CoinCrabError::RateLimited("429".to_string())), FfiStatus::RateLimited);
+        assert_eq!(check_symbol(" btc "), Ok("btc"));
+        for symbol in ["", "  ", "BTC/24h", "BT C", "#"] {
+            assert_eq!(check_symbol(symbol).unwrap_err().status, FfiStatus::InvalidSymbol, "{:?}", symbol);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 // Re-export shared types for convenience
-pub use shared::{CryptoCurrency, HistoricalDataResult};
+pub use shared::{CryptoCurrency, HistoricalChunk, HistoricalDataResult, PrefetchHint, PriceDelta, PriceEnvelope, RequestError, HistoricalRequestEnvelope, RequestResponse, HistoricalFetchError, ServerState, ServerStatus};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse {
@@ -10,11 +10,32 @@ pub struct ApiResponse {
     pub cached: bool,
 }
 
+// Version of the JSON returned through the FFI; bumped when a field changes meaning or goes away
+pub const RESULT_SCHEMA_VERSION: u32 = 1;
+
+// When and from which provider the server fetched the cached listings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DataSource {
+    pub fetched_at: Option<i64>, // Unix timestamp (seconds)
+    pub provider: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct CryptoClientResult {
     pub success: bool,
     pub data: Option<Vec<CryptoCurrency>>,
     pub error: Option<String>,
     pub last_updated: Option<String>,
+    // True when the data comes from the on-device copy because the broker was unreachable
     pub cached: bool,
+    // Seconds since the offline copy was received; only set when cached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_seconds: Option<u64>,
+    // Server fetch time (RFC 3339) and provider, e.g. "Data as of 12:03 via CoinMarketCap"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub coin_count: usize,
+    pub schema_version: u32,
 }
\ No newline at end of file
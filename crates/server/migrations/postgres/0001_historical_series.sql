@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS historical_series (
+    symbol TEXT NOT NULL,
+    timeframe TEXT NOT NULL,
+    fetched_at BIGINT NOT NULL,
+    PRIMARY KEY (symbol, timeframe)
+);
+
+CREATE TABLE IF NOT EXISTS historical_points (
+    symbol TEXT NOT NULL,
+    timeframe TEXT NOT NULL,
+    timestamp DOUBLE PRECISION NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    volume DOUBLE PRECISION,
+    PRIMARY KEY (symbol, timeframe, timestamp),
+    FOREIGN KEY (symbol, timeframe) REFERENCES historical_series (symbol, timeframe) ON DELETE CASCADE
+);
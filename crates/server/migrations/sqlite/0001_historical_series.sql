@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS historical_series (
+    symbol TEXT NOT NULL,
+    timeframe TEXT NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (symbol, timeframe)
+);
+
+CREATE TABLE IF NOT EXISTS historical_points (
+    symbol TEXT NOT NULL,
+    timeframe TEXT NOT NULL,
+    timestamp REAL NOT NULL,
+    price REAL NOT NULL,
+    volume REAL,
+    PRIMARY KEY (symbol, timeframe, timestamp)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS coin_identities (
+    symbol TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    cmc_id INTEGER,
+    slug TEXT,
+    coingecko_id TEXT
+);
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::config::AlertsConfig;
+use crate::types::CryptoCurrency;
+use shared::{CoinCrabError, CoinCrabResult};
+use super::{AlertDirection, AlertIndex, AlertRule};
+
+// One rule as a device registers it; id is the device's own name for the rule
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRuleRequest {
+    pub id: Option<String>,
+    pub symbol: String,
+    pub direction: AlertDirection,
+    pub threshold: f64,
+}
+
+// Published to crypto/alerts/{device_id}
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TriggeredAlert {
+    #[serde(flatten)]
+    pub rule: AlertRule,
+    pub price: f64,
+    // Unix timestamp (seconds)
+    pub triggered_at: i64,
+}
+
+// Price alerts registered by devices. Each registration replaces the device's rules; a rule
+// fires once, when a price update crosses its threshold, and is then removed. Rules are kept
+// in ALERT_RULES_PATH when set so they survive restarts.
+pub struct AlertEngine {
+    index: Mutex<AlertIndex>,
+    // Price each symbol had at the last evaluation, to detect crossings
+    last_prices: Mutex<HashMap<String, f64>>,
+    rules_path: Option<PathBuf>,
+    max_rules_per_device: usize,
+}
+
+impl AlertEngine {
+    pub fn new(config: &AlertsConfig) -> Self {
+        AlertEngine {
+            index: Mutex::new(AlertIndex::new()),
+            last_prices: Mutex::new(HashMap::new()),
+            rules_path: config.rules_path.as_ref().map(PathBuf::from),
+            max_rules_per_device: config.max_rules_per_device,
+        }
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.index.lock().unwrap().len()
+    }
+
+    // Rules saved by the last run; returns how many were loaded
+    pub fn load(&self) -> CoinCrabResult<usize> {
+        let Some(path) = self.rules_path.as_ref().filter(|path| path.exists()) else {
+            return Ok(0);
+        };
+        let rules: Vec<AlertRule> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let mut index = self.index.lock().unwrap();
+        let count = rules.len();
+        rules.into_iter().for_each(|rule| index.insert(rule));
+        Ok(count)
+    }
+
+    // Write to a temp file and rename so a crash mid-write never loses every rule
+    pub fn save(&self) -> CoinCrabResult<()> {
+        let Some(path) = &self.rules_path else {
+            return Ok(());
+        };
+        let rules: Vec<AlertRule> = self.index.lock().unwrap().rules().cloned().collect();
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(&rules)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // save() off the async runtime
+    pub async fn persist(self: &Arc<Self>) -> CoinCrabResult<()> {
+        if self.rules_path.is_none() {
+            return Ok(());
+        }
+        let engine = Arc::clone(self);
+        tokio::task::spawn_blocking(move || engine.save())
+            .await
+            .map_err(|e| CoinCrabError::Io(format!("Alert rule save task failed: {}", e)))?
+    }
+
+    // Replace every rule of the device; an empty list removes them. Returns the rule count.
+    pub fn register(&self, device_id: &str, requests: Vec<AlertRuleRequest>) -> CoinCrabResult<usize> {
+        if requests.len() > self.max_rules_per_device {
+            return Err(CoinCrabError::Config(format!(
+                "{} alert rules exceed the limit of {} per device", requests.len(), self.max_rules_per_device
+            )));
+        }
+        if let Some(invalid) = requests.iter().find(|request| !request.threshold.is_finite() || request.threshold <= 0.0) {
+            return Err(CoinCrabError::Parse(format!("Invalid threshold {} for {}", invalid.threshold, invalid.symbol)));
+        }
+        let mut index = self.index.lock().unwrap();
+        let previous: Vec<String> = index.rules().filter(|rule| rule.device_id == device_id).map(|rule| rule.id.clone()).collect();
+        previous.iter().for_each(|id| {
+            index.remove(id);
+        });
+        let count = requests.len();
+        for (position, request) in requests.into_iter().enumerate() {
+            let name = request.id.unwrap_or_else(|| position.to_string());
+            index.insert(AlertRule {
+                id: format!("{}:{}", device_id, name),
+                device_id: device_id.to_string(),
+                symbol: request.symbol.to_uppercase(),
+                direction: request.direction,
+                threshold: request.threshold,
+            });
+        }
+        Ok(count)
+    }
+
+    // Rules the listings' prices crossed since the last evaluation, removed from the engine
+    pub fn evaluate(&self, listings: &[CryptoCurrency], now: i64) -> Vec<TriggeredAlert> {
+        let mut index = self.index.lock().unwrap();
+        let mut last_prices = self.last_prices.lock().unwrap();
+        let mut triggered = Vec::new();
+        for coin in listings {
+            let symbol = coin.symbol.to_uppercase();
+            let price = coin.quote.usd.price;
+            if !price.is_finite() {
+                continue;
+            }
+            let previous = last_prices.insert(symbol.clone(), price);
+            let crossed: Vec<AlertRule> = index.crossed(&symbol, previous, price).into_iter().cloned().collect();
+            for rule in crossed {
+                index.remove(&rule.id);
+                triggered.push(TriggeredAlert { rule, price, triggered_at: now });
+            }
+        }
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(symbol: &str, price: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    fn request(symbol: &str, direction: AlertDirection, threshold: f64) -> AlertRuleRequest {
+        AlertRuleRequest { id: None, symbol: symbol.to_string(), direction, threshold }
+    }
+
+    #[test]
+    fn test_rules_fire_once_on_crossing() {
+        let engine = AlertEngine::new(&AlertsConfig::default());
+        engine.register("phone", vec![request("btc", AlertDirection::Above, 50_000.0), request("ETH", AlertDirection::Below, 2_000.0)]).unwrap();
+        engine.register("tablet", vec![request("BTC", AlertDirection::Above, 60_000.0)]).unwrap();
+        // Registering again replaces the device's rules rather than adding to them
+        engine.register("phone", vec![request("BTC", AlertDirection::Above, 50_000.0)]).unwrap();
+        assert_eq!(engine.rule_count(), 2);
+
+        assert!(engine.evaluate(&[coin("BTC", 49_000.0), coin("ETH", 1_500.0)], 100).is_empty());
+        let triggered = engine.evaluate(&[coin("BTC", 51_000.0)], 200);
+        assert_eq!(triggered.len(), 1);
+        assert_eq!((triggered[0].rule.id.as_str(), triggered[0].price, triggered[0].triggered_at), ("phone:0", 51_000.0, 200));
+
+        // Fired rules are gone; dipping and rising again doesn't repeat the alert
+        engine.evaluate(&[coin("BTC", 49_000.0)], 300);
+        assert!(engine.evaluate(&[coin("BTC", 51_000.0)], 400).is_empty());
+        assert_eq!(engine.rule_count(), 1);
+    }
+
+    #[test]
+    fn test_register_validation_and_persistence() {
+        let path = std::env::temp_dir().join(format!("coin-crab-alerts-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = AlertsConfig { rules_path: Some(path.display().to_string()), max_rules_per_device: 2 };
+        let engine = AlertEngine::new(&config);
+        assert!(engine.register("phone", vec![request("BTC", AlertDirection::Above, 1.0); 3]).is_err());
+        assert!(engine.register("phone", vec![request("BTC", AlertDirection::Above, f64::NAN)]).is_err());
+
+        let named = AlertRuleRequest { id: Some("moon".to_string()), ..request("BTC", AlertDirection::Above, 100_000.0) };
+        engine.register("phone", vec![named]).unwrap();
+        engine.save().unwrap();
+
+        let restored = AlertEngine::new(&config);
+        assert_eq!(restored.load().unwrap(), 1);
+        assert_eq!(restored.index.lock().unwrap().get("phone:moon").map(|rule| rule.threshold), Some(100_000.0));
+        let _ = std::fs::remove_file(&path);
+    }
+}
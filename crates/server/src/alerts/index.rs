@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use super::{AlertDirection, AlertRule};
+
+// Rules by symbol with their thresholds kept sorted, so a price move only looks at the
+// thresholds it crossed: two binary searches per updated coin instead of a pass over every
+// rule. Evaluating a refresh costs O(coins × log rules + triggered).
+#[derive(Debug, Default)]
+pub struct AlertIndex {
+    rules: HashMap<String, AlertRule>,
+    by_symbol: HashMap<String, SymbolThresholds>,
+}
+
+// (threshold, rule id), ascending by threshold
+#[derive(Debug, Default)]
+struct SymbolThresholds {
+    above: Vec<(f64, String)>,
+    below: Vec<(f64, String)>,
+}
+
+impl SymbolThresholds {
+    fn side(&mut self, direction: AlertDirection) -> &mut Vec<(f64, String)> {
+        match direction {
+            AlertDirection::Above => &mut self.above,
+            AlertDirection::Below => &mut self.below,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.above.is_empty() && self.below.is_empty()
+    }
+}
+
+impl AlertIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, id: &str) -> Option<&AlertRule> {
+        self.rules.get(id)
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &AlertRule> {
+        self.rules.values()
+    }
+
+    // Replaces a rule with the same id. Rules with a non-finite threshold are ignored.
+    pub fn insert(&mut self, rule: AlertRule) {
+        if !rule.threshold.is_finite() {
+            return;
+        }
+        self.remove(&rule.id);
+        let thresholds = self.by_symbol.entry(rule.symbol.to_uppercase()).or_default().side(rule.direction);
+        let position = thresholds.partition_point(|(threshold, _)| *threshold < rule.threshold);
+        thresholds.insert(position, (rule.threshold, rule.id.clone()));
+        self.rules.insert(rule.id.clone(), rule);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<AlertRule> {
+        let rule = self.rules.remove(id)?;
+        let symbol = rule.symbol.to_uppercase();
+        if let Some(symbol_thresholds) = self.by_symbol.get_mut(&symbol) {
+            let thresholds = symbol_thresholds.side(rule.direction);
+            let start = thresholds.partition_point(|(threshold, _)| *threshold < rule.threshold);
+            if let Some(offset) = thresholds[start..].iter().position(|(_, rule_id)| rule_id == id) {
+                thresholds.remove(start + offset);
+            }
+            if symbol_thresholds.is_empty() {
+                self.by_symbol.remove(&symbol);
+            }
+        }
+        Some(rule)
+    }
+
+    // Rules a move from previous to current satisfies: above rules with a threshold in
+    // (previous, current], below rules with one in [current, previous). Without a previous
+    // price every rule whose condition currently holds is returned.
+    pub fn crossed(&self, symbol: &str, previous: Option<f64>, current: f64) -> Vec<&AlertRule> {
+        let Some(thresholds) = self.by_symbol.get(&symbol.to_uppercase()) else {
+            return Vec::new();
+        };
+        let above_start = previous.map_or(0, |previous| thresholds.above.partition_point(|(t, _)| *t <= previous));
+        let above_end = thresholds.above.partition_point(|(t, _)| *t <= current);
+        let below_start = thresholds.below.partition_point(|(t, _)| *t < current);
+        let below_end = previous.map_or(thresholds.below.len(), |previous| thresholds.below.partition_point(|(t, _)| *t < previous));
+
+        let above = thresholds.above.get(above_start..above_end).unwrap_or_default();
+        let below = thresholds.below.get(below_start..below_end).unwrap_or_default();
+        above
+            .iter()
+            .chain(below)
+            .filter_map(|(_, id)| self.rules.get(id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str, symbol: &str, direction: AlertDirection, threshold: f64) -> AlertRule {
+        AlertRule { id: id.to_string(), device_id: "device".to_string(), symbol: symbol.to_string(), direction, threshold }
+    }
+
+    fn ids(rules: Vec<&AlertRule>) -> Vec<&str> {
+        let mut ids: Vec<&str> = rules.into_iter().map(|rule| rule.id.as_str()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_crossed_thresholds() {
+        let mut index = AlertIndex::new();
+        index.insert(rule("a", "BTC", AlertDirection::Above, 50_000.0));
+        index.insert(rule("b", "btc", AlertDirection::Above, 52_000.0));
+        index.insert(rule("c", "BTC", AlertDirection::Below, 45_000.0));
+        index.insert(rule("d", "ETH", AlertDirection::Above, 1.0));
+
+        assert!(index.crossed("BTC", Some(48_000.0), 49_999.0).is_empty());
+        assert_eq!(ids(index.crossed("BTC", Some(49_999.0), 50_000.0)), vec!["a"]);
+        assert_eq!(ids(index.crossed("BTC", Some(49_000.0), 53_000.0)), vec!["a", "b"]);
+        // Staying above a threshold doesn't fire it again
+        assert!(index.crossed("BTC", Some(50_500.0), 51_000.0).is_empty());
+        assert_eq!(ids(index.crossed("BTC", Some(46_000.0), 45_000.0)), vec!["c"]);
+        // First price seen: whatever already holds
+        assert_eq!(ids(index.crossed("BTC", None, 51_000.0)), vec!["a"]);
+        assert_eq!(ids(index.crossed("btc", None, 44_000.0)), vec!["c"]);
+        assert!(index.crossed("SOL", None, 100.0).is_empty());
+    }
+
+    #[test]
+    fn test_insert_replaces_and_remove_unindexes() {
+        let mut index = AlertIndex::new();
+        index.insert(rule("a", "BTC", AlertDirection::Above, 50_000.0));
+        index.insert(rule("b", "BTC", AlertDirection::Above, 50_000.0));
+        index.insert(rule("a", "BTC", AlertDirection::Below, 40_000.0));
+        index.insert(rule("nan", "BTC", AlertDirection::Above, f64::NAN));
+        assert_eq!(index.len(), 2);
+        assert_eq!(ids(index.crossed("BTC", Some(49_000.0), 50_000.0)), vec!["b"]);
+
+        assert_eq!(index.remove("b").map(|rule| rule.id), Some("b".to_string()));
+        assert!(index.crossed("BTC", Some(49_000.0), 50_000.0).is_empty());
+        index.remove("a");
+        assert!(index.is_empty() && index.by_symbol.is_empty());
+    }
+
+    #[test]
+    fn test_many_rules_only_visit_crossed_thresholds() {
+        let mut index = AlertIndex::new();
+        for i in 0..20_000 {
+            let symbol = format!("C{}", i % 100);
+            let direction = if i % 2 == 0 { AlertDirection::Above } else { AlertDirection::Below };
+            index.insert(rule(&i.to_string(), &symbol, direction, f64::from(i / 100)));
+        }
+        assert_eq!(index.len(), 20_000);
+        // C0 holds above rules at 0, 1, ..., 199; a move from 99.5 to 110.0 crosses 100..=110
+        assert_eq!(index.crossed("C0", Some(99.5), 110.0).len(), 11);
+        let total: usize = (0..100).map(|i| index.crossed(&format!("C{}", i), Some(50.5), 50.7).len()).sum();
+        assert_eq!(total, 0);
+    }
+}
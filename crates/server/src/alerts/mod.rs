@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+mod engine;
+mod index;
+
+pub use engine::{AlertEngine, AlertRuleRequest, TriggeredAlert};
+pub use index::AlertIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertDirection {
+    // Fires when the price rises to or through the threshold
+    Above,
+    // Fires when the price falls to or through the threshold
+    Below,
+}
+
+// One price alert a device asked for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub id: String,
+    pub device_id: String,
+    pub symbol: String,
+    pub direction: AlertDirection,
+    pub threshold: f64,
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+use crate::config::AnomalyConfig;
+use crate::types::CryptoCurrency;
+
+// Providers occasionally return bad ticks: a price off by orders of magnitude, zero, or a quote
+// that stopped updating. Each listings fetch is checked against the previous one before it is
+// cached and published; bad coins are reported and, unless configured to only flag them, keep
+// their previous quote.
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    // Moved more than the allowed percentage since the previous fetch
+    PriceJump,
+    // Zero, negative or not a number
+    InvalidPrice,
+    // last_updated older than the allowed age
+    StaleQuote,
+}
+
+// One bad tick, as reported on crypto/diagnostics/anomalies
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PriceAnomaly {
+    pub symbol: String,
+    pub kind: AnomalyKind,
+    pub price: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_price: Option<f64>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub last_updated: String,
+    // Held back (the previous quote was kept, or the coin left out) rather than only flagged
+    pub suppressed: bool,
+}
+
+pub struct PriceScreen {
+    config: AnomalyConfig,
+    // Jumps held back on the last fetch, by coin id. A jump confirmed by the next fetch is a
+    // real move and is let through.
+    held_back: Mutex<HashMap<i32, f64>>,
+}
+
+impl PriceScreen {
+    pub fn new(config: AnomalyConfig) -> Self {
+        PriceScreen { config, held_back: Mutex::new(HashMap::new()) }
+    }
+
+    fn jumped(&self, from: f64, to: f64) -> bool {
+        ((to - from) / from).abs() * 100.0 > self.config.max_jump_percent
+    }
+
+    fn check(&self, coin: &CryptoCurrency, previous: Option<&CryptoCurrency>, now: DateTime<Utc>) -> Option<AnomalyKind> {
+        let price = coin.quote.usd.price;
+        if !(price.is_finite() && price > 0.0) {
+            return Some(AnomalyKind::InvalidPrice);
+        }
+        let mut held_back = self.held_back.lock().unwrap();
+        let previous_price = previous.map(|previous| previous.quote.usd.price).filter(|price| *price > 0.0);
+        match previous_price {
+            Some(previous_price) if self.jumped(previous_price, price) => {
+                let confirmed = held_back.remove(&coin.id).is_some_and(|held| !self.jumped(held, price));
+                if confirmed {
+                    info!("{} moved from {} to {} over two fetches - accepting", coin.symbol, previous_price, price);
+                } else {
+                    held_back.insert(coin.id, price);
+                    return Some(AnomalyKind::PriceJump);
+                }
+            }
+            _ => {
+                held_back.remove(&coin.id);
+            }
+        }
+        let max_age = self.config.max_quote_age_seconds;
+        let updated = DateTime::parse_from_rfc3339(&coin.quote.usd.last_updated).ok();
+        if max_age > 0 && updated.is_some_and(|updated| (now - updated.with_timezone(&Utc)).num_seconds() > max_age as i64) {
+            return Some(AnomalyKind::StaleQuote);
+        }
+        None
+    }
+
+    // The coins fit to publish, and the anomalies found among them
+    pub fn screen(&self, previous: &[CryptoCurrency], coins: Vec<CryptoCurrency>, now: DateTime<Utc>) -> (Vec<CryptoCurrency>, Vec<PriceAnomaly>) {
+        if !self.config.enabled {
+            return (coins, Vec::new());
+        }
+        let previous: HashMap<i32, &CryptoCurrency> = previous.iter().map(|coin| (coin.id, coin)).collect();
+        let mut screened = Vec::with_capacity(coins.len());
+        let mut anomalies = Vec::new();
+        for coin in coins {
+            let last = previous.get(&coin.id).copied();
+            let Some(kind) = self.check(&coin, last, now) else {
+                screened.push(coin);
+                continue;
+            };
+            anomalies.push(PriceAnomaly {
+                symbol: coin.symbol.clone(),
+                kind,
+                price: coin.quote.usd.price,
+                previous_price: last.map(|last| last.quote.usd.price),
+                last_updated: coin.quote.usd.last_updated.clone(),
+                suppressed: self.config.suppress,
+            });
+            match (self.config.suppress, last) {
+                (false, _) => screened.push(coin),
+                (true, Some(last)) => screened.push(last.clone()),
+                // Nothing to fall back to, so the coin sits this fetch out
+                (true, None) => {}
+            }
+        }
+        if !anomalies.is_empty() {
+            warn!("{} suspicious quotes in the listings: {}", anomalies.len(),
+                  anomalies.iter().map(|anomaly| format!("{} ({:?})", anomaly.symbol, anomaly.kind)).collect::<Vec<_>>().join(", "));
+        }
+        (screened, anomalies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(id: i32, symbol: &str, price: f64, last_updated: &str) -> CryptoCurrency {
+        CryptoCurrency {
+            id,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: last_updated.to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_bad_ticks_keep_the_previous_quote() {
+        let screen = PriceScreen::new(AnomalyConfig::default());
+        let fresh = "2024-06-01T11:59:00Z";
+        let previous = vec![coin(1, "BTC", 60_000.0, fresh), coin(2, "ETH", 3_000.0, fresh), coin(3, "SOL", 150.0, fresh)];
+        let fetched = vec![
+            coin(1, "BTC", 600.0, fresh),
+            coin(2, "ETH", 0.0, fresh),
+            coin(3, "SOL", 155.0, "2024-05-30T00:00:00Z"),
+            coin(4, "NEW", -1.0, fresh),
+        ];
+        let (screened, anomalies) = screen.screen(&previous, fetched, now());
+
+        let kinds: Vec<(&str, AnomalyKind)> = anomalies.iter().map(|anomaly| (anomaly.symbol.as_str(), anomaly.kind)).collect();
+        assert_eq!(kinds, vec![
+            ("BTC", AnomalyKind::PriceJump),
+            ("ETH", AnomalyKind::InvalidPrice),
+            ("SOL", AnomalyKind::StaleQuote),
+            ("NEW", AnomalyKind::InvalidPrice),
+        ]);
+        assert_eq!(anomalies[0].previous_price, Some(60_000.0));
+        // Suppressed coins keep their previous quote; a new coin has none and is left out
+        assert_eq!(screened, previous);
+    }
+
+    #[test]
+    fn test_confirmed_jumps_and_flag_only_mode() {
+        let screen = PriceScreen::new(AnomalyConfig::default());
+        let fresh = "2024-06-01T11:59:00Z";
+        let previous = vec![coin(1, "LUNA", 80.0, fresh)];
+        let (screened, anomalies) = screen.screen(&previous, vec![coin(1, "LUNA", 20.0, fresh)], now());
+        assert_eq!((screened[0].quote.usd.price, anomalies.len()), (80.0, 1));
+        // The crash is still there on the next fetch, so it is real
+        let (screened, anomalies) = screen.screen(&screened, vec![coin(1, "LUNA", 19.0, fresh)], now());
+        assert_eq!((screened[0].quote.usd.price, anomalies.len()), (19.0, 0));
+
+        let flag_only = PriceScreen::new(AnomalyConfig { suppress: false, ..AnomalyConfig::default() });
+        let (screened, anomalies) = flag_only.screen(&previous, vec![coin(1, "LUNA", 0.0, fresh)], now());
+        assert_eq!(screened[0].quote.usd.price, 0.0);
+        assert!(!anomalies[0].suppressed);
+
+        let disabled = PriceScreen::new(AnomalyConfig { enabled: false, ..AnomalyConfig::default() });
+        assert!(disabled.screen(&previous, vec![coin(1, "LUNA", 0.0, fresh)], now()).1.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use crate::config::{ClientRateLimitConfig, HttpAuthConfig};
+use crate::http_rate_limit::too_many_requests;
+use crate::mqtt::rate_limit::{ClientRateLimiter, RateDecision};
+
+// REST API authentication: "Authorization: Bearer <token>" with either one of the configured
+// API keys or an HS256 JWT signed with HTTP_JWT_SECRET. Every caller is rate limited on its
+// own token bucket, keyed by the API key's name or the JWT's subject.
+
+// Request extension naming the authenticated caller, for the middleware and handlers after this one
+#[derive(Clone)]
+pub struct ApiCaller(pub String);
+
+pub struct HttpAuth {
+    config: HttpAuthConfig,
+    // Callers without a limit of their own
+    limiter: ClientRateLimiter,
+    // API keys with their own requests_per_minute, by name
+    key_limiters: HashMap<String, ClientRateLimiter>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    sub: String,
+    // Unix timestamps (seconds)
+    exp: i64,
+    nbf: Option<i64>,
+}
+
+// Compare without an early exit, so response timing doesn't reveal how much of a key matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// RFC 2104 HMAC over SHA-256
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+// The JWT's subject if the token is signed with the secret and currently valid
+fn verify_jwt(token: &str, secret: &str, now: i64) -> Result<String, &'static str> {
+    let mut segments = token.split('.');
+    let (Some(header), Some(claims), Some(signature), None) = (segments.next(), segments.next(), segments.next(), segments.next()) else {
+        return Err("malformed token");
+    };
+    let decode = |segment: &str| URL_SAFE_NO_PAD.decode(segment).map_err(|_| "malformed token");
+
+    let header: JwtHeader = serde_json::from_slice(&decode(header)?).map_err(|_| "malformed token")?;
+    if header.alg != "HS256" {
+        return Err("unsupported token algorithm");
+    }
+    let signed = &token[..token.len() - signature.len() - 1];
+    if !constant_time_eq(&hmac_sha256(secret.as_bytes(), signed.as_bytes()), &decode(signature)?) {
+        return Err("invalid token signature");
+    }
+
+    let claims: JwtClaims = serde_json::from_slice(&decode(claims)?).map_err(|_| "token needs sub and exp claims")?;
+    if claims.exp <= now {
+        return Err("token expired");
+    }
+    if claims.nbf.is_some_and(|nbf| nbf > now) {
+        return Err("token not yet valid");
+    }
+    Ok(claims.sub)
+}
+
+impl HttpAuth {
+    pub fn new(config: &HttpAuthConfig) -> Self {
+        let limit = |requests_per_minute| ClientRateLimiter::new(ClientRateLimitConfig { requests_per_minute, burst: config.burst });
+        HttpAuth {
+            config: config.clone(),
+            limiter: limit(config.requests_per_minute),
+            key_limiters: config
+                .api_keys
+                .iter()
+                .filter_map(|key| Some((key.name.clone(), limit(key.requests_per_minute?))))
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    // Exempt paths match exactly, or by prefix when they end in '*'
+    pub fn is_exempt(&self, path: &str) -> bool {
+        self.config.exempt_paths.iter().any(|exempt| match exempt.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == exempt,
+        })
+    }
+
+    // The caller's name for an Authorization header value
+    pub fn authenticate(&self, authorization: Option<&str>, now: i64) -> Result<String, &'static str> {
+        let authorization = authorization.ok_or("missing Authorization header")?;
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .ok_or("expected Authorization: Bearer <token>")?;
+        if let Some(key) = self.config.api_keys.iter().find(|key| constant_time_eq(key.key.as_bytes(), token.as_bytes())) {
+            return Ok(key.name.clone());
+        }
+        match &self.config.jwt_secret {
+            Some(secret) if token.matches('.').count() == 2 => verify_jwt(token, secret, now).map(|subject| format!("jwt:{}", subject)),
+            _ => Err("invalid API key"),
+        }
+    }
+
+    pub fn check_rate(&self, caller: &str, now: Instant) -> RateDecision {
+        self.key_limiters.get(caller).unwrap_or(&self.limiter).check(caller, now)
+    }
+}
+
+fn reject<B>(req: ServiceRequest, response: HttpResponse) -> ServiceResponse<EitherBody<B>> {
+    req.into_response(response).map_into_right_body()
+}
+
+// Middleware for the whole app; a no-op while no keys or JWT secret are configured
+pub async fn require_api_auth<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let Some(auth) = req.app_data::<web::Data<HttpAuth>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    if !auth.is_enabled() || auth.is_exempt(req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let authorization = req.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+    let caller = match auth.authenticate(authorization, chrono::Utc::now().timestamp()) {
+        Ok(caller) => caller,
+        Err(error) => {
+            let response = HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .json(serde_json::json!({ "error": error }));
+            return Ok(reject(req, response));
+        }
+    };
+
+    let retry_after = match auth.check_rate(&caller, Instant::now()) {
+        RateDecision::Allowed => {
+            req.extensions_mut().insert(ApiCaller(caller));
+            return next.call(req).await.map(ServiceResponse::map_into_left_body);
+        }
+        RateDecision::Throttled { retry_after } => {
+            warn!("Throttling REST API caller {} for {:.1}s", caller, retry_after.as_secs_f64());
+            retry_after
+        }
+        RateDecision::StillThrottled { retry_after } => retry_after,
+    };
+    Ok(reject(req, too_many_requests(retry_after)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use crate::config::HttpApiKey;
+
+    const SECRET: &str = "test-secret";
+
+    fn jwt(claims: serde_json::Value) -> String {
+        let encode = |value: serde_json::Value| URL_SAFE_NO_PAD.encode(value.to_string());
+        let signed = format!("{}.{}", encode(serde_json::json!({ "alg": "HS256", "typ": "JWT" })), encode(claims));
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(hmac_sha256(SECRET.as_bytes(), signed.as_bytes())))
+    }
+
+    fn auth_config() -> HttpAuthConfig {
+        HttpAuthConfig {
+            api_keys: vec![HttpApiKey { name: "ios".to_string(), key: "abc123".to_string(), requests_per_minute: None }],
+            jwt_secret: Some(SECRET.to_string()),
+            requests_per_minute: 60,
+            burst: 2,
+            ..HttpAuthConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let auth = HttpAuth::new(&auth_config());
+        let now = 1_700_000_000;
+        assert_eq!(auth.authenticate(Some("Bearer abc123"), now), Ok("ios".to_string()));
+        assert!(auth.authenticate(None, now).is_err());
+        assert!(auth.authenticate(Some("Bearer abc124"), now).is_err());
+        assert!(auth.authenticate(Some("abc123"), now).is_err());
+
+        let valid = jwt(serde_json::json!({ "sub": "dashboard", "exp": now + 60 }));
+        assert_eq!(auth.authenticate(Some(&format!("Bearer {}", valid)), now), Ok("jwt:dashboard".to_string()));
+        let expired = jwt(serde_json::json!({ "sub": "dashboard", "exp": now }));
+        assert_eq!(auth.authenticate(Some(&format!("Bearer {}", expired)), now), Err("token expired"));
+        let tampered = valid.replace(valid.split('.').nth(1).unwrap(), &URL_SAFE_NO_PAD.encode(r#"{"sub":"admin","exp":9999999999}"#));
+        assert_eq!(auth.authenticate(Some(&format!("Bearer {}", tampered)), now), Err("invalid token signature"));
+    }
+
+    #[actix_web::test]
+    async fn test_middleware() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(HttpAuth::new(&auth_config())))
+                .wrap(from_fn(require_api_auth))
+                .route("/health", web::get().to(|| async { "ok" }))
+                .route("/api/cmc-mapping", web::get().to(|| async { "mapping" })),
+        ).await;
+        let get = |path: &str, key: Option<&str>| {
+            let request = TestRequest::get().uri(path);
+            match key {
+                Some(key) => request.insert_header((AUTHORIZATION, format!("Bearer {}", key))),
+                None => request,
+            }.to_request()
+        };
+
+        assert_eq!(call_service(&app, get("/health", None)).await.status(), 200);
+        let response = call_service(&app, get("/api/cmc-mapping", None)).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+
+        // Burst of 2, then throttled with a Retry-After
+        assert_eq!(call_service(&app, get("/api/cmc-mapping", Some("abc123"))).await.status(), 200);
+        assert_eq!(call_service(&app, get("/api/cmc-mapping", Some("abc123"))).await.status(), 200);
+        let response = call_service(&app, get("/api/cmc-mapping", Some("abc123"))).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::web;
+use tracing::warn;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::config::ClusterCacheConfig;
+use crate::identity::{CoinIdentity, IdentityMap};
+use crate::types::{AppState, CryptoCurrency};
+use shared::{CoinCrabError, CoinCrabResult};
+
+// The mapping is refetched once a day at most, however many instances start
+const MAPPING_TTL: Duration = Duration::from_secs(86400);
+// Matches the in-memory logo expiry
+const LOGO_TTL: Duration = Duration::from_secs(86400);
+
+// Listings as last fetched by any instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedListings {
+    pub listings: Vec<CryptoCurrency>,
+    // Unix seconds of the fetch
+    pub fetched_at: u64,
+}
+
+impl SharedListings {
+    pub fn fetched_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.fetched_at)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SharedMapping {
+    cmc_mapping: HashMap<String, u32>,
+    identities: Vec<CoinIdentity>,
+}
+
+// Redis cache of the latest listings, the coin mapping and logos, shared by every server
+// instance behind a load balancer so they serve the same data and a provider request made
+// by one instance is not repeated by the others. Redis errors are logged and treated as a
+// miss; without REDIS_URL every call is a no-op.
+pub struct ClusterCache {
+    connection: Option<ConnectionManager>,
+    key_prefix: String,
+}
+
+fn redis_error(e: redis::RedisError) -> CoinCrabError {
+    CoinCrabError::Io(format!("Redis error: {}", e))
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl ClusterCache {
+    pub async fn connect(config: &ClusterCacheConfig) -> CoinCrabResult<Self> {
+        let connection = match &config.redis_url {
+            Some(url) => {
+                let client = redis::Client::open(url.as_str()).map_err(redis_error)?;
+                Some(ConnectionManager::new(client).await.map_err(redis_error)?)
+            }
+            None => None,
+        };
+        Ok(ClusterCache { connection, key_prefix: config.key_prefix.clone() })
+    }
+
+    pub fn disabled() -> Self {
+        ClusterCache { connection: None, key_prefix: String::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.key_prefix, name)
+    }
+
+    async fn get_bytes(&self, name: &str) -> Option<Vec<u8>> {
+        let mut connection = self.connection.clone()?;
+        match connection.get::<_, Option<Vec<u8>>>(self.key(name)).await {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read {} from Redis: {}", name, e);
+                None
+            }
+        }
+    }
+
+    async fn set_bytes(&self, name: &str, value: Vec<u8>, ttl: Duration) {
+        let Some(mut connection) = self.connection.clone() else {
+            return;
+        };
+        if let Err(e) = connection.set_ex::<_, _, ()>(self.key(name), value, ttl.as_secs().max(1)).await {
+            warn!("Failed to write {} to Redis: {}", name, e);
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let bytes = self.get_bytes(name).await?;
+        serde_json::from_slice(&bytes)
+            .inspect_err(|e| warn!("Ignoring unreadable {} in Redis: {}", name, e))
+            .ok()
+    }
+
+    async fn set_json<T: Serialize>(&self, name: &str, value: &T, ttl: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+        match serde_json::to_vec(value) {
+            Ok(bytes) => self.set_bytes(name, bytes, ttl).await,
+            Err(e) => warn!("Failed to serialize {} for Redis: {}", name, e),
+        }
+    }
+
+    pub async fn listings(&self) -> Option<SharedListings> {
+        self.get_json("latest_prices").await
+    }
+
+    // Kept for a few update intervals so standbys still have prices if the leader stalls
+    pub async fn store_listings(&self, listings: &[CryptoCurrency], fetched_at: SystemTime, update_interval: Duration) {
+        let shared = SharedListings { listings: listings.to_vec(), fetched_at: unix_seconds(fetched_at) };
+        self.set_json("latest_prices", &shared, update_interval * 3).await;
+    }
+
+    // Install the shared CMC mapping and identity map. Returns the number of mapped coins, 0
+    // (leaving the state untouched) when no instance has fetched it within the last day.
+    pub async fn restore_mapping(&self, state: &web::Data<AppState>) -> usize {
+        let Some(shared) = self.get_json::<SharedMapping>("cmc_mapping").await else {
+            return 0;
+        };
+        let count = shared.cmc_mapping.len();
+        if count > 0 {
+            *state.cmc_mapping.lock().unwrap() = shared.cmc_mapping;
+            *state.identity_map.lock().unwrap() = IdentityMap::from_identities(shared.identities);
+        }
+        count
+    }
+
+    pub async fn store_mapping(&self, state: &web::Data<AppState>) {
+        let shared = SharedMapping {
+            cmc_mapping: state.cmc_mapping.lock().unwrap().clone(),
+            identities: state.identity_map.lock().unwrap().identities().into_iter().cloned().collect(),
+        };
+        self.set_json("cmc_mapping", &shared, MAPPING_TTL).await;
+    }
+
+    // Logo bytes under the in-memory logo cache key
+    pub async fn logo(&self, cache_key: &str) -> Option<Vec<u8>> {
+        self.get_bytes(&format!("logo:{}", cache_key)).await
+    }
+
+    pub async fn store_logo(&self, cache_key: &str, image: Vec<u8>) {
+        self.set_bytes(&format!("logo:{}", cache_key), image, LOGO_TTL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_disabled_cache_is_a_no_op() {
+        let cache = ClusterCache::disabled();
+        assert!(!cache.is_enabled());
+        cache.store_listings(&[], SystemTime::now(), Duration::from_secs(60)).await;
+        cache.store_logo("BTC", b"png".to_vec()).await;
+        assert!(cache.listings().await.is_none());
+        assert!(cache.logo("BTC").await.is_none());
+
+        let config = ClusterCacheConfig { redis_url: Some("not a url".to_string()), ..ClusterCacheConfig::default() };
+        assert!(ClusterCache::connect(&config).await.is_err());
+        assert!(!ClusterCache::connect(&ClusterCacheConfig::default()).await.unwrap().is_enabled());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+// Concurrent loads of the same key share one call: the first caller runs its load and the
+// others wait for its result. If the running caller is dropped before finishing, a waiting
+// caller runs its own load instead. The key is forgotten once a result is in, so later
+// callers load again (normally hitting whatever cache the load filled).
+pub struct InFlight<T> {
+    pending: Mutex<HashMap<String, Arc<OnceCell<T>>>>,
+}
+
+impl<T: Clone> Default for InFlight<T> {
+    fn default() -> Self {
+        InFlight { pending: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<T: Clone> InFlight<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn run<F, Fut>(&self, key: &str, load: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let cell = self.pending.lock().unwrap().entry(key.to_string()).or_default().clone();
+        let result = cell.get_or_init(load).await.clone();
+        let mut pending = self.pending.lock().unwrap();
+        if pending.get(key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+            pending.remove(key);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_loads_share_one_call() {
+        let in_flight = Arc::new(InFlight::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let load = |key: &'static str| {
+            let in_flight = in_flight.clone();
+            let calls = calls.clone();
+            tokio::spawn(async move {
+                in_flight
+                    .run(key, || async {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        format!("{} series", key)
+                    })
+                    .await
+            })
+        };
+
+        let handles: Vec<_> = (0..10).map(|_| load("BTC:24h")).chain([load("ETH:24h")]).collect();
+        let mut results = Vec::new();
+        for handle in handles {
+            results.push(handle.await.unwrap());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(results.iter().filter(|result| *result == "BTC:24h series").count(), 10);
+        assert!(in_flight.pending.lock().unwrap().is_empty());
+
+        // Finished keys load again
+        load("BTC:24h").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_loader_is_replaced() {
+        let in_flight: Arc<InFlight<u32>> = Arc::new(InFlight::new());
+        let first = {
+            let in_flight = in_flight.clone();
+            tokio::spawn(async move { in_flight.run("BTC:7d", std::future::pending).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        first.abort();
+        assert_eq!(in_flight.run("BTC:7d", || async { 7 }).await, 7);
+    }
+}
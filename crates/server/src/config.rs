@@ -1,17 +1,1193 @@
-use log::{info, warn};
-use std::path::Path;
+use tracing::{info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{LazyLock, RwLock};
+use shared::{CoinCrabError, CoinCrabResult};
+use crate::data::HISTORICAL_TIMEFRAMES;
+use crate::fields::QuoteFields;
+use crate::identity::IdentityMap;
 
 pub struct ServerConfig {
     pub api_key: String,
     pub log_level: String,
+    // Per-module levels on top of log_level, in RUST_LOG syntax
+    pub log_filter: String,
+    // Host the server's own MQTT clients (publisher, request subscriber) connect to
     pub mqtt_broker_host: String,
     pub mqtt_broker_port: u16,
+    // Interface the embedded broker listens on
+    pub mqtt_bind_address: String,
     pub http_icon_port: u16,
+    // Interface the HTTP server listens on; 127.0.0.1 when behind a reverse proxy
+    pub http_bind_address: String,
+    pub http_auth: HttpAuthConfig,
+    pub historical_rate_limit: HistoricalRateLimitConfig,
+    pub cors: CorsConfig,
     pub update_interval_seconds: u64,
+    // TOML file the settings are read from (COIN_CRAB_CONFIG); environment variables win over it
+    pub config_file: PathBuf,
+    pub http_client: HttpClientConfig,
+    pub dry_run: bool,
+    pub topic_prefix: String,
+    pub leader: LeaderConfig,
+    pub mqtt_tls: MqttTlsConfig,
+    // Have the leader revert publishes to the server-owned topics (crypto/prices/#,
+    // crypto/historical/#) that didn't come from the server. Best-effort: the broker still fans
+    // the forged message out first. On by default.
+    pub topic_acl: bool,
+    // Historical requests fetched from the provider at the same time
+    pub historical_request_concurrency: usize,
+    pub update_tiers: UpdateTierConfig,
+    pub price_delta: PriceDeltaConfig,
+    // How long published prices stay valid (expires_at in the price envelope)
+    pub price_ttl_seconds: u64,
+    // Publish MessagePack copies of the price payloads on "<topic>/msgpack" alongside JSON
+    pub msgpack_payloads: bool,
+    // Publish indicators computed over each historical series on crypto/indicators/{SYMBOL}/{timeframe}
+    pub indicator_topics: bool,
+    pub client_rate_limit: ClientRateLimitConfig,
+    // Client refresh requests don't trigger a fetch if the listings are younger than this
+    pub price_refresh_min_interval_seconds: u64,
+    // Most symbols one device's watchlist may hold
+    pub watchlist_max_symbols: usize,
+    // Seconds between sweeps clearing retained topics of coins and timeframes no longer served; 0 disables
+    pub retained_compaction_interval_seconds: u64,
+    pub market_data: MarketDataConfig,
+    pub binance_stream: BinanceStreamConfig,
+    pub cache_persistence: CachePersistenceConfig,
+    pub logo_cache: LogoCacheConfig,
+    // Quote fields included in price payloads; price is always sent
+    pub payload_fields: QuoteFields,
+    pub storage: StorageConfig,
+    pub backfill: BackfillConfig,
+    pub anomalies: AnomalyConfig,
+    pub cluster_cache: ClusterCacheConfig,
+    pub daily_summary: DailySummaryConfig,
+    pub alerts: AlertsConfig,
+    pub fx: FxConfig,
+    pub portfolio: PortfolioConfig,
+    pub dead_letters: DeadLetterConfig,
+    pub pinned: PinnedConfig,
+    pub tracked: TrackedSeriesConfig,
+}
+
+// Coins and timeframes the historical maintenance tasks work on
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedSeriesConfig {
+    // Retained historical topics cleared once older than their timeframe's lifetime, so clients refetch them
+    pub refresh_symbols: Vec<String>,
+    pub refresh_timeframes: Vec<String>,
+    // Series loaded ahead of everything else at startup
+    pub priority_symbols: Vec<String>,
+    pub priority_timeframes: Vec<String>,
+}
+
+impl Default for TrackedSeriesConfig {
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        TrackedSeriesConfig {
+            refresh_symbols: list(&["BTC", "ETH", "ADA", "SOL", "DOT", "MATIC", "LINK", "XRP", "LTC", "BCH"]),
+            refresh_timeframes: list(&HISTORICAL_TIMEFRAMES),
+            priority_symbols: list(&["BTC", "ETH"]),
+            priority_timeframes: list(&["24h", "7d"]),
+        }
+    }
+}
+
+impl TrackedSeriesConfig {
+    pub fn from_env() -> CoinCrabResult<Self> {
+        let defaults = TrackedSeriesConfig::default();
+        let symbols = |name: &str, default: Vec<String>| env_string(name).map(|list| parse_symbol_list(&list)).unwrap_or(default);
+        Ok(TrackedSeriesConfig {
+            refresh_symbols: symbols("TRACKED_SYMBOLS", defaults.refresh_symbols),
+            refresh_timeframes: timeframes_from_env("TRACKED_TIMEFRAMES", defaults.refresh_timeframes)?,
+            priority_symbols: symbols("PRIORITY_SYMBOLS", defaults.priority_symbols),
+            priority_timeframes: timeframes_from_env("PRIORITY_TIMEFRAMES", defaults.priority_timeframes)?,
+        })
+    }
+
+    // Drops symbols the CMC mapping doesn't know, returning them. Nothing is dropped while the
+    // mapping is empty, since it may simply not have loaded yet.
+    pub fn retain_mapped(&mut self, identities: &IdentityMap) -> Vec<String> {
+        if identities.len() == 0 {
+            return Vec::new();
+        }
+        let mut unknown = Vec::new();
+        for symbols in [&mut self.refresh_symbols, &mut self.priority_symbols] {
+            symbols.retain(|symbol| {
+                let known = identities.by_symbol(symbol).is_some();
+                if !known && !unknown.contains(symbol) {
+                    unknown.push(symbol.clone());
+                }
+                known
+            });
+        }
+        unknown
+    }
+}
+
+// An unsupported timeframe would only ever clear or load nothing, so it fails startup
+fn timeframes_from_env(name: &str, default: Vec<String>) -> CoinCrabResult<Vec<String>> {
+    let Some(list) = env_string(name) else {
+        return Ok(default);
+    };
+    let timeframes: Vec<String> = list.split(',').map(|timeframe| timeframe.trim().to_lowercase()).filter(|timeframe| !timeframe.is_empty()).collect();
+    match timeframes.iter().find(|timeframe| !HISTORICAL_TIMEFRAMES.contains(&timeframe.as_str())) {
+        Some(invalid) => Err(CoinCrabError::Config(format!(
+            "{} contains unsupported timeframe '{}' (expected one of {})",
+            name,
+            invalid,
+            HISTORICAL_TIMEFRAMES.join(", ")
+        ))),
+        None => Ok(timeframes),
+    }
+}
+
+// Coins kept in the feed whatever their rank, managed through /api/pinned
+#[derive(Debug, Clone, Default)]
+pub struct PinnedConfig {
+    // Always pinned; more can be added at runtime
+    pub symbols: Vec<String>,
+    // JSON file the runtime pins are kept in across restarts; None keeps them in memory only
+    pub path: Option<String>,
+}
+
+impl PinnedConfig {
+    pub fn from_env() -> Self {
+        PinnedConfig {
+            symbols: env_string("PINNED_SYMBOLS")
+                .map(|list| parse_symbol_list(&list))
+                .unwrap_or_default(),
+            path: env_string("PINNED_SYMBOLS_PATH"),
+        }
+    }
+}
+
+// Publishes that failed every retry, inspected and replayed through /api/admin/dead-letters
+#[derive(Debug, Clone)]
+pub struct DeadLetterConfig {
+    // JSON file the log is kept in across restarts; None keeps it in memory only
+    pub path: Option<String>,
+    // Oldest entries are dropped beyond this
+    pub max_entries: usize,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        DeadLetterConfig {
+            path: None,
+            max_entries: 500,
+        }
+    }
+}
+
+impl DeadLetterConfig {
+    pub fn from_env() -> Self {
+        let defaults = DeadLetterConfig::default();
+        DeadLetterConfig {
+            path: env_string("DEAD_LETTER_PATH"),
+            max_entries: env_or("DEAD_LETTER_MAX_ENTRIES", defaults.max_entries).max(1),
+        }
+    }
+}
+
+// Holdings managed through /api/portfolios/{portfolio_id}
+#[derive(Debug, Clone)]
+pub struct PortfolioConfig {
+    // JSON file the holdings are kept in across restarts; None keeps them in memory only
+    pub path: Option<String>,
+    // Most coins one portfolio may hold
+    pub max_holdings: usize,
+}
+
+impl Default for PortfolioConfig {
+    fn default() -> Self {
+        PortfolioConfig {
+            path: None,
+            max_holdings: 200,
+        }
+    }
+}
+
+impl PortfolioConfig {
+    pub fn from_env() -> Self {
+        let defaults = PortfolioConfig::default();
+        PortfolioConfig {
+            path: env_string("PORTFOLIO_PATH"),
+            max_holdings: env_or("PORTFOLIO_MAX_HOLDINGS", defaults.max_holdings).max(1),
+        }
+    }
+}
+
+// Fiat quotes derived from the USD listings with exchange rates, instead of asking the
+// market data provider to convert into each currency
+#[derive(Debug, Clone)]
+pub struct FxConfig {
+    // ISO codes to add beside USD; empty disables conversion
+    pub currencies: Vec<String>,
+    // Latest USD rates, as served by open.er-api.com or frankfurter.app
+    pub api_url: String,
+    pub refresh_seconds: u64,
+}
+
+impl Default for FxConfig {
+    fn default() -> Self {
+        FxConfig {
+            currencies: Vec::new(),
+            api_url: "https://open.er-api.com/v6/latest/USD".to_string(),
+            refresh_seconds: 3600,
+        }
+    }
+}
+
+impl FxConfig {
+    pub fn from_env() -> Self {
+        let defaults = FxConfig::default();
+        let currencies = env_string("FX_CURRENCIES")
+            .map(|list| {
+                list.split(',')
+                    .map(|code| code.trim().to_uppercase())
+                    .filter(|code| !code.is_empty() && code != "USD")
+                    .collect()
+            })
+            .unwrap_or_default();
+        FxConfig {
+            currencies,
+            api_url: env_string("FX_API_URL").unwrap_or(defaults.api_url),
+            refresh_seconds: env_or("FX_REFRESH_SECONDS", defaults.refresh_seconds).max(60),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.currencies.is_empty()
+    }
+}
+
+// Price alerts devices register on crypto/alerts/register/{device_id}
+#[derive(Debug, Clone)]
+pub struct AlertsConfig {
+    // JSON file the rules are kept in across restarts; None keeps them in memory only
+    pub rules_path: Option<String>,
+    // A registration with more rules than this is rejected
+    pub max_rules_per_device: usize,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig {
+            rules_path: None,
+            max_rules_per_device: 50,
+        }
+    }
+}
+
+impl AlertsConfig {
+    pub fn from_env() -> Self {
+        let defaults = AlertsConfig::default();
+        AlertsConfig {
+            rules_path: env_string("ALERT_RULES_PATH"),
+            max_rules_per_device: env_or("ALERT_MAX_RULES_PER_DEVICE", defaults.max_rules_per_device).max(1),
+        }
+    }
+}
+
+// Opt-in daily digest of the market, published at a fixed UTC time
+#[derive(Debug, Clone)]
+pub struct DailySummaryConfig {
+    // (hour, minute) in UTC; None disables the summary
+    pub time_utc: Option<(u32, u32)>,
+    // Gainers and losers listed in the summary
+    pub movers: usize,
+    // Also POSTed as JSON to this URL when set
+    pub webhook_url: Option<String>,
+}
+
+impl Default for DailySummaryConfig {
+    fn default() -> Self {
+        DailySummaryConfig {
+            time_utc: None,
+            movers: 5,
+            webhook_url: None,
+        }
+    }
+}
+
+impl DailySummaryConfig {
+    // A malformed time fails startup rather than silently never sending the summary
+    pub fn from_env() -> CoinCrabResult<Self> {
+        let defaults = DailySummaryConfig::default();
+        let time_utc = match env_string("DAILY_SUMMARY_TIME_UTC") {
+            Some(time) => Some(parse_utc_time(&time)?),
+            None => None,
+        };
+        Ok(DailySummaryConfig {
+            time_utc,
+            movers: env_or("DAILY_SUMMARY_MOVERS", defaults.movers).max(1),
+            webhook_url: env_string("DAILY_SUMMARY_WEBHOOK_URL"),
+        })
+    }
+}
+
+// "HH:MM" in 24-hour time
+fn parse_utc_time(time: &str) -> CoinCrabResult<(u32, u32)> {
+    let invalid = || CoinCrabError::Config(format!("Invalid DAILY_SUMMARY_TIME_UTC '{}' (expected HH:MM)", time));
+    let (hour, minute) = time.trim().split_once(':').ok_or_else(invalid)?;
+    let hour: u32 = hour.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute.parse().map_err(|_| invalid())?;
+    if hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    Ok((hour, minute))
+}
+
+// Redis cache of the listings, coin mapping and logos shared by instances behind a load
+// balancer. Disabled unless a Redis URL is set.
+#[derive(Debug, Clone)]
+pub struct ClusterCacheConfig {
+    pub redis_url: Option<String>,
+    // Prepended to every key, so several deployments can share one Redis
+    pub key_prefix: String,
+}
+
+impl Default for ClusterCacheConfig {
+    fn default() -> Self {
+        ClusterCacheConfig {
+            redis_url: None,
+            key_prefix: "coin-crab".to_string(),
+        }
+    }
+}
+
+impl ClusterCacheConfig {
+    pub fn from_env() -> Self {
+        let defaults = ClusterCacheConfig::default();
+        ClusterCacheConfig {
+            redis_url: env_string("REDIS_URL"),
+            key_prefix: env_string("REDIS_KEY_PREFIX").unwrap_or(defaults.key_prefix),
+        }
+    }
+}
+
+// Database of historical series and the coin mapping, read before the market data provider
+// and loaded at startup. Disabled unless a database is configured.
+#[derive(Debug, Clone, Default)]
+pub struct StorageConfig {
+    pub sqlite_path: Option<String>,
+    // PostgreSQL connection string for multi-instance deployments; takes precedence over SQLite
+    pub database_url: Option<String>,
+}
+
+impl StorageConfig {
+    pub fn from_env() -> Self {
+        StorageConfig {
+            sqlite_path: env_string("HISTORY_SQLITE_PATH"),
+            database_url: env_string("DATABASE_URL"),
+        }
+    }
+}
+
+// Background job filling the history store with series it is missing or holds stale, so
+// charts of less popular coins are served from the store instead of waiting on the provider.
+// Runs on the leader and only with a storage database.
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+    // Seconds between runs; 0 disables
+    pub interval_seconds: u64,
+    // Coins to backfill; the tracked symbols when empty
+    pub symbols: Vec<String>,
+    pub timeframes: Vec<String>,
+    // Provider calls one run may spend, bounding its share of the API credits
+    pub max_fetches_per_run: usize,
+    pub fetch_delay_ms: u64,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        BackfillConfig {
+            interval_seconds: 3600,
+            symbols: Vec::new(),
+            timeframes: HISTORICAL_TIMEFRAMES.iter().map(|timeframe| timeframe.to_string()).collect(),
+            max_fetches_per_run: 30,
+            fetch_delay_ms: 1000,
+        }
+    }
+}
+
+impl BackfillConfig {
+    pub fn from_env() -> CoinCrabResult<Self> {
+        let defaults = BackfillConfig::default();
+        Ok(BackfillConfig {
+            interval_seconds: env_or("BACKFILL_INTERVAL_SECONDS", defaults.interval_seconds),
+            symbols: env_string("BACKFILL_SYMBOLS").map(|list| parse_symbol_list(&list)).unwrap_or_default(),
+            timeframes: timeframes_from_env("BACKFILL_TIMEFRAMES", defaults.timeframes)?,
+            max_fetches_per_run: env_or("BACKFILL_MAX_FETCHES_PER_RUN", defaults.max_fetches_per_run),
+            fetch_delay_ms: env_or("BACKFILL_FETCH_DELAY_MS", defaults.fetch_delay_ms),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.interval_seconds > 0 && self.max_fetches_per_run > 0
+    }
+}
+
+// Sanity checks on each listings fetch before it is cached and published; see anomalies.rs
+#[derive(Debug, Clone)]
+pub struct AnomalyConfig {
+    pub enabled: bool,
+    // A price moving more than this since the previous fetch is suspect until the next fetch
+    // confirms it
+    pub max_jump_percent: f64,
+    // Quotes whose last_updated is older than this are stale; 0 disables the check
+    pub max_quote_age_seconds: u64,
+    // Keep the previous quote of a bad coin; when false anomalies are only reported
+    pub suppress: bool,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            enabled: true,
+            max_jump_percent: 50.0,
+            max_quote_age_seconds: 3600,
+            suppress: true,
+        }
+    }
+}
+
+impl AnomalyConfig {
+    pub fn from_env() -> Self {
+        let defaults = AnomalyConfig::default();
+        AnomalyConfig {
+            enabled: env_or("ANOMALY_CHECKS_ENABLED", defaults.enabled),
+            max_jump_percent: env_or("ANOMALY_MAX_JUMP_PERCENT", defaults.max_jump_percent).max(0.0),
+            max_quote_age_seconds: env_or("ANOMALY_MAX_QUOTE_AGE_SECONDS", defaults.max_quote_age_seconds),
+            suppress: env_or("ANOMALY_SUPPRESS", defaults.suppress),
+        }
+    }
+}
+
+// On-disk copy of the price and historical caches, restored at startup so a restart doesn't
+// begin with empty caches. Disabled unless a path is set.
+#[derive(Debug, Clone)]
+pub struct CachePersistenceConfig {
+    pub path: Option<String>,
+    // Changes are written at most this often, off the fetch and publish path
+    pub flush_interval_seconds: u64,
+}
+
+impl Default for CachePersistenceConfig {
+    fn default() -> Self {
+        CachePersistenceConfig {
+            path: None,
+            flush_interval_seconds: 30,
+        }
+    }
+}
+
+impl CachePersistenceConfig {
+    pub fn from_env() -> Self {
+        let defaults = CachePersistenceConfig::default();
+        CachePersistenceConfig {
+            path: env_string("CACHE_PERSIST_PATH"),
+            flush_interval_seconds: env_or("CACHE_FLUSH_INTERVAL_SECONDS", defaults.flush_interval_seconds).max(1),
+        }
+    }
+}
+
+// Logo images are kept in memory up to a budget and, with a cache directory, on disk so a
+// restart doesn't download every logo again
+#[derive(Debug, Clone)]
+pub struct LogoCacheConfig {
+    pub dir: Option<String>,
+    pub memory_budget_mb: usize,
+    pub disk_budget_mb: u64,
+    // Logos no client asked for in this long are deleted from disk
+    pub max_age_days: u64,
+}
+
+impl Default for LogoCacheConfig {
+    fn default() -> Self {
+        LogoCacheConfig {
+            dir: None,
+            memory_budget_mb: 32,
+            disk_budget_mb: 256,
+            max_age_days: 30,
+        }
+    }
+}
+
+impl LogoCacheConfig {
+    pub fn from_env() -> Self {
+        let defaults = LogoCacheConfig::default();
+        LogoCacheConfig {
+            dir: env_string("LOGO_CACHE_DIR"),
+            memory_budget_mb: env_or("LOGO_CACHE_MEMORY_MB", defaults.memory_budget_mb),
+            disk_budget_mb: env_or("LOGO_CACHE_DISK_MB", defaults.disk_budget_mb),
+            max_age_days: env_or("LOGO_CACHE_MAX_AGE_DAYS", defaults.max_age_days),
+        }
+    }
+}
+
+// Real-time prices from Binance's trade stream, published between listings fetches. Coins are
+// priced from their USDT pair; the other listing fields keep the last provider values.
+#[derive(Debug, Clone)]
+pub struct BinanceStreamConfig {
+    pub enabled: bool,
+    // Explicit symbols to stream, used instead of the top-ranked coins when set
+    pub symbols: Vec<String>,
+    // Number of top-ranked listed coins streamed when no symbols are given
+    pub top_n: usize,
+    // Trades are coalesced and published at most this often
+    pub publish_interval_ms: u64,
+}
+
+impl Default for BinanceStreamConfig {
+    fn default() -> Self {
+        BinanceStreamConfig {
+            enabled: false,
+            symbols: Vec::new(),
+            top_n: 20,
+            publish_interval_ms: 500,
+        }
+    }
+}
+
+impl BinanceStreamConfig {
+    pub fn from_env() -> Self {
+        let defaults = BinanceStreamConfig::default();
+        BinanceStreamConfig {
+            enabled: env_or("BINANCE_STREAM_ENABLED", defaults.enabled),
+            symbols: env_string("BINANCE_STREAM_SYMBOLS")
+                .map(|list| parse_symbol_list(&list))
+                .unwrap_or_default(),
+            top_n: env_or("BINANCE_STREAM_TOP_N", defaults.top_n).max(1),
+            publish_interval_ms: env_or("BINANCE_STREAM_PUBLISH_INTERVAL_MS", defaults.publish_interval_ms).max(100),
+        }
+    }
+}
+
+// CoinGecko as the secondary market data provider, taking over while CMC rate limits us or
+// rejects the API key
+#[derive(Debug, Clone)]
+pub struct MarketDataConfig {
+    pub coingecko_fallback: bool,
+    // Optional demo plan key; the public API works without one at a lower rate limit
+    pub coingecko_api_key: Option<String>,
+    // How long CoinGecko serves requests before CMC is tried again
+    pub failover_cooldown_seconds: u64,
+    pub cmc_circuit_breaker: CircuitBreakerConfig,
+}
+
+// Stops calling CoinMarketCap after repeated 429/5xx responses; see providers/circuit_breaker.rs
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    // Consecutive failures that open the breaker
+    pub failure_threshold: u32,
+    // First open period, doubled after each failed probe up to max_open_seconds
+    pub open_seconds: u64,
+    pub max_open_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 3,
+            open_seconds: 60,
+            max_open_seconds: 1800,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn from_env() -> Self {
+        let defaults = CircuitBreakerConfig::default();
+        CircuitBreakerConfig {
+            failure_threshold: env_or("CMC_BREAKER_FAILURE_THRESHOLD", defaults.failure_threshold).max(1),
+            open_seconds: env_or("CMC_BREAKER_OPEN_SECONDS", defaults.open_seconds).max(1),
+            max_open_seconds: env_or("CMC_BREAKER_MAX_OPEN_SECONDS", defaults.max_open_seconds),
+        }
+    }
+}
+
+impl Default for MarketDataConfig {
+    fn default() -> Self {
+        MarketDataConfig {
+            coingecko_fallback: true,
+            coingecko_api_key: None,
+            failover_cooldown_seconds: 300,
+            cmc_circuit_breaker: CircuitBreakerConfig::default(),
+        }
+    }
+}
+
+impl MarketDataConfig {
+    pub fn from_env() -> Self {
+        let defaults = MarketDataConfig::default();
+        MarketDataConfig {
+            coingecko_fallback: env_or("COINGECKO_FALLBACK_ENABLED", defaults.coingecko_fallback),
+            coingecko_api_key: env_string("COINGECKO_API_KEY"),
+            failover_cooldown_seconds: env_or("MARKET_DATA_FAILOVER_COOLDOWN_SECONDS", defaults.failover_cooldown_seconds).max(1),
+            cmc_circuit_breaker: CircuitBreakerConfig::from_env(),
+        }
+    }
+}
+
+// Per-client limit on MQTT requests that can cost provider credits. Each client gets a token
+// bucket of `burst` requests refilled at `requests_per_minute`; 0 requests per minute disables it.
+#[derive(Debug, Clone)]
+pub struct ClientRateLimitConfig {
+    pub requests_per_minute: u32,
+    pub burst: u32,
+}
+
+impl Default for ClientRateLimitConfig {
+    fn default() -> Self {
+        ClientRateLimitConfig {
+            requests_per_minute: 30,
+            burst: 10,
+        }
+    }
+}
+
+impl ClientRateLimitConfig {
+    pub fn from_env() -> Self {
+        let defaults = ClientRateLimitConfig::default();
+        ClientRateLimitConfig {
+            requests_per_minute: env_or("CLIENT_REQUESTS_PER_MINUTE", defaults.requests_per_minute),
+            burst: env_or("CLIENT_REQUEST_BURST", defaults.burst).max(1),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_minute > 0
+    }
+}
+
+// One REST API key: callers send "Authorization: Bearer <key>"; the name is what logs and rate
+// limits refer to, so the key itself is never logged
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpApiKey {
+    pub name: String,
+    pub key: String,
+    // Overrides HTTP_AUTH_REQUESTS_PER_MINUTE for this key
+    pub requests_per_minute: Option<u32>,
+}
+
+// Authentication of the REST API. Off unless API keys or a JWT secret are configured; paths
+// listed in exempt_paths (e.g. /health for load balancers) never need a key.
+#[derive(Debug, Clone)]
+pub struct HttpAuthConfig {
+    pub api_keys: Vec<HttpApiKey>,
+    // HS256 secret for JWTs; their "sub" claim is the caller's name for rate limiting
+    pub jwt_secret: Option<String>,
+    pub exempt_paths: Vec<String>,
+    // Per caller; 0 disables rate limiting
+    pub requests_per_minute: u32,
+    pub burst: u32,
+}
+
+impl Default for HttpAuthConfig {
+    fn default() -> Self {
+        HttpAuthConfig {
+            api_keys: Vec::new(),
+            jwt_secret: None,
+            exempt_paths: vec!["/health".to_string()],
+            requests_per_minute: 120,
+            burst: 30,
+        }
+    }
+}
+
+impl HttpAuthConfig {
+    pub fn from_env() -> CoinCrabResult<Self> {
+        let defaults = HttpAuthConfig::default();
+        Ok(HttpAuthConfig {
+            api_keys: match env_string("HTTP_API_KEYS") {
+                Some(list) => parse_api_keys(&list)?,
+                None => Vec::new(),
+            },
+            jwt_secret: env_string("HTTP_JWT_SECRET"),
+            exempt_paths: env_string("HTTP_AUTH_EXEMPT_PATHS")
+                .map(|list| list.split(',').map(|path| path.trim().to_string()).filter(|path| !path.is_empty()).collect())
+                .unwrap_or(defaults.exempt_paths),
+            requests_per_minute: env_or("HTTP_AUTH_REQUESTS_PER_MINUTE", defaults.requests_per_minute),
+            burst: env_or("HTTP_AUTH_BURST", defaults.burst).max(1),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.jwt_secret.is_some()
+    }
+}
+
+// "name:key[:requests_per_minute]" entries, comma-separated. A malformed entry fails startup
+// rather than silently locking its caller out.
+fn parse_api_keys(list: &str) -> CoinCrabResult<Vec<HttpApiKey>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || CoinCrabError::Config(format!("Invalid HTTP_API_KEYS entry for '{}': expected name:key[:requests_per_minute]",
+                                                            entry.split(':').next().unwrap_or_default()));
+            let mut parts = entry.split(':').map(str::trim);
+            let (Some(name), Some(key)) = (parts.next(), parts.next()) else {
+                return Err(invalid());
+            };
+            let requests_per_minute = match parts.next() {
+                Some(limit) => Some(limit.parse().map_err(|_| invalid())?),
+                None => None,
+            };
+            if name.is_empty() || key.is_empty() || parts.next().is_some() {
+                return Err(invalid());
+            }
+            Ok(HttpApiKey { name: name.to_string(), key: key.to_string(), requests_per_minute })
+        })
+        .collect()
+}
+
+// Browser origins allowed to call the REST API cross-origin; none by default, "*" for any
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    // How long browsers may cache a preflight response
+    pub max_age_seconds: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].iter().map(|method| method.to_string()).collect(),
+            max_age_seconds: 3600,
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Self {
+        let defaults = CorsConfig::default();
+        let list = |name: &str| env_string(name).map(|list| {
+            list.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect::<Vec<_>>()
+        });
+        CorsConfig {
+            allowed_origins: list("CORS_ALLOWED_ORIGINS").unwrap_or(defaults.allowed_origins),
+            allowed_methods: list("CORS_ALLOWED_METHODS")
+                .map(|methods| methods.iter().map(|method| method.to_uppercase()).collect())
+                .unwrap_or(defaults.allowed_methods),
+            max_age_seconds: env_or("CORS_MAX_AGE_SECONDS", defaults.max_age_seconds),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_origins.is_empty()
+    }
+}
+
+// Token bucket on the REST historical endpoints, whose uncached requests each cost provider
+// calls. Authenticated callers get a bucket per API key or JWT subject, everyone else one per
+// IP address; 0 requests per minute disables it.
+#[derive(Debug, Clone)]
+pub struct HistoricalRateLimitConfig {
+    pub requests_per_minute: u32,
+    pub burst: u32,
+    // Take the client address from X-Forwarded-For; only safe behind a proxy that sets it
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for HistoricalRateLimitConfig {
+    fn default() -> Self {
+        HistoricalRateLimitConfig {
+            requests_per_minute: 20,
+            burst: 5,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+impl HistoricalRateLimitConfig {
+    pub fn from_env() -> Self {
+        let defaults = HistoricalRateLimitConfig::default();
+        HistoricalRateLimitConfig {
+            requests_per_minute: env_or("HTTP_HISTORICAL_REQUESTS_PER_MINUTE", defaults.requests_per_minute),
+            burst: env_or("HTTP_HISTORICAL_BURST", defaults.burst).max(1),
+            trust_forwarded_for: env_or("HTTP_TRUST_FORWARDED_FOR", defaults.trust_forwarded_for),
+        }
+    }
+}
+
+// Delta mode: between full snapshots only coins that moved more than the threshold are
+// published, to crypto/prices/delta and their per-symbol topics
+#[derive(Debug, Clone)]
+pub struct PriceDeltaConfig {
+    pub enabled: bool,
+    // Minimum price move, in percent of the last published price
+    pub threshold_percent: f64,
+    // Publish a full crypto/prices/latest snapshot every this many updates
+    pub snapshot_every: u32,
+}
+
+impl Default for PriceDeltaConfig {
+    fn default() -> Self {
+        PriceDeltaConfig {
+            enabled: false,
+            threshold_percent: 0.1,
+            snapshot_every: 10,
+        }
+    }
+}
+
+impl PriceDeltaConfig {
+    pub fn from_env() -> Self {
+        let defaults = PriceDeltaConfig::default();
+        PriceDeltaConfig {
+            enabled: env_or("PRICE_DELTA_ENABLED", defaults.enabled),
+            threshold_percent: env_or("PRICE_DELTA_THRESHOLD_PERCENT", defaults.threshold_percent).max(0.0),
+            snapshot_every: env_or("PRICE_DELTA_SNAPSHOT_EVERY", defaults.snapshot_every).max(1),
+        }
+    }
+}
+
+// Hot tier of coins refreshed more often than the listings interval via the cheaper quotes
+// endpoint; every other coin follows UPDATE_INTERVAL_SECONDS
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateTierConfig {
+    // Number of top-ranked coins in the hot tier; 0 disables it unless symbols are listed
+    pub hot_tier_size: usize,
+    // Explicit hot-tier symbols, used instead of the top-ranked coins when set
+    pub hot_symbols: Vec<String>,
+    pub hot_interval_seconds: u64,
+}
+
+impl Default for UpdateTierConfig {
+    fn default() -> Self {
+        UpdateTierConfig {
+            hot_tier_size: 10,
+            hot_symbols: Vec::new(),
+            hot_interval_seconds: 60,
+        }
+    }
+}
+
+impl UpdateTierConfig {
+    pub fn from_env() -> Self {
+        let defaults = UpdateTierConfig::default();
+        UpdateTierConfig {
+            hot_tier_size: env_or("HOT_TIER_SIZE", defaults.hot_tier_size),
+            hot_symbols: env_string("HOT_TIER_SYMBOLS")
+                .map(|list| parse_symbol_list(&list))
+                .unwrap_or_default(),
+            hot_interval_seconds: env_or("HOT_TIER_INTERVAL_SECONDS", defaults.hot_interval_seconds).max(1),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.hot_tier_size > 0 || !self.hot_symbols.is_empty()
+    }
+}
+
+// Split a comma-separated symbol list, upper-casing and dropping blanks
+fn parse_symbol_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|symbol| symbol.trim().to_uppercase())
+        .filter(|symbol| !symbol.is_empty())
+        .collect()
+}
+
+// TLS (MQTTS) listener for the embedded broker. The embedded rumqttd listener requires
+// client certificates signed by the CA, and the key must be an RSA key in PEM format.
+#[derive(Debug, Clone)]
+pub struct MqttTlsConfig {
+    pub enabled: bool,
+    pub port: u16,
+    pub ca_path: String,
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+impl Default for MqttTlsConfig {
+    fn default() -> Self {
+        MqttTlsConfig {
+            enabled: false,
+            port: 8883,
+            ca_path: "certs/ca.pem".to_string(),
+            cert_path: "certs/server.pem".to_string(),
+            key_path: "certs/server.key".to_string(),
+        }
+    }
+}
+
+impl MqttTlsConfig {
+    pub fn from_env() -> Self {
+        let defaults = MqttTlsConfig::default();
+        MqttTlsConfig {
+            enabled: env_or("MQTT_TLS_ENABLED", defaults.enabled),
+            port: env_or("MQTT_TLS_PORT", defaults.port),
+            ca_path: env_string("MQTT_TLS_CA_PATH").unwrap_or(defaults.ca_path),
+            cert_path: env_string("MQTT_TLS_CERT_PATH").unwrap_or(defaults.cert_path),
+            key_path: env_string("MQTT_TLS_KEY_PATH").unwrap_or(defaults.key_path),
+        }
+    }
+}
+
+// Lease-based leader election between instances sharing a broker
+#[derive(Debug, Clone)]
+pub struct LeaderConfig {
+    // Lease file shared by all instances; None disables election
+    pub lease_file: Option<String>,
+    pub lease_ttl_seconds: u64,
+}
+
+impl Default for LeaderConfig {
+    fn default() -> Self {
+        LeaderConfig {
+            lease_file: None,
+            lease_ttl_seconds: 30,
+        }
+    }
+}
+
+impl LeaderConfig {
+    pub fn from_env() -> Self {
+        let defaults = LeaderConfig::default();
+        LeaderConfig {
+            lease_file: env_string("LEADER_LEASE_FILE"),
+            lease_ttl_seconds: env_or("LEADER_LEASE_TTL_SECONDS", defaults.lease_ttl_seconds),
+        }
+    }
+}
+
+// Settings for the outbound reqwest client used for provider requests
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    pub connect_timeout_seconds: u64,
+    pub request_timeout_seconds: u64,
+    pub max_retries: u32,
+    pub retry_backoff_ms: u64,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout_seconds: u64,
+    pub proxy: ProxyConfig,
+    // Identifies this deployment in the User-Agent sent to providers
+    pub instance_id: String,
+    // Extra headers added to every provider request
+    pub extra_headers: Vec<(String, String)>,
+}
+
+// Egress proxy settings. Proxy URLs may use http://, https://, socks5:// or socks5h://
+// (socks5h resolves provider hostnames on the proxy, socks5 resolves them locally).
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    // Proxy used for every provider without an override
+    pub default_url: Option<String>,
+    // Per-provider overrides keyed by host; a url of "direct" bypasses the proxy for that host
+    pub overrides: Vec<ProviderProxy>,
+    // Hosts (or domain suffixes) that are always reached directly
+    pub no_proxy: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderProxy {
+    pub host: String,
+    pub url: String,
+}
+
+pub const CMC_API_HOST: &str = "pro-api.coinmarketcap.com";
+pub const CMC_LOGO_HOST: &str = "s2.coinmarketcap.com";
+
+impl ProxyConfig {
+    pub fn from_env() -> Self {
+        let overrides = [("CMC_API_PROXY_URL", CMC_API_HOST), ("CMC_LOGO_PROXY_URL", CMC_LOGO_HOST)]
+            .into_iter()
+            .filter_map(|(var, host)| {
+                env_string(var).map(|url| ProviderProxy { host: host.to_string(), url })
+            })
+            .collect();
+
+        ProxyConfig {
+            default_url: env_string("HTTP_PROXY_URL"),
+            overrides,
+            no_proxy: env_string("HTTP_NO_PROXY")
+                .map(|list| parse_no_proxy_list(&list))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.default_url.is_some() || !self.overrides.is_empty()
+    }
+}
+
+// Split a comma-separated no-proxy list, dropping blanks and normalising case
+fn parse_no_proxy_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+// Parse "Name=value,Other=value" pairs; entries without a name are dropped
+fn parse_header_list(list: &str) -> Vec<(String, String)> {
+    list.split(',')
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once('=')?;
+            let name = name.trim();
+            (!name.is_empty()).then(|| (name.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            connect_timeout_seconds: 10,
+            request_timeout_seconds: 30,
+            max_retries: 2,
+            retry_backoff_ms: 500,
+            pool_max_idle_per_host: 10,
+            pool_idle_timeout_seconds: 90,
+            proxy: ProxyConfig::default(),
+            instance_id: "default".to_string(),
+            extra_headers: Vec::new(),
+        }
+    }
+}
+
+impl HttpClientConfig {
+    pub fn from_env() -> Self {
+        let defaults = HttpClientConfig::default();
+        HttpClientConfig {
+            connect_timeout_seconds: env_or("HTTP_CONNECT_TIMEOUT_SECONDS", defaults.connect_timeout_seconds),
+            request_timeout_seconds: env_or("HTTP_REQUEST_TIMEOUT_SECONDS", defaults.request_timeout_seconds),
+            max_retries: env_or("HTTP_MAX_RETRIES", defaults.max_retries),
+            retry_backoff_ms: env_or("HTTP_RETRY_BACKOFF_MS", defaults.retry_backoff_ms),
+            pool_max_idle_per_host: env_or("HTTP_POOL_MAX_IDLE_PER_HOST", defaults.pool_max_idle_per_host),
+            pool_idle_timeout_seconds: env_or("HTTP_POOL_IDLE_TIMEOUT_SECONDS", defaults.pool_idle_timeout_seconds),
+            proxy: ProxyConfig::from_env(),
+            instance_id: env_string("HTTP_INSTANCE_ID").unwrap_or(defaults.instance_id),
+            extra_headers: env_string("HTTP_EXTRA_HEADERS")
+                .map(|list| parse_header_list(&list))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// Config file read when COIN_CRAB_CONFIG isn't set; it is optional
+const DEFAULT_CONFIG_FILE: &str = "crates/server/coin-crab.toml";
+
+// Settings from the config file, keyed by the environment variable they stand in for
+static FILE_SETTINGS: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+pub fn config_file_path() -> PathBuf {
+    std::env::var("COIN_CRAB_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_FILE))
+}
+
+// Read the TOML config file into the settings every *_from_env reads, replacing what an
+// earlier read left. A missing file means no file settings. Returns the number of settings.
+pub fn load_config_file(path: &Path) -> CoinCrabResult<usize> {
+    let settings = match std::fs::read_to_string(path) {
+        Ok(contents) => parse_config_file(&contents)
+            .map_err(|e| CoinCrabError::Config(format!("Invalid config file {}: {}", path.display(), e)))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(CoinCrabError::Config(format!("Failed to read config file {}: {}", path.display(), e))),
+    };
+    let count = settings.len();
+    *FILE_SETTINGS.write().unwrap() = settings;
+    Ok(count)
+}
+
+// Tables name the prefix of the variables they hold, so `[mqtt] broker_port = 1883` sets
+// MQTT_BROKER_PORT; lists become comma-separated values
+fn parse_config_file(contents: &str) -> Result<HashMap<String, String>, toml::de::Error> {
+    let table: toml::Table = toml::from_str(contents)?;
+    let mut settings = HashMap::new();
+    flatten_settings("", &table, &mut settings);
+    Ok(settings)
+}
+
+fn flatten_settings(prefix: &str, table: &toml::Table, settings: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let name = if prefix.is_empty() { key.to_uppercase() } else { format!("{}_{}", prefix, key.to_uppercase()) };
+        match value {
+            toml::Value::Table(table) => flatten_settings(&name, table, settings),
+            value => {
+                settings.insert(name, setting_value(value));
+            }
+        }
+    }
+}
+
+fn setting_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Array(items) => items.iter().map(setting_value).collect::<Vec<_>>().join(","),
+        value => value.to_string(),
+    }
+}
+
+// A setting from the environment, or else from the config file
+fn setting(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .or_else(|| FILE_SETTINGS.read().unwrap().get(name).cloned())
+}
+
+// Read an optional tuning value, falling back to the default if unset or invalid
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    setting(name)
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+// Read an optional string, treating blank values as unset
+fn env_string(name: &str) -> Option<String> {
+    setting(name)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// The settings a running server picks up when the config file changes or on SIGHUP; every
+// other setting needs a restart
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableConfig {
+    pub update_interval_seconds: u64,
+    pub update_tiers: UpdateTierConfig,
+    pub tracked: TrackedSeriesConfig,
+}
+
+impl Default for ReloadableConfig {
+    fn default() -> Self {
+        ReloadableConfig {
+            update_interval_seconds: 900,
+            update_tiers: UpdateTierConfig::default(),
+            tracked: TrackedSeriesConfig::default(),
+        }
+    }
+}
+
+impl ReloadableConfig {
+    pub fn from_env() -> CoinCrabResult<Self> {
+        let update_interval_seconds = env_or("UPDATE_INTERVAL_SECONDS", 0u64);
+        let update_interval_seconds = if update_interval_seconds == 0 {
+            warn!("UPDATE_INTERVAL_SECONDS not set, using default (900 seconds / 15 minutes)");
+            ReloadableConfig::default().update_interval_seconds
+        } else {
+            update_interval_seconds
+        };
+        Ok(ReloadableConfig {
+            update_interval_seconds,
+            update_tiers: UpdateTierConfig::from_env(),
+            tracked: TrackedSeriesConfig::from_env()?,
+        })
+    }
 }
 
 impl ServerConfig {
-    pub fn load() -> Result<Self, String> {
+    pub fn load() -> CoinCrabResult<Self> {
         // Load .env file first with debug information
         let current_dir = std::env::current_dir()
             .map(|p| p.display().to_string())
@@ -28,77 +1204,207 @@ impl ServerConfig {
             Err(e) => println!("Failed to load .env.server: {}", e),
         }
 
-        let api_key = std::env::var("CMC_API_KEY")
-            .unwrap_or_else(|_| {
+        // Read after .env.server, whose variables (like the real environment) override the file
+        let config_file = config_file_path();
+        match load_config_file(&config_file)? {
+            0 => println!("No settings read from {}", config_file.display()),
+            count => println!("Loaded {} settings from {}", count, config_file.display()),
+        }
+
+        let api_key = env_string("CMC_API_KEY")
+            .unwrap_or_else(|| {
                 warn!("CMC_API_KEY environment variable not set, using placeholder");
                 "YOUR_API_KEY_HERE".to_string()
             });
 
-        let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "INFO".to_string());
+        let log_level = env_string("LOG_LEVEL").unwrap_or_else(|| "INFO".to_string());
+        // RUST_LOG is still honoured as before when LOG_FILTER isn't set
+        let log_filter = env_string("LOG_FILTER").or_else(|| env_string("RUST_LOG")).unwrap_or_default();
         
-        let mqtt_broker_host = std::env::var("MQTT_BROKER_HOST").unwrap_or_else(|_| {
-            warn!("MQTT_BROKER_HOST not set in .env file, using localhost (0.0.0.0)");
-            "0.0.0.0".to_string()
+        let configured_broker_host = env_string("MQTT_BROKER_HOST");
+        
+        // Older configs only set MQTT_BROKER_HOST, which used to be the listen address as well
+        let mqtt_bind_address = env_string("MQTT_BIND_ADDRESS")
+            .or_else(|| configured_broker_host.clone())
+            .unwrap_or_else(|| "0.0.0.0".to_string());
+        
+        let mqtt_broker_host = configured_broker_host.unwrap_or_else(|| {
+            warn!("MQTT_BROKER_HOST not set in .env file, using localhost (127.0.0.1)");
+            "127.0.0.1".to_string()
         });
         
-        let mqtt_broker_port = std::env::var("MQTT_BROKER_PORT")
-            .and_then(|s| s.parse().map_err(|_| std::env::VarError::NotPresent))
-            .unwrap_or_else(|_| {
+        let mqtt_broker_port = env_string("MQTT_BROKER_PORT")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(|| {
                 warn!("MQTT_BROKER_PORT not set in .env file, using default (1883)");
                 1883
             });
 
-        let http_icon_port = std::env::var("HTTP_ICON_PORT")
-            .and_then(|s| s.parse().map_err(|_| std::env::VarError::NotPresent))
-            .unwrap_or_else(|_| {
+        let http_icon_port = env_string("HTTP_ICON_PORT")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(|| {
                 warn!("HTTP_ICON_PORT not set in .env file, using default (8080)");
                 8080
             });
 
-        let update_interval_seconds = std::env::var("UPDATE_INTERVAL_SECONDS")
-            .and_then(|s| s.parse().map_err(|_| std::env::VarError::NotPresent))
-            .unwrap_or_else(|_| {
-                warn!("UPDATE_INTERVAL_SECONDS not set in .env file, using default (900 seconds / 15 minutes)");
-                900
-            });
+        let http_bind_address = env_string("HTTP_BIND_ADDRESS").unwrap_or_else(|| "0.0.0.0".to_string());
+
+        let http_auth = HttpAuthConfig::from_env()?;
+
+        let historical_rate_limit = HistoricalRateLimitConfig::from_env();
+
+        let cors = CorsConfig::from_env();
+
+        let ReloadableConfig { update_interval_seconds, update_tiers, tracked } = ReloadableConfig::from_env()?;
+
+        let http_client = HttpClientConfig::from_env();
+
+        // Either --dry-run on the command line or DRY_RUN=true in the environment
+        let dry_run = std::env::args().any(|arg| arg == "--dry-run")
+            || env_or("DRY_RUN", false);
+
+        // Optional namespace so staging and production can share one broker
+        let topic_prefix = env_string("MQTT_TOPIC_PREFIX").unwrap_or_default();
+
+        let leader = LeaderConfig::from_env();
+
+        let mqtt_tls = MqttTlsConfig::from_env();
+
+        let topic_acl = env_or("MQTT_TOPIC_ACL", true);
+
+        let historical_request_concurrency = env_or("HISTORICAL_REQUEST_CONCURRENCY", 2usize).max(1);
+
+        let price_delta = PriceDeltaConfig::from_env();
+
+        // Two missed updates before clients treat prices as expired
+        let price_ttl_seconds = env_or("PRICE_TTL_SECONDS", update_interval_seconds * 2).max(1);
+
+        let msgpack_payloads = env_or("MQTT_MSGPACK_PAYLOADS", false);
+
+        let indicator_topics = env_or("MQTT_INDICATOR_TOPICS", false);
+
+        let client_rate_limit = ClientRateLimitConfig::from_env();
+
+        let price_refresh_min_interval_seconds = env_or("PRICE_REFRESH_MIN_INTERVAL_SECONDS", 60);
+
+        let watchlist_max_symbols = env_or("WATCHLIST_MAX_SYMBOLS", 100usize).max(1);
+
+        let retained_compaction_interval_seconds = env_or("RETAINED_COMPACTION_INTERVAL_SECONDS", 0);
+
+        let market_data = MarketDataConfig::from_env();
+
+        let binance_stream = BinanceStreamConfig::from_env();
+
+        let cache_persistence = CachePersistenceConfig::from_env();
+
+        let logo_cache = LogoCacheConfig::from_env();
+
+        // A typo here would silently strip fields from every client's feed, so it fails startup
+        let payload_fields = match env_string("PAYLOAD_FIELDS") {
+            Some(list) => QuoteFields::parse(&list)?,
+            None => QuoteFields::default(),
+        };
+
+        let storage = StorageConfig::from_env();
+
+        let backfill = BackfillConfig::from_env()?;
+
+        let anomalies = AnomalyConfig::from_env();
+
+        let cluster_cache = ClusterCacheConfig::from_env();
+
+        let daily_summary = DailySummaryConfig::from_env()?;
+
+        let alerts = AlertsConfig::from_env();
+
+        let fx = FxConfig::from_env();
+
+        let portfolio = PortfolioConfig::from_env();
+
+        let dead_letters = DeadLetterConfig::from_env();
+
+        let pinned = PinnedConfig::from_env();
 
         Ok(ServerConfig {
             api_key,
             log_level,
+            log_filter,
             mqtt_broker_host,
             mqtt_broker_port,
+            mqtt_bind_address,
             http_icon_port,
+            http_bind_address,
+            http_auth,
+            historical_rate_limit,
+            cors,
             update_interval_seconds,
+            config_file,
+            http_client,
+            dry_run,
+            topic_prefix,
+            leader,
+            mqtt_tls,
+            topic_acl,
+            historical_request_concurrency,
+            update_tiers,
+            price_delta,
+            price_ttl_seconds,
+            msgpack_payloads,
+            indicator_topics,
+            client_rate_limit,
+            price_refresh_min_interval_seconds,
+            watchlist_max_symbols,
+            retained_compaction_interval_seconds,
+            market_data,
+            binance_stream,
+            cache_persistence,
+            logo_cache,
+            payload_fields,
+            storage,
+            backfill,
+            anomalies,
+            cluster_cache,
+            daily_summary,
+            alerts,
+            fx,
+            portfolio,
+            dead_letters,
+            pinned,
+            tracked,
         })
     }
 
+    pub fn reloadable(&self) -> ReloadableConfig {
+        ReloadableConfig {
+            update_interval_seconds: self.update_interval_seconds,
+            update_tiers: self.update_tiers.clone(),
+            tracked: self.tracked.clone(),
+        }
+    }
+
+    // In delta mode the retained snapshot is only replaced every snapshot_every updates, with
+    // deltas keeping it current in between, so it has to stay valid for the whole cycle
+    pub fn snapshot_ttl_seconds(&self) -> u64 {
+        if self.price_delta.enabled {
+            let cycle = u64::from(self.price_delta.snapshot_every.saturating_sub(1)) * self.update_interval_seconds;
+            self.price_ttl_seconds + cycle
+        } else {
+            self.price_ttl_seconds
+        }
+    }
+
+    // rumqttd is silenced and the publisher only logs warnings unless LOG_FILTER says otherwise
+    pub fn log_directives(&self) -> [&str; 3] {
+        ["rumqttd=off", "coin_crab_server::mqtt::publisher=warn", &self.log_filter]
+    }
+
     pub fn setup_logging(&self) {
-        let mut builder = env_logger::Builder::from_default_env();
-        
-        // Set base log level from environment
-        let level_filter = match self.log_level.to_uppercase().as_str() {
-            "OFF" => log::LevelFilter::Off,
-            "ERROR" => log::LevelFilter::Error,
-            "WARN" => log::LevelFilter::Warn,
-            "INFO" => log::LevelFilter::Info,
-            "DEBUG" => log::LevelFilter::Debug,
-            "TRACE" => log::LevelFilter::Trace,
-            _ => log::LevelFilter::Info,
-        };
+        tracing_subscriber::registry()
+            .with(shared::env_filter(&self.log_level, &self.log_directives()))
+            .with(tracing_subscriber::fmt::layer())
+            .init();
         
-        builder.filter_level(level_filter);
-        
-        // Always suppress rumqttd logs regardless of main log level
-        builder.filter_module("rumqttd", log::LevelFilter::Off);
-        builder.filter_module("rumqttd::router", log::LevelFilter::Off);
-        builder.filter_module("rumqttd::router::routing", log::LevelFilter::Off);
-        
-        // Suppress MQTT publisher error messages
-        builder.filter_module("coin_crab_server::mqtt::publisher", log::LevelFilter::Warn);
-        
-        builder.init();
-        
-        info!("Logging initialized with level: {}", self.log_level);
+        info!(level = %self.log_level, filter = %self.log_filter, "Logging initialized");
     }
 }
 
@@ -111,45 +1417,228 @@ mod tests {
         let config = ServerConfig {
             api_key: "test_key".to_string(),
             log_level: "DEBUG".to_string(),
+            log_filter: "coin_crab_server::data=trace".to_string(),
             mqtt_broker_host: "localhost".to_string(),
             mqtt_broker_port: 1883,
+            mqtt_bind_address: "0.0.0.0".to_string(),
             http_icon_port: 8080,
+            http_bind_address: "127.0.0.1".to_string(),
+            http_auth: HttpAuthConfig::default(),
+            historical_rate_limit: HistoricalRateLimitConfig::default(),
+            cors: CorsConfig::default(),
             update_interval_seconds: 300,
+            config_file: PathBuf::from(DEFAULT_CONFIG_FILE),
+            http_client: HttpClientConfig::default(),
+            dry_run: false,
+            topic_prefix: "staging".to_string(),
+            leader: LeaderConfig::default(),
+            mqtt_tls: MqttTlsConfig::default(),
+            topic_acl: true,
+            historical_request_concurrency: 2,
+            update_tiers: UpdateTierConfig::default(),
+            price_delta: PriceDeltaConfig::default(),
+            price_ttl_seconds: 600,
+            msgpack_payloads: false,
+            indicator_topics: false,
+            client_rate_limit: ClientRateLimitConfig::default(),
+            price_refresh_min_interval_seconds: 60,
+            watchlist_max_symbols: 100,
+            retained_compaction_interval_seconds: 0,
+            market_data: MarketDataConfig::default(),
+            binance_stream: BinanceStreamConfig::default(),
+            cache_persistence: CachePersistenceConfig::default(),
+            logo_cache: LogoCacheConfig::default(),
+            payload_fields: QuoteFields::default(),
+            storage: StorageConfig::default(),
+            backfill: BackfillConfig::default(),
+            anomalies: AnomalyConfig::default(),
+            cluster_cache: ClusterCacheConfig::default(),
+            daily_summary: DailySummaryConfig::default(),
+            alerts: AlertsConfig::default(),
+            fx: FxConfig::default(),
+            portfolio: PortfolioConfig::default(),
+            dead_letters: DeadLetterConfig::default(),
+            pinned: PinnedConfig::default(),
+            tracked: TrackedSeriesConfig::default(),
         };
 
         assert_eq!(config.api_key, "test_key");
         assert_eq!(config.log_level, "DEBUG");
+        let filter = shared::env_filter(&config.log_level, &config.log_directives()).to_string();
+        assert!(filter.contains("rumqttd=off") && filter.contains("coin_crab_server::data=trace"), "{}", filter);
         assert_eq!(config.mqtt_broker_host, "localhost");
         assert_eq!(config.mqtt_broker_port, 1883);
         assert_eq!(config.http_icon_port, 8080);
+        assert_eq!(config.http_bind_address, "127.0.0.1");
+        assert_eq!(config.mqtt_bind_address, "0.0.0.0");
         assert_eq!(config.update_interval_seconds, 300);
+        assert_eq!(config.http_client.max_retries, 2);
+        assert!(!config.dry_run);
+        assert_eq!(config.topic_prefix, "staging");
+        assert!(config.leader.lease_file.is_none());
+        assert!(!config.mqtt_tls.enabled);
+        assert_eq!(config.mqtt_tls.port, 8883);
+        assert_eq!(config.historical_request_concurrency, 2);
+        assert!(config.update_tiers.is_enabled());
+        assert_eq!(config.update_tiers.hot_interval_seconds, 60);
+        assert!(!config.price_delta.enabled);
+        assert!(config.client_rate_limit.is_enabled());
+        assert_eq!(config.price_refresh_min_interval_seconds, 60);
+        assert!(config.market_data.coingecko_fallback);
+        assert!(!config.binance_stream.enabled);
+        assert!(config.cache_persistence.path.is_none());
+        assert!(config.payload_fields.is_all());
+        assert!(config.storage.sqlite_path.is_none());
+        assert!(config.storage.database_url.is_none());
+        assert!(config.backfill.is_enabled());
+        assert!(config.backfill.symbols.is_empty());
+        assert!(config.anomalies.enabled && config.anomalies.suppress);
+        assert_eq!(config.anomalies.max_jump_percent, 50.0);
+        assert!(config.cluster_cache.redis_url.is_none());
+        assert_eq!(config.cluster_cache.key_prefix, "coin-crab");
+        assert!(config.daily_summary.time_utc.is_none());
+        assert_eq!(config.snapshot_ttl_seconds(), 600);
+
+        let delta_mode = ServerConfig {
+            price_delta: PriceDeltaConfig { enabled: true, snapshot_every: 10, ..PriceDeltaConfig::default() },
+            ..config
+        };
+        assert_eq!(delta_mode.snapshot_ttl_seconds(), 600 + 9 * 300);
+    }
+
+    #[test]
+    fn test_update_tier_symbols() {
+        assert_eq!(parse_symbol_list(" btc, ,Eth "), vec!["BTC", "ETH"]);
+        let disabled = UpdateTierConfig { hot_tier_size: 0, ..UpdateTierConfig::default() };
+        assert!(!disabled.is_enabled());
+        let listed = UpdateTierConfig { hot_symbols: vec!["SOL".to_string()], ..disabled };
+        assert!(listed.is_enabled());
+    }
+
+    #[test]
+    fn test_env_or_parsing() {
+        std::env::set_var("TEST_ENV_OR_VALID", " 42 ");
+        std::env::set_var("TEST_ENV_OR_INVALID", "not-a-number");
+
+        assert_eq!(env_or("TEST_ENV_OR_VALID", 7u64), 42);
+        assert_eq!(env_or("TEST_ENV_OR_INVALID", 7u64), 7);
+        assert_eq!(env_or("TEST_ENV_OR_MISSING", 7u64), 7);
+
+        std::env::remove_var("TEST_ENV_OR_VALID");
+        std::env::remove_var("TEST_ENV_OR_INVALID");
+    }
+
+    #[test]
+    fn test_parse_no_proxy_list() {
+        assert_eq!(
+            parse_no_proxy_list(" localhost, .Internal.Example.com ,,10.0.0.1"),
+            vec!["localhost", ".internal.example.com", "10.0.0.1"]
+        );
+        assert!(parse_no_proxy_list("").is_empty());
+        assert!(!ProxyConfig::default().is_configured());
+    }
+
+    #[test]
+    fn test_parse_header_list() {
+        assert_eq!(
+            parse_header_list("X-Team = markets, X-Env=prod,missing-value,=orphan"),
+            vec![
+                ("X-Team".to_string(), "markets".to_string()),
+                ("X-Env".to_string(), "prod".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_utc_time() {
+        assert_eq!(parse_utc_time("08:30").unwrap(), (8, 30));
+        assert_eq!(parse_utc_time(" 23:59 ").unwrap(), (23, 59));
+        for invalid in ["24:00", "12:60", "8", "aa:bb"] {
+            assert!(parse_utc_time(invalid).is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn test_tracked_series() {
+        let mut tracked = TrackedSeriesConfig::default();
+        assert!(tracked.retain_mapped(&IdentityMap::new()).is_empty());
+
+        let mut identities = IdentityMap::new();
+        identities.record_cmc(1, "BTC", "Bitcoin", "bitcoin");
+        identities.record_cmc(1027, "ETH", "Ethereum", "ethereum");
+        let unmapped = tracked.retain_mapped(&identities);
+        assert_eq!(tracked.refresh_symbols, vec!["BTC", "ETH"]);
+        assert_eq!(tracked.priority_symbols, vec!["BTC", "ETH"]);
+        assert_eq!(unmapped.len(), 8);
+
+        std::env::set_var("TEST_TRACKED_TIMEFRAMES", " 24H,7d ");
+        assert_eq!(timeframes_from_env("TEST_TRACKED_TIMEFRAMES", Vec::new()).unwrap(), vec!["24h", "7d"]);
+        std::env::set_var("TEST_TRACKED_TIMEFRAMES", "24h,2w");
+        assert!(timeframes_from_env("TEST_TRACKED_TIMEFRAMES", Vec::new()).is_err());
+        std::env::remove_var("TEST_TRACKED_TIMEFRAMES");
+    }
+
+    #[test]
+    fn test_parse_api_keys() {
+        let keys = parse_api_keys("ios:abc123, dashboard:def456:600,").unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], HttpApiKey { name: "ios".to_string(), key: "abc123".to_string(), requests_per_minute: None });
+        assert_eq!(keys[1].requests_per_minute, Some(600));
+
+        assert!(parse_api_keys("abc123").is_err());
+        assert!(parse_api_keys("ios:").is_err());
+        assert!(parse_api_keys("ios:abc:fast").is_err());
+        assert!(!HttpAuthConfig::default().is_enabled());
+    }
+
+    #[test]
+    fn test_config_file_settings() {
+        let settings = parse_config_file(r#"
+            update_interval_seconds = 600
+            [mqtt]
+            broker_port = 1884
+            [tracked]
+            symbols = ["btc", "eth"]
+            [dry]
+            run = true
+        "#).unwrap();
+        assert_eq!(settings["UPDATE_INTERVAL_SECONDS"], "600");
+        assert_eq!(settings["MQTT_BROKER_PORT"], "1884");
+        assert_eq!(settings["TRACKED_SYMBOLS"], "btc,eth");
+        assert_eq!(settings["DRY_RUN"], "true");
+        assert!(parse_config_file("[mqtt").is_err());
+
+        // The environment wins over the file, which wins over the default
+        let path = std::env::temp_dir().join(format!("coin-crab-test-{}.toml", std::process::id()));
+        std::fs::write(&path, "[test_file]\nsetting = 7\noverridden = 8\n").unwrap();
+        assert_eq!(load_config_file(&path).unwrap(), 2);
+        std::env::set_var("TEST_FILE_OVERRIDDEN", "9");
+        assert_eq!(env_or("TEST_FILE_SETTING", 1), 7);
+        assert_eq!(env_or("TEST_FILE_OVERRIDDEN", 1), 9);
+        std::env::remove_var("TEST_FILE_OVERRIDDEN");
+
+        // A file that goes away no longer contributes settings
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(load_config_file(&path).unwrap(), 0);
+        assert_eq!(env_or("TEST_FILE_SETTING", 1), 1);
     }
 
     #[test]
     fn test_log_level_mapping() {
+        use tracing_subscriber::filter::LevelFilter;
         // Test that different log levels map to the correct filter level
         let test_cases = vec![
-            ("OFF", log::LevelFilter::Off),
-            ("ERROR", log::LevelFilter::Error),
-            ("WARN", log::LevelFilter::Warn),
-            ("INFO", log::LevelFilter::Info),
-            ("DEBUG", log::LevelFilter::Debug),
-            ("TRACE", log::LevelFilter::Trace),
-            ("invalid", log::LevelFilter::Info), // Default case
+            ("OFF", LevelFilter::OFF),
+            ("ERROR", LevelFilter::ERROR),
+            ("WARN", LevelFilter::WARN),
+            ("INFO", LevelFilter::INFO),
+            ("DEBUG", LevelFilter::DEBUG),
+            ("TRACE", LevelFilter::TRACE),
+            ("invalid", LevelFilter::INFO), // Default case
         ];
 
         for (input, expected) in test_cases {
-            let level_filter = match input.to_uppercase().as_str() {
-                "OFF" => log::LevelFilter::Off,
-                "ERROR" => log::LevelFilter::Error,
-                "WARN" => log::LevelFilter::Warn,
-                "INFO" => log::LevelFilter::Info,
-                "DEBUG" => log::LevelFilter::Debug,
-                "TRACE" => log::LevelFilter::Trace,
-                _ => log::LevelFilter::Info,
-            };
-            
-            assert_eq!(level_filter, expected);
+            assert_eq!(shared::level_filter(input), expected);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,76 @@
+use actix_cors::Cors;
+use actix_web::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RETRY_AFTER};
+use actix_web::http::Method;
+use tracing::warn;
+use crate::config::CorsConfig;
+
+// Cross-origin policy for browser dashboards. Preflight requests are answered here, before
+// authentication, since browsers send them without the Authorization header.
+pub fn build_cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_headers([ACCEPT, AUTHORIZATION, CONTENT_TYPE, IF_NONE_MATCH])
+        .expose_headers([ETAG, RETRY_AFTER])
+        .max_age(config.max_age_seconds);
+
+    let methods: Vec<Method> = config
+        .allowed_methods
+        .iter()
+        .filter_map(|method| match method.parse() {
+            Ok(method) => Some(method),
+            Err(_) => {
+                warn!("Ignoring invalid CORS method {}", method);
+                None
+            }
+        })
+        .collect();
+    cors = cors.allowed_methods(methods);
+
+    if config.allowed_origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        config.allowed_origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+    use actix_web::dev::Service;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App};
+
+    #[actix_web::test]
+    async fn test_configured_origins_only() {
+        let config = CorsConfig { allowed_origins: vec!["https://dashboard.example.com".to_string()], ..CorsConfig::default() };
+        let app = init_service(
+            App::new()
+                .wrap(build_cors(&config))
+                .route("/api/crypto-prices", web::get().to(|| async { "prices" })),
+        ).await;
+
+        let request = TestRequest::get()
+            .uri("/api/crypto-prices")
+            .insert_header((ORIGIN, "https://dashboard.example.com"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://dashboard.example.com");
+
+        let preflight = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/crypto-prices")
+            .insert_header((ORIGIN, "https://dashboard.example.com"))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        assert!(call_service(&app, preflight).await.status().is_success());
+
+        let request = TestRequest::get()
+            .uri("/api/crypto-prices")
+            .insert_header((ORIGIN, "https://evil.example.com"))
+            .to_request();
+        // Rejected outright, or at least without the header the browser needs
+        if let Ok(response) = app.call(request).await {
+            assert!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        }
+    }
+}
@@ -1,153 +1,523 @@
 use actix_web::web;
-use reqwest::Client;
-use std::time::{Duration, SystemTime};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
 use tokio::time;
-use log::{info, warn, error};
-use crate::types::{AppState, CoinMarketCapResponse, CmcMappingResponse};
-use crate::mqtt::{publish_crypto_data_to_mqtt, publish_empty_retained_message};
-#[cfg(test)]
-use crate::mqtt::publish_historical_data_to_mqtt;
-use shared::{HistoricalDataPoint, HistoricalDataResult};
-
-async fn fetch_crypto_data(state: &web::Data<AppState>) {
-    info!("Fetching data from CoinMarketCap API");
-    info!("Using API key: {}...", &state.api_key[..8.min(state.api_key.len())]);
-
-    let response = state.client
-        .get("https://pro-api.coinmarketcap.com/v1/cryptocurrency/listings/latest")
-        .query(&[("limit", "100"), ("convert", "USD")])
-        .header("X-CMC_PRO_API_KEY", &state.api_key)
-        .header("Accept", "application/json")
-        .send()
-        .await;
-
-    match response {
-        Ok(resp) => {
-            let status = resp.status();
-            if status.is_success() {
-                match resp.json::<CoinMarketCapResponse>().await {
-                    Ok(cmc_data) => {
-                        info!("Successfully fetched {} cryptocurrencies", cmc_data.data.len());
-
-                        // Clone data for MQTT publishing before moving to cache
-                        let crypto_data_for_mqtt = cmc_data.data.clone();
-
-                        // Update cache (scoped to release locks before await)
-                        {
-                            let mut cache = state.cache.lock().unwrap();
-                            *cache = Some(cmc_data.data);
-
-                            let mut last_fetch = state.last_fetch.lock().unwrap();
-                            *last_fetch = SystemTime::now();
-                        }
-
-                        // Publish real market data to MQTT
-                        info!("Publishing MQTT update with all {} cryptocurrencies", crypto_data_for_mqtt.len());
-                        let _ = tokio::time::timeout(
-                            Duration::from_millis(100),
-                            publish_crypto_data_to_mqtt(&state.mqtt_client, &crypto_data_for_mqtt)
-                        ).await;
-                    }
-                    Err(e) => {
-                        error!("Failed to parse CoinMarketCap response: {}", e);
-                    }
-                }
-            } else {
-                error!("CoinMarketCap API returned status: {}", status);
-                if let Ok(error_text) = resp.text().await {
-                    error!("Error response: {}", error_text);
-                }
-                if status.as_u16() == 429 {
-                    warn!("Rate limit reached, using cached data");
-                } else if status.as_u16() == 401 {
-                    error!("API key authentication failed - check your CMC_API_KEY");
-                }
+use tracing::{info, instrument, warn, error, debug, Span};
+use crate::alerts::TriggeredAlert;
+use crate::config::{ReloadableConfig, TrackedSeriesConfig, UpdateTierConfig};
+use crate::watchlists::bundle;
+use crate::portfolio::{value_portfolio, PortfolioValuation};
+use crate::pinned::{quote_requests, PinnedSymbols};
+use crate::movers::trending_movers;
+use crate::types::{AppState, CryptoCurrency, PriceRanges};
+use crate::identity::IdentityMap;
+use crate::gaps::{find_gaps, find_gaps_every};
+use crate::storage::{is_fresh, StoredHistory};
+use crate::providers::{timeframe_days, HistoricalRange, MarketDataProvider};
+use crate::mqtt::{plan_price_publish, publish_alert_to_mqtt, publish_portfolio_valuation_to_mqtt, publish_watchlist_bundle_to_mqtt, publish_crypto_data_to_mqtt, publish_empty_retained_message, publish_historical_data_to_mqtt, publish_prefetch_hints_to_mqtt, publish_price_anomalies_to_mqtt, publish_price_delta_to_mqtt, publish_symbol_prices_to_mqtt, publish_trending_to_mqtt, PricePublish};
+use crate::mqtt::retained::retained_topics;
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataResult, PayloadCodec, PrefetchHint};
+
+// Only series cached within this window are advertised as prefetch hints
+const PREFETCH_HINT_MAX_AGE_SECS: u64 = 3600;
+const MAX_PREFETCH_HINTS: usize = 20;
+// One publish per coin, so per-symbol topics get longer than the single latest-prices publish
+const SYMBOL_PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Timeframes the historical topics are published and refreshed for
+pub const HISTORICAL_TIMEFRAMES: [&str; 6] = ["1h", "24h", "7d", "30d", "90d", "365d"];
+// Spacing between pinned history loads, which may each be a provider call
+const PREWARM_DELAY: Duration = Duration::from_millis(500);
+
+#[instrument(name = "market_fetch", skip_all, fields(provider = state.market_data.name(), coins))]
+pub async fn fetch_crypto_data(state: &web::Data<AppState>) {
+    match state.market_data.latest_listings(100).await {
+        Ok(mut coins) => {
+            Span::current().record("coins", coins.len());
+            info!("Successfully fetched {} cryptocurrencies from {}", coins.len(), state.market_data.name());
+            add_pinned_coins(state, &mut coins).await;
+
+            // Check the ticks against the last listings before anything is cached or published
+            let (screened, anomalies) = {
+                let cache = state.cache.lock().unwrap();
+                state.price_screen.screen(cache.as_deref().unwrap_or(&[]), coins, chrono::Utc::now())
+            };
+            coins = screened;
+            if !anomalies.is_empty() {
+                publish_price_anomalies_to_mqtt(&state.mqtt_client, &anomalies).await;
             }
+
+            // Clone data for MQTT publishing before moving to cache
+            let crypto_data_for_mqtt = coins.clone();
+
+            // Share the fetch with the other instances before taking the cache lock
+            let fetched_at = SystemTime::now();
+            state.cluster_cache.store_listings(&coins, fetched_at, Duration::from_secs(state.update_interval_seconds())).await;
+
+            // Update cache (scoped to release locks before await)
+            {
+                let mut cache = state.cache.lock().unwrap();
+                *cache = Some(coins);
+
+                let mut last_fetch = state.last_fetch.lock().unwrap();
+                *last_fetch = fetched_at;
+            }
+            state.persistence.mark_dirty();
+
+            publish_listings(state, crypto_data_for_mqtt).await;
+        }
+        Err(CoinCrabError::RateLimited(e)) => {
+            warn!("Rate limit reached, using cached data: {}", e);
+        }
+        Err(CoinCrabError::Config(e)) => {
+            error!("API key authentication failed - check your CMC_API_KEY: {}", e);
         }
         Err(e) => {
-            error!("Failed to fetch data from CoinMarketCap: {}", e);
+            error!("Failed to fetch market data: {}", e);
+        }
+    }
+}
+
+// Pinned coins outside the top 100 are quoted separately and appended after the ranked coins.
+// A failed quote only costs those coins this round.
+async fn add_pinned_coins(state: &web::Data<AppState>, listings: &mut Vec<CryptoCurrency>) {
+    let missing = state.pinned.missing_from(listings);
+    if missing.is_empty() {
+        return;
+    }
+    let requests = quote_requests(&missing, &state.identity_map.lock().unwrap());
+    if requests.len() < missing.len() {
+        warn!("No coin mapping for some pinned symbols: {}", missing.join(","));
+    }
+    if requests.is_empty() {
+        return;
+    }
+    match state.market_data.latest_quotes(&requests).await {
+        Ok(quotes) => {
+            debug!("Quoted {} pinned coins outside the listings", quotes.len());
+            listings.extend(quotes);
+        }
+        Err(e) => warn!("Failed to quote pinned coins {}: {}", missing.join(","), e),
+    }
+}
+
+// Coins for the per-symbol topics: every pinned coin, changed or not, ahead of the other
+// changed coins so a publish timeout never cuts them off
+fn per_symbol_coins(listings: &[CryptoCurrency], changed: Vec<CryptoCurrency>, pinned: &PinnedSymbols) -> Vec<CryptoCurrency> {
+    let mut coins: Vec<CryptoCurrency> = listings.iter().filter(|coin| pinned.is_pinned(&coin.symbol)).cloned().collect();
+    coins.extend(changed.into_iter().filter(|coin| !pinned.is_pinned(&coin.symbol)));
+    coins
+}
+
+// Publish the full listings through the price pipeline: a snapshot or delta on
+// crypto/prices/latest (or .../delta), then the per-symbol topics and watchlist bundles of the
+// coins that changed
+pub async fn publish_listings(state: &web::Data<AppState>, mut listings: Vec<CryptoCurrency>) {
+    state.fx.apply(&mut listings);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let alerts = state.alerts.evaluate(&listings, now);
+    let changed = match plan_price_publish(&listings) {
+        PricePublish::Snapshot => {
+            debug!("Publishing MQTT update with all {} cryptocurrencies", listings.len());
+            let fetched_at = *state.last_fetch.lock().unwrap();
+            let _ = tokio::time::timeout(
+                Duration::from_millis(100),
+                publish_crypto_data_to_mqtt(&state.mqtt_client, &listings, fetched_at, state.market_data.listings_source(), state.fx.metadata())
+            ).await;
+            listings.clone()
+        }
+        PricePublish::Delta(delta) => {
+            let _ = tokio::time::timeout(
+                Duration::from_millis(100),
+                publish_price_delta_to_mqtt(&state.mqtt_client, &delta)
+            ).await;
+            delta.coins
+        }
+        PricePublish::Unchanged => {
+            debug!("No price moved beyond the delta threshold - skipping MQTT update");
+            Vec::new()
+        }
+    };
+    let per_symbol = per_symbol_coins(&listings, changed.clone(), &state.pinned);
+    if !per_symbol.is_empty() {
+        let _ = tokio::time::timeout(
+            SYMBOL_PUBLISH_TIMEOUT,
+            publish_symbol_prices_to_mqtt(&state.mqtt_client, &per_symbol)
+        ).await;
+    }
+    if !changed.is_empty() {
+        let fetched_at = state.last_fetch.lock().unwrap().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        let (gainers, losers) = trending_movers(&listings, fetched_at);
+        publish_trending_to_mqtt(&state.mqtt_client, &gainers, &losers).await;
+    }
+    publish_watchlist_bundles(state, &listings, &changed).await;
+    publish_portfolio_valuations(state, &listings, &changed, now).await;
+    deliver_alerts(state, alerts).await;
+}
+
+// Republish the bundle of every watchlist holding a coin that changed
+async fn publish_watchlist_bundles(state: &web::Data<AppState>, listings: &[CryptoCurrency], changed: &[CryptoCurrency]) {
+    let affected = state.watchlists.affected_by(changed);
+    if affected.is_empty() {
+        return;
+    }
+    let fetched_at = *state.last_fetch.lock().unwrap();
+    for (device_id, symbols) in &affected {
+        let coins = bundle(symbols, listings);
+        publish_watchlist_bundle_to_mqtt(&state.mqtt_client, device_id, &coins, fetched_at, state.market_data.listings_source(), state.fx.metadata()).await;
+    }
+    debug!("Published {} watchlist bundles", affected.len());
+}
+
+// Revalue every portfolio holding a coin that changed
+async fn publish_portfolio_valuations(state: &web::Data<AppState>, listings: &[CryptoCurrency], changed: &[CryptoCurrency], now: i64) {
+    for (portfolio_id, holdings) in state.portfolios.affected_by(changed) {
+        let valuation = value_portfolio(&portfolio_id, &holdings, listings, now);
+        publish_portfolio_valuation_to_mqtt(&state.mqtt_client, &valuation).await;
+    }
+}
+
+// Current valuation of a portfolio from the cached listings, republished after its holdings
+// change; a removed portfolio's retained valuation is cleared. None if it holds nothing.
+pub async fn revalue_portfolio(state: &web::Data<AppState>, portfolio_id: &str) -> Option<PortfolioValuation> {
+    let publish = state.leader.is_leader();
+    let Some(holdings) = state.portfolios.holdings(portfolio_id) else {
+        if publish {
+            publish_empty_retained_message(&state.mqtt_client, &format!("crypto/portfolios/{}/valuation", portfolio_id)).await;
+        }
+        return None;
+    };
+    let listings = state.cache.lock().unwrap().clone().unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let valuation = value_portfolio(portfolio_id, &holdings, &listings, now);
+    if publish {
+        publish_portfolio_valuation_to_mqtt(&state.mqtt_client, &valuation).await;
+    }
+    Some(valuation)
+}
+
+// A newly registered watchlist gets its bundle right away from the cached listings
+pub async fn publish_watchlist(state: &web::Data<AppState>, device_id: &str, symbols: &[String]) {
+    let Some(listings) = state.cache.lock().unwrap().clone() else {
+        return;
+    };
+    let fetched_at = *state.last_fetch.lock().unwrap();
+    let mut coins = bundle(symbols, &listings);
+    state.fx.apply(&mut coins);
+    publish_watchlist_bundle_to_mqtt(&state.mqtt_client, device_id, &coins, fetched_at, state.market_data.listings_source(), state.fx.metadata()).await;
+}
+
+// Fired rules are removed, so the rule file is rewritten whenever any fire
+async fn deliver_alerts(state: &web::Data<AppState>, alerts: Vec<TriggeredAlert>) {
+    if alerts.is_empty() {
+        return;
+    }
+    for alert in &alerts {
+        publish_alert_to_mqtt(&state.mqtt_client, alert).await;
+    }
+    if let Err(e) = state.alerts.persist().await {
+        error!("Failed to save alert rules: {}", e);
+    }
+}
+
+// Take listings another instance put in the shared cache if they are newer than ours and,
+// when max_age is given, younger than it. Returns the adopted listings.
+async fn adopt_shared_listings(state: &web::Data<AppState>, max_age: Option<Duration>) -> Option<Vec<CryptoCurrency>> {
+    let shared = state.cluster_cache.listings().await?;
+    let fetched_at = shared.fetched_time();
+    if !is_newer_shared_fetch(fetched_at, *state.last_fetch.lock().unwrap(), max_age, SystemTime::now()) {
+        return None;
+    }
+    *state.cache.lock().unwrap() = Some(shared.listings.clone());
+    *state.last_fetch.lock().unwrap() = fetched_at;
+    state.persistence.mark_dirty();
+    Some(shared.listings)
+}
+
+fn is_newer_shared_fetch(shared: SystemTime, local: SystemTime, max_age: Option<Duration>, now: SystemTime) -> bool {
+    let young_enough = max_age.is_none_or(|max_age| now.duration_since(shared).unwrap_or_default() < max_age);
+    shared > local && young_enough
+}
+
+// Leader: fetch the listings, unless another instance fetched them within the update interval
+// (e.g. the previous leader just before a failover). Standby: take the leader's listings.
+async fn refresh_listings(state: &web::Data<AppState>) {
+    if state.leader.is_leader() {
+        let interval = Duration::from_secs(state.update_interval_seconds());
+        match adopt_shared_listings(state, Some(interval)).await {
+            Some(listings) => {
+                info!("Using {} cryptocurrencies fetched by another instance", listings.len());
+                publish_listings(state, listings).await;
+            }
+            None => fetch_crypto_data(state).await,
+        }
+    } else if let Some(listings) = adopt_shared_listings(state, None).await {
+        debug!("Standby instance - took {} cryptocurrencies from the shared cache", listings.len());
+    } else {
+        debug!("Standby instance - skipping scheduled CMC fetch");
+    }
+}
+
+// Ticks once per listings interval, and starts over when a config reload changes the interval
+struct ListingsInterval {
+    settings: watch::Receiver<ReloadableConfig>,
+    seconds: u64,
+    interval: time::Interval,
+}
+
+impl ListingsInterval {
+    fn new(state: &AppState) -> Self {
+        let mut settings = state.settings.clone();
+        let seconds = settings.borrow_and_update().update_interval_seconds;
+        ListingsInterval { settings, seconds, interval: time::interval(Duration::from_secs(seconds)) }
+    }
+
+    async fn tick(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.interval.tick() => return,
+                Ok(()) = self.settings.changed() => {
+                    let seconds = self.settings.borrow_and_update().update_interval_seconds;
+                    if seconds != self.seconds {
+                        info!("Listings interval changed from {}s to {}s", self.seconds, seconds);
+                        self.seconds = seconds;
+                        let period = Duration::from_secs(seconds);
+                        self.interval = time::interval_at(time::Instant::now() + period, period);
+                    }
+                }
+            }
         }
     }
 }
 
 pub async fn fetch_data_periodically(state: web::Data<AppState>) {
+    let mut interval = ListingsInterval::new(&state);
     info!("Starting data fetch with interval: {} seconds ({} minutes)",
-          state.update_interval_seconds,
-          state.update_interval_seconds / 60);
+          interval.seconds,
+          interval.seconds / 60);
 
     // Fetch data immediately on startup before starting the interval timer
     info!("Fetching initial data on startup...");
-    fetch_crypto_data(&state).await;
-
-    let mut interval = time::interval(Duration::from_secs(state.update_interval_seconds));
+    refresh_listings(&state).await;
 
     // Consume the first tick that fires immediately to avoid duplicate fetch
     interval.tick().await;
 
     loop {
         interval.tick().await;
-        fetch_crypto_data(&state).await;
+        // Standby instances skip polling so CMC credits are only spent by the leader
+        refresh_listings(&state).await;
     }
 }
 
-pub async fn clear_mqtt_cache_periodically(state: web::Data<AppState>) {
-    info!("Starting periodic MQTT cache clearing task");
-    
-    // Wait 5 minutes before starting periodic cache clearing
-    tokio::time::sleep(Duration::from_secs(300)).await;
+// Refresh the hot tier between listings fetches. Only per-symbol topics are published at this
+// cadence; crypto/prices/latest keeps the listings interval. Starts over with the new tier
+// whenever a config reload changes it or the listings interval.
+pub async fn fetch_hot_tier_periodically(state: web::Data<AppState>) {
+    let mut settings = state.settings.clone();
+    loop {
+        let (tiers, listings_interval) = {
+            let current = settings.borrow_and_update();
+            (current.update_tiers.clone(), current.update_interval_seconds)
+        };
+        if !tiers.is_enabled() || tiers.hot_interval_seconds >= listings_interval {
+            info!("Hot update tier disabled - all coins follow the {}s listings interval", listings_interval);
+            if settings.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+        info!("Starting hot tier refresh every {} seconds ({})", tiers.hot_interval_seconds,
+              if tiers.hot_symbols.is_empty() { format!("top {} coins", tiers.hot_tier_size) } else { tiers.hot_symbols.join(",") });
+        
+        let mut interval = time::interval(Duration::from_secs(tiers.hot_interval_seconds));
+        
+        // The startup listings fetch (or the last round of the previous tier) covers the first round
+        interval.tick().await;
+        
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if state.leader.is_leader() {
+                        fetch_hot_tier(&state, &tiers).await;
+                    }
+                }
+                changed = settings.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    let current = settings.borrow();
+                    if current.update_tiers != tiers || current.update_interval_seconds != listings_interval {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn fetch_hot_tier(state: &web::Data<AppState>, tiers: &UpdateTierConfig) {
+    let coins: Vec<CryptoCurrency> = match state.cache.lock().unwrap().as_deref() {
+        Some(listings) => {
+            let ids = hot_tier_ids(listings, tiers);
+            listings.iter().filter(|coin| ids.contains(&coin.id)).cloned().collect()
+        }
+        None => Vec::new(),
+    };
+    if coins.is_empty() {
+        debug!("No listings cached yet - skipping hot tier refresh");
+        return;
+    }
+    let quotes = match state.market_data.latest_quotes(&coins).await {
+        Ok(quotes) => quotes,
+        Err(e) => {
+            error!("Failed to fetch hot tier quotes: {}", e);
+            return;
+        }
+    };
     
-    let timeframes = ["1h", "24h", "7d", "30d", "90d", "365d"];
-    let symbols = ["BTC", "ETH", "ADA", "SOL", "DOT", "MATIC", "LINK", "XRP", "LTC", "BCH"];
+    let updated = match state.cache.lock().unwrap().as_mut() {
+        Some(listings) => merge_quotes(listings, quotes),
+        None => return,
+    };
+    state.persistence.mark_dirty();
+    info!("Refreshed {} hot tier quotes", updated.len());
+    let _ = tokio::time::timeout(
+        SYMBOL_PUBLISH_TIMEOUT,
+        publish_symbol_prices_to_mqtt(&state.mqtt_client, &updated)
+    ).await;
+}
+
+// Hot-tier coin ids: the configured symbols, or the top-ranked coins of the last listings fetch
+// (listings are returned in market-cap rank order)
+fn hot_tier_ids(listings: &[CryptoCurrency], tiers: &UpdateTierConfig) -> Vec<i32> {
+    if tiers.hot_symbols.is_empty() {
+        listings.iter().take(tiers.hot_tier_size).map(|coin| coin.id).collect()
+    } else {
+        listings
+            .iter()
+            .filter(|coin| tiers.hot_symbols.contains(&coin.symbol.to_uppercase()))
+            .map(|coin| coin.id)
+            .collect()
+    }
+}
+
+// Replace cached coins with fresher quotes, keeping listing order; returns the updated coins
+fn merge_quotes(listings: &mut [CryptoCurrency], quotes: Vec<CryptoCurrency>) -> Vec<CryptoCurrency> {
+    let mut quotes: HashMap<i32, CryptoCurrency> = quotes.into_iter().map(|quote| (quote.id, quote)).collect();
+    let mut updated = Vec::new();
+    for coin in listings.iter_mut() {
+        if let Some(quote) = quotes.remove(&coin.id) {
+            *coin = quote;
+            updated.push(coin.clone());
+        }
+    }
+    updated
+}
+
+// Load and publish every timeframe of the given coins, so their charts open from the cache
+// and the retained historical topics. Series still fresh in the cache cost no provider call.
+pub async fn prewarm_history(state: &web::Data<AppState>, symbols: &[String]) {
+    for symbol in symbols {
+        for &timeframe in &HISTORICAL_TIMEFRAMES {
+            let result = load_historical_data(state, symbol, timeframe).await;
+            if !result.success {
+                warn!("Failed to pre-warm {} {}: {:?}", symbol, timeframe, result.error);
+            } else if tokio::time::timeout(
+                Duration::from_millis(1000),
+                publish_historical_data_to_mqtt(&state.mqtt_client, symbol, timeframe, &result)
+            ).await.is_err() {
+                warn!("MQTT publish timeout for pinned {} {}", symbol, timeframe);
+            }
+            tokio::time::sleep(PREWARM_DELAY).await;
+        }
+    }
+}
+
+// Keep the pinned coins' history warm on the leader, once per listings interval
+pub async fn prewarm_pinned_history_periodically(state: web::Data<AppState>) {
+    let mut interval = ListingsInterval::new(&state);
+    loop {
+        interval.tick().await;
+        let symbols = state.pinned.symbols();
+        if symbols.is_empty() || !state.leader.is_leader() {
+            continue;
+        }
+        debug!("Pre-warming history of {} pinned coins", symbols.len());
+        prewarm_history(&state, &symbols).await;
+    }
+}
+
+// How long a retained series of a timeframe stays on the broker after it was published;
+// clients subscribing after that get nothing and request a fresh copy
+fn cache_clear_interval_secs(timeframe: &str) -> u64 {
+    match timeframe {
+        "1h" => 300,    // 5 minutes
+        "24h" => 3600,  // 1 hour  
+        "7d" => 7200,   // 2 hours
+        "30d" => 21600, // 6 hours
+        "90d" => 86400, // 1 day
+        "365d" => 86400, // 1 day
+        _ => 3600,
+    }
+}
+
+// Time to live of a retained topic that is a tracked series (in any encoding); None for
+// every other topic, which never expires
+fn retained_series_ttl(topic: &str, tracked: &TrackedSeriesConfig) -> Option<Duration> {
+    let (topic, _) = PayloadCodec::from_topic(topic);
+    let (symbol, timeframe) = topic.strip_prefix("crypto/historical/")?.split_once('/')?;
+    let is_tracked = tracked.refresh_symbols.iter().any(|tracked| tracked.eq_ignore_ascii_case(symbol))
+        && tracked.refresh_timeframes.iter().any(|tracked| tracked == timeframe);
+    is_tracked.then(|| Duration::from_secs(cache_clear_interval_secs(timeframe)))
+}
+
+// How often retained series are checked for expiry
+const RETAINED_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Clears each retained tracked series once it is older than its timeframe's time to live.
+// Only series this server actually published are cleared, each on its own clock. The tracked
+// series are read on every check, so a config reload applies from the next one.
+pub async fn expire_retained_history_periodically(state: web::Data<AppState>) {
+    info!("Expiring retained historical series of {} tracked symbols", state.settings.borrow().tracked.refresh_symbols.len());
     
+    let mut interval = tokio::time::interval(RETAINED_EXPIRY_CHECK_INTERVAL);
     loop {
-        for &timeframe in &timeframes {
-            // Get the update interval for this timeframe
-            let interval_secs = match timeframe {
-                "1h" => 300,    // 5 minutes
-                "24h" => 3600,  // 1 hour  
-                "7d" => 7200,   // 2 hours
-                "30d" => 21600, // 6 hours
-                "90d" => 86400, // 1 day
-                "365d" => 86400, // 1 day
-                _ => 3600,
-            };
-            
-            info!("Clearing MQTT cache for timeframe {} (interval: {}s)", timeframe, interval_secs);
-            
-            // Clear MQTT retained messages for this timeframe
-            for &symbol in &symbols {
-                let topic = format!("crypto/historical/{}/{}", symbol, timeframe);
-                
-                // Publish empty retained message to clear the topic
-                if let Err(_) = tokio::time::timeout(
-                    Duration::from_millis(1000),
-                    publish_empty_retained_message(&state.mqtt_client, &topic)
-                ).await {
-                    warn!("Timeout clearing MQTT cache for {}", topic);
-                }
+        interval.tick().await;
+        if !state.leader.is_leader() {
+            continue;
+        }
+        let tracked = state.settings.borrow().tracked.clone();
+        let expired = retained_topics().expired(Instant::now(), |topic| retained_series_ttl(topic, &tracked));
+        for topic in &expired {
+            // Publish empty retained message to clear the topic
+            if tokio::time::timeout(
+                Duration::from_millis(1000),
+                publish_empty_retained_message(&state.mqtt_client, topic)
+            ).await.is_err() {
+                warn!("Timeout clearing expired retained series {}", topic);
             }
-            
-            info!("Cleared MQTT cache for timeframe {}, next clear in {}s", timeframe, interval_secs);
-            tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+        }
+        if !expired.is_empty() {
+            info!("Cleared {} expired retained historical series", expired.len());
         }
     }
 }
 
 #[cfg(test)]
-pub async fn publish_initial_priority_data(state: &web::Data<AppState>) {
+#[allow(dead_code)]
+pub async fn publish_initial_priority_data(state: &web::Data<AppState>, tracked: &TrackedSeriesConfig) {
     // Only fetch data for the most popular cryptocurrencies to avoid rate limits
-    let priority_symbols = ["BTC", "ETH"];
-    let priority_timeframes = ["24h", "7d"]; // Most commonly used timeframes
-    
     info!("Fetching priority historical data to avoid rate limits on startup");
     
     let mut failed_requests = Vec::new();
     
-    for &symbol in &priority_symbols {
-        for &timeframe in &priority_timeframes {
+    for symbol in tracked.priority_symbols.iter().map(String::as_str) {
+        for timeframe in tracked.priority_timeframes.iter().map(String::as_str) {
             info!("Fetching and publishing initial historical data for {} {}", symbol, timeframe);
             
-            match fetch_historical_data_server(symbol, timeframe, &state.api_key, &state.client).await {
+            match fetch_historical_data_server(state.market_data.as_ref(), symbol, timeframe).await {
                 result if result.success => {
                     // Cache the result
                     let cache_key = format!("{}:{}", symbol, timeframe);
@@ -157,10 +527,10 @@ pub async fn publish_initial_priority_data(state: &web::Data<AppState>) {
                     }
                     
                     // Publish to MQTT with retain=true for immediate availability
-                    if let Err(_) = tokio::time::timeout(
+                    if tokio::time::timeout(
                         Duration::from_millis(1000),
                         publish_historical_data_to_mqtt(&state.mqtt_client, symbol, timeframe, &result)
-                    ).await {
+                    ).await.is_err() {
                         warn!("MQTT publish timeout for initial {} {}", symbol, timeframe);
                     }
                 }
@@ -183,7 +553,7 @@ pub async fn publish_initial_priority_data(state: &web::Data<AppState>) {
         for (symbol, timeframe) in failed_requests {
             info!("Retrying historical data for {} {}", symbol, timeframe);
             
-            match fetch_historical_data_server(symbol, timeframe, &state.api_key, &state.client).await {
+            match fetch_historical_data_server(state.market_data.as_ref(), symbol, timeframe).await {
                 result if result.success => {
                     // Cache the result
                     let cache_key = format!("{}:{}", symbol, timeframe);
@@ -193,10 +563,10 @@ pub async fn publish_initial_priority_data(state: &web::Data<AppState>) {
                     }
                     
                     // Publish to MQTT with retain=true for immediate availability
-                    if let Err(_) = tokio::time::timeout(
+                    if tokio::time::timeout(
                         Duration::from_millis(1000),
                         publish_historical_data_to_mqtt(&state.mqtt_client, symbol, timeframe, &result)
-                    ).await {
+                    ).await.is_err() {
                         warn!("MQTT publish timeout for retry {} {}", symbol, timeframe);
                     }
                     info!("Successfully published historical data for {} {} on retry", symbol, timeframe);
@@ -214,282 +584,461 @@ pub async fn publish_initial_priority_data(state: &web::Data<AppState>) {
     info!("Completed initial historical data publishing");
 }
 
-// Helper functions for historical data processing
-fn get_start_time(days: u32) -> String {
-    let now = chrono::Utc::now();
-    let start_time = now - chrono::Duration::days(days as i64);
-    start_time.format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string()
-}
-
-fn get_current_time() -> String {
-    let now = chrono::Utc::now();
-    now.format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string()
-}
-
-fn get_interval_for_timeframe(timeframe: &str) -> &str {
-    match timeframe {
-        "1h" => "5m",
-        "24h" | "1d" => "1h",
-        "7d" => "2h",
-        "30d" => "6h",
-        "90d" => "1d",  // Use daily intervals for 90d
-        "365d" | "1y" => "1d",
-        "all" => "1d",  // Use daily intervals for all time
-        _ => "1h",
-    }
-}
-
 pub async fn fetch_historical_data_server(
+    provider: &dyn MarketDataProvider,
     symbol: &str, 
     timeframe: &str, 
-    api_key: &str, 
-    client: &Client
 ) -> HistoricalDataResult {
     let symbol = symbol.to_uppercase();
+    info!("Fetching historical data for {} with timeframe {} ({} days) from {}",
+          symbol, timeframe, timeframe_days(timeframe), provider.name());
     
-    // Convert timeframe to days for CMC API
-    let days = match timeframe {
-        "1h" => 1,
-        "24h" | "1d" => 1,
-        "7d" => 7,
-        "30d" => 30,
-        "90d" => 90,
-        "365d" | "1y" => 365,
-        "all" => 365,  // Limit "all" to 1 year due to CMC API constraints
-        _ => 30,
+    let (success, data, error) = match provider.historical_quotes(&symbol, timeframe).await {
+        Ok(points) if points.is_empty() => (false, Vec::new(), Some("No historical data points found".to_string())),
+        Ok(points) => {
+            info!("Successfully fetched {} historical data points", points.len());
+            (true, points, None)
+        }
+        Err(e) => (false, Vec::new(), Some(e.message().to_string())),
     };
-    
-    info!("Fetching historical data for {} with timeframe {} ({} days)", symbol, timeframe, days);
-    
-    // First get the cryptocurrency ID from symbol
-    let quotes_url = format!(
-        "https://pro-api.coinmarketcap.com/v1/cryptocurrency/quotes/latest?symbol={}&convert=USD",
-        symbol
-    );
-    
-    let crypto_id = match client
-        .get(&quotes_url)
-        .header("X-CMC_PRO_API_KEY", api_key)
-        .header("Accept", "application/json")
-        .send()
-        .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<serde_json::Value>().await {
-                    Ok(json) => {
-                        if let Some(data) = json.get("data").and_then(|d| d.get(&symbol)) {
-                            if let Some(id) = data.get("id").and_then(|id| id.as_u64()) {
-                                id
-                            } else {
-                                return HistoricalDataResult {
-                                    success: false,
-                                    data: Vec::new(),
-                                    error: Some("Could not find cryptocurrency ID".to_string()),
-                                    symbol: Some(symbol),
-                                    timeframe: Some(timeframe.to_string()),
-                                };
-                            }
-                        } else {
-                            return HistoricalDataResult {
-                                success: false,
-                                data: Vec::new(),
-                                error: Some("Invalid symbol or no data found".to_string()),
-                                symbol: Some(symbol),
-                                timeframe: Some(timeframe.to_string()),
-                            };
-                        }
-                    }
-                    Err(e) => {
-                        return HistoricalDataResult {
-                            success: false,
-                            data: Vec::new(),
-                            error: Some(format!("JSON parsing error: {}", e)),
-                            symbol: Some(symbol),
-                            timeframe: Some(timeframe.to_string()),
-                        };
-                    }
-                }
-            } else {
-                return HistoricalDataResult {
-                    success: false,
-                    data: Vec::new(),
-                    error: Some(format!("HTTP error getting crypto ID: {}", response.status())),
-                    symbol: Some(symbol),
-                    timeframe: Some(timeframe.to_string()),
-                };
+    let gaps = find_gaps(timeframe, &data);
+    if !gaps.is_empty() {
+        warn!("Historical data for {} {} has {} gaps", symbol, timeframe, gaps.len());
+    }
+    HistoricalDataResult {
+        success,
+        data,
+        error,
+        symbol: Some(symbol),
+        timeframe: Some(timeframe.to_string()),
+        gaps,
+        buckets: Vec::new(),
+        schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+    }
+}
+
+// Historical series for a request: a fresh copy from the in-memory cache, then from the
+// history store, and only then from the provider. Fetched series are cached and stored.
+pub async fn load_historical_data(state: &web::Data<AppState>, symbol: &str, timeframe: &str) -> HistoricalDataResult {
+    let symbol = symbol.to_uppercase();
+    let cache_key = format!("{}:{}", symbol, timeframe);
+    let now = SystemTime::now();
+    let cached = state.historical_cache.lock().unwrap().get(&cache_key).and_then(|(result, cached_at)| {
+        (result.success && is_fresh(timeframe, *cached_at, now)).then(|| result.clone())
+    });
+    if let Some(result) = cached {
+        debug!("Serving {} from the historical cache", cache_key);
+        return result;
+    }
+    if let Some(stored) = state.storage.load(&symbol, timeframe).await {
+        debug!("Serving {} from the history store", cache_key);
+        let cached_at = stored.cached_at();
+        let result = stored.into_result();
+        state.historical_cache.lock().unwrap().insert(cache_key, (result.clone(), cached_at));
+        state.persistence.mark_dirty();
+        return result;
+    }
+
+    // Requests for a series that is already being fetched wait for that fetch
+    state.historical_in_flight.run(&cache_key, || async {
+        let result = fetch_historical_data_server(state.market_data.as_ref(), &symbol, timeframe).await;
+        if result.success {
+            state.historical_cache.lock().unwrap().insert(cache_key.clone(), (result.clone(), now));
+            state.persistence.mark_dirty();
+            state.storage.save(&result, now).await;
+            if !result.gaps.is_empty() {
+                state.gap_repairs.schedule(&cache_key, &result.gaps, Instant::now());
             }
         }
-        Err(e) => {
-            return HistoricalDataResult {
-                success: false,
-                data: Vec::new(),
-                error: Some(format!("Network error getting crypto ID: {}", e)),
-                symbol: Some(symbol),
-                timeframe: Some(timeframe.to_string()),
-            };
+        result
+    }).await
+}
+
+// History of an explicit window. Not cached, since windows rarely repeat, but clients asking for
+// the same window at once share one provider call.
+pub async fn load_historical_range(state: &web::Data<AppState>, symbol: &str, range: &HistoricalRange) -> HistoricalDataResult {
+    let symbol = symbol.to_uppercase();
+    let label = range.label();
+    let key = format!("{}:{}:{}", symbol, label, range.interval);
+    state.historical_in_flight.run(&key, || async {
+        info!("Fetching historical data for {} over {} every {} from {}", symbol, label, range.interval, state.market_data.name());
+        let (success, data, error) = match state.market_data.historical_range(&symbol, range).await {
+            Ok(points) if points.is_empty() => (false, Vec::new(), Some("No historical data points found".to_string())),
+            Ok(points) => (true, points, None),
+            Err(e) => (false, Vec::new(), Some(e.message().to_string())),
+        };
+        HistoricalDataResult {
+            success,
+            gaps: find_gaps_every(range.spacing(), &data),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+            data,
+            error,
+            symbol: Some(symbol.clone()),
+            timeframe: Some(label.clone()),
+        }
+    }).await
+}
+
+// A coin the loaded mapping no longer knows has been delisted, and the provider can't look its
+// history up any more. Whatever history is still cached or stored is served instead, however
+// old. None if the coin is listed or nothing is stored for it.
+pub async fn load_delisted_history(state: &web::Data<AppState>, symbol: &str, timeframe: &str) -> Option<HistoricalDataResult> {
+    let symbol = symbol.to_uppercase();
+    let delisted = {
+        let identities = state.identity_map.lock().unwrap();
+        identities.len() > 0 && identities.resolve(&symbol).is_none()
+    };
+    if !delisted {
+        return None;
+    }
+    let cached = state.historical_cache.lock().unwrap()
+        .get(&format!("{}:{}", symbol, timeframe))
+        .filter(|(result, _)| result.success)
+        .map(|(result, _)| result.clone());
+    match cached {
+        Some(result) => Some(result),
+        None => state.storage.load_any_age(&symbol, timeframe).await.map(StoredHistory::into_result),
+    }
+}
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+// Rolling 24h and 52-week high/low for a symbol from every cached series for it, plus the
+// current price. A window without any cached history is left as None.
+pub fn compute_price_ranges(
+    symbol: &str,
+    current_price: Option<f64>,
+    history: &HashMap<String, (HistoricalDataResult, SystemTime)>,
+    now: SystemTime,
+) -> PriceRanges {
+    let now_ts = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    let points: Vec<(f64, f64)> = history
+        .iter()
+        .filter(|(key, (result, _))| {
+            result.success
+                && key.split_once(':').is_some_and(|(s, _)| s.eq_ignore_ascii_case(symbol))
+        })
+        .flat_map(|(_, (result, _))| result.data.iter().map(|p| (p.timestamp, p.price)))
+        .collect();
+
+    let window = |days: f64| -> (Option<f64>, Option<f64>) {
+        let prices: Vec<f64> = points
+            .iter()
+            .filter(|(timestamp, _)| *timestamp >= now_ts - days * SECONDS_PER_DAY)
+            .map(|&(_, price)| price)
+            .collect();
+        if prices.is_empty() {
+            return (None, None);
         }
+        let prices = prices.into_iter().chain(current_price);
+        let (high, low) = prices.fold((f64::MIN, f64::MAX), |(hi, lo), p| (hi.max(p), lo.min(p)));
+        (Some(high), Some(low))
     };
+
+    let (high_24h, low_24h) = window(1.0);
+    let (high_52w, low_52w) = window(365.0);
+    PriceRanges { high_24h, low_24h, high_52w, low_52w }
+}
+
+// Keep only points newer than the client's last timestamp, for incremental chart refreshes
+pub fn historical_points_after(mut result: HistoricalDataResult, after: f64) -> HistoricalDataResult {
+    result.data.retain(|point| point.timestamp > after);
+    result
+}
+
+pub async fn fetch_cmc_mapping(state: web::Data<AppState>) -> CoinCrabResult<()> {
+    let currencies = state.market_data.primary().coin_metadata().await.inspect_err(|e| {
+        error!("CMC mapping request failed: {}", e);
+    })?;
     
-    // Now get historical data using the cryptocurrency ID
-    let interval = get_interval_for_timeframe(timeframe);
-    let start_time = get_start_time(days);
-    let end_time = get_current_time();
-    
-    let historical_url = format!(
-        "https://pro-api.coinmarketcap.com/v1/cryptocurrency/quotes/historical?id={}&time_start={}&time_end={}&interval={}",
-        crypto_id,
-        start_time,
-        end_time,
-        interval
-    );
+    let mut mapping = std::collections::HashMap::new();
+    for currency in &currencies {
+        if let Some(cmc_id) = currency.cmc_id {
+            mapping.insert(currency.symbol.to_uppercase(), cmc_id);
+        }
+    }
     
-    info!("CMC API URL: {}", historical_url);
+    // The identity map follows the coin the mapping settled on for each symbol
+    let mut identities = IdentityMap::new();
+    for currency in &currencies {
+        let (Some(cmc_id), Some(slug)) = (currency.cmc_id, &currency.slug) else {
+            continue;
+        };
+        if mapping.get(&currency.symbol.to_uppercase()) == Some(&cmc_id) {
+            identities.record_cmc(cmc_id, &currency.symbol, &currency.name, slug);
+        }
+    }
     
-    match client
-        .get(&historical_url)
-        .header("X-CMC_PRO_API_KEY", api_key)
-        .header("Accept", "application/json")
-        .send()
-        .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<serde_json::Value>().await {
-                    Ok(json) => {
-                        let mut historical_points = Vec::new();
-                        
-                        if let Some(data) = json.get("data").and_then(|d| d.get("quotes").and_then(|q| q.as_array())) {
-                            for quote in data {
-                                if let (Some(timestamp_str), Some(price_data)) = (
-                                    quote.get("timestamp").and_then(|t| t.as_str()),
-                                    quote.get("quote").and_then(|q| q.get("USD"))
-                                ) {
-                                    if let (Ok(timestamp), Some(price)) = (
-                                        chrono::DateTime::parse_from_rfc3339(timestamp_str),
-                                        price_data.get("price").and_then(|p| p.as_f64())
-                                    ) {
-                                        historical_points.push(HistoricalDataPoint {
-                                            timestamp: timestamp.timestamp() as f64,
-                                            price,
-                                            volume: price_data.get("volume_24h").and_then(|v| v.as_f64()),
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                        
-                        if historical_points.is_empty() {
-                            HistoricalDataResult {
-                                success: false,
-                                data: Vec::new(),
-                                error: Some("No historical data points found".to_string()),
-                                symbol: Some(symbol),
-                                timeframe: Some(timeframe.to_string()),
-                            }
-                        } else {
-                            info!("Successfully fetched {} historical data points", historical_points.len());
-                            HistoricalDataResult {
-                                success: true,
-                                data: historical_points,
-                                error: None,
-                                symbol: Some(symbol),
-                                timeframe: Some(timeframe.to_string()),
-                            }
-                        }
-                    }
-                    Err(e) => HistoricalDataResult {
-                        success: false,
-                        data: Vec::new(),
-                        error: Some(format!("JSON parsing error: {}", e)),
-                        symbol: Some(symbol),
-                        timeframe: Some(timeframe.to_string()),
-                    },
-                }
-            } else {
-                HistoricalDataResult {
-                    success: false,
-                    data: Vec::new(),
-                    error: Some(format!("HTTP error: {}", response.status())),
-                    symbol: Some(symbol),
-                    timeframe: Some(timeframe.to_string()),
-                }
-            }
+    let count = mapping.len();
+    *state.cmc_mapping.lock().unwrap() = mapping;
+    *state.identity_map.lock().unwrap() = identities;
+    info!("Successfully loaded {} CMC cryptocurrency mappings", count);
+    Ok(())
+}
+
+// Attach CoinGecko ids to the coins in the identity map. CoinGecko's coin list needs no API key;
+// run after fetch_cmc_mapping, which replaces the map. Skipped when the CoinGecko fallback is off.
+pub async fn fetch_coingecko_ids(state: web::Data<AppState>) -> CoinCrabResult<()> {
+    let Some(secondary) = state.market_data.secondary() else {
+        return Ok(());
+    };
+    let coins = secondary.coin_metadata().await?;
+
+    let mut identities = state.identity_map.lock().unwrap();
+    for coin in &coins {
+        if let Some(coingecko_id) = &coin.coingecko_id {
+            identities.record_coingecko(coingecko_id, &coin.symbol, &coin.name);
         }
-        Err(e) => HistoricalDataResult {
-            success: false,
-            data: Vec::new(),
-            error: Some(format!("Network error: {}", e)),
-            symbol: Some(symbol),
-            timeframe: Some(timeframe.to_string()),
-        },
+    }
+    info!("Matched {} of {} mapped coins to CoinGecko ids", identities.coingecko_len(), identities.len());
+    Ok(())
+}
+
+// Build the prefetch hint list from freshly cached, successful historical results (newest first)
+pub fn collect_prefetch_hints(
+    historical_cache: &HashMap<String, (HistoricalDataResult, SystemTime)>,
+    now: SystemTime,
+) -> Vec<PrefetchHint> {
+    let mut hints: Vec<PrefetchHint> = Vec::new();
+
+    for (result, cached_time) in historical_cache.values() {
+        if !result.success {
+            continue;
+        }
+        let age = now.duration_since(*cached_time).unwrap_or(Duration::from_secs(0));
+        if age > Duration::from_secs(PREFETCH_HINT_MAX_AGE_SECS) {
+            continue;
+        }
+        let (Some(symbol), Some(timeframe)) = (&result.symbol, &result.timeframe) else {
+            continue;
+        };
+
+        let symbol = symbol.to_uppercase();
+        let cached_at = cached_time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+
+        // Requests for "btc" and "BTC" share one hint, keeping the most recent
+        match hints.iter_mut().find(|h| h.symbol == symbol && &h.timeframe == timeframe) {
+            Some(existing) => existing.cached_at = existing.cached_at.max(cached_at),
+            None => hints.push(PrefetchHint {
+                symbol,
+                timeframe: timeframe.clone(),
+                cached_at,
+            }),
+        }
+    }
+
+    hints.sort_by_key(|h| std::cmp::Reverse(h.cached_at));
+    hints.truncate(MAX_PREFETCH_HINTS);
+    hints
+}
+
+pub async fn publish_prefetch_hints(state: &web::Data<AppState>) {
+    let hints = {
+        let hist_cache = state.historical_cache.lock().unwrap();
+        collect_prefetch_hints(&hist_cache, SystemTime::now())
+    };
+
+    if tokio::time::timeout(
+        Duration::from_millis(1000),
+        publish_prefetch_hints_to_mqtt(&state.mqtt_client, &hints)
+    ).await.is_err() {
+        warn!("MQTT publish timeout for prefetch hints");
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::CmcQuotesResponse;
+    use shared::HistoricalDataPoint;
+
+    fn cached_series(symbol: &str, timeframe: &str, success: bool) -> HistoricalDataResult {
+        HistoricalDataResult {
+            success,
+            data: Vec::new(),
+            error: None,
+            symbol: Some(symbol.to_string()),
+            timeframe: Some(timeframe.to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        }
+    }
+
+    fn listed(id: i32, symbol: &str, price: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: shared::Quote {
+                usd: shared::UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
 
     #[test]
-    fn test_get_start_time() {
-        let start_time = get_start_time(30);
-        
-        // Verify it's a valid ISO 8601 timestamp
-        assert!(start_time.contains("T"));
-        assert!(start_time.ends_with("Z"));
-        assert_eq!(start_time.len(), 24); // Format: 2024-01-01T00:00:00.000Z
-        
-        // Parse the timestamp to ensure it's valid
-        let parsed = chrono::DateTime::parse_from_rfc3339(&start_time);
-        assert!(parsed.is_ok());
-        
-        // Verify it's approximately 30 days ago
-        let parsed_time = parsed.unwrap();
-        let now = chrono::Utc::now();
-        let diff = now.signed_duration_since(parsed_time.with_timezone(&chrono::Utc));
-        
-        // Should be between 29.9 and 30.1 days (allowing for execution time)
-        assert!(diff.num_days() >= 29 && diff.num_days() <= 31);
+    fn test_pinned_coins_always_published_first() {
+        use crate::config::PinnedConfig;
+        let pinned = PinnedSymbols::new(&PinnedConfig { symbols: vec!["PEPE".to_string()], path: None });
+        let listings = vec![listed(1, "BTC", 50000.0), listed(2, "ETH", 3000.0), listed(24478, "PEPE", 0.00001)];
+
+        let symbols = |coins: Vec<CryptoCurrency>| coins.into_iter().map(|coin| coin.symbol).collect::<Vec<_>>();
+        assert_eq!(symbols(per_symbol_coins(&listings, vec![listings[1].clone()], &pinned)), vec!["PEPE", "ETH"]);
+        assert_eq!(symbols(per_symbol_coins(&listings, vec![listings[2].clone()], &pinned)), vec!["PEPE"]);
+        assert_eq!(symbols(per_symbol_coins(&listings, Vec::new(), &pinned)), vec!["PEPE"]);
     }
 
     #[test]
-    fn test_get_current_time() {
-        let current_time = get_current_time();
-        
-        // Verify it's a valid ISO 8601 timestamp
-        assert!(current_time.contains("T"));
-        assert!(current_time.ends_with("Z"));
-        assert_eq!(current_time.len(), 24); // Format: 2024-01-01T00:00:00.000Z
-        
-        // Parse the timestamp to ensure it's valid
-        let parsed = chrono::DateTime::parse_from_rfc3339(&current_time);
-        assert!(parsed.is_ok());
-        
-        // Verify it's very recent (within 1 second)
-        let parsed_time = parsed.unwrap();
-        let now = chrono::Utc::now();
-        let diff = now.signed_duration_since(parsed_time.with_timezone(&chrono::Utc));
-        
-        assert!(diff.num_seconds().abs() <= 1);
+    fn test_hot_tier_ids() {
+        let listings = vec![listed(1, "BTC", 1.0), listed(1027, "ETH", 1.0), listed(5426, "SOL", 1.0)];
+        let top = UpdateTierConfig { hot_tier_size: 2, ..UpdateTierConfig::default() };
+        assert_eq!(hot_tier_ids(&listings, &top), vec![1, 1027]);
+
+        let listed_symbols = UpdateTierConfig { hot_symbols: vec!["SOL".to_string(), "NOPE".to_string()], ..top };
+        assert_eq!(hot_tier_ids(&listings, &listed_symbols), vec![5426]);
+    }
+
+    #[test]
+    fn test_merge_quotes_updates_in_place() {
+        let mut listings = vec![listed(1, "BTC", 50000.0), listed(1027, "ETH", 3000.0)];
+        let json = r#"{"data":{"1027":{"id":1027,"name":"Ethereum","symbol":"ETH","slug":"ethereum",
+            "quote":{"USD":{"price":3100.0,"percent_change_1h":0.1,"percent_change_24h":1.0,"percent_change_7d":2.0,
+            "market_cap":1.0,"volume_24h":2.0,"last_updated":"2024-01-01T00:01:00Z"}}}}}"#;
+        let quotes: CmcQuotesResponse = serde_json::from_str(json).unwrap();
+
+        let updated = merge_quotes(&mut listings, quotes.data.into_values().collect());
+        assert_eq!(updated.len(), 1);
+        assert_eq!(listings[1].quote.usd.price, 3100.0);
+        assert_eq!(listings[0].quote.usd.price, 50000.0);
+    }
+
+    #[test]
+    fn test_compute_price_ranges() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let now_ts = 1_704_067_200.0;
+        let series = |points: &[(f64, f64)]| HistoricalDataResult {
+            success: true,
+            data: points
+                .iter()
+                .map(|&(timestamp, price)| HistoricalDataPoint { timestamp, price, volume: None })
+                .collect(),
+            error: None,
+            symbol: Some("BTC".to_string()),
+            timeframe: None,
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        };
+
+        let mut history = HashMap::new();
+        history.insert("BTC:24h".to_string(), (series(&[(now_ts - 3600.0, 100.0), (now_ts - 7200.0, 90.0)]), now));
+        history.insert("btc:365d".to_string(), (series(&[(now_ts - 100.0 * SECONDS_PER_DAY, 150.0), (now_ts - 400.0 * SECONDS_PER_DAY, 10.0)]), now));
+        history.insert("ETH:24h".to_string(), (series(&[(now_ts - 60.0, 5.0)]), now));
+
+        let ranges = compute_price_ranges("BTC", Some(95.0), &history, now);
+        assert_eq!(ranges.high_24h, Some(100.0));
+        assert_eq!(ranges.low_24h, Some(90.0));
+        // Points older than 52 weeks are excluded
+        assert_eq!(ranges.high_52w, Some(150.0));
+        assert_eq!(ranges.low_52w, Some(90.0));
+
+        // The current price extends the range
+        let ranges = compute_price_ranges("BTC", Some(200.0), &history, now);
+        assert_eq!(ranges.high_24h, Some(200.0));
+
+        // No cached history means no ranges
+        assert_eq!(compute_price_ranges("SOL", Some(1.0), &history, now), PriceRanges::default());
+    }
+
+    #[test]
+    fn test_historical_points_after() {
+        let mut series = cached_series("BTC", "24h", true);
+        series.data = [100.0, 200.0, 300.0]
+            .iter()
+            .map(|&timestamp| HistoricalDataPoint { timestamp, price: 1.0, volume: None })
+            .collect();
+
+        let delta = historical_points_after(series.clone(), 200.0);
+        assert_eq!(delta.data.len(), 1);
+        assert_eq!(delta.data[0].timestamp, 300.0);
+        assert!(delta.success);
+
+        assert_eq!(historical_points_after(series.clone(), 0.0).data.len(), 3);
+        assert!(historical_points_after(series, 300.0).data.is_empty());
+    }
+
+    #[test]
+    fn test_is_newer_shared_fetch() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let minutes_ago = |minutes: u64| now - Duration::from_secs(minutes * 60);
+        let interval = Some(Duration::from_secs(900));
+        // Another instance fetched 5 minutes ago, we last fetched 20 minutes ago
+        assert!(is_newer_shared_fetch(minutes_ago(5), minutes_ago(20), interval, now));
+        // Our own fetch is never newer than itself
+        assert!(!is_newer_shared_fetch(minutes_ago(5), minutes_ago(5), interval, now));
+        // Too old for the leader to skip a fetch, still newer than what a standby holds
+        assert!(!is_newer_shared_fetch(minutes_ago(16), minutes_ago(20), interval, now));
+        assert!(is_newer_shared_fetch(minutes_ago(16), minutes_ago(20), None, now));
+    }
+
+    #[test]
+    fn test_collect_prefetch_hints_filters_and_orders() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let mut cache = HashMap::new();
+        cache.insert("BTC:24h".to_string(), (cached_series("BTC", "24h", true), now - Duration::from_secs(60)));
+        cache.insert("ETH:7d".to_string(), (cached_series("ETH", "7d", true), now - Duration::from_secs(10)));
+        cache.insert("SOL:24h".to_string(), (cached_series("SOL", "24h", false), now));
+        cache.insert("ADA:30d".to_string(), (cached_series("ADA", "30d", true), now - Duration::from_secs(7200)));
+
+        let hints = collect_prefetch_hints(&cache, now);
+
+        assert_eq!(hints.len(), 2);
+        assert_eq!(hints[0].symbol, "ETH");
+        assert_eq!(hints[1].symbol, "BTC");
+        assert_eq!(hints[1].cached_at, 1_704_067_140);
     }
 
     #[test]
-    fn test_get_interval_for_timeframe() {
-        assert_eq!(get_interval_for_timeframe("1h"), "5m");
-        assert_eq!(get_interval_for_timeframe("24h"), "1h");
-        assert_eq!(get_interval_for_timeframe("1d"), "1h");
-        assert_eq!(get_interval_for_timeframe("7d"), "2h");
-        assert_eq!(get_interval_for_timeframe("30d"), "6h");
-        assert_eq!(get_interval_for_timeframe("90d"), "1d");
-        assert_eq!(get_interval_for_timeframe("365d"), "1d");
-        assert_eq!(get_interval_for_timeframe("1y"), "1d");
-        assert_eq!(get_interval_for_timeframe("all"), "1d");
-        assert_eq!(get_interval_for_timeframe("invalid"), "1h"); // Default case
+    fn test_collect_prefetch_hints_deduplicates_symbol_case() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let mut cache = HashMap::new();
+        cache.insert("btc:24h".to_string(), (cached_series("btc", "24h", true), now - Duration::from_secs(30)));
+        cache.insert("BTC:24h".to_string(), (cached_series("BTC", "24h", true), now - Duration::from_secs(5)));
+
+        let hints = collect_prefetch_hints(&cache, now);
+
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].symbol, "BTC");
+        assert_eq!(hints[0].cached_at, 1_704_067_195);
+    }
+
+    #[test]
+    fn test_collect_prefetch_hints_caps_count() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let mut cache = HashMap::new();
+        for i in 0..(MAX_PREFETCH_HINTS + 5) {
+            let symbol = format!("C{}", i);
+            cache.insert(format!("{}:24h", symbol), (cached_series(&symbol, "24h", true), now - Duration::from_secs(i as u64)));
+        }
+
+        let hints = collect_prefetch_hints(&cache, now);
+        assert_eq!(hints.len(), MAX_PREFETCH_HINTS);
+        assert_eq!(hints[0].symbol, "C0");
     }
 
     #[test]
     fn test_timeframe_to_days_conversion() {
-        // Test the conversion logic used in fetch_historical_data_server
+        // Test the conversion logic shared by the providers
         let test_cases = vec![
             ("1h", 1),
             ("24h", 1),
@@ -504,17 +1053,7 @@ mod tests {
         ];
 
         for (timeframe, expected_days) in test_cases {
-            let days = match timeframe {
-                "1h" => 1,
-                "24h" | "1d" => 1,
-                "7d" => 7,
-                "30d" => 30,
-                "90d" => 90,
-                "365d" | "1y" => 365,
-                "all" => 365,
-                _ => 30,
-            };
-            
+            let days = timeframe_days(timeframe);
             assert_eq!(days, expected_days, "Failed for timeframe: {}", timeframe);
         }
     }
@@ -533,6 +1072,9 @@ mod tests {
             error: None,
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
         };
 
         assert!(result.success);
@@ -561,31 +1103,34 @@ mod tests {
 
     #[test] 
     fn test_cache_intervals() {
-        // Test the cache clearing intervals from clear_mqtt_cache_periodically
+        // Test the retained series time to live from expire_retained_history_periodically
         let timeframes = ["1h", "24h", "7d", "30d", "90d", "365d"];
         let expected_intervals = [300, 3600, 7200, 21600, 86400, 86400];
         
         for (i, &timeframe) in timeframes.iter().enumerate() {
-            let interval_secs = match timeframe {
-                "1h" => 300,    // 5 minutes
-                "24h" => 3600,  // 1 hour  
-                "7d" => 7200,   // 2 hours
-                "30d" => 21600, // 6 hours
-                "90d" => 86400, // 1 day
-                "365d" => 86400, // 1 day
-                _ => 3600,
-            };
+            let interval_secs = cache_clear_interval_secs(timeframe);
             
             assert_eq!(interval_secs, expected_intervals[i], 
                       "Interval mismatch for timeframe: {}", timeframe);
         }
     }
 
+    #[test]
+    fn test_retained_series_ttl() {
+        let tracked = TrackedSeriesConfig::default();
+        assert_eq!(retained_series_ttl("crypto/historical/BTC/1h", &tracked), Some(Duration::from_secs(300)));
+        assert_eq!(retained_series_ttl("crypto/historical/BTC/7d/msgpack", &tracked), Some(Duration::from_secs(7200)));
+        // Untracked coins and other families are left alone
+        assert_eq!(retained_series_ttl("crypto/historical/PEPE/1h", &tracked), None);
+        assert_eq!(retained_series_ttl("crypto/prices/BTC", &tracked), None);
+    }
+
     #[test]
     fn test_priority_symbols_and_timeframes() {
-        // Test the priority data configuration from publish_initial_priority_data
-        let priority_symbols = ["BTC", "ETH"];
-        let priority_timeframes = ["24h", "7d"];
+        // Test the default priority data configuration for publish_initial_priority_data
+        let tracked = TrackedSeriesConfig::default();
+        let priority_symbols: Vec<&str> = tracked.priority_symbols.iter().map(String::as_str).collect();
+        let priority_timeframes: Vec<&str> = tracked.priority_timeframes.iter().map(String::as_str).collect();
         
         assert_eq!(priority_symbols.len(), 2);
         assert_eq!(priority_timeframes.len(), 2);
@@ -596,48 +1141,3 @@ mod tests {
         assert!(priority_timeframes.contains(&"7d"));
     }
 }
-
-pub async fn fetch_cmc_mapping(state: web::Data<AppState>) -> Result<(), String> {
-    info!("Fetching CMC cryptocurrency mapping data...");
-    
-    let response = state.client
-        .get("https://pro-api.coinmarketcap.com/v1/cryptocurrency/map")
-        .query(&[("limit", "5000")])
-        .header("X-CMC_PRO_API_KEY", &state.api_key)
-        .header("Accept", "application/json")
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send CMC mapping request: {}", e))?;
-    
-    if response.status().is_success() {
-        let cmc_response: CmcMappingResponse = response
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse CMC mapping response: {}", e))?;
-        
-        if cmc_response.status.error_code == 0 {
-            let mut mapping = std::collections::HashMap::new();
-            for currency in cmc_response.data {
-                mapping.insert(currency.symbol.to_uppercase(), currency.id);
-            }
-            
-            let count = mapping.len();
-            *state.cmc_mapping.lock().unwrap() = mapping;
-            info!("Successfully loaded {} CMC cryptocurrency mappings", count);
-
-            Ok(())
-        } else {
-            let error_msg = format!("CMC API error: {} (code: {})", 
-                cmc_response.status.error_message.unwrap_or("Unknown error".to_string()),
-                cmc_response.status.error_code
-            );
-            error!("{}", error_msg);
-            Err(error_msg)
-        }
-    } else {
-        let error_msg = format!("CMC mapping request failed with status: {}", response.status());
-        error!("{}", error_msg);
-        Err(error_msg)
-    }
-}
-
@@ -0,0 +1,147 @@
+use std::sync::RwLock;
+use serde::Serialize;
+use serde_json::Value;
+use shared::{CoinCrabError, CoinCrabResult};
+
+// Quote fields that can be left out of payloads; price is always sent
+pub const OPTIONAL_QUOTE_FIELDS: [&str; 6] = [
+    "percent_change_1h",
+    "percent_change_24h",
+    "percent_change_7d",
+    "market_cap",
+    "volume_24h",
+    "last_updated",
+];
+
+// Which optional quote fields go into published and returned price payloads
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteFields {
+    included: Vec<&'static str>,
+}
+
+impl Default for QuoteFields {
+    fn default() -> Self {
+        QuoteFields { included: OPTIONAL_QUOTE_FIELDS.to_vec() }
+    }
+}
+
+impl QuoteFields {
+    // Comma-separated field names, e.g. "price,percent_change_24h". "price" may be listed
+    // but is implied; unknown names are an error so typos don't silently empty a feed.
+    pub fn parse(list: &str) -> CoinCrabResult<Self> {
+        let mut included = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name == "price" {
+                continue;
+            }
+            let Some(field) = OPTIONAL_QUOTE_FIELDS.iter().find(|field| field.eq_ignore_ascii_case(name)) else {
+                return Err(CoinCrabError::Config(format!(
+                    "Unknown quote field '{}' (expected price or one of {})", name, OPTIONAL_QUOTE_FIELDS.join(", ")
+                )));
+            };
+            if !included.contains(field) {
+                included.push(*field);
+            }
+        }
+        Ok(QuoteFields { included })
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.included.len() == OPTIONAL_QUOTE_FIELDS.len()
+    }
+
+    // Serialize a payload and drop the unselected fields from every coin quote in it
+    pub fn select<T: Serialize + ?Sized>(&self, payload: &T) -> CoinCrabResult<Value> {
+        let mut value = serde_json::to_value(payload)?;
+        if !self.is_all() {
+            self.strip(&mut value);
+        }
+        Ok(value)
+    }
+
+    // Coins are found by their "quote": {"USD": {...}} member wherever they are nested
+    // (listings, envelopes, deltas, coin details); converted currencies beside USD are
+    // trimmed the same way
+    fn strip(&self, value: &mut Value) {
+        match value {
+            Value::Array(items) => items.iter_mut().for_each(|item| self.strip(item)),
+            Value::Object(map) => {
+                if let Some(Value::Object(quote)) = map.get_mut("quote") {
+                    for currency in quote.values_mut() {
+                        if let Value::Object(fields) = currency {
+                            fields.retain(|key, _| key == "price" || self.included.contains(&key.as_str()));
+                        }
+                    }
+                }
+                map.values_mut().for_each(|child| self.strip(child));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Server-wide selection from PAYLOAD_FIELDS, used for MQTT publishes and as the HTTP default
+static DEFAULT_QUOTE_FIELDS: RwLock<Option<QuoteFields>> = RwLock::new(None);
+
+pub fn set_default_quote_fields(fields: QuoteFields) {
+    *DEFAULT_QUOTE_FIELDS.write().unwrap() = Some(fields);
+}
+
+pub fn default_quote_fields() -> QuoteFields {
+    DEFAULT_QUOTE_FIELDS.read().unwrap().clone().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{CryptoCurrency, PriceEnvelope, Quote, UsdQuote};
+
+    fn coin() -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: "Bitcoin".to_string(),
+            symbol: "BTC".to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 50000.0,
+                    percent_change_1h: 0.5,
+                    percent_change_24h: 2.5,
+                    percent_change_7d: 10.0,
+                    market_cap: 9.0e11,
+                    volume_24h: 5.0e10,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_quote_fields() {
+        let fields = QuoteFields::parse(" price, Percent_Change_24h ,market_cap,market_cap").unwrap();
+        assert_eq!(fields.included, vec!["percent_change_24h", "market_cap"]);
+        assert!(!fields.is_all());
+        assert!(QuoteFields::parse("price").unwrap().included.is_empty());
+        assert!(matches!(QuoteFields::parse("price,volume"), Err(CoinCrabError::Config(_))));
+        assert!(QuoteFields::default().is_all());
+    }
+
+    #[test]
+    fn test_select_strips_nested_quotes() {
+        let fields = QuoteFields::parse("percent_change_24h").unwrap();
+        let envelope = PriceEnvelope::new(vec![coin(), coin()], 60);
+        let value = fields.select(&envelope).unwrap();
+        let usd = &value["data"][1]["quote"]["USD"];
+        assert_eq!(usd.as_object().unwrap().len(), 2);
+        assert_eq!(usd["price"], 50000.0);
+        assert_eq!(usd["percent_change_24h"], 2.5);
+        assert!(value["expires_at"].is_number());
+
+        // The compact form still parses as the shared type
+        let parsed: PriceEnvelope<Vec<CryptoCurrency>> = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.data[0].quote.usd.volume_24h, 0.0);
+
+        let full = QuoteFields::default().select(&coin()).unwrap();
+        assert_eq!(full["quote"]["USD"].as_object().unwrap().len(), 7);
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::Duration;
+use actix_web::web;
+use tracing::{error, info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use crate::config::FxConfig;
+use crate::http_client::{send_with_retry, RetryPolicy};
+use crate::types::{AppState, CryptoCurrency};
+use shared::{CoinCrabError, CoinCrabResult, FxMetadata, UsdQuote};
+
+// open.er-api.com sends base_code, frankfurter.app base
+#[derive(Debug, Deserialize)]
+struct RatesResponse {
+    #[serde(alias = "base_code")]
+    base: Option<String>,
+    rates: HashMap<String, f64>,
+}
+
+// Exchange rates for the configured currencies, refreshed in the background. Until the first
+// fetch succeeds coins carry only their USD quote.
+pub struct FxRates {
+    currencies: Vec<String>,
+    current: RwLock<Option<FxMetadata>>,
+}
+
+impl FxRates {
+    pub fn new(config: &FxConfig) -> Self {
+        FxRates { currencies: config.currencies.clone(), current: RwLock::new(None) }
+    }
+
+    pub fn metadata(&self) -> Option<FxMetadata> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn set(&self, metadata: FxMetadata) {
+        *self.current.write().unwrap() = Some(metadata);
+    }
+
+    // Recompute every coin's converted quotes from its USD quote, so they follow price updates
+    pub fn apply(&self, coins: &mut [CryptoCurrency]) {
+        let current = self.current.read().unwrap();
+        let Some(metadata) = current.as_ref() else {
+            return;
+        };
+        for coin in coins {
+            coin.quote.converted = metadata
+                .rates
+                .iter()
+                .map(|(currency, rate)| (currency.clone(), convert(&coin.quote.usd, *rate)))
+                .collect();
+        }
+    }
+
+    // Rates for the configured currencies only; a currency the provider doesn't know is
+    // logged and left out
+    pub fn select(&self, response: &HashMap<String, f64>, provider: &str, fetched_at: i64) -> FxMetadata {
+        let mut rates = BTreeMap::new();
+        for currency in &self.currencies {
+            match response.get(currency) {
+                Some(rate) if rate.is_finite() && *rate > 0.0 => {
+                    rates.insert(currency.clone(), *rate);
+                }
+                _ => warn!("No exchange rate for {} from {}", currency, provider),
+            }
+        }
+        FxMetadata { base: "USD".to_string(), rates, provider: provider.to_string(), fetched_at }
+    }
+}
+
+// Amounts scale with the rate; percent changes stay the USD ones
+fn convert(usd: &UsdQuote, rate: f64) -> UsdQuote {
+    UsdQuote {
+        price: usd.price * rate,
+        market_cap: usd.market_cap * rate,
+        volume_24h: usd.volume_24h * rate,
+        ..usd.clone()
+    }
+}
+
+async fn fetch_rates(client: &Client, retry_policy: &RetryPolicy, url: &str) -> CoinCrabResult<HashMap<String, f64>> {
+    let response = send_with_retry(client.get(url), retry_policy)
+        .await
+        .map_err(|e| CoinCrabError::Http(format!("Exchange rate request failed: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(CoinCrabError::Http(format!("Exchange rate provider returned {}", response.status())));
+    }
+    let body: RatesResponse = response
+        .json()
+        .await
+        .map_err(|e| CoinCrabError::Parse(format!("Failed to parse exchange rates: {}", e)))?;
+    if body.base.as_deref().is_some_and(|base| !base.eq_ignore_ascii_case("USD")) {
+        return Err(CoinCrabError::Config(format!("Exchange rates are based on {:?}, expected USD", body.base)));
+    }
+    Ok(body.rates)
+}
+
+pub async fn refresh_fx_rates_periodically(state: web::Data<AppState>, config: FxConfig) {
+    if !config.is_enabled() {
+        return;
+    }
+    info!("Deriving {} quotes from exchange rates every {}s", config.currencies.join(", "), config.refresh_seconds);
+    let provider = reqwest::Url::parse(&config.api_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| config.api_url.clone());
+    let mut interval = tokio::time::interval(Duration::from_secs(config.refresh_seconds));
+    loop {
+        interval.tick().await;
+        match fetch_rates(&state.client, &state.retry_policy, &config.api_url).await {
+            Ok(rates) => {
+                let metadata = state.fx.select(&rates, &provider, chrono::Utc::now().timestamp());
+                info!("Updated {} exchange rates from {}", metadata.rates.len(), provider);
+                state.fx.set(metadata);
+            }
+            // The previous rates stay in use
+            Err(e) => error!("Failed to refresh exchange rates: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Quote;
+
+    #[test]
+    fn test_apply_derives_quotes_from_usd() {
+        let config = FxConfig { currencies: vec!["EUR".to_string(), "XYZ".to_string()], ..FxConfig::default() };
+        let fx = FxRates::new(&config);
+        let mut coins = vec![CryptoCurrency {
+            id: 1,
+            name: "Bitcoin".to_string(),
+            symbol: "BTC".to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 100.0,
+                    percent_change_1h: 1.0,
+                    percent_change_24h: 2.0,
+                    percent_change_7d: 3.0,
+                    market_cap: 1000.0,
+                    volume_24h: 10.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }];
+        fx.apply(&mut coins);
+        assert!(coins[0].quote.converted.is_empty());
+
+        let response: RatesResponse = serde_json::from_str(r#"{"result":"success","base_code":"USD","rates":{"USD":1,"EUR":0.5,"GBP":0.8}}"#).unwrap();
+        let metadata = fx.select(&response.rates, "open.er-api.com", 1_700_000_000);
+        assert_eq!(metadata.rates.keys().collect::<Vec<_>>(), vec!["EUR"]);
+        fx.set(metadata);
+        fx.apply(&mut coins);
+        let eur = &coins[0].quote.converted["EUR"];
+        assert_eq!((eur.price, eur.market_cap, eur.volume_24h, eur.percent_change_24h), (50.0, 500.0, 5.0, 2.0));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::web;
+use tracing::{debug, info, warn};
+use crate::data::fetch_historical_data_server;
+use crate::mqtt::publish_historical_data_to_mqtt;
+use crate::providers::MarketDataProvider;
+use crate::storage::max_age;
+use crate::types::AppState;
+use shared::{HistoricalDataPoint, HistoricalGap};
+
+// Historical series can come back with stretches of missing samples, e.g. from a provider
+// outage. Those stretches are flagged in the series' gaps and the series is fetched again later,
+// taking only the samples inside the gaps, until they are filled or the retries run out.
+
+// Points more than this many sampling intervals apart have samples missing between them
+const GAP_FACTOR: f64 = 2.5;
+// Delay before each re-fetch, giving an outage time to end
+const REPAIR_DELAYS: [Duration; 3] = [Duration::from_secs(600), Duration::from_secs(1800), Duration::from_secs(7200)];
+const REPAIR_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Gaps between consecutive points of a series sorted by timestamp. A stored series is refreshed
+// once per sampling interval, so max_age is also the provider's spacing of the timeframe.
+pub fn find_gaps(timeframe: &str, points: &[HistoricalDataPoint]) -> Vec<HistoricalGap> {
+    find_gaps_every(max_age(timeframe), points)
+}
+
+// Same, for a series sampled every `spacing`
+pub fn find_gaps_every(spacing: Duration, points: &[HistoricalDataPoint]) -> Vec<HistoricalGap> {
+    let limit = spacing.as_secs_f64() * GAP_FACTOR;
+    points
+        .windows(2)
+        .filter(|pair| pair[1].timestamp - pair[0].timestamp > limit)
+        .map(|pair| HistoricalGap { start: pair[0].timestamp, end: pair[1].timestamp })
+        .collect()
+}
+
+// The series with the re-fetched samples that fall inside its gaps added
+pub fn fill_gaps(points: &[HistoricalDataPoint], gaps: &[HistoricalGap], refetched: &[HistoricalDataPoint]) -> Vec<HistoricalDataPoint> {
+    let mut filled = points.to_vec();
+    filled.extend(
+        refetched
+            .iter()
+            .filter(|point| gaps.iter().any(|gap| point.timestamp > gap.start && point.timestamp < gap.end))
+            .cloned(),
+    );
+    filled.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+    filled
+}
+
+struct Repair {
+    due: Instant,
+    attempts: usize,
+}
+
+// Series waiting for a re-fetch, keyed SYMBOL:timeframe
+#[derive(Default)]
+pub struct GapRepairs {
+    pending: Mutex<HashMap<String, Repair>>,
+    // Gaps still there after the last retry. The provider has no data for them, so the same
+    // gaps in a later fetch aren't retried again.
+    abandoned: Mutex<HashMap<String, Vec<HistoricalGap>>>,
+}
+
+impl GapRepairs {
+    pub fn schedule(&self, key: &str, gaps: &[HistoricalGap], now: Instant) {
+        if self.abandoned.lock().unwrap().get(key).is_some_and(|abandoned| gaps.iter().all(|gap| abandoned.contains(gap))) {
+            return;
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert(Repair { due: now + REPAIR_DELAYS[0], attempts: 0 });
+    }
+
+    pub fn due(&self, now: Instant) -> Vec<String> {
+        self.pending.lock().unwrap().iter().filter(|(_, repair)| repair.due <= now).map(|(key, _)| key.clone()).collect()
+    }
+
+    fn finish(&self, key: &str) {
+        self.pending.lock().unwrap().remove(key);
+        self.abandoned.lock().unwrap().remove(key);
+    }
+
+    // Schedule the next attempt, or give up on the remaining gaps after the last one
+    fn retry(&self, key: &str, remaining: &[HistoricalGap], now: Instant) {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(repair) = pending.get_mut(key) else {
+            return;
+        };
+        repair.attempts += 1;
+        match REPAIR_DELAYS.get(repair.attempts) {
+            Some(delay) => repair.due = now + *delay,
+            None => {
+                pending.remove(key);
+                warn!("Giving up on {} gaps in {} after {} re-fetches", remaining.len(), key, REPAIR_DELAYS.len());
+                self.abandoned.lock().unwrap().insert(key.to_string(), remaining.to_vec());
+            }
+        }
+    }
+}
+
+// Re-fetch one series and fill what it can of its gaps in the cache, the store and the
+// retained topic
+async fn repair_series(state: &web::Data<AppState>, key: &str) {
+    let Some((symbol, timeframe)) = key.split_once(':') else {
+        state.gap_repairs.finish(key);
+        return;
+    };
+    let cached = state.historical_cache.lock().unwrap().get(key).cloned();
+    let Some((series, cached_at)) = cached.filter(|(series, _)| series.success && !series.gaps.is_empty()) else {
+        // Replaced by a complete fetch in the meantime, or evicted
+        state.gap_repairs.finish(key);
+        return;
+    };
+
+    let refetched = fetch_historical_data_server(state.market_data.as_ref(), symbol, timeframe).await;
+    if !refetched.success {
+        debug!("Re-fetch of {} for its gaps failed: {:?}", key, refetched.error);
+        state.gap_repairs.retry(key, &series.gaps, Instant::now());
+        return;
+    }
+    let data = fill_gaps(&series.data, &series.gaps, &refetched.data);
+    let added = data.len() - series.data.len();
+    let gaps = find_gaps(timeframe, &data);
+    if added > 0 {
+        info!("Filled {} missing samples of {}; {} gaps remain", added, key, gaps.len());
+        let repaired = shared::HistoricalDataResult { data, gaps: gaps.clone(), ..series };
+        // Keeps the original fetch time, so the series is refreshed on its usual schedule
+        state.historical_cache.lock().unwrap().insert(key.to_string(), (repaired.clone(), cached_at));
+        state.persistence.mark_dirty();
+        state.storage.save(&repaired, cached_at).await;
+        if tokio::time::timeout(
+            Duration::from_millis(1000),
+            publish_historical_data_to_mqtt(&state.mqtt_client, symbol, timeframe, &repaired)
+        ).await.is_err() {
+            warn!("MQTT publish timeout for repaired {}", key);
+        }
+    }
+    if gaps.is_empty() {
+        state.gap_repairs.finish(key);
+    } else {
+        state.gap_repairs.retry(key, &gaps, Instant::now());
+    }
+}
+
+// Work through the scheduled re-fetches on the leader, holding off while the provider is rate limited
+pub async fn repair_gaps_periodically(state: web::Data<AppState>) {
+    let mut interval = tokio::time::interval(REPAIR_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !state.leader.is_leader() || state.market_data.retry_after().is_some() {
+            continue;
+        }
+        for key in state.gap_repairs.due(Instant::now()) {
+            repair_series(&state, &key).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(timestamps: &[f64]) -> Vec<HistoricalDataPoint> {
+        timestamps.iter().map(|&timestamp| HistoricalDataPoint { timestamp, price: 1.0, volume: None }).collect()
+    }
+
+    #[test]
+    fn test_find_and_fill_gaps() {
+        // Hourly samples of a 24h series with 03:00 to 05:00 missing
+        let series = points(&[0.0, 3600.0, 7200.0, 21_600.0, 25_200.0]);
+        let gaps = find_gaps("24h", &series);
+        assert_eq!(gaps, vec![HistoricalGap { start: 7200.0, end: 21_600.0 }]);
+        // One missing sample is within the tolerance
+        assert!(find_gaps("24h", &points(&[0.0, 7200.0])).is_empty());
+
+        // Only re-fetched samples inside the gap are taken
+        let refetched = points(&[3600.0, 10_800.0, 14_400.0, 18_000.0, 28_800.0]);
+        let filled = fill_gaps(&series, &gaps, &refetched);
+        let timestamps: Vec<f64> = filled.iter().map(|point| point.timestamp).collect();
+        assert_eq!(timestamps, vec![0.0, 3600.0, 7200.0, 10_800.0, 14_400.0, 18_000.0, 21_600.0, 25_200.0]);
+        assert!(find_gaps("24h", &filled).is_empty());
+    }
+
+    #[test]
+    fn test_repairs_retry_then_give_up() {
+        let repairs = GapRepairs::default();
+        let now = Instant::now();
+        let gaps = vec![HistoricalGap { start: 0.0, end: 10_800.0 }];
+        repairs.schedule("BTC:24h", &gaps, now);
+        assert!(repairs.due(now).is_empty());
+        assert_eq!(repairs.due(now + REPAIR_DELAYS[0]), vec!["BTC:24h".to_string()]);
+
+        for _ in 0..REPAIR_DELAYS.len() {
+            repairs.retry("BTC:24h", &gaps, now);
+        }
+        assert!(repairs.due(now + Duration::from_secs(86_400)).is_empty());
+        // The same gaps aren't retried again, new ones are
+        repairs.schedule("BTC:24h", &gaps, now);
+        assert!(repairs.due(now + REPAIR_DELAYS[0]).is_empty());
+        repairs.schedule("BTC:24h", &[HistoricalGap { start: 20_000.0, end: 30_000.0 }], now);
+        assert_eq!(repairs.due(now + REPAIR_DELAYS[0]).len(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::UNIX_EPOCH;
+use actix_web::{post, web, HttpResponse, Responder};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use crate::types::{AppState, CryptoCurrency};
+use shared::UsdQuote;
+
+// POST /graphql lets clients pick the fields they need, e.g.
+// { coins(limit: 10) { symbol price percentChange24h } }
+// instead of downloading every quote field of every coin from /api/crypto-prices.
+
+pub type CoinSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+// Deep enough for any query over coins and quotes
+const MAX_QUERY_DEPTH: usize = 6;
+
+pub fn build_schema() -> CoinSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription).limit_depth(MAX_QUERY_DEPTH).finish()
+}
+
+// The listings as of the request, with converted quotes applied; resolvers read only this
+struct Listings {
+    coins: Vec<CryptoCurrency>,
+    fetched_at: Option<i64>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Listed coins in listing order, optionally only the given symbols
+    async fn coins(&self, ctx: &Context<'_>, symbols: Option<Vec<String>>, limit: Option<usize>) -> Vec<Coin> {
+        let listings = ctx.data_unchecked::<Listings>();
+        let symbols: Option<Vec<String>> = symbols.map(|symbols| symbols.iter().map(|symbol| symbol.to_uppercase()).collect());
+        listings
+            .coins
+            .iter()
+            .filter(|coin| symbols.as_ref().is_none_or(|symbols| symbols.contains(&coin.symbol.to_uppercase())))
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|coin| Coin(coin.clone()))
+            .collect()
+    }
+
+    async fn coin(&self, ctx: &Context<'_>, symbol: String) -> Option<Coin> {
+        ctx.data_unchecked::<Listings>()
+            .coins
+            .iter()
+            .find(|coin| coin.symbol.eq_ignore_ascii_case(&symbol))
+            .map(|coin| Coin(coin.clone()))
+    }
+
+    // Unix seconds of the last listings fetch; null before the first one
+    async fn fetched_at(&self, ctx: &Context<'_>) -> Option<i64> {
+        ctx.data_unchecked::<Listings>().fetched_at
+    }
+}
+
+pub struct Coin(CryptoCurrency);
+
+// The USD quote is flattened onto the coin; other currencies come from quote(currency:)
+#[Object]
+impl Coin {
+    async fn id(&self) -> i32 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn symbol(&self) -> &str {
+        &self.0.symbol
+    }
+
+    async fn price(&self) -> f64 {
+        self.0.quote.usd.price
+    }
+
+    #[graphql(name = "percentChange1h")]
+    async fn percent_change_1h(&self) -> f64 {
+        self.0.quote.usd.percent_change_1h
+    }
+
+    #[graphql(name = "percentChange24h")]
+    async fn percent_change_24h(&self) -> f64 {
+        self.0.quote.usd.percent_change_24h
+    }
+
+    #[graphql(name = "percentChange7d")]
+    async fn percent_change_7d(&self) -> f64 {
+        self.0.quote.usd.percent_change_7d
+    }
+
+    async fn market_cap(&self) -> f64 {
+        self.0.quote.usd.market_cap
+    }
+
+    #[graphql(name = "volume24h")]
+    async fn volume_24h(&self) -> f64 {
+        self.0.quote.usd.volume_24h
+    }
+
+    async fn last_updated(&self) -> &str {
+        &self.0.quote.usd.last_updated
+    }
+
+    // USD or one of the configured FX_CURRENCIES; null for any other currency
+    async fn quote(&self, #[graphql(default = "USD")] currency: String) -> Option<CoinQuote> {
+        let currency = currency.to_uppercase();
+        let quote = match currency.as_str() {
+            "USD" => &self.0.quote.usd,
+            other => self.0.quote.converted.get(other)?,
+        };
+        Some(CoinQuote::new(currency, quote))
+    }
+}
+
+// Field names keep the REST API's 1h/24h/7d spelling rather than the default 1H/24H/7D
+#[derive(SimpleObject)]
+pub struct CoinQuote {
+    currency: String,
+    price: f64,
+    #[graphql(name = "percentChange1h")]
+    percent_change_1h: f64,
+    #[graphql(name = "percentChange24h")]
+    percent_change_24h: f64,
+    #[graphql(name = "percentChange7d")]
+    percent_change_7d: f64,
+    market_cap: f64,
+    #[graphql(name = "volume24h")]
+    volume_24h: f64,
+}
+
+impl CoinQuote {
+    fn new(currency: String, quote: &UsdQuote) -> Self {
+        CoinQuote {
+            currency,
+            price: quote.price,
+            percent_change_1h: quote.percent_change_1h,
+            percent_change_24h: quote.percent_change_24h,
+            percent_change_7d: quote.percent_change_7d,
+            market_cap: quote.market_cap,
+            volume_24h: quote.volume_24h,
+        }
+    }
+}
+
+fn listings_snapshot(data: &AppState) -> Listings {
+    let mut coins = data.cache.lock().unwrap().clone().unwrap_or_default();
+    data.fx.apply(&mut coins);
+    let fetched_at = (!coins.is_empty())
+        .then(|| data.last_fetch.lock().unwrap().duration_since(UNIX_EPOCH).ok())
+        .flatten()
+        .map(|age| age.as_secs() as i64);
+    Listings { coins, fetched_at }
+}
+
+// Query errors are reported in the response's errors array with a 200, as GraphQL clients expect
+#[post("/graphql")]
+pub async fn graphql_query(schema: web::Data<CoinSchema>, data: web::Data<AppState>, request: web::Json<async_graphql::Request>) -> impl Responder {
+    let request = request.into_inner().data(listings_snapshot(&data));
+    HttpResponse::Ok().json(schema.execute(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Quote;
+
+    fn coin(id: i32, symbol: &str, price: f64) -> CryptoCurrency {
+        let usd = UsdQuote {
+            price,
+            percent_change_1h: 0.1,
+            percent_change_24h: 2.5,
+            percent_change_7d: -3.0,
+            market_cap: price * 1000.0,
+            volume_24h: price * 10.0,
+            last_updated: "2024-01-01T00:00:00Z".to_string(),
+        };
+        let eur = UsdQuote { price: price * 0.5, ..usd.clone() };
+        CryptoCurrency {
+            id,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote { usd, converted: [("EUR".to_string(), eur)].into_iter().collect() },
+        }
+    }
+
+    async fn run(query: &str) -> serde_json::Value {
+        let listings = Listings { coins: vec![coin(1, "BTC", 50000.0), coin(2, "ETH", 3000.0), coin(3, "SOL", 100.0)], fetched_at: Some(1_700_000_000) };
+        let response = build_schema().execute(async_graphql::Request::new(query).data(listings)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        serde_json::to_value(response.data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_queries_return_only_requested_fields() {
+        let data = run("{ coins(limit: 2) { symbol price percentChange24h } }").await;
+        assert_eq!(data, serde_json::json!({ "coins": [
+            { "symbol": "BTC", "price": 50000.0, "percentChange24h": 2.5 },
+            { "symbol": "ETH", "price": 3000.0, "percentChange24h": 2.5 },
+        ] }));
+
+        let data = run(r#"{ coins(symbols: ["sol"]) { id } coin(symbol: "eth") { quote(currency: "eur") { currency price } } fetchedAt }"#).await;
+        assert_eq!(data, serde_json::json!({
+            "coins": [{ "id": 3 }],
+            "coin": { "quote": { "currency": "EUR", "price": 1500.0 } },
+            "fetchedAt": 1_700_000_000,
+        }));
+
+        let data = run(r#"{ coin(symbol: "BTC") { quote(currency: "JPY") { price } } missing: coin(symbol: "XYZ") { id } }"#).await;
+        assert_eq!(data, serde_json::json!({ "coin": { "quote": null }, "missing": null }));
+    }
+}
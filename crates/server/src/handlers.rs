@@ -1,27 +1,176 @@
-use actix_web::{web, Responder, get};
-use log::{info, warn};
-use std::time::{Duration, SystemTime};
-use crate::types::{AppState, ApiResponse, HistoricalQuery};
-use crate::data::fetch_historical_data_server;
-use crate::mqtt::publish_historical_data_to_mqtt;
+use actix_web::{web, HttpRequest, HttpResponse, Responder, delete, get, post, put};
+use serde::Serialize;
+use tracing::{info, warn};
+use std::collections::HashSet;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::types::{
+    AppState, ApiResponse, CoinDetail, CryptoCurrency, FieldsQuery, IndicatorsQuery, MoversQuery, SearchQuery, LogoBundleQuery, LogoQuery, StatsQuery, PricesQuery, HistoricalQuery, HistoricalSinceQuery, HistoricalBatchItem, HistoricalBatchResponse, HistoricalPage, HistoricalDataResult, WatchlistResponse,
+    DEFAULT_HISTORICAL_PAGE_SIZE, MAX_HISTORICAL_PAGE_SIZE,
+};
+use crate::fields::{default_quote_fields, QuoteFields};
+use crate::data::{HISTORICAL_TIMEFRAMES, compute_price_ranges, historical_points_after, load_delisted_history, load_historical_data, load_historical_range, prewarm_history, publish_prefetch_hints, publish_watchlist, revalue_portfolio};
+use crate::portfolio::{value_portfolio, Holding};
+use crate::watchlists::bundle;
+use crate::movers::{rank_movers, MoverWindow, MAX_MOVERS, TRENDING_COUNT};
+use crate::search::{search_coins, DEFAULT_SEARCH_RESULTS, MAX_SEARCH_RESULTS};
+use crate::logos::{etag_for, etag_matches, content_hash, load_logo, LogoError, LogoFormat, DEFAULT_LOGO_SIZE, LOGO_SIZES};
+use crate::indicators::{compute_indicators, parse_indicator_list};
+use crate::stats::{compute_price_stats, parse_window_days, stored_window_points, timeframe_for_window};
+use crate::providers::HistoricalRange;
+use crate::mqtt::{dead_letter_log, publish_historical_data_to_mqtt, replay_dead_letter};
+use crate::http_rate_limit::charge_historical;
+use shared::{CoinCrabError, CoinCrabResult, MIN_SAMPLED_POINTS};
+
+// The fields= query parameter if given, otherwise the server's PAYLOAD_FIELDS
+fn requested_quote_fields(fields: Option<&str>) -> Result<QuoteFields, Box<HttpResponse>> {
+    match fields {
+        Some(list) => QuoteFields::parse(list).map_err(|e| {
+            Box::new(HttpResponse::BadRequest().json(serde_json::json!({ "error": e.message() })))
+        }),
+        None => Ok(default_quote_fields()),
+    }
+}
+
+fn quotes_response<T: Serialize>(fields: &QuoteFields, body: &T) -> HttpResponse {
+    if fields.is_all() {
+        return HttpResponse::Ok().json(body);
+    }
+    match fields.select(body) {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e.message() })),
+    }
+}
+
+// quotes_response with a content-hash ETag, so clients polling with If-None-Match get a 304
+// while nothing changed. no-cache makes caches revalidate rather than serve stale prices.
+fn quotes_response_with_etag<T: Serialize>(req: &HttpRequest, fields: &QuoteFields, body: &T) -> HttpResponse {
+    use actix_web::http::header;
+
+    let body = match fields.select(body).and_then(|value| Ok(serde_json::to_vec(&value)?)) {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": e.message() })),
+    };
+    let etag = etag_for(&content_hash(&body));
+    let cache_control = header::CacheControl(vec![header::CacheDirective::NoCache]);
+    if client_has_etag(req, &etag) {
+        return HttpResponse::NotModified()
+            .append_header((header::ETAG, etag))
+            .append_header(cache_control)
+            .finish();
+    }
+    HttpResponse::Ok()
+        .content_type(header::ContentType::json())
+        .append_header((header::ETAG, etag))
+        .append_header(cache_control)
+        .body(body)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListingSort {
+    MarketCap,
+    Change24h,
+    Volume,
+}
+
+impl ListingSort {
+    fn parse(sort: &str) -> Result<Self, String> {
+        match sort {
+            "market_cap" => Ok(ListingSort::MarketCap),
+            "change_24h" => Ok(ListingSort::Change24h),
+            "volume" => Ok(ListingSort::Volume),
+            other => Err(format!("Unknown sort '{}': expected market_cap, change_24h or volume", other)),
+        }
+    }
+
+    fn key(self, coin: &CryptoCurrency) -> f64 {
+        match self {
+            ListingSort::MarketCap => coin.quote.usd.market_cap,
+            ListingSort::Change24h => coin.quote.usd.percent_change_24h,
+            ListingSort::Volume => coin.quote.usd.volume_24h,
+        }
+    }
+}
+
+// Filters by ?symbols=, sorts by ?sort=&order=, then pages with ?offset=&limit=. Returns the
+// page and how many coins matched before paging.
+fn select_listings(listings: &[CryptoCurrency], query: &PricesQuery) -> Result<(Vec<CryptoCurrency>, usize), String> {
+    let sort = query.sort.as_deref().map(ListingSort::parse).transpose()?;
+    let descending = match query.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(other) => return Err(format!("Unknown order '{}': expected asc or desc", other)),
+    };
+    let symbols: Option<HashSet<String>> = query.symbols.as_deref().map(|list| {
+        list.split(',').map(|symbol| symbol.trim().to_uppercase()).filter(|symbol| !symbol.is_empty()).collect()
+    });
+
+    let mut coins: Vec<&CryptoCurrency> = listings
+        .iter()
+        .filter(|coin| symbols.as_ref().is_none_or(|symbols| symbols.contains(&coin.symbol.to_uppercase())))
+        .collect();
+    if let Some(sort) = sort {
+        // Stable, so ties keep listing order
+        coins.sort_by(|a, b| {
+            let ordering = sort.key(a).total_cmp(&sort.key(b));
+            if descending { ordering.reverse() } else { ordering }
+        });
+    }
+    let total_count = coins.len();
+    let page = coins
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    Ok((page, total_count))
+}
 
 #[get("/api/crypto-prices")]
-pub async fn get_prices(data: web::Data<AppState>) -> impl Responder {
+pub async fn get_prices(req: HttpRequest, query: web::Query<PricesQuery>, data: web::Data<AppState>) -> impl Responder {
+    let fields = match requested_quote_fields(query.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(response) => return *response,
+    };
     let cache = data.cache.lock().unwrap();
     let last_fetch = data.last_fetch.lock().unwrap();
     
     match cache.as_ref() {
-        Some(crypto_data) => {
+        Some(listings) => {
+            let (crypto_data, total_count) = match select_listings(listings, &query) {
+                Ok(selected) => selected,
+                Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": message })),
+            };
             let age = last_fetch.elapsed().unwrap_or(Duration::from_secs(0));
             let cached = age > Duration::from_secs(30);
             
+            // Ranges are opt-in for listings since they add a lookup per coin
+            let ranges = query.include_ranges.unwrap_or(false).then(|| {
+                let history = data.historical_cache.lock().unwrap();
+                let now = SystemTime::now();
+                crypto_data
+                    .iter()
+                    .map(|coin| {
+                        let ranges = compute_price_ranges(&coin.symbol, Some(coin.quote.usd.price), &history, now);
+                        (coin.symbol.clone(), ranges)
+                    })
+                    .collect()
+            });
+            
+            let mut coins = crypto_data;
+            data.fx.apply(&mut coins);
+            let paginated = query.offset.is_some() || query.limit.is_some();
             let response = ApiResponse {
-                data: crypto_data.clone(),
+                data: coins,
                 last_updated: format!("{:?}", *last_fetch),
                 cached,
+                ranges,
+                fx: data.fx.metadata(),
+                total_count: paginated.then_some(total_count),
             };
             
-            web::Json(response)
+            quotes_response_with_etag(&req, &fields, &response)
         }
         None => {
             warn!("No cached data available");
@@ -29,53 +178,570 @@ pub async fn get_prices(data: web::Data<AppState>) -> impl Responder {
                 data: vec![],
                 last_updated: "Never".to_string(),
                 cached: false,
+                ranges: None,
+                fx: None,
+                total_count: None,
             };
-            web::Json(response)
+            HttpResponse::Ok().json(response)
         }
     }
 }
 
+// Biggest gainers and losers of the cached listings over 1h or 24h (the default)
+#[get("/api/movers")]
+pub async fn get_movers(query: web::Query<MoversQuery>, data: web::Data<AppState>) -> impl Responder {
+    let window_name = query.window.as_deref().unwrap_or("24h");
+    let Some(window) = MoverWindow::parse(window_name) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid window '{}'; expected 1h or 24h", window_name)
+        }));
+    };
+    let limit = query.limit.unwrap_or(TRENDING_COUNT).clamp(1, MAX_MOVERS);
+    let listings = data.cache.lock().unwrap().clone().unwrap_or_default();
+    let (gainers, losers) = rank_movers(&listings, window, limit);
+    let fetched_at = (!listings.is_empty())
+        .then(|| data.last_fetch.lock().unwrap().duration_since(UNIX_EPOCH).ok())
+        .flatten()
+        .map(|age| age.as_secs() as i64);
+    HttpResponse::Ok().json(serde_json::json!({
+        "window": window_name,
+        "gainers": gainers,
+        "losers": losers,
+        "fetched_at": fetched_at,
+    }))
+}
+
+// Single coin from the latest listing, with rolling 24h and 52-week ranges
+#[get("/api/coin/{symbol}")]
+pub async fn get_coin_detail(path: web::Path<String>, query: web::Query<FieldsQuery>, data: web::Data<AppState>) -> impl Responder {
+    let fields = match requested_quote_fields(query.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(response) => return *response,
+    };
+    let symbol = path.into_inner();
+    let coin = data.cache.lock().unwrap()
+        .as_ref()
+        .and_then(|coins| coins.iter().find(|c| c.symbol.eq_ignore_ascii_case(&symbol)).cloned());
+    
+    let Some(mut coin) = coin else {
+        warn!("Coin detail requested for unknown symbol: {}", symbol);
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No data for symbol: {}", symbol)
+        }));
+    };
+    
+    let ranges = {
+        let history = data.historical_cache.lock().unwrap();
+        compute_price_ranges(&coin.symbol, Some(coin.quote.usd.price), &history, SystemTime::now())
+    };
+    data.fx.apply(std::slice::from_mut(&mut coin));
+    quotes_response(&fields, &CoinDetail { coin, ranges })
+}
+
+const DEFAULT_STATS_WINDOW: &str = "90d";
+
+// ATR, drawdown and Sharpe-style ratios over stored history. If no cached series covers the
+// window, the covering timeframe is fetched once and cached like any other historical request.
+#[get("/api/stats/{symbol}")]
+pub async fn get_price_stats(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<StatsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let symbol = path.into_inner().to_uppercase();
+    let window = query.window.as_deref().unwrap_or(DEFAULT_STATS_WINDOW);
+    let Some(days) = parse_window_days(window) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid window '{}'; expected 1d to 365d, e.g. 90d", window)
+        }));
+    };
+    let now_ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    
+    let stored = stored_window_points(&symbol, days, &data.historical_cache.lock().unwrap(), now_ts);
+    let points = match stored {
+        Some(points) => points,
+        None => {
+            let timeframe = timeframe_for_window(days);
+            info!("No stored {} history covering {} - fetching {}", symbol, window, timeframe);
+            if let Some(response) = charge_historical(&req, 1) {
+                return response;
+            }
+            let result = load_historical_data(&data, &symbol, timeframe).await;
+            if !result.success {
+                return HttpResponse::NotFound().json(serde_json::json!({
+                    "error": format!("No history available for symbol: {}", symbol)
+                }));
+            }
+            let history = data.historical_cache.lock().unwrap();
+            stored_window_points(&symbol, days, &history, now_ts).unwrap_or_default()
+        }
+    };
+    
+    HttpResponse::Ok().json(compute_price_stats(&symbol, window, &points))
+}
+
 #[get("/health")]
-pub async fn health_check() -> impl Responder {
+pub async fn health_check(data: web::Data<AppState>) -> impl Responder {
     web::Json(serde_json::json!({
         "status": "ok",
-        "timestamp": SystemTime::now()
+        "timestamp": SystemTime::now(),
+        // Payload schema published, and how many clients announced each one they can read
+        "schema_version": shared::PAYLOAD_SCHEMA_VERSION,
+        "client_schema_versions": data.client_capabilities.schema_versions()
     }))
 }
 
 #[get("/api/historical/{symbol}")]
 pub async fn get_historical_data(
+    req: HttpRequest,
     path: web::Path<String>,
     query: web::Query<HistoricalQuery>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    let symbol = path.into_inner();
+    let symbol = data.canonical_symbol(&path.into_inner());
     let timeframe = &query.timeframe;
     
-    info!("Historical data request: {} with timeframe {}", symbol, timeframe);
-    
-    // Implement the actual CMC historical data fetching
-    let result = fetch_historical_data_server(&symbol, &timeframe, &data.api_key, &data.client).await;
+    info!("Historical data request: {} with timeframe {} (page {:?}, page_size {:?}, max_points {:?})",
+          symbol, timeframe, query.page, query.page_size, query.max_points);
+    if query.max_points.is_some_and(|max_points| max_points < MIN_SAMPLED_POINTS) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("max_points must be at least {}", MIN_SAMPLED_POINTS)
+        }));
+    }
+    if let Some(response) = charge_historical(&req, 1) {
+        return response;
+    }
     
-    // Cache the result and publish to MQTT for future requests
-    let cache_key = format!("{}:{}", symbol, timeframe);
-    {
-        let mut hist_cache = data.historical_cache.lock().unwrap();
-        hist_cache.insert(cache_key.clone(), (result.clone(), SystemTime::now()));
+    if let Some(start) = &query.start {
+        let range = match HistoricalRange::parse(start, query.end.as_deref(), query.interval.as_deref(), chrono::Utc::now().timestamp()) {
+            Ok(range) => range,
+            Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.message() })),
+        };
+        info!("Historical range request: {} over {} every {}", symbol, range.label(), range.interval);
+        // Custom windows aren't shared series, so nothing is published to MQTT
+        let result = load_historical_range(&data, &symbol, &range).await;
+        let result = match query.max_points {
+            Some(max_points) => shared::downsample(result, max_points),
+            None => result,
+        };
+        return HttpResponse::Ok().json(paginate_historical(result, query.page, query.page_size));
+    }
+    if timeframe.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Either timeframe or start is required"
+        }));
     }
     
-    // Publish to MQTT so subsequent requests can use MQTT instead of HTTP
-    if result.success {
+    let (result, delisted) = match load_delisted_history(&data, &symbol, timeframe).await {
+        Some(result) => {
+            info!("{} is delisted - serving {} stored points", symbol, result.data.len());
+            (result, true)
+        }
+        None => (load_historical_data(&data, &symbol, timeframe).await, false),
+    };
+    
+    // Publish to MQTT so subsequent requests can use MQTT instead of HTTP. Only the leader
+    // publishes, so instances never race each other on the retained topics.
+    if result.success && !delisted && data.leader.is_leader() {
         info!("Publishing {} {} to MQTT for caching", symbol, timeframe);
-        if let Err(_) = tokio::time::timeout(
+        if tokio::time::timeout(
             Duration::from_millis(1000),
-            publish_historical_data_to_mqtt(&data.mqtt_client, &symbol, &timeframe, &result)
-        ).await {
+            publish_historical_data_to_mqtt(&data.mqtt_client, &symbol, timeframe, &result)
+        ).await.is_err() {
             warn!("MQTT publish timeout for {} {}", symbol, timeframe);
         }
+        publish_prefetch_hints(&data).await;
+    }
+    
+    let result = match query.max_points {
+        Some(max_points) => shared::downsample(result, max_points),
+        None => result,
+    };
+    let mut page = paginate_historical(result, query.page, query.page_size);
+    page.delisted = delisted;
+    HttpResponse::Ok().json(page)
+}
+
+// Most series one batch may ask for
+const MAX_BATCH_SERIES: usize = 20;
+// Series loaded at once; a batch of uncached series shouldn't burst the provider's rate limit
+const BATCH_CONCURRENCY: usize = 2;
+
+// Sparklines for a whole screen in one request. Repeated series are loaded once; cached and
+// stored series are served without a provider call, the rest are fetched a few at a time.
+#[post("/api/historical/batch")]
+pub async fn get_historical_batch(req: HttpRequest, body: web::Json<Vec<HistoricalBatchItem>>, data: web::Data<AppState>) -> impl Responder {
+    let mut seen = HashSet::new();
+    let series: Vec<(String, String)> = body
+        .into_inner()
+        .into_iter()
+        .map(|item| (data.canonical_symbol(item.symbol.trim()), item.timeframe.trim().to_string()))
+        .filter(|key| seen.insert(key.clone()))
+        .collect();
+    if series.iter().any(|(symbol, timeframe)| symbol.is_empty() || timeframe.is_empty()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Every series needs a symbol and a timeframe" }));
+    }
+    if series.len() > MAX_BATCH_SERIES {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("{} series requested; at most {} per batch", series.len(), MAX_BATCH_SERIES)
+        }));
+    }
+    if let Some(response) = charge_historical(&req, series.len()) {
+        return response;
+    }
+    info!("Historical batch request for {} series", series.len());
+
+    let results = futures_util::stream::iter(series)
+        .map(|(symbol, timeframe)| {
+            let data = data.clone();
+            async move {
+                if let Some(result) = load_delisted_history(&data, &symbol, &timeframe).await {
+                    return result;
+                }
+                let result = load_historical_data(&data, &symbol, &timeframe).await;
+                if result.success && data.leader.is_leader() && tokio::time::timeout(
+                    Duration::from_millis(1000),
+                    publish_historical_data_to_mqtt(&data.mqtt_client, &symbol, &timeframe, &result)
+                ).await.is_err() {
+                    warn!("MQTT publish timeout for {} {}", symbol, timeframe);
+                }
+                result
+            }
+        })
+        .buffered(BATCH_CONCURRENCY)
+        .collect()
+        .await;
+    HttpResponse::Ok().json(HistoricalBatchResponse { results })
+}
+
+// Incremental refresh: only the points newer than the client's last timestamp
+#[get("/api/historical/{symbol}/since")]
+pub async fn get_historical_since(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<HistoricalSinceQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let symbol = data.canonical_symbol(&path.into_inner());
+    let timeframe = &query.timeframe;
+    
+    info!("Historical delta request: {} with timeframe {} after {}", symbol, timeframe, query.after);
+    if let Some(response) = charge_historical(&req, 1) {
+        return response;
     }
     
-    web::Json(result)
+    let result = load_historical_data(&data, &symbol, timeframe).await;
+    
+    HttpResponse::Ok().json(historical_points_after(result, query.after))
+}
+
+// Largest period accepted; longer windows would leave nothing of most series
+const MAX_INDICATOR_PERIOD: usize = 200;
+
+#[get("/api/indicators/{symbol}")]
+pub async fn get_indicators(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<IndicatorsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let symbol = data.canonical_symbol(&path.into_inner());
+    let Some(indicators) = parse_indicator_list(query.indicator.as_deref().unwrap_or("")) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Unknown indicator; expected sma, ema, rsi, macd or bollinger"
+        }));
+    };
+    if query.period.is_some_and(|period| !(2..=MAX_INDICATOR_PERIOD).contains(&period)) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("period must be between 2 and {}", MAX_INDICATOR_PERIOD)
+        }));
+    }
+    if let Some(response) = charge_historical(&req, 1) {
+        return response;
+    }
+    
+    let result = load_historical_data(&data, &symbol, &query.timeframe).await;
+    if !result.success {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No history available for symbol: {}", symbol)
+        }));
+    }
+    
+    HttpResponse::Ok().json(compute_indicators(&result, &indicators, query.period))
+}
+
+fn watchlist_response(data: &AppState, device_id: String, symbols: Vec<String>) -> WatchlistResponse {
+    let mut coins = data.cache.lock().unwrap().as_deref().map(|listings| bundle(&symbols, listings)).unwrap_or_default();
+    data.fx.apply(&mut coins);
+    WatchlistResponse { device_id, symbols, data: coins }
+}
+
+// Body is a JSON array of symbols; an empty array removes the watchlist
+#[put("/api/watchlists/{device_id}")]
+pub async fn put_watchlist(
+    path: web::Path<String>,
+    body: web::Json<Vec<String>>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let symbols = match data.watchlists.register(&device_id, body.into_inner()) {
+        Ok(symbols) => symbols,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.message() })),
+    };
+    info!("Registered watchlist of {} symbols for {} over HTTP", symbols.len(), device_id);
+    if !symbols.is_empty() && data.leader.is_leader() {
+        publish_watchlist(&data, &device_id, &symbols).await;
+    }
+    HttpResponse::Ok().json(watchlist_response(&data, device_id, symbols))
+}
+
+#[get("/api/watchlists/{device_id}")]
+pub async fn get_watchlist(path: web::Path<String>, query: web::Query<FieldsQuery>, data: web::Data<AppState>) -> impl Responder {
+    let fields = match requested_quote_fields(query.fields.as_deref()) {
+        Ok(fields) => fields,
+        Err(response) => return *response,
+    };
+    let device_id = path.into_inner();
+    let Some(symbols) = data.watchlists.symbols(&device_id) else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No watchlist registered for {}", device_id)
+        }));
+    };
+    quotes_response(&fields, &watchlist_response(&data, device_id, symbols))
+}
+
+#[get("/api/portfolios/{portfolio_id}")]
+pub async fn get_portfolio(path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let portfolio_id = path.into_inner();
+    let Some(holdings) = data.portfolios.holdings(&portfolio_id) else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No portfolio: {}", portfolio_id)
+        }));
+    };
+    let listings = data.cache.lock().unwrap().clone().unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    HttpResponse::Ok().json(value_portfolio(&portfolio_id, &holdings, &listings, now))
+}
+
+// Adds a holding, or replaces the one with the same symbol; returns the new valuation
+#[post("/api/portfolios/{portfolio_id}/holdings")]
+pub async fn add_portfolio_holding(
+    path: web::Path<String>,
+    body: web::Json<Holding>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let portfolio_id = path.into_inner();
+    if let Err(e) = data.portfolios.upsert(&portfolio_id, body.into_inner()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.message() }));
+    }
+    if let Err(e) = data.portfolios.persist().await {
+        warn!("Failed to save portfolios: {}", e);
+    }
+    HttpResponse::Ok().json(revalue_portfolio(&data, &portfolio_id).await)
+}
+
+#[delete("/api/portfolios/{portfolio_id}/holdings/{symbol}")]
+pub async fn remove_portfolio_holding(path: web::Path<(String, String)>, data: web::Data<AppState>) -> impl Responder {
+    let (portfolio_id, symbol) = path.into_inner();
+    if !data.portfolios.remove(&portfolio_id, &symbol) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("{} holds no {}", portfolio_id, symbol)
+        }));
+    }
+    if let Err(e) = data.portfolios.persist().await {
+        warn!("Failed to save portfolios: {}", e);
+    }
+    match revalue_portfolio(&data, &portfolio_id).await {
+        Some(valuation) => HttpResponse::Ok().json(valuation),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
+
+fn pinned_response(data: &AppState) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "symbols": data.pinned.symbols() }))
+}
+
+#[get("/api/pinned")]
+pub async fn get_pinned(data: web::Data<AppState>) -> impl Responder {
+    pinned_response(&data)
+}
+
+// A coin outside the top 100 is quoted from the next listings fetch on; its history is
+// loaded right away
+#[put("/api/pinned/{symbol}")]
+pub async fn pin_symbol(req: HttpRequest, path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let symbol = data.canonical_symbol(path.trim());
+    let unknown = {
+        let identities = data.identity_map.lock().unwrap();
+        identities.len() > 0 && identities.by_symbol(&symbol).is_none()
+    };
+    if unknown {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": format!("Unknown symbol: {}", symbol) }));
+    }
+    // A new pin loads every timeframe of the coin
+    if !data.pinned.symbols().contains(&symbol) {
+        if let Some(response) = charge_historical(&req, HISTORICAL_TIMEFRAMES.len()) {
+            return response;
+        }
+    }
+    match data.pinned.pin(&symbol) {
+        Ok(false) => {}
+        Ok(true) => {
+            if let Err(e) = data.pinned.persist().await {
+                warn!("Failed to save pinned symbols: {}", e);
+            }
+            if data.leader.is_leader() {
+                let state = data.clone();
+                tokio::spawn(async move { prewarm_history(&state, &[symbol]).await });
+            }
+        }
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.message() })),
+    }
+    pinned_response(&data)
+}
+
+#[delete("/api/pinned/{symbol}")]
+pub async fn unpin_symbol(path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let symbol = data.canonical_symbol(path.trim());
+    match data.pinned.unpin(&symbol) {
+        Ok(true) => {
+            if let Err(e) = data.pinned.persist().await {
+                warn!("Failed to save pinned symbols: {}", e);
+            }
+            pinned_response(&data)
+        }
+        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({ "error": format!("{} is not pinned", symbol) })),
+        Err(e) => HttpResponse::Conflict().json(serde_json::json!({ "error": e.message() })),
+    }
+}
+
+// The dead-letter admin endpoints; only registered when the REST API requires authentication
+pub fn dead_letter_admin(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_dead_letters)
+        .service(replay_dead_letters)
+        .service(replay_dead_letter_by_id)
+        .service(delete_dead_letter)
+        .service(clear_dead_letters);
+}
+
+fn dead_letters_unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({ "error": "Dead-letter log is not enabled" }))
+}
+
+#[get("/api/admin/dead-letters")]
+pub async fn list_dead_letters() -> impl Responder {
+    match dead_letter_log() {
+        Some(log) => HttpResponse::Ok().json(log.list()),
+        None => dead_letters_unavailable(),
+    }
+}
+
+// Replays every dead letter once; the ones that go through are removed from the log
+#[post("/api/admin/dead-letters/replay")]
+pub async fn replay_dead_letters(data: web::Data<AppState>) -> impl Responder {
+    let Some(log) = dead_letter_log() else {
+        return dead_letters_unavailable();
+    };
+    let mut replayed = Vec::new();
+    let mut failed = Vec::new();
+    for letter in log.list() {
+        match replay_dead_letter(&data.mqtt_client, &letter).await {
+            Ok(()) => {
+                log.remove(letter.id);
+                replayed.push(letter.id);
+            }
+            Err(e) => failed.push(serde_json::json!({ "id": letter.id, "error": e.message() })),
+        }
+    }
+    if let Err(e) = log.persist().await {
+        warn!("Failed to save dead-letter log: {}", e);
+    }
+    info!("Replayed {} dead letters, {} failed", replayed.len(), failed.len());
+    HttpResponse::Ok().json(serde_json::json!({ "replayed": replayed, "failed": failed }))
+}
+
+#[post("/api/admin/dead-letters/{id}/replay")]
+pub async fn replay_dead_letter_by_id(path: web::Path<u64>, data: web::Data<AppState>) -> impl Responder {
+    let Some(log) = dead_letter_log() else {
+        return dead_letters_unavailable();
+    };
+    let id = path.into_inner();
+    let Some(letter) = log.get(id) else {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": format!("No dead letter {}", id) }));
+    };
+    if let Err(e) = replay_dead_letter(&data.mqtt_client, &letter).await {
+        return HttpResponse::BadGateway().json(serde_json::json!({ "error": e.message() }));
+    }
+    log.remove(id);
+    if let Err(e) = log.persist().await {
+        warn!("Failed to save dead-letter log: {}", e);
+    }
+    HttpResponse::NoContent().finish()
+}
+
+#[delete("/api/admin/dead-letters")]
+pub async fn clear_dead_letters() -> impl Responder {
+    let Some(log) = dead_letter_log() else {
+        return dead_letters_unavailable();
+    };
+    let cleared = log.clear();
+    if let Err(e) = log.persist().await {
+        warn!("Failed to save dead-letter log: {}", e);
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "cleared": cleared }))
+}
+
+#[delete("/api/admin/dead-letters/{id}")]
+pub async fn delete_dead_letter(path: web::Path<u64>) -> impl Responder {
+    let Some(log) = dead_letter_log() else {
+        return dead_letters_unavailable();
+    };
+    let id = path.into_inner();
+    if !log.remove(id) {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": format!("No dead letter {}", id) }));
+    }
+    if let Err(e) = log.persist().await {
+        warn!("Failed to save dead-letter log: {}", e);
+    }
+    HttpResponse::NoContent().finish()
+}
+
+// Slice a historical result down to the requested page (1-based), capping the page size.
+// Without page or page_size a series up to MAX_HISTORICAL_PAGE_SIZE points comes back whole, as
+// it did before pagination existed; longer ones are cut to their first page, and total_pages
+// tells the caller to page through the rest.
+fn paginate_historical(
+    mut result: HistoricalDataResult,
+    page: Option<usize>,
+    page_size: Option<usize>,
+) -> HistoricalPage {
+    let total_count = result.data.len();
+    let (page, page_size) = match (page, page_size) {
+        (None, None) => (1, total_count.clamp(1, MAX_HISTORICAL_PAGE_SIZE)),
+        (page, page_size) => (
+            page.unwrap_or(1).max(1),
+            page_size.unwrap_or(DEFAULT_HISTORICAL_PAGE_SIZE).clamp(1, MAX_HISTORICAL_PAGE_SIZE),
+        ),
+    };
+    let total_pages = total_count.div_ceil(page_size);
+
+    let start = (page - 1).saturating_mul(page_size).min(total_count);
+    let end = start.saturating_add(page_size).min(total_count);
+    result.data = result.data.drain(start..end).collect();
+    // Bucket statistics run parallel to the points
+    if !result.buckets.is_empty() {
+        result.buckets = result.buckets.drain(start..end).collect();
+    }
+
+    HistoricalPage {
+        result,
+        page,
+        page_size,
+        total_count,
+        total_pages,
+        delisted: false,
+    }
 }
 
 #[get("/api/cmc-mapping")]
@@ -84,90 +750,230 @@ pub async fn get_cmc_mapping(data: web::Data<AppState>) -> impl Responder {
     web::Json(mapping.clone())
 }
 
+// Every coin in the cross-provider identity map (CMC id, slug, CoinGecko id per symbol)
+#[get("/api/coin-identities")]
+pub async fn get_coin_identities(data: web::Data<AppState>) -> impl Responder {
+    let identities = data.identity_map.lock().unwrap();
+    web::Json(identities.identities().into_iter().cloned().collect::<Vec<_>>())
+}
+
+// Coins whose symbol, name or slug match q by exact match, prefix, substring or a typo or two,
+// best match first
+#[get("/api/search")]
+pub async fn search_symbols(query: web::Query<SearchQuery>, data: web::Data<AppState>) -> impl Responder {
+    if query.q.trim().is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "q is required" }));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_RESULTS).clamp(1, MAX_SEARCH_RESULTS);
+    let listings = data.cache.lock().unwrap().clone().unwrap_or_default();
+    let matches = search_coins(&data.identity_map.lock().unwrap(), &listings, &query.q, limit);
+    HttpResponse::Ok().json(serde_json::json!({ "query": query.q, "results": matches }))
+}
+
+// One coin looked up by symbol, CMC id or CoinGecko id
+#[get("/api/coin-identities/{key}")]
+pub async fn get_coin_identity(path: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let key = path.into_inner();
+    match data.identity_map.lock().unwrap().resolve(&key) {
+        Some(identity) => HttpResponse::Ok().json(identity),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No coin identity for: {}", key)
+        })),
+    }
+}
+
+const MAX_BUNDLE_SYMBOLS: usize = 200;
+// Logos fetched from the CDN at the same time while building a bundle
+const BUNDLE_FETCH_CONCURRENCY: usize = 8;
+
+// Whether the client already holds the representation identified by `etag`
+fn client_has_etag(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(actix_web::http::header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, etag))
+}
+
+// `?size=` picks one of LOGO_SIZES (64 by default) and clients listing image/webp in Accept
+// get WebP, falling back to PNG for coins without a WebP logo. Each variant is cached separately.
 #[get("/api/logo/{symbol}")]
 pub async fn get_crypto_logo(
+    req: HttpRequest,
     path: web::Path<String>,
+    query: web::Query<LogoQuery>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     use actix_web::{HttpResponse, http::header};
-    use std::time::{Duration, SystemTime};
     
     let symbol = path.into_inner().to_uppercase();
+    let size = query.size.unwrap_or(DEFAULT_LOGO_SIZE);
+    if !LOGO_SIZES.contains(&size) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unsupported logo size {}; expected one of {:?}", size, LOGO_SIZES)
+        }));
+    }
+    let accept = req.headers().get(header::ACCEPT).and_then(|value| value.to_str().ok());
+    let mut format = LogoFormat::negotiate(accept);
+    let mut result = load_logo(&data, &symbol, size, format).await;
+    if format == LogoFormat::Webp && matches!(result, Err(LogoError::NoMapping | LogoError::NotFound)) {
+        format = LogoFormat::Png;
+        result = load_logo(&data, &symbol, size, format).await;
+    }
     
-    // Check cache first (with 24 hour expiry)
-    {
-        let cache = data.logo_cache.lock().unwrap();
-        if let Some((image_data, cached_time)) = cache.get(&symbol) {
-            if cached_time.elapsed().unwrap_or(Duration::from_secs(u64::MAX)) < Duration::from_secs(24 * 60 * 60) {
-                return HttpResponse::Ok()
-                    .content_type("image/png")
-                    .append_header(header::CacheControl(vec![
-                        header::CacheDirective::Public,
-                        header::CacheDirective::MaxAge(86400), // 24 hours
-                    ]))
-                    .body(image_data.clone());
+    match result {
+        Ok((hash, image_bytes)) => {
+            // The content hash is a strong validator: same hash, same bytes
+            let etag = etag_for(&hash);
+            let not_modified = client_has_etag(&req, &etag);
+            let mut response = if not_modified {
+                HttpResponse::NotModified()
+            } else {
+                HttpResponse::Ok()
+            };
+            response
+                .content_type(format.content_type())
+                .append_header((header::ETAG, etag))
+                .append_header((header::VARY, "Accept"))
+                .append_header(header::CacheControl(vec![
+                    header::CacheDirective::Public,
+                    header::CacheDirective::MaxAge(86400), // 24 hours
+                ]));
+            if not_modified {
+                response.finish()
+            } else {
+                response.body(image_bytes.as_ref().clone())
             }
         }
+        Err(LogoError::NoMapping) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No logo mapping found for symbol: {}", symbol)
+        })),
+        Err(LogoError::NotFound) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Logo not found"
+        })),
+        Err(LogoError::ReadFailed) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to read image data"
+        })),
+        Err(LogoError::FetchFailed) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to fetch logo"
+        })),
     }
+}
+
+// Upper-cased, de-duplicated symbols in request order
+fn parse_symbol_list(symbols: &str) -> Vec<String> {
+    let mut parsed: Vec<String> = Vec::new();
+    for symbol in symbols.split(',').map(|s| s.trim().to_uppercase()).filter(|s| !s.is_empty()) {
+        if !parsed.contains(&symbol) {
+            parsed.push(symbol);
+        }
+    }
+    parsed
+}
+
+// Store-only zip with one "{SYMBOL}.png" entry per logo
+fn build_logo_zip(logos: &[(String, String, Arc<Vec<u8>>)]) -> CoinCrabResult<Vec<u8>> {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
     
-    // Get CMC ID for symbol
-    let cmc_id = {
-        let mapping = data.cmc_mapping.lock().unwrap();
-        match mapping.get(&symbol).copied() {
-            Some(id) => id,
-            None => {
-                warn!("No CMC mapping found for symbol: {}", symbol);
-                return HttpResponse::NotFound().json(serde_json::json!({
-                    "error": format!("No logo mapping found for symbol: {}", symbol)
-                }));
+    let zip_error = |e: zip::result::ZipError| CoinCrabError::Io(format!("Failed to build logo bundle: {}", e));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (symbol, _, bytes) in logos {
+        writer.start_file(format!("{}.png", symbol), options).map_err(zip_error)?;
+        writer.write_all(bytes)?;
+    }
+    Ok(writer.finish().map_err(zip_error)?.into_inner())
+}
+
+// All requested logos in one zip, so the app can load its initial icon set in a single request.
+// Symbols without a logo are listed in the X-Missing-Symbols header.
+#[get("/api/logos/bundle")]
+pub async fn get_logo_bundle(
+    req: HttpRequest,
+    query: web::Query<LogoBundleQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    use actix_web::{HttpResponse, http::header};
+    
+    let size = query.size.unwrap_or(DEFAULT_LOGO_SIZE);
+    if !LOGO_SIZES.contains(&size) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unsupported logo size {}; expected one of {:?}", size, LOGO_SIZES)
+        }));
+    }
+    let symbols = parse_symbol_list(&query.symbols);
+    if symbols.is_empty() || symbols.len() > MAX_BUNDLE_SYMBOLS {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Expected between 1 and {} symbols", MAX_BUNDLE_SYMBOLS)
+        }));
+    }
+    
+    let mut logos = Vec::new();
+    let mut missing = Vec::new();
+    for chunk in symbols.chunks(BUNDLE_FETCH_CONCURRENCY) {
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, symbol) in chunk.iter().enumerate() {
+            let data = data.clone();
+            let symbol = symbol.clone();
+            tasks.spawn(async move {
+                let result = load_logo(&data, &symbol, size, LogoFormat::Png).await;
+                (index, symbol, result)
+            });
+        }
+        let mut results = Vec::new();
+        while let Some(Ok(result)) = tasks.join_next().await {
+            results.push(result);
+        }
+        // Keep the bundle in request order
+        results.sort_by_key(|(index, _, _)| *index);
+        for (_, symbol, result) in results {
+            match result {
+                Ok((hash, bytes)) => logos.push((symbol, hash, bytes)),
+                Err(_) => missing.push(symbol),
             }
         }
-    };
+    }
     
-    // Fetch from CoinMarketCap
-    let logo_url = format!("https://s2.coinmarketcap.com/static/img/coins/64x64/{}.png", cmc_id);
+    if logos.is_empty() {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No logos found for the requested symbols"
+        }));
+    }
     
-    match data.client.get(&logo_url).send().await {
-        Ok(response) if response.status().is_success() => {
-            match response.bytes().await {
-                Ok(image_data) => {
-                    let image_bytes = image_data.to_vec();
-                    
-                    // Cache the image
-                    {
-                        let mut cache = data.logo_cache.lock().unwrap();
-                        cache.insert(symbol, (image_bytes.clone(), SystemTime::now()));
-                    }
-                    
-                    HttpResponse::Ok()
-                        .content_type("image/png")
-                        .append_header(header::CacheControl(vec![
-                            header::CacheDirective::Public,
-                            header::CacheDirective::MaxAge(86400), // 24 hours
-                        ]))
-                        .body(image_bytes)
-                },
-                Err(e) => {
-                    warn!("Failed to read logo image bytes for {}: {}", symbol, e);
-                    HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": "Failed to read image data"
-                    }))
-                }
-            }
-        },
-        Ok(response) => {
-            warn!("CMC logo request failed with status {} for symbol: {}", response.status(), symbol);
-            HttpResponse::NotFound().json(serde_json::json!({
-                "error": "Logo not found"
-            }))
-        },
+    // Same symbols at the same content hashes produce the same archive, so the bundle gets a
+    // strong ETag without having to build the zip first
+    let manifest: Vec<String> = logos.iter().map(|(symbol, hash, _)| format!("{}:{}", symbol, hash)).collect();
+    let etag = etag_for(&content_hash(format!("{}|{}", size, manifest.join(",")).as_bytes()));
+    if client_has_etag(&req, &etag) {
+        return HttpResponse::NotModified()
+            .append_header((header::ETAG, etag))
+            .finish();
+    }
+    
+    let bundle = match build_logo_zip(&logos) {
+        Ok(bundle) => bundle,
         Err(e) => {
-            warn!("Failed to fetch logo for symbol {}: {}", symbol, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch logo"
-            }))
+            warn!("{}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to build logo bundle"
+            }));
         }
+    };
+    info!("Serving logo bundle with {} logos ({} missing) at {}px", logos.len(), missing.len(), size);
+    
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("application/zip")
+        .append_header((header::ETAG, etag))
+        .append_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"logos-{}.zip\"", size)))
+        .append_header(header::CacheControl(vec![
+            header::CacheDirective::Public,
+            header::CacheDirective::MaxAge(86400), // 24 hours
+        ]));
+    if !missing.is_empty() {
+        response.append_header(("X-Missing-Symbols", missing.join(",")));
     }
+    response.body(bundle)
 }
 
 #[cfg(test)]
@@ -178,6 +984,20 @@ mod tests {
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
     use shared::{CryptoCurrency, Quote, UsdQuote};
+    use crate::alerts::AlertEngine;
+    use crate::watchlists::WatchlistRegistry;
+    use crate::fx::FxRates;
+    use crate::portfolio::PortfolioStore;
+    use crate::pinned::PinnedSymbols;
+    use crate::config::{AlertsConfig, FxConfig, PortfolioConfig, PinnedConfig, HttpClientConfig, ReloadableConfig};
+    use crate::http_client::RetryPolicy;
+    use crate::leader::LeaderElection;
+    use crate::logos::LogoCache;
+    use crate::identity::IdentityMap;
+    use crate::persistence::WriteBehindCache;
+    use crate::storage::Storage;
+    use crate::cluster_cache::ClusterCache;
+    use crate::providers::{CoinMarketCapProvider, MarketData};
 
     fn create_test_app_state() -> web::Data<AppState> {
         let test_crypto = CryptoCurrency {
@@ -194,6 +1014,7 @@ mod tests {
                     volume_24h: 50000000000.0,
                     last_updated: "2024-01-01T00:00:00Z".to_string(),
                 },
+                converted: Default::default(),
             },
         };
 
@@ -207,15 +1028,69 @@ mod tests {
             cache: Arc::new(Mutex::new(Some(vec![test_crypto]))),
             last_fetch: Arc::new(Mutex::new(SystemTime::now())),
             client: Client::new(),
-            api_key: "test_api_key".to_string(),
+            retry_policy: RetryPolicy::from_config(&HttpClientConfig::default()),
+            market_data: Arc::new(MarketData::new(
+                Arc::new(CoinMarketCapProvider::new(Client::new(), RetryPolicy::from_config(&HttpClientConfig::default()), "test_api_key".to_string())),
+                None,
+                Duration::from_secs(300),
+            )),
             mqtt_client: Arc::new(mqtt_client),
             historical_cache: Arc::new(Mutex::new(HashMap::new())),
-            update_interval_seconds: 300,
+            settings: tokio::sync::watch::channel(ReloadableConfig { update_interval_seconds: 300, ..Default::default() }).1,
             cmc_mapping: Arc::new(Mutex::new(HashMap::new())),
-            logo_cache: Arc::new(Mutex::new(HashMap::new())),
+            identity_map: Arc::new(Mutex::new(IdentityMap::new())),
+        logo_cache: Arc::new(Mutex::new(LogoCache::new())),
+            leader: Arc::new(LeaderElection::standalone()),
+            persistence: Arc::new(WriteBehindCache::disabled()),
+            storage: Arc::new(Storage::disabled()),
+            cluster_cache: Arc::new(ClusterCache::disabled()),
+            alerts: Arc::new(AlertEngine::new(&AlertsConfig::default())),
+            watchlists: Arc::new(WatchlistRegistry::new(100)),
+            fx: Arc::new(FxRates::new(&FxConfig::default())),
+            portfolios: Arc::new(PortfolioStore::new(&PortfolioConfig::default())),
+            pinned: Arc::new(PinnedSymbols::new(&PinnedConfig::default())),
+            historical_in_flight: Arc::new(crate::coalesce::InFlight::new()),
+            gap_repairs: Arc::new(crate::gaps::GapRepairs::default()),
+            price_screen: Arc::new(crate::anomalies::PriceScreen::new(crate::config::AnomalyConfig::default())),
+            client_capabilities: Arc::new(crate::mqtt::capabilities::ClientCapabilityRegistry::new()),
         })
     }
 
+    #[test]
+    async fn test_search_symbols() {
+        let state = create_test_app_state();
+        state.identity_map.lock().unwrap().record_cmc(1, "BTC", "Bitcoin", "bitcoin");
+        state.identity_map.lock().unwrap().record_cmc(1027, "ETH", "Ethereum", "ethereum");
+        let app = test::init_service(actix_web::App::new().app_data(state).service(search_symbols)).await;
+
+        let req = test::TestRequest::get().uri("/api/search?q=bit").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["results"], serde_json::json!([
+            { "symbol": "BTC", "name": "Bitcoin", "slug": "bitcoin", "cmc_id": 1, "rank": 1, "match_kind": "name_prefix" }
+        ]));
+
+        let req = test::TestRequest::get().uri("/api/search?q=%20").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    async fn test_get_movers() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_movers)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/movers").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["window"], "24h");
+        assert_eq!(body["gainers"][0]["symbol"], "BTC");
+        assert_eq!(body["losers"], serde_json::json!([]));
+
+        let req = test::TestRequest::get().uri("/api/movers?window=7d").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
     #[test]
     async fn test_get_prices_handler() {
         // Test the handler function logic directly without actix-web setup
@@ -228,6 +1103,332 @@ mod tests {
         assert_eq!(cache.as_ref().unwrap()[0].symbol, "BTC");
     }
 
+    #[test]
+    async fn test_select_listings() {
+        let coin = |symbol: &str, market_cap: f64, change: f64, volume: f64| CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: change,
+                    percent_change_7d: 0.0,
+                    market_cap,
+                    volume_24h: volume,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        };
+        let listings = vec![coin("BTC", 300.0, 1.0, 20.0), coin("ETH", 200.0, -4.0, 30.0), coin("SOL", 100.0, 9.0, 10.0)];
+        let select = |uri: &str| {
+            let query = web::Query::<PricesQuery>::from_query(uri).unwrap();
+            select_listings(&listings, &query).map(|(coins, total)| (coins.into_iter().map(|coin| coin.symbol).collect::<Vec<_>>(), total))
+        };
+
+        assert_eq!(select("").unwrap(), (vec!["BTC".to_string(), "ETH".to_string(), "SOL".to_string()], 3));
+        assert_eq!(select("sort=change_24h&limit=2").unwrap(), (vec!["SOL".to_string(), "BTC".to_string()], 3));
+        assert_eq!(select("sort=volume&order=asc&offset=1").unwrap(), (vec!["BTC".to_string(), "ETH".to_string()], 3));
+        assert_eq!(select("symbols=sol,eth&sort=market_cap&limit=1").unwrap(), (vec!["ETH".to_string()], 2));
+        assert_eq!(select("offset=5").unwrap(), (vec![], 3));
+        assert!(select("sort=price").is_err());
+        assert!(select("order=up").is_err());
+
+        let app = test::init_service(actix_web::App::new().app_data(create_test_app_state()).service(get_prices)).await;
+        let req = test::TestRequest::get().uri("/api/crypto-prices?limit=10").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total_count"], 1);
+        let req = test::TestRequest::get().uri("/api/crypto-prices?sort=name").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    async fn test_get_prices_etag() {
+        use actix_web::http::{header, StatusCode};
+
+        let app = test::init_service(actix_web::App::new().app_data(create_test_app_state()).service(get_prices)).await;
+        let req = test::TestRequest::get().uri("/api/crypto-prices").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+        let req = test::TestRequest::get()
+            .uri("/api/crypto-prices")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), etag.as_str());
+
+        // A different selection is a different representation
+        let req = test::TestRequest::get()
+            .uri("/api/crypto-prices?fields=price")
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[test]
+    async fn test_get_coin_detail() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_coin_detail)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/coin/btc").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["symbol"], "BTC");
+        assert!(body["high_24h"].is_null());
+
+        let req = test::TestRequest::get().uri("/api/coin/NOPE").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::get().uri("/api/coin/BTC?fields=price,percent_change_24h").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let usd = body["quote"]["USD"].as_object().unwrap();
+        assert_eq!(usd.len(), 2);
+        assert_eq!(usd["percent_change_24h"], 2.5);
+
+        let req = test::TestRequest::get().uri("/api/coin/BTC?fields=price,volume").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    async fn test_get_price_stats_from_stored_history() {
+        let state = create_test_app_state();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
+        let points = (0..30)
+            .map(|i| shared::HistoricalDataPoint { timestamp: now - (29 - i) as f64 * 86_400.0, price: 100.0 + i as f64, volume: None })
+            .collect();
+        let series = HistoricalDataResult { success: true, data: points, error: None, symbol: Some("BTC".to_string()), timeframe: Some("30d".to_string()), gaps: Vec::new(), buckets: Vec::new(), schema_version: shared::PAYLOAD_SCHEMA_VERSION };
+        state.historical_cache.lock().unwrap().insert("BTC:30d".to_string(), (series, SystemTime::now()));
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_price_stats)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/stats/btc?window=30d").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["symbol"], "BTC");
+        assert_eq!(body["points"], 30);
+        assert_eq!(body["max_drawdown"], 0.0);
+        assert!((body["atr"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+
+        let req = test::TestRequest::get().uri("/api/stats/BTC?window=2y").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    async fn test_get_indicators_from_cached_series() {
+        let state = create_test_app_state();
+        state.historical_cache.lock().unwrap().insert("BTC:30d".to_string(), (create_test_series(30), SystemTime::now()));
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_indicators)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/indicators/btc?timeframe=30d&indicator=sma,rsi&period=10").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["sma"].as_array().unwrap().len(), 21);
+        assert_eq!(body["rsi"].as_array().unwrap().len(), 20);
+        assert!(body.get("macd").is_none());
+
+        for uri in ["/api/indicators/BTC?timeframe=30d&indicator=vwap", "/api/indicators/BTC?timeframe=30d&period=1"] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    async fn test_watchlist_round_trip() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_watchlist)
+                .service(put_watchlist)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/watchlists/phone").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::put().uri("/api/watchlists/phone").set_json(["btc", "NOPE"]).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["symbols"], serde_json::json!(["BTC", "NOPE"]));
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+
+        let req = test::TestRequest::get().uri("/api/watchlists/phone?fields=price").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"][0]["quote"]["USD"].as_object().unwrap().len(), 1);
+    }
+
+    #[test]
+    async fn test_portfolio_holdings_and_valuation() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_portfolio)
+                .service(add_portfolio_holding)
+                .service(remove_portfolio_holding)
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/portfolios/me/holdings")
+            .set_json(serde_json::json!({ "symbol": "btc", "amount": 0.5, "cost_basis": 20000.0 }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total_value"], 25000.0);
+        assert_eq!(body["profit_loss"], 5000.0);
+        assert_eq!(body["holdings"][0]["allocation_percent"], 100.0);
+
+        let req = test::TestRequest::get().uri("/api/portfolios/me").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["profit_loss_percent"], 25.0);
+
+        let req = test::TestRequest::post()
+            .uri("/api/portfolios/me/holdings")
+            .set_json(serde_json::json!({ "symbol": "ETH", "amount": -1.0, "cost_basis": 0.0 }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::delete().uri("/api/portfolios/me/holdings/BTC").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::NO_CONTENT);
+        let req = test::TestRequest::get().uri("/api/portfolios/me").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    async fn test_get_logo_bundle_from_cache() {
+        let state = create_test_app_state();
+        {
+            let mut cache = state.logo_cache.lock().unwrap();
+            cache.insert("BTC", b"btc-64".to_vec(), None, None, SystemTime::now());
+            cache.insert("ETH", b"eth-64".to_vec(), None, None, SystemTime::now());
+            cache.insert("BTC@128", b"btc-128".to_vec(), None, None, SystemTime::now());
+        }
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_logo_bundle)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/logos/bundle?symbols=eth,btc,NOPE,eth").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/zip");
+        assert_eq!(resp.headers().get("x-missing-symbols").unwrap(), "NOPE");
+        let body = test::read_body(resp).await;
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.by_index(0).unwrap().name(), "ETH.png");
+        let mut btc = Vec::new();
+        std::io::Read::read_to_end(&mut archive.by_name("BTC.png").unwrap(), &mut btc).unwrap();
+        assert_eq!(btc, b"btc-64");
+
+        let req = test::TestRequest::get().uri("/api/logos/bundle?symbols=BTC&size=128").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+        let mut btc = Vec::new();
+        std::io::Read::read_to_end(&mut archive.by_name("BTC.png").unwrap(), &mut btc).unwrap();
+        assert_eq!(btc, b"btc-128");
+    }
+
+    #[test]
+    async fn test_get_crypto_logo_etag() {
+        let state = create_test_app_state();
+        state.logo_cache.lock().unwrap().insert("BTC", b"btc-64".to_vec(), None, None, SystemTime::now());
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_crypto_logo)
+                .service(get_logo_bundle)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/logo/btc").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let etag = resp.headers().get("etag").unwrap().to_str().unwrap().to_string();
+        assert_eq!(etag, etag_for(&content_hash(b"btc-64")));
+
+        // A client that already has this image gets an empty 304
+        let req = test::TestRequest::get()
+            .uri("/api/logo/BTC")
+            .insert_header(("If-None-Match", etag.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_MODIFIED);
+        assert!(test::read_body(resp).await.is_empty());
+
+        // Bundles are validated the same way
+        let req = test::TestRequest::get().uri("/api/logos/bundle?symbols=BTC").to_request();
+        let resp = test::call_service(&app, req).await;
+        let bundle_etag = resp.headers().get("etag").unwrap().to_str().unwrap().to_string();
+        assert_ne!(bundle_etag, etag);
+        let req = test::TestRequest::get()
+            .uri("/api/logos/bundle?symbols=BTC")
+            .insert_header(("If-None-Match", bundle_etag))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    async fn test_get_crypto_logo_sizes_and_formats() {
+        let state = create_test_app_state();
+        {
+            let mut cache = state.logo_cache.lock().unwrap();
+            cache.insert("BTC@128", b"btc-128".to_vec(), None, None, SystemTime::now());
+            cache.insert("BTC@128.webp", b"btc-128-webp".to_vec(), None, None, SystemTime::now());
+        }
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_crypto_logo)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/logo/btc?size=128").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), "image/png");
+        assert_eq!(resp.headers().get("vary").unwrap(), "Accept");
+        assert_eq!(test::read_body(resp).await.as_ref(), b"btc-128");
+
+        let req = test::TestRequest::get()
+            .uri("/api/logo/btc?size=128")
+            .insert_header(("Accept", "image/avif,image/webp,*/*;q=0.8"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), "image/webp");
+        assert_eq!(resp.headers().get("etag").unwrap().to_str().unwrap(), etag_for(&content_hash(b"btc-128-webp")));
+        assert_eq!(test::read_body(resp).await.as_ref(), b"btc-128-webp");
+
+        let req = test::TestRequest::get().uri("/api/logo/btc?size=48").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    async fn test_get_logo_bundle_rejects_bad_requests() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_logo_bundle)
+        ).await;
+
+        for uri in ["/api/logos/bundle?symbols=BTC&size=50", "/api/logos/bundle?symbols=,,"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        assert_eq!(parse_symbol_list(" btc, ,ETH,btc "), vec!["BTC", "ETH"]);
+    }
+
     #[test]
     async fn test_health_check_response() {
         // Test health check creates proper JSON structure
@@ -287,6 +1488,7 @@ mod tests {
                     volume_24h: 50000000000.0,
                     last_updated: "2024-01-01T00:00:00Z".to_string(),
                 },
+                converted: Default::default(),
             },
         };
 
@@ -294,6 +1496,9 @@ mod tests {
             data: vec![test_crypto],
             last_updated: "test_timestamp".to_string(),
             cached: true,
+            ranges: None,
+            fx: None,
+            total_count: None,
         };
 
         assert_eq!(response.data.len(), 1);
@@ -305,11 +1510,211 @@ mod tests {
     async fn test_historical_query_structure() {
         let query = HistoricalQuery {
             timeframe: "24h".to_string(),
+            start: None,
+            end: None,
+            interval: None,
+            page: None,
+            page_size: None,
+            max_points: None,
         };
 
         assert_eq!(query.timeframe, "24h");
     }
 
+    fn create_test_series(points: usize) -> HistoricalDataResult {
+        HistoricalDataResult {
+            success: true,
+            data: (0..points)
+                .map(|i| shared::HistoricalDataPoint {
+                    timestamp: 1704067200.0 + i as f64 * 3600.0,
+                    price: 45000.0 + i as f64,
+                    volume: None,
+                })
+                .collect(),
+            error: None,
+            symbol: Some("BTC".to_string()),
+            timeframe: Some("30d".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        }
+    }
+
+    #[test]
+    async fn test_paginate_historical_defaults() {
+        // No paging parameters: a short series whole
+        let page = paginate_historical(create_test_series(300), None, None);
+        assert_eq!(page.page_size, 300);
+        assert_eq!(page.total_pages, 1);
+        assert_eq!(page.result.data.len(), 300);
+
+        // ...and a long one only up to the maximum page size
+        let page = paginate_historical(create_test_series(1200), None, None);
+        assert_eq!(page.page, 1);
+        assert_eq!(page.page_size, MAX_HISTORICAL_PAGE_SIZE);
+        assert_eq!(page.total_count, 1200);
+        assert_eq!(page.total_pages, 2);
+        assert_eq!(page.result.data.len(), MAX_HISTORICAL_PAGE_SIZE);
+
+        // A page without a size gets the default size
+        let page = paginate_historical(create_test_series(1200), Some(1), None);
+        assert_eq!(page.page_size, DEFAULT_HISTORICAL_PAGE_SIZE);
+        assert_eq!(page.total_pages, 3);
+        assert_eq!(page.result.data.len(), DEFAULT_HISTORICAL_PAGE_SIZE);
+
+        let empty = paginate_historical(create_test_series(0), None, None);
+        assert_eq!(empty.total_pages, 0);
+        assert!(empty.result.data.is_empty());
+    }
+
+    #[test]
+    async fn test_paginate_historical_pages() {
+        let second = paginate_historical(create_test_series(25), Some(2), Some(10));
+        assert_eq!(second.total_pages, 3);
+        assert_eq!(second.result.data.len(), 10);
+        assert_eq!(second.result.data[0].price, 45010.0);
+
+        let last = paginate_historical(create_test_series(25), Some(3), Some(10));
+        assert_eq!(last.result.data.len(), 5);
+        assert_eq!(last.result.data[0].price, 45020.0);
+
+        let beyond = paginate_historical(create_test_series(25), Some(9), Some(10));
+        assert!(beyond.result.data.is_empty());
+        assert_eq!(beyond.total_count, 25);
+    }
+
+    #[test]
+    async fn test_paginate_historical_clamps_parameters() {
+        let page = paginate_historical(create_test_series(5), Some(0), Some(0));
+        assert_eq!(page.page, 1);
+        assert_eq!(page.page_size, 1);
+        assert_eq!(page.total_pages, 5);
+
+        let page = paginate_historical(create_test_series(5), None, Some(usize::MAX));
+        assert_eq!(page.page_size, MAX_HISTORICAL_PAGE_SIZE);
+        assert_eq!(page.result.data.len(), 5);
+    }
+
+    #[test]
+    async fn test_get_historical_data_for_delisted_coin() {
+        let state = create_test_app_state();
+        state.identity_map.lock().unwrap().record_cmc(1, "BTC", "Bitcoin", "bitcoin");
+        // Long past its freshness window, but it's all the history there will ever be
+        let cached_at = SystemTime::now() - Duration::from_secs(400 * 86_400);
+        state.historical_cache.lock().unwrap().insert("LUNA:30d".to_string(), (create_test_series(12), cached_at));
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_historical_data)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/historical/luna?timeframe=30d").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["delisted"], true);
+        assert_eq!(body["total_count"], 12);
+    }
+
+    #[test]
+    async fn test_get_historical_data_downsampled() {
+        let state = create_test_app_state();
+        state.historical_cache.lock().unwrap().insert("BTC:30d".to_string(), (create_test_series(720), SystemTime::now()));
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_historical_data)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/historical/BTC?timeframe=30d&max_points=50").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total_count"], 50);
+        assert_eq!(body["data"][0]["timestamp"], 1704067200.0);
+        // Each point comes with the statistics of the samples it stands for
+        assert_eq!(body["buckets"].as_array().unwrap().len(), 50);
+        assert_eq!(body["buckets"][0]["points"], 1);
+
+        let req = test::TestRequest::get().uri("/api/historical/BTC?timeframe=30d&max_points=50&page=2&page_size=20").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["buckets"].as_array().unwrap().len(), 20);
+        let timestamp = body["data"][0]["timestamp"].as_f64().unwrap();
+        assert!(body["buckets"][0]["start"].as_f64().unwrap() <= timestamp && timestamp <= body["buckets"][0]["end"].as_f64().unwrap());
+
+        let req = test::TestRequest::get().uri("/api/historical/BTC?timeframe=30d&max_points=2").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    async fn test_get_historical_range_validation() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_historical_data)
+        ).await;
+
+        for uri in [
+            "/api/historical/BTC",
+            "/api/historical/BTC?start=march",
+            "/api/historical/BTC?start=2024-04-01T00:00:00Z&end=2024-03-01T00:00:00Z",
+            "/api/historical/BTC?start=2024-03-01T00:00:00Z&interval=1w",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert!(body["error"].is_string());
+        }
+    }
+
+    #[test]
+    async fn test_pin_and_unpin_symbols() {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(create_test_app_state())
+                .service(get_pinned)
+                .service(pin_symbol)
+                .service(unpin_symbol)
+        ).await;
+
+        let req = test::TestRequest::put().uri("/api/pinned/pepe").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["symbols"], serde_json::json!(["PEPE"]));
+
+        let req = test::TestRequest::delete().uri("/api/pinned/PEPE").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::delete().uri("/api/pinned/PEPE").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::NOT_FOUND);
+        let req = test::TestRequest::get().uri("/api/pinned").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["symbols"], serde_json::json!([]));
+    }
+
+    #[test]
+    async fn test_get_historical_batch() {
+        let state = create_test_app_state();
+        let now = SystemTime::now();
+        state.historical_cache.lock().unwrap().insert("BTC:24h".to_string(), (create_test_series(5), now));
+        state.historical_cache.lock().unwrap().insert("ETH:7d".to_string(), (create_test_series(3), now));
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(state)
+                .service(get_historical_batch)
+        ).await;
+
+        let body = serde_json::json!([
+            { "symbol": "eth", "timeframe": "7d" },
+            { "symbol": "BTC", "timeframe": "24h" },
+            { "symbol": "ETH", "timeframe": "7d" },
+        ]);
+        let req = test::TestRequest::post().uri("/api/historical/batch").set_json(&body).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let lengths: Vec<usize> = body["results"].as_array().unwrap().iter().map(|result| result["data"].as_array().unwrap().len()).collect();
+        assert_eq!(lengths, vec![3, 5]);
+
+        let too_many: Vec<serde_json::Value> = (0..=MAX_BATCH_SERIES).map(|i| serde_json::json!({ "symbol": format!("C{}", i), "timeframe": "24h" })).collect();
+        let req = test::TestRequest::post().uri("/api/historical/batch").set_json(&too_many).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
     #[test]
     async fn test_timeout_duration() {
         let timeout = Duration::from_millis(1000);
@@ -0,0 +1,256 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+use tracing::{info, warn};
+use crate::config::{HttpClientConfig, ProxyConfig};
+use shared::{CoinCrabError, CoinCrabResult};
+
+// Override value that sends a provider's traffic directly instead of through the default proxy
+const DIRECT_PROXY: &str = "direct";
+
+// Retry settings applied to outbound provider requests
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &HttpClientConfig) -> Self {
+        RetryPolicy {
+            max_retries: config.max_retries,
+            initial_backoff: Duration::from_millis(config.retry_backoff_ms),
+        }
+    }
+
+    // Exponential backoff: initial, 2x initial, 4x initial, ...
+    pub fn backoff_for(&self, attempt: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt.min(16)))
+    }
+}
+
+pub fn build_http_client(config: &HttpClientConfig) -> CoinCrabResult<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+        .timeout(Duration::from_secs(config.request_timeout_seconds))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_seconds))
+        .user_agent(user_agent(&config.instance_id))
+        .default_headers(build_default_headers(&config.extra_headers)?);
+
+    // Explicit proxy settings replace the HTTP_PROXY/HTTPS_PROXY variables reqwest reads by default
+    if config.proxy.is_configured() {
+        builder = builder.no_proxy().proxy(build_proxy(&config.proxy)?);
+    }
+
+    builder
+        .build()
+        .map_err(|e| CoinCrabError::Http(format!("Failed to build HTTP client: {}", e)))
+}
+
+// e.g. "coin-crab-server/0.1.0 (instance=prod-eu-1)"
+pub fn user_agent(instance_id: &str) -> String {
+    format!("{}/{} (instance={})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), instance_id)
+}
+
+fn build_default_headers(headers: &[(String, String)]) -> CoinCrabResult<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| CoinCrabError::Config(format!("Invalid header name '{}': {}", name, e)))?;
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|e| CoinCrabError::Config(format!("Invalid value for header '{}': {}", name, e)))?;
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
+}
+
+fn build_proxy(config: &ProxyConfig) -> CoinCrabResult<Proxy> {
+    // Validate every URL up front so a typo fails at startup rather than on the first request
+    let urls = config.default_url.iter().chain(config.overrides.iter().map(|o| &o.url));
+    for url in urls.filter(|url| *url != DIRECT_PROXY) {
+        Proxy::all(url.as_str()).map_err(|e| CoinCrabError::Config(format!("Invalid proxy URL '{}': {}", url, e)))?;
+    }
+
+    if let Some(url) = &config.default_url {
+        info!("Routing provider requests through proxy {}", url);
+    }
+    for o in &config.overrides {
+        info!("Routing requests for {} through proxy {}", o.host, o.url);
+    }
+
+    let config = config.clone();
+    Ok(Proxy::custom(move |url| {
+        select_proxy(&config, url.host_str()?)
+            .and_then(|proxy| reqwest::Url::parse(proxy).ok())
+    }))
+}
+
+// Pick the proxy for a host: no-proxy entries win, then per-provider overrides, then the default
+fn select_proxy<'a>(config: &'a ProxyConfig, host: &str) -> Option<&'a str> {
+    let host = host.to_lowercase();
+    if config.no_proxy.iter().any(|pattern| host_matches(&host, pattern)) {
+        return None;
+    }
+
+    let url = config.overrides.iter()
+        .find(|o| host_matches(&host, &o.host.to_lowercase()))
+        .map(|o| o.url.as_str())
+        .or(config.default_url.as_deref())?;
+
+    (url != DIRECT_PROXY).then_some(url)
+}
+
+// Matches "*", an exact host, or a domain suffix ("example.com" and ".example.com" both cover api.example.com)
+fn host_matches(host: &str, pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let domain = pattern.trim_start_matches('.');
+    host == domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+// Send a request, retrying timeouts, connection failures and 5xx responses with backoff.
+// 4xx responses (including 429) are returned immediately so callers can handle them.
+pub async fn send_with_retry(request: RequestBuilder, policy: &RetryPolicy) -> reqwest::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        // Requests with streaming bodies can't be cloned, so they only get one attempt
+        let Some(current) = request.try_clone() else {
+            return request.send().await;
+        };
+
+        match current.send().await {
+            Ok(response) if is_retryable_status(response.status()) && attempt < policy.max_retries => {
+                warn!("Provider request returned {}, retrying (attempt {}/{})",
+                      response.status(), attempt + 1, policy.max_retries);
+            }
+            Err(e) if is_retryable_error(&e) && attempt < policy.max_retries => {
+                warn!("Provider request failed: {}, retrying (attempt {}/{})",
+                      e, attempt + 1, policy.max_retries);
+            }
+            result => return result,
+        }
+
+        tokio::time::sleep(policy.backoff_for(attempt)).await;
+        attempt += 1;
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error()
+}
+
+fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProviderProxy, CMC_API_HOST, CMC_LOGO_HOST};
+
+    #[test]
+    fn test_retry_policy_from_config() {
+        let config = HttpClientConfig {
+            max_retries: 4,
+            retry_backoff_ms: 250,
+            ..HttpClientConfig::default()
+        };
+        let policy = RetryPolicy::from_config(&config);
+
+        assert_eq!(policy.max_retries, 4);
+        assert_eq!(policy.initial_backoff, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+        };
+
+        assert_eq!(policy.backoff_for(0), Duration::from_millis(500));
+        assert_eq!(policy.backoff_for(1), Duration::from_millis(1000));
+        assert_eq!(policy.backoff_for(2), Duration::from_millis(2000));
+        // Large attempt counts must not overflow
+        let _ = policy.backoff_for(u32::MAX);
+    }
+
+    #[test]
+    fn test_retryable_statuses() {
+        assert!(is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+        assert!(!is_retryable_status(StatusCode::OK));
+    }
+
+    #[test]
+    fn test_build_http_client() {
+        let client = build_http_client(&HttpClientConfig::default());
+        assert!(client.is_ok());
+    }
+
+    #[test]
+    fn test_user_agent_identifies_instance() {
+        let ua = user_agent("prod-eu-1");
+        assert!(ua.starts_with("coin-crab-server/"));
+        assert!(ua.ends_with("(instance=prod-eu-1)"));
+    }
+
+    #[test]
+    fn test_build_default_headers() {
+        let headers = build_default_headers(&[("X-Team".to_string(), "markets".to_string())]).unwrap();
+        assert_eq!(headers.get("x-team").unwrap(), "markets");
+
+        assert!(build_default_headers(&[("Bad Header".to_string(), "v".to_string())]).is_err());
+        assert!(build_default_headers(&[("X-Ok".to_string(), "line\nbreak".to_string())]).is_err());
+    }
+
+    fn proxy_config() -> ProxyConfig {
+        ProxyConfig {
+            default_url: Some("http://proxy.corp:3128".to_string()),
+            overrides: vec![
+                ProviderProxy { host: CMC_API_HOST.to_string(), url: "socks5h://10.0.0.2:1080".to_string() },
+                ProviderProxy { host: CMC_LOGO_HOST.to_string(), url: DIRECT_PROXY.to_string() },
+            ],
+            no_proxy: vec!["localhost".to_string(), ".internal.corp".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_select_proxy() {
+        let config = proxy_config();
+
+        assert_eq!(select_proxy(&config, CMC_API_HOST), Some("socks5h://10.0.0.2:1080"));
+        assert_eq!(select_proxy(&config, CMC_LOGO_HOST), None);
+        assert_eq!(select_proxy(&config, "api.example.com"), Some("http://proxy.corp:3128"));
+        assert_eq!(select_proxy(&config, "localhost"), None);
+        assert_eq!(select_proxy(&config, "metrics.internal.corp"), None);
+        assert_eq!(select_proxy(&ProxyConfig::default(), CMC_API_HOST), None);
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("api.example.com", "example.com"));
+        assert!(host_matches("api.example.com", ".example.com"));
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("anything", "*"));
+        assert!(!host_matches("badexample.com", "example.com"));
+        assert!(!host_matches("example.com.evil", "example.com"));
+    }
+
+    #[test]
+    fn test_build_http_client_with_proxies() {
+        let config = HttpClientConfig { proxy: proxy_config(), ..HttpClientConfig::default() };
+        assert!(build_http_client(&config).is_ok());
+
+        let invalid = HttpClientConfig {
+            proxy: ProxyConfig { default_url: Some("not a url".to_string()), ..ProxyConfig::default() },
+            ..HttpClientConfig::default()
+        };
+        assert!(build_http_client(&invalid).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use tracing::warn;
+use crate::auth::ApiCaller;
+use crate::config::{ClientRateLimitConfig, HistoricalRateLimitConfig};
+use crate::mqtt::rate_limit::{ClientRateLimiter, RateDecision};
+
+// Every series a request loads may miss the cache and cost provider calls, so the handlers that
+// can load history charge a token bucket per caller, one token per series, on top of the
+// API-wide limit of HTTP authentication
+
+pub struct HistoricalRateLimit {
+    limiter: ClientRateLimiter,
+    trust_forwarded_for: bool,
+}
+
+impl HistoricalRateLimit {
+    pub fn new(config: &HistoricalRateLimitConfig) -> Self {
+        HistoricalRateLimit {
+            limiter: ClientRateLimiter::new(ClientRateLimitConfig {
+                requests_per_minute: config.requests_per_minute,
+                burst: config.burst,
+            }),
+            trust_forwarded_for: config.trust_forwarded_for,
+        }
+    }
+
+    // The authenticated caller, or else the client address
+    fn bucket(&self, req: &HttpRequest) -> String {
+        if let Some(ApiCaller(caller)) = req.extensions().get::<ApiCaller>() {
+            return format!("key:{}", caller);
+        }
+        let info = req.connection_info();
+        let address = if self.trust_forwarded_for { info.realip_remote_addr() } else { info.peer_addr() };
+        format!("ip:{}", address.unwrap_or("unknown"))
+    }
+}
+
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string()))
+        .json(serde_json::json!({ "error": "rate limit exceeded" }))
+}
+
+// Call before loading `series` historical series; returns the 429 to send instead when the
+// caller is out of tokens. Without a configured limit (e.g. in tests) everything is allowed.
+pub fn charge_historical(req: &HttpRequest, series: usize) -> Option<HttpResponse> {
+    let limit = req.app_data::<web::Data<HistoricalRateLimit>>()?;
+    let bucket = limit.bucket(req);
+    let cost = u32::try_from(series).unwrap_or(u32::MAX);
+    let retry_after = match limit.limiter.check_cost(&bucket, cost, Instant::now()) {
+        RateDecision::Allowed => return None,
+        RateDecision::Throttled { retry_after } => {
+            warn!("Throttling historical requests from {} for {:.1}s", bucket, retry_after.as_secs_f64());
+            retry_after
+        }
+        RateDecision::StillThrottled { retry_after } => retry_after,
+    };
+    Some(too_many_requests(retry_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    async fn history(req: HttpRequest) -> HttpResponse {
+        charge_historical(&req, 1).unwrap_or_else(|| HttpResponse::Ok().body("history"))
+    }
+
+    async fn batch(req: HttpRequest) -> HttpResponse {
+        charge_historical(&req, 3).unwrap_or_else(|| HttpResponse::Ok().body("batch"))
+    }
+
+    #[actix_web::test]
+    async fn test_historical_requests_are_limited_per_address() {
+        let config = HistoricalRateLimitConfig { requests_per_minute: 60, burst: 3, ..HistoricalRateLimitConfig::default() };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(HistoricalRateLimit::new(&config)))
+                .route("/api/historical/batch", web::post().to(batch))
+                .route("/api/historical/{symbol}", web::get().to(history))
+                .route("/api/prices", web::get().to(|| async { "prices" })),
+        ).await;
+        let get = |path: &str, address: &str| TestRequest::get().uri(path).peer_addr(address.parse().unwrap()).to_request();
+
+        assert_eq!(call_service(&app, get("/api/historical/BTC", "10.0.0.1:5000")).await.status(), 200);
+        assert_eq!(call_service(&app, get("/api/historical/ETH", "10.0.0.1:5001")).await.status(), 200);
+        assert_eq!(call_service(&app, get("/api/historical/SOL", "10.0.0.1:5002")).await.status(), 200);
+        let response = call_service(&app, get("/api/historical/ETH", "10.0.0.1:5003")).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+        // Other addresses and other endpoints are unaffected
+        assert_eq!(call_service(&app, get("/api/historical/ETH", "10.0.0.2:5000")).await.status(), 200);
+        assert_eq!(call_service(&app, get("/api/prices", "10.0.0.1:5000")).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_batches_cost_a_token_per_series() {
+        let config = HistoricalRateLimitConfig { requests_per_minute: 60, burst: 3, ..HistoricalRateLimitConfig::default() };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(HistoricalRateLimit::new(&config)))
+                .route("/api/historical/batch", web::post().to(batch))
+                .route("/api/historical/{symbol}", web::get().to(history)),
+        ).await;
+        let address = "10.0.0.1:5000".parse().unwrap();
+
+        let response = call_service(&app, TestRequest::post().uri("/api/historical/batch").peer_addr(address).to_request()).await;
+        assert_eq!(response.status(), 200);
+        // The batch of 3 used up the whole burst
+        let response = call_service(&app, TestRequest::get().uri("/api/historical/BTC").peer_addr(address).to_request()).await;
+        assert_eq!(response.status(), 429);
+    }
+}
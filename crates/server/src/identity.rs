@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+// One coin as known to each provider. Historical series, logos and MQTT topics are keyed by
+// the canonical symbol (the CMC symbol, uppercased), so data fetched through another provider
+// has to be translated back to it before it is cached or published.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinIdentity {
+    pub symbol: String,
+    pub name: String,
+    pub cmc_id: Option<u32>,
+    pub slug: Option<String>,
+    pub coingecko_id: Option<String>,
+}
+
+// Cross-provider identity map. CMC's map is authoritative for which coin a symbol means;
+// CoinGecko ids are attached to those coins, never the other way round, because CoinGecko
+// lists many unrelated tokens under popular symbols.
+#[derive(Default)]
+pub struct IdentityMap {
+    by_symbol: HashMap<String, CoinIdentity>,
+    by_cmc_id: HashMap<u32, String>,
+    by_coingecko_id: HashMap<String, String>,
+}
+
+impl IdentityMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A symbol keeps the first coin recorded for it
+    pub fn record_cmc(&mut self, cmc_id: u32, symbol: &str, name: &str, slug: &str) {
+        let symbol = symbol.to_uppercase();
+        if self.by_symbol.contains_key(&symbol) {
+            return;
+        }
+        self.by_cmc_id.insert(cmc_id, symbol.clone());
+        self.by_symbol.insert(symbol.clone(), CoinIdentity {
+            symbol,
+            name: name.to_string(),
+            cmc_id: Some(cmc_id),
+            slug: Some(slug.to_string()),
+            coingecko_id: None,
+        });
+    }
+
+    // Attach a CoinGecko id to the coin with that symbol if it is the same coin: its id equals
+    // the CMC slug (the usual case, e.g. "bitcoin"), or failing that its name matches. A slug
+    // match replaces an earlier name match. Returns true if the id was attached.
+    pub fn record_coingecko(&mut self, coingecko_id: &str, symbol: &str, name: &str) -> bool {
+        let Some(identity) = self.by_symbol.get_mut(&symbol.to_uppercase()) else {
+            return false;
+        };
+        let slug_match = identity.slug.as_deref() == Some(coingecko_id);
+        let name_match = identity.name.eq_ignore_ascii_case(name);
+        let replaces = match &identity.coingecko_id {
+            None => slug_match || name_match,
+            Some(existing) => slug_match && identity.slug.as_deref() != Some(existing.as_str()),
+        };
+        if !replaces {
+            return false;
+        }
+        if let Some(previous) = identity.coingecko_id.replace(coingecko_id.to_string()) {
+            self.by_coingecko_id.remove(&previous);
+        }
+        self.by_coingecko_id.insert(coingecko_id.to_string(), identity.symbol.clone());
+        true
+    }
+
+    pub fn by_symbol(&self, symbol: &str) -> Option<&CoinIdentity> {
+        self.by_symbol.get(&symbol.to_uppercase())
+    }
+
+    pub fn by_cmc_id(&self, cmc_id: u32) -> Option<&CoinIdentity> {
+        self.by_symbol.get(self.by_cmc_id.get(&cmc_id)?)
+    }
+
+    pub fn by_coingecko_id(&self, coingecko_id: &str) -> Option<&CoinIdentity> {
+        self.by_symbol.get(self.by_coingecko_id.get(coingecko_id)?)
+    }
+
+    // Look a coin up by whichever identifier the caller has: symbol, CMC id or CoinGecko id
+    pub fn resolve(&self, key: &str) -> Option<&CoinIdentity> {
+        self.by_symbol(key)
+            .or_else(|| key.parse().ok().and_then(|cmc_id| self.by_cmc_id(cmc_id)))
+            .or_else(|| self.by_coingecko_id(key))
+    }
+
+    // Rebuild a map from stored identities; a symbol keeps the first coin recorded for it
+    pub fn from_identities(identities: impl IntoIterator<Item = CoinIdentity>) -> Self {
+        let mut map = Self::new();
+        for identity in identities {
+            if map.by_symbol.contains_key(&identity.symbol) {
+                continue;
+            }
+            if let Some(cmc_id) = identity.cmc_id {
+                map.by_cmc_id.insert(cmc_id, identity.symbol.clone());
+            }
+            if let Some(coingecko_id) = &identity.coingecko_id {
+                map.by_coingecko_id.insert(coingecko_id.clone(), identity.symbol.clone());
+            }
+            map.by_symbol.insert(identity.symbol.clone(), identity);
+        }
+        map
+    }
+
+    pub fn identities(&self) -> Vec<&CoinIdentity> {
+        let mut identities: Vec<&CoinIdentity> = self.by_symbol.values().collect();
+        identities.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        identities
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_symbol.len()
+    }
+
+    pub fn coingecko_len(&self) -> usize {
+        self.by_coingecko_id.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> IdentityMap {
+        let mut map = IdentityMap::new();
+        map.record_cmc(1, "BTC", "Bitcoin", "bitcoin");
+        map.record_cmc(1027, "eth", "Ethereum", "ethereum");
+        map.record_cmc(5426, "SOL", "Solana", "solana");
+        // Lower-ranked coin reusing a symbol doesn't take it over
+        map.record_cmc(9999, "BTC", "Bitcoin Clone", "bitcoin-clone");
+        map
+    }
+
+    #[test]
+    fn test_coingecko_ids_attach_to_the_cmc_coin() {
+        let mut map = map();
+        assert!(!map.record_coingecko("bitcoin-clone-token", "btc", "Bitcoin Clone"));
+        assert!(map.record_coingecko("bitcoin", "btc", "Bitcoin"));
+        // Name match first, later replaced by the slug match
+        assert!(map.record_coingecko("ethereum-wormhole", "eth", "Ethereum"));
+        assert!(map.record_coingecko("ethereum", "eth", "Ethereum"));
+        assert!(!map.record_coingecko("ethereum-bridged", "eth", "Ethereum"));
+        assert!(!map.record_coingecko("dogecoin", "doge", "Dogecoin"));
+
+        assert_eq!(map.by_coingecko_id("ethereum").unwrap().symbol, "ETH");
+        assert!(map.by_coingecko_id("ethereum-wormhole").is_none());
+        assert_eq!(map.by_symbol("eth").unwrap().coingecko_id.as_deref(), Some("ethereum"));
+        assert_eq!(map.coingecko_len(), 2);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn test_resolve_by_any_identifier() {
+        let mut map = map();
+        map.record_coingecko("solana", "sol", "Solana");
+        assert_eq!(map.resolve("sol").unwrap().cmc_id, Some(5426));
+        assert_eq!(map.resolve("1027").unwrap().symbol, "ETH");
+        assert_eq!(map.resolve("solana").unwrap().symbol, "SOL");
+        assert!(map.resolve("9999").is_none());
+        let symbols: Vec<&str> = map.identities().iter().map(|identity| identity.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["BTC", "ETH", "SOL"]);
+
+        // A map rebuilt from its identities (as stored) resolves the same way
+        let restored = IdentityMap::from_identities(map.identities().into_iter().cloned());
+        assert_eq!(restored.resolve("solana").unwrap().symbol, "SOL");
+        assert_eq!(restored.resolve("1").unwrap().symbol, "BTC");
+        assert_eq!((restored.len(), restored.coingecko_len()), (3, 1));
+    }
+}
@@ -0,0 +1,279 @@
+use serde::Serialize;
+use crate::types::HistoricalDataResult;
+
+// Technical indicators over a historical series, for charts that overlay them. Every
+// indicator is computed from the close-to-close prices the series stores; points before an
+// indicator has enough history are left out rather than padded.
+
+pub const DEFAULT_PERIOD: usize = 20;
+pub const RSI_PERIOD: usize = 14;
+pub const MACD_FAST: usize = 12;
+pub const MACD_SLOW: usize = 26;
+pub const MACD_SIGNAL: usize = 9;
+// Band width in standard deviations
+pub const BOLLINGER_WIDTH: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indicator {
+    Sma,
+    Ema,
+    Rsi,
+    Macd,
+    Bollinger,
+}
+
+impl Indicator {
+    pub const ALL: [Indicator; 5] = [Indicator::Sma, Indicator::Ema, Indicator::Rsi, Indicator::Macd, Indicator::Bollinger];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "sma" => Some(Indicator::Sma),
+            "ema" => Some(Indicator::Ema),
+            "rsi" => Some(Indicator::Rsi),
+            "macd" => Some(Indicator::Macd),
+            "bollinger" | "bb" => Some(Indicator::Bollinger),
+            _ => None,
+        }
+    }
+}
+
+// Comma-separated indicator names; None if any is unknown. Empty selects all of them.
+pub fn parse_indicator_list(names: &str) -> Option<Vec<Indicator>> {
+    let names: Vec<&str> = names.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+    if names.is_empty() {
+        return Some(Indicator::ALL.to_vec());
+    }
+    names.into_iter().map(Indicator::parse).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndicatorPoint {
+    pub timestamp: f64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MacdPoint {
+    pub timestamp: f64,
+    pub macd: f64,
+    pub signal: f64,
+    pub histogram: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BollingerPoint {
+    pub timestamp: f64,
+    pub middle: f64,
+    pub upper: f64,
+    pub lower: f64,
+}
+
+// Only the requested indicators are present
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndicatorSet {
+    pub symbol: String,
+    pub timeframe: String,
+    // Period used for SMA, EMA, RSI and Bollinger Bands
+    pub period: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sma: Option<Vec<IndicatorPoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ema: Option<Vec<IndicatorPoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rsi: Option<Vec<IndicatorPoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub macd: Option<Vec<MacdPoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bollinger: Option<Vec<BollingerPoint>>,
+}
+
+// period None uses each indicator's customary default (20, RSI 14)
+pub fn compute_indicators(result: &HistoricalDataResult, indicators: &[Indicator], period: Option<usize>) -> IndicatorSet {
+    let mut points: Vec<(f64, f64)> = result.data.iter().map(|point| (point.timestamp, point.price)).collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let timestamps: Vec<f64> = points.iter().map(|(timestamp, _)| *timestamp).collect();
+    let prices: Vec<f64> = points.iter().map(|(_, price)| *price).collect();
+    let rsi_period = period.unwrap_or(RSI_PERIOD).max(1);
+    let period = period.unwrap_or(DEFAULT_PERIOD).max(1);
+    let wants = |indicator: Indicator| indicators.contains(&indicator);
+    let series = |values: Vec<Option<f64>>| -> Vec<IndicatorPoint> {
+        timestamps
+            .iter()
+            .zip(values)
+            .filter_map(|(timestamp, value)| Some(IndicatorPoint { timestamp: *timestamp, value: value? }))
+            .collect()
+    };
+
+    IndicatorSet {
+        symbol: result.symbol.clone().unwrap_or_default(),
+        timeframe: result.timeframe.clone().unwrap_or_default(),
+        period,
+        sma: wants(Indicator::Sma).then(|| series(sma(&prices, period))),
+        ema: wants(Indicator::Ema).then(|| series(ema(&prices, period))),
+        rsi: wants(Indicator::Rsi).then(|| series(rsi(&prices, rsi_period))),
+        macd: wants(Indicator::Macd).then(|| {
+            timestamps
+                .iter()
+                .zip(macd(&prices))
+                .filter_map(|(timestamp, values)| {
+                    let (macd, signal) = values?;
+                    Some(MacdPoint { timestamp: *timestamp, macd, signal, histogram: macd - signal })
+                })
+                .collect()
+        }),
+        bollinger: wants(Indicator::Bollinger).then(|| {
+            timestamps
+                .iter()
+                .zip(bollinger(&prices, period, BOLLINGER_WIDTH))
+                .filter_map(|(timestamp, bands)| {
+                    let (middle, upper, lower) = bands?;
+                    Some(BollingerPoint { timestamp: *timestamp, middle, upper, lower })
+                })
+                .collect()
+        }),
+    }
+}
+
+// Each function returns one value per input price, None until the window is filled
+
+pub fn sma(prices: &[f64], period: usize) -> Vec<Option<f64>> {
+    (0..prices.len())
+        .map(|i| (i + 1 >= period).then(|| prices[i + 1 - period..=i].iter().sum::<f64>() / period as f64))
+        .collect()
+}
+
+// Seeded with the SMA of the first period prices
+pub fn ema(prices: &[f64], period: usize) -> Vec<Option<f64>> {
+    let k = 2.0 / (period as f64 + 1.0);
+    let mut previous: Option<f64> = None;
+    sma(prices, period)
+        .into_iter()
+        .zip(prices)
+        .map(|(seed, price)| {
+            previous = match previous {
+                Some(previous) => Some(price * k + previous * (1.0 - k)),
+                None => seed,
+            };
+            previous
+        })
+        .collect()
+}
+
+// Wilder's smoothing; 100 when there were no losses in the window
+pub fn rsi(prices: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut values = vec![None; prices.len()];
+    if prices.len() <= period {
+        return values;
+    }
+    let changes: Vec<f64> = prices.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let mut avg_gain = changes[..period].iter().map(|c| c.max(0.0)).sum::<f64>() / period as f64;
+    let mut avg_loss = changes[..period].iter().map(|c| (-c).max(0.0)).sum::<f64>() / period as f64;
+    let value = |gain: f64, loss: f64| if loss == 0.0 { 100.0 } else { 100.0 - 100.0 / (1.0 + gain / loss) };
+    values[period] = Some(value(avg_gain, avg_loss));
+    for (i, change) in changes.iter().enumerate().skip(period) {
+        avg_gain = (avg_gain * (period - 1) as f64 + change.max(0.0)) / period as f64;
+        avg_loss = (avg_loss * (period - 1) as f64 + (-change).max(0.0)) / period as f64;
+        values[i + 1] = Some(value(avg_gain, avg_loss));
+    }
+    values
+}
+
+// (MACD line, signal line) with the standard 12/26/9 periods
+pub fn macd(prices: &[f64]) -> Vec<Option<(f64, f64)>> {
+    let line: Vec<Option<f64>> = ema(prices, MACD_FAST)
+        .into_iter()
+        .zip(ema(prices, MACD_SLOW))
+        .map(|(fast, slow)| Some(fast? - slow?))
+        .collect();
+    let start = line.iter().position(Option::is_some).unwrap_or(line.len());
+    let defined: Vec<f64> = line[start..].iter().flatten().copied().collect();
+    let mut signal = vec![None; start];
+    signal.extend(ema(&defined, MACD_SIGNAL));
+    line.into_iter().zip(signal).map(|(macd, signal)| Some((macd?, signal?))).collect()
+}
+
+// (middle, upper, lower), the bands width standard deviations from the SMA
+pub fn bollinger(prices: &[f64], period: usize, width: f64) -> Vec<Option<(f64, f64, f64)>> {
+    sma(prices, period)
+        .into_iter()
+        .enumerate()
+        .map(|(i, middle)| {
+            let middle = middle?;
+            let window = &prices[i + 1 - period..=i];
+            let deviation = (window.iter().map(|price| (price - middle).powi(2)).sum::<f64>() / period as f64).sqrt();
+            Some((middle, middle + width * deviation, middle - width * deviation))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::HistoricalDataPoint;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("value should be defined");
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_moving_averages() {
+        let prices = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(sma(&prices, 3), vec![None, None, Some(2.0), Some(3.0), Some(4.0)]);
+        // Seed 2.0, then k = 0.5
+        assert_eq!(ema(&prices, 3), vec![None, None, Some(2.0), Some(3.0), Some(4.0)]);
+        assert_close(ema(&[1.0, 2.0, 3.0, 10.0], 3)[3], 6.0);
+        assert!(sma(&prices, 6).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_rsi_and_bollinger() {
+        let rising: Vec<f64> = (1..=20).map(f64::from).collect();
+        let values = rsi(&rising, 14);
+        assert!(values[13].is_none());
+        assert_close(values[14], 100.0);
+
+        // Gains and losses of 1 each: the seed averages are equal, then +1 tips it to 0.75/0.25
+        assert_close(rsi(&[10.0, 11.0, 10.0], 2)[2], 50.0);
+        assert_close(rsi(&[10.0, 11.0, 10.0, 11.0], 2)[3], 75.0);
+
+        let bands = bollinger(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0], 8, 2.0);
+        let (middle, upper, lower) = bands[7].unwrap();
+        assert_eq!((middle, upper, lower), (5.0, 9.0, 1.0));
+    }
+
+    #[test]
+    fn test_macd_starts_once_signal_is_seeded() {
+        let prices: Vec<f64> = (0..40).map(|i| 100.0 + f64::from(i)).collect();
+        let values = macd(&prices);
+        let first = values.iter().position(Option::is_some).unwrap();
+        assert_eq!(first, MACD_SLOW - 1 + MACD_SIGNAL - 1);
+        // A steady trend settles to a constant MACD, so the histogram goes to zero
+        let (line, signal) = values[39].unwrap();
+        assert!(line > 0.0 && (line - signal).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_compute_indicators_selects_and_aligns() {
+        let result = HistoricalDataResult {
+            success: true,
+            // Out of order on purpose
+            data: (0..30).rev().map(|i| HistoricalDataPoint { timestamp: f64::from(i) * 60.0, price: f64::from(i), volume: None }).collect(),
+            error: None,
+            symbol: Some("BTC".to_string()),
+            timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+        };
+        let set = compute_indicators(&result, &parse_indicator_list("sma, rsi").unwrap(), Some(5));
+        assert!(set.ema.is_none() && set.macd.is_none() && set.bollinger.is_none());
+        let sma = set.sma.unwrap();
+        assert_eq!(sma.len(), 26);
+        assert_eq!(sma[0], IndicatorPoint { timestamp: 240.0, value: 2.0 });
+        assert_eq!(set.rsi.unwrap().len(), 25);
+
+        assert_eq!(parse_indicator_list("").unwrap(), Indicator::ALL.to_vec());
+        assert!(parse_indicator_list("sma,vwap").is_none());
+    }
+}
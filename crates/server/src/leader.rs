@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn, debug};
+use crate::config::LeaderConfig;
+use shared::CoinCrabResult;
+
+// Lease record stored in the shared lease file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct Lease {
+    holder: String,
+    expires_at: u64,
+}
+
+// Store-based leader election. Instances sharing a lease file (e.g. on a shared volume)
+// compete for a time-limited lease; only the holder polls CMC and publishes to MQTT,
+// the others keep serving HTTP and take over once the lease expires.
+// Without a lease file the instance is always the leader (single-instance deployments).
+pub struct LeaderElection {
+    lease_path: Option<PathBuf>,
+    instance_id: String,
+    ttl: Duration,
+    is_leader: AtomicBool,
+}
+
+impl LeaderElection {
+    pub fn new(config: &LeaderConfig, instance_id: &str) -> Self {
+        LeaderElection {
+            lease_path: config.lease_file.as_ref().map(PathBuf::from),
+            instance_id: instance_id.to_string(),
+            ttl: Duration::from_secs(config.lease_ttl_seconds.max(1)),
+            is_leader: AtomicBool::new(config.lease_file.is_none()),
+        }
+    }
+
+    // Election disabled: this instance always fetches and publishes
+    #[cfg(test)]
+    pub fn standalone() -> Self {
+        LeaderElection::new(&LeaderConfig::default(), "standalone")
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::Relaxed)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    // Acquire or renew the lease. Returns whether this instance is now the leader.
+    pub fn try_acquire(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.try_acquire_at(now)
+    }
+
+    fn try_acquire_at(&self, now: u64) -> bool {
+        let Some(path) = &self.lease_path else {
+            return true;
+        };
+
+        let leader = match read_lease(path) {
+            Some(lease) if lease.holder != self.instance_id && lease.expires_at > now => false,
+            _ => {
+                let lease = Lease {
+                    holder: self.instance_id.clone(),
+                    expires_at: now + self.ttl.as_secs(),
+                };
+                match write_lease(path, &lease, &self.instance_id) {
+                    // Re-read so that when two instances race for an expired lease only the last writer wins
+                    Ok(()) => read_lease(path).map(|l| l.holder == self.instance_id).unwrap_or(false),
+                    Err(e) => {
+                        warn!("Failed to write leader lease {}: {}", path.display(), e);
+                        false
+                    }
+                }
+            }
+        };
+
+        let was_leader = self.is_leader.swap(leader, Ordering::Relaxed);
+        if leader != was_leader {
+            if leader {
+                info!("Instance {} acquired the leader lease - fetching and publishing", self.instance_id);
+            } else {
+                info!("Instance {} is on standby - another instance holds the leader lease", self.instance_id);
+            }
+        }
+        leader
+    }
+
+    // Renew often enough that the lease never lapses while this instance is healthy
+    fn renew_interval(&self) -> Duration {
+        (self.ttl / 3).max(Duration::from_secs(1))
+    }
+}
+
+fn read_lease(path: &PathBuf) -> Option<Lease> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+// Write to a per-instance temp file and rename so readers never see a partial lease
+fn write_lease(path: &PathBuf, lease: &Lease, instance_id: &str) -> CoinCrabResult<()> {
+    let json = serde_json::to_string(lease)?;
+    let tmp_path = path.with_extension(format!("{}.tmp", instance_id));
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub async fn run_leader_election(election: Arc<LeaderElection>) {
+    if election.lease_path.is_none() {
+        return;
+    }
+
+    loop {
+        tokio::time::sleep(election.renew_interval()).await;
+        let leader = election.try_acquire();
+        debug!("Leader lease check for {}: leader={}", election.instance_id, leader);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease_config(name: &str) -> LeaderConfig {
+        let path = std::env::temp_dir().join(format!("coin-crab-lease-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        LeaderConfig {
+            lease_file: Some(path.display().to_string()),
+            lease_ttl_seconds: 30,
+        }
+    }
+
+    #[test]
+    fn test_standalone_is_always_leader() {
+        let election = LeaderElection::standalone();
+        assert!(election.is_leader());
+        assert!(election.try_acquire());
+    }
+
+    #[test]
+    fn test_only_one_instance_holds_the_lease() {
+        let config = lease_config("exclusive");
+        let a = LeaderElection::new(&config, "a");
+        let b = LeaderElection::new(&config, "b");
+        assert!(!a.is_leader());
+
+        assert!(a.try_acquire_at(1000));
+        assert!(!b.try_acquire_at(1010));
+        // The holder can renew its own lease
+        assert!(a.try_acquire_at(1020));
+        assert!(!b.is_leader());
+
+        let _ = std::fs::remove_file(config.lease_file.unwrap());
+    }
+
+    #[test]
+    fn test_standby_takes_over_expired_lease() {
+        let config = lease_config("takeover");
+        let a = LeaderElection::new(&config, "a");
+        let b = LeaderElection::new(&config, "b");
+
+        assert!(a.try_acquire_at(1000));
+        // a stops renewing; its lease runs out after the ttl
+        assert!(b.try_acquire_at(1031));
+        assert!(!a.try_acquire_at(1040));
+        assert!(!a.is_leader());
+
+        let _ = std::fs::remove_file(config.lease_file.unwrap());
+    }
+
+    #[test]
+    fn test_renew_interval() {
+        let config = LeaderConfig { lease_file: None, lease_ttl_seconds: 30 };
+        let election = LeaderElection::new(&config, "a");
+        assert_eq!(election.renew_interval(), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,659 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use crate::config::LogoCacheConfig;
+use crate::http_client::send_with_retry;
+use crate::identity::CoinIdentity;
+use crate::providers::MarketDataProvider;
+use crate::types::AppState;
+use shared::CoinCrabResult;
+
+// Content-addressed logo store. Each cache key ("BTC", "BTC@128", "BTC@128.webp") points at the
+// SHA-256 of the image, so identical images are stored once and the hash doubles as a
+// strong ETag for clients. The CDN's own validators are kept so refreshes can be
+// conditional and an unchanged logo costs a 304 instead of a full download.
+//
+// Images are held in memory up to a byte budget, least recently used first out. With a cache
+// directory they are also written to disk, so evicted images are read back instead of
+// downloaded again and a restart starts with every logo it had. The directory is bounded the
+// same way, and logos nobody asked for within the max age are deleted.
+
+pub const LOGO_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Index of cache keys kept next to the image files
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogoEntry {
+    pub hash: String,
+    pub fetched_at: SystemTime,
+    // Validators returned by the CDN, sent back as If-None-Match / If-Modified-Since
+    pub cdn_etag: Option<String>,
+    pub cdn_last_modified: Option<String>,
+    // Last time a client was served this logo; on disk as of the last index write
+    pub last_used: SystemTime,
+}
+
+impl LogoEntry {
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        now.duration_since(self.fetched_at).unwrap_or(Duration::ZERO) < LOGO_CACHE_TTL
+    }
+}
+
+struct Blob {
+    bytes: Arc<Vec<u8>>,
+    // Position in access order, for evicting the least recently used image
+    last_used: u64,
+}
+
+// Image files named by their hash, plus the index
+struct LogoDir {
+    dir: PathBuf,
+    budget_bytes: u64,
+    max_age: Duration,
+    // Size of each image file on disk
+    sizes: HashMap<String, u64>,
+}
+
+impl LogoDir {
+    fn image_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash)
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.sizes.values().sum()
+    }
+
+    // An image read back from disk, if it is there and intact
+    fn read(&self, hash: &str) -> Option<Vec<u8>> {
+        let bytes = std::fs::read(self.image_path(hash)).ok()?;
+        (content_hash(&bytes) == hash).then_some(bytes)
+    }
+
+    fn write(&mut self, hash: &str, bytes: &[u8]) {
+        if self.sizes.contains_key(hash) {
+            return;
+        }
+        match std::fs::write(self.image_path(hash), bytes) {
+            Ok(()) => {
+                self.sizes.insert(hash.to_string(), bytes.len() as u64);
+            }
+            Err(e) => warn!("Failed to write logo {} to {}: {}", hash, self.dir.display(), e),
+        }
+    }
+
+    fn remove(&mut self, hash: &str) {
+        if self.sizes.remove(hash).is_some() {
+            if let Err(e) = std::fs::remove_file(self.image_path(hash)) {
+                warn!("Failed to remove cached logo {}: {}", hash, e);
+            }
+        }
+    }
+
+    // Write to a temp file and rename so a crash mid-write never leaves a truncated index
+    fn save_index(&self, entries: &HashMap<String, LogoEntry>) {
+        let path = self.dir.join(INDEX_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let result = serde_json::to_string(entries)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&tmp_path, json))
+            .and_then(|()| std::fs::rename(&tmp_path, &path));
+        if let Err(e) = result {
+            warn!("Failed to save logo cache index in {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+pub struct LogoCache {
+    entries: HashMap<String, LogoEntry>,
+    blobs: HashMap<String, Blob>,
+    memory_budget_bytes: usize,
+    memory_bytes: usize,
+    disk: Option<LogoDir>,
+    clock: u64,
+}
+
+impl Default for LogoCache {
+    fn default() -> Self {
+        LogoCache {
+            entries: HashMap::new(),
+            blobs: HashMap::new(),
+            memory_budget_bytes: usize::MAX,
+            memory_bytes: 0,
+            disk: None,
+            clock: 0,
+        }
+    }
+}
+
+impl LogoCache {
+    // Memory only, without a budget
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Cache with the configured budgets, picking up the index and images left in the cache
+    // directory. Entries whose image file is missing or damaged are dropped, as are image files
+    // no entry refers to. Other files in the directory are left alone.
+    pub fn open(config: &LogoCacheConfig) -> CoinCrabResult<Self> {
+        let mut cache = LogoCache {
+            memory_budget_bytes: config.memory_budget_mb.saturating_mul(1024 * 1024),
+            ..Self::default()
+        };
+        let Some(dir) = &config.dir else {
+            return Ok(cache);
+        };
+        let dir = Path::new(dir);
+        std::fs::create_dir_all(dir)?;
+        let index_path = dir.join(INDEX_FILE);
+        let mut entries: HashMap<String, LogoEntry> = if index_path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&index_path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        let mut sizes = HashMap::new();
+        for file in std::fs::read_dir(dir)?.flatten() {
+            let name = file.file_name().to_string_lossy().into_owned();
+            if !is_image_file_name(&name) {
+                continue;
+            }
+            match file.metadata() {
+                Ok(metadata) if entries.values().any(|entry| entry.hash == name) => {
+                    sizes.insert(name, metadata.len());
+                }
+                _ => {
+                    let _ = std::fs::remove_file(file.path());
+                }
+            }
+        }
+        entries.retain(|_, entry| sizes.contains_key(&entry.hash));
+
+        cache.entries = entries;
+        cache.disk = Some(LogoDir {
+            dir: dir.to_path_buf(),
+            budget_bytes: config.disk_budget_mb.saturating_mul(1024 * 1024),
+            max_age: Duration::from_secs(config.max_age_days * 24 * 60 * 60),
+            sizes,
+        });
+        cache.evict_from_disk(SystemTime::now(), None);
+        info!("Opened logo cache in {} with {} logos", dir.display(), cache.entries.len());
+        Ok(cache)
+    }
+
+    // Entry for a key whether or not it is still fresh (stale entries drive conditional refreshes)
+    pub fn entry(&self, key: &str) -> Option<&LogoEntry> {
+        self.entries.get(key)
+    }
+
+    // Image bytes for a hash, read back from disk if they were evicted from memory
+    pub fn image(&mut self, hash: &str) -> Option<Arc<Vec<u8>>> {
+        self.clock += 1;
+        if let Some(blob) = self.blobs.get_mut(hash) {
+            blob.last_used = self.clock;
+            return Some(blob.bytes.clone());
+        }
+        let bytes = self.disk.as_ref()?.read(hash)?;
+        let bytes = self.hold(hash, bytes);
+        self.evict_from_memory(hash);
+        Some(bytes)
+    }
+
+    // Hash and image bytes for a key cached within the TTL
+    pub fn fresh(&mut self, key: &str, now: SystemTime) -> Option<(String, Arc<Vec<u8>>)> {
+        let entry = self.entries.get_mut(key).filter(|entry| entry.is_fresh(now))?;
+        entry.last_used = now;
+        let hash = entry.hash.clone();
+        let image = self.image(&hash)?;
+        Some((hash, image))
+    }
+
+    // Store an image under a key and return its content hash
+    pub fn insert(
+        &mut self,
+        key: &str,
+        bytes: Vec<u8>,
+        cdn_etag: Option<String>,
+        cdn_last_modified: Option<String>,
+        now: SystemTime,
+    ) -> String {
+        let hash = content_hash(&bytes);
+        if let Some(disk) = &mut self.disk {
+            disk.write(&hash, &bytes);
+        }
+        if !self.blobs.contains_key(&hash) {
+            self.hold(&hash, bytes);
+        }
+        let entry = LogoEntry {
+            hash: hash.clone(),
+            fetched_at: now,
+            cdn_etag,
+            cdn_last_modified,
+            last_used: now,
+        };
+        if let Some(previous) = self.entries.insert(key.to_string(), entry) {
+            self.release(&previous.hash);
+        }
+        self.evict_from_disk(now, Some(key));
+        self.evict_from_memory(&hash);
+        hash
+    }
+
+    // The CDN confirmed the cached image is unchanged (304): keep serving it for another TTL
+    pub fn revalidated(&mut self, key: &str, now: SystemTime) -> Option<(String, Arc<Vec<u8>>)> {
+        let entry = self.entries.get_mut(key)?;
+        entry.fetched_at = now;
+        entry.last_used = now;
+        let hash = entry.hash.clone();
+        self.save_index();
+        let image = self.image(&hash)?;
+        Some((hash, image))
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn blob_count(&self) -> usize {
+        self.blobs.len()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    fn hold(&mut self, hash: &str, bytes: Vec<u8>) -> Arc<Vec<u8>> {
+        self.clock += 1;
+        self.memory_bytes += bytes.len();
+        let bytes = Arc::new(bytes);
+        self.blobs.insert(hash.to_string(), Blob { bytes: bytes.clone(), last_used: self.clock });
+        bytes
+    }
+
+    fn drop_from_memory(&mut self, hash: &str) {
+        if let Some(blob) = self.blobs.remove(hash) {
+            self.memory_bytes -= blob.bytes.len();
+        }
+    }
+
+    // Drop an image once no key refers to it any more
+    fn release(&mut self, hash: &str) {
+        if !self.entries.values().any(|entry| entry.hash == hash) {
+            self.drop_from_memory(hash);
+            if let Some(disk) = &mut self.disk {
+                disk.remove(hash);
+            }
+        }
+    }
+
+    // Least recently used images leave memory until it is within budget. Keys of images that
+    // aren't on disk go too, so they are fetched again rather than revalidated.
+    fn evict_from_memory(&mut self, keep: &str) {
+        while self.memory_bytes > self.memory_budget_bytes {
+            let Some(hash) = self
+                .blobs
+                .iter()
+                .filter(|(hash, _)| hash.as_str() != keep)
+                .min_by_key(|(_, blob)| blob.last_used)
+                .map(|(hash, _)| hash.clone())
+            else {
+                break;
+            };
+            self.drop_from_memory(&hash);
+            if !self.disk.as_ref().is_some_and(|disk| disk.sizes.contains_key(&hash)) {
+                self.entries.retain(|_, entry| entry.hash != hash);
+            }
+        }
+    }
+
+    // Logos unused for the max age leave the cache directory, then the least recently used
+    // ones until it is within budget. Saves the index either way.
+    fn evict_from_disk(&mut self, now: SystemTime, keep: Option<&str>) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        let max_age = disk.max_age;
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(key, entry)| Some(key.as_str()) != keep && now.duration_since(entry.last_used).unwrap_or(Duration::ZERO) > max_age)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.remove_entry(&key);
+        }
+        while self.disk.as_ref().is_some_and(|disk| disk.total_bytes() > disk.budget_bytes) {
+            let Some(key) = self
+                .entries
+                .iter()
+                .filter(|(key, _)| Some(key.as_str()) != keep)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove_entry(&key);
+        }
+        self.save_index();
+    }
+
+    fn remove_entry(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.release(&entry.hash);
+        }
+    }
+
+    fn save_index(&self) {
+        if let Some(disk) = &self.disk {
+            disk.save_index(&self.entries);
+        }
+    }
+}
+
+// Logo sizes served by the CMC image CDN
+pub const LOGO_SIZES: [u32; 5] = [16, 32, 64, 128, 200];
+pub const DEFAULT_LOGO_SIZE: u32 = 64;
+
+#[derive(Debug)]
+pub enum LogoError {
+    NoMapping,
+    NotFound,
+    ReadFailed,
+    FetchFailed,
+}
+
+// 64px PNGs keep the plain symbol key used before other sizes and formats existed
+fn logo_cache_key(symbol: &str, size: u32, format: LogoFormat) -> String {
+    let key = if size == DEFAULT_LOGO_SIZE {
+        symbol.to_string()
+    } else {
+        format!("{}@{}", symbol, size)
+    };
+    match format {
+        LogoFormat::Png => key,
+        LogoFormat::Webp => format!("{}.webp", key),
+    }
+}
+
+// Serve a logo from the logo cache (24 hour expiry) or fetch it from CoinMarketCap.
+// Expired entries are refreshed with a conditional request, so an unchanged logo costs a 304.
+// Returns the content hash along with the image bytes.
+pub async fn load_logo(data: &web::Data<AppState>, symbol: &str, size: u32, format: LogoFormat) -> Result<(String, Arc<Vec<u8>>), LogoError> {
+    use reqwest::StatusCode;
+    use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+    // Logos are cached under the canonical symbol however the coin was named
+    let symbol = &data.canonical_symbol(symbol);
+    let cache_key = logo_cache_key(symbol, size, format);
+    let stale = {
+        let mut cache = data.logo_cache.lock().unwrap();
+        if let Some(cached) = cache.fresh(&cache_key, SystemTime::now()) {
+            return Ok(cached);
+        }
+        cache.entry(&cache_key).cloned()
+    };
+
+    // Another instance may already have fetched it
+    if let Some(image_data) = data.cluster_cache.logo(&cache_key).await {
+        let mut cache = data.logo_cache.lock().unwrap();
+        let hash = cache.insert(&cache_key, image_data, None, None, SystemTime::now());
+        return cache.image(&hash).map(|image| (hash, image)).ok_or(LogoError::ReadFailed);
+    }
+
+    // Coins CMC never mapped can still have a logo from a coin CoinGecko listed
+    let identity = data.identity_map.lock().unwrap().by_symbol(symbol).cloned().unwrap_or_else(|| CoinIdentity {
+        symbol: symbol.clone(),
+        name: symbol.clone(),
+        cmc_id: None,
+        slug: None,
+        coingecko_id: None,
+    });
+    let Some(logo_url) = data.market_data.logo_url(&identity, size, format) else {
+        warn!("No {} logo source found for symbol: {}", format.extension(), symbol);
+        return Err(LogoError::NoMapping);
+    };
+
+    // Fetch from the provider's CDN, revalidating against its validators when we have them
+    let mut request = data.client.get(&logo_url);
+    if let Some(entry) = &stale {
+        if let Some(etag) = &entry.cdn_etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.cdn_last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    match send_with_retry(request, &data.retry_policy).await {
+        Ok(response) if response.status() == StatusCode::NOT_MODIFIED && stale.is_some() => {
+            let mut cache = data.logo_cache.lock().unwrap();
+            cache.revalidated(&cache_key, SystemTime::now()).ok_or(LogoError::NotFound)
+        }
+        Ok(response) if response.status().is_success() => {
+            let header_value = |name| {
+                response.headers().get(name)
+                    .and_then(|value: &HeaderValue| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            let cdn_etag = header_value(ETAG);
+            let cdn_last_modified = header_value(LAST_MODIFIED);
+            match response.bytes().await {
+                Ok(image_data) => {
+                    data.cluster_cache.store_logo(&cache_key, image_data.to_vec()).await;
+                    let mut cache = data.logo_cache.lock().unwrap();
+                    let hash = cache.insert(&cache_key, image_data.to_vec(), cdn_etag, cdn_last_modified, SystemTime::now());
+                    let image = cache.image(&hash).ok_or(LogoError::ReadFailed)?;
+                    info!(
+                        "Cached logo {} ({} logos, {} images in memory, {} KB)",
+                        cache_key, cache.key_count(), cache.blob_count(), cache.memory_bytes() / 1024
+                    );
+                    Ok((hash, image))
+                }
+                Err(e) => {
+                    warn!("Failed to read logo image bytes for {}: {}", symbol, e);
+                    Err(LogoError::ReadFailed)
+                }
+            }
+        }
+        Ok(response) => {
+            warn!("Logo request failed with status {} for symbol: {}", response.status(), symbol);
+            Err(LogoError::NotFound)
+        }
+        Err(e) => {
+            warn!("Failed to fetch logo for symbol {}: {}", symbol, e);
+            Err(LogoError::FetchFailed)
+        }
+    }
+}
+
+// Hex-encoded SHA-256 of the image bytes
+// Image files are named by their content hash, 64 lowercase hex digits
+fn is_image_file_name(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Strong ETag header value for a content hash
+pub fn etag_for(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+// Whether an If-None-Match header value matches our ETag ("*", a list, or weak forms)
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// Image formats a logo can be served in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogoFormat {
+    Png,
+    Webp,
+}
+
+impl LogoFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            LogoFormat::Png => "image/png",
+            LogoFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            LogoFormat::Png => "png",
+            LogoFormat::Webp => "webp",
+        }
+    }
+
+    // WebP when the Accept header lists image/webp without refusing it (q=0), otherwise PNG
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let accepts_webp = accept.unwrap_or_default().split(',').any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            params.next().is_some_and(|media| media.eq_ignore_ascii_case("image/webp"))
+                && !params.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q <= 0.0)
+                })
+        });
+        if accepts_webp {
+            LogoFormat::Webp
+        } else {
+            LogoFormat::Png
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_images_are_stored_once() {
+        let mut cache = LogoCache::new();
+        let now = SystemTime::now();
+        let a = cache.insert("BTC", b"same".to_vec(), None, None, now);
+        let b = cache.insert("WBTC", b"same".to_vec(), None, None, now);
+        assert_eq!(a, b);
+        assert_eq!(cache.key_count(), 2);
+        assert_eq!(cache.blob_count(), 1);
+
+        // Replacing one key keeps the shared image alive for the other
+        cache.insert("BTC", b"new".to_vec(), None, None, now);
+        assert_eq!(cache.blob_count(), 2);
+        cache.insert("WBTC", b"new".to_vec(), None, None, now);
+        assert_eq!(cache.blob_count(), 1);
+    }
+
+    #[test]
+    fn test_freshness_and_revalidation() {
+        let mut cache = LogoCache::new();
+        let fetched = SystemTime::now();
+        cache.insert("ETH", b"png".to_vec(), Some("\"cdn-1\"".to_string()), None, fetched);
+
+        let later = fetched + LOGO_CACHE_TTL + Duration::from_secs(1);
+        assert!(cache.fresh("ETH", fetched).is_some());
+        assert!(cache.fresh("ETH", later).is_none());
+        assert_eq!(cache.entry("ETH").unwrap().cdn_etag.as_deref(), Some("\"cdn-1\""));
+
+        let (hash, bytes) = cache.revalidated("ETH", later).unwrap();
+        assert_eq!(hash, content_hash(b"png"));
+        assert_eq!(bytes.as_slice(), b"png");
+        assert!(cache.fresh("ETH", later).is_some());
+    }
+
+    #[test]
+    fn test_memory_budget_evicts_least_recently_used() {
+        let mut cache = LogoCache::open(&LogoCacheConfig { memory_budget_mb: 0, ..LogoCacheConfig::default() }).unwrap();
+        cache.memory_budget_bytes = 8;
+        let now = SystemTime::now();
+        cache.insert("BTC", b"btc-".to_vec(), None, None, now);
+        cache.insert("ETH", b"eth-".to_vec(), None, None, now);
+        assert!(cache.fresh("BTC", now).is_some());
+        cache.insert("SOL", b"sol-".to_vec(), None, None, now);
+
+        // ETH was used least recently, and without a directory it has to be fetched again
+        assert_eq!(cache.memory_bytes(), 8);
+        assert!(cache.fresh("ETH", now).is_none());
+        assert!(cache.entry("ETH").is_none());
+        assert!(cache.fresh("BTC", now).is_some());
+    }
+
+    #[test]
+    fn test_disk_cache_survives_restart_and_evicts() {
+        let dir = std::env::temp_dir().join(format!("coin-crab-logos-{}", std::process::id()));
+        let config = LogoCacheConfig { dir: Some(dir.to_string_lossy().into_owned()), ..LogoCacheConfig::default() };
+        let now = SystemTime::now();
+        {
+            let mut cache = LogoCache::open(&config).unwrap();
+            cache.insert("BTC", b"btc".to_vec(), Some("\"cdn-1\"".to_string()), None, now);
+            cache.insert("ETH", b"eth".to_vec(), None, None, now);
+        }
+        std::fs::write(dir.join("stray"), b"not a logo").unwrap();
+        std::fs::write(dir.join(content_hash(b"orphan")), b"orphan").unwrap();
+
+        let mut cache = LogoCache::open(&config).unwrap();
+        assert_eq!(cache.key_count(), 2);
+        assert_eq!(cache.blob_count(), 0);
+        // Only image files no entry refers to are removed
+        assert!(!dir.join(content_hash(b"orphan")).exists());
+        assert!(dir.join("stray").exists());
+        let (hash, bytes) = cache.fresh("BTC", now).unwrap();
+        assert_eq!(hash, content_hash(b"btc"));
+        assert_eq!(bytes.as_slice(), b"btc");
+        assert_eq!(cache.entry("BTC").unwrap().cdn_etag.as_deref(), Some("\"cdn-1\""));
+
+        // Evicted from memory, read back from disk
+        cache.memory_budget_bytes = 0;
+        cache.insert("SOL", b"sol".to_vec(), None, None, now);
+        assert_eq!(cache.blob_count(), 1);
+        assert_eq!(cache.fresh("ETH", now).unwrap().1.as_slice(), b"eth");
+
+        // Unused past the max age
+        let later = now + Duration::from_secs(31 * 24 * 60 * 60);
+        cache.insert("ADA", b"ada".to_vec(), None, None, later);
+        assert_eq!(cache.key_count(), 1);
+        assert!(!dir.join(content_hash(b"btc")).exists());
+
+        // Over the disk budget, least recently used first
+        cache.disk.as_mut().unwrap().budget_bytes = 6;
+        cache.insert("DOT", b"dot".to_vec(), None, None, later + Duration::from_secs(1));
+        assert!(cache.fresh("ADA", later + Duration::from_secs(2)).is_some());
+        cache.insert("XRP", b"xrp".to_vec(), None, None, later + Duration::from_secs(3));
+        assert!(cache.entry("DOT").is_none());
+        assert!(cache.entry("ADA").is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_etag_matching() {
+        let etag = etag_for(&content_hash(b"png"));
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert!(etag_matches(&etag, &etag));
+        assert!(etag_matches(&format!("\"other\", W/{}", etag), &etag));
+        assert!(etag_matches("*", &etag));
+        assert!(!etag_matches("\"other\"", &etag));
+    }
+
+    #[test]
+    fn test_format_negotiation() {
+        assert_eq!(LogoFormat::negotiate(None), LogoFormat::Png);
+        assert_eq!(LogoFormat::negotiate(Some("image/png,*/*")), LogoFormat::Png);
+        assert_eq!(LogoFormat::negotiate(Some("image/avif, image/webp, */*;q=0.8")), LogoFormat::Webp);
+        assert_eq!(LogoFormat::negotiate(Some("Image/WebP;q=0.5")), LogoFormat::Webp);
+        assert_eq!(LogoFormat::negotiate(Some("image/webp;q=0, image/png")), LogoFormat::Png);
+    }
+}
@@ -1,12 +1,11 @@
 // Server Main - Modular Architecture
 // Main server entry point that coordinates all modules
 
-use actix_web::{web, App, HttpServer, middleware::Logger};
-use reqwest::Client;
+use actix_web::{web, App, HttpServer, middleware::{from_fn, Condition, Logger}};
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::collections::HashMap;
-use log::{info, error};
+use tracing::{info, warn, error};
 
 // Module declarations
 mod types;
@@ -14,39 +13,139 @@ mod config;
 mod handlers;
 mod mqtt;
 mod data;
+mod http_client;
+mod leader;
+mod logos;
+mod stats;
+mod identity;
+mod providers;
+mod persistence;
+mod fields;
+mod storage;
+mod gaps;
+mod anomalies;
+mod cluster_cache;
+mod summary;
+mod indicators;
+mod verify;
+mod watchlists;
+mod fx;
+mod portfolio;
+mod pinned;
+mod coalesce;
+mod movers;
+mod search;
+mod alerts;
+mod graphql;
+mod reload;
+mod auth;
+mod http_rate_limit;
+mod cors;
 
 // Import our modules
 use types::AppState;
-use config::ServerConfig;
-use handlers::{get_prices, health_check, get_historical_data, get_cmc_mapping, get_crypto_logo};
-use mqtt::{setup_mqtt_broker, setup_mqtt_request_handling, clear_all_retained_messages};
-use data::{fetch_data_periodically, clear_mqtt_cache_periodically, fetch_cmc_mapping};
+use config::{LogoCacheConfig, ServerConfig};
+use handlers::{get_prices, get_movers, get_coin_detail, get_price_stats, health_check, get_historical_data, get_historical_since, get_historical_batch, get_indicators, get_watchlist, put_watchlist, get_portfolio, add_portfolio_holding, remove_portfolio_holding, dead_letter_admin, get_pinned, pin_symbol, unpin_symbol, get_cmc_mapping, search_symbols, get_coin_identities, get_coin_identity, get_crypto_logo, get_logo_bundle};
+use mqtt::{enforce_topic_acl, setup_mqtt_broker, setup_mqtt_request_handling, clear_all_retained_messages, publish_topic_catalog, publish_server_status, compact_retained_periodically, set_dry_run, set_topic_prefix, set_price_delta_mode, set_price_ttl, set_msgpack_payloads, set_indicator_topics, set_dead_letter_log, DeadLetterLog};
+use mqtt::capabilities::ClientCapabilityRegistry;
+use data::{fetch_data_periodically, fetch_hot_tier_periodically, expire_retained_history_periodically, prewarm_pinned_history_periodically, fetch_cmc_mapping, fetch_coingecko_ids};
+use http_client::{build_http_client, RetryPolicy};
+use leader::{LeaderElection, run_leader_election};
+use logos::LogoCache;
+use identity::IdentityMap;
+use providers::{run_binance_stream, MarketData};
+use persistence::{flush_cache_periodically, CacheBackend, JsonFileBackend, WriteBehindCache};
+use fields::set_default_quote_fields;
+use storage::{backfill_history_periodically, open_store, Storage};
+use gaps::{repair_gaps_periodically, GapRepairs};
+use anomalies::PriceScreen;
+use cluster_cache::ClusterCache;
+use summary::run_daily_summary;
+use alerts::AlertEngine;
+use watchlists::WatchlistRegistry;
+use fx::{refresh_fx_rates_periodically, FxRates};
+use portfolio::PortfolioStore;
+use pinned::PinnedSymbols;
+use coalesce::InFlight;
+use verify::{parse_verify_args, run_verify_history};
+use graphql::{build_schema, graphql_query};
+use reload::reload_config_on_change;
+use auth::{require_api_auth, HttpAuth};
+use http_rate_limit::HistoricalRateLimit;
+use cors::build_cors;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Load configuration
-    let config = ServerConfig::load().map_err(|e| {
+    let mut config = ServerConfig::load().map_err(|e| {
         eprintln!("Failed to load server configuration: {}", e);
-        std::io::Error::new(std::io::ErrorKind::Other, e)
+        std::io::Error::other(e)
     })?;
     
     // Setup logging
     config.setup_logging();
+
+    // One-off maintenance commands run instead of the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("verify-history") {
+        let broken = match parse_verify_args(&args[1..]) {
+            Ok(options) => run_verify_history(&config, &options).await,
+            Err(e) => Err(e),
+        }
+        .map_err(|e| {
+            eprintln!("verify-history failed: {}", e);
+            std::io::Error::other(e)
+        })?;
+        std::process::exit(if broken { 1 } else { 0 });
+    }
+
+    set_dry_run(config.dry_run);
+    set_topic_prefix(&config.topic_prefix);
+    set_price_delta_mode(&config.price_delta);
+    set_price_ttl(config.price_ttl_seconds, config.snapshot_ttl_seconds());
+    set_msgpack_payloads(config.msgpack_payloads);
+    set_indicator_topics(config.indicator_topics);
+    set_default_quote_fields(config.payload_fields.clone());
+    let dead_letters = Arc::new(DeadLetterLog::new(&config.dead_letters));
+    match dead_letters.load() {
+        Ok(0) => {}
+        Ok(count) => warn!("{} dead-lettered publishes waiting for replay", count),
+        Err(e) => error!("Failed to load dead-letter log: {}", e),
+    }
+    set_dead_letter_log(dead_letters);
+    if !config.topic_prefix.is_empty() {
+        info!("Using MQTT topic prefix: {}", config.topic_prefix);
+    }
+    if config.dry_run {
+        tracing::warn!("Dry-run mode enabled: data will be fetched and cached but MQTT publishes are only logged");
+    }
+    
+    // Decide leadership before touching the broker so a standby never clears the leader's retained data
+    let instance_id = format!("{}-{}", config.http_client.instance_id, std::process::id());
+    let leader = Arc::new(LeaderElection::new(&config.leader, &instance_id));
+    if leader.try_acquire() {
+        info!("Instance {} is the leader", leader.instance_id());
+    }
     
     // Setup MQTT broker and client
-    let mqtt_client = match setup_mqtt_broker(&config.mqtt_broker_host, config.mqtt_broker_port).await {
-        Ok(client) => {
+    let mqtt_client = match setup_mqtt_broker(&config.mqtt_bind_address, &config.mqtt_broker_host, config.mqtt_broker_port, &config.mqtt_tls).await {
+        Ok((client, port)) => {
             info!("MQTT broker and client setup complete");
+            // MQTT_BROKER_PORT=0 lets the broker pick a port; the request handler connects to it
+            config.mqtt_broker_port = port;
 
             // Clear any retained messages from previous sessions
-            info!("Clearing retained messages from broker...");
-            clear_all_retained_messages(&client).await;
+            if leader.is_leader() {
+                info!("Clearing retained messages from broker...");
+                clear_all_retained_messages(&client).await;
+                publish_topic_catalog(&client).await;
+            }
 
             client
         }
         Err(e) => {
-            log::error!("Failed to setup MQTT broker: {}", e);
-            log::warn!("Falling back to HTTP-only mode");
+            tracing::error!("Failed to setup MQTT broker: {}", e);
+            tracing::warn!("Falling back to HTTP-only mode");
             // Create a dummy client as fallback
             use rumqttc::MqttOptions;
             let mqttoptions = MqttOptions::new("dummy-client", &config.mqtt_broker_host, config.mqtt_broker_port + 1);
@@ -55,58 +154,266 @@ async fn main() -> std::io::Result<()> {
         }
     };
     
+    let http_client = build_http_client(&config.http_client).map_err(|e| {
+        error!("{}", e);
+        std::io::Error::other(e)
+    })?;
+    
+    let identity_map = Arc::new(Mutex::new(IdentityMap::new()));
+    let retry_policy = RetryPolicy::from_config(&config.http_client);
+    let market_data = MarketData::from_config(&config.market_data, &config.api_key, &http_client, retry_policy, identity_map.clone());
+    
+    let cache_backend = config.cache_persistence.path.as_ref().map(|path| {
+        info!("Persisting caches to {}", path);
+        Arc::new(JsonFileBackend::new(path)) as Arc<dyn CacheBackend>
+    });
+    
+    // An unusable cache directory only costs re-downloading logos after restarts
+    let logo_cache = LogoCache::open(&config.logo_cache).unwrap_or_else(|e| {
+        error!("Failed to open logo cache directory, keeping logos in memory only: {}", e);
+        LogoCache::open(&LogoCacheConfig { dir: None, ..config.logo_cache.clone() }).unwrap_or_default()
+    });
+    
+    // A store that can't be opened only costs the warm start; history is fetched as before
+    let storage = match open_store(&config.storage).await {
+        Ok(store) => {
+            if let Some(store) = &store {
+                info!("Storing historical data and coin mapping in {}", store.name());
+            }
+            store
+        }
+        Err(e) => {
+            error!("Failed to open the storage database: {}", e);
+            None
+        }
+    };
+    
+    // Without Redis each instance keeps its own caches, as a single server does
+    let cluster_cache = match ClusterCache::connect(&config.cluster_cache).await {
+        Ok(cache) => {
+            if cache.is_enabled() {
+                info!("Sharing prices, coin mapping and logos through Redis");
+            }
+            cache
+        }
+        Err(e) => {
+            error!("Failed to connect to Redis: {}", e);
+            ClusterCache::disabled()
+        }
+    };
+    
+    let (settings, settings_receiver) = tokio::sync::watch::channel(config.reloadable());
     let state = web::Data::new(AppState {
         cache: Arc::new(Mutex::new(None)),
         last_fetch: Arc::new(Mutex::new(SystemTime::now())),
-        client: Client::new(),
-        api_key: config.api_key,
+        client: http_client,
+        retry_policy,
+        market_data: Arc::new(market_data),
         mqtt_client,
         historical_cache: Arc::new(Mutex::new(HashMap::new())),
-        update_interval_seconds: config.update_interval_seconds,
+        settings: settings_receiver,
         cmc_mapping: Arc::new(Mutex::new(HashMap::new())),
-        logo_cache: Arc::new(Mutex::new(HashMap::new())),
+        identity_map,
+        logo_cache: Arc::new(Mutex::new(logo_cache)),
+        leader: leader.clone(),
+        persistence: Arc::new(WriteBehindCache::new(cache_backend)),
+        storage: Arc::new(Storage::new(storage)),
+        cluster_cache: Arc::new(cluster_cache),
+        alerts: Arc::new(AlertEngine::new(&config.alerts)),
+        watchlists: Arc::new(WatchlistRegistry::new(config.watchlist_max_symbols)),
+        fx: Arc::new(FxRates::new(&config.fx)),
+        portfolios: Arc::new(PortfolioStore::new(&config.portfolio)),
+        pinned: Arc::new(PinnedSymbols::new(&config.pinned)),
+        historical_in_flight: Arc::new(InFlight::new()),
+        gap_repairs: Arc::new(GapRepairs::default()),
+        price_screen: Arc::new(PriceScreen::new(config.anomalies.clone())),
+        client_capabilities: Arc::new(ClientCapabilityRegistry::new()),
     });
     
+    // Serve the last known prices and history until the first fetches complete
+    if let Err(e) = state.persistence.restore(&state).await {
+        error!("Failed to restore persisted cache: {}", e);
+    }
+    if let Err(e) = state.storage.warm(&state).await {
+        error!("Failed to warm historical cache from the store: {}", e);
+    }
+    match state.alerts.load() {
+        Ok(0) => {}
+        Ok(count) => info!("Loaded {} alert rules", count),
+        Err(e) => error!("Failed to load alert rules: {}", e),
+    }
+    match state.portfolios.load() {
+        Ok(0) => {}
+        Ok(count) => info!("Loaded {} portfolios", count),
+        Err(e) => error!("Failed to load portfolios: {}", e),
+    }
+    match state.pinned.load() {
+        Ok(0) => {}
+        Ok(count) => info!("Loaded {} pinned symbols", count),
+        Err(e) => error!("Failed to load pinned symbols: {}", e),
+    }
+    
     // Setup MQTT request handling now that AppState is created
-    if let Err(e) = setup_mqtt_request_handling(state.clone()).await {
-        log::error!("Failed to setup MQTT request handling: {}", e);
-        log::warn!("MQTT requests will not be processed");
+    if let Err(e) = setup_mqtt_request_handling(state.clone(), &config).await {
+        tracing::error!("Failed to setup MQTT request handling: {}", e);
+        tracing::warn!("MQTT requests will not be processed");
+    }
+    
+    // Fetch CMC mapping at startup, unless another instance fetched it recently
+    let shared_mappings = state.cluster_cache.restore_mapping(&state).await;
+    if shared_mappings > 0 {
+        info!("Using {} CMC mappings from the shared cache", shared_mappings);
+    } else {
+        info!("Fetching CMC mapping data at startup...");
+        match fetch_cmc_mapping(state.clone()).await {
+            Ok(()) => {
+                // Attaches CoinGecko ids to the coins CMC just mapped
+                if let Err(e) = fetch_coingecko_ids(state.clone()).await {
+                    tracing::warn!("Failed to fetch CoinGecko coin list at startup: {}", e);
+                }
+                state.storage.save_identities(&state).await;
+                state.cluster_cache.store_mapping(&state).await;
+            }
+            Err(e) => {
+                error!("Failed to fetch CMC mapping at startup: {}", e);
+                match state.storage.restore_identities(&state).await {
+                    Ok(0) => info!("Server will start with empty mapping - mappings can be updated later"),
+                    Ok(count) => info!("Using {} stored coin mappings until the next successful fetch", count),
+                    Err(e) => error!("Failed to load stored coin mapping: {}", e),
+                }
+            }
+        }
     }
     
-    // Fetch CMC mapping at startup
-    info!("Fetching CMC mapping data at startup...");
-    if let Err(e) = fetch_cmc_mapping(state.clone()).await {
-        error!("Failed to fetch CMC mapping at startup: {}", e);
-        info!("Server will start with empty mapping - mappings can be updated later");
+    let mut unmapped = Vec::new();
+    settings.send_modify(|current| unmapped = current.tracked.retain_mapped(&state.identity_map.lock().unwrap()));
+    if !unmapped.is_empty() {
+        warn!("Not tracking symbols missing from the CMC mapping: {}", unmapped.join(","));
     }
     
+    tokio::spawn(run_leader_election(leader));
+    
     let state_clone = state.clone();
     tokio::spawn(async move {
         fetch_data_periodically(state_clone).await;
     });
     
-    // Spawn historical data publishing task (every hour)
-    let state_clone_hist = state.clone();
-    tokio::spawn(async move {
-        clear_mqtt_cache_periodically(state_clone_hist).await;
-    });
+    tokio::spawn(fetch_hot_tier_periodically(state.clone()));
+    
+    tokio::spawn(prewarm_pinned_history_periodically(state.clone()));
+    
+    tokio::spawn(backfill_history_periodically(state.clone(), config.backfill.clone()));
+    
+    tokio::spawn(repair_gaps_periodically(state.clone()));
+    
+    tokio::spawn(run_binance_stream(state.clone(), config.binance_stream.clone()));
     
-    info!("Starting crypto market data server on http://127.0.0.1:{}", config.http_icon_port);
-    info!("MQTT broker listening on {}:{}", config.mqtt_broker_host, config.mqtt_broker_port);
+    tokio::spawn(flush_cache_periodically(state.clone(), config.cache_persistence.flush_interval_seconds));
+    
+    tokio::spawn(run_daily_summary(state.clone(), config.daily_summary.clone()));
+    tokio::spawn(refresh_fx_rates_periodically(state.clone(), config.fx.clone()));
+    tokio::spawn(compact_retained_periodically(
+        state.clone(),
+        config.retained_compaction_interval_seconds,
+        config.mqtt_broker_host.clone(),
+        config.mqtt_broker_port,
+        config.mqtt_tls.enabled,
+    ));
+    
+    tokio::spawn(expire_retained_history_periodically(state.clone()));
+    
+    tokio::spawn(enforce_topic_acl(
+        state.clone(),
+        config.topic_acl,
+        config.mqtt_broker_host.clone(),
+        config.mqtt_broker_port,
+        config.mqtt_tls.enabled,
+    ));
+    
+    tokio::spawn(reload_config_on_change(state.clone(), settings, config.config_file.clone()));
+    
+    info!("Starting crypto market data server on http://{}:{}", config.http_bind_address, config.http_icon_port);
+    info!("MQTT broker listening on {}:{}", config.mqtt_bind_address, config.mqtt_broker_port);
+    if config.mqtt_tls.enabled {
+        info!("MQTTS listening on {}:{}", config.mqtt_bind_address, config.mqtt_tls.port);
+    }
     info!("MQTT broker console on 127.0.0.1:3030");
     info!("Ready to accept connections...");
     
+    let shutdown_state = state.clone();
+    let schema = web::Data::new(build_schema());
+    let http_auth = web::Data::new(HttpAuth::new(&config.http_auth));
+    if http_auth.is_enabled() {
+        info!("REST API requires an API key or JWT (exempt: {})", config.http_auth.exempt_paths.join(", "));
+    } else {
+        warn!("REST API is open - set HTTP_API_KEYS or HTTP_JWT_SECRET to require authentication");
+    }
+    // Replaying and discarding dead letters is for operators only
+    let admin_routes = http_auth.is_enabled() && !http_auth.is_exempt("/api/admin/dead-letters");
+    if !admin_routes {
+        info!("Dead-letter admin endpoints disabled - they need REST API authentication");
+    }
+    let historical_rate_limit = web::Data::new(HistoricalRateLimit::new(&config.historical_rate_limit));
+    if config.historical_rate_limit.requests_per_minute > 0 {
+        info!("Limiting historical loads to {} series per minute per caller (burst {})",
+              config.historical_rate_limit.requests_per_minute, config.historical_rate_limit.burst);
+    }
+    let cors = config.cors.clone();
+    if cors.is_enabled() {
+        info!("REST API allows cross-origin requests from {}", cors.allowed_origins.join(", "));
+    }
     HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
+            .app_data(schema.clone())
+            .app_data(http_auth.clone())
+            .app_data(historical_rate_limit.clone())
+            // Middleware registered later runs first: logger, CORS (answering preflights), then
+            // authentication, which identifies the caller for the historical limit in the handlers
+            .wrap(from_fn(require_api_auth))
+            .wrap(Condition::new(cors.is_enabled(), build_cors(&cors)))
             .wrap(Logger::default())
             .service(get_prices)
+            .service(get_movers)
+            .service(get_coin_detail)
+            .service(get_price_stats)
             .service(health_check)
+            .service(get_historical_batch)
+            .service(get_historical_since)
             .service(get_historical_data)
+            .service(get_indicators)
+            .service(get_watchlist)
+            .service(put_watchlist)
+            .service(get_portfolio)
+            .service(add_portfolio_holding)
+            .service(remove_portfolio_holding)
+            .configure(|cfg| if admin_routes { dead_letter_admin(cfg) })
+            .service(get_pinned)
+            .service(pin_symbol)
+            .service(unpin_symbol)
             .service(get_cmc_mapping)
+            .service(search_symbols)
+            .service(get_coin_identities)
+            .service(get_coin_identity)
             .service(get_crypto_logo)
+            .service(get_logo_bundle)
+            .service(graphql_query)
     })
-    .bind(("0.0.0.0", config.http_icon_port))?
+    .bind((config.http_bind_address.as_str(), config.http_icon_port))?
     .run()
-    .await
+    .await?;
+    
+    // Write out whatever changed since the last background flush
+    if shutdown_state.persistence.is_dirty() {
+        info!("Persisting cache changes before exit...");
+        if let Err(e) = shutdown_state.persistence.flush(&shutdown_state).await {
+            error!("Failed to persist cache on shutdown: {}", e);
+        }
+    }
+    // A clean exit never triggers the publisher's Last Will, so say so ourselves
+    let offline = publish_server_status(&shutdown_state.mqtt_client, shared::ServerState::Offline);
+    if tokio::time::timeout(Duration::from_secs(2), offline).await.is_err() {
+        warn!("Timed out publishing offline server status");
+    }
+    Ok(())
 }
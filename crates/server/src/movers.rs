@@ -0,0 +1,134 @@
+use serde::Serialize;
+use crate::types::CryptoCurrency;
+
+// Coins published on crypto/trending/{gainers,losers} per window
+pub const TRENDING_COUNT: usize = 10;
+// Upper bound for /api/movers?limit=
+pub const MAX_MOVERS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoverWindow {
+    Hour,
+    Day,
+}
+
+impl MoverWindow {
+    pub fn parse(window: &str) -> Option<Self> {
+        match window {
+            "1h" => Some(MoverWindow::Hour),
+            "24h" => Some(MoverWindow::Day),
+            _ => None,
+        }
+    }
+
+    fn change(&self, coin: &CryptoCurrency) -> f64 {
+        match self {
+            MoverWindow::Hour => coin.quote.usd.percent_change_1h,
+            MoverWindow::Day => coin.quote.usd.percent_change_24h,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mover {
+    pub symbol: String,
+    pub name: String,
+    pub price: f64,
+    pub percent_change_1h: f64,
+    pub percent_change_24h: f64,
+}
+
+impl Mover {
+    fn from_coin(coin: &CryptoCurrency) -> Self {
+        Mover {
+            symbol: coin.symbol.clone(),
+            name: coin.name.clone(),
+            price: coin.quote.usd.price,
+            percent_change_1h: coin.quote.usd.percent_change_1h,
+            percent_change_24h: coin.quote.usd.percent_change_24h,
+        }
+    }
+}
+
+// Biggest gainers (largest rise first) and losers (largest fall first) over the window. A coin
+// that didn't move in a direction isn't listed for it, so either list may be short.
+pub fn rank_movers(listings: &[CryptoCurrency], window: MoverWindow, count: usize) -> (Vec<Mover>, Vec<Mover>) {
+    let mut by_change: Vec<&CryptoCurrency> = listings.iter().filter(|coin| window.change(coin).is_finite()).collect();
+    by_change.sort_by(|a, b| window.change(b).total_cmp(&window.change(a)));
+    let gainers = by_change
+        .iter()
+        .take_while(|coin| window.change(coin) > 0.0)
+        .take(count)
+        .map(|coin| Mover::from_coin(coin))
+        .collect();
+    let losers = by_change
+        .iter()
+        .rev()
+        .take_while(|coin| window.change(coin) < 0.0)
+        .take(count)
+        .map(|coin| Mover::from_coin(coin))
+        .collect();
+    (gainers, losers)
+}
+
+// Payload of crypto/trending/gainers and crypto/trending/losers: one ranking per window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrendingMovers {
+    #[serde(rename = "1h")]
+    pub hour: Vec<Mover>,
+    #[serde(rename = "24h")]
+    pub day: Vec<Mover>,
+    // Unix seconds of the listings the rankings come from
+    pub fetched_at: i64,
+}
+
+// (gainers, losers) for the trending topics
+pub fn trending_movers(listings: &[CryptoCurrency], fetched_at: i64) -> (TrendingMovers, TrendingMovers) {
+    let (hour_gainers, hour_losers) = rank_movers(listings, MoverWindow::Hour, TRENDING_COUNT);
+    let (day_gainers, day_losers) = rank_movers(listings, MoverWindow::Day, TRENDING_COUNT);
+    (
+        TrendingMovers { hour: hour_gainers, day: day_gainers, fetched_at },
+        TrendingMovers { hour: hour_losers, day: day_losers, fetched_at },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(symbol: &str, change_1h: f64, change_24h: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: change_1h,
+                    percent_change_24h: change_24h,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_rank_movers() {
+        let listings = vec![coin("BTC", 0.5, -2.0), coin("ETH", -1.5, 4.0), coin("SOL", 2.0, 9.0), coin("DOGE", 0.0, f64::NAN), coin("XRP", -0.2, -6.0)];
+        let symbols = |movers: &[Mover]| movers.iter().map(|mover| mover.symbol.clone()).collect::<Vec<_>>();
+
+        let (gainers, losers) = rank_movers(&listings, MoverWindow::Day, 10);
+        assert_eq!(symbols(&gainers), vec!["SOL", "ETH"]);
+        assert_eq!(symbols(&losers), vec!["XRP", "BTC"]);
+
+        let (gainers, losers) = rank_movers(&listings, MoverWindow::Hour, 1);
+        assert_eq!(symbols(&gainers), vec!["SOL"]);
+        assert_eq!(symbols(&losers), vec!["ETH"]);
+        assert_eq!(MoverWindow::parse("7d"), None);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use actix_web::web;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use tracing::{debug, error, info, warn};
+use crate::mqtt::broker::internal_client_host;
+use crate::types::AppState;
+use super::publisher::{prefixed_topic, publish, unprefixed_topic};
+
+// rumqttd has no per-topic ACLs, so any client could publish bogus (possibly retained) data to
+// the topics every other client caches. Until the broker can reject those publishes, the
+// leader can watch the topics and revert what it didn't send: every server publish to them is
+// fingerprinted here first, and the guard restores the server's last retained payload or
+// clears the topic. This only repairs the topic after the fact - subscribers still receive the
+// forged message - so it is a best-effort mitigation, not access control. It is on unless
+// MQTT_TOPIC_ACL=false, and relies on the leader being the only instance that publishes.
+
+// Topic filters only the server may publish to
+pub const SERVER_OWNED_FILTERS: [&str; 3] = ["crypto/prices/#", "crypto/historical/#", "crypto/assets/#"];
+
+// Fingerprints kept per topic for publishes the guard hasn't seen yet
+const RECENT_FINGERPRINTS: usize = 16;
+
+pub fn is_server_owned(topic: &str) -> bool {
+    SERVER_OWNED_FILTERS
+        .iter()
+        .any(|filter| topic.starts_with(filter.trim_end_matches('#')))
+}
+
+fn fingerprint(payload: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Default)]
+struct Published {
+    recent: VecDeque<u64>,
+    // The payload the broker should be retaining, if the server retained one
+    retained: Option<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Authentic,
+    // Not sent by the server; restore this retained payload, or clear the topic if None
+    Forged { restore: Option<Vec<u8>> },
+}
+
+// What the server published to its own topics (namespace stripped)
+#[derive(Default)]
+pub struct PublishLedger {
+    topics: Mutex<HashMap<String, Published>>,
+}
+
+impl PublishLedger {
+    // Called before the publish is sent, so the guard never sees it first
+    pub fn record(&self, topic: &str, retain: bool, payload: &[u8]) {
+        if !is_server_owned(topic) {
+            return;
+        }
+        let mut topics = self.topics.lock().unwrap();
+        let published = topics.entry(topic.to_string()).or_default();
+        if published.recent.len() == RECENT_FINGERPRINTS {
+            published.recent.pop_front();
+        }
+        published.recent.push_back(fingerprint(payload));
+        if retain {
+            published.retained = (!payload.is_empty()).then(|| payload.to_vec());
+        }
+    }
+
+    // Each recorded publish vouches for one message, so a replayed copy is caught as well
+    pub fn check(&self, topic: &str, payload: &[u8]) -> Verdict {
+        let fingerprint = fingerprint(payload);
+        let mut topics = self.topics.lock().unwrap();
+        let Some(published) = topics.get_mut(topic) else {
+            return Verdict::Forged { restore: None };
+        };
+        match published.recent.iter().position(|recent| *recent == fingerprint) {
+            Some(index) => {
+                published.recent.remove(index);
+                Verdict::Authentic
+            }
+            None => Verdict::Forged { restore: published.retained.clone() },
+        }
+    }
+
+    // A retained copy the broker sends on subscribing is authentic only if it is what the
+    // server last retained on the topic
+    pub fn check_retained(&self, topic: &str, payload: &[u8]) -> Verdict {
+        let topics = self.topics.lock().unwrap();
+        let retained = topics.get(topic).and_then(|published| published.retained.as_ref());
+        match retained {
+            Some(retained) if retained.as_slice() == payload => Verdict::Authentic,
+            _ => Verdict::Forged { restore: retained.cloned() },
+        }
+    }
+}
+
+static PUBLISH_LEDGER: LazyLock<PublishLedger> = LazyLock::new(PublishLedger::default);
+
+pub fn publish_ledger() -> &'static PublishLedger {
+    &PUBLISH_LEDGER
+}
+
+async fn revert_forged_publish(state: &AppState, topic: &str, restore: Option<Vec<u8>>) {
+    let restored = restore.is_some();
+    if let Err(e) = publish(&state.mqtt_client, topic, QoS::AtLeastOnce, true, restore.unwrap_or_default()).await {
+        error!("Failed to revert forged publish to {}: {}", topic, e);
+    } else if restored {
+        info!("Restored the server's retained message on {}", topic);
+    } else {
+        info!("Cleared forged retained message on {}", topic);
+    }
+}
+
+// Watch the server-owned topics on the leader and revert publishes the server didn't make,
+// including forged messages the broker already retained when the guard subscribed
+pub async fn enforce_topic_acl(state: web::Data<AppState>, enabled: bool, broker_host: String, broker_port: u16, tls_enabled: bool) {
+    if !enabled {
+        info!("MQTT topic ACL disabled - any client can publish to {}", SERVER_OWNED_FILTERS.join(", "));
+        return;
+    }
+    let broker_host = internal_client_host(&broker_host, tls_enabled);
+    let mut options = MqttOptions::new("crypto-server-acl", broker_host, broker_port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_clean_session(true);
+    options.set_max_packet_size(1024 * 1024, 1024 * 1024);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    // The broker can't reject these publishes, so say what the guard actually does
+    warn!("MQTT topic ACL is best-effort: other clients can still publish to {}; the leader reverts what the server didn't send", SERVER_OWNED_FILTERS.join(", "));
+
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                // Clean session, so the filters are subscribed again on every connect
+                for filter in SERVER_OWNED_FILTERS {
+                    if let Err(e) = client.try_subscribe(prefixed_topic(filter), QoS::AtMostOnce) {
+                        warn!("Failed to subscribe to {} for the topic ACL: {}", filter, e);
+                    }
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => {
+                if !state.leader.is_leader() {
+                    continue;
+                }
+                let Some(topic) = unprefixed_topic(&message.topic) else {
+                    continue;
+                };
+                let verdict = if message.retain {
+                    publish_ledger().check_retained(topic, &message.payload)
+                } else {
+                    publish_ledger().check(topic, &message.payload)
+                };
+                if let Verdict::Forged { restore } = verdict {
+                    warn!("Reverting a publish to {} that did not come from the server ({} bytes)", topic, message.payload.len());
+                    revert_forged_publish(&state, topic, restore).await;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("MQTT topic ACL connection error: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_owned_topics() {
+        assert!(is_server_owned("crypto/prices/latest"));
+        assert!(is_server_owned("crypto/historical/BTC/24h/msgpack"));
+        assert!(is_server_owned("crypto/assets/logos/BTC"));
+        assert!(!is_server_owned("crypto/requests/historical"));
+        assert!(!is_server_owned("crypto/pricesx"));
+    }
+
+    #[test]
+    fn test_only_recorded_publishes_are_authentic() {
+        let ledger = PublishLedger::default();
+        assert_eq!(ledger.check("crypto/prices/latest", b"bogus"), Verdict::Forged { restore: None });
+
+        ledger.record("crypto/prices/latest", true, b"real");
+        ledger.record("crypto/requests/historical", false, b"BTC:24h");
+        assert_eq!(ledger.check("crypto/prices/latest", b"bogus"), Verdict::Forged { restore: Some(b"real".to_vec()) });
+        assert_eq!(ledger.check("crypto/prices/latest", b"real"), Verdict::Authentic);
+        // A replayed copy of a real message is caught too
+        assert_eq!(ledger.check("crypto/prices/latest", b"real"), Verdict::Forged { restore: Some(b"real".to_vec()) });
+
+        // Clearing the topic leaves nothing to restore
+        ledger.record("crypto/prices/latest", true, b"");
+        assert_eq!(ledger.check("crypto/prices/latest", b"bogus"), Verdict::Forged { restore: None });
+    }
+
+    #[test]
+    fn test_retained_copies_must_match_the_servers() {
+        let ledger = PublishLedger::default();
+        // Retained before the server published anything, e.g. right after startup
+        assert_eq!(ledger.check_retained("crypto/historical/BTC/24h", b"bogus"), Verdict::Forged { restore: None });
+
+        ledger.record("crypto/historical/BTC/24h", true, b"series");
+        assert_eq!(ledger.check_retained("crypto/historical/BTC/24h", b"series"), Verdict::Authentic);
+        // Unlike live messages, the same retained copy can arrive again on every resubscribe
+        assert_eq!(ledger.check_retained("crypto/historical/BTC/24h", b"series"), Verdict::Authentic);
+        assert_eq!(
+            ledger.check_retained("crypto/historical/BTC/24h", b"bogus"),
+            Verdict::Forged { restore: Some(b"series".to_vec()) }
+        );
+    }
+}
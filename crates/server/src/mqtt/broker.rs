@@ -1,31 +1,129 @@
-use rumqttd::{Broker, Config as BrokerConfig};
+use rumqttd::{Broker, Config as BrokerConfig, TlsConfig};
 use rumqttc::{MqttOptions, AsyncClient, Event, Packet};
+use std::net::{SocketAddr, TcpListener};
 use std::path::Path;
 use std::thread;
 use std::time::Duration;
 use std::sync::Arc;
-use log::{info, error, debug};
+use tracing::{info, error, debug};
+use crate::config::MqttTlsConfig;
+use shared::ServerState;
+use super::presence::{publish_server_status, server_last_will};
+use super::publisher::dead_letter_unacknowledged;
+use shared::{CoinCrabError, CoinCrabResult};
 
-pub async fn setup_mqtt_broker(broker_host: &str, broker_port: u16) -> Result<Arc<AsyncClient>, String> {
-    info!("Starting embedded MQTT broker on {}:{}", broker_host, broker_port);
+// With TLS enabled the plaintext listener only accepts loopback connections, so the
+// server's own publisher/subscriber clients must connect via 127.0.0.1
+pub fn internal_client_host(broker_host: &str, tls_enabled: bool) -> String {
+    if tls_enabled {
+        "127.0.0.1".to_string()
+    } else {
+        broker_host.to_string()
+    }
+}
+
+// "host:port" for a listener; IPv6 hosts need brackets
+fn listen_address(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+// Used when rumqttd.toml isn't in the working directory, e.g. tests run from a crate dir
+const DEFAULT_BROKER_CONFIG: &str = include_str!("../../../../rumqttd.toml");
+
+// Port 0 picks a free port from the OS, so parallel test runs don't collide on 1883. The
+// probe listener is released before the broker binds, which is fine for tests but racy
+// enough that deployments should keep a fixed port.
+fn resolve_port(host: &str, port: u16) -> CoinCrabResult<u16> {
+    if port != 0 {
+        return Ok(port);
+    }
+    let address = listen_address(host, 0);
+    TcpListener::bind(&address)
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| CoinCrabError::Config(format!("No free port on {}: {}", address, e)))
+}
+
+// Add a TLS listener on the public interface and move the plaintext listener to loopback
+fn apply_tls_listener(
+    config: &mut BrokerConfig,
+    bind_address: &str,
+    broker_port: u16,
+    tls: &MqttTlsConfig,
+) -> CoinCrabResult<()> {
+    let tls_config = TlsConfig::Rustls {
+        capath: tls.ca_path.clone(),
+        certpath: tls.cert_path.clone(),
+        keypath: tls.key_path.clone(),
+    };
+    if !tls_config.validate_paths() {
+        return Err(CoinCrabError::Config(format!(
+            "MQTT TLS certificate files not found (ca={}, cert={}, key={})",
+            tls.ca_path, tls.cert_path, tls.key_path
+        )));
+    }
+
+    let plaintext = config.v4.get_mut("1")
+        .ok_or_else(|| CoinCrabError::Config("Broker config has no [v4.1] listener".to_string()))?;
+    let mut tls_listener = plaintext.clone();
+    plaintext.listen = SocketAddr::from(([127, 0, 0, 1], broker_port));
+
+    tls_listener.name = "v4-tls".to_string();
+    let address = listen_address(bind_address, tls.port);
+    tls_listener.listen = address
+        .parse()
+        .map_err(|e| CoinCrabError::Config(format!("Invalid TLS listen address {}: {}", address, e)))?;
+    tls_listener.tls = Some(tls_config);
+    config.v4.insert("tls".to_string(), tls_listener);
+    Ok(())
+}
+
+// Start the embedded broker listening on bind_address and connect the publisher client to it
+// via broker_host. Returns the client and the port the broker listens on, which differs from
+// broker_port when that is 0; the server's other MQTT clients must connect to the returned port.
+pub async fn setup_mqtt_broker(
+    bind_address: &str,
+    broker_host: &str,
+    broker_port: u16,
+    tls: &MqttTlsConfig,
+) -> CoinCrabResult<(Arc<AsyncClient>, u16)> {
+    let ephemeral = broker_port == 0;
+    // With TLS the plaintext listener moves to loopback, so that's where the port must be free
+    let broker_port = resolve_port(if tls.enabled { "127.0.0.1" } else { bind_address }, broker_port)?;
+    info!("Starting embedded MQTT broker on {}", listen_address(bind_address, broker_port));
     
     // Load configuration from file and update port dynamically
     let config_path = "rumqttd.toml";
-    if !Path::new(config_path).exists() {
-        return Err(format!("MQTT broker config file {} not found", config_path));
-    }
-    
-    let config_content = std::fs::read_to_string(config_path)
-        .map_err(|e| format!("Failed to read broker config: {}", e))?;
+    let config_content = if Path::new(config_path).exists() {
+        std::fs::read_to_string(config_path)
+            .map_err(|e| CoinCrabError::Config(format!("Failed to read broker config: {}", e)))?
+    } else {
+        info!("MQTT broker config file {} not found - using the built-in configuration", config_path);
+        DEFAULT_BROKER_CONFIG.to_string()
+    };
     
     // Replace the hardcoded port with the dynamic port
     let updated_config_content = config_content.replace(
         "listen = \"0.0.0.0:1883\"", 
-        &format!("listen = \"{}:{}\"", broker_host, broker_port)
+        &format!("listen = \"{}\"", listen_address(bind_address, broker_port))
     );
     
-    let config: BrokerConfig = toml::from_str(&updated_config_content)
-        .map_err(|e| format!("Failed to parse broker config: {}", e))?;
+    let mut config: BrokerConfig = toml::from_str(&updated_config_content)
+        .map_err(|e| CoinCrabError::Config(format!("Failed to parse broker config: {}", e)))?;
+    // A second broker on the same console port would fail to start it
+    if ephemeral {
+        config.console.listen = listen_address("127.0.0.1", resolve_port("127.0.0.1", 0)?);
+    }
+    
+    if tls.enabled {
+        apply_tls_listener(&mut config, bind_address, broker_port, tls)?;
+        info!("MQTTS listener on {}; plaintext listener restricted to 127.0.0.1:{}",
+              listen_address(bind_address, tls.port), broker_port);
+    }
     
     // Start broker in background thread (broker.start() is blocking)
     thread::spawn(move || {
@@ -40,21 +138,28 @@ pub async fn setup_mqtt_broker(broker_host: &str, broker_port: u16) -> Result<Ar
     tokio::time::sleep(Duration::from_secs(3)).await;
     
     // Create MQTT client for publishing
-    let mut mqttoptions = MqttOptions::new("crypto-server-publisher", broker_host, broker_port);
+    let client_host = internal_client_host(broker_host, tls.enabled);
+    let mut mqttoptions = MqttOptions::new("crypto-server-publisher", client_host, broker_port);
     mqttoptions.set_keep_alive(Duration::from_secs(30));
     mqttoptions.set_clean_session(true);
     mqttoptions.set_max_packet_size(102400, 102400); // Match broker config
+    // The broker marks the server offline if this connection is lost
+    mqttoptions.set_last_will(server_last_will());
     
     let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
     
     // Start eventloop for the main MQTT client to enable publishing
     let client_clone = client.clone();
+    let status_client = client.clone();
     tokio::spawn(async move {
         info!("Starting MQTT client eventloop for publishing");
         loop {
             match eventloop.poll().await {
                 Ok(Event::Incoming(Packet::ConnAck(_))) => {
                     info!("MQTT publisher client connected to broker");
+                    // Not awaited here: the publish is only sent once this loop polls again
+                    let client = status_client.clone();
+                    tokio::spawn(async move { publish_server_status(&client, ServerState::Online).await });
                 }
                 Ok(Event::Incoming(Packet::PingResp)) => {
                     // Normal keepalive, no need to log
@@ -64,6 +169,7 @@ pub async fn setup_mqtt_broker(broker_host: &str, broker_port: u16) -> Result<Ar
                 }
                 Err(e) => {
                     error!("MQTT publisher error: {}", e);
+                    dead_letter_unacknowledged(&mut eventloop.pending, &e.to_string()).await;
                     // Attempt to reconnect after error
                     tokio::time::sleep(Duration::from_secs(5)).await;
                 }
@@ -74,5 +180,104 @@ pub async fn setup_mqtt_broker(broker_host: &str, broker_port: u16) -> Result<Ar
     // Wait for connection
     tokio::time::sleep(Duration::from_secs(2)).await;
     
-    Ok(Arc::new(client_clone))
+    Ok((Arc::new(client_clone), broker_port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broker_config() -> BrokerConfig {
+        toml::from_str(DEFAULT_BROKER_CONFIG).unwrap()
+    }
+
+    #[test]
+    fn test_internal_client_host() {
+        assert_eq!(internal_client_host("0.0.0.0", false), "0.0.0.0");
+        assert_eq!(internal_client_host("0.0.0.0", true), "127.0.0.1");
+    }
+
+    #[test]
+    fn test_listen_address() {
+        assert_eq!(listen_address("0.0.0.0", 1883), "0.0.0.0:1883");
+        assert_eq!(listen_address("::", 1883), "[::]:1883");
+        assert_eq!(listen_address("[::1]", 8883), "[::1]:8883");
+    }
+
+    #[test]
+    fn test_resolve_port() {
+        assert_eq!(resolve_port("127.0.0.1", 1883).unwrap(), 1883);
+        assert_ne!(resolve_port("127.0.0.1", 0).unwrap(), 0);
+        assert!(resolve_port("not an address", 0).is_err());
+    }
+
+    // Two brokers side by side, each reachable on the port it reports
+    #[actix_web::test]
+    async fn test_ephemeral_brokers_do_not_collide() {
+        let tls = MqttTlsConfig::default();
+        let (first, second) = tokio::join!(
+            setup_mqtt_broker("127.0.0.1", "127.0.0.1", 0, &tls),
+            setup_mqtt_broker("127.0.0.1", "127.0.0.1", 0, &tls),
+        );
+        let ((_, first_port), (second, second_port)) = (first.unwrap(), second.unwrap());
+        assert_ne!(first_port, second_port);
+
+        let (subscriber, mut eventloop) = AsyncClient::new(MqttOptions::new("test-subscriber", "127.0.0.1", second_port), 10);
+        subscriber.subscribe("test/ephemeral", rumqttc::QoS::AtLeastOnce).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::SubAck(_))) => {
+                        second.publish("test/ephemeral", rumqttc::QoS::AtLeastOnce, false, "hello").await.unwrap();
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => return publish.payload,
+                    Ok(_) => {}
+                    Err(e) => panic!("Subscriber failed: {}", e),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(&received[..], b"hello");
+    }
+
+    #[test]
+    fn test_tls_listener_requires_cert_files() {
+        let mut config = broker_config();
+        let tls = MqttTlsConfig {
+            enabled: true,
+            ca_path: "/nonexistent/ca.pem".to_string(),
+            ..MqttTlsConfig::default()
+        };
+        assert!(apply_tls_listener(&mut config, "0.0.0.0", 1883, &tls).is_err());
+        assert_eq!(config.v4.len(), 1);
+    }
+
+    #[test]
+    fn test_tls_listener_added() {
+        let dir = std::env::temp_dir().join(format!("coin-crab-tls-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| {
+            let p = dir.join(name);
+            std::fs::write(&p, "placeholder").unwrap();
+            p.display().to_string()
+        };
+        let tls = MqttTlsConfig {
+            enabled: true,
+            port: 8883,
+            ca_path: path("ca.pem"),
+            cert_path: path("server.pem"),
+            key_path: path("server.key"),
+        };
+
+        let mut config = broker_config();
+        apply_tls_listener(&mut config, "0.0.0.0", 1883, &tls).unwrap();
+
+        assert_eq!(config.v4["1"].listen, "127.0.0.1:1883".parse().unwrap());
+        assert!(config.v4["1"].tls.is_none());
+        assert_eq!(config.v4["tls"].listen, "0.0.0.0:8883".parse().unwrap());
+        assert!(config.v4["tls"].tls.is_some());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
\ No newline at end of file
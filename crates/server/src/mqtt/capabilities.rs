@@ -0,0 +1,80 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{info, warn};
+use shared::{ClientCapabilities, PAYLOAD_SCHEMA_VERSION};
+
+// Client ids are chosen by the clients, so the registry forgets the least recently seen ones
+// past this many
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// What each client build announced on crypto/clients/{client_id}/capabilities. Before the
+// schema version is bumped, this tells how many connected builds would stop reading the feed.
+// Kept in memory; clients announce again on every connect.
+#[derive(Default)]
+pub struct ClientCapabilityRegistry {
+    clients: Mutex<HashMap<String, (ClientCapabilities, Instant)>>,
+}
+
+impl ClientCapabilityRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns false for clients that can't read the payloads this server publishes
+    pub fn record(&self, client_id: &str, capabilities: ClientCapabilities) -> bool {
+        let supported = capabilities.schema_version >= PAYLOAD_SCHEMA_VERSION;
+        if supported {
+            info!("Client {} ({}) reads payload schema {}", client_id,
+                  capabilities.client_version.as_deref().unwrap_or("unknown version"), capabilities.schema_version);
+        } else {
+            warn!("Client {} ({}) only reads payload schema {}; this server publishes {}", client_id,
+                  capabilities.client_version.as_deref().unwrap_or("unknown version"), capabilities.schema_version, PAYLOAD_SCHEMA_VERSION);
+        }
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(client_id) {
+            let oldest = clients.iter().min_by_key(|(_, (_, seen))| *seen).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                clients.remove(&oldest);
+            }
+        }
+        clients.insert(client_id.to_string(), (capabilities, Instant::now()));
+        supported
+    }
+
+    // Number of clients per announced schema version
+    pub fn schema_versions(&self) -> BTreeMap<u32, usize> {
+        let mut versions = BTreeMap::new();
+        for (capabilities, _) in self.clients.lock().unwrap().values() {
+            *versions.entry(capabilities.schema_version).or_insert(0) += 1;
+        }
+        versions
+    }
+}
+
+// Client id of a crypto/clients/{client_id}/capabilities topic (namespace already stripped)
+pub fn capabilities_client(topic: &str) -> Option<&str> {
+    shared::split_client_topic(topic).filter(|(_, rest)| *rest == "capabilities").map(|(client_id, _)| client_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(schema_version: u32) -> ClientCapabilities {
+        ClientCapabilities { schema_version, client_version: Some("0.1.0".to_string()), codecs: Vec::new() }
+    }
+
+    #[test]
+    fn test_registry_counts_schema_versions() {
+        let registry = ClientCapabilityRegistry::new();
+        assert!(registry.record("ios-1", capabilities(PAYLOAD_SCHEMA_VERSION)));
+        assert!(!registry.record("ios-2", capabilities(0)));
+        // Announcing again after a reconnect replaces the entry
+        assert!(registry.record("ios-2", capabilities(PAYLOAD_SCHEMA_VERSION)));
+        assert_eq!(registry.schema_versions(), BTreeMap::from([(PAYLOAD_SCHEMA_VERSION, 2)]));
+
+        assert_eq!(capabilities_client("crypto/clients/ios-1/capabilities"), Some("ios-1"));
+        assert_eq!(capabilities_client("crypto/clients/ios-1/errors"), None);
+    }
+}
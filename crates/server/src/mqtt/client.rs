@@ -36,7 +36,7 @@ mod tests {
         assert_eq!(options.client_id(), client_id);
         assert_eq!(options.broker_address(), (broker_host.to_string(), broker_port));
         assert_eq!(options.keep_alive(), Duration::from_secs(30));
-        assert_eq!(options.clean_session(), true);
+        assert!(options.clean_session());
         
         // Test that max packet size is set (we can't directly access it, but we can verify it doesn't panic)
         let max_packet_size = options.max_packet_size();
@@ -54,7 +54,7 @@ mod tests {
         assert_eq!(options.client_id(), client_id);
         assert_eq!(options.broker_address(), (broker_host.to_string(), broker_port));
         assert_eq!(options.keep_alive(), Duration::from_secs(30));
-        assert_eq!(options.clean_session(), true);
+        assert!(options.clean_session());
     }
 
     #[test]
@@ -81,7 +81,7 @@ mod tests {
         
         assert_eq!(keep_alive.as_secs(), 30);
         assert_eq!(max_packet_size, 102400); // 100KB
-        assert_eq!(clean_session, true);
+        assert!(clean_session);
     }
 
     #[test]
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use actix_web::web;
+use tracing::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use tokio::time::Instant;
+use crate::data::HISTORICAL_TIMEFRAMES;
+use crate::mqtt::broker::internal_client_host;
+use crate::mqtt::{prefixed_topic, publish_empty_retained_message, unprefixed_topic};
+use crate::types::AppState;
+use shared::{CoinCrabError, CoinCrabResult, PayloadCodec};
+
+// Retained messages outlive the coins and timeframes they were published for: a coin that
+// drops out of the listings keeps its crypto/prices/{SYMBOL} and historical topics on the
+// broker forever. The broker has no admin API to list them, so the leader periodically
+// subscribes to crypto/#, collects the retained messages it is sent and clears the ones no
+// longer part of the active set.
+
+// How long the scan listens for retained messages after subscribing
+const SCAN_WINDOW: Duration = Duration::from_secs(5);
+
+// Per-coin topics, by family: crypto/prices/{SYMBOL}, crypto/historical/{SYMBOL}/{timeframe}[/since]
+// and crypto/indicators/{SYMBOL}/{timeframe}, each possibly with a codec suffix. Topics of any
+// other family are never stale.
+pub fn is_stale_retained_topic(topic: &str, active_symbols: &HashSet<String>, timeframes: &[&str]) -> bool {
+    let (topic, _) = PayloadCodec::from_topic(topic);
+    let parts: Vec<&str> = topic.split('/').collect();
+    let inactive = |symbol: &str| !active_symbols.contains(symbol);
+    match parts.as_slice() {
+        ["crypto", "prices", "latest" | "delta"] => false,
+        ["crypto", "prices", symbol] => inactive(symbol),
+        ["crypto", "historical", symbol, timeframe] | ["crypto", "historical", symbol, timeframe, "since"] | ["crypto", "indicators", symbol, timeframe] => {
+            inactive(symbol) || !timeframes.contains(timeframe)
+        }
+        _ => false,
+    }
+}
+
+// Retained topics (namespace stripped) currently held by the broker under crypto/
+async fn scan_retained_topics(broker_host: &str, broker_port: u16) -> CoinCrabResult<Vec<String>> {
+    let client_id = format!("crypto-server-compaction-{}", std::process::id());
+    let mut options = MqttOptions::new(client_id, broker_host, broker_port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_clean_session(true);
+    options.set_max_packet_size(1024 * 1024, 1024 * 1024);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    client
+        .subscribe(prefixed_topic("crypto/#"), QoS::AtMostOnce)
+        .await
+        .map_err(|e| CoinCrabError::Mqtt(format!("Failed to subscribe for compaction: {}", e)))?;
+
+    let mut topics = Vec::new();
+    let deadline = Instant::now() + SCAN_WINDOW;
+    loop {
+        match tokio::time::timeout_at(deadline, eventloop.poll()).await {
+            Err(_) => break,
+            Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                // Live publishes arriving during the scan aren't retained copies
+                if publish.retain && !publish.payload.is_empty() {
+                    if let Some(topic) = unprefixed_topic(&publish.topic) {
+                        topics.push(topic.to_string());
+                    }
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(CoinCrabError::Mqtt(format!("Compaction scan failed: {}", e))),
+        }
+    }
+    let _ = client.disconnect().await;
+    Ok(topics)
+}
+
+// Returns the number of retained messages cleared
+pub async fn compact_retained_messages(state: &web::Data<AppState>, broker_host: &str, broker_port: u16) -> CoinCrabResult<usize> {
+    let active_symbols: HashSet<String> = match state.cache.lock().unwrap().as_ref() {
+        Some(listings) => listings.iter().map(|coin| coin.symbol.to_uppercase()).collect(),
+        // Without listings every coin would look inactive
+        None => return Ok(0),
+    };
+    let retained = scan_retained_topics(broker_host, broker_port).await?;
+    let stale: Vec<&String> = retained
+        .iter()
+        .filter(|topic| is_stale_retained_topic(topic, &active_symbols, &HISTORICAL_TIMEFRAMES))
+        .collect();
+    for topic in &stale {
+        publish_empty_retained_message(&state.mqtt_client, topic).await;
+    }
+    debug!("Compaction scanned {} retained topics", retained.len());
+    Ok(stale.len())
+}
+
+pub async fn compact_retained_periodically(state: web::Data<AppState>, interval_seconds: u64, broker_host: String, broker_port: u16, tls_enabled: bool) {
+    if interval_seconds == 0 {
+        return;
+    }
+    let broker_host = internal_client_host(&broker_host, tls_enabled);
+    info!("Compacting retained MQTT messages every {}s", interval_seconds);
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+    // The first tick fires immediately; give the first fetch time to fill the listings
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if !state.leader.is_leader() {
+            continue;
+        }
+        match compact_retained_messages(&state, &broker_host, broker_port).await {
+            Ok(0) => debug!("No stale retained messages"),
+            Ok(cleared) => info!("Cleared {} stale retained messages", cleared),
+            Err(e) => warn!("Retained message compaction failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_retained_topics() {
+        let active: HashSet<String> = ["BTC", "ETH"].iter().map(|symbol| symbol.to_string()).collect();
+        let timeframes = ["24h", "7d"];
+        let stale = |topic: &str| is_stale_retained_topic(topic, &active, &timeframes);
+
+        assert!(!stale("crypto/prices/latest"));
+        assert!(!stale("crypto/prices/latest/msgpack"));
+        assert!(!stale("crypto/prices/BTC"));
+        assert!(stale("crypto/prices/LUNA"));
+        assert!(!stale("crypto/historical/ETH/7d"));
+        assert!(stale("crypto/historical/ETH/1y"));
+        assert!(stale("crypto/historical/LUNA/24h/since"));
+        assert!(stale("crypto/indicators/LUNA/24h"));
+        // Device topics and summaries belong to other features
+        assert!(!stale("crypto/watchlists/phone/prices"));
+        assert!(!stale("crypto/summary/daily"));
+    }
+}
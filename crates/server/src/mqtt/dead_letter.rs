@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use rumqttc::{QoS, Request};
+use serde::{Deserialize, Serialize};
+use crate::config::DeadLetterConfig;
+use shared::{CoinCrabError, CoinCrabResult};
+use super::publisher::unprefixed_topic;
+
+// A publish the broker never acknowledged before the publisher's connection dropped. Kept so it
+// can be inspected and replayed through /api/admin/dead-letters instead of being lost with a
+// log line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: u64,
+    // Without the topic prefix; replays go through the current one
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: DeadLetterPayload,
+    pub error: String,
+    // Unix seconds
+    pub failed_at: i64,
+}
+
+// JSON payloads stay readable; binary ones (MessagePack) are stored as hex
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "encoding", content = "data", rename_all = "snake_case")]
+pub enum DeadLetterPayload {
+    Utf8(String),
+    Hex(String),
+}
+
+impl DeadLetterPayload {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => DeadLetterPayload::Utf8(text),
+            Err(e) => DeadLetterPayload::Hex(e.into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> CoinCrabResult<Vec<u8>> {
+        match self {
+            DeadLetterPayload::Utf8(text) => Ok(text.clone().into_bytes()),
+            DeadLetterPayload::Hex(hex) => (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                        .ok_or_else(|| CoinCrabError::Parse("Invalid hex payload".to_string()))
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn qos_from_u8(qos: u8) -> QoS {
+    match qos {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Entries {
+    next_id: u64,
+    letters: VecDeque<DeadLetter>,
+}
+
+// The most recent failed publishes, oldest dropped first, kept in DEAD_LETTER_PATH when set
+pub struct DeadLetterLog {
+    entries: Mutex<Entries>,
+    path: Option<PathBuf>,
+    max_entries: usize,
+}
+
+impl DeadLetterLog {
+    pub fn new(config: &DeadLetterConfig) -> Self {
+        DeadLetterLog {
+            entries: Mutex::new(Entries { next_id: 1, letters: VecDeque::new() }),
+            path: config.path.as_ref().map(PathBuf::from),
+            max_entries: config.max_entries,
+        }
+    }
+
+    pub fn record(&self, topic: &str, qos: QoS, retain: bool, payload: Vec<u8>, error: &str, failed_at: i64) -> u64 {
+        let mut entries = self.entries.lock().unwrap();
+        let id = entries.next_id;
+        entries.next_id += 1;
+        if entries.letters.len() == self.max_entries {
+            entries.letters.pop_front();
+        }
+        entries.letters.push_back(DeadLetter {
+            id,
+            topic: topic.to_string(),
+            qos: qos as u8,
+            retain,
+            payload: DeadLetterPayload::new(payload),
+            error: error.to_string(),
+            failed_at,
+        });
+        id
+    }
+
+    // Takes the publishes out of the requests rumqttc held back when the connection failed:
+    // the unacknowledged in-flight ones and those still queued. They would otherwise be resent
+    // blindly on reconnect; here they wait to be replayed. Other requests stay queued.
+    pub fn record_unacknowledged(&self, pending: &mut VecDeque<Request>, error: &str, failed_at: i64) -> usize {
+        let before = pending.len();
+        pending.retain(|request| {
+            let Request::Publish(publish) = request else {
+                return true;
+            };
+            let topic = unprefixed_topic(&publish.topic).unwrap_or(&publish.topic);
+            self.record(topic, publish.qos, publish.retain, publish.payload.to_vec(), error, failed_at);
+            false
+        });
+        before - pending.len()
+    }
+
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.entries.lock().unwrap().letters.iter().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<DeadLetter> {
+        self.entries.lock().unwrap().letters.iter().find(|letter| letter.id == id).cloned()
+    }
+
+    pub fn remove(&self, id: u64) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.letters.len();
+        entries.letters.retain(|letter| letter.id != id);
+        entries.letters.len() < before
+    }
+
+    // Returns how many were dropped
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.letters.len();
+        entries.letters.clear();
+        count
+    }
+
+    pub fn load(&self) -> CoinCrabResult<usize> {
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Ok(0);
+        };
+        let mut loaded: Entries = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        while loaded.letters.len() > self.max_entries {
+            loaded.letters.pop_front();
+        }
+        let count = loaded.letters.len();
+        *self.entries.lock().unwrap() = loaded;
+        Ok(count)
+    }
+
+    // Write to a temp file and rename so a crash mid-write never loses the log
+    pub fn save(&self) -> CoinCrabResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&*self.entries.lock().unwrap())?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // save() off the async runtime
+    pub async fn persist(self: &Arc<Self>) -> CoinCrabResult<()> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        let log = Arc::clone(self);
+        tokio::task::spawn_blocking(move || log.save())
+            .await
+            .map_err(|e| CoinCrabError::Io(format!("Dead-letter save task failed: {}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_round_trip() {
+        let json = DeadLetterPayload::new(br#"{"price":1}"#.to_vec());
+        assert_eq!(json, DeadLetterPayload::Utf8(r#"{"price":1}"#.to_string()));
+        let binary = DeadLetterPayload::new(vec![0x81, 0xa5, 0x00, 0xff]);
+        assert_eq!(binary, DeadLetterPayload::Hex("81a500ff".to_string()));
+        assert_eq!(binary.to_bytes().unwrap(), vec![0x81, 0xa5, 0x00, 0xff]);
+        assert!(DeadLetterPayload::Hex("abc".to_string()).to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_log_is_bounded_and_persisted() {
+        let path = std::env::temp_dir().join(format!("coin-crab-dead-letters-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = DeadLetterConfig { path: Some(path.display().to_string()), max_entries: 2 };
+        let log = DeadLetterLog::new(&config);
+        for topic in ["crypto/prices/latest", "crypto/prices/BTC", "crypto/historical/BTC/24h"] {
+            log.record(topic, QoS::AtLeastOnce, true, b"{}".to_vec(), "channel closed", 100);
+        }
+        let ids: Vec<u64> = log.list().iter().map(|letter| letter.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert!(log.remove(2));
+        assert!(!log.remove(2));
+
+        log.save().unwrap();
+        let restored = DeadLetterLog::new(&config);
+        assert_eq!(restored.load().unwrap(), 1);
+        assert_eq!(restored.get(3).unwrap().topic, "crypto/historical/BTC/24h");
+        // Ids keep counting from where the saved log left off
+        assert_eq!(restored.record("crypto/prices/ETH", QoS::AtMostOnce, false, Vec::new(), "closed", 101), 4);
+        assert_eq!(restored.clear(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unacknowledged_publishes_are_recorded() {
+        let log = DeadLetterLog::new(&DeadLetterConfig { path: None, max_entries: 10 });
+        let mut pending = VecDeque::from([
+            Request::Publish(rumqttc::Publish::new("crypto/prices/latest", QoS::AtLeastOnce, "[]")),
+            Request::PingReq(rumqttc::PingReq),
+            Request::Publish(rumqttc::Publish::new("crypto/historical/BTC/24h", QoS::AtMostOnce, "{}")),
+        ]);
+
+        assert_eq!(log.record_unacknowledged(&mut pending, "connection reset", 100), 2);
+        assert_eq!(pending.len(), 1);
+        let letters = log.list();
+        assert_eq!(letters[0].topic, "crypto/prices/latest");
+        assert_eq!(letters[0].payload, DeadLetterPayload::Utf8("[]".to_string()));
+        assert_eq!(letters[1].qos, 0);
+        assert_eq!(letters[1].error, "connection reset");
+    }
+}
@@ -0,0 +1,179 @@
+use tracing::{error, info};
+use rumqttc::{AsyncClient, QoS};
+use serde::Serialize;
+use crate::data::HISTORICAL_TIMEFRAMES;
+use super::presence::SERVER_STATUS_TOPIC;
+use super::publisher::{indicator_topics_enabled, payload_codecs, price_deltas_enabled, publish, topic_prefix};
+
+// Retained on crypto/meta/topics so consumers can discover what this server publishes and
+// accepts instead of relying on the README. Topics are listed without the namespace; prefix
+// them with topic_prefix when it is set.
+
+pub const TOPIC_CATALOG_TOPIC: &str = "crypto/meta/topics";
+// Bumped when the catalog document itself changes shape
+const CATALOG_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopicDirection {
+    // The server publishes, clients subscribe
+    Publish,
+    // Clients publish, the server subscribes
+    Subscribe,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopicFamily {
+    pub name: &'static str,
+    // {placeholders} stand for one topic level
+    pub topic: &'static str,
+    pub direction: TopicDirection,
+    pub retained: bool,
+    pub qos: u8,
+    // Payload type and the version of its schema; see shared/src/types.rs
+    pub payload: &'static str,
+    pub schema_version: u32,
+    // How to build a request payload, for subscribe families
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_format: Option<&'static str>,
+    // Families switched off by configuration are listed but not published
+    pub enabled: bool,
+    // Extra encodings of the same payload on "<topic>/<codec>"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopicCatalog {
+    pub version: u32,
+    pub topic_prefix: String,
+    pub timeframes: Vec<&'static str>,
+    pub families: Vec<TopicFamily>,
+}
+
+fn family(name: &'static str, topic: &'static str, direction: TopicDirection, retained: bool, qos: u8, payload: &'static str) -> TopicFamily {
+    TopicFamily {
+        name,
+        topic,
+        direction,
+        retained,
+        qos,
+        payload,
+        // Payloads that carry their own schema_version
+        schema_version: if payload.starts_with("PriceEnvelope") || payload == "HistoricalDataResult" { shared::PAYLOAD_SCHEMA_VERSION } else { 1 },
+        request_format: None,
+        enabled: true,
+        codecs: Vec::new(),
+    }
+}
+
+// The catalog for the current configuration
+pub fn topic_catalog() -> TopicCatalog {
+    use TopicDirection::{Publish, Subscribe};
+    let codecs: Vec<&'static str> = payload_codecs().iter().filter_map(|codec| codec.suffix()).collect();
+    let deltas = price_deltas_enabled();
+    let indicators = indicator_topics_enabled();
+    let historical_request = r#"JSON {"request_id", "symbol", "timeframe" | "start" "end"? "interval"?, "after"?, "max_points"?, "reply_to": "crypto/responses/{client_id}/{request_id}"}; legacy SYMBOL:timeframe[:after] text gets no response"#;
+
+    let families = vec![
+        TopicFamily { codecs: codecs.clone(), ..family("prices.latest", "crypto/prices/latest", Publish, true, 1, "PriceEnvelope<[CryptoCurrency]>") },
+        TopicFamily { codecs: codecs.clone(), enabled: deltas, ..family("prices.delta", "crypto/prices/delta", Publish, false, 1, "PriceEnvelope<PriceDelta>") },
+        family("prices.symbol", "crypto/prices/{symbol}", Publish, true, 0, "PriceEnvelope<CryptoCurrency>"),
+        family("historical", "crypto/historical/{symbol}/{timeframe}", Publish, true, 0, "HistoricalDataResult"),
+        family("historical.chunk", "crypto/historical/{symbol}/{timeframe}/chunk/{index}/{total}", Publish, false, 1, "HistoricalChunk"),
+        family("historical.since", "crypto/historical/{symbol}/{timeframe}/since", Publish, false, 0, "HistoricalDataResult"),
+        family("historical.sampled", "crypto/historical/{symbol}/{timeframe}/sampled/{client_id}", Publish, false, 0, "HistoricalDataResult"),
+        family("historical.range", "crypto/historical/{symbol}/range/{client_id}/{request_id}", Publish, false, 1, "HistoricalDataResult"),
+        TopicFamily { enabled: indicators, ..family("indicators", "crypto/indicators/{symbol}/{timeframe}", Publish, true, 0, "IndicatorSet") },
+        family("prefetch.popular", "crypto/prefetch/popular", Publish, true, 1, "[PrefetchHint]"),
+        family("summary.daily", "crypto/summary/daily", Publish, true, 1, "DailySummary"),
+        family("diagnostics.anomalies", "crypto/diagnostics/anomalies", Publish, false, 1, "[PriceAnomaly]"),
+        family("trending.gainers", "crypto/trending/gainers", Publish, true, 1, "TrendingMovers"),
+        family("trending.losers", "crypto/trending/losers", Publish, true, 1, "TrendingMovers"),
+        family("alerts", "crypto/alerts/{device_id}", Publish, false, 1, "TriggeredAlert"),
+        TopicFamily { codecs: codecs.clone(), ..family("watchlists.prices", "crypto/watchlists/{device_id}/prices", Publish, true, 1, "PriceEnvelope<[CryptoCurrency]>") },
+        family("portfolios.valuation", "crypto/portfolios/{portfolio_id}/valuation", Publish, true, 1, "PortfolioValuation"),
+        family("clients.errors", "crypto/clients/{client_id}/errors", Publish, false, 1, "RequestError"),
+        family("errors.historical", "crypto/errors/historical/{symbol}/{timeframe}", Publish, false, 1, "HistoricalFetchError"),
+        family("responses", "crypto/responses/{client_id}/{request_id}", Publish, false, 1, "RequestResponse"),
+        family("assets.logos", "crypto/assets/logos/{symbol}", Publish, true, 1, "image/png"),
+        family("meta.topics", TOPIC_CATALOG_TOPIC, Publish, true, 1, "TopicCatalog"),
+        family("server.status", SERVER_STATUS_TOPIC, Publish, true, 1, "ServerStatus"),
+        TopicFamily {
+            request_format: Some(historical_request),
+            ..family("requests.historical", "crypto/clients/{client_id}/requests/historical", Subscribe, false, 1, "HistoricalRequestEnvelope")
+        },
+        TopicFamily {
+            request_format: Some(historical_request),
+            ..family("requests.historical.background", "crypto/clients/{client_id}/requests/historical/background", Subscribe, false, 1, "HistoricalRequestEnvelope")
+        },
+        TopicFamily {
+            request_format: Some("Any payload; ignored"),
+            ..family("requests.refresh_prices", "crypto/clients/{client_id}/requests/refresh-prices", Subscribe, false, 1, "text")
+        },
+        TopicFamily {
+            request_format: Some("JSON array of symbols or a comma-separated list; published to crypto/assets/logos/{symbol}"),
+            ..family("requests.logos", "crypto/clients/{client_id}/requests/logos", Subscribe, false, 1, "[String]")
+        },
+        TopicFamily {
+            request_format: Some(r#"JSON array of {"id"?, "symbol", "direction": "above"|"below", "threshold"}; replaces the device's rules"#),
+            ..family("alerts.register", "crypto/alerts/register/{device_id}", Subscribe, false, 1, "[AlertRuleRequest]")
+        },
+        TopicFamily {
+            request_format: Some("JSON array of symbols or a comma-separated list; empty removes the watchlist"),
+            ..family("watchlists.register", "crypto/watchlists/register/{device_id}", Subscribe, false, 1, "[String]")
+        },
+        TopicFamily {
+            request_format: Some(r#"JSON {"schema_version", "client_version"?, "codecs"?}, sent after each connect"#),
+            ..family("clients.capabilities", "crypto/clients/{client_id}/capabilities", Subscribe, false, 1, "ClientCapabilities")
+        },
+        family("diagnostics.parse_failures", "crypto/diagnostics/parse_failures", Subscribe, false, 0, "text"),
+    ];
+
+    TopicCatalog {
+        version: CATALOG_VERSION,
+        topic_prefix: topic_prefix(),
+        timeframes: HISTORICAL_TIMEFRAMES.to_vec(),
+        families,
+    }
+}
+
+pub async fn publish_topic_catalog(mqtt_client: &AsyncClient) {
+    let catalog = topic_catalog();
+    let payload = match serde_json::to_string(&catalog) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize topic catalog for MQTT: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, TOPIC_CATALOG_TOPIC, QoS::AtLeastOnce, true, payload).await {
+        error!("Failed to publish to {}: {}", TOPIC_CATALOG_TOPIC, e);
+    } else {
+        info!("Published {} topic families to {}", catalog.families.len(), TOPIC_CATALOG_TOPIC);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_catalog() {
+        let catalog = topic_catalog();
+        let names: Vec<&str> = catalog.families.iter().map(|family| family.name).collect();
+        let mut unique = names.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), names.len());
+        assert!(catalog.families.iter().filter(|family| family.direction == TopicDirection::Subscribe).all(|family| !family.retained));
+
+        let json = serde_json::to_value(&catalog).unwrap();
+        let latest = &json["families"][0];
+        assert_eq!(latest["topic"], "crypto/prices/latest");
+        assert_eq!(latest["schema_version"], 1);
+        assert!(latest.get("request_format").is_none());
+        assert_eq!(json["timeframes"].as_array().unwrap().len(), HISTORICAL_TIMEFRAMES.len());
+    }
+}
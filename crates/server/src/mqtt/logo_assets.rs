@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use actix_web::web;
+use rumqttc::QoS;
+use tracing::{debug, error, info, warn};
+use crate::logos::{load_logo, LogoFormat, DEFAULT_LOGO_SIZE};
+use crate::types::AppState;
+use super::publisher::publish;
+
+// Logos over the MQTT connection the app already has, so icons don't need a second HTTP
+// connection: clients subscribe to crypto/assets/logos/{SYMBOL} for the icons they show and
+// ask for the missing ones on crypto/clients/{client_id}/requests/logos. Each logo is published
+// retained as a 64px PNG, so later subscribers get it from the broker without asking.
+
+// Symbols per request, bounding the CDN fetches one request can cause
+pub const MAX_LOGOS_PER_REQUEST: usize = 100;
+// Leaves room for the topic within the broker's 100 KB packet limit
+const MAX_LOGO_BYTES: usize = 96 * 1024;
+
+// Content hash last published per symbol, so repeated requests don't resend unchanged images
+static PUBLISHED_LOGOS: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+// Upper-cased, de-duplicated symbols from a JSON array or comma-separated list
+pub fn requested_symbols(payload: &[u8]) -> Vec<String> {
+    let symbols: Vec<String> = serde_json::from_slice(payload)
+        .unwrap_or_else(|_| String::from_utf8_lossy(payload).split(',').map(str::to_string).collect());
+    let mut requested: Vec<String> = Vec::new();
+    for symbol in symbols.iter().map(|symbol| symbol.trim().to_uppercase()) {
+        if !symbol.is_empty() && symbol.chars().all(|c| c.is_ascii_alphanumeric()) && !requested.contains(&symbol) {
+            requested.push(symbol);
+        }
+    }
+    requested.truncate(MAX_LOGOS_PER_REQUEST);
+    requested
+}
+
+// Load and publish the requested logos; symbols without a logo are skipped
+pub async fn publish_requested_logos(state: &web::Data<AppState>, payload: &[u8]) {
+    let symbols = requested_symbols(payload);
+    let mut published = 0;
+    for symbol in &symbols {
+        match load_logo(state, symbol, DEFAULT_LOGO_SIZE, LogoFormat::Png).await {
+            Ok((hash, bytes)) => {
+                if publish_logo(state, symbol, &hash, &bytes).await {
+                    published += 1;
+                }
+            }
+            Err(e) => debug!("No logo to publish for {}: {:?}", symbol, e),
+        }
+    }
+    info!("Published {} of {} requested logos to MQTT", published, symbols.len());
+}
+
+async fn publish_logo(state: &AppState, symbol: &str, hash: &str, bytes: &[u8]) -> bool {
+    if PUBLISHED_LOGOS.lock().unwrap().get(symbol).is_some_and(|published| published == hash) {
+        return false;
+    }
+    if bytes.len() > MAX_LOGO_BYTES {
+        warn!("Logo for {} is too large to publish over MQTT ({} bytes)", symbol, bytes.len());
+        return false;
+    }
+    let topic = shared::logo_topic(symbol);
+    match publish(&state.mqtt_client, &topic, QoS::AtLeastOnce, true, bytes.to_vec()).await {
+        Ok(()) => {
+            PUBLISHED_LOGOS.lock().unwrap().insert(symbol.to_string(), hash.to_string());
+            true
+        }
+        Err(e) => {
+            error!("Failed to publish logo to {}: {}", topic, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requested_symbols() {
+        assert_eq!(requested_symbols(br#"["btc", "ETH", "btc"]"#), vec!["BTC", "ETH"]);
+        assert_eq!(requested_symbols(b"sol, ada,,../x"), vec!["SOL", "ADA"]);
+        let many: Vec<String> = (0..150).map(|i| format!("C{}", i)).collect();
+        assert_eq!(requested_symbols(many.join(",").as_bytes()).len(), MAX_LOGOS_PER_REQUEST);
+    }
+}
@@ -1,11 +1,28 @@
+pub mod acl;
 pub mod broker;
+pub mod capabilities;
 pub mod client;
+pub mod compaction;
+pub mod dead_letter;
+pub mod discovery;
+pub mod logo_assets;
+pub mod presence;
+pub mod price_delta;
 pub mod publisher;
+pub mod rate_limit;
 pub mod request_handler;
+pub mod request_queue;
+pub mod retained;
 
 // Re-export main functions for convenience
+pub use acl::enforce_topic_acl;
 pub use broker::setup_mqtt_broker;
-pub use publisher::{publish_crypto_data_to_mqtt, publish_historical_data_to_mqtt, publish_empty_retained_message, clear_all_retained_messages};
+pub use compaction::compact_retained_periodically;
+pub use dead_letter::DeadLetterLog;
+pub use discovery::publish_topic_catalog;
+pub use presence::publish_server_status;
+pub use publisher::{publish_crypto_data_to_mqtt, publish_symbol_prices_to_mqtt, publish_watchlist_bundle_to_mqtt, publish_price_delta_to_mqtt, publish_historical_data_to_mqtt, publish_historical_delta_to_mqtt, publish_historical_range_to_mqtt, publish_sampled_historical_to_mqtt, publish_prefetch_hints_to_mqtt, publish_daily_summary_to_mqtt, publish_price_anomalies_to_mqtt, publish_alert_to_mqtt, publish_portfolio_valuation_to_mqtt, publish_trending_to_mqtt, publish_request_error_to_mqtt, publish_request_response_to_mqtt, publish_historical_error_to_mqtt, publish_empty_retained_message, clear_all_retained_messages, set_dead_letter_log, dead_letter_log, replay_dead_letter, set_dry_run, set_topic_prefix, set_price_delta_mode, set_price_ttl, set_msgpack_payloads, set_indicator_topics, plan_price_publish, prefixed_topic, unprefixed_topic};
+pub use price_delta::PricePublish;
 pub use request_handler::setup_mqtt_request_handling;
 
 #[cfg(test)]
@@ -28,12 +45,12 @@ mod tests {
         let _publish_crypto = publish_crypto_data_to_mqtt;
         let _publish_historical = publish_historical_data_to_mqtt;
         let _publish_empty = publish_empty_retained_message;
+        let _publish_prefetch = publish_prefetch_hints_to_mqtt;
         
         // Verify request handler function exists
         let _setup_handler = setup_mqtt_request_handling;
         
         // If we reach this point, all re-exports are working
-        assert!(true);
     }
 
     #[test]
@@ -0,0 +1,68 @@
+use std::sync::OnceLock;
+use rumqttc::{AsyncClient, LastWill, QoS};
+use tracing::{error, info};
+use shared::{ServerState, ServerStatus};
+use super::publisher::{prefixed_topic, publish};
+
+// Retained server presence, so clients can tell "the server is down" from "no new data yet".
+// The publisher client registers an "offline" Last Will and publishes "online" on every
+// ConnAck; the broker sends the will if the publisher's connection drops uncleanly.
+
+pub const SERVER_STATUS_TOPIC: &str = "crypto/server/status";
+
+// Unix timestamp (seconds), fixed the first time it is asked for
+fn started_at() -> i64 {
+    static STARTED_AT: OnceLock<i64> = OnceLock::new();
+    *STARTED_AT.get_or_init(|| chrono::Utc::now().timestamp())
+}
+
+pub fn server_status(status: ServerState) -> ServerStatus {
+    ServerStatus {
+        status,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started_at: started_at(),
+    }
+}
+
+// Registered on the publisher's connection; the topic prefix must be set before this is built
+pub fn server_last_will() -> LastWill {
+    let payload = serde_json::to_vec(&server_status(ServerState::Offline)).unwrap_or_default();
+    LastWill::new(prefixed_topic(SERVER_STATUS_TOPIC), payload, QoS::AtLeastOnce, true)
+}
+
+pub async fn publish_server_status(mqtt_client: &AsyncClient, status: ServerState) {
+    let payload = match serde_json::to_string(&server_status(status)) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize server status for MQTT: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, SERVER_STATUS_TOPIC, QoS::AtLeastOnce, true, payload).await {
+        error!("Failed to publish to {}: {}", SERVER_STATUS_TOPIC, e);
+    } else {
+        info!("Published server status {:?} to {}", status, SERVER_STATUS_TOPIC);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::publisher::SettingsGuard;
+
+    #[test]
+    fn test_server_last_will() {
+        let _settings = SettingsGuard::lock();
+        let will = server_last_will();
+        assert_eq!(will.topic, prefixed_topic(SERVER_STATUS_TOPIC));
+        assert!(will.retain);
+        let status: ServerStatus = serde_json::from_slice(&will.message).unwrap();
+        assert_eq!(status.status, ServerState::Offline);
+        assert_eq!(status.started_at, server_status(ServerState::Online).started_at);
+
+        let json = serde_json::to_value(server_status(ServerState::Online)).unwrap();
+        assert_eq!(json["status"], "online");
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    }
+}
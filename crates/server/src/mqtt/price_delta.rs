@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use crate::config::PriceDeltaConfig;
+use crate::types::CryptoCurrency;
+use shared::PriceDelta;
+
+// Decides, per price update, whether to publish the full listing or only the coins that
+// moved. Prices are compared against the last price *published* for each coin, so slow
+// drift still crosses the threshold eventually instead of hiding in many small steps.
+//
+// Deltas are not retained: a client that connects mid-cycle starts from the retained
+// snapshot and catches up fully at the next one, so snapshot_every bounds how stale it can be.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PricePublish {
+    Snapshot,
+    Delta(PriceDelta),
+    // Nothing moved beyond the threshold; no sequence number is used up
+    Unchanged,
+}
+
+pub struct PriceDeltaTracker {
+    threshold_percent: f64,
+    snapshot_every: u32,
+    epoch: u64,
+    seq: u64,
+    updates_since_snapshot: u32,
+    // Last published price per coin id
+    baseline: HashMap<i32, f64>,
+}
+
+impl PriceDeltaTracker {
+    pub fn new(config: &PriceDeltaConfig, epoch: u64) -> Self {
+        PriceDeltaTracker {
+            threshold_percent: config.threshold_percent,
+            snapshot_every: config.snapshot_every.max(1),
+            epoch,
+            seq: 0,
+            updates_since_snapshot: 0,
+            baseline: HashMap::new(),
+        }
+    }
+
+    pub fn plan(&mut self, coins: &[CryptoCurrency]) -> PricePublish {
+        self.updates_since_snapshot += 1;
+        // A coin leaving the listing can't be expressed as a delta
+        let listing_shrank = self.baseline.keys().any(|id| !coins.iter().any(|coin| coin.id == *id));
+        if self.baseline.is_empty() || listing_shrank || self.updates_since_snapshot >= self.snapshot_every {
+            self.updates_since_snapshot = 0;
+            self.baseline = coins.iter().map(|coin| (coin.id, coin.quote.usd.price)).collect();
+            return PricePublish::Snapshot;
+        }
+
+        let changed: Vec<CryptoCurrency> = coins
+            .iter()
+            .filter(|coin| self.moved(coin))
+            .cloned()
+            .collect();
+        if changed.is_empty() {
+            return PricePublish::Unchanged;
+        }
+        for coin in &changed {
+            self.baseline.insert(coin.id, coin.quote.usd.price);
+        }
+        self.seq += 1;
+        PricePublish::Delta(PriceDelta { epoch: self.epoch, seq: self.seq, coins: changed })
+    }
+
+    fn moved(&self, coin: &CryptoCurrency) -> bool {
+        let price = coin.quote.usd.price;
+        match self.baseline.get(&coin.id) {
+            None => true,
+            Some(&0.0) => price != 0.0,
+            Some(&last) => ((price - last) / last).abs() * 100.0 >= self.threshold_percent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(id: i32, symbol: &str, price: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    fn tracker(snapshot_every: u32) -> PriceDeltaTracker {
+        let config = PriceDeltaConfig { enabled: true, threshold_percent: 0.5, snapshot_every };
+        PriceDeltaTracker::new(&config, 42)
+    }
+
+    fn delta_symbols(publish: PricePublish) -> (u64, Vec<String>) {
+        match publish {
+            PricePublish::Delta(delta) => (delta.seq, delta.coins.into_iter().map(|c| c.symbol).collect()),
+            other => panic!("expected a delta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_only_coins_beyond_threshold_are_sent() {
+        let mut tracker = tracker(100);
+        assert_eq!(tracker.plan(&[coin(1, "BTC", 100.0), coin(2, "ETH", 10.0)]), PricePublish::Snapshot);
+
+        let (seq, symbols) = delta_symbols(tracker.plan(&[coin(1, "BTC", 100.4), coin(2, "ETH", 10.1)]));
+        assert_eq!((seq, symbols), (1, vec!["ETH".to_string()]));
+
+        // BTC drifted 0.4% then another 0.2%: compared to the last published price it now qualifies
+        let (seq, symbols) = delta_symbols(tracker.plan(&[coin(1, "BTC", 100.6), coin(2, "ETH", 10.1)]));
+        assert_eq!((seq, symbols), (2, vec!["BTC".to_string()]));
+
+        // New coins are included in full
+        let (_, symbols) = delta_symbols(tracker.plan(&[coin(1, "BTC", 100.6), coin(2, "ETH", 10.1), coin(3, "SOL", 1.0)]));
+        assert_eq!(symbols, vec!["SOL".to_string()]);
+    }
+
+    #[test]
+    fn test_periodic_and_forced_snapshots() {
+        let mut tracker = tracker(3);
+        let listing = [coin(1, "BTC", 100.0), coin(2, "ETH", 10.0)];
+        let moved = [coin(1, "BTC", 110.0), coin(2, "ETH", 10.0)];
+        assert_eq!(tracker.plan(&listing), PricePublish::Snapshot);
+        assert!(matches!(tracker.plan(&moved), PricePublish::Delta(_)));
+        assert_eq!(tracker.plan(&moved), PricePublish::Unchanged);
+        assert_eq!(tracker.plan(&moved), PricePublish::Snapshot);
+
+        // ETH dropping out of the listing forces a snapshot; seq keeps counting within the epoch
+        assert_eq!(tracker.plan(&moved[..1]), PricePublish::Snapshot);
+        match tracker.plan(&listing[..1]) {
+            PricePublish::Delta(delta) => assert_eq!((delta.epoch, delta.seq), (42, 2)),
+            other => panic!("expected a delta, got {:?}", other),
+        }
+    }
+}
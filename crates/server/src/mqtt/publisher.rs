@@ -1,25 +1,283 @@
-use rumqttc::{AsyncClient, QoS};
-use log::{info, warn, error};
+use rumqttc::{AsyncClient, ClientError, QoS};
+use tracing::{debug, info, instrument, warn, error, Span};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::alerts::TriggeredAlert;
+use crate::config::PriceDeltaConfig;
+use crate::fields::default_quote_fields;
+use crate::summary::DailySummary;
+use crate::anomalies::PriceAnomaly;
+use crate::indicators::{compute_indicators, Indicator};
+use crate::portfolio::PortfolioValuation;
+use crate::movers::TrendingMovers;
 use crate::types::CryptoCurrency;
-use shared::HistoricalDataResult;
+use super::dead_letter::{qos_from_u8, DeadLetter, DeadLetterLog};
+use super::price_delta::{PriceDeltaTracker, PricePublish};
+use super::retained::retained_topics;
+use super::acl::publish_ledger;
+use serde::Serialize;
+use shared::{CoinCrabError, CoinCrabResult, FxMetadata, HistoricalChunk, HistoricalDataResult, PayloadCodec, PrefetchHint, PriceDelta, PriceEnvelope, RequestError, RequestResponse, HistoricalFetchError};
 
-pub async fn publish_crypto_data_to_mqtt(mqtt_client: &AsyncClient, crypto_data: &[CryptoCurrency]) {
-    // Publish all crypto data to main topic with retention
-    let payload = match serde_json::to_string(crypto_data) {
-        Ok(json) => json,
-        Err(e) => {
-            error!("Failed to serialize crypto data for MQTT: {}", e);
-            return;
-        }
+// When set, publishes are logged instead of sent so fetching can be validated without reaching clients
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+// Namespace applied to every topic the server publishes or subscribes to (empty = none)
+static TOPIC_PREFIX: RwLock<String> = RwLock::new(String::new());
+
+pub fn set_topic_prefix(prefix: &str) {
+    *TOPIC_PREFIX.write().unwrap() = shared::normalize_topic_prefix(prefix);
+}
+
+pub fn topic_prefix() -> String {
+    TOPIC_PREFIX.read().unwrap().clone()
+}
+
+pub fn prefixed_topic(topic: &str) -> String {
+    shared::with_topic_prefix(&TOPIC_PREFIX.read().unwrap(), topic)
+}
+
+// Incoming topic without the namespace; None if it belongs to another namespace
+pub fn unprefixed_topic(topic: &str) -> Option<&str> {
+    shared::strip_topic_prefix(&TOPIC_PREFIX.read().unwrap(), topic)
+}
+
+// Also publish MessagePack copies of the full price payloads on "<topic>/msgpack"
+static MSGPACK_PAYLOADS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_msgpack_payloads(enabled: bool) {
+    MSGPACK_PAYLOADS.store(enabled, Ordering::Relaxed);
+}
+
+// Follow each historical series with its indicators on crypto/indicators/{SYMBOL}/{timeframe}
+static INDICATOR_TOPICS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_indicator_topics(enabled: bool) {
+    INDICATOR_TOPICS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn indicator_topics_enabled() -> bool {
+    INDICATOR_TOPICS.load(Ordering::Relaxed)
+}
+
+pub(super) fn payload_codecs() -> &'static [PayloadCodec] {
+    if MSGPACK_PAYLOADS.load(Ordering::Relaxed) {
+        &[PayloadCodec::Json, PayloadCodec::MsgPack]
+    } else {
+        &[PayloadCodec::Json]
+    }
+}
+
+// Validity windows stamped on price publishes; the retained snapshot may need a longer one
+static PRICE_TTL_SECS: AtomicU64 = AtomicU64::new(1800);
+static SNAPSHOT_TTL_SECS: AtomicU64 = AtomicU64::new(1800);
+
+pub fn set_price_ttl(price_ttl_seconds: u64, snapshot_ttl_seconds: u64) {
+    PRICE_TTL_SECS.store(price_ttl_seconds, Ordering::Relaxed);
+    SNAPSHOT_TTL_SECS.store(snapshot_ttl_seconds, Ordering::Relaxed);
+}
+
+fn price_envelope<T>(data: T) -> PriceEnvelope<T> {
+    PriceEnvelope::new(data, PRICE_TTL_SECS.load(Ordering::Relaxed))
+}
+
+// Set when delta mode is enabled; None publishes a full snapshot on every update
+static PRICE_DELTAS: Mutex<Option<PriceDeltaTracker>> = Mutex::new(None);
+
+pub fn set_price_delta_mode(config: &PriceDeltaConfig) {
+    let tracker = config.enabled.then(|| {
+        // Clients reset their sequence tracking when the epoch changes, i.e. on restart
+        let epoch = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        PriceDeltaTracker::new(config, epoch)
+    });
+    *PRICE_DELTAS.lock().unwrap() = tracker;
+}
+
+pub fn price_deltas_enabled() -> bool {
+    PRICE_DELTAS.lock().unwrap().is_some()
+}
+
+// How the next price update should go out: full snapshot, delta, or nothing
+pub fn plan_price_publish(crypto_data: &[CryptoCurrency]) -> PricePublish {
+    match PRICE_DELTAS.lock().unwrap().as_mut() {
+        Some(tracker) => tracker.plan(crypto_data),
+        None => PricePublish::Snapshot,
+    }
+}
+
+// Failed publishes end up here after their retries; unset, they are only logged
+static DEAD_LETTERS: RwLock<Option<Arc<DeadLetterLog>>> = RwLock::new(None);
+
+pub fn set_dead_letter_log(log: Arc<DeadLetterLog>) {
+    *DEAD_LETTERS.write().unwrap() = Some(log);
+}
+
+pub fn dead_letter_log() -> Option<Arc<DeadLetterLog>> {
+    DEAD_LETTERS.read().unwrap().clone()
+}
+
+// Publishes the broker hadn't acknowledged when the publisher's connection dropped go to the
+// dead-letter log; called from the publisher's event loop
+pub(super) async fn dead_letter_unacknowledged(pending: &mut std::collections::VecDeque<rumqttc::Request>, error: &str) {
+    let Some(log) = dead_letter_log() else {
+        return;
     };
-    
-    if let Err(e) = mqtt_client.publish("crypto/prices/latest", QoS::AtLeastOnce, true, payload).await {
+    let count = log.record_unacknowledged(pending, error, chrono::Utc::now().timestamp());
+    if count == 0 {
+        return;
+    }
+    warn!(count, "Publishes not acknowledged before the connection dropped, kept as dead letters");
+    if let Err(e) = log.persist().await {
+        error!("Failed to save dead-letter log: {}", e);
+    }
+}
+
+// Single point through which every server publish goes. This only queues the publish for the
+// event loop, so it fails only if the event loop is gone; broker-side failures are caught there.
+#[instrument(level = "debug", name = "mqtt_publish", skip_all, fields(topic = %topic, qos = ?qos, retain, bytes))]
+pub(super) async fn publish(
+    mqtt_client: &AsyncClient,
+    topic: &str,
+    qos: QoS,
+    retain: bool,
+    payload: impl Into<Vec<u8>>,
+) -> Result<(), ClientError> {
+    let payload = payload.into();
+    let full_topic = prefixed_topic(topic);
+    Span::current().record("bytes", payload.len());
+    if is_dry_run() {
+        // warn! so the line survives the publisher module's log filter
+        warn!(topic = %full_topic, bytes = payload.len(), ?qos, retain, "[dry-run] Would publish");
+        return Ok(());
+    }
+    publish_ledger().record(topic, retain, &payload);
+    let cleared = payload.is_empty();
+    if let Err(e) = mqtt_client.publish(&full_topic, qos, retain, payload).await {
+        warn!(topic = %full_topic, error = %e, "Publish failed, MQTT event loop is not running");
+        return Err(e);
+    }
+    if retain {
+        retained_topics().record(topic, cleared, Instant::now());
+    }
+    Ok(())
+}
+
+// Send a dead letter again, once; the caller removes it from the log on success
+pub async fn replay_dead_letter(mqtt_client: &AsyncClient, letter: &DeadLetter) -> CoinCrabResult<()> {
+    if is_dry_run() {
+        return Err(CoinCrabError::Config("Dead letters are not replayed in dry-run mode".to_string()));
+    }
+    let payload = letter.payload.to_bytes()?;
+    let cleared = payload.is_empty();
+    publish_ledger().record(&letter.topic, letter.retain, &payload);
+    mqtt_client
+        .publish(prefixed_topic(&letter.topic), qos_from_u8(letter.qos), letter.retain, payload)
+        .await
+        .map_err(|e| CoinCrabError::Mqtt(format!("Replay of dead letter {} failed: {}", letter.id, e)))?;
+    if letter.retain {
+        retained_topics().record(&letter.topic, cleared, Instant::now());
+    }
+    Ok(())
+}
+
+// Publish a value in every enabled encoding: JSON on the topic itself (the default existing
+// clients rely on), binary encodings on the suffixed topics
+async fn publish_encoded<T: Serialize + ?Sized>(
+    mqtt_client: &AsyncClient,
+    topic: &str,
+    qos: QoS,
+    retain: bool,
+    value: &T,
+) -> CoinCrabResult<()> {
+    for codec in payload_codecs() {
+        let payload = codec.encode(value)?;
+        publish(mqtt_client, &codec.topic(topic), qos, retain, payload)
+            .await
+            .map_err(|e| CoinCrabError::Mqtt(format!("{:?} publish failed: {}", codec, e)))?;
+    }
+    Ok(())
+}
+
+// Price payloads carry only the configured quote fields (PAYLOAD_FIELDS)
+async fn publish_quotes<T: Serialize + ?Sized>(
+    mqtt_client: &AsyncClient,
+    topic: &str,
+    qos: QoS,
+    retain: bool,
+    value: &T,
+) -> CoinCrabResult<()> {
+    let fields = default_quote_fields();
+    if fields.is_all() {
+        return publish_encoded(mqtt_client, topic, qos, retain, value).await;
+    }
+    publish_encoded(mqtt_client, topic, qos, retain, &fields.select(value)?).await
+}
+
+// fetched_at and source let clients show "Data as of 12:03 via CoinMarketCap"
+// fx documents how the non-USD quotes were derived
+pub async fn publish_crypto_data_to_mqtt(mqtt_client: &AsyncClient, crypto_data: &[CryptoCurrency], fetched_at: SystemTime, source: &str, fx: Option<FxMetadata>) {
+    // Publish all crypto data to main topic with retention
+    let fetched_at = fetched_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let envelope = PriceEnvelope::new(crypto_data, SNAPSHOT_TTL_SECS.load(Ordering::Relaxed)).with_source(fetched_at, source).with_fx(fx);
+    if let Err(e) = publish_quotes(mqtt_client, "crypto/prices/latest", QoS::AtLeastOnce, true, &envelope).await {
         error!("Failed to publish to crypto/prices/latest: {}", e);
     } else {
         info!("Published {} cryptocurrencies to MQTT topic crypto/prices/latest", crypto_data.len());
     }
 }
 
+// Only the coins on one device's watchlist, in the same envelope as crypto/prices/latest
+pub async fn publish_watchlist_bundle_to_mqtt(mqtt_client: &AsyncClient, device_id: &str, coins: &[CryptoCurrency], fetched_at: SystemTime, source: &str, fx: Option<FxMetadata>) {
+    let topic = format!("crypto/watchlists/{}/prices", device_id);
+    let fetched_at = fetched_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let envelope = PriceEnvelope::new(coins, SNAPSHOT_TTL_SECS.load(Ordering::Relaxed)).with_source(fetched_at, source).with_fx(fx);
+    if let Err(e) = publish_quotes(mqtt_client, &topic, QoS::AtLeastOnce, true, &envelope).await {
+        error!("Failed to publish to {}: {}", topic, e);
+    } else {
+        debug!("Published {} watched cryptocurrencies to {}", coins.len(), topic);
+    }
+}
+
+// Not retained: a delta is only meaningful on top of the snapshot before it
+pub async fn publish_price_delta_to_mqtt(mqtt_client: &AsyncClient, delta: &PriceDelta) {
+    if let Err(e) = publish_quotes(mqtt_client, "crypto/prices/delta", QoS::AtLeastOnce, false, &price_envelope(delta)).await {
+        error!("Failed to publish to crypto/prices/delta: {}", e);
+    } else {
+        info!("Published delta #{} with {} changed cryptocurrencies to crypto/prices/delta", delta.seq, delta.coins.len());
+    }
+}
+
+// Retained per-coin topics (crypto/prices/{SYMBOL}) for clients following individual symbols;
+// a new subscriber gets the current quote immediately
+pub async fn publish_symbol_prices_to_mqtt(mqtt_client: &AsyncClient, crypto_data: &[CryptoCurrency]) {
+    let fields = default_quote_fields();
+    let mut failed = 0;
+    for crypto in crypto_data {
+        let topic = format!("crypto/prices/{}", crypto.symbol.to_uppercase());
+        let payload = match fields.select(&price_envelope(crypto)).and_then(|value| Ok(serde_json::to_string(&value)?)) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize {} for MQTT: {}", crypto.symbol, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Err(e) = publish(mqtt_client, &topic, QoS::AtMostOnce, true, payload).await {
+            warn!("Failed to publish to {}: {}", topic, e);
+            failed += 1;
+        }
+    }
+    info!("Published {} per-symbol price topics ({} failed)", crypto_data.len() - failed, failed);
+}
+
 pub async fn publish_historical_data_to_mqtt(
     mqtt_client: &AsyncClient, 
     symbol: &str, 
@@ -35,18 +293,320 @@ pub async fn publish_historical_data_to_mqtt(
     };
     
     let topic = format!("crypto/historical/{}/{}", symbol.to_uppercase(), timeframe);
+    if payload.len() > MAX_HISTORICAL_PAYLOAD_BYTES {
+        publish_historical_chunks(mqtt_client, &topic, &payload).await;
+    } else {
+        // Use QoS 0 for historical data (less critical than live prices)
+        // Set retain=true so clients get immediate data when subscribing
+        if let Err(e) = publish(mqtt_client, &topic, QoS::AtMostOnce, true, payload).await {
+            error!("Failed to publish historical data to {}: {}", topic, e);
+        } else {
+            info!("Published historical data for {} {} to MQTT", symbol, timeframe);
+        }
+    }
+    
+    if data.success && indicator_topics_enabled() {
+        publish_indicators_to_mqtt(mqtt_client, symbol, timeframe, data).await;
+    }
+}
+
+// Retained like the series they are computed from, with every indicator at its default period
+async fn publish_indicators_to_mqtt(mqtt_client: &AsyncClient, symbol: &str, timeframe: &str, data: &HistoricalDataResult) {
+    let indicators = compute_indicators(data, &Indicator::ALL, None);
+    let payload = match serde_json::to_string(&indicators) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize indicators for MQTT: {}", e);
+            return;
+        }
+    };
     
-    // Use QoS 0 for historical data (less critical than live prices)
-    // Set retain=true so clients get immediate data when subscribing
-    if let Err(e) = mqtt_client.publish(&topic, QoS::AtMostOnce, true, payload).await {
-        error!("Failed to publish historical data to {}: {}", topic, e);
+    let topic = format!("crypto/indicators/{}/{}", symbol.to_uppercase(), timeframe);
+    if payload.len() > MAX_HISTORICAL_PAYLOAD_BYTES {
+        warn!("Indicators for {} {} are too large for MQTT ({} bytes) - available over HTTP only", symbol, timeframe, payload.len());
+        return;
+    }
+    if let Err(e) = publish(mqtt_client, &topic, QoS::AtMostOnce, true, payload).await {
+        error!("Failed to publish indicators to {}: {}", topic, e);
     } else {
-        info!("Published historical data for {} {} to MQTT", symbol, timeframe);
+        debug!("Published indicators for {} {} to MQTT", symbol, timeframe);
+    }
+}
+
+// Series larger than this go out in chunks; the broker and clients reject packets over 100 KiB
+const MAX_HISTORICAL_PAYLOAD_BYTES: usize = 96 * 1024;
+// Characters of series JSON per chunk. Escaping the JSON into the chunk's string field at
+// most doubles it, so a chunk stays under the packet limit.
+const HISTORICAL_CHUNK_CHARS: usize = 40 * 1024;
+
+// Split at char boundaries into pieces of at most max_bytes (max_bytes must be at least 4)
+fn split_payload(payload: &str, max_bytes: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(max_bytes);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    pieces
+}
+
+// Not retained: a new subscriber could otherwise receive chunks left over from an older,
+// differently sized publish. Clients that miss a transfer request the series again.
+async fn publish_historical_chunks(mqtt_client: &AsyncClient, topic: &str, payload: &str) {
+    let transfer_id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    let pieces = split_payload(payload, HISTORICAL_CHUNK_CHARS);
+    for (index, piece) in pieces.iter().enumerate() {
+        let chunk = HistoricalChunk { transfer_id, data: piece.to_string() };
+        let chunk_json = match serde_json::to_string(&chunk) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize historical chunk for MQTT: {}", e);
+                return;
+            }
+        };
+        let chunk_topic = shared::historical_chunk_topic(topic, index, pieces.len());
+        // QoS 1: losing any one chunk loses the whole transfer
+        if let Err(e) = publish(mqtt_client, &chunk_topic, QoS::AtLeastOnce, false, chunk_json).await {
+            error!("Failed to publish historical chunk to {}: {}", chunk_topic, e);
+            return;
+        }
+    }
+    info!("Published {} bytes of historical data to {} in {} chunks", payload.len(), topic, pieces.len());
+}
+
+// A downsampled series for the clients that asked for it, each on its own topic; not retained
+// since everyone else gets the full series. False when it doesn't fit in one packet.
+pub async fn publish_sampled_historical_to_mqtt(
+    mqtt_client: &AsyncClient,
+    symbol: &str,
+    timeframe: &str,
+    client_ids: &[&str],
+    data: &HistoricalDataResult
+) -> bool {
+    let payload = match serde_json::to_string(data) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize sampled historical data for MQTT: {}", e);
+            return false;
+        }
+    };
+    if payload.len() > MAX_HISTORICAL_PAYLOAD_BYTES {
+        debug!("Sampled {} {} is too large for one packet ({} bytes)", symbol, timeframe, payload.len());
+        return false;
+    }
+    for client_id in client_ids {
+        let topic = shared::sampled_historical_topic(symbol, timeframe, client_id);
+        if let Err(e) = publish(mqtt_client, &topic, QoS::AtMostOnce, false, payload.clone()).await {
+            error!("Failed to publish sampled historical data to {}: {}", topic, e);
+        }
+    }
+    info!("Published {} sampled points of {} {} to {} clients", data.data.len(), symbol, timeframe, client_ids.len());
+    true
+}
+
+// A custom window for the client that asked for it, on its own topic and not retained. Split
+// into chunks like a full series when it doesn't fit in one packet.
+pub async fn publish_historical_range_to_mqtt(mqtt_client: &AsyncClient, topic: &str, data: &HistoricalDataResult) {
+    let payload = match serde_json::to_string(data) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize historical range for MQTT: {}", e);
+            return;
+        }
+    };
+    if payload.len() > MAX_HISTORICAL_PAYLOAD_BYTES {
+        publish_historical_chunks(mqtt_client, topic, &payload).await;
+    } else if let Err(e) = publish(mqtt_client, topic, QoS::AtLeastOnce, false, payload).await {
+        error!("Failed to publish historical range to {}: {}", topic, e);
+        return;
+    }
+    info!("Published {} points to {}", data.data.len(), topic);
+}
+
+// Incremental update for clients that already hold the series; not retained because
+// each delta is relative to the requesting client's last timestamp
+pub async fn publish_historical_delta_to_mqtt(
+    mqtt_client: &AsyncClient,
+    symbol: &str,
+    timeframe: &str,
+    data: &HistoricalDataResult
+) {
+    let payload = match serde_json::to_string(data) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize historical delta for MQTT: {}", e);
+            return;
+        }
+    };
+
+    let topic = format!("crypto/historical/{}/{}/since", symbol.to_uppercase(), timeframe);
+    if let Err(e) = publish(mqtt_client, &topic, QoS::AtMostOnce, false, payload).await {
+        error!("Failed to publish historical delta to {}: {}", topic, e);
+    } else {
+        info!("Published {} new points for {} {} to MQTT", data.data.len(), symbol, timeframe);
+    }
+}
+
+pub async fn publish_prefetch_hints_to_mqtt(mqtt_client: &AsyncClient, hints: &[PrefetchHint]) {
+    let payload = match serde_json::to_string(hints) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize prefetch hints for MQTT: {}", e);
+            return;
+        }
+    };
+
+    // Retained so clients receive the current hint list as soon as they subscribe
+    if let Err(e) = publish(mqtt_client, "crypto/prefetch/popular", QoS::AtLeastOnce, true, payload).await {
+        error!("Failed to publish to crypto/prefetch/popular: {}", e);
+    } else {
+        info!("Published {} prefetch hints to MQTT topic crypto/prefetch/popular", hints.len());
+    }
+}
+
+// Retained, so a client opening the app later in the day still sees the latest summary
+// Retained so an app opening the portfolio sees the latest valuation immediately
+pub async fn publish_portfolio_valuation_to_mqtt(mqtt_client: &AsyncClient, valuation: &PortfolioValuation) {
+    let topic = format!("crypto/portfolios/{}/valuation", valuation.portfolio_id);
+    let payload = match serde_json::to_string(valuation) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize valuation of {} for MQTT: {}", valuation.portfolio_id, e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, &topic, QoS::AtLeastOnce, true, payload).await {
+        error!("Failed to publish to {}: {}", topic, e);
+    } else {
+        debug!("Published valuation of {} ({:.2} USD) to {}", valuation.portfolio_id, valuation.total_value, topic);
+    }
+}
+
+// Not retained: an alert is delivered once, to whoever is subscribed when it fires
+pub async fn publish_alert_to_mqtt(mqtt_client: &AsyncClient, alert: &TriggeredAlert) {
+    let topic = format!("crypto/alerts/{}", alert.rule.device_id);
+    let payload = match serde_json::to_string(alert) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize alert {} for MQTT: {}", alert.rule.id, e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, &topic, QoS::AtLeastOnce, false, payload).await {
+        error!("Failed to publish to {}: {}", topic, e);
+    } else {
+        info!("Published alert {} ({} {:?} {}) to MQTT topic {}", alert.rule.id, alert.rule.symbol, alert.rule.direction, alert.rule.threshold, topic);
+    }
+}
+
+pub async fn publish_daily_summary_to_mqtt(mqtt_client: &AsyncClient, summary: &DailySummary) {
+    let payload = match serde_json::to_string(summary) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize daily summary for MQTT: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, "crypto/summary/daily", QoS::AtLeastOnce, true, payload).await {
+        error!("Failed to publish to crypto/summary/daily: {}", e);
+    } else {
+        info!("Published daily summary for {} to MQTT topic crypto/summary/daily", summary.date);
+    }
+}
+
+// Bad ticks found in one listings fetch, for monitoring. Not retained: each report covers
+// only the fetch it was made for.
+pub async fn publish_price_anomalies_to_mqtt(mqtt_client: &AsyncClient, anomalies: &[PriceAnomaly]) {
+    let payload = match serde_json::to_string(anomalies) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize price anomalies for MQTT: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, "crypto/diagnostics/anomalies", QoS::AtLeastOnce, false, payload).await {
+        error!("Failed to publish to crypto/diagnostics/anomalies: {}", e);
+    }
+}
+
+// Retained so a trending screen opens with the latest rankings
+pub async fn publish_trending_to_mqtt(mqtt_client: &AsyncClient, gainers: &TrendingMovers, losers: &TrendingMovers) {
+    for (topic, movers) in [("crypto/trending/gainers", gainers), ("crypto/trending/losers", losers)] {
+        let payload = match serde_json::to_string(movers) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize {} for MQTT: {}", topic, e);
+                continue;
+            }
+        };
+        if let Err(e) = publish(mqtt_client, topic, QoS::AtLeastOnce, true, payload).await {
+            error!("Failed to publish to {}: {}", topic, e);
+        }
+    }
+    debug!("Published trending gainers and losers");
+}
+
+// Tell one client its request was rejected; only that client subscribes to its error topic
+pub async fn publish_request_error_to_mqtt(mqtt_client: &AsyncClient, client_id: &str, request_error: &RequestError) {
+    let payload = match serde_json::to_string(request_error) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize request error for MQTT: {}", e);
+            return;
+        }
+    };
+
+    let topic = shared::client_topic(client_id, "errors");
+    if let Err(e) = publish(mqtt_client, &topic, QoS::AtLeastOnce, false, payload).await {
+        warn!("Failed to publish to {}: {}", topic, e);
+    }
+}
+
+// Tell every client waiting on a series that fetching it failed. Not retained: a client that
+// subscribes later should ask again rather than see an old failure.
+pub async fn publish_historical_error_to_mqtt(mqtt_client: &AsyncClient, fetch_error: &HistoricalFetchError) {
+    let payload = match serde_json::to_string(fetch_error) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize historical fetch error for MQTT: {}", e);
+            return;
+        }
+    };
+
+    let topic = shared::historical_error_topic(&fetch_error.symbol, &fetch_error.timeframe);
+    if let Err(e) = publish(mqtt_client, &topic, QoS::AtLeastOnce, false, payload).await {
+        warn!("Failed to publish to {}: {}", topic, e);
+    } else {
+        info!("Published fetch error for {} {} to {}", fetch_error.symbol, fetch_error.timeframe, topic);
+    }
+}
+
+// Answer one correlated request on the reply topic its envelope named
+pub async fn publish_request_response_to_mqtt(mqtt_client: &AsyncClient, reply_topic: &str, response: &RequestResponse) {
+    let payload = match serde_json::to_string(response) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize request response for MQTT: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = publish(mqtt_client, reply_topic, QoS::AtLeastOnce, false, payload).await {
+        warn!("Failed to publish to {}: {}", reply_topic, e);
     }
 }
 
 pub async fn publish_empty_retained_message(mqtt_client: &AsyncClient, topic: &str) {
-    match mqtt_client.publish(topic, rumqttc::QoS::AtLeastOnce, true, "").await {
+    match publish(mqtt_client, topic, QoS::AtLeastOnce, true, "").await {
         Ok(_) => info!("Cleared MQTT retained message for topic: {}", topic),
         Err(e) => warn!("Failed to clear MQTT retained message for {}: {}", topic, e),
     }
@@ -56,7 +616,10 @@ pub async fn clear_all_retained_messages(mqtt_client: &AsyncClient) {
     info!("Clearing all retained MQTT messages on broker startup...");
 
     // Clear the main crypto prices topic
-    publish_empty_retained_message(mqtt_client, "crypto/prices/latest").await;
+    for codec in [PayloadCodec::Json, PayloadCodec::MsgPack] {
+        publish_empty_retained_message(mqtt_client, &codec.topic("crypto/prices/latest")).await;
+    }
+    publish_empty_retained_message(mqtt_client, "crypto/prefetch/popular").await;
 
     // Clear historical data topics - we need to clear known patterns
     // Since we can't use wildcards in publish, clear common historical topics
@@ -73,6 +636,34 @@ pub async fn clear_all_retained_messages(mqtt_client: &AsyncClient) {
     info!("Completed clearing retained MQTT messages");
 }
 
+// Held by tests that change the process-wide publisher settings: serializes them and restores
+// the previous settings on drop, even if the test panics
+#[cfg(test)]
+pub(crate) struct SettingsGuard {
+    _lock: std::sync::MutexGuard<'static, ()>,
+    dry_run: bool,
+    topic_prefix: String,
+}
+
+#[cfg(test)]
+static SETTINGS_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(test)]
+impl SettingsGuard {
+    pub(crate) fn lock() -> Self {
+        let lock = SETTINGS_TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        SettingsGuard { _lock: lock, dry_run: is_dry_run(), topic_prefix: topic_prefix() }
+    }
+}
+
+#[cfg(test)]
+impl Drop for SettingsGuard {
+    fn drop(&mut self) {
+        set_dry_run(self.dry_run);
+        *TOPIC_PREFIX.write().unwrap() = std::mem::take(&mut self.topic_prefix);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,10 +684,20 @@ mod tests {
                     volume_24h: 50000000000.0,
                     last_updated: "2024-01-01T00:00:00Z".to_string(),
                 },
+                converted: Default::default(),
             },
         }
     }
 
+    #[test]
+    fn test_split_payload_respects_char_boundaries() {
+        let payload = "ab\u{20ac}cd\u{20ac}";
+        let pieces = split_payload(payload, 4);
+        assert_eq!(pieces, vec!["ab", "\u{20ac}c", "d\u{20ac}"]);
+        assert_eq!(pieces.concat(), payload);
+        assert!(split_payload("", 4).is_empty());
+    }
+
     #[test]
     fn test_crypto_data_serialization() {
         let crypto = create_test_crypto();
@@ -113,6 +714,9 @@ mod tests {
             success: true,
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
             data: vec![
                 HistoricalDataPoint {
                     timestamp: 1704067200.0, // Unix timestamp for 2024-01-01T00:00:00Z
@@ -136,6 +740,43 @@ mod tests {
         assert!(json.contains("45500.0"));
     }
 
+    #[test]
+    fn test_prefetch_hints_serialization() {
+        let hints = vec![PrefetchHint {
+            symbol: "BTC".to_string(),
+            timeframe: "24h".to_string(),
+            cached_at: 1704067200,
+        }];
+
+        let json = serde_json::to_string(&hints).unwrap();
+        assert!(json.starts_with('['));
+        assert!(json.contains("\"timeframe\":\"24h\""));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_skips_broker() {
+        // Dropping the event loop closes the request channel, so a real publish fails
+        let options = rumqttc::MqttOptions::new("dry-run-test", "127.0.0.1", 1);
+        let (client, eventloop) = AsyncClient::new(options, 1);
+        drop(eventloop);
+        let _settings = SettingsGuard::lock();
+
+        assert!(publish(&client, "crypto/prices/latest", QoS::AtLeastOnce, true, "x").await.is_err());
+
+        set_dry_run(true);
+        assert!(publish(&client, "crypto/prices/latest", QoS::AtLeastOnce, true, "x").await.is_ok());
+    }
+
+    #[test]
+    fn test_prefixed_topic() {
+        let _settings = SettingsGuard::lock();
+        set_topic_prefix("");
+        assert_eq!(prefixed_topic("crypto/prices/latest"), "crypto/prices/latest");
+
+        set_topic_prefix("/staging/");
+        assert_eq!(prefixed_topic("crypto/prices/latest"), "staging/crypto/prices/latest");
+    }
+
     #[test]
     fn test_mqtt_topic_formatting() {
         let symbol = "BTC";
@@ -166,6 +807,7 @@ mod tests {
                         volume_24h: 25000000000.0,
                         last_updated: "2024-01-01T00:00:00Z".to_string(),
                     },
+                    converted: Default::default(),
                 },
             },
         ];
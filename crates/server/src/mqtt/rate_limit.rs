@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::config::ClientRateLimitConfig;
+
+// Above this many tracked clients, buckets that have refilled completely are dropped
+const MAX_IDLE_CLIENTS: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // Set while the client is being rejected, so only the first rejection is logged loudly
+    throttled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateDecision {
+    Allowed,
+    // First rejection since the client was last allowed through
+    Throttled { retry_after: Duration },
+    // Still throttled; already reported
+    StillThrottled { retry_after: Duration },
+}
+
+// Token bucket per MQTT client id, protecting provider credits from a single client that
+// floods the request topics
+pub struct ClientRateLimiter {
+    config: ClientRateLimitConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl ClientRateLimiter {
+    pub fn new(config: ClientRateLimitConfig) -> Self {
+        ClientRateLimiter {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, client_id: &str, now: Instant) -> RateDecision {
+        self.check_cost(client_id, 1, now)
+    }
+
+    // Take `cost` tokens at once. A request costing more than the burst is let through on a full
+    // bucket and leaves it in debt, so large requests are slowed down rather than never allowed.
+    pub fn check_cost(&self, client_id: &str, cost: u32, now: Instant) -> RateDecision {
+        if !self.config.is_enabled() {
+            return RateDecision::Allowed;
+        }
+        let burst = f64::from(self.config.burst);
+        let per_second = f64::from(self.config.requests_per_minute) / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < burst
+            });
+        }
+        let bucket = buckets.entry(client_id.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            throttled: false,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.updated = now;
+
+        let needed = f64::from(cost.max(1)).min(burst);
+        if bucket.tokens >= needed {
+            bucket.tokens -= f64::from(cost.max(1));
+            bucket.throttled = false;
+            return RateDecision::Allowed;
+        }
+        let retry_after = Duration::from_secs_f64((needed - bucket.tokens) / per_second);
+        if std::mem::replace(&mut bucket.throttled, true) {
+            RateDecision::StillThrottled { retry_after }
+        } else {
+            RateDecision::Throttled { retry_after }
+        }
+    }
+
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests_per_minute: u32, burst: u32) -> ClientRateLimiter {
+        ClientRateLimiter::new(ClientRateLimitConfig { requests_per_minute, burst })
+    }
+
+    #[test]
+    fn test_burst_then_throttle_per_client() {
+        let limiter = limiter(60, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check("app-a", start), RateDecision::Allowed);
+        }
+        match limiter.check("app-a", start) {
+            RateDecision::Throttled { retry_after } => assert_eq!(retry_after, Duration::from_secs(1)),
+            other => panic!("expected throttling, got {:?}", other),
+        }
+        assert!(matches!(limiter.check("app-a", start), RateDecision::StillThrottled { .. }));
+
+        // Other clients have their own budget
+        assert_eq!(limiter.check("app-b", start), RateDecision::Allowed);
+
+        // One token back per second at 60/min
+        assert_eq!(limiter.check("app-a", start + Duration::from_secs(1)), RateDecision::Allowed);
+        assert!(matches!(limiter.check("app-a", start + Duration::from_secs(1)), RateDecision::Throttled { .. }));
+        assert_eq!(limiter.tracked_clients(), 2);
+    }
+
+    #[test]
+    fn test_cost_above_burst_runs_into_debt() {
+        let limiter = limiter(60, 5);
+        let start = Instant::now();
+        assert_eq!(limiter.check_cost("app", 3, start), RateDecision::Allowed);
+        // Only 2 tokens left
+        match limiter.check_cost("app", 3, start) {
+            RateDecision::Throttled { retry_after } => assert_eq!(retry_after, Duration::from_secs(1)),
+            other => panic!("expected throttling, got {:?}", other),
+        }
+        // 20 series need a full bucket and leave 15 tokens owed
+        let later = start + Duration::from_secs(3);
+        assert_eq!(limiter.check_cost("app", 20, later), RateDecision::Allowed);
+        match limiter.check("app", later) {
+            RateDecision::Throttled { retry_after } => assert_eq!(retry_after, Duration::from_secs(16)),
+            other => panic!("expected throttling, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_disabled_limit_allows_everything() {
+        let limiter = limiter(0, 1);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(limiter.check("app", now), RateDecision::Allowed);
+        }
+        assert_eq!(limiter.tracked_clients(), 0);
+    }
+}
@@ -1,28 +1,339 @@
 use actix_web::web;
 use rumqttc::{MqttOptions, AsyncClient, QoS, Event, Packet};
-use std::time::Duration;
-use log::{info, warn, error, debug};
+use std::time::{Duration, Instant};
+use tracing::{info, warn, error, debug};
+use crate::alerts::AlertRuleRequest;
 use crate::types::AppState;
-use crate::data::fetch_historical_data_server;
-use crate::mqtt::publish_historical_data_to_mqtt;
+use crate::config::ServerConfig;
+use crate::mqtt::broker::internal_client_host;
+use crate::data::{fetch_crypto_data, historical_points_after, load_historical_data, load_historical_range, publish_prefetch_hints, publish_watchlist};
+use crate::watchlists::parse_watchlist_payload;
+use crate::mqtt::{publish_historical_data_to_mqtt, publish_historical_delta_to_mqtt, publish_historical_range_to_mqtt, publish_sampled_historical_to_mqtt, publish_request_error_to_mqtt, publish_request_response_to_mqtt, publish_historical_error_to_mqtt, prefixed_topic, unprefixed_topic};
+use crate::mqtt::capabilities::capabilities_client;
+use crate::mqtt::logo_assets::publish_requested_logos;
+use crate::mqtt::rate_limit::{ClientRateLimiter, RateDecision};
+use crate::mqtt::request_queue::{HistoricalRequest, ReplyTo, RequestPriority, RequestQueue, RequestedRange};
+use crate::providers::{HistoricalRange, MarketDataProvider};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use shared::{ClientCapabilities, CoinCrabError, CoinCrabResult, MIN_SAMPLED_POINTS, HistoricalFetchError, HistoricalRequestEnvelope, RequestError, RequestResponse};
 
-pub async fn setup_mqtt_request_handling(state: web::Data<AppState>) -> Result<(), String> {
+// Rate limit bucket shared by builds that still send requests on the unidentified topics
+const UNIDENTIFIED_CLIENT: &str = "unidentified";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RequestKind {
+    Historical(RequestPriority),
+    // Pull-to-refresh: fetch the listings now instead of waiting for the next interval
+    RefreshPrices,
+    // Publish logos to crypto/assets/logos/{SYMBOL}
+    Logos,
+}
+
+// Kind of a request topic (namespace already stripped) and the client id for per-client
+// topics: crypto/clients/{id}/requests/..., or the legacy shared crypto/requests/...
+fn classify_request_topic(topic: &str) -> Option<(RequestKind, Option<&str>)> {
+    let (client_id, request) = match shared::split_client_topic(topic) {
+        Some((client_id, rest)) => (Some(client_id), rest),
+        None => (None, topic.strip_prefix("crypto/")?),
+    };
+    match request {
+        "requests/historical" => Some((RequestKind::Historical(RequestPriority::Interactive), client_id)),
+        "requests/historical/background" => Some((RequestKind::Historical(RequestPriority::Background), client_id)),
+        "requests/refresh-prices" => Some((RequestKind::RefreshPrices, client_id)),
+        "requests/logos" => Some((RequestKind::Logos, client_id)),
+        _ => None,
+    }
+}
+
+// Device id of a crypto/alerts/register/{device_id} topic (namespace already stripped)
+fn alert_registration_device(topic: &str) -> Option<&str> {
+    topic
+        .strip_prefix("crypto/alerts/register/")
+        .filter(|device_id| !device_id.is_empty() && !device_id.contains('/'))
+}
+
+// Every instance applies registrations so a standby that takes over already has the rules
+async fn register_alerts(state: &web::Data<AppState>, device_id: &str, payload: &[u8]) {
+    let requests: Vec<AlertRuleRequest> = match serde_json::from_slice(payload) {
+        Ok(requests) => requests,
+        Err(e) => {
+            warn!("Invalid alert registration from {}: {}", device_id, e);
+            return;
+        }
+    };
+    match state.alerts.register(device_id, requests) {
+        Ok(count) => info!("Registered {} alert rules for {} ({} in total)", count, device_id, state.alerts.rule_count()),
+        Err(e) => {
+            warn!("Rejected alert registration from {}: {}", device_id, e);
+            return;
+        }
+    }
+    if let Err(e) = state.alerts.persist().await {
+        error!("Failed to save alert rules: {}", e);
+    }
+}
+
+// Device id of a crypto/watchlists/register/{device_id} topic (namespace already stripped)
+fn watchlist_registration_device(topic: &str) -> Option<&str> {
+    topic
+        .strip_prefix("crypto/watchlists/register/")
+        .filter(|device_id| !device_id.is_empty() && !device_id.contains('/'))
+}
+
+// Every instance keeps the watchlist; only the leader publishes the first bundle
+async fn register_watchlist(state: &web::Data<AppState>, device_id: &str, payload: &[u8]) {
+    let symbols = match parse_watchlist_payload(payload).and_then(|symbols| state.watchlists.register(device_id, symbols)) {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            warn!("Rejected watchlist from {}: {}", device_id, e);
+            return;
+        }
+    };
+    info!("Registered watchlist of {} symbols for {} ({} watchlists)", symbols.len(), device_id, state.watchlists.len());
+    if !symbols.is_empty() && state.leader.is_leader() {
+        publish_watchlist(state, device_id, &symbols).await;
+    }
+}
+
+// Per-client limits only bound each client; this bounds provider calls across all of them.
+// Listings fetched less than min_interval ago are already fresh, and only one refresh runs at a time.
+fn start_price_refresh(state: &web::Data<AppState>, in_flight: &Arc<AtomicBool>, min_interval: Duration) {
+    let age = state.last_fetch.lock().unwrap().elapsed().unwrap_or_default();
+    if age < min_interval {
+        debug!("Prices fetched {}s ago - ignoring refresh request", age.as_secs());
+        return;
+    }
+    if in_flight.swap(true, Ordering::SeqCst) {
+        debug!("Price refresh already running - ignoring refresh request");
+        return;
+    }
+    info!("Refreshing prices on client request ({}s since the last fetch)", age.as_secs());
+    let state = state.clone();
+    let in_flight = in_flight.clone();
+    tokio::spawn(async move {
+        fetch_crypto_data(&state).await;
+        in_flight.store(false, Ordering::SeqCst);
+    });
+}
+
+// Apply the per-client rate limit. Throttled clients that identified themselves are told
+// when to retry; unidentified ones can only be dropped. Err holds the retry delay.
+async fn admit_request(
+    state: &web::Data<AppState>,
+    limiter: &ClientRateLimiter,
+    client_id: Option<&str>,
+    payload: &str,
+) -> Result<(), Duration> {
+    let bucket = client_id.unwrap_or(UNIDENTIFIED_CLIENT);
+    let retry_after = match limiter.check(bucket, Instant::now()) {
+        RateDecision::Allowed => return Ok(()),
+        RateDecision::Throttled { retry_after } => {
+            warn!("Throttling requests from client {} for {:.1}s ({} clients tracked)",
+                  bucket, retry_after.as_secs_f64(), limiter.tracked_clients());
+            retry_after
+        }
+        RateDecision::StillThrottled { retry_after } => {
+            debug!("Dropping request {} from throttled client {}", payload, bucket);
+            retry_after
+        }
+    };
+    if let Some(client_id) = client_id {
+        let request_error = RequestError {
+            request: payload.to_string(),
+            error: "rate limit exceeded".to_string(),
+            retry_after_seconds: Some(retry_after.as_secs_f64().ceil() as u64),
+        };
+        publish_request_error_to_mqtt(&state.mqtt_client, client_id, &request_error).await;
+    }
+    Err(retry_after)
+}
+
+// Parse "SYMBOL:TIMEFRAME", or "SYMBOL:TIMEFRAME:AFTER" for an incremental refresh
+// where AFTER is the unix timestamp of the newest point the client already has
+fn parse_historical_request(payload: &str) -> Option<(String, String, Option<f64>)> {
+    let mut parts = payload.split(':');
+    let symbol = parts.next().filter(|s| !s.is_empty())?;
+    let timeframe = parts.next().filter(|s| !s.is_empty())?;
+    let after = match parts.next() {
+        Some(after) => Some(after.parse::<f64>().ok()?),
+        None => None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((symbol.to_string(), timeframe.to_string(), after))
+}
+
+// Most points a downsampled series is sent with, keeping it and the bucket statistics that
+// come with each point within one MQTT packet
+const MAX_SAMPLED_POINTS: usize = 350;
+
+// A historical request payload: the JSON envelope, or the legacy text format (no replies).
+// An envelope's reply_to must be a response topic for its own request id and, on a per-client
+// request topic, for that client, so one client can't direct replies at another.
+fn historical_request(payload: &str, client_id: Option<&str>, priority: RequestPriority) -> Option<HistoricalRequest> {
+    if !payload.trim_start().starts_with('{') {
+        let (symbol, timeframe, after) = parse_historical_request(payload)?;
+        return Some(HistoricalRequest { symbol, timeframe, after, max_points: None, range: None, priority, replies: Vec::new() });
+    }
+    let envelope: HistoricalRequestEnvelope = serde_json::from_str(payload).ok()?;
+    let (reply_client, reply_request) = shared::split_response_topic(&envelope.reply_to)?;
+    if reply_request != envelope.request_id || client_id.is_some_and(|id| id != reply_client) {
+        return None;
+    }
+    if envelope.symbol.is_empty() || (envelope.timeframe.is_empty() && envelope.start.is_none()) {
+        return None;
+    }
+    if envelope.max_points.is_some_and(|max_points| max_points < MIN_SAMPLED_POINTS) {
+        return None;
+    }
+    Some(HistoricalRequest {
+        symbol: envelope.symbol,
+        timeframe: envelope.timeframe,
+        after: envelope.after,
+        max_points: envelope.max_points.map(|max_points| max_points.min(MAX_SAMPLED_POINTS)),
+        range: envelope.start.map(|start| RequestedRange { start, end: envelope.end, interval: envelope.interval }),
+        priority,
+        replies: vec![ReplyTo { request_id: envelope.request_id, topic: envelope.reply_to }],
+    })
+}
+
+// Answer everyone waiting on a request
+async fn send_replies(state: &web::Data<AppState>, replies: &[ReplyTo], response: impl Fn(&str) -> RequestResponse) {
+    for reply in replies {
+        publish_request_response_to_mqtt(&state.mqtt_client, &reply.topic, &response(&reply.request_id)).await;
+    }
+}
+
+// Fetch one queued series and publish it (or just the new points for an incremental refresh)
+async fn process_historical_request(state: &web::Data<AppState>, request: HistoricalRequest) {
+    let HistoricalRequest { symbol, timeframe, after, max_points, range, priority, replies } = request;
+    let symbol = state.canonical_symbol(&symbol);
+    if let Some(range) = range {
+        process_range_request(state, &symbol, range, max_points, &replies).await;
+        return;
+    }
+    info!("Processing {:?} request for {} {}", priority, symbol, timeframe);
+    
+    let result = load_historical_data(state, &symbol, &timeframe).await;
+    
+    if !result.success {
+        error!("Failed to fetch {} {}: {:?}", symbol, timeframe, result.error);
+        let fetch_error = HistoricalFetchError {
+            error: result.error.unwrap_or_else(|| format!("Failed to fetch {} {}", symbol, timeframe)),
+            retry_after_seconds: state.market_data.retry_after().map(|delay| delay.as_secs().max(1)),
+            failed_at: chrono::Utc::now().timestamp(),
+            symbol,
+            timeframe,
+        };
+        publish_historical_error_to_mqtt(&state.mqtt_client, &fetch_error).await;
+        send_replies(state, &replies, |request_id| RequestResponse {
+            retry_after_seconds: fetch_error.retry_after_seconds,
+            ..RequestResponse::failed(request_id, &fetch_error.error)
+        }).await;
+        return;
+    }
+    
+    info!("Loaded {} {} - publishing to MQTT", symbol, timeframe);
+    // Replies follow the data on the same connection, so clients have the series when they arrive
+    if let Some(after) = after {
+        let delta = historical_points_after(result, after);
+        publish_historical_delta_to_mqtt(&state.mqtt_client, &symbol, &timeframe, &delta).await;
+        send_replies(state, &replies, RequestResponse::succeeded).await;
+        return;
+    }
+    if let Some(max_points) = max_points {
+        let sampled = shared::downsample(result.clone(), max_points);
+        let clients: Vec<&str> = replies.iter().filter_map(|reply| Some(shared::split_response_topic(&reply.topic)?.0)).collect();
+        if publish_sampled_historical_to_mqtt(&state.mqtt_client, &symbol, &timeframe, &clients, &sampled).await {
+            send_replies(state, &replies, RequestResponse::succeeded).await;
+            return;
+        }
+    }
+    publish_historical_data_to_mqtt(&state.mqtt_client, &symbol, &timeframe, &result).await;
+    info!("Published {} {} to MQTT successfully", symbol, timeframe);
+    send_replies(state, &replies, RequestResponse::succeeded).await;
+    publish_prefetch_hints(state).await;
+}
+
+// Fetch a custom window and send it to each requester's range topic. Invalid windows are
+// answered with the reason instead of being dropped.
+async fn process_range_request(state: &web::Data<AppState>, symbol: &str, requested: RequestedRange, max_points: Option<usize>, replies: &[ReplyTo]) {
+    let now = chrono::Utc::now().timestamp();
+    let range = match HistoricalRange::parse(&requested.start, requested.end.as_deref(), requested.interval.as_deref(), now) {
+        Ok(range) => range,
+        Err(e) => {
+            warn!("Rejecting historical range request for {}: {}", symbol, e);
+            send_replies(state, replies, |request_id| RequestResponse::failed(request_id, e.message())).await;
+            return;
+        }
+    };
+    info!("Processing range request for {} over {} every {}", symbol, range.label(), range.interval);
+
+    let result = load_historical_range(state, symbol, &range).await;
+    if !result.success {
+        let error = result.error.unwrap_or_else(|| format!("Failed to fetch {} over {}", symbol, range.label()));
+        error!("{}", error);
+        let retry_after_seconds = state.market_data.retry_after().map(|delay| delay.as_secs().max(1));
+        send_replies(state, replies, |request_id| RequestResponse { retry_after_seconds, ..RequestResponse::failed(request_id, &error) }).await;
+        return;
+    }
+    let result = match max_points {
+        Some(max_points) => shared::downsample(result, max_points),
+        None => result,
+    };
+    for reply in replies {
+        let Some((client_id, _)) = shared::split_response_topic(&reply.topic) else {
+            continue;
+        };
+        let topic = shared::historical_range_topic(symbol, client_id, &reply.request_id);
+        publish_historical_range_to_mqtt(&state.mqtt_client, &topic, &result).await;
+    }
+    send_replies(state, replies, RequestResponse::succeeded).await;
+}
+
+// A fixed pool of workers drains the queue, bounding concurrent provider requests
+fn spawn_request_workers(state: web::Data<AppState>, queue: Arc<RequestQueue>, concurrency: usize) {
+    for _ in 0..concurrency.max(1) {
+        let state = state.clone();
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            loop {
+                let request = queue.pop().await;
+                process_historical_request(&state, request).await;
+            }
+        });
+    }
+}
+
+pub async fn setup_mqtt_request_handling(state: web::Data<AppState>, config: &ServerConfig) -> CoinCrabResult<()> {
+    let concurrency = config.historical_request_concurrency;
+    let rate_limit = config.client_rate_limit.clone();
     let client = &*state.mqtt_client;
+    let request_topic = prefixed_topic("crypto/requests/historical");
+    // Pre-warm requests from idle clients; served only when no interactive request is waiting
+    let background_topic = prefixed_topic("crypto/requests/historical/background");
+    // Same requests from clients that identify themselves, rate limited per client id
+    let client_request_topics = [
+        prefixed_topic(&shared::client_topic("+", "requests/historical")),
+        prefixed_topic(&shared::client_topic("+", "requests/historical/background")),
+        prefixed_topic(&shared::client_topic("+", "requests/refresh-prices")),
+        prefixed_topic(&shared::client_topic("+", "requests/logos")),
+        prefixed_topic("crypto/requests/refresh-prices"),
+    ];
     
     // Subscribe to historical data request topic
-    if let Err(e) = client.subscribe("crypto/requests/historical", QoS::AtLeastOnce).await {
+    if let Err(e) = client.subscribe(&request_topic, QoS::AtLeastOnce).await {
         error!("Failed to subscribe to request topic: {}", e);
-        return Err(format!("Failed to subscribe to request topic: {}", e));
+        return Err(CoinCrabError::Mqtt(format!("Failed to subscribe to request topic: {}", e)));
     } else {
-        info!("Subscribed to crypto/requests/historical topic");
+        info!("Subscribed to {} topic", request_topic);
     }
     
     // Create a new client connection for the event loop
-    let broker_host = std::env::var("MQTT_BROKER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let broker_port = std::env::var("MQTT_BROKER_PORT")
-        .and_then(|s| s.parse().map_err(|_| std::env::VarError::NotPresent))
-        .unwrap_or(1883);
-    let mut mqttoptions = MqttOptions::new("crypto-server-subscriber", &broker_host, broker_port);
+    let broker_host = internal_client_host(&config.mqtt_broker_host, config.mqtt_tls.enabled);
+    let mut mqttoptions = MqttOptions::new("crypto-server-subscriber", &broker_host, config.mqtt_broker_port);
     mqttoptions.set_keep_alive(Duration::from_secs(30));
     mqttoptions.set_clean_session(true);
     mqttoptions.set_max_packet_size(102400, 102400);
@@ -30,9 +341,43 @@ pub async fn setup_mqtt_request_handling(state: web::Data<AppState>) -> Result<(
     let (event_client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
     
     // Subscribe with the event client
-    if let Err(e) = event_client.subscribe("crypto/requests/historical", QoS::AtLeastOnce).await {
+    if let Err(e) = event_client.subscribe(&request_topic, QoS::AtLeastOnce).await {
         error!("Failed to subscribe to request topic with event client: {}", e);
-        return Err(format!("Failed to subscribe to request topic: {}", e));
+        return Err(CoinCrabError::Mqtt(format!("Failed to subscribe to request topic: {}", e)));
+    }
+    if let Err(e) = event_client.subscribe(&background_topic, QoS::AtLeastOnce).await {
+        warn!("Failed to subscribe to background request topic: {}", e);
+    }
+    for topic in &client_request_topics {
+        if let Err(e) = event_client.subscribe(topic, QoS::AtLeastOnce).await {
+            warn!("Failed to subscribe to {}: {}", topic, e);
+        }
+    }
+    if rate_limit.is_enabled() {
+        info!("Limiting historical requests to {} per minute per client (burst {})",
+              rate_limit.requests_per_minute, rate_limit.burst);
+    }
+    let limiter = ClientRateLimiter::new(rate_limit);
+    let refresh_min_interval = Duration::from_secs(config.price_refresh_min_interval_seconds);
+    let refresh_in_flight = Arc::new(AtomicBool::new(false));
+    
+    let queue = Arc::new(RequestQueue::new());
+    spawn_request_workers(state.clone(), queue.clone(), concurrency);
+    info!("Processing historical requests with {} workers", concurrency.max(1));
+    
+    // Anonymized parse-failure reports from clients with MQTT_REPORT_PARSE_FAILURES enabled
+    let diagnostics_topic = prefixed_topic("crypto/diagnostics/parse_failures");
+    if let Err(e) = event_client.subscribe(&diagnostics_topic, QoS::AtMostOnce).await {
+        warn!("Failed to subscribe to client diagnostics topic: {}", e);
+    }
+    if let Err(e) = event_client.subscribe(&prefixed_topic("crypto/alerts/register/+"), QoS::AtLeastOnce).await {
+        warn!("Failed to subscribe to alert registration topic: {}", e);
+    }
+    if let Err(e) = event_client.subscribe(&prefixed_topic("crypto/watchlists/register/+"), QoS::AtLeastOnce).await {
+        warn!("Failed to subscribe to watchlist registration topic: {}", e);
+    }
+    if let Err(e) = event_client.subscribe(&prefixed_topic(&shared::client_topic("+", "capabilities")), QoS::AtLeastOnce).await {
+        warn!("Failed to subscribe to client capabilities topic: {}", e);
     }
     
     // Clone state for the event loop
@@ -49,43 +394,74 @@ pub async fn setup_mqtt_request_handling(state: web::Data<AppState>) -> Result<(
                 }
                 Ok(Event::Incoming(Packet::Publish(publish))) => {
                     let topic = &publish.topic;
-                    if topic == "crypto/requests/historical" {
-                        let payload = std::str::from_utf8(&publish.payload).unwrap_or("").to_string();
-                        info!("Received historical data request: {}", payload);
-                        
-                        // Parse request (format: "SYMBOL:TIMEFRAME")
-                        if let Some((symbol, timeframe)) = payload.split_once(':') {
-                            let symbol = symbol.to_string();
-                            let timeframe = timeframe.to_string();
-                            info!("Processing request for {} {}", symbol, timeframe);
-                            
-                            // Fetch data from CMC API and publish to MQTT
-                            let state_clone = state_for_requests.clone();
-                            tokio::spawn(async move {
-                                let result = fetch_historical_data_server(
-                                    &symbol, 
-                                    &timeframe, 
-                                    &state_clone.api_key, 
-                                    &state_clone.client
-                                ).await;
-                                
-                                if result.success {
-                                    info!("Successfully fetched {} {} - publishing to MQTT", symbol, timeframe);
-                                    publish_historical_data_to_mqtt(
-                                        &state_clone.mqtt_client, 
-                                        &symbol, 
-                                        &timeframe, 
-                                        &result
-                                    ).await;
-                                    info!("Published {} {} to MQTT successfully", symbol, timeframe);
-                                } else {
-                                    error!("Failed to fetch {} {}: {:?}", symbol, timeframe, result.error);
-                                }
-                            });
-                        } else {
-                            warn!("Invalid request format: {}", payload);
+                    if *topic == diagnostics_topic {
+                        warn!("Client parse failure report: {}", String::from_utf8_lossy(&publish.payload));
+                        continue;
+                    }
+                    if let Some(device_id) = unprefixed_topic(topic).and_then(alert_registration_device) {
+                        register_alerts(&state_for_requests, device_id, &publish.payload).await;
+                        continue;
+                    }
+                    if let Some(device_id) = unprefixed_topic(topic).and_then(watchlist_registration_device) {
+                        register_watchlist(&state_for_requests, device_id, &publish.payload).await;
+                        continue;
+                    }
+                    if let Some(client_id) = unprefixed_topic(topic).and_then(capabilities_client) {
+                        match serde_json::from_slice::<ClientCapabilities>(&publish.payload) {
+                            Ok(capabilities) => {
+                                state_for_requests.client_capabilities.record(client_id, capabilities);
+                            }
+                            Err(e) => warn!("Invalid capabilities from {}: {}", client_id, e),
                         }
+                        continue;
                     }
+                    let Some((kind, client_id)) = unprefixed_topic(topic).and_then(classify_request_topic) else {
+                        continue;
+                    };
+                    // Every instance on a shared broker receives requests; only the leader answers them
+                    if !state_for_requests.leader.is_leader() {
+                        debug!("Standby instance - ignoring client request");
+                        continue;
+                    }
+                    let payload = std::str::from_utf8(&publish.payload).unwrap_or("").to_string();
+                    info!("Received {:?} request from {}: {}", kind,
+                          client_id.unwrap_or(UNIDENTIFIED_CLIENT), payload);
+                    let admitted = admit_request(&state_for_requests, &limiter, client_id, &payload).await;
+                    let priority = match kind {
+                        RequestKind::Historical(priority) => priority,
+                        RequestKind::RefreshPrices => {
+                            if admitted.is_ok() {
+                                start_price_refresh(&state_for_requests, &refresh_in_flight, refresh_min_interval);
+                            }
+                            continue;
+                        }
+                        RequestKind::Logos => {
+                            // CDN fetches shouldn't hold up the event loop
+                            if admitted.is_ok() {
+                                let state = state_for_requests.clone();
+                                let payload = publish.payload.clone();
+                                tokio::spawn(async move { publish_requested_logos(&state, &payload).await });
+                            }
+                            continue;
+                        }
+                    };
+                    
+                    let Some(request) = historical_request(&payload, client_id, priority) else {
+                        warn!("Invalid request format: {}", payload);
+                        continue;
+                    };
+                    if let Err(retry_after) = admitted {
+                        let retry_after_seconds = Some(retry_after.as_secs_f64().ceil() as u64);
+                        send_replies(&state_for_requests, &request.replies, |request_id| RequestResponse {
+                            retry_after_seconds,
+                            ..RequestResponse::failed(request_id, "rate limit exceeded")
+                        }).await;
+                        continue;
+                    }
+                    if !queue.push(request) {
+                        debug!("Historical request {} already queued", payload);
+                    }
+                    debug!("{} historical requests pending", queue.pending());
                 }
                 Ok(event) => {
                     debug!("MQTT request handler event: {:?}", event);
@@ -108,6 +484,20 @@ mod tests {
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
     use reqwest::Client;
+    use crate::alerts::AlertEngine;
+    use crate::watchlists::WatchlistRegistry;
+    use crate::fx::FxRates;
+    use crate::portfolio::PortfolioStore;
+    use crate::pinned::PinnedSymbols;
+    use crate::config::{AlertsConfig, FxConfig, PortfolioConfig, PinnedConfig, HttpClientConfig, ReloadableConfig};
+    use crate::http_client::RetryPolicy;
+    use crate::leader::LeaderElection;
+    use crate::logos::LogoCache;
+    use crate::identity::IdentityMap;
+    use crate::persistence::WriteBehindCache;
+    use crate::storage::Storage;
+    use crate::cluster_cache::ClusterCache;
+    use crate::providers::{CoinMarketCapProvider, MarketData, MarketDataProvider};
 
     fn create_test_app_state() -> web::Data<AppState> {
         // Create a mock MQTT client (this won't actually connect in tests)
@@ -120,12 +510,31 @@ mod tests {
             cache: Arc::new(Mutex::new(None)),
             last_fetch: Arc::new(Mutex::new(std::time::SystemTime::now())),
             client: Client::new(),
-            api_key: "test_api_key".to_string(),
+            retry_policy: RetryPolicy::from_config(&HttpClientConfig::default()),
+            market_data: Arc::new(MarketData::new(
+                Arc::new(CoinMarketCapProvider::new(Client::new(), RetryPolicy::from_config(&HttpClientConfig::default()), "test_api_key".to_string())),
+                None,
+                Duration::from_secs(300),
+            )),
             mqtt_client: Arc::new(mqtt_client),
             historical_cache: Arc::new(Mutex::new(HashMap::new())),
-            update_interval_seconds: 300,
+            settings: tokio::sync::watch::channel(ReloadableConfig { update_interval_seconds: 300, ..Default::default() }).1,
             cmc_mapping: Arc::new(Mutex::new(HashMap::new())),
-            logo_cache: Arc::new(Mutex::new(HashMap::new())),
+            identity_map: Arc::new(Mutex::new(IdentityMap::new())),
+        logo_cache: Arc::new(Mutex::new(LogoCache::new())),
+            leader: Arc::new(LeaderElection::standalone()),
+            persistence: Arc::new(WriteBehindCache::disabled()),
+            storage: Arc::new(Storage::disabled()),
+            cluster_cache: Arc::new(ClusterCache::disabled()),
+            alerts: Arc::new(AlertEngine::new(&AlertsConfig::default())),
+            watchlists: Arc::new(WatchlistRegistry::new(100)),
+            fx: Arc::new(FxRates::new(&FxConfig::default())),
+            portfolios: Arc::new(PortfolioStore::new(&PortfolioConfig::default())),
+            pinned: Arc::new(PinnedSymbols::new(&PinnedConfig::default())),
+            historical_in_flight: Arc::new(crate::coalesce::InFlight::new()),
+            gap_repairs: Arc::new(crate::gaps::GapRepairs::default()),
+            price_screen: Arc::new(crate::anomalies::PriceScreen::new(crate::config::AnomalyConfig::default())),
+            client_capabilities: Arc::new(crate::mqtt::capabilities::ClientCapabilityRegistry::new()),
         })
     }
 
@@ -157,6 +566,89 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_historical_request() {
+        assert_eq!(
+            parse_historical_request("BTC:24h"),
+            Some(("BTC".to_string(), "24h".to_string(), None))
+        );
+        assert_eq!(
+            parse_historical_request("ETH:7d:1704067200"),
+            Some(("ETH".to_string(), "7d".to_string(), Some(1704067200.0)))
+        );
+        for invalid in ["BTC", "BTC:", ":24h", "", "BTC:24h:extra", "BTC:24h:1:2"] {
+            assert_eq!(parse_historical_request(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_historical_request_envelope() {
+        let payload = r#"{"request_id":"r1","symbol":"BTC","timeframe":"24h","after":1704067200,"reply_to":"crypto/responses/ios-42/r1"}"#;
+        let request = historical_request(payload, Some("ios-42"), RequestPriority::Interactive).unwrap();
+        assert_eq!((request.symbol.as_str(), request.timeframe.as_str(), request.after), ("BTC", "24h", Some(1704067200.0)));
+        assert_eq!(request.replies, vec![ReplyTo { request_id: "r1".to_string(), topic: "crypto/responses/ios-42/r1".to_string() }]);
+
+        // Legacy text requests get no reply
+        let legacy = historical_request("ETH:7d", Some("ios-42"), RequestPriority::Background).unwrap();
+        assert!(legacy.replies.is_empty());
+
+        // Downsampling requests are capped to what fits in one packet
+        let payload = r#"{"request_id":"r2","symbol":"BTC","timeframe":"365d","max_points":5000,"reply_to":"crypto/responses/ios-42/r2"}"#;
+        let request = historical_request(payload, Some("ios-42"), RequestPriority::Interactive).unwrap();
+        assert_eq!(request.max_points, Some(MAX_SAMPLED_POINTS));
+
+        // A custom window stands in for the timeframe; it is checked when served
+        let payload = r#"{"request_id":"r3","symbol":"BTC","start":"2024-03-01T00:00:00Z","interval":"1d","reply_to":"crypto/responses/ios-42/r3"}"#;
+        let request = historical_request(payload, Some("ios-42"), RequestPriority::Interactive).unwrap();
+        assert_eq!(
+            request.range,
+            Some(RequestedRange { start: "2024-03-01T00:00:00Z".to_string(), end: None, interval: Some("1d".to_string()) })
+        );
+
+        for invalid in [
+            // Replies to another client or another request
+            r#"{"request_id":"r1","symbol":"BTC","timeframe":"24h","reply_to":"crypto/responses/ios-7/r1"}"#,
+            r#"{"request_id":"r1","symbol":"BTC","timeframe":"24h","reply_to":"crypto/responses/ios-42/r2"}"#,
+            r#"{"request_id":"r1","symbol":"BTC","timeframe":"24h","reply_to":"crypto/prices/latest"}"#,
+            r#"{"request_id":"r1","symbol":"","timeframe":"24h","reply_to":"crypto/responses/ios-42/r1"}"#,
+            r#"{"request_id":"r1","symbol":"BTC","reply_to":"crypto/responses/ios-42/r1"}"#,
+            r#"{"request_id":"r1","symbol":"BTC","timeframe":"24h","max_points":2,"reply_to":"crypto/responses/ios-42/r1"}"#,
+            r#"{"symbol":"BTC","timeframe":"24h"}"#,
+        ] {
+            assert!(historical_request(invalid, Some("ios-42"), RequestPriority::Interactive).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_classify_request_topic() {
+        assert_eq!(
+            classify_request_topic("crypto/requests/historical"),
+            Some((RequestKind::Historical(RequestPriority::Interactive), None))
+        );
+        assert_eq!(
+            classify_request_topic("crypto/clients/ios-42/requests/historical/background"),
+            Some((RequestKind::Historical(RequestPriority::Background), Some("ios-42")))
+        );
+        assert_eq!(
+            classify_request_topic("crypto/clients/ios-42/requests/refresh-prices"),
+            Some((RequestKind::RefreshPrices, Some("ios-42")))
+        );
+        assert_eq!(classify_request_topic("crypto/requests/refresh-prices"), Some((RequestKind::RefreshPrices, None)));
+        assert_eq!(classify_request_topic("crypto/clients/ios-42/requests/logos"), Some((RequestKind::Logos, Some("ios-42"))));
+        assert_eq!(classify_request_topic("crypto/clients/ios-42/errors"), None);
+        assert_eq!(classify_request_topic("crypto/prices/latest"), None);
+    }
+
+    #[test]
+    fn test_registration_devices() {
+        assert_eq!(alert_registration_device("crypto/alerts/register/ios-42"), Some("ios-42"));
+        assert_eq!(alert_registration_device("crypto/alerts/register/"), None);
+        assert_eq!(alert_registration_device("crypto/alerts/register/ios-42/extra"), None);
+        assert_eq!(alert_registration_device("crypto/alerts/ios-42"), None);
+        assert_eq!(watchlist_registration_device("crypto/watchlists/register/ios-42"), Some("ios-42"));
+        assert_eq!(watchlist_registration_device("crypto/watchlists/ios-42/prices"), None);
+    }
+
     #[test]
     fn test_invalid_request_format_parsing() {
         // Test invalid request formats
@@ -205,7 +697,7 @@ mod tests {
         assert_eq!(mqttoptions.client_id(), "test-subscriber");
         assert_eq!(mqttoptions.broker_address(), (broker_host.to_string(), broker_port));
         assert_eq!(mqttoptions.keep_alive(), Duration::from_secs(30));
-        assert_eq!(mqttoptions.clean_session(), true);
+        assert!(mqttoptions.clean_session());
     }
 
     #[test]
@@ -249,9 +741,9 @@ mod tests {
         let qos = QoS::AtLeastOnce;
         // Test that QoS can be used (we can't test much more without actual MQTT connection)
         match qos {
-            QoS::AtMostOnce => assert!(false, "Expected AtLeastOnce"),
-            QoS::AtLeastOnce => assert!(true),
-            QoS::ExactlyOnce => assert!(false, "Expected AtLeastOnce"),
+            QoS::AtMostOnce => panic!("Expected AtLeastOnce"),
+            QoS::AtLeastOnce => {},
+            QoS::ExactlyOnce => panic!("Expected AtLeastOnce"),
         }
     }
 
@@ -280,8 +772,8 @@ mod tests {
         let state = create_test_app_state();
         
         // Test that all fields are accessible
-        assert_eq!(state.api_key, "test_api_key");
-        assert_eq!(state.update_interval_seconds, 300);
+        assert_eq!(state.market_data.name(), "CoinMarketCap");
+        assert_eq!(state.update_interval_seconds(), 300);
         
         // Test that caches are initialized
         let cache = state.cache.lock().unwrap();
@@ -0,0 +1,219 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+// Interactive requests (a user opening a chart) are served before background
+// pre-warm requests, whatever order they arrived in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RequestPriority {
+    Background,
+    Interactive,
+}
+
+// Where the outcome of a request sent as a JSON envelope goes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyTo {
+    pub request_id: String,
+    pub topic: String,
+}
+
+// A custom window as the client sent it; checked when the request is served, so an open end
+// means the time of the fetch
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestedRange {
+    pub start: String,
+    pub end: Option<String>,
+    pub interval: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalRequest {
+    pub symbol: String,
+    pub timeframe: String,
+    // Incremental refresh: only points newer than this timestamp
+    pub after: Option<f64>,
+    // Downsample to this many points and send to the requesters only
+    pub max_points: Option<usize>,
+    // Replaces the timeframe; the series goes to each requester's range topic
+    pub range: Option<RequestedRange>,
+    pub priority: RequestPriority,
+    // Everyone waiting on this series; duplicates add theirs instead of being queued
+    pub replies: Vec<ReplyTo>,
+}
+
+impl HistoricalRequest {
+    fn same_series(&self, other: &HistoricalRequest) -> bool {
+        self.symbol.eq_ignore_ascii_case(&other.symbol)
+            && self.timeframe == other.timeframe
+            && self.after == other.after
+            && self.max_points == other.max_points
+            && self.range == other.range
+    }
+}
+
+// Heap entry: highest priority first, then FIFO by arrival sequence
+struct QueuedRequest {
+    request: HistoricalRequest,
+    seq: u64,
+}
+
+impl PartialEq for QueuedRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedRequest {}
+
+impl PartialOrd for QueuedRequest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedRequest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.request.priority
+            .cmp(&other.request.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    heap: BinaryHeap<QueuedRequest>,
+    next_seq: u64,
+}
+
+// Pending historical requests shared between the MQTT listener and the worker tasks
+#[derive(Default)]
+pub struct RequestQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+impl RequestQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Queue a request. A duplicate of a queued request is dropped, or promoted if it
+    // arrives with a higher priority; either way its replies are kept. Returns false if
+    // the request was a duplicate.
+    pub fn push(&self, mut request: HistoricalRequest) -> bool {
+        {
+            let mut state = self.state.lock().unwrap();
+            // BinaryHeap has no iter_mut; the queue is short enough to rebuild
+            let mut queued = std::mem::take(&mut state.heap).into_vec();
+            if let Some(position) = queued.iter().position(|q| q.request.same_series(&request)) {
+                if queued[position].request.priority >= request.priority {
+                    queued[position].request.replies.append(&mut request.replies);
+                    state.heap = queued.into();
+                    return false;
+                }
+                let existing = queued.swap_remove(position);
+                request.replies.splice(0..0, existing.request.replies);
+            }
+            state.heap = queued.into();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.heap.push(QueuedRequest { request, seq });
+        }
+        self.notify.notify_one();
+        true
+    }
+
+    pub fn try_pop(&self) -> Option<HistoricalRequest> {
+        self.state.lock().unwrap().heap.pop().map(|q| q.request)
+    }
+
+    // Wait for the next request in priority order
+    pub async fn pop(&self) -> HistoricalRequest {
+        loop {
+            if let Some(request) = self.try_pop() {
+                return request;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().heap.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(symbol: &str, priority: RequestPriority) -> HistoricalRequest {
+        HistoricalRequest {
+            symbol: symbol.to_string(),
+            timeframe: "24h".to_string(),
+            after: None,
+            max_points: None,
+            range: None,
+            priority,
+            replies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_interactive_requests_jump_the_queue() {
+        let queue = RequestQueue::new();
+        for symbol in ["AAA", "BBB", "CCC"] {
+            queue.push(request(symbol, RequestPriority::Background));
+        }
+        queue.push(request("BTC", RequestPriority::Interactive));
+
+        assert_eq!(queue.try_pop().unwrap().symbol, "BTC");
+        // Background jobs keep their arrival order
+        assert_eq!(queue.try_pop().unwrap().symbol, "AAA");
+        assert_eq!(queue.try_pop().unwrap().symbol, "BBB");
+        assert_eq!(queue.try_pop().unwrap().symbol, "CCC");
+        assert!(queue.try_pop().is_none());
+    }
+
+    #[test]
+    fn test_duplicates_are_dropped_or_promoted() {
+        let queue = RequestQueue::new();
+        assert!(queue.push(request("AAA", RequestPriority::Background)));
+        assert!(queue.push(request("ETH", RequestPriority::Background)));
+        assert!(!queue.push(request("eth", RequestPriority::Background)));
+        assert_eq!(queue.pending(), 2);
+
+        // The user opened a chart that was waiting in the warmup batch
+        assert!(queue.push(request("ETH", RequestPriority::Interactive)));
+        assert_eq!(queue.pending(), 2);
+        let next = queue.try_pop().unwrap();
+        assert_eq!(next.symbol, "ETH");
+        assert_eq!(next.priority, RequestPriority::Interactive);
+    }
+
+    #[test]
+    fn test_duplicates_keep_their_replies() {
+        let reply = |id: &str| ReplyTo { request_id: id.to_string(), topic: format!("crypto/responses/ios-1/{}", id) };
+        let queue = RequestQueue::new();
+        queue.push(HistoricalRequest { replies: vec![reply("a")], ..request("ETH", RequestPriority::Background) });
+        queue.push(HistoricalRequest { replies: vec![reply("b")], ..request("ETH", RequestPriority::Background) });
+        queue.push(HistoricalRequest { replies: vec![reply("c")], ..request("ETH", RequestPriority::Interactive) });
+
+        let next = queue.try_pop().unwrap();
+        assert_eq!(next.replies, vec![reply("a"), reply("b"), reply("c")]);
+        assert!(queue.try_pop().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pop_waits_for_push() {
+        let queue = std::sync::Arc::new(RequestQueue::new());
+        let waiter = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.pop().await })
+        };
+        tokio::task::yield_now().await;
+        queue.push(request("SOL", RequestPriority::Interactive));
+
+        assert_eq!(waiter.await.unwrap().symbol, "SOL");
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+// Retained topics this server has published (namespace stripped), with when. Every retained
+// publish goes through publisher::publish, which records it here; an empty retained payload
+// clears the topic and removes it. Maintenance tasks use it to clear only what is actually on
+// the broker and has gone stale. A server that becomes leader knows only what it published
+// itself; compaction still removes topics of coins that are no longer listed.
+#[derive(Default)]
+pub struct RetainedTopics {
+    published: Mutex<HashMap<String, Instant>>,
+}
+
+impl RetainedTopics {
+    pub fn record(&self, topic: &str, cleared: bool, now: Instant) {
+        let mut published = self.published.lock().unwrap();
+        if cleared {
+            published.remove(topic);
+        } else {
+            published.insert(topic.to_string(), now);
+        }
+    }
+
+    // Topics published longer ago than their time to live; topics without one never expire
+    pub fn expired(&self, now: Instant, ttl: impl Fn(&str) -> Option<Duration>) -> Vec<String> {
+        self.published
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(topic, published_at)| ttl(topic).is_some_and(|ttl| now.saturating_duration_since(**published_at) >= ttl))
+            .map(|(topic, _)| topic.clone())
+            .collect()
+    }
+}
+
+static RETAINED_TOPICS: LazyLock<RetainedTopics> = LazyLock::new(RetainedTopics::default);
+
+pub fn retained_topics() -> &'static RetainedTopics {
+    &RETAINED_TOPICS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_stale_topics_expire() {
+        let retained = RetainedTopics::default();
+        let start = Instant::now();
+        let ttl = |topic: &str| topic.ends_with("/1h").then_some(Duration::from_secs(300));
+        retained.record("crypto/historical/BTC/1h", false, start);
+        retained.record("crypto/historical/ETH/1h", false, start + Duration::from_secs(200));
+        retained.record("crypto/prices/latest", false, start);
+
+        assert!(retained.expired(start + Duration::from_secs(299), ttl).is_empty());
+        assert_eq!(retained.expired(start + Duration::from_secs(300), ttl), vec!["crypto/historical/BTC/1h"]);
+
+        // Republishing restarts the clock; clearing forgets the topic
+        retained.record("crypto/historical/BTC/1h", false, start + Duration::from_secs(300));
+        retained.record("crypto/historical/ETH/1h", true, start + Duration::from_secs(300));
+        assert!(retained.expired(start + Duration::from_secs(500), ttl).is_empty());
+    }
+}
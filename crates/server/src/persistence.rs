@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::web;
+use tracing::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use crate::types::{AppState, CryptoCurrency, HistoricalDataResult};
+use shared::{CoinCrabError, CoinCrabResult};
+
+// The in-memory caches as stored on disk. Only successful historical series are kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheSnapshot {
+    pub listings: Option<Vec<CryptoCurrency>>,
+    // Unix seconds of the listings fetch
+    pub fetched_at: u64,
+    pub historical: Vec<StoredSeries>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSeries {
+    pub key: String,
+    pub result: HistoricalDataResult,
+    pub cached_at: u64,
+}
+
+// Where snapshots are kept. Both calls block on disk I/O and run on the blocking thread pool.
+pub trait CacheBackend: Send + Sync {
+    fn load(&self) -> CoinCrabResult<Option<CacheSnapshot>>;
+    fn save(&self, snapshot: &CacheSnapshot) -> CoinCrabResult<()>;
+}
+
+// Whole snapshot as one JSON file
+pub struct JsonFileBackend {
+    path: PathBuf,
+}
+
+impl JsonFileBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonFileBackend { path: path.into() }
+    }
+}
+
+impl CacheBackend for JsonFileBackend {
+    fn load(&self) -> CoinCrabResult<Option<CacheSnapshot>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&self.path)?;
+        Ok(Some(serde_json::from_str(&contents)?))
+    }
+
+    // Write to a temp file and rename so a crash mid-write never leaves a truncated cache
+    fn save(&self, snapshot: &CacheSnapshot) -> CoinCrabResult<()> {
+        let json = serde_json::to_string(snapshot)?;
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+// Write-behind persistence: the fetch and publish paths only set a dirty flag, and a
+// background task writes a snapshot off the async runtime at most once per flush interval.
+// Without a backend every call is a no-op.
+pub struct WriteBehindCache {
+    backend: Option<Arc<dyn CacheBackend>>,
+    dirty: AtomicBool,
+}
+
+impl WriteBehindCache {
+    pub fn new(backend: Option<Arc<dyn CacheBackend>>) -> Self {
+        WriteBehindCache {
+            backend,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        Self::new(None)
+    }
+
+    pub fn mark_dirty(&self) {
+        if self.backend.is_some() {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    // Restore the caches from the last snapshot, if any
+    pub async fn restore(&self, state: &web::Data<AppState>) -> CoinCrabResult<()> {
+        let Some(backend) = self.backend.clone() else {
+            return Ok(());
+        };
+        let snapshot = tokio::task::spawn_blocking(move || backend.load())
+            .await
+            .map_err(|e| CoinCrabError::Io(format!("Cache load task failed: {}", e)))??;
+        let Some(snapshot) = snapshot else {
+            info!("No persisted cache found - starting empty");
+            return Ok(());
+        };
+        let coins = snapshot.listings.as_ref().map_or(0, Vec::len);
+        let series = snapshot.historical.len();
+        apply_snapshot(state, snapshot);
+        info!("Restored {} cached coins and {} historical series from disk", coins, series);
+        Ok(())
+    }
+
+    // Write a snapshot if anything changed since the last one. A failed write leaves the
+    // cache dirty so the next flush retries it.
+    pub async fn flush(&self, state: &web::Data<AppState>) -> CoinCrabResult<bool> {
+        let Some(backend) = self.backend.clone() else {
+            return Ok(false);
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(false);
+        }
+        let snapshot = take_snapshot(state);
+        let result = tokio::task::spawn_blocking(move || backend.save(&snapshot))
+            .await
+            .map_err(|e| CoinCrabError::Io(format!("Cache flush task failed: {}", e)))
+            .and_then(|saved| saved);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Release);
+        }
+        result.map(|_| true)
+    }
+}
+
+pub async fn flush_cache_periodically(state: web::Data<AppState>, interval_seconds: u64) {
+    if state.persistence.backend.is_none() {
+        return;
+    }
+    info!("Flushing cache changes to disk every {} seconds", interval_seconds);
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+    loop {
+        interval.tick().await;
+        match state.persistence.flush(&state).await {
+            Ok(true) => debug!("Flushed cache snapshot to disk"),
+            Ok(false) => {}
+            Err(e) => error!("Failed to persist cache: {}", e),
+        }
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Copy the caches under their locks; serialization happens later on the blocking pool
+fn take_snapshot(state: &AppState) -> CacheSnapshot {
+    let listings = state.cache.lock().unwrap().clone();
+    let fetched_at = unix_seconds(*state.last_fetch.lock().unwrap());
+    let historical = state
+        .historical_cache
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, (result, _))| result.success)
+        .map(|(key, (result, cached_at))| StoredSeries {
+            key: key.clone(),
+            result: result.clone(),
+            cached_at: unix_seconds(*cached_at),
+        })
+        .collect();
+    CacheSnapshot { listings, fetched_at, historical }
+}
+
+fn apply_snapshot(state: &AppState, snapshot: CacheSnapshot) {
+    if snapshot.listings.is_some() {
+        *state.cache.lock().unwrap() = snapshot.listings;
+        *state.last_fetch.lock().unwrap() = UNIX_EPOCH + Duration::from_secs(snapshot.fetched_at);
+    }
+    let restored: HashMap<String, (HistoricalDataResult, SystemTime)> = snapshot
+        .historical
+        .into_iter()
+        .map(|series| {
+            // Snapshots from before payloads were versioned read as version 0; the series is the same
+            let result = HistoricalDataResult { schema_version: shared::PAYLOAD_SCHEMA_VERSION, ..series.result };
+            (series.key, (result, UNIX_EPOCH + Duration::from_secs(series.cached_at)))
+        })
+        .collect();
+    state.historical_cache.lock().unwrap().extend(restored);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_file_backend_round_trip() {
+        let path = std::env::temp_dir().join(format!("coin-crab-cache-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let backend = JsonFileBackend::new(&path);
+        assert!(backend.load().unwrap().is_none());
+
+        let snapshot = CacheSnapshot {
+            listings: Some(Vec::new()),
+            fetched_at: 1_704_067_200,
+            historical: vec![StoredSeries {
+                key: "BTC:7d".to_string(),
+                result: HistoricalDataResult {
+                    success: true,
+                    data: Vec::new(),
+                    error: None,
+                    symbol: Some("BTC".to_string()),
+                    timeframe: Some("7d".to_string()),
+                    gaps: Vec::new(),
+                    buckets: Vec::new(),
+                    schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+                },
+                cached_at: 1_704_067_100,
+            }],
+        };
+        backend.save(&snapshot).unwrap();
+        let loaded = backend.load().unwrap().unwrap();
+        assert_eq!(loaded.fetched_at, 1_704_067_200);
+        assert_eq!(loaded.historical[0].key, "BTC:7d");
+        assert!(!path.with_extension("tmp").exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_dirty_flag_needs_a_backend() {
+        let disabled = WriteBehindCache::disabled();
+        disabled.mark_dirty();
+        assert!(!disabled.is_dirty());
+
+        let enabled = WriteBehindCache::new(Some(Arc::new(JsonFileBackend::new("/nonexistent/cache.json"))));
+        assert!(!enabled.is_dirty());
+        enabled.mark_dirty();
+        assert!(enabled.is_dirty());
+    }
+}
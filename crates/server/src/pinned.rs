@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use crate::config::PinnedConfig;
+use crate::identity::IdentityMap;
+use crate::types::CryptoCurrency;
+use shared::{CoinCrabError, CoinCrabResult, Quote, UsdQuote};
+
+// Most coins pinned at runtime; each one outside the top 100 costs part of a quotes call
+const MAX_RUNTIME_PINS: usize = 50;
+
+// Symbols that never drop out of the feed: quoted separately when the listings miss them,
+// published per-symbol with every update and their history kept warm. PINNED_SYMBOLS are
+// always pinned; pins added through /api/pinned are kept in PINNED_SYMBOLS_PATH when set.
+pub struct PinnedSymbols {
+    configured: Vec<String>,
+    added: Mutex<Vec<String>>,
+    path: Option<PathBuf>,
+}
+
+impl PinnedSymbols {
+    pub fn new(config: &PinnedConfig) -> Self {
+        PinnedSymbols {
+            configured: config.symbols.clone(),
+            added: Mutex::new(Vec::new()),
+            path: config.path.as_ref().map(PathBuf::from),
+        }
+    }
+
+    // Configured pins first, then runtime pins in the order they were added
+    pub fn symbols(&self) -> Vec<String> {
+        let mut symbols = self.configured.clone();
+        for symbol in self.added.lock().unwrap().iter() {
+            if !symbols.contains(symbol) {
+                symbols.push(symbol.clone());
+            }
+        }
+        symbols
+    }
+
+    pub fn is_pinned(&self, symbol: &str) -> bool {
+        let symbol = symbol.to_uppercase();
+        self.configured.contains(&symbol) || self.added.lock().unwrap().contains(&symbol)
+    }
+
+    // Returns false if the symbol was already pinned
+    pub fn pin(&self, symbol: &str) -> CoinCrabResult<bool> {
+        let symbol = symbol.trim().to_uppercase();
+        if symbol.is_empty() {
+            return Err(CoinCrabError::Parse("symbol is required".to_string()));
+        }
+        if self.is_pinned(&symbol) {
+            return Ok(false);
+        }
+        let mut added = self.added.lock().unwrap();
+        if added.len() >= MAX_RUNTIME_PINS {
+            return Err(CoinCrabError::Config(format!("At most {} coins can be pinned at runtime", MAX_RUNTIME_PINS)));
+        }
+        added.push(symbol);
+        Ok(true)
+    }
+
+    // Returns false if the symbol wasn't pinned. Configured pins can only be removed from
+    // PINNED_SYMBOLS.
+    pub fn unpin(&self, symbol: &str) -> CoinCrabResult<bool> {
+        let symbol = symbol.trim().to_uppercase();
+        if self.configured.contains(&symbol) {
+            return Err(CoinCrabError::Config(format!("{} is pinned by PINNED_SYMBOLS", symbol)));
+        }
+        let mut added = self.added.lock().unwrap();
+        let before = added.len();
+        added.retain(|pinned| *pinned != symbol);
+        Ok(added.len() < before)
+    }
+
+    // Pinned symbols the listings don't include
+    pub fn missing_from(&self, listings: &[CryptoCurrency]) -> Vec<String> {
+        let listed: HashSet<String> = listings.iter().map(|coin| coin.symbol.to_uppercase()).collect();
+        self.symbols().into_iter().filter(|symbol| !listed.contains(symbol)).collect()
+    }
+
+    pub fn load(&self) -> CoinCrabResult<usize> {
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Ok(0);
+        };
+        let added: Vec<String> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let count = added.len();
+        *self.added.lock().unwrap() = added;
+        Ok(count)
+    }
+
+    // Write to a temp file and rename so a crash mid-write never loses the pins
+    pub fn save(&self) -> CoinCrabResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&*self.added.lock().unwrap())?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // save() off the async runtime
+    pub async fn persist(self: &Arc<Self>) -> CoinCrabResult<()> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        let pinned = Arc::clone(self);
+        tokio::task::spawn_blocking(move || pinned.save())
+            .await
+            .map_err(|e| CoinCrabError::Io(format!("Pinned symbols save task failed: {}", e)))?
+    }
+}
+
+// Providers quote coins from an earlier listing by id, so a pinned coin outside the listings is
+// asked for with a blank quote under its CMC id. Symbols the mapping doesn't know are skipped.
+pub fn quote_requests(symbols: &[String], identities: &IdentityMap) -> Vec<CryptoCurrency> {
+    symbols
+        .iter()
+        .filter_map(|symbol| {
+            let identity = identities.by_symbol(symbol)?;
+            Some(CryptoCurrency {
+                id: i32::try_from(identity.cmc_id?).ok()?,
+                name: identity.name.clone(),
+                symbol: identity.symbol.clone(),
+                quote: Quote {
+                    usd: UsdQuote {
+                        price: 0.0,
+                        percent_change_1h: 0.0,
+                        percent_change_24h: 0.0,
+                        percent_change_7d: 0.0,
+                        market_cap: 0.0,
+                        volume_24h: 0.0,
+                        last_updated: String::new(),
+                    },
+                    converted: Default::default(),
+                },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_and_persistence() {
+        let path = std::env::temp_dir().join(format!("coin-crab-pinned-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = PinnedConfig { symbols: vec!["PEPE".to_string()], path: Some(path.display().to_string()) };
+        let pinned = PinnedSymbols::new(&config);
+        assert!(pinned.pin(" bonk ").unwrap());
+        assert!(!pinned.pin("PEPE").unwrap());
+        assert!(pinned.unpin("pepe").is_err());
+        assert_eq!(pinned.symbols(), vec!["PEPE", "BONK"]);
+
+        pinned.save().unwrap();
+        let restored = PinnedSymbols::new(&config);
+        assert_eq!(restored.load().unwrap(), 1);
+        assert!(restored.is_pinned("bonk"));
+        assert!(restored.unpin("BONK").unwrap());
+        assert!(!restored.unpin("BONK").unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_quote_requests_for_missing_pins() {
+        let mut identities = IdentityMap::new();
+        identities.record_cmc(24478, "PEPE", "Pepe", "pepe");
+        let pinned = PinnedSymbols::new(&PinnedConfig { symbols: vec!["PEPE".to_string(), "NOPE".to_string()], path: None });
+
+        let missing = pinned.missing_from(&[]);
+        assert_eq!(missing, vec!["PEPE", "NOPE"]);
+        let requests = quote_requests(&missing, &identities);
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].id, requests[0].symbol.as_str()), (24478, "PEPE"));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::config::PortfolioConfig;
+use crate::types::CryptoCurrency;
+use shared::{CoinCrabError, CoinCrabResult};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Holding {
+    pub symbol: String,
+    pub amount: f64,
+    // Total paid for the amount, in USD
+    pub cost_basis: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HoldingValuation {
+    pub symbol: String,
+    pub amount: f64,
+    pub price: f64,
+    pub value: f64,
+    pub cost_basis: f64,
+    pub profit_loss: f64,
+    // None when the cost basis is zero
+    pub profit_loss_percent: Option<f64>,
+    // Share of the portfolio's total value
+    pub allocation_percent: f64,
+}
+
+// Published retained on crypto/portfolios/{portfolio_id}/valuation and served by
+// GET /api/portfolios/{portfolio_id}
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortfolioValuation {
+    pub portfolio_id: String,
+    pub total_value: f64,
+    pub total_cost: f64,
+    pub profit_loss: f64,
+    pub profit_loss_percent: Option<f64>,
+    pub holdings: Vec<HoldingValuation>,
+    // Held symbols missing from the listings; left out of the totals
+    pub unpriced: Vec<String>,
+    // Unix seconds
+    pub valued_at: i64,
+}
+
+fn percent(part: f64, whole: f64) -> Option<f64> {
+    (whole != 0.0).then(|| part / whole * 100.0)
+}
+
+pub fn value_portfolio(portfolio_id: &str, holdings: &[Holding], listings: &[CryptoCurrency], now: i64) -> PortfolioValuation {
+    let prices: HashMap<String, f64> = listings.iter().map(|coin| (coin.symbol.to_uppercase(), coin.quote.usd.price)).collect();
+    let mut unpriced = Vec::new();
+    let mut valued: Vec<HoldingValuation> = Vec::new();
+    for holding in holdings {
+        let Some(&price) = prices.get(&holding.symbol) else {
+            unpriced.push(holding.symbol.clone());
+            continue;
+        };
+        let value = holding.amount * price;
+        valued.push(HoldingValuation {
+            symbol: holding.symbol.clone(),
+            amount: holding.amount,
+            price,
+            value,
+            cost_basis: holding.cost_basis,
+            profit_loss: value - holding.cost_basis,
+            profit_loss_percent: percent(value - holding.cost_basis, holding.cost_basis),
+            allocation_percent: 0.0,
+        });
+    }
+    let total_value: f64 = valued.iter().map(|holding| holding.value).sum();
+    let total_cost: f64 = valued.iter().map(|holding| holding.cost_basis).sum();
+    for holding in &mut valued {
+        holding.allocation_percent = percent(holding.value, total_value).unwrap_or(0.0);
+    }
+    valued.sort_by(|a, b| b.value.total_cmp(&a.value));
+
+    PortfolioValuation {
+        portfolio_id: portfolio_id.to_string(),
+        total_value,
+        total_cost,
+        profit_loss: total_value - total_cost,
+        profit_loss_percent: percent(total_value - total_cost, total_cost),
+        holdings: valued,
+        unpriced,
+        valued_at: now,
+    }
+}
+
+// Holdings by portfolio id, kept in PORTFOLIO_PATH when set so they survive restarts
+pub struct PortfolioStore {
+    portfolios: Mutex<HashMap<String, Vec<Holding>>>,
+    path: Option<PathBuf>,
+    max_holdings: usize,
+}
+
+impl PortfolioStore {
+    pub fn new(config: &PortfolioConfig) -> Self {
+        PortfolioStore {
+            portfolios: Mutex::new(HashMap::new()),
+            path: config.path.as_ref().map(PathBuf::from),
+            max_holdings: config.max_holdings,
+        }
+    }
+
+    pub fn holdings(&self, portfolio_id: &str) -> Option<Vec<Holding>> {
+        self.portfolios.lock().unwrap().get(portfolio_id).cloned()
+    }
+
+    // Adds the holding, replacing one of the same symbol. Returns the portfolio's holdings.
+    pub fn upsert(&self, portfolio_id: &str, holding: Holding) -> CoinCrabResult<Vec<Holding>> {
+        if !holding.amount.is_finite() || holding.amount < 0.0 || !holding.cost_basis.is_finite() || holding.cost_basis < 0.0 {
+            return Err(CoinCrabError::Parse("amount and cost_basis must be non-negative numbers".to_string()));
+        }
+        let symbol = holding.symbol.trim().to_uppercase();
+        if symbol.is_empty() {
+            return Err(CoinCrabError::Parse("symbol is required".to_string()));
+        }
+        let mut portfolios = self.portfolios.lock().unwrap();
+        let holdings = portfolios.entry(portfolio_id.to_string()).or_default();
+        match holdings.iter().position(|existing| existing.symbol == symbol) {
+            Some(index) => holdings[index] = Holding { symbol, ..holding },
+            None if holdings.len() >= self.max_holdings => {
+                return Err(CoinCrabError::Config(format!("Portfolios hold at most {} coins", self.max_holdings)));
+            }
+            None => holdings.push(Holding { symbol, ..holding }),
+        }
+        Ok(holdings.clone())
+    }
+
+    // Returns whether the portfolio held the symbol; an emptied portfolio is removed
+    pub fn remove(&self, portfolio_id: &str, symbol: &str) -> bool {
+        let mut portfolios = self.portfolios.lock().unwrap();
+        let Some(holdings) = portfolios.get_mut(portfolio_id) else {
+            return false;
+        };
+        let before = holdings.len();
+        holdings.retain(|holding| !holding.symbol.eq_ignore_ascii_case(symbol));
+        let removed = holdings.len() < before;
+        if holdings.is_empty() {
+            portfolios.remove(portfolio_id);
+        }
+        removed
+    }
+
+    // Portfolios holding any of the changed coins, so unaffected valuations aren't republished
+    pub fn affected_by(&self, changed: &[CryptoCurrency]) -> Vec<(String, Vec<Holding>)> {
+        let changed: HashSet<String> = changed.iter().map(|coin| coin.symbol.to_uppercase()).collect();
+        self.portfolios
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, holdings)| holdings.iter().any(|holding| changed.contains(&holding.symbol)))
+            .map(|(id, holdings)| (id.clone(), holdings.clone()))
+            .collect()
+    }
+
+    pub fn load(&self) -> CoinCrabResult<usize> {
+        let Some(path) = self.path.as_ref().filter(|path| path.exists()) else {
+            return Ok(0);
+        };
+        let portfolios: HashMap<String, Vec<Holding>> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let count = portfolios.len();
+        *self.portfolios.lock().unwrap() = portfolios;
+        Ok(count)
+    }
+
+    // Write to a temp file and rename so a crash mid-write never loses every portfolio
+    pub fn save(&self) -> CoinCrabResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&*self.portfolios.lock().unwrap())?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // save() off the async runtime
+    pub async fn persist(self: &Arc<Self>) -> CoinCrabResult<()> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        let store = Arc::clone(self);
+        tokio::task::spawn_blocking(move || store.save())
+            .await
+            .map_err(|e| CoinCrabError::Io(format!("Portfolio save task failed: {}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(symbol: &str, price: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    fn holding(symbol: &str, amount: f64, cost_basis: f64) -> Holding {
+        Holding { symbol: symbol.to_string(), amount, cost_basis }
+    }
+
+    #[test]
+    fn test_value_portfolio() {
+        let holdings = vec![holding("ETH", 2.0, 1000.0), holding("BTC", 0.5, 10_000.0), holding("GONE", 1.0, 50.0)];
+        let valuation = value_portfolio("me", &holdings, &[coin("BTC", 30_000.0), coin("ETH", 2_500.0)], 100);
+
+        assert_eq!((valuation.total_value, valuation.total_cost, valuation.profit_loss), (20_000.0, 11_000.0, 9_000.0));
+        assert_eq!(valuation.unpriced, vec!["GONE"]);
+        let btc = &valuation.holdings[0];
+        assert_eq!((btc.symbol.as_str(), btc.value, btc.allocation_percent), ("BTC", 15_000.0, 75.0));
+        assert_eq!(btc.profit_loss_percent, Some(50.0));
+        assert_eq!(valuation.holdings[1].profit_loss_percent, Some(400.0));
+
+        let free = value_portfolio("me", &[holding("BTC", 1.0, 0.0)], &[coin("BTC", 1.0)], 100);
+        assert_eq!((free.profit_loss_percent, free.holdings[0].profit_loss_percent), (None, None));
+    }
+
+    #[test]
+    fn test_store_upsert_remove_and_persist() {
+        let path = std::env::temp_dir().join(format!("coin-crab-portfolio-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = PortfolioConfig { path: Some(path.display().to_string()), max_holdings: 2 };
+        let store = PortfolioStore::new(&config);
+        store.upsert("me", holding("btc", 1.0, 100.0)).unwrap();
+        store.upsert("me", holding("BTC", 2.0, 300.0)).unwrap();
+        store.upsert("me", holding("ETH", 1.0, 10.0)).unwrap();
+        assert!(store.upsert("me", holding("SOL", 1.0, 1.0)).is_err());
+        assert!(store.upsert("me", holding("SOL", -1.0, 1.0)).is_err());
+        assert_eq!(store.holdings("me").unwrap()[0], holding("BTC", 2.0, 300.0));
+        assert_eq!(store.affected_by(&[coin("ETH", 1.0)]).len(), 1);
+        assert!(store.affected_by(&[coin("SOL", 1.0)]).is_empty());
+
+        assert!(store.remove("me", "eth"));
+        assert!(!store.remove("me", "eth"));
+        store.save().unwrap();
+        let restored = PortfolioStore::new(&config);
+        assert_eq!(restored.load().unwrap(), 1);
+        assert_eq!(restored.holdings("me"), Some(vec![holding("BTC", 2.0, 300.0)]));
+
+        assert!(store.remove("me", "BTC"));
+        assert!(store.holdings("me").is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+}
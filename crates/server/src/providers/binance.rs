@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use actix_web::web;
+use futures_util::StreamExt;
+use tracing::{debug, info, warn};
+use tokio::time;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use crate::config::BinanceStreamConfig;
+use crate::data::publish_listings;
+use crate::types::{AppState, BinanceStreamMessage, CryptoCurrency};
+use shared::{CoinCrabError, CoinCrabResult};
+
+const STREAM_BASE: &str = "wss://stream.binance.com:9443/stream?streams=";
+// Coins are priced from their USDT pair; USDT itself has none
+const QUOTE_ASSET: &str = "USDT";
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// Reconnect periodically so the streamed set follows changes in the top-ranked coins
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(3600);
+// Top coins trade every second; this long without a message means the connection is dead
+const STALE_STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+const NO_LISTINGS_RETRY: Duration = Duration::from_secs(5);
+
+// Keep a Binance trade stream open for the configured coins and publish the latest trade prices
+// through the regular price pipeline, so prices move between listings fetches
+pub async fn run_binance_stream(state: web::Data<AppState>, config: BinanceStreamConfig) {
+    if !config.enabled {
+        return;
+    }
+    info!("Starting Binance price stream ({}, published every {}ms)",
+          if config.symbols.is_empty() { format!("top {} coins", config.top_n) } else { config.symbols.join(",") },
+          config.publish_interval_ms);
+
+    let mut reconnect_delay = INITIAL_RECONNECT_DELAY;
+    loop {
+        let symbols = stream_symbols(&state, &config);
+        if symbols.is_empty() {
+            debug!("No listings cached yet - waiting before opening the Binance stream");
+            time::sleep(NO_LISTINGS_RETRY).await;
+            continue;
+        }
+
+        let started = Instant::now();
+        match stream_session(&state, &config, &symbols).await {
+            Ok(()) => info!("Resubscribing to the Binance stream"),
+            Err(e) => {
+                if started.elapsed() > MAX_RECONNECT_DELAY {
+                    reconnect_delay = INITIAL_RECONNECT_DELAY;
+                }
+                warn!("Binance stream disconnected: {} - reconnecting in {:?}", e, reconnect_delay);
+                time::sleep(reconnect_delay).await;
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+// One connection, until it fails (Err) or is due for resubscription (Ok)
+async fn stream_session(state: &web::Data<AppState>, config: &BinanceStreamConfig, symbols: &[String]) -> CoinCrabResult<()> {
+    let (mut ws, _) = connect_async(stream_url(symbols))
+        .await
+        .map_err(|e| CoinCrabError::Http(format!("Failed to connect to Binance stream: {}", e)))?;
+    info!("Connected to Binance stream for {} symbols", symbols.len());
+
+    let mut pending: HashMap<String, f64> = HashMap::new();
+    let mut last_message = Instant::now();
+    let mut flush = time::interval(Duration::from_millis(config.publish_interval_ms));
+    flush.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    let resubscribe = time::sleep(RESUBSCRIBE_INTERVAL);
+    tokio::pin!(resubscribe);
+
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                last_message = Instant::now();
+                match message {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Some((symbol, price)) = parse_trade(&text) {
+                            pending.insert(symbol, price);
+                        }
+                    }
+                    // Pings are answered by tungstenite while reading
+                    Some(Ok(Message::Close(frame))) => {
+                        return Err(CoinCrabError::Http(format!("Binance closed the stream: {:?}", frame)));
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(CoinCrabError::Http(format!("Binance stream error: {}", e))),
+                    None => return Err(CoinCrabError::Http("Binance stream ended".to_string())),
+                }
+            }
+            _ = flush.tick() => {
+                if last_message.elapsed() > STALE_STREAM_TIMEOUT {
+                    return Err(CoinCrabError::Timeout(format!("No Binance messages for {:?}", STALE_STREAM_TIMEOUT)));
+                }
+                if !pending.is_empty() {
+                    flush_prices(state, std::mem::take(&mut pending)).await;
+                }
+            }
+            _ = &mut resubscribe => {
+                let _ = ws.close(None).await;
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Apply coalesced trade prices to the cached listings and publish them (leader only)
+async fn flush_prices(state: &web::Data<AppState>, prices: HashMap<String, f64>) {
+    let updated_at = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    let listings = {
+        let mut cache = state.cache.lock().unwrap();
+        let Some(listings) = cache.as_mut() else {
+            return;
+        };
+        if apply_stream_prices(listings, &prices, &updated_at) == 0 {
+            return;
+        }
+        listings.clone()
+    };
+    state.persistence.mark_dirty();
+    if state.leader.is_leader() {
+        publish_listings(state, listings).await;
+    }
+}
+
+fn stream_symbols(state: &web::Data<AppState>, config: &BinanceStreamConfig) -> Vec<String> {
+    let symbols: Vec<String> = if config.symbols.is_empty() {
+        match state.cache.lock().unwrap().as_deref() {
+            Some(listings) => listings.iter().take(config.top_n).map(|coin| coin.symbol.to_uppercase()).collect(),
+            None => Vec::new(),
+        }
+    } else {
+        config.symbols.clone()
+    };
+    symbols.into_iter().filter(|symbol| symbol != QUOTE_ASSET).collect()
+}
+
+// Combined stream of aggregated trades, e.g. .../stream?streams=btcusdt@aggTrade/ethusdt@aggTrade
+fn stream_url(symbols: &[String]) -> String {
+    let streams: Vec<String> = symbols
+        .iter()
+        .map(|symbol| format!("{}{}@aggTrade", symbol.to_lowercase(), QUOTE_ASSET.to_lowercase()))
+        .collect();
+    format!("{}{}", STREAM_BASE, streams.join("/"))
+}
+
+// (symbol, price) of an aggTrade message on a USDT pair
+fn parse_trade(text: &str) -> Option<(String, f64)> {
+    let message: BinanceStreamMessage = serde_json::from_str(text).ok()?;
+    let symbol = message.data.pair.strip_suffix(QUOTE_ASSET)?;
+    let price: f64 = message.data.price.parse().ok()?;
+    (price > 0.0).then(|| (symbol.to_string(), price))
+}
+
+// Reprice cached coins from trade prices. Market cap scales with the price, and the percent
+// changes are rebased on the new price (their reference prices haven't moved). Returns the
+// number of coins updated.
+fn apply_stream_prices(listings: &mut [CryptoCurrency], prices: &HashMap<String, f64>, updated_at: &str) -> usize {
+    let mut updated = 0;
+    for coin in listings.iter_mut() {
+        let Some(&price) = prices.get(&coin.symbol.to_uppercase()) else {
+            continue;
+        };
+        let usd = &mut coin.quote.usd;
+        if usd.price <= 0.0 || usd.price == price {
+            continue;
+        }
+        let ratio = price / usd.price;
+        let rebase = |percent: f64| ((1.0 + percent / 100.0) * ratio - 1.0) * 100.0;
+        usd.percent_change_1h = rebase(usd.percent_change_1h);
+        usd.percent_change_24h = rebase(usd.percent_change_24h);
+        usd.percent_change_7d = rebase(usd.percent_change_7d);
+        usd.market_cap *= ratio;
+        usd.price = price;
+        usd.last_updated = updated_at.to_string();
+        updated += 1;
+    }
+    updated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(symbol: &str, price: f64, percent_change_24h: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price,
+                    percent_change_1h: 0.0,
+                    percent_change_24h,
+                    percent_change_7d: 0.0,
+                    market_cap: price * 1000.0,
+                    volume_24h: 5.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_trade_and_stream_url() {
+        let text = r#"{"stream":"btcusdt@aggTrade","data":{"e":"aggTrade","E":1714564800123,"s":"BTCUSDT","a":1,"p":"64012.50000000","q":"0.1","T":1714564800120,"m":true}}"#;
+        assert_eq!(parse_trade(text), Some(("BTC".to_string(), 64012.5)));
+        assert_eq!(parse_trade(r#"{"stream":"btcbusd@aggTrade","data":{"s":"BTCBUSD","p":"1.0"}}"#), None);
+        assert_eq!(parse_trade(r#"{"result":null,"id":1}"#), None);
+
+        let url = stream_url(&["BTC".to_string(), "ETH".to_string()]);
+        assert_eq!(url, "wss://stream.binance.com:9443/stream?streams=btcusdt@aggTrade/ethusdt@aggTrade");
+    }
+
+    #[test]
+    fn test_apply_stream_prices_rebases_changes() {
+        // Up 25% on the day at 100, so the price 24h ago was 80
+        let mut listings = vec![coin("BTC", 100.0, 25.0), coin("ETH", 10.0, 0.0)];
+        let prices = HashMap::from([("BTC".to_string(), 120.0), ("DOGE".to_string(), 0.1)]);
+
+        assert_eq!(apply_stream_prices(&mut listings, &prices, "2024-01-01T00:00:01.000Z"), 1);
+        let btc = &listings[0].quote.usd;
+        assert_eq!(btc.price, 120.0);
+        assert!((btc.percent_change_24h - 50.0).abs() < 1e-9);
+        assert!((btc.market_cap - 120_000.0).abs() < 1e-6);
+        assert_eq!(btc.volume_24h, 5.0);
+        assert_eq!(btc.last_updated, "2024-01-01T00:00:01.000Z");
+        assert_eq!(listings[1].quote.usd.price, 10.0);
+
+        // An unchanged price doesn't count as an update
+        assert_eq!(apply_stream_prices(&mut listings, &prices, "2024-01-01T00:00:02.000Z"), 0);
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use crate::config::CircuitBreakerConfig;
+use shared::{CoinCrabError, CoinCrabResult};
+
+// A probe that never reports back (its caller was dropped) stops blocking other requests after this
+const PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    // Requests go through; failures counts consecutive 429/5xx/transport failures
+    Closed { failures: u32 },
+    // Requests fail fast until `until`; backoff is how long this opening lasts
+    Open { until: Instant, backoff: Duration },
+    // One probe request is out; its result closes the breaker or reopens it for longer
+    HalfOpen { probe_started: Instant, backoff: Duration },
+}
+
+// Stops calling a provider that keeps answering 429 or 5xx. After `failure_threshold`
+// consecutive failures it opens and requests fail with CoinCrabError::RateLimited, which
+// fails over to the secondary provider or leaves the cached data in place. Once the open
+// period passes a single probe is let through: success closes the breaker, failure reopens
+// it for twice as long, up to max_open_seconds. A longer Retry-After from the provider wins.
+pub struct CircuitBreaker {
+    provider: &'static str,
+    failure_threshold: u32,
+    open_duration: Duration,
+    max_open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(provider: &'static str, config: &CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            provider,
+            failure_threshold: config.failure_threshold.max(1),
+            open_duration: Duration::from_secs(config.open_seconds.max(1)),
+            max_open_duration: Duration::from_secs(config.max_open_seconds.max(config.open_seconds.max(1))),
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    // Called before each request
+    pub fn allow(&self) -> CoinCrabResult<()> {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> CoinCrabResult<()> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until, backoff } if now >= until => {
+                info!("{} circuit half-open, sending a probe request", self.provider);
+                *state = BreakerState::HalfOpen { probe_started: now, backoff };
+                Ok(())
+            }
+            BreakerState::HalfOpen { probe_started, backoff } if now.duration_since(probe_started) >= PROBE_TIMEOUT => {
+                *state = BreakerState::HalfOpen { probe_started: now, backoff };
+                Ok(())
+            }
+            BreakerState::Open { until, .. } => Err(CoinCrabError::RateLimited(format!(
+                "{} circuit open for another {}s",
+                self.provider,
+                until.saturating_duration_since(now).as_secs()
+            ))),
+            BreakerState::HalfOpen { .. } => Err(CoinCrabError::RateLimited(format!("{} circuit half-open, waiting for the probe", self.provider))),
+        }
+    }
+
+    // How much longer requests fail fast; None unless the breaker is open
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after_at(Instant::now())
+    }
+
+    fn retry_after_at(&self, now: Instant) -> Option<Duration> {
+        match *self.state.lock().unwrap() {
+            BreakerState::Open { until, .. } if until > now => Some(until - now),
+            _ => None,
+        }
+    }
+
+    // The provider answered with something other than 429 or 5xx
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(*state, BreakerState::HalfOpen { .. } | BreakerState::Open { .. }) {
+            info!("{} circuit closed", self.provider);
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    // A 429, 5xx or transport failure, with the provider's Retry-After when it sent one
+    pub fn record_failure(&self, retry_after: Option<Duration>) {
+        self.record_failure_at(Instant::now(), retry_after);
+    }
+
+    fn record_failure_at(&self, now: Instant, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        let backoff = match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.failure_threshold => {
+                *state = BreakerState::Closed { failures: failures + 1 };
+                return;
+            }
+            BreakerState::Closed { .. } => self.open_duration,
+            // A request sent before the breaker opened; the current opening stands
+            BreakerState::Open { .. } => return,
+            BreakerState::HalfOpen { backoff, .. } => (backoff * 2).min(self.max_open_duration),
+        };
+        let open_for = retry_after.map_or(backoff, |retry_after| retry_after.max(backoff));
+        warn!("{} circuit open for {}s after repeated failures", self.provider, open_for.as_secs());
+        *state = BreakerState::Open { until: now + open_for, backoff };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new("CMC", &CircuitBreakerConfig { failure_threshold: 3, open_seconds: 60, max_open_seconds: 200 })
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = breaker();
+        let start = Instant::now();
+        breaker.record_failure_at(start, None);
+        breaker.record_failure_at(start, None);
+        breaker.record_success();
+        breaker.record_failure_at(start, None);
+        breaker.record_failure_at(start, None);
+        assert!(breaker.allow_at(start).is_ok());
+
+        breaker.record_failure_at(start, None);
+        assert!(matches!(breaker.allow_at(start + Duration::from_secs(59)), Err(CoinCrabError::RateLimited(_))));
+    }
+
+    #[test]
+    fn test_half_open_probe_and_backoff() {
+        let breaker = breaker();
+        let start = Instant::now();
+        for _ in 0..3 {
+            breaker.record_failure_at(start, None);
+        }
+
+        // One probe once the open period is over; everything else waits for it
+        let probe_at = start + Duration::from_secs(60);
+        assert!(breaker.allow_at(probe_at).is_ok());
+        assert!(breaker.allow_at(probe_at).is_err());
+
+        // A failed probe doubles the open period, capped at max_open_seconds
+        breaker.record_failure_at(probe_at, None);
+        assert!(breaker.allow_at(probe_at + Duration::from_secs(119)).is_err());
+        let probe_at = probe_at + Duration::from_secs(120);
+        assert!(breaker.allow_at(probe_at).is_ok());
+        breaker.record_failure_at(probe_at, None);
+        assert!(breaker.allow_at(probe_at + Duration::from_secs(199)).is_err());
+        let probe_at = probe_at + Duration::from_secs(200);
+        assert!(breaker.allow_at(probe_at).is_ok());
+
+        // A successful probe closes it
+        breaker.record_success();
+        assert!(breaker.allow_at(probe_at).is_ok());
+        assert!(breaker.allow_at(probe_at).is_ok());
+    }
+
+    #[test]
+    fn test_retry_after_and_stuck_probe() {
+        let breaker = breaker();
+        let start = Instant::now();
+        for _ in 0..3 {
+            breaker.record_failure_at(start, Some(Duration::from_secs(600)));
+        }
+        assert!(breaker.allow_at(start + Duration::from_secs(599)).is_err());
+        assert_eq!(breaker.retry_after_at(start + Duration::from_secs(590)), Some(Duration::from_secs(10)));
+
+        let probe_at = start + Duration::from_secs(600);
+        assert!(breaker.allow_at(probe_at).is_ok());
+        assert_eq!(breaker.retry_after_at(probe_at), None);
+        assert!(breaker.allow_at(probe_at + PROBE_TIMEOUT).is_ok());
+    }
+}
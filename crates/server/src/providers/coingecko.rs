@@ -0,0 +1,283 @@
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use tracing::{debug, info};
+use reqwest::{Client, RequestBuilder};
+use crate::http_client::RetryPolicy;
+use crate::identity::{CoinIdentity, IdentityMap};
+use crate::logos::LogoFormat;
+use crate::types::{CoinGeckoListEntry, CoinGeckoMarket, CoinGeckoMarketChart, CryptoCurrency};
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint, Quote, UsdQuote};
+use super::{get_json, timeframe_days, HistoricalRange, MarketDataProvider};
+
+const API_BASE: &str = "https://api.coingecko.com/api/v3";
+const NAME: &str = "CoinGecko";
+
+// A coin from the last listings fetch, so quotes, history and logos can find its CoinGecko id
+#[derive(Debug, Clone)]
+struct ListedCoin {
+    id: i32,
+    symbol: String,
+    coingecko_id: String,
+    image: Option<String>,
+}
+
+// CoinGecko's public API. Coins are translated back to CMC ids and symbols through the
+// identity map, so clients can't tell which provider served them.
+pub struct CoinGeckoProvider {
+    client: Client,
+    retry_policy: RetryPolicy,
+    // Demo plan key; the keyless public API works too, at a lower rate limit
+    api_key: Option<String>,
+    identities: Arc<Mutex<IdentityMap>>,
+    listed: Mutex<Vec<ListedCoin>>,
+}
+
+impl CoinGeckoProvider {
+    pub fn new(client: Client, retry_policy: RetryPolicy, api_key: Option<String>, identities: Arc<Mutex<IdentityMap>>) -> Self {
+        CoinGeckoProvider {
+            client,
+            retry_policy,
+            api_key,
+            identities,
+            listed: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        let request = self.client
+            .get(format!("{}/{}", API_BASE, path))
+            .header("Accept", "application/json");
+        match &self.api_key {
+            Some(key) => request.header("x-cg-demo-api-key", key),
+            None => request,
+        }
+    }
+
+    async fn markets(&self, query: &[(&str, &str)]) -> CoinCrabResult<Vec<CoinGeckoMarket>> {
+        let request = self.get("coins/markets")
+            .query(&[("vs_currency", "usd"), ("price_change_percentage", "1h,24h,7d")])
+            .query(query);
+        get_json(NAME, request, &self.retry_policy, None).await
+    }
+
+    fn coingecko_id_for_symbol(&self, symbol: &str) -> Option<String> {
+        let mapped = self.identities.lock().unwrap().by_symbol(symbol).and_then(|identity| identity.coingecko_id.clone());
+        mapped.or_else(|| {
+            let listed = self.listed.lock().unwrap();
+            listed.iter().find(|coin| coin.symbol.eq_ignore_ascii_case(symbol)).map(|coin| coin.coingecko_id.clone())
+        })
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for CoinGeckoProvider {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn latest_listings(&self, limit: usize) -> CoinCrabResult<Vec<CryptoCurrency>> {
+        info!("Fetching data from CoinGecko API");
+        // 250 is CoinGecko's page size limit
+        let per_page = limit.min(250).to_string();
+        let markets = self.markets(&[("order", "market_cap_desc"), ("per_page", per_page.as_str()), ("page", "1")]).await?;
+
+        let identities = self.identities.lock().unwrap();
+        let mut listed = Vec::with_capacity(markets.len());
+        let coins: Vec<CryptoCurrency> = markets
+            .iter()
+            .filter_map(|market| {
+                let coin = to_crypto_currency(market, &identities)?;
+                listed.push(ListedCoin {
+                    id: coin.id,
+                    symbol: coin.symbol.clone(),
+                    coingecko_id: market.id.clone(),
+                    image: market.image.clone(),
+                });
+                Some(coin)
+            })
+            .collect();
+        drop(identities);
+        *self.listed.lock().unwrap() = listed;
+        Ok(coins)
+    }
+
+    async fn latest_quotes(&self, coins: &[CryptoCurrency]) -> CoinCrabResult<Vec<CryptoCurrency>> {
+        let ids = {
+            let identities = self.identities.lock().unwrap();
+            let listed = self.listed.lock().unwrap();
+            coins
+                .iter()
+                .filter_map(|coin| {
+                    let mapped = u32::try_from(coin.id).ok()
+                        .and_then(|cmc_id| identities.by_cmc_id(cmc_id))
+                        .and_then(|identity| identity.coingecko_id.clone());
+                    mapped.or_else(|| listed.iter().find(|l| l.id == coin.id).map(|l| l.coingecko_id.clone()))
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let markets = self.markets(&[("ids", ids.as_str())]).await?;
+        let identities = self.identities.lock().unwrap();
+        Ok(markets.iter().filter_map(|market| to_crypto_currency(market, &identities)).collect())
+    }
+
+    async fn historical_quotes(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let Some(coingecko_id) = self.coingecko_id_for_symbol(symbol) else {
+            return Err(CoinCrabError::Parse(format!("No CoinGecko id known for {}", symbol)));
+        };
+        let days = timeframe_days(timeframe).to_string();
+        debug!("CoinGecko market chart for {} over {} days", coingecko_id, days);
+        let request = self.get(&format!("coins/{}/market_chart", coingecko_id))
+            .query(&[("vs_currency", "usd"), ("days", days.as_str())]);
+        let chart: CoinGeckoMarketChart = get_json(NAME, request, &self.retry_policy, None).await?;
+        Ok(chart_points(chart))
+    }
+
+    // CoinGecko picks the granularity from the length of the window, so the interval is ignored
+    async fn historical_range(&self, symbol: &str, range: &HistoricalRange) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let Some(coingecko_id) = self.coingecko_id_for_symbol(symbol) else {
+            return Err(CoinCrabError::Parse(format!("No CoinGecko id known for {}", symbol)));
+        };
+        debug!("CoinGecko market chart for {} from {} to {}", coingecko_id, range.start, range.end);
+        let request = self.get(&format!("coins/{}/market_chart/range", coingecko_id))
+            .query(&[("vs_currency", "usd"), ("from", range.start.to_string().as_str()), ("to", range.end.to_string().as_str())]);
+        let chart: CoinGeckoMarketChart = get_json(NAME, request, &self.retry_policy, None).await?;
+        Ok(chart_points(chart))
+    }
+
+    async fn coin_metadata(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+        info!("Fetching CoinGecko coin list...");
+        let coins: Vec<CoinGeckoListEntry> = get_json(NAME, self.get("coins/list"), &self.retry_policy, None).await?;
+        Ok(coins
+            .into_iter()
+            .map(|coin| CoinIdentity {
+                symbol: coin.symbol,
+                name: coin.name,
+                cmc_id: None,
+                slug: None,
+                coingecko_id: Some(coin.id),
+            })
+            .collect())
+    }
+
+    // CoinGecko only hosts PNGs
+    fn logo_url(&self, identity: &CoinIdentity, size: u32, format: LogoFormat) -> Option<String> {
+        if format != LogoFormat::Png {
+            return None;
+        }
+        let listed = self.listed.lock().unwrap();
+        let coin = listed.iter().find(|coin| {
+            identity.coingecko_id.as_deref() == Some(coin.coingecko_id.as_str())
+                || identity.symbol.eq_ignore_ascii_case(&coin.symbol)
+        })?;
+        Some(sized_image_url(coin.image.as_deref()?, size))
+    }
+}
+
+// Listing entry in the server's shape. Coins the identity map knows take their CMC id and
+// symbol; others (e.g. when the CMC map never loaded) get a negative id from their market-cap
+// rank so they still can't collide with a CMC id. Coins without a price or rank are skipped,
+// as are unmapped coins reusing the symbol of a mapped one.
+fn to_crypto_currency(market: &CoinGeckoMarket, identities: &IdentityMap) -> Option<CryptoCurrency> {
+    let (id, symbol, name) = match identities.by_coingecko_id(&market.id) {
+        Some(identity) => (i32::try_from(identity.cmc_id?).ok()?, identity.symbol.clone(), identity.name.clone()),
+        None if identities.by_symbol(&market.symbol).is_some() => return None,
+        None => (-i32::try_from(market.market_cap_rank?).ok()?, market.symbol.to_uppercase(), market.name.clone()),
+    };
+    Some(CryptoCurrency {
+        id,
+        name,
+        symbol,
+        quote: Quote {
+            usd: UsdQuote {
+                price: market.current_price?,
+                percent_change_1h: market.price_change_percentage_1h_in_currency.unwrap_or_default(),
+                percent_change_24h: market.price_change_percentage_24h_in_currency.unwrap_or_default(),
+                percent_change_7d: market.price_change_percentage_7d_in_currency.unwrap_or_default(),
+                market_cap: market.market_cap.unwrap_or_default(),
+                volume_24h: market.total_volume.unwrap_or_default(),
+                last_updated: market.last_updated.clone().unwrap_or_default(),
+            },
+            converted: Default::default(),
+        },
+    })
+}
+
+fn chart_points(chart: CoinGeckoMarketChart) -> Vec<HistoricalDataPoint> {
+    chart
+        .prices
+        .iter()
+        .enumerate()
+        .map(|(i, [timestamp_ms, price])| HistoricalDataPoint {
+            timestamp: (timestamp_ms / 1000.0).floor(),
+            price: *price,
+            // Volumes share the price timestamps
+            volume: chart.total_volumes.get(i).map(|[_, volume]| *volume),
+        })
+        .collect()
+}
+
+// Market images are the "large" (250px) variant; "small" is 50px and "thumb" 25px
+fn sized_image_url(image: &str, size: u32) -> String {
+    let variant = match size {
+        0..=25 => "/thumb/",
+        26..=50 => "/small/",
+        _ => return image.to_string(),
+    };
+    image.replacen("/large/", variant, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKETS: &str = r#"[
+        {"id":"bitcoin","symbol":"btc","name":"Bitcoin","image":"https://assets.coingecko.com/coins/images/1/large/bitcoin.png",
+         "market_cap_rank":1,"current_price":64000.5,"market_cap":1.26e12,"total_volume":3.1e10,
+         "price_change_percentage_1h_in_currency":0.1,"price_change_percentage_24h_in_currency":-1.2,
+         "price_change_percentage_7d_in_currency":4.5,"last_updated":"2024-05-01T12:00:00.000Z"},
+        {"id":"new-token","symbol":"new","name":"New Token","image":null,"market_cap_rank":77,"current_price":0.5,
+         "market_cap":null,"total_volume":null,"price_change_percentage_1h_in_currency":null,
+         "price_change_percentage_24h_in_currency":null,"price_change_percentage_7d_in_currency":null,"last_updated":null},
+        {"id":"bitcoin-clone","symbol":"btc","name":"Bitcoin Clone","image":null,"market_cap_rank":900,"current_price":0.01,
+         "market_cap":null,"total_volume":null,"last_updated":null},
+        {"id":"unpriced","symbol":"upx","name":"Unpriced","image":null,"market_cap_rank":null,"current_price":null,
+         "market_cap":null,"total_volume":null,"last_updated":null}
+    ]"#;
+
+    #[test]
+    fn test_markets_map_to_cmc_identities() {
+        let mut identities = IdentityMap::new();
+        identities.record_cmc(1, "BTC", "Bitcoin", "bitcoin");
+        identities.record_coingecko("bitcoin", "btc", "Bitcoin");
+        let markets: Vec<CoinGeckoMarket> = serde_json::from_str(MARKETS).unwrap();
+
+        let coins: Vec<CryptoCurrency> = markets.iter().filter_map(|m| to_crypto_currency(m, &identities)).collect();
+        assert_eq!(coins.len(), 2);
+        assert_eq!((coins[0].id, coins[0].symbol.as_str()), (1, "BTC"));
+        assert_eq!(coins[0].quote.usd.price, 64000.5);
+        assert_eq!(coins[0].quote.usd.percent_change_24h, -1.2);
+        assert_eq!((coins[1].id, coins[1].symbol.as_str()), (-77, "NEW"));
+        assert_eq!(coins[1].quote.usd.market_cap, 0.0);
+    }
+
+    #[test]
+    fn test_chart_points_and_image_sizes() {
+        let chart: CoinGeckoMarketChart = serde_json::from_str(
+            r#"{"prices":[[1714564800123,64000.0],[1714568400000,64100.0]],"market_caps":[],"total_volumes":[[1714564800123,3.0e10]]}"#
+        ).unwrap();
+        let points = chart_points(chart);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp, 1714564800.0);
+        assert_eq!(points[0].volume, Some(3.0e10));
+        assert_eq!(points[1].volume, None);
+
+        let image = "https://assets.coingecko.com/coins/images/1/large/bitcoin.png";
+        assert_eq!(sized_image_url(image, 16), "https://assets.coingecko.com/coins/images/1/thumb/bitcoin.png");
+        assert_eq!(sized_image_url(image, 32), "https://assets.coingecko.com/coins/images/1/small/bitcoin.png");
+        assert_eq!(sized_image_url(image, 128), image);
+    }
+}
@@ -0,0 +1,263 @@
+use async_trait::async_trait;
+use std::time::Duration;
+use tracing::info;
+use reqwest::{Client, RequestBuilder};
+use crate::config::CircuitBreakerConfig;
+use crate::http_client::RetryPolicy;
+use crate::identity::CoinIdentity;
+use crate::logos::LogoFormat;
+use crate::types::{CmcMappingResponse, CmcQuotesResponse, CoinMarketCapResponse, CryptoCurrency};
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint};
+use super::circuit_breaker::CircuitBreaker;
+use super::range::format_time;
+use super::{get_json, timeframe_days, HistoricalRange, MarketDataProvider};
+
+const API_BASE: &str = "https://pro-api.coinmarketcap.com/v1/cryptocurrency";
+const NAME: &str = "CoinMarketCap";
+
+pub struct CoinMarketCapProvider {
+    client: Client,
+    retry_policy: RetryPolicy,
+    api_key: String,
+    // Every CMC call goes through it, so a rate-limited key isn't hammered
+    breaker: CircuitBreaker,
+}
+
+impl CoinMarketCapProvider {
+    pub fn new(client: Client, retry_policy: RetryPolicy, api_key: String) -> Self {
+        let breaker = CircuitBreaker::new(NAME, &CircuitBreakerConfig::default());
+        CoinMarketCapProvider { client, retry_policy, api_key, breaker }
+    }
+
+    pub fn with_circuit_breaker(self, config: &CircuitBreakerConfig) -> Self {
+        CoinMarketCapProvider { breaker: CircuitBreaker::new(NAME, config), ..self }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client
+            .get(format!("{}/{}", API_BASE, path))
+            .header("X-CMC_PRO_API_KEY", &self.api_key)
+            .header("Accept", "application/json")
+    }
+
+    // The cryptocurrency ID of a symbol, which quotes/historical needs
+    async fn crypto_id(&self, symbol: &str) -> CoinCrabResult<u64> {
+        let request = self.get("quotes/latest")
+            .query(&[("symbol", symbol), ("convert", "USD")]);
+        let json: serde_json::Value = get_json(NAME, request, &self.retry_policy, Some(&self.breaker)).await?;
+        let Some(data) = json.get("data").and_then(|d| d.get(symbol)) else {
+            return Err(CoinCrabError::Parse("Invalid symbol or no data found".to_string()));
+        };
+        data.get("id")
+            .and_then(|id| id.as_u64())
+            .ok_or_else(|| CoinCrabError::Parse("Could not find cryptocurrency ID".to_string()))
+    }
+
+    async fn quotes_between(&self, crypto_id: u64, start_time: &str, end_time: &str, interval: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        info!("CMC historical quotes for id {} from {} to {} every {}", crypto_id, start_time, end_time, interval);
+        let request = self.get("quotes/historical").query(&[
+            ("id", crypto_id.to_string().as_str()),
+            ("time_start", start_time),
+            ("time_end", end_time),
+            ("interval", interval),
+        ]);
+        let json: serde_json::Value = get_json(NAME, request, &self.retry_policy, Some(&self.breaker)).await?;
+        Ok(parse_historical_quotes(&json))
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for CoinMarketCapProvider {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.breaker.retry_after()
+    }
+
+    async fn latest_listings(&self, limit: usize) -> CoinCrabResult<Vec<CryptoCurrency>> {
+        info!("Fetching data from CoinMarketCap API");
+        info!("Using API key: {}...", &self.api_key[..8.min(self.api_key.len())]);
+        let request = self.get("listings/latest")
+            .query(&[("limit", limit.to_string().as_str()), ("convert", "USD")]);
+        let response: CoinMarketCapResponse = get_json(NAME, request, &self.retry_policy, Some(&self.breaker)).await?;
+        Ok(response.data)
+    }
+
+    // quotes/latest costs one credit per 100 coins, far less than a full listings call
+    async fn latest_quotes(&self, coins: &[CryptoCurrency]) -> CoinCrabResult<Vec<CryptoCurrency>> {
+        let id_list = coins.iter().map(|coin| coin.id.to_string()).collect::<Vec<_>>().join(",");
+        let request = self.get("quotes/latest")
+            .query(&[("id", id_list.as_str()), ("convert", "USD")]);
+        let response: CmcQuotesResponse = get_json(NAME, request, &self.retry_policy, Some(&self.breaker)).await?;
+        Ok(response.data.into_values().collect())
+    }
+
+    async fn historical_quotes(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let days = timeframe_days(timeframe);
+        let crypto_id = self.crypto_id(symbol).await?;
+        let interval = get_interval_for_timeframe(timeframe);
+        self.quotes_between(crypto_id, &get_start_time(days), &get_current_time(), interval).await
+    }
+
+    async fn historical_range(&self, symbol: &str, range: &HistoricalRange) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let crypto_id = self.crypto_id(symbol).await?;
+        self.quotes_between(crypto_id, &format_time(range.start), &format_time(range.end), range.interval).await
+    }
+
+    async fn coin_metadata(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+        info!("Fetching CMC cryptocurrency mapping data...");
+        let request = self.get("map").query(&[("limit", "5000")]);
+        let response: CmcMappingResponse = get_json(NAME, request, &self.retry_policy, Some(&self.breaker)).await?;
+        if response.status.error_code != 0 {
+            return Err(CoinCrabError::Http(format!("CMC API error: {} (code: {})",
+                response.status.error_message.unwrap_or("Unknown error".to_string()),
+                response.status.error_code
+            )));
+        }
+        Ok(response
+            .data
+            .into_iter()
+            .map(|currency| CoinIdentity {
+                symbol: currency.symbol,
+                name: currency.name,
+                cmc_id: Some(currency.id),
+                slug: Some(currency.slug),
+                coingecko_id: None,
+            })
+            .collect())
+    }
+
+    fn logo_url(&self, identity: &CoinIdentity, size: u32, format: LogoFormat) -> Option<String> {
+        let cmc_id = identity.cmc_id?;
+        Some(format!("https://s2.coinmarketcap.com/static/img/coins/{}x{}/{}.{}", size, size, cmc_id, format.extension()))
+    }
+}
+
+// Points of a quotes/historical response; quotes without a parseable timestamp or price are skipped
+fn parse_historical_quotes(json: &serde_json::Value) -> Vec<HistoricalDataPoint> {
+    let mut historical_points = Vec::new();
+    if let Some(data) = json.get("data").and_then(|d| d.get("quotes").and_then(|q| q.as_array())) {
+        for quote in data {
+            if let (Some(timestamp_str), Some(price_data)) = (
+                quote.get("timestamp").and_then(|t| t.as_str()),
+                quote.get("quote").and_then(|q| q.get("USD"))
+            ) {
+                if let (Ok(timestamp), Some(price)) = (
+                    chrono::DateTime::parse_from_rfc3339(timestamp_str),
+                    price_data.get("price").and_then(|p| p.as_f64())
+                ) {
+                    historical_points.push(HistoricalDataPoint {
+                        timestamp: timestamp.timestamp() as f64,
+                        price,
+                        volume: price_data.get("volume_24h").and_then(|v| v.as_f64()),
+                    });
+                }
+            }
+        }
+    }
+    historical_points
+}
+
+// Helper functions for historical data processing
+fn get_start_time(days: u32) -> String {
+    let now = chrono::Utc::now();
+    let start_time = now - chrono::Duration::days(days as i64);
+    start_time.format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string()
+}
+
+fn get_current_time() -> String {
+    let now = chrono::Utc::now();
+    now.format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string()
+}
+
+fn get_interval_for_timeframe(timeframe: &str) -> &str {
+    match timeframe {
+        "1h" => "5m",
+        "24h" | "1d" => "1h",
+        "7d" => "2h",
+        "30d" => "6h",
+        "90d" => "1d",  // Use daily intervals for 90d
+        "365d" | "1y" => "1d",
+        "all" => "1d",  // Use daily intervals for all time
+        _ => "1h",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_historical_quotes() {
+        let json: serde_json::Value = serde_json::from_str(r#"{"data":{"id":1,"quotes":[
+            {"timestamp":"2024-01-01T00:00:00.000Z","quote":{"USD":{"price":42000.5,"volume_24h":1.5e10}}},
+            {"timestamp":"not a time","quote":{"USD":{"price":1.0}}},
+            {"timestamp":"2024-01-02T00:00:00.000Z","quote":{"USD":{"price":43000.0}}}
+        ]}}"#).unwrap();
+        let points = parse_historical_quotes(&json);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp, 1704067200.0);
+        assert_eq!(points[0].volume, Some(1.5e10));
+        assert_eq!(points[1].price, 43000.0);
+        assert_eq!(points[1].volume, None);
+    }
+
+    #[test]
+    fn test_get_start_time() {
+        let start_time = get_start_time(30);
+
+        // Verify it's a valid ISO 8601 timestamp
+        assert!(start_time.contains("T"));
+        assert!(start_time.ends_with("Z"));
+        assert_eq!(start_time.len(), 24); // Format: 2024-01-01T00:00:00.000Z
+
+        // Parse the timestamp to ensure it's valid
+        let parsed = chrono::DateTime::parse_from_rfc3339(&start_time);
+        assert!(parsed.is_ok());
+
+        // Verify it's approximately 30 days ago
+        let parsed_time = parsed.unwrap();
+        let now = chrono::Utc::now();
+        let diff = now.signed_duration_since(parsed_time.with_timezone(&chrono::Utc));
+
+        // Should be between 29.9 and 30.1 days (allowing for execution time)
+        assert!(diff.num_days() >= 29 && diff.num_days() <= 31);
+    }
+
+    #[test]
+    fn test_get_current_time() {
+        let current_time = get_current_time();
+
+        // Verify it's a valid ISO 8601 timestamp
+        assert!(current_time.contains("T"));
+        assert!(current_time.ends_with("Z"));
+        assert_eq!(current_time.len(), 24); // Format: 2024-01-01T00:00:00.000Z
+
+        // Parse the timestamp to ensure it's valid
+        let parsed = chrono::DateTime::parse_from_rfc3339(&current_time);
+        assert!(parsed.is_ok());
+
+        // Verify it's very recent (within 1 second)
+        let parsed_time = parsed.unwrap();
+        let now = chrono::Utc::now();
+        let diff = now.signed_duration_since(parsed_time.with_timezone(&chrono::Utc));
+
+        assert!(diff.num_seconds().abs() <= 1);
+    }
+
+    #[test]
+    fn test_get_interval_for_timeframe() {
+        assert_eq!(get_interval_for_timeframe("1h"), "5m");
+        assert_eq!(get_interval_for_timeframe("24h"), "1h");
+        assert_eq!(get_interval_for_timeframe("1d"), "1h");
+        assert_eq!(get_interval_for_timeframe("7d"), "2h");
+        assert_eq!(get_interval_for_timeframe("30d"), "6h");
+        assert_eq!(get_interval_for_timeframe("90d"), "1d");
+        assert_eq!(get_interval_for_timeframe("365d"), "1d");
+        assert_eq!(get_interval_for_timeframe("1y"), "1d");
+        assert_eq!(get_interval_for_timeframe("all"), "1d");
+        assert_eq!(get_interval_for_timeframe("invalid"), "1h"); // Default case
+    }
+}
@@ -0,0 +1,367 @@
+pub mod binance;
+pub mod circuit_breaker;
+pub mod coingecko;
+pub mod coinmarketcap;
+pub mod range;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use tracing::{instrument, warn, Span};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use crate::config::MarketDataConfig;
+use crate::http_client::{send_with_retry, RetryPolicy};
+use crate::identity::{CoinIdentity, IdentityMap};
+use crate::logos::LogoFormat;
+use crate::types::CryptoCurrency;
+use circuit_breaker::CircuitBreaker;
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint};
+
+pub use binance::run_binance_stream;
+pub use coingecko::CoinGeckoProvider;
+pub use coinmarketcap::CoinMarketCapProvider;
+pub use range::HistoricalRange;
+
+// A source of market data. Coins are always returned keyed the way the rest of the server
+// keys them (CMC ids and symbols, see identity.rs), whichever provider served them.
+//
+// Errors that mean the provider can't serve us right now - CoinCrabError::RateLimited for
+// 429s and CoinCrabError::Config for a rejected API key - make MarketData fail over.
+#[async_trait]
+pub trait MarketDataProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Top coins by market cap, in rank order
+    async fn latest_listings(&self, limit: usize) -> CoinCrabResult<Vec<CryptoCurrency>>;
+
+    // Fresh quotes for coins from an earlier listing; coins the provider can't quote are left out
+    async fn latest_quotes(&self, coins: &[CryptoCurrency]) -> CoinCrabResult<Vec<CryptoCurrency>>;
+
+    async fn historical_quotes(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>>;
+
+    // History of an explicit window, already validated against CMC's limits
+    async fn historical_range(&self, symbol: &str, range: &HistoricalRange) -> CoinCrabResult<Vec<HistoricalDataPoint>>;
+
+    // Every coin the provider knows, with only this provider's ids filled in
+    async fn coin_metadata(&self) -> CoinCrabResult<Vec<CoinIdentity>>;
+
+    // Logo image URL, as close to size x size pixels as the provider offers, or None when the
+    // provider has no logo for the coin in that format
+    fn logo_url(&self, identity: &CoinIdentity, size: u32, format: LogoFormat) -> Option<String>;
+
+    // While the provider is refusing requests (e.g. its circuit breaker is open), how long
+    // until it will be asked again
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Days of history behind a timeframe; "all" is capped at a year like the provider plans allow
+pub fn timeframe_days(timeframe: &str) -> u32 {
+    match timeframe {
+        "1h" => 1,
+        "24h" | "1d" => 1,
+        "7d" => 7,
+        "30d" => 30,
+        "90d" => 90,
+        "365d" | "1y" => 365,
+        "all" => 365,
+        _ => 30,
+    }
+}
+
+fn status_error(provider: &str, status: StatusCode, body: &str) -> CoinCrabError {
+    // Cut on a char boundary; error bodies can be any UTF-8
+    let detail: String = body.chars().take(300).collect();
+    match status.as_u16() {
+        429 => CoinCrabError::RateLimited(format!("{} rate limit reached: {}", provider, detail)),
+        401 | 403 => CoinCrabError::Config(format!("{} rejected the API key ({}): {}", provider, status, detail)),
+        _ => CoinCrabError::Http(format!("{} returned status {}: {}", provider, status, detail)),
+    }
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+// Send a provider request and parse the JSON body, classifying failed responses. With a
+// breaker, requests fail fast while it is open and every answer is reported to it.
+#[instrument(level = "debug", name = "provider_request", skip(request, retry_policy, breaker), fields(url, status))]
+async fn get_json<T: DeserializeOwned>(provider: &str, request: RequestBuilder, retry_policy: &RetryPolicy, breaker: Option<&CircuitBreaker>) -> CoinCrabResult<T> {
+    if let Some(breaker) = breaker {
+        breaker.allow()?;
+    }
+    let response = send_with_retry(request, retry_policy).await.map_err(|e| {
+        if let Some(breaker) = breaker {
+            breaker.record_failure(None);
+        }
+        CoinCrabError::Http(format!("{} request failed: {}", provider, e))
+    })?;
+    let status = response.status();
+    if let Some(breaker) = breaker {
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            breaker.record_failure(retry_after(&response));
+        } else {
+            breaker.record_success();
+        }
+    }
+    // Path only: query strings may carry API keys
+    Span::current().record("url", response.url().path()).record("status", status.as_u16());
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(status_error(provider, status, &body));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| CoinCrabError::Parse(format!("Failed to parse {} response: {}", provider, e)))
+}
+
+// The configured providers with automatic failover. Requests go to the primary until it
+// reports a rate limit or a rejected key; then the secondary serves them for the cooldown,
+// after which the primary is tried again.
+pub struct MarketData {
+    primary: Arc<dyn MarketDataProvider>,
+    secondary: Option<Arc<dyn MarketDataProvider>>,
+    cooldown: Duration,
+    primary_unavailable_until: Mutex<Option<Instant>>,
+    // Provider that served the last successful listings fetch
+    listings_source: Mutex<Option<&'static str>>,
+}
+
+impl MarketData {
+    pub fn new(primary: Arc<dyn MarketDataProvider>, secondary: Option<Arc<dyn MarketDataProvider>>, cooldown: Duration) -> Self {
+        MarketData {
+            primary,
+            secondary,
+            cooldown,
+            primary_unavailable_until: Mutex::new(None),
+            listings_source: Mutex::new(None),
+        }
+    }
+
+    // CoinMarketCap, with CoinGecko behind it unless the fallback is disabled
+    pub fn from_config(config: &MarketDataConfig, cmc_api_key: &str, client: &Client, retry_policy: RetryPolicy, identities: Arc<Mutex<IdentityMap>>) -> Self {
+        let primary = Arc::new(
+            CoinMarketCapProvider::new(client.clone(), retry_policy, cmc_api_key.to_string())
+                .with_circuit_breaker(&config.cmc_circuit_breaker),
+        );
+        let secondary = config.coingecko_fallback.then(|| {
+            Arc::new(CoinGeckoProvider::new(client.clone(), retry_policy, config.coingecko_api_key.clone(), identities)) as Arc<dyn MarketDataProvider>
+        });
+        MarketData::new(primary, secondary, Duration::from_secs(config.failover_cooldown_seconds))
+    }
+
+    pub fn primary(&self) -> &dyn MarketDataProvider {
+        self.primary.as_ref()
+    }
+
+    pub fn secondary(&self) -> Option<&dyn MarketDataProvider> {
+        self.secondary.as_deref()
+    }
+
+    // Named in price snapshots; the provider that would be asked next before any fetch succeeded
+    pub fn listings_source(&self) -> &'static str {
+        self.listings_source.lock().unwrap().unwrap_or_else(|| self.name())
+    }
+
+    // Provider to try first, and whether it is the primary
+    fn current(&self) -> (&dyn MarketDataProvider, bool) {
+        let until = *self.primary_unavailable_until.lock().unwrap();
+        match (&self.secondary, until) {
+            (Some(secondary), Some(until)) if Instant::now() < until => (secondary.as_ref(), false),
+            _ => (self.primary.as_ref(), true),
+        }
+    }
+
+    // After a failed primary request: the provider to retry on, if the failure warrants failover
+    fn fallback_after<T>(&self, used_primary: bool, result: &CoinCrabResult<T>) -> Option<&dyn MarketDataProvider> {
+        let Err(e) = result else {
+            return None;
+        };
+        let secondary = self.secondary.as_deref()?;
+        if !used_primary || !matches!(e, CoinCrabError::RateLimited(_) | CoinCrabError::Config(_)) {
+            return None;
+        }
+        warn!("{} unavailable ({}) - failing over to {} for {}s",
+              self.primary.name(), e, secondary.name(), self.cooldown.as_secs());
+        *self.primary_unavailable_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+        Some(secondary)
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for MarketData {
+    fn name(&self) -> &'static str {
+        self.current().0.name()
+    }
+
+    async fn latest_listings(&self, limit: usize) -> CoinCrabResult<Vec<CryptoCurrency>> {
+        let (mut provider, used_primary) = self.current();
+        let mut result = provider.latest_listings(limit).await;
+        if let Some(fallback) = self.fallback_after(used_primary, &result) {
+            provider = fallback;
+            result = fallback.latest_listings(limit).await;
+        }
+        if result.is_ok() {
+            *self.listings_source.lock().unwrap() = Some(provider.name());
+        }
+        result
+    }
+
+    async fn latest_quotes(&self, coins: &[CryptoCurrency]) -> CoinCrabResult<Vec<CryptoCurrency>> {
+        let (provider, used_primary) = self.current();
+        let result = provider.latest_quotes(coins).await;
+        match self.fallback_after(used_primary, &result) {
+            Some(fallback) => fallback.latest_quotes(coins).await,
+            None => result,
+        }
+    }
+
+    async fn historical_quotes(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let (provider, used_primary) = self.current();
+        let result = provider.historical_quotes(symbol, timeframe).await;
+        match self.fallback_after(used_primary, &result) {
+            Some(fallback) => fallback.historical_quotes(symbol, timeframe).await,
+            None => result,
+        }
+    }
+
+    async fn historical_range(&self, symbol: &str, range: &HistoricalRange) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let (provider, used_primary) = self.current();
+        let result = provider.historical_range(symbol, range).await;
+        match self.fallback_after(used_primary, &result) {
+            Some(fallback) => fallback.historical_range(symbol, range).await,
+            None => result,
+        }
+    }
+
+    async fn coin_metadata(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+        let (provider, used_primary) = self.current();
+        let result = provider.coin_metadata().await;
+        match self.fallback_after(used_primary, &result) {
+            Some(fallback) => fallback.coin_metadata().await,
+            None => result,
+        }
+    }
+
+    // Logo CDNs don't spend API credits, so the primary's logos are used whenever it has one
+    fn logo_url(&self, identity: &CoinIdentity, size: u32, format: LogoFormat) -> Option<String> {
+        self.primary
+            .logo_url(identity, size, format)
+            .or_else(|| self.secondary.as_ref()?.logo_url(identity, size, format))
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.current().0.retry_after()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Serves one canned listing result and counts the calls
+    struct FakeProvider {
+        name: &'static str,
+        listings: fn() -> CoinCrabResult<Vec<CryptoCurrency>>,
+        calls: AtomicUsize,
+    }
+
+    impl FakeProvider {
+        fn new(name: &'static str, listings: fn() -> CoinCrabResult<Vec<CryptoCurrency>>) -> Arc<Self> {
+            Arc::new(FakeProvider { name, listings, calls: AtomicUsize::new(0) })
+        }
+    }
+
+    #[async_trait]
+    impl MarketDataProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn latest_listings(&self, _limit: usize) -> CoinCrabResult<Vec<CryptoCurrency>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            (self.listings)()
+        }
+
+        async fn latest_quotes(&self, _coins: &[CryptoCurrency]) -> CoinCrabResult<Vec<CryptoCurrency>> {
+            Ok(Vec::new())
+        }
+
+        async fn historical_quotes(&self, _symbol: &str, _timeframe: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+            Err(CoinCrabError::Http("unreachable".to_string()))
+        }
+
+        async fn historical_range(&self, _symbol: &str, _range: &HistoricalRange) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+            Err(CoinCrabError::Http("unreachable".to_string()))
+        }
+
+        async fn coin_metadata(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+            Ok(Vec::new())
+        }
+
+        fn logo_url(&self, identity: &CoinIdentity, _size: u32, format: LogoFormat) -> Option<String> {
+            Some(format!("{}/{}.{}", self.name, identity.symbol, format.extension()))
+        }
+    }
+
+    fn rate_limited() -> CoinCrabResult<Vec<CryptoCurrency>> {
+        Err(CoinCrabError::RateLimited("429".to_string()))
+    }
+
+    fn server_error() -> CoinCrabResult<Vec<CryptoCurrency>> {
+        Err(CoinCrabError::Http("500".to_string()))
+    }
+
+    fn empty() -> CoinCrabResult<Vec<CryptoCurrency>> {
+        Ok(Vec::new())
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_on_rate_limit_for_the_cooldown() {
+        let primary = FakeProvider::new("primary", rate_limited);
+        let secondary = FakeProvider::new("secondary", empty);
+        let market_data = MarketData::new(primary.clone(), Some(secondary.clone()), Duration::from_secs(300));
+
+        assert!(market_data.latest_listings(10).await.is_ok());
+        assert!(market_data.latest_listings(10).await.is_ok());
+        // The primary is skipped while cooling down
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(secondary.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(market_data.name(), "secondary");
+        assert_eq!(market_data.listings_source(), "secondary");
+
+        let identity = CoinIdentity { symbol: "BTC".to_string(), name: "Bitcoin".to_string(), cmc_id: Some(1), slug: None, coingecko_id: None };
+        assert_eq!(market_data.logo_url(&identity, 64, LogoFormat::Png).as_deref(), Some("primary/BTC.png"));
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_do_not_fail_over() {
+        let primary = FakeProvider::new("primary", server_error);
+        let secondary = FakeProvider::new("secondary", empty);
+        let market_data = MarketData::new(primary.clone(), Some(secondary.clone()), Duration::from_secs(300));
+        assert!(matches!(market_data.latest_listings(10).await, Err(CoinCrabError::Http(_))));
+        assert_eq!(secondary.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(market_data.listings_source(), "primary");
+
+        // Without a secondary the primary's error is returned as is
+        let alone = MarketData::new(FakeProvider::new("primary", rate_limited), None, Duration::from_secs(300));
+        assert!(matches!(alone.latest_listings(10).await, Err(CoinCrabError::RateLimited(_))));
+    }
+
+    #[test]
+    fn test_status_classification() {
+        assert!(matches!(status_error("CMC", StatusCode::TOO_MANY_REQUESTS, ""), CoinCrabError::RateLimited(_)));
+        assert!(matches!(status_error("CMC", StatusCode::UNAUTHORIZED, ""), CoinCrabError::Config(_)));
+        assert!(matches!(status_error("CMC", StatusCode::BAD_GATEWAY, ""), CoinCrabError::Http(_)));
+
+        // A multibyte character straddling the cut-off doesn't panic
+        let body = format!("{}€", "x".repeat(299));
+        assert!(matches!(status_error("CMC", StatusCode::BAD_GATEWAY, &body), CoinCrabError::Http(detail) if detail.ends_with('€')));
+    }
+}
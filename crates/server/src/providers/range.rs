@@ -0,0 +1,159 @@
+use std::time::Duration;
+use chrono::{DateTime, SecondsFormat, Utc};
+use shared::{CoinCrabError, CoinCrabResult};
+
+// Intervals CMC's quotes/historical accepts, with their length in seconds
+const INTERVALS: [(&str, i64); 21] = [
+    ("5m", 300),
+    ("10m", 600),
+    ("15m", 900),
+    ("30m", 1800),
+    ("45m", 2700),
+    ("1h", 3600),
+    ("2h", 7200),
+    ("3h", 10_800),
+    ("4h", 14_400),
+    ("6h", 21_600),
+    ("12h", 43_200),
+    ("1d", 86_400),
+    ("2d", 172_800),
+    ("3d", 259_200),
+    ("7d", 604_800),
+    ("14d", 1_209_600),
+    ("15d", 1_296_000),
+    ("30d", 2_592_000),
+    ("60d", 5_184_000),
+    ("90d", 7_776_000),
+    ("365d", 31_536_000),
+];
+
+// quotes/historical returns at most this many points per call
+const MAX_POINTS: i64 = 10_000;
+// Interval picked when none is given: the finest that keeps the window within this many points
+const DEFAULT_POINTS: i64 = 500;
+// CMC has no quotes before 2013-04-28
+const EARLIEST_START: i64 = 1_367_107_200;
+
+// An explicit window of history ("last March") instead of a timeframe counting back from now
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalRange {
+    // Unix seconds
+    pub start: i64,
+    pub end: i64,
+    pub interval: &'static str,
+}
+
+// Unix seconds, or an RFC 3339 timestamp
+fn parse_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return seconds.is_finite().then_some(seconds as i64);
+    }
+    DateTime::parse_from_rfc3339(value).ok().map(|time| time.timestamp())
+}
+
+fn invalid(message: String) -> CoinCrabError {
+    CoinCrabError::Parse(message)
+}
+
+impl HistoricalRange {
+    // From request parameters; end defaults to now
+    pub fn parse(start: &str, end: Option<&str>, interval: Option<&str>, now: i64) -> CoinCrabResult<Self> {
+        let start = parse_time(start).ok_or_else(|| invalid(format!("Invalid start '{}': expected unix seconds or RFC 3339", start)))?;
+        let end = match end {
+            Some(end) => parse_time(end).ok_or_else(|| invalid(format!("Invalid end '{}': expected unix seconds or RFC 3339", end)))?,
+            None => now,
+        };
+        Self::new(start, end, interval, now)
+    }
+
+    // A window CMC will serve in one call: in the past, after its first quotes and short enough
+    // for the interval
+    pub fn new(start: i64, end: i64, interval: Option<&str>, now: i64) -> CoinCrabResult<Self> {
+        if start >= end {
+            return Err(invalid("start must be before end".to_string()));
+        }
+        if start < EARLIEST_START {
+            return Err(invalid(format!("No history before {}", format_time(EARLIEST_START))));
+        }
+        let end = end.min(now);
+        if start >= end {
+            return Err(invalid("start must be in the past".to_string()));
+        }
+
+        let span = end - start;
+        let (interval, seconds) = match interval {
+            Some(interval) => {
+                let interval = interval.trim().to_lowercase();
+                let interval = if interval == "24h" { "1d".to_string() } else { interval };
+                *INTERVALS.iter().find(|(name, _)| *name == interval).ok_or_else(|| {
+                    let names: Vec<&str> = INTERVALS.iter().map(|(name, _)| *name).collect();
+                    invalid(format!("Unsupported interval '{}' (expected one of {})", interval, names.join(", ")))
+                })?
+            }
+            None => *INTERVALS.iter().find(|(_, seconds)| span / seconds <= DEFAULT_POINTS).unwrap_or(&INTERVALS[INTERVALS.len() - 1]),
+        };
+        if span / seconds > MAX_POINTS {
+            return Err(invalid(format!(
+                "A {}-day window at {} intervals is more than {} points; use a longer interval",
+                span / 86_400, interval, MAX_POINTS
+            )));
+        }
+        Ok(HistoricalRange { start, end, interval })
+    }
+
+    pub fn spacing(&self) -> Duration {
+        let seconds = INTERVALS.iter().find(|(name, _)| *name == self.interval).map_or(86_400, |(_, seconds)| *seconds);
+        Duration::from_secs(seconds as u64)
+    }
+
+    // ISO 8601 interval, used in place of a timeframe in results and logs
+    pub fn label(&self) -> String {
+        format!("{}/{}", format_time(self.start), format_time(self.end))
+    }
+}
+
+pub fn format_time(seconds: i64) -> String {
+    DateTime::<Utc>::from_timestamp(seconds, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-06-01T00:00:00Z
+    const NOW: i64 = 1_717_200_000;
+
+    #[test]
+    fn test_parse_range() {
+        let march = HistoricalRange::parse("2024-03-01T00:00:00Z", Some("1711929600"), None, NOW).unwrap();
+        assert_eq!((march.start, march.end), (1_709_251_200, 1_711_929_600));
+        // 31 days: hourly would be 744 points, so 2h is the finest default within 500
+        assert_eq!(march.interval, "2h");
+        assert_eq!(march.label(), "2024-03-01T00:00:00Z/2024-04-01T00:00:00Z");
+
+        let open_ended = HistoricalRange::parse("1714521600", None, Some("24h"), NOW).unwrap();
+        assert_eq!((open_ended.end, open_ended.interval), (NOW, "1d"));
+        assert_eq!(open_ended.spacing(), Duration::from_secs(86_400));
+    }
+
+    #[test]
+    fn test_range_limits() {
+        for (start, end, interval) in [
+            ("March", None, None),
+            ("1711929600", Some("1709251200"), None),
+            ("2010-01-01T00:00:00Z", None, None),
+            ("1800000000", None, None),
+            ("1709251200", None, Some("1w")),
+            // A year of 5-minute quotes
+            ("2023-06-01T00:00:00Z", None, Some("5m")),
+        ] {
+            assert!(HistoricalRange::parse(start, end, interval, NOW).is_err(), "{} {:?} {:?}", start, end, interval);
+        }
+        // Ends in the future are clamped to now
+        let range = HistoricalRange::parse("1714521600", Some("1900000000"), None, NOW).unwrap();
+        assert_eq!(range.end, NOW);
+    }
+}
@@ -0,0 +1,94 @@
+use actix_web::web;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use crate::config::{load_config_file, ReloadableConfig};
+use crate::types::AppState;
+
+// How often the config file's modification time is checked
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Re-read the config file on SIGHUP or when it changes, and hand the settings that can change
+// at runtime (listings interval, hot tier, tracked series) to the periodic tasks. Everything
+// else is still read once at startup.
+pub async fn reload_config_on_change(state: web::Data<AppState>, settings: watch::Sender<ReloadableConfig>, path: PathBuf) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!("Failed to listen for SIGHUP, config reloads only follow file changes: {}", e);
+            None
+        }
+    };
+    info!("Reloading {} on SIGHUP or when it changes", path.display());
+
+    let mut last_modified = modified_at(&path);
+    let mut poll = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            Some(()) = async { hangup.as_mut()?.recv().await } => {
+                info!("SIGHUP received, reloading {}", path.display());
+            }
+            _ = poll.tick() => {
+                let modified = modified_at(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                info!("{} changed, reloading", path.display());
+            }
+        }
+        reload_config(&state, &settings, &path);
+    }
+}
+
+// A config that fails to read or parse is logged and the running settings are kept
+fn reload_config(state: &AppState, settings: &watch::Sender<ReloadableConfig>, path: &Path) {
+    if let Err(e) = load_config_file(path) {
+        error!("Keeping the running config: {}", e);
+        return;
+    }
+    let mut reloaded = match ReloadableConfig::from_env() {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            error!("Keeping the running config: {}", e);
+            return;
+        }
+    };
+    let unmapped = reloaded.tracked.retain_mapped(&state.identity_map.lock().unwrap());
+    if !unmapped.is_empty() {
+        warn!("Not tracking symbols missing from the CMC mapping: {}", unmapped.join(","));
+    }
+
+    let changed = settings.send_if_modified(|current| {
+        if *current == reloaded {
+            return false;
+        }
+        *current = reloaded;
+        true
+    });
+    if changed {
+        info!("Applied reloaded update interval, hot tier and tracked series; other settings take effect on restart");
+    } else {
+        info!("Update interval, hot tier and tracked series unchanged; other settings take effect on restart");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modified_at() {
+        let path = std::env::temp_dir().join(format!("coin-crab-reload-{}.toml", std::process::id()));
+        assert_eq!(modified_at(&path), None);
+        std::fs::write(&path, "update_interval_seconds = 600\n").unwrap();
+        assert!(modified_at(&path).is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use crate::identity::IdentityMap;
+use crate::types::CryptoCurrency;
+
+pub const DEFAULT_SEARCH_RESULTS: usize = 20;
+pub const MAX_SEARCH_RESULTS: usize = 50;
+
+// How a coin matched the query; matches are ranked in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Symbol,
+    Name,
+    SymbolPrefix,
+    NamePrefix,
+    Contains,
+    // Within a typo or two of the symbol or name
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchMatch {
+    pub symbol: String,
+    pub name: String,
+    pub slug: Option<String>,
+    pub cmc_id: Option<u32>,
+    // Position in the current listings (1 = largest market cap); None outside them
+    pub rank: Option<usize>,
+    pub match_kind: MatchKind,
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// query, symbol and name are lowercase
+fn match_kind(query: &str, symbol: &str, name: &str, slug: Option<&str>) -> Option<MatchKind> {
+    if symbol == query {
+        return Some(MatchKind::Symbol);
+    }
+    if name == query || slug == Some(query) {
+        return Some(MatchKind::Name);
+    }
+    if symbol.starts_with(query) {
+        return Some(MatchKind::SymbolPrefix);
+    }
+    // "coin" finds "Bitcoin Cash" only by substring, "cash" finds it by word prefix
+    if name.starts_with(query) || name.split_whitespace().any(|word| word.starts_with(query)) {
+        return Some(MatchKind::NamePrefix);
+    }
+    if name.contains(query) || symbol.contains(query) {
+        return Some(MatchKind::Contains);
+    }
+    // Fuzzy matching on very short queries would match almost every symbol
+    let typos = match query.chars().count() {
+        0..=3 => return None,
+        4..=5 => 1,
+        _ => 2,
+    };
+    (edit_distance(query, symbol) <= typos || edit_distance(query, name) <= typos).then_some(MatchKind::Fuzzy)
+}
+
+// Coins of the identity map matching the query, best match first. Equal matches are ordered
+// by market cap rank when listed, then by CMC id (older coins first).
+pub fn search_coins(identities: &IdentityMap, listings: &[CryptoCurrency], query: &str, limit: usize) -> Vec<SearchMatch> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let ranks: HashMap<String, usize> = listings.iter().enumerate().map(|(index, coin)| (coin.symbol.to_uppercase(), index + 1)).collect();
+    let mut matches: Vec<SearchMatch> = identities
+        .identities()
+        .into_iter()
+        .filter_map(|identity| {
+            let match_kind = match_kind(&query, &identity.symbol.to_lowercase(), &identity.name.to_lowercase(), identity.slug.as_deref())?;
+            Some(SearchMatch {
+                symbol: identity.symbol.clone(),
+                name: identity.name.clone(),
+                slug: identity.slug.clone(),
+                cmc_id: identity.cmc_id,
+                rank: ranks.get(&identity.symbol).copied(),
+                match_kind,
+            })
+        })
+        .collect();
+    matches.sort_by_key(|found| (found.match_kind, found.rank.unwrap_or(usize::MAX), found.cmc_id.unwrap_or(u32::MAX)));
+    matches.truncate(limit);
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn listed(symbol: &str) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let mut identities = IdentityMap::new();
+        identities.record_cmc(1, "BTC", "Bitcoin", "bitcoin");
+        identities.record_cmc(1831, "BCH", "Bitcoin Cash", "bitcoin-cash");
+        identities.record_cmc(3717, "WBTC", "Wrapped Bitcoin", "wrapped-bitcoin");
+        identities.record_cmc(1027, "ETH", "Ethereum", "ethereum");
+        identities.record_cmc(5426, "SOL", "Solana", "solana");
+        let listings = vec![listed("BTC"), listed("ETH"), listed("WBTC"), listed("BCH")];
+        let symbols = |query: &str| search_coins(&identities, &listings, query, 10).into_iter().map(|found| found.symbol).collect::<Vec<_>>();
+
+        assert_eq!(symbols("btc"), vec!["BTC", "WBTC"]);
+        assert_eq!(symbols("bitcoin"), vec!["BTC", "WBTC", "BCH"]);
+        assert_eq!(symbols("cash"), vec!["BCH"]);
+        // Typos
+        assert_eq!(symbols("etherium"), vec!["ETH"]);
+        assert_eq!(symbols("solna"), vec!["SOL"]);
+        assert!(symbols("  ").is_empty());
+
+        let found = &search_coins(&identities, &listings, "Ethereum", 1)[0];
+        assert_eq!((found.match_kind, found.rank, found.cmc_id), (MatchKind::Name, Some(2), Some(1027)));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("solana", "solana"), 0);
+        assert_eq!(edit_distance("solna", "solana"), 1);
+        assert_eq!(edit_distance("etherium", "ethereum"), 1);
+        assert_eq!(edit_distance("", "btc"), 3);
+    }
+}
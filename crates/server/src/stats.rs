@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+use crate::types::{HistoricalDataResult, PriceStats};
+
+// Risk statistics over a price series for the analytics tab. Stored history holds one price
+// per interval (no separate high/low), so the true range is taken close-to-close.
+
+pub const ATR_PERIOD: usize = 14;
+const SECONDS_PER_YEAR: f64 = 365.0 * 86_400.0;
+
+// Timeframes fetched from the provider, with the days each one covers
+pub const STATS_TIMEFRAMES: [(&str, u32); 5] = [("24h", 1), ("7d", 7), ("30d", 30), ("90d", 90), ("365d", 365)];
+
+// Parse a "{days}d" window such as "90d"; None if malformed or longer than a year
+pub fn parse_window_days(window: &str) -> Option<u32> {
+    let days: u32 = window.strip_suffix('d')?.parse().ok()?;
+    (1..=365).contains(&days).then_some(days)
+}
+
+// Shortest provider timeframe whose history covers the window
+pub fn timeframe_for_window(days: u32) -> &'static str {
+    STATS_TIMEFRAMES
+        .iter()
+        .find(|(_, covered)| *covered >= days)
+        .map(|(timeframe, _)| *timeframe)
+        .unwrap_or("365d")
+}
+
+// Points from the shortest cached series covering the window, trimmed to the window and
+// sorted by time. Series are not mixed, since their resolutions differ.
+pub fn stored_window_points(
+    symbol: &str,
+    days: u32,
+    history: &HashMap<String, (HistoricalDataResult, SystemTime)>,
+    now_ts: f64,
+) -> Option<Vec<(f64, f64)>> {
+    let since = now_ts - f64::from(days) * 86_400.0;
+    STATS_TIMEFRAMES
+        .iter()
+        .filter(|(_, covered)| *covered >= days)
+        .find_map(|(timeframe, _)| {
+            history.iter().find_map(|(key, (result, _))| {
+                let (key_symbol, key_timeframe) = key.split_once(':')?;
+                (result.success && key_timeframe == *timeframe && key_symbol.eq_ignore_ascii_case(symbol))
+                    .then_some(result)
+            })
+        })
+        .map(|result| {
+            let mut points: Vec<(f64, f64)> = result.data
+                .iter()
+                .filter(|point| point.timestamp >= since)
+                .map(|point| (point.timestamp, point.price))
+                .collect();
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            points
+        })
+        .filter(|points| !points.is_empty())
+}
+
+// Statistics for (timestamp, price) points sorted by time. Ratios are annualized from the
+// series' median spacing with a zero risk-free rate; values needing more points stay None.
+pub fn compute_price_stats(symbol: &str, window: &str, points: &[(f64, f64)]) -> PriceStats {
+    let prices: Vec<f64> = points.iter().map(|&(_, price)| price).collect();
+    let returns: Vec<f64> = prices
+        .windows(2)
+        .filter(|pair| pair[0] > 0.0)
+        .map(|pair| pair[1] / pair[0] - 1.0)
+        .collect();
+    let periods_per_year = median_spacing(points).map(|spacing| SECONDS_PER_YEAR / spacing);
+
+    let (max_drawdown, max_drawdown_peak, max_drawdown_trough) = match max_drawdown(points) {
+        Some((drawdown, peak, trough)) => (Some(drawdown), Some(peak), Some(trough)),
+        None => (None, None, None),
+    };
+    let atr = average_true_range(&prices, ATR_PERIOD);
+    let last_price = prices.last().copied();
+    let mean_return = mean(&returns);
+    let std_dev = std_dev(&returns);
+    let downside_dev = downside_deviation(&returns);
+
+    let annualize = |ratio: f64| periods_per_year.map(|periods| ratio * periods.sqrt());
+    PriceStats {
+        symbol: symbol.to_uppercase(),
+        window: window.to_string(),
+        points: points.len(),
+        start: points.first().map(|&(timestamp, _)| timestamp),
+        end: points.last().map(|&(timestamp, _)| timestamp),
+        total_return: match (prices.first(), last_price) {
+            (Some(&first), Some(last)) if first > 0.0 && prices.len() > 1 => Some(last / first - 1.0),
+            _ => None,
+        },
+        annualized_volatility: std_dev.and_then(annualize),
+        atr,
+        atr_percent: atr.zip(last_price).filter(|(_, last)| *last > 0.0).map(|(atr, last)| atr / last * 100.0),
+        max_drawdown,
+        max_drawdown_peak,
+        max_drawdown_trough,
+        sharpe_ratio: mean_return
+            .zip(std_dev)
+            .filter(|(_, sd)| *sd > 0.0)
+            .and_then(|(mean, sd)| annualize(mean / sd)),
+        sortino_ratio: mean_return
+            .zip(downside_dev)
+            .filter(|(_, dd)| *dd > 0.0)
+            .and_then(|(mean, dd)| annualize(mean / dd)),
+    }
+}
+
+// Wilder-smoothed average of close-to-close ranges; needs period + 1 prices
+fn average_true_range(prices: &[f64], period: usize) -> Option<f64> {
+    let ranges: Vec<f64> = prices.windows(2).map(|pair| (pair[1] - pair[0]).abs()).collect();
+    if period == 0 || ranges.len() < period {
+        return None;
+    }
+    let seed = ranges[..period].iter().sum::<f64>() / period as f64;
+    Some(ranges[period..].iter().fold(seed, |atr, range| (atr * (period as f64 - 1.0) + range) / period as f64))
+}
+
+// Largest peak-to-trough decline as a fraction of the peak, with the peak and trough timestamps
+fn max_drawdown(points: &[(f64, f64)]) -> Option<(f64, f64, f64)> {
+    let &(first_ts, first_price) = points.first()?;
+    let mut peak = (first_ts, first_price);
+    let mut worst = (0.0, first_ts, first_ts);
+    for &(timestamp, price) in &points[1..] {
+        if price > peak.1 {
+            peak = (timestamp, price);
+        } else if peak.1 > 0.0 {
+            let drawdown = (peak.1 - price) / peak.1;
+            if drawdown > worst.0 {
+                worst = (drawdown, peak.0, timestamp);
+            }
+        }
+    }
+    Some(worst)
+}
+
+fn median_spacing(points: &[(f64, f64)]) -> Option<f64> {
+    let mut gaps: Vec<f64> = points
+        .windows(2)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .filter(|gap| *gap > 0.0)
+        .collect();
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort_by(|a, b| a.total_cmp(b));
+    Some(gaps[gaps.len() / 2])
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+// Sample standard deviation
+fn std_dev(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = mean(values)?;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    Some(variance.sqrt())
+}
+
+// Root mean square of negative returns (target return of zero)
+fn downside_deviation(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let squares = values.iter().map(|v| v.min(0.0).powi(2)).sum::<f64>();
+    Some((squares / values.len() as f64).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: f64 = 86_400.0;
+
+    fn daily(prices: &[f64]) -> Vec<(f64, f64)> {
+        prices.iter().enumerate().map(|(i, &price)| (i as f64 * DAY, price)).collect()
+    }
+
+    #[test]
+    fn test_window_parsing() {
+        assert_eq!(parse_window_days("90d"), Some(90));
+        assert_eq!(parse_window_days("0d"), None);
+        assert_eq!(parse_window_days("400d"), None);
+        assert_eq!(parse_window_days("3m"), None);
+        assert_eq!(timeframe_for_window(1), "24h");
+        assert_eq!(timeframe_for_window(14), "30d");
+        assert_eq!(timeframe_for_window(365), "365d");
+    }
+
+    #[test]
+    fn test_drawdown_and_returns() {
+        let stats = compute_price_stats("btc", "7d", &daily(&[100.0, 120.0, 90.0, 110.0, 60.0, 80.0]));
+        assert_eq!(stats.symbol, "BTC");
+        assert_eq!(stats.points, 6);
+        assert!((stats.max_drawdown.unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(stats.max_drawdown_peak, Some(DAY));
+        assert_eq!(stats.max_drawdown_trough, Some(4.0 * DAY));
+        assert!((stats.total_return.unwrap() + 0.2).abs() < 1e-9);
+        // Arithmetic mean return is positive here despite the loss; the ratios are still defined
+        assert!(stats.sharpe_ratio.unwrap() > 0.0);
+        assert!(stats.sortino_ratio.unwrap() > stats.sharpe_ratio.unwrap());
+        // Too few points for a 14-period ATR
+        assert!(stats.atr.is_none());
+    }
+
+    #[test]
+    fn test_atr_of_constant_moves() {
+        // Alternating +/-2 moves: every true range is 2
+        let prices: Vec<f64> = (0..30).map(|i| if i % 2 == 0 { 100.0 } else { 102.0 }).collect();
+        let stats = compute_price_stats("ETH", "30d", &daily(&prices));
+        assert!((stats.atr.unwrap() - 2.0).abs() < 1e-9);
+        assert!(stats.atr_percent.unwrap() > 1.9);
+        assert!(stats.annualized_volatility.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_stored_window_points_prefers_covering_series() {
+        let series = |prices: &[f64]| {
+            let result = HistoricalDataResult {
+                success: true,
+                data: daily(prices)
+                    .into_iter()
+                    .map(|(timestamp, price)| shared::HistoricalDataPoint { timestamp, price, volume: None })
+                    .collect(),
+                error: None,
+                symbol: Some("BTC".to_string()),
+                timeframe: None,
+                gaps: Vec::new(),
+                buckets: Vec::new(),
+                schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+            };
+            (result, SystemTime::now())
+        };
+        let mut history = HashMap::new();
+        history.insert("btc:7d".to_string(), series(&[1.0; 8]));
+        history.insert("BTC:365d".to_string(), series(&[2.0; 100]));
+        let now_ts = 99.0 * DAY;
+
+        // 7d series doesn't cover 30 days, so the yearly series is trimmed instead
+        let points = stored_window_points("BTC", 30, &history, now_ts).unwrap();
+        assert_eq!(points.len(), 31);
+        assert!(points.iter().all(|&(_, price)| price == 2.0));
+        assert_eq!(stored_window_points("BTC", 7, &history, 7.0 * DAY).unwrap()[0].1, 1.0);
+        assert!(stored_window_points("ETH", 7, &history, now_ts).is_none());
+    }
+
+    #[test]
+    fn test_single_point_has_no_ratios() {
+        let stats = compute_price_stats("SOL", "90d", &daily(&[100.0]));
+        assert_eq!(stats.max_drawdown, Some(0.0));
+        assert!(stats.total_return.is_none());
+        assert!(stats.sharpe_ratio.is_none());
+        assert!(stats.annualized_volatility.is_none());
+    }
+}
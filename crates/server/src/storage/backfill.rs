@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use actix_web::web;
+use tracing::{debug, info, warn};
+use crate::config::BackfillConfig;
+use crate::data::load_historical_data;
+use crate::providers::MarketDataProvider;
+use crate::types::AppState;
+use super::{is_fresh, StoredHistory};
+
+// Series of the wanted coins and timeframes that the store is missing or holds stale, missing
+// ones first and then the oldest, at most `limit` of them
+pub fn backfill_plan(
+    symbols: &[String],
+    timeframes: &[String],
+    stored: &HashMap<(String, String), SystemTime>,
+    now: SystemTime,
+    limit: usize,
+) -> Vec<(String, String)> {
+    let mut gaps: Vec<(Option<SystemTime>, String, String)> = Vec::new();
+    for symbol in symbols.iter().map(|symbol| symbol.to_uppercase()) {
+        for timeframe in timeframes {
+            let cached_at = stored.get(&(symbol.clone(), timeframe.clone())).copied();
+            if cached_at.is_some_and(|cached_at| is_fresh(timeframe, cached_at, now)) {
+                continue;
+            }
+            gaps.push((cached_at, symbol.clone(), timeframe.clone()));
+        }
+    }
+    // None (never stored) sorts before any time; the sort is stable, so config order breaks ties
+    gaps.sort_by_key(|(cached_at, _, _)| *cached_at);
+    gaps.into_iter().take(limit).map(|(_, symbol, timeframe)| (symbol, timeframe)).collect()
+}
+
+fn fetch_times(stored: Vec<StoredHistory>) -> HashMap<(String, String), SystemTime> {
+    stored
+        .into_iter()
+        .map(|history| {
+            let cached_at = history.cached_at();
+            ((history.symbol, history.timeframe), cached_at)
+        })
+        .collect()
+}
+
+// One pass over the configured coins; returns the number of series stored
+async fn backfill_once(state: &web::Data<AppState>, config: &BackfillConfig) -> usize {
+    let stored = match state.storage.load_all().await {
+        Ok(stored) => fetch_times(stored),
+        Err(e) => {
+            warn!("Skipping history backfill, the store could not be read: {}", e);
+            return 0;
+        }
+    };
+    let symbols = if config.symbols.is_empty() {
+        state.settings.borrow().tracked.refresh_symbols.clone()
+    } else {
+        config.symbols.clone()
+    };
+    let plan = backfill_plan(&symbols, &config.timeframes, &stored, SystemTime::now(), config.max_fetches_per_run);
+    if plan.is_empty() {
+        debug!("History store is complete for {} backfilled coins", symbols.len());
+        return 0;
+    }
+
+    info!("Backfilling {} historical series", plan.len());
+    let mut filled = 0;
+    for (symbol, timeframe) in plan {
+        // A series fresh in memory (e.g. restored from the cache snapshot) only needs storing
+        let cached = state.historical_cache.lock().unwrap()
+            .get(&format!("{}:{}", symbol, timeframe))
+            .filter(|(result, cached_at)| result.success && is_fresh(&timeframe, *cached_at, SystemTime::now()))
+            .cloned();
+        if let Some((result, cached_at)) = cached {
+            state.storage.save(&result, cached_at).await;
+            filled += 1;
+            continue;
+        }
+        // A rate-limited provider would only reject the rest; the next run picks them up
+        if let Some(retry_after) = state.market_data.retry_after() {
+            info!("Pausing history backfill for {}s while the provider is rate limited", retry_after.as_secs());
+            break;
+        }
+        let result = load_historical_data(state, &symbol, &timeframe).await;
+        if result.success {
+            filled += 1;
+        } else {
+            warn!("Failed to backfill {} {}: {:?}", symbol, timeframe, result.error);
+        }
+        tokio::time::sleep(Duration::from_millis(config.fetch_delay_ms)).await;
+    }
+    filled
+}
+
+// Keep the history store filled on the leader, once per backfill interval. The first run starts
+// after one interval, leaving the startup fetches the provider's attention.
+pub async fn backfill_history_periodically(state: web::Data<AppState>, config: BackfillConfig) {
+    if !config.is_enabled() || !state.storage.is_enabled() {
+        return;
+    }
+    info!("Backfilling up to {} historical series every {}s", config.max_fetches_per_run, config.interval_seconds);
+
+    let period = Duration::from_secs(config.interval_seconds);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        if !state.leader.is_leader() {
+            continue;
+        }
+        let filled = backfill_once(&state, &config).await;
+        if filled > 0 {
+            info!("Backfilled {} historical series into the store", filled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_plan_fills_missing_then_stalest() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let list = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        let key = |symbol: &str, timeframe: &str| (symbol.to_string(), timeframe.to_string());
+        let stored = HashMap::from([
+            (key("BTC", "24h"), now - Duration::from_secs(60)),
+            (key("BTC", "7d"), now - Duration::from_secs(86_400)),
+            (key("ETH", "24h"), now - Duration::from_secs(7_200)),
+        ]);
+        let symbols = list(&["btc", "eth"]);
+        let timeframes = list(&["24h", "7d"]);
+
+        // BTC 24h is fresh; ETH 7d was never stored; BTC 7d is older than ETH 24h
+        assert_eq!(
+            backfill_plan(&symbols, &timeframes, &stored, now, 10),
+            vec![key("ETH", "7d"), key("BTC", "7d"), key("ETH", "24h")]
+        );
+        assert_eq!(backfill_plan(&symbols, &timeframes, &stored, now, 1), vec![key("ETH", "7d")]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::web;
+use tracing::{info, warn};
+use crate::config::StorageConfig;
+use crate::gaps::find_gaps;
+use crate::identity::{CoinIdentity, IdentityMap};
+use crate::types::{AppState, HistoricalDataResult};
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint};
+
+mod backfill;
+mod postgres;
+mod sqlite;
+
+pub use backfill::backfill_history_periodically;
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+// One stored series: every point of a symbol/timeframe as last fetched from the provider
+#[derive(Debug, Clone)]
+pub struct StoredHistory {
+    pub symbol: String,
+    pub timeframe: String,
+    pub points: Vec<HistoricalDataPoint>,
+    // Unix seconds of the fetch
+    pub fetched_at: u64,
+}
+
+impl StoredHistory {
+    pub fn into_result(self) -> HistoricalDataResult {
+        HistoricalDataResult {
+            success: true,
+            gaps: find_gaps(&self.timeframe, &self.points),
+            buckets: Vec::new(),
+            schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+            data: self.points,
+            error: None,
+            symbol: Some(self.symbol),
+            timeframe: Some(self.timeframe),
+        }
+    }
+
+    pub fn cached_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.fetched_at)
+    }
+}
+
+// Durable home of historical series (keyed by symbol/timeframe) and the coin mapping. Calls
+// block on the database and run on the blocking thread pool.
+pub trait DataStore: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn load(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Option<StoredHistory>>;
+    fn load_all(&self) -> CoinCrabResult<Vec<StoredHistory>>;
+    // Replaces any points stored for the same symbol/timeframe
+    fn save(&self, history: &StoredHistory) -> CoinCrabResult<()>;
+    fn load_identities(&self) -> CoinCrabResult<Vec<CoinIdentity>>;
+    // Replaces the whole stored mapping
+    fn save_identities(&self, identities: &[CoinIdentity]) -> CoinCrabResult<()>;
+}
+
+// The configured store: Postgres when DATABASE_URL is set (shared by every replica), else
+// SQLite when a file path is set, else none
+pub async fn open_store(config: &StorageConfig) -> CoinCrabResult<Option<Arc<dyn DataStore>>> {
+    if let Some(url) = &config.database_url {
+        return Ok(Some(Arc::new(PostgresStore::connect(url).await?)));
+    }
+    match &config.sqlite_path {
+        Some(path) => Ok(Some(Arc::new(SqliteStore::open(path)?))),
+        None => Ok(None),
+    }
+}
+
+// Run a store call on the blocking pool
+async fn blocking<T: Send + 'static>(
+    store: Arc<dyn DataStore>,
+    call: impl FnOnce(&dyn DataStore) -> CoinCrabResult<T> + Send + 'static,
+) -> CoinCrabResult<T> {
+    tokio::task::spawn_blocking(move || call(store.as_ref()))
+        .await
+        .map_err(|e| CoinCrabError::Io(format!("Storage task failed: {}", e)))?
+}
+
+// A stored series is reused until the provider would have a newer sample for it, i.e. for one
+// sampling interval of the timeframe
+pub fn max_age(timeframe: &str) -> Duration {
+    let minutes = match timeframe {
+        "1h" => 5,
+        "24h" | "1d" => 60,
+        "7d" => 120,
+        "30d" => 360,
+        _ => 1440,
+    };
+    Duration::from_secs(minutes * 60)
+}
+
+pub fn is_fresh(timeframe: &str, cached_at: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(cached_at).map_or(true, |age| age < max_age(timeframe))
+}
+
+// Database in front of the market data provider. Without a store every call is a no-op and
+// reads always miss. Store errors are logged and treated as misses so a broken database never
+// blocks historical requests.
+pub struct Storage {
+    store: Option<Arc<dyn DataStore>>,
+}
+
+impl Storage {
+    pub fn new(store: Option<Arc<dyn DataStore>>) -> Self {
+        Storage { store }
+    }
+
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        Self::new(None)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.store.is_some()
+    }
+
+    // The stored series, if it is still fresh
+    pub async fn load(&self, symbol: &str, timeframe: &str) -> Option<StoredHistory> {
+        self.load_any_age(symbol, timeframe)
+            .await
+            .filter(|h| is_fresh(&h.timeframe, h.cached_at(), SystemTime::now()))
+    }
+
+    // The stored series however old it is
+    pub async fn load_any_age(&self, symbol: &str, timeframe: &str) -> Option<StoredHistory> {
+        let store = self.store.clone()?;
+        let (symbol, timeframe) = (symbol.to_string(), timeframe.to_string());
+        match blocking(store, move |store| store.load(&symbol, &timeframe)).await {
+            Ok(history) => history,
+            Err(e) => {
+                warn!("Failed to read stored history: {}", e);
+                None
+            }
+        }
+    }
+
+    // Every stored series however old, for a scan of the whole store
+    pub async fn load_all(&self) -> CoinCrabResult<Vec<StoredHistory>> {
+        match self.store.clone() {
+            Some(store) => blocking(store, |store| store.load_all()).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub async fn load_identities(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+        match self.store.clone() {
+            Some(store) => blocking(store, |store| store.load_identities()).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Store a successfully fetched series
+    pub async fn save(&self, result: &HistoricalDataResult, fetched_at: SystemTime) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        let (Some(symbol), Some(timeframe)) = (result.symbol.clone(), result.timeframe.clone()) else {
+            return;
+        };
+        if !result.success {
+            return;
+        }
+        let history = StoredHistory {
+            symbol,
+            timeframe,
+            points: result.data.clone(),
+            fetched_at: fetched_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        };
+        if let Err(e) = blocking(store, move |store| store.save(&history)).await {
+            warn!("Failed to store historical series: {}", e);
+        }
+    }
+
+    // Load every stored series into the in-memory historical cache at startup, so ranges,
+    // stats and prefetch hints have history before the first request
+    pub async fn warm(&self, state: &web::Data<AppState>) -> CoinCrabResult<()> {
+        let Some(store) = self.store.clone() else {
+            return Ok(());
+        };
+        let name = store.name();
+        let stored = blocking(store, |store| store.load_all()).await?;
+        let count = stored.len();
+        let mut cache = state.historical_cache.lock().unwrap();
+        for history in stored {
+            let key = format!("{}:{}", history.symbol, history.timeframe);
+            let cached_at = history.cached_at();
+            // A restored snapshot may already hold a newer copy
+            if cache.get(&key).is_some_and(|(_, existing)| *existing >= cached_at) {
+                continue;
+            }
+            cache.insert(key, (history.into_result(), cached_at));
+        }
+        info!("Warmed historical cache with {} series from {}", count, name);
+        Ok(())
+    }
+
+    // Install the stored coin mapping, so symbols resolve before (or without) a successful
+    // mapping fetch. Returns the number of coins restored.
+    pub async fn restore_identities(&self, state: &web::Data<AppState>) -> CoinCrabResult<usize> {
+        let Some(store) = self.store.clone() else {
+            return Ok(0);
+        };
+        let identities = blocking(store, |store| store.load_identities()).await?;
+        if identities.is_empty() {
+            return Ok(0);
+        }
+        let mapping: HashMap<String, u32> = identities
+            .iter()
+            .filter_map(|identity| Some((identity.symbol.clone(), identity.cmc_id?)))
+            .collect();
+        let identities = IdentityMap::from_identities(identities);
+        let count = identities.len();
+        *state.cmc_mapping.lock().unwrap() = mapping;
+        *state.identity_map.lock().unwrap() = identities;
+        Ok(count)
+    }
+
+    // Store the current coin mapping for the next start and for other replicas
+    pub async fn save_identities(&self, state: &web::Data<AppState>) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        let identities: Vec<CoinIdentity> = state.identity_map.lock().unwrap().identities().into_iter().cloned().collect();
+        if let Err(e) = blocking(store, move |store| store.save_identities(&identities)).await {
+            warn!("Failed to store coin mapping: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freshness_follows_timeframe_interval() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let ten_minutes_ago = now - Duration::from_secs(600);
+        assert!(!is_fresh("1h", ten_minutes_ago, now));
+        assert!(is_fresh("24h", ten_minutes_ago, now));
+        assert!(is_fresh("all", now - Duration::from_secs(23 * 3600), now));
+        // Clock skew (cached in the future) counts as fresh
+        assert!(is_fresh("1h", now + Duration::from_secs(60), now));
+    }
+}
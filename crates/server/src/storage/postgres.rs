@@ -0,0 +1,208 @@
+use std::sync::{Mutex, MutexGuard};
+use tracing::warn;
+use tokio::runtime::Handle;
+use tokio_postgres::{Client, NoTls};
+use crate::identity::CoinIdentity;
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint};
+use super::{DataStore, StoredHistory};
+
+// Applied in order and recorded in schema_migrations
+const MIGRATIONS: &[(i64, &str)] = &[
+    (1, include_str!("../../migrations/postgres/0001_historical_series.sql")),
+    (2, include_str!("../../migrations/postgres/0002_coin_identities.sql")),
+];
+
+// Key of the advisory lock held while migrating
+const MIGRATION_LOCK_ID: i64 = 0x636f_696e_6372_6162;
+
+// Shared PostgreSQL database, so several server replicas read and write the same historical
+// series and coin mapping. The connection runs on the server's runtime; the blocking store
+// calls wait on it from the blocking pool. A dropped connection is reopened on the next call.
+pub struct PostgresStore {
+    url: String,
+    runtime: Handle,
+    client: Mutex<Client>,
+}
+
+fn db_error(e: tokio_postgres::Error) -> CoinCrabError {
+    CoinCrabError::Io(format!("PostgreSQL error: {}", e))
+}
+
+async fn open(url: &str) -> CoinCrabResult<Client> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await.map_err(db_error)?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!("PostgreSQL connection closed: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+impl PostgresStore {
+    pub async fn connect(url: &str) -> CoinCrabResult<Self> {
+        let mut client = open(url).await?;
+        migrate(&mut client).await?;
+        Ok(PostgresStore { url: url.to_string(), runtime: Handle::current(), client: Mutex::new(client) })
+    }
+
+    fn client(&self) -> CoinCrabResult<MutexGuard<'_, Client>> {
+        let mut client = self.client.lock().unwrap();
+        if client.is_closed() {
+            *client = self.runtime.block_on(open(&self.url))?;
+        }
+        Ok(client)
+    }
+}
+
+async fn load_points(client: &Client, symbol: &str, timeframe: &str) -> Result<Vec<HistoricalDataPoint>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT timestamp, price, volume FROM historical_points WHERE symbol = $1 AND timeframe = $2 ORDER BY timestamp",
+        &[&symbol, &timeframe],
+    ).await?;
+    Ok(rows
+        .iter()
+        .map(|row| HistoricalDataPoint { timestamp: row.get(0), price: row.get(1), volume: row.get(2) })
+        .collect())
+}
+
+// Replicas starting together wait on the lock instead of racing the same migration
+async fn migrate(client: &mut Client) -> CoinCrabResult<()> {
+    let transaction = client.transaction().await.map_err(db_error)?;
+    transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await.map_err(db_error)?;
+    transaction
+        .batch_execute("CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY, applied_at TIMESTAMPTZ NOT NULL DEFAULT now())")
+        .await
+        .map_err(db_error)?;
+    let applied: i64 = transaction
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])
+        .await
+        .map_err(db_error)?
+        .get(0);
+    for (version, migration) in pending_migrations(applied) {
+        transaction.batch_execute(migration).await.map_err(db_error)?;
+        transaction.execute("INSERT INTO schema_migrations (version) VALUES ($1)", &[version]).await.map_err(db_error)?;
+    }
+    transaction.commit().await.map_err(db_error)
+}
+
+fn pending_migrations(applied: i64) -> impl Iterator<Item = &'static (i64, &'static str)> {
+    MIGRATIONS.iter().filter(move |(version, _)| *version > applied)
+}
+
+impl DataStore for PostgresStore {
+    fn name(&self) -> &'static str {
+        "PostgreSQL"
+    }
+
+    fn load(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Option<StoredHistory>> {
+        let client = self.client()?;
+        self.runtime.block_on(async {
+            let Some(row) = client.query_opt(
+                "SELECT fetched_at FROM historical_series WHERE symbol = $1 AND timeframe = $2",
+                &[&symbol, &timeframe],
+            ).await? else {
+                return Ok(None);
+            };
+            let fetched_at: i64 = row.get(0);
+            Ok(Some(StoredHistory {
+                symbol: symbol.to_string(),
+                timeframe: timeframe.to_string(),
+                points: load_points(&client, symbol, timeframe).await?,
+                fetched_at: fetched_at.max(0) as u64,
+            }))
+        }).map_err(db_error)
+    }
+
+    fn load_all(&self) -> CoinCrabResult<Vec<StoredHistory>> {
+        let client = self.client()?;
+        self.runtime.block_on(async {
+            let series = client.query("SELECT symbol, timeframe, fetched_at FROM historical_series", &[]).await?;
+            let mut stored = Vec::with_capacity(series.len());
+            for row in series {
+                let (symbol, timeframe, fetched_at): (String, String, i64) = (row.get(0), row.get(1), row.get(2));
+                let points = load_points(&client, &symbol, &timeframe).await?;
+                stored.push(StoredHistory { symbol, timeframe, points, fetched_at: fetched_at.max(0) as u64 });
+            }
+            Ok(stored)
+        }).map_err(db_error)
+    }
+
+    // Points go in as arrays in one statement rather than one round trip each
+    fn save(&self, history: &StoredHistory) -> CoinCrabResult<()> {
+        let timestamps: Vec<f64> = history.points.iter().map(|p| p.timestamp).collect();
+        let prices: Vec<f64> = history.points.iter().map(|p| p.price).collect();
+        let volumes: Vec<Option<f64>> = history.points.iter().map(|p| p.volume).collect();
+        let mut client = self.client()?;
+        self.runtime.block_on(async {
+            let transaction = client.transaction().await?;
+            transaction.execute(
+                "INSERT INTO historical_series (symbol, timeframe, fetched_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (symbol, timeframe) DO UPDATE SET fetched_at = EXCLUDED.fetched_at",
+                &[&history.symbol, &history.timeframe, &(history.fetched_at as i64)],
+            ).await?;
+            transaction.execute(
+                "DELETE FROM historical_points WHERE symbol = $1 AND timeframe = $2",
+                &[&history.symbol, &history.timeframe],
+            ).await?;
+            transaction.execute(
+                "INSERT INTO historical_points (symbol, timeframe, timestamp, price, volume)
+                 SELECT $1, $2, t.timestamp, t.price, t.volume
+                 FROM UNNEST($3::float8[], $4::float8[], $5::float8[]) AS t(timestamp, price, volume)
+                 ON CONFLICT DO NOTHING",
+                &[&history.symbol, &history.timeframe, &timestamps, &prices, &volumes],
+            ).await?;
+            transaction.commit().await
+        }).map_err(db_error)
+    }
+
+    fn load_identities(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+        let client = self.client()?;
+        let rows = self.runtime
+            .block_on(client.query("SELECT symbol, name, cmc_id, slug, coingecko_id FROM coin_identities", &[]))
+            .map_err(db_error)?;
+        Ok(rows
+            .iter()
+            .map(|row| CoinIdentity {
+                symbol: row.get(0),
+                name: row.get(1),
+                cmc_id: row.get::<_, Option<i64>>(2).and_then(|id| u32::try_from(id).ok()),
+                slug: row.get(3),
+                coingecko_id: row.get(4),
+            })
+            .collect())
+    }
+
+    fn save_identities(&self, identities: &[CoinIdentity]) -> CoinCrabResult<()> {
+        let symbols: Vec<&str> = identities.iter().map(|i| i.symbol.as_str()).collect();
+        let names: Vec<&str> = identities.iter().map(|i| i.name.as_str()).collect();
+        let cmc_ids: Vec<Option<i64>> = identities.iter().map(|i| i.cmc_id.map(i64::from)).collect();
+        let slugs: Vec<Option<&str>> = identities.iter().map(|i| i.slug.as_deref()).collect();
+        let coingecko_ids: Vec<Option<&str>> = identities.iter().map(|i| i.coingecko_id.as_deref()).collect();
+        let mut client = self.client()?;
+        self.runtime.block_on(async {
+            let transaction = client.transaction().await?;
+            transaction.execute("DELETE FROM coin_identities", &[]).await?;
+            transaction.execute(
+                "INSERT INTO coin_identities (symbol, name, cmc_id, slug, coingecko_id)
+                 SELECT * FROM UNNEST($1::text[], $2::text[], $3::int8[], $4::text[], $5::text[])
+                 ON CONFLICT DO NOTHING",
+                &[&symbols, &names, &cmc_ids, &slugs, &coingecko_ids],
+            ).await?;
+            transaction.commit().await
+        }).map_err(db_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_migrations() {
+        let versions: Vec<i64> = MIGRATIONS.iter().map(|(version, _)| *version).collect();
+        assert_eq!(versions, (1..=MIGRATIONS.len() as i64).collect::<Vec<_>>());
+        assert_eq!(pending_migrations(0).count(), MIGRATIONS.len());
+        assert_eq!(pending_migrations(1).map(|(version, _)| *version).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(pending_migrations(MIGRATIONS.len() as i64).count(), 0);
+    }
+}
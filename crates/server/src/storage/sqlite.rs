@@ -0,0 +1,238 @@
+use std::path::Path;
+use std::sync::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::identity::CoinIdentity;
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint};
+use super::{DataStore, StoredHistory};
+
+// Applied in order; the database's user_version records how many have run
+const MIGRATIONS: &[&str] = &[
+    include_str!("../../migrations/sqlite/0001_historical_series.sql"),
+    include_str!("../../migrations/sqlite/0002_coin_identities.sql"),
+];
+
+// Embedded SQLite database file, for single-instance deployments
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+fn db_error(e: rusqlite::Error) -> CoinCrabError {
+    CoinCrabError::Io(format!("SQLite error: {}", e))
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> CoinCrabResult<Self> {
+        let connection = Connection::open(path).map_err(db_error)?;
+        // WAL keeps reads from blocking behind a series being written
+        connection.pragma_update(None, "journal_mode", "WAL").map_err(db_error)?;
+        Self::with_connection(connection)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> CoinCrabResult<Self> {
+        Self::with_connection(Connection::open_in_memory().map_err(db_error)?)
+    }
+
+    fn with_connection(mut connection: Connection) -> CoinCrabResult<Self> {
+        migrate(&mut connection)?;
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+
+    fn load_points(connection: &Connection, symbol: &str, timeframe: &str) -> CoinCrabResult<Vec<HistoricalDataPoint>> {
+        let mut statement = connection
+            .prepare_cached("SELECT timestamp, price, volume FROM historical_points WHERE symbol = ?1 AND timeframe = ?2 ORDER BY timestamp")
+            .map_err(db_error)?;
+        let points = statement
+            .query_map(params![symbol, timeframe], |row| {
+                Ok(HistoricalDataPoint { timestamp: row.get(0)?, price: row.get(1)?, volume: row.get(2)? })
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        Ok(points)
+    }
+}
+
+fn migrate(connection: &mut Connection) -> CoinCrabResult<()> {
+    let applied: usize = connection
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(db_error)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let transaction = connection.transaction().map_err(db_error)?;
+        transaction.execute_batch(migration).map_err(db_error)?;
+        transaction.pragma_update(None, "user_version", index + 1).map_err(db_error)?;
+        transaction.commit().map_err(db_error)?;
+    }
+    Ok(())
+}
+
+impl DataStore for SqliteStore {
+    fn name(&self) -> &'static str {
+        "SQLite"
+    }
+
+    fn load(&self, symbol: &str, timeframe: &str) -> CoinCrabResult<Option<StoredHistory>> {
+        let connection = self.connection.lock().unwrap();
+        let fetched_at: Option<i64> = connection
+            .query_row(
+                "SELECT fetched_at FROM historical_series WHERE symbol = ?1 AND timeframe = ?2",
+                params![symbol, timeframe],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+        let Some(fetched_at) = fetched_at else {
+            return Ok(None);
+        };
+        Ok(Some(StoredHistory {
+            symbol: symbol.to_string(),
+            timeframe: timeframe.to_string(),
+            points: Self::load_points(&connection, symbol, timeframe)?,
+            fetched_at: fetched_at.max(0) as u64,
+        }))
+    }
+
+    fn load_all(&self) -> CoinCrabResult<Vec<StoredHistory>> {
+        let connection = self.connection.lock().unwrap();
+        let series: Vec<(String, String, i64)> = connection
+            .prepare("SELECT symbol, timeframe, fetched_at FROM historical_series")
+            .map_err(db_error)?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(db_error)?
+            .collect::<Result<_, _>>()
+            .map_err(db_error)?;
+        series
+            .into_iter()
+            .map(|(symbol, timeframe, fetched_at)| {
+                let points = Self::load_points(&connection, &symbol, &timeframe)?;
+                Ok(StoredHistory { symbol, timeframe, points, fetched_at: fetched_at.max(0) as u64 })
+            })
+            .collect()
+    }
+
+    fn save(&self, history: &StoredHistory) -> CoinCrabResult<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(db_error)?;
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO historical_series (symbol, timeframe, fetched_at) VALUES (?1, ?2, ?3)",
+                params![history.symbol, history.timeframe, history.fetched_at as i64],
+            )
+            .map_err(db_error)?;
+        transaction
+            .execute(
+                "DELETE FROM historical_points WHERE symbol = ?1 AND timeframe = ?2",
+                params![history.symbol, history.timeframe],
+            )
+            .map_err(db_error)?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT OR REPLACE INTO historical_points (symbol, timeframe, timestamp, price, volume) VALUES (?1, ?2, ?3, ?4, ?5)")
+                .map_err(db_error)?;
+            for point in &history.points {
+                insert
+                    .execute(params![history.symbol, history.timeframe, point.timestamp, point.price, point.volume])
+                    .map_err(db_error)?;
+            }
+        }
+        transaction.commit().map_err(db_error)
+    }
+
+    fn load_identities(&self) -> CoinCrabResult<Vec<CoinIdentity>> {
+        let connection = self.connection.lock().unwrap();
+        let identities = connection
+            .prepare("SELECT symbol, name, cmc_id, slug, coingecko_id FROM coin_identities")
+            .map_err(db_error)?
+            .query_map([], |row| {
+                Ok(CoinIdentity {
+                    symbol: row.get(0)?,
+                    name: row.get(1)?,
+                    cmc_id: row.get(2)?,
+                    slug: row.get(3)?,
+                    coingecko_id: row.get(4)?,
+                })
+            })
+            .map_err(db_error)?
+            .collect::<Result<_, _>>()
+            .map_err(db_error)?;
+        Ok(identities)
+    }
+
+    fn save_identities(&self, identities: &[CoinIdentity]) -> CoinCrabResult<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(db_error)?;
+        transaction.execute("DELETE FROM coin_identities", []).map_err(db_error)?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT OR REPLACE INTO coin_identities (symbol, name, cmc_id, slug, coingecko_id) VALUES (?1, ?2, ?3, ?4, ?5)")
+                .map_err(db_error)?;
+            for identity in identities {
+                insert
+                    .execute(params![identity.symbol, identity.name, identity.cmc_id, identity.slug, identity.coingecko_id])
+                    .map_err(db_error)?;
+            }
+        }
+        transaction.commit().map_err(db_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(symbol: &str, timeframe: &str, prices: &[f64], fetched_at: u64) -> StoredHistory {
+        StoredHistory {
+            symbol: symbol.to_string(),
+            timeframe: timeframe.to_string(),
+            points: prices
+                .iter()
+                .enumerate()
+                .map(|(i, price)| HistoricalDataPoint { timestamp: 1_704_067_200.0 + i as f64 * 3600.0, price: *price, volume: None })
+                .collect(),
+            fetched_at,
+        }
+    }
+
+    #[test]
+    fn test_sqlite_store_round_trip() {
+        let store = SqliteStore::in_memory().unwrap();
+        assert!(store.load("BTC", "24h").unwrap().is_none());
+
+        store.save(&history("BTC", "24h", &[1.0, 2.0, 3.0], 100)).unwrap();
+        store.save(&history("ETH", "7d", &[10.0], 200)).unwrap();
+        // Saving again replaces the series rather than merging points
+        store.save(&history("BTC", "24h", &[4.0, 5.0], 300)).unwrap();
+
+        let btc = store.load("BTC", "24h").unwrap().unwrap();
+        assert_eq!(btc.fetched_at, 300);
+        assert_eq!(btc.points.iter().map(|p| p.price).collect::<Vec<_>>(), vec![4.0, 5.0]);
+        assert!(store.load("BTC", "7d").unwrap().is_none());
+
+        let mut all = store.load_all().unwrap();
+        all.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        assert_eq!(all.len(), 2);
+        assert_eq!((all[1].symbol.as_str(), all[1].points.len()), ("ETH", 1));
+    }
+
+    #[test]
+    fn test_sqlite_identities_replace_previous_mapping() {
+        let store = SqliteStore::in_memory().unwrap();
+        let btc = CoinIdentity {
+            symbol: "BTC".to_string(),
+            name: "Bitcoin".to_string(),
+            cmc_id: Some(1),
+            slug: Some("bitcoin".to_string()),
+            coingecko_id: Some("bitcoin".to_string()),
+        };
+        let eth = CoinIdentity { symbol: "ETH".to_string(), name: "Ethereum".to_string(), cmc_id: Some(1027), slug: None, coingecko_id: None };
+        store.save_identities(&[btc.clone(), eth]).unwrap();
+        store.save_identities(std::slice::from_ref(&btc)).unwrap();
+        assert_eq!(store.load_identities().unwrap(), vec![btc]);
+
+        // Migrations already applied are skipped on reopen
+        let mut connection = store.connection.into_inner().unwrap();
+        migrate(&mut connection).unwrap();
+        let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+}
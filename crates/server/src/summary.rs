@@ -0,0 +1,180 @@
+use std::time::Duration;
+use actix_web::web;
+use chrono::{DateTime, NaiveTime, Utc};
+use tracing::{debug, error, info};
+use serde::Serialize;
+use crate::config::DailySummaryConfig;
+use crate::http_client::send_with_retry;
+use crate::mqtt::publish_daily_summary_to_mqtt;
+use crate::types::{AppState, CryptoCurrency};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Mover {
+    pub symbol: String,
+    pub name: String,
+    pub price: f64,
+    pub percent_change_24h: f64,
+}
+
+impl Mover {
+    fn from_coin(coin: &CryptoCurrency) -> Self {
+        Mover {
+            symbol: coin.symbol.clone(),
+            name: coin.name.clone(),
+            price: coin.quote.usd.price,
+            percent_change_24h: coin.quote.usd.percent_change_24h,
+        }
+    }
+}
+
+// Digest of the last 24 hours of the listings, sent once a day
+#[derive(Debug, Clone, Serialize)]
+pub struct DailySummary {
+    // UTC date the summary was generated on, YYYY-MM-DD
+    pub date: String,
+    pub generated_at: String,
+    pub coins: usize,
+    pub total_market_cap: f64,
+    pub market_cap_change_24h_percent: f64,
+    pub top_gainers: Vec<Mover>,
+    pub top_losers: Vec<Mover>,
+}
+
+pub fn compose_summary(listings: &[CryptoCurrency], movers: usize, now: DateTime<Utc>) -> DailySummary {
+    let total_market_cap: f64 = listings.iter().map(|coin| coin.quote.usd.market_cap).sum();
+    // Each coin's market cap a day ago, backed out of its 24h change
+    let previous_market_cap: f64 = listings
+        .iter()
+        .map(|coin| coin.quote.usd.market_cap / (1.0 + coin.quote.usd.percent_change_24h / 100.0))
+        .filter(|cap| cap.is_finite())
+        .sum();
+    let market_cap_change_24h_percent = if previous_market_cap > 0.0 {
+        (total_market_cap - previous_market_cap) / previous_market_cap * 100.0
+    } else {
+        0.0
+    };
+
+    let mut by_change: Vec<&CryptoCurrency> = listings.iter().filter(|coin| coin.quote.usd.percent_change_24h.is_finite()).collect();
+    by_change.sort_by(|a, b| b.quote.usd.percent_change_24h.total_cmp(&a.quote.usd.percent_change_24h));
+    let top_gainers: Vec<Mover> = by_change
+        .iter()
+        .take(movers)
+        .filter(|coin| coin.quote.usd.percent_change_24h > 0.0)
+        .map(|coin| Mover::from_coin(coin))
+        .collect();
+    let top_losers: Vec<Mover> = by_change
+        .iter()
+        .rev()
+        .take(movers)
+        .filter(|coin| coin.quote.usd.percent_change_24h < 0.0)
+        .map(|coin| Mover::from_coin(coin))
+        .collect();
+
+    DailySummary {
+        date: now.format("%Y-%m-%d").to_string(),
+        generated_at: now.to_rfc3339(),
+        coins: listings.len(),
+        total_market_cap,
+        market_cap_change_24h_percent,
+        top_gainers,
+        top_losers,
+    }
+}
+
+// Next occurrence of hour:minute UTC strictly after now
+pub fn next_run(now: DateTime<Utc>, hour: u32, minute: u32) -> DateTime<Utc> {
+    let time = NaiveTime::from_hms_opt(hour, minute, 0).unwrap_or(NaiveTime::MIN);
+    let today = now.date_naive().and_time(time).and_utc();
+    if today > now {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    }
+}
+
+// Only the leader sends the summary, from the listings it already has cached
+pub async fn run_daily_summary(state: web::Data<AppState>, config: DailySummaryConfig) {
+    let Some((hour, minute)) = config.time_utc else {
+        debug!("Daily summary disabled");
+        return;
+    };
+    info!("Daily summary scheduled for {:02}:{:02} UTC", hour, minute);
+
+    loop {
+        let now = Utc::now();
+        let wait = (next_run(now, hour, minute) - now).to_std().unwrap_or(Duration::ZERO);
+        tokio::time::sleep(wait).await;
+        if state.leader.is_leader() {
+            send_daily_summary(&state, &config).await;
+        }
+    }
+}
+
+async fn send_daily_summary(state: &web::Data<AppState>, config: &DailySummaryConfig) {
+    let Some(listings) = state.cache.lock().unwrap().clone() else {
+        error!("No listings cached - skipping daily summary");
+        return;
+    };
+    let summary = compose_summary(&listings, config.movers, Utc::now());
+    publish_daily_summary_to_mqtt(&state.mqtt_client, &summary).await;
+
+    if let Some(url) = &config.webhook_url {
+        match send_with_retry(state.client.post(url).json(&summary), &state.retry_policy).await {
+            Ok(response) if response.status().is_success() => info!("Delivered daily summary to webhook"),
+            Ok(response) => error!("Daily summary webhook returned {}", response.status()),
+            Err(e) => error!("Failed to deliver daily summary to webhook: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(symbol: &str, market_cap: f64, percent_change_24h: f64) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: 0.0,
+                    percent_change_24h,
+                    percent_change_7d: 0.0,
+                    market_cap,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_compose_summary() {
+        let listings = vec![coin("BTC", 200.0, 100.0), coin("ETH", 50.0, -50.0), coin("SOL", 30.0, 50.0), coin("USDT", 20.0, 0.0)];
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let summary = compose_summary(&listings, 2, now);
+
+        assert_eq!(summary.date, "2024-03-01");
+        assert_eq!(summary.coins, 4);
+        assert_eq!(summary.total_market_cap, 300.0);
+        // 100 + 100 + 20 + 20 a day ago
+        assert!((summary.market_cap_change_24h_percent - 25.0).abs() < 1e-9);
+        let symbols = |movers: &[Mover]| movers.iter().map(|m| m.symbol.clone()).collect::<Vec<_>>();
+        assert_eq!(symbols(&summary.top_gainers), vec!["BTC", "SOL"]);
+        // Unchanged coins are neither gainers nor losers
+        assert_eq!(symbols(&summary.top_losers), vec!["ETH"]);
+    }
+
+    #[test]
+    fn test_next_run() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        assert_eq!(next_run(now, 9, 30), Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap());
+        assert_eq!(next_run(now, 8, 0), Utc.with_ymd_and_hms(2024, 3, 2, 8, 0, 0).unwrap());
+        assert_eq!(next_run(now, 7, 0), Utc.with_ymd_and_hms(2024, 3, 2, 7, 0, 0).unwrap());
+    }
+}
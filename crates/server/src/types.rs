@@ -4,37 +4,278 @@ use rumqttc::AsyncClient;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
+use tokio::sync::watch;
+use crate::config::ReloadableConfig;
+use crate::http_client::RetryPolicy;
+use crate::coalesce::InFlight;
+use crate::gaps::GapRepairs;
+use crate::anomalies::PriceScreen;
+use crate::mqtt::capabilities::ClientCapabilityRegistry;
+use crate::identity::IdentityMap;
+use crate::leader::LeaderElection;
+use crate::logos::LogoCache;
+use crate::persistence::WriteBehindCache;
+use crate::storage::Storage;
+use crate::cluster_cache::ClusterCache;
+use crate::alerts::AlertEngine;
+use crate::watchlists::WatchlistRegistry;
+use crate::pinned::PinnedSymbols;
+use crate::fx::FxRates;
+use crate::portfolio::PortfolioStore;
+use crate::providers::MarketData;
 
 // Re-export shared types for convenience
 pub use shared::{CryptoCurrency, HistoricalDataResult};
+use shared::FxMetadata;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoinMarketCapResponse {
     pub data: Vec<CryptoCurrency>,
 }
 
+// quotes/latest response: coins keyed by CMC id
+#[derive(Debug, Clone, Deserialize)]
+pub struct CmcQuotesResponse {
+    pub data: HashMap<String, CryptoCurrency>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse {
     pub data: Vec<CryptoCurrency>,
     pub last_updated: String,
     pub cached: bool,
+    // Per-symbol price ranges, only present when requested with include_ranges=true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ranges: Option<HashMap<String, PriceRanges>>,
+    // Exchange rates behind the non-USD quotes, when FX_CURRENCIES is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fx: Option<FxMetadata>,
+    // Coins matching the filter before offset/limit, present when either is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<usize>,
+}
+
+// A device's watchlist and the current quotes of its coins
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchlistResponse {
+    pub device_id: String,
+    pub symbols: Vec<String>,
+    pub data: Vec<CryptoCurrency>,
+}
+
+#[derive(Deserialize)]
+pub struct PricesQuery {
+    pub include_ranges: Option<bool>,
+    // Comma-separated quote fields, overriding the server's PAYLOAD_FIELDS
+    pub fields: Option<String>,
+    // Comma-separated symbols to return instead of every listed coin
+    pub symbols: Option<String>,
+    // market_cap, change_24h or volume; listing order when absent
+    pub sort: Option<String>,
+    // asc or desc (default)
+    pub order: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct FieldsQuery {
+    pub fields: Option<String>,
+}
+
+// Optional logo size in pixels (defaults to 64)
+#[derive(Deserialize)]
+pub struct LogoQuery {
+    pub size: Option<u32>,
+}
+
+// Comma-separated symbols and an optional logo size in pixels (defaults to 64)
+#[derive(Deserialize)]
+pub struct LogoBundleQuery {
+    pub symbols: String,
+    pub size: Option<u32>,
+}
+
+// Rolling highs/lows computed from cached history; None when no history covers the window
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceRanges {
+    pub high_24h: Option<f64>,
+    pub low_24h: Option<f64>,
+    pub high_52w: Option<f64>,
+    pub low_52w: Option<f64>,
+}
+
+// Query for /api/indicators/{symbol}. indicator is a comma-separated list (sma, ema, rsi,
+// macd, bollinger), all of them when omitted; period overrides SMA/EMA/RSI/Bollinger periods.
+#[derive(Deserialize)]
+pub struct IndicatorsQuery {
+    pub timeframe: String,
+    pub indicator: Option<String>,
+    pub period: Option<usize>,
+}
+
+// Query for /api/stats/{symbol}; window is "{days}d", defaulting to 90d
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    pub window: Option<String>,
+}
+
+// Risk statistics over a window of stored history. Drawdown and returns are fractions
+// (0.25 = 25%); fields needing more data points than the window holds are None.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PriceStats {
+    pub symbol: String,
+    pub window: String,
+    pub points: usize,
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub total_return: Option<f64>,
+    pub annualized_volatility: Option<f64>,
+    // Average true range (14 periods) in USD and as a percentage of the last price
+    pub atr: Option<f64>,
+    pub atr_percent: Option<f64>,
+    pub max_drawdown: Option<f64>,
+    pub max_drawdown_peak: Option<f64>,
+    pub max_drawdown_trough: Option<f64>,
+    pub sharpe_ratio: Option<f64>,
+    pub sortino_ratio: Option<f64>,
+}
+
+// Coin detail payload: the listing entry with its price ranges alongside
+#[derive(Debug, Clone, Serialize)]
+pub struct CoinDetail {
+    #[serde(flatten)]
+    pub coin: CryptoCurrency,
+    #[serde(flatten)]
+    pub ranges: PriceRanges,
 }
 
 pub struct AppState {
     pub cache: Arc<Mutex<Option<Vec<CryptoCurrency>>>>,
     pub last_fetch: Arc<Mutex<SystemTime>>,
     pub client: Client,
-    pub api_key: String,
+    pub retry_policy: RetryPolicy,
+    // CMC with automatic failover to CoinGecko
+    pub market_data: Arc<MarketData>,
     pub mqtt_client: Arc<AsyncClient>,
     pub historical_cache: Arc<Mutex<HashMap<String, (HistoricalDataResult, SystemTime)>>>,
-    pub update_interval_seconds: u64,
+    // Settings the config reload can change while the server runs
+    pub settings: watch::Receiver<ReloadableConfig>,
     pub cmc_mapping: Arc<Mutex<HashMap<String, u32>>>,
-    pub logo_cache: Arc<Mutex<HashMap<String, (Vec<u8>, SystemTime)>>>,
+    // CMC id <-> CoinGecko id <-> symbol, so data from either provider is keyed the same way
+    pub identity_map: Arc<Mutex<IdentityMap>>,
+    pub logo_cache: Arc<Mutex<LogoCache>>,
+    pub leader: Arc<LeaderElection>,
+    // Cache changes are persisted in the background; writers only mark it dirty
+    pub persistence: Arc<WriteBehindCache>,
+    // Durable historical series, read before the market data provider is called
+    pub storage: Arc<Storage>,
+    // Listings, mapping and logos shared with the other instances through Redis
+    pub cluster_cache: Arc<ClusterCache>,
+    // Price alert rules, evaluated on every price update
+    pub alerts: Arc<AlertEngine>,
+    // Symbols each device follows, for its per-device price bundle
+    pub watchlists: Arc<WatchlistRegistry>,
+    // Exchange rates for the non-USD quotes (FX_CURRENCIES)
+    pub fx: Arc<FxRates>,
+    pub portfolios: Arc<PortfolioStore>,
+    // Coins kept in the feed outside the top 100
+    pub pinned: Arc<PinnedSymbols>,
+    // Provider fetches of historical series running now, keyed SYMBOL:timeframe, so clients
+    // asking for the same series at once share one call
+    pub historical_in_flight: Arc<InFlight<HistoricalDataResult>>,
+    // Series fetched with missing samples, waiting to be fetched again
+    pub gap_repairs: Arc<GapRepairs>,
+    // Checks each listings fetch for bad ticks before it is published
+    pub price_screen: Arc<PriceScreen>,
+    // Payload schema each client build can read, as announced on connect
+    pub client_capabilities: Arc<ClientCapabilityRegistry>,
+}
+
+impl AppState {
+    // Symbol that caches and topics are keyed by, for a symbol in any case, a CMC id or a
+    // CoinGecko id. Keys the identity map doesn't know are uppercased.
+    pub fn canonical_symbol(&self, key: &str) -> String {
+        self.identity_map
+            .lock()
+            .unwrap()
+            .resolve(key)
+            .map(|identity| identity.symbol.clone())
+            .unwrap_or_else(|| key.to_uppercase())
+    }
+
+    pub fn update_interval_seconds(&self) -> u64 {
+        self.settings.borrow().update_interval_seconds
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct MoversQuery {
+    // "1h" or "24h"
+    pub window: Option<String>,
+    pub limit: Option<usize>,
 }
 
 #[derive(Deserialize)]
 pub struct HistoricalQuery {
+    // Empty when the window is given by start/end instead
+    #[serde(default)]
+    pub timeframe: String,
+    // A custom window, as unix seconds or RFC 3339; end defaults to now and the interval to one
+    // that fits the window
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub interval: Option<String>,
+    pub page: Option<usize>,
+    pub page_size: Option<usize>,
+    // Downsample the series to at most this many points before paging
+    pub max_points: Option<usize>,
+}
+
+// One series of a POST /api/historical/batch body
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoricalBatchItem {
+    pub symbol: String,
+    pub timeframe: String,
+}
+
+// Results in request order, one per distinct series
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoricalBatchResponse {
+    pub results: Vec<HistoricalDataResult>,
+}
+
+// Query for incremental refreshes: only points with timestamp > after (unix seconds)
+#[derive(Deserialize)]
+pub struct HistoricalSinceQuery {
     pub timeframe: String,
+    pub after: f64,
+}
+
+// Default and upper bound for the number of points in one historical page; requests without
+// paging parameters are capped at the upper bound too
+pub const DEFAULT_HISTORICAL_PAGE_SIZE: usize = 500;
+pub const MAX_HISTORICAL_PAGE_SIZE: usize = 1000;
+
+// Historical result for a single page, with pagination metadata alongside the
+// regular HistoricalDataResult fields so existing clients can still parse it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoricalPage {
+    #[serde(flatten)]
+    pub result: HistoricalDataResult,
+    pub page: usize,
+    pub page_size: usize,
+    pub total_count: usize,
+    pub total_pages: usize,
+    // Served from stored history because the coin is no longer listed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delisted: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +286,55 @@ pub struct CmcCurrency {
     pub slug: String,
 }
 
+// Entry of CoinGecko's /coins/list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoinGeckoListEntry {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+}
+
+// Entry of CoinGecko's /coins/markets; fields CoinGecko has no value for come back as null
+#[derive(Debug, Clone, Deserialize)]
+pub struct CoinGeckoMarket {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+    pub image: Option<String>,
+    pub market_cap_rank: Option<u32>,
+    pub current_price: Option<f64>,
+    pub market_cap: Option<f64>,
+    pub total_volume: Option<f64>,
+    pub price_change_percentage_1h_in_currency: Option<f64>,
+    pub price_change_percentage_24h_in_currency: Option<f64>,
+    pub price_change_percentage_7d_in_currency: Option<f64>,
+    pub last_updated: Option<String>,
+}
+
+// CoinGecko's /coins/{id}/market_chart: [unix millis, value] pairs
+#[derive(Debug, Clone, Deserialize)]
+pub struct CoinGeckoMarketChart {
+    pub prices: Vec<[f64; 2]>,
+    #[serde(default)]
+    pub total_volumes: Vec<[f64; 2]>,
+}
+
+// Message of Binance's combined stream endpoint, carrying one aggregated trade
+#[derive(Debug, Clone, Deserialize)]
+pub struct BinanceStreamMessage {
+    pub data: BinanceAggTrade,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BinanceAggTrade {
+    // Trading pair, e.g. "BTCUSDT"
+    #[serde(rename = "s")]
+    pub pair: String,
+    // Decimal string, e.g. "64012.50000000"
+    #[serde(rename = "p")]
+    pub price: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CmcMappingResponse {
     pub status: CmcStatus,
@@ -110,6 +400,7 @@ mod tests {
                     volume_24h: 50000000000.0,
                     last_updated: "2024-01-01T00:00:00Z".to_string(),
                 },
+                converted: Default::default(),
             },
         };
 
@@ -117,12 +408,16 @@ mod tests {
             data: vec![crypto],
             last_updated: "2024-01-01T00:00:00Z".to_string(),
             cached: false,
+            ranges: None,
+            fx: None,
+            total_count: None,
         };
 
         let json = serde_json::to_string(&api_response).unwrap();
         assert!(json.contains("Bitcoin"));
         assert!(json.contains("50000"));
         assert!(json.contains("cached"));
+        assert!(!json.contains("ranges"));
     }
 
     #[test]
@@ -130,5 +425,45 @@ mod tests {
         let json = r#"{"timeframe": "24h"}"#;
         let query: HistoricalQuery = serde_json::from_str(json).unwrap();
         assert_eq!(query.timeframe, "24h");
+        assert!(query.page.is_none());
+        assert!(query.page_size.is_none());
+
+        let json = r#"{"timeframe": "7d", "page": 2, "page_size": 50}"#;
+        let query: HistoricalQuery = serde_json::from_str(json).unwrap();
+        assert_eq!(query.page, Some(2));
+        assert_eq!(query.page_size, Some(50));
+    }
+
+    #[test]
+    fn test_historical_page_serialization_is_flat() {
+        let page = HistoricalPage {
+            result: HistoricalDataResult {
+                success: true,
+                data: vec![],
+                error: None,
+                symbol: Some("BTC".to_string()),
+                timeframe: Some("24h".to_string()),
+                gaps: Vec::new(),
+                buckets: Vec::new(),
+                schema_version: shared::PAYLOAD_SCHEMA_VERSION,
+            },
+            page: 1,
+            page_size: 500,
+            total_count: 0,
+            total_pages: 0,
+            delisted: false,
+        };
+
+        let json = serde_json::to_value(&page).unwrap();
+        assert_eq!(json["success"], true);
+        assert_eq!(json["symbol"], "BTC");
+        assert_eq!(json["total_count"], 0);
+        assert!(json.get("result").is_none());
+        // Only flagged when true
+        assert!(json.get("delisted").is_none());
+
+        // The flattened payload still parses as a plain HistoricalDataResult
+        let plain: HistoricalDataResult = serde_json::from_value(json).unwrap();
+        assert!(plain.success);
     }
 }
\ No newline at end of file
@@ -0,0 +1,205 @@
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use crate::config::ServerConfig;
+use crate::data::fetch_historical_data_server;
+use crate::http_client::{build_http_client, RetryPolicy};
+use crate::identity::IdentityMap;
+use crate::providers::MarketData;
+use crate::storage::{open_store, Storage, StoredHistory};
+use shared::{CoinCrabError, CoinCrabResult, HistoricalDataPoint};
+
+// `coin-crab-server verify-history [--repair] [--max-jump RATIO]`: scan every series in the
+// history store for points no provider should have returned, print a report and, with
+// --repair, refetch the broken series and store the fresh copy if it is clean.
+
+// Consecutive points whose prices differ by more than this factor are flagged
+pub const DEFAULT_MAX_JUMP_RATIO: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyOptions {
+    pub repair: bool,
+    pub max_jump_ratio: f64,
+}
+
+// Arguments after the subcommand name
+pub fn parse_verify_args(args: &[String]) -> CoinCrabResult<VerifyOptions> {
+    let mut options = VerifyOptions { repair: false, max_jump_ratio: DEFAULT_MAX_JUMP_RATIO };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--repair" => options.repair = true,
+            "--max-jump" => {
+                options.max_jump_ratio = args
+                    .next()
+                    .and_then(|ratio| ratio.parse::<f64>().ok())
+                    .filter(|ratio| ratio.is_finite() && *ratio > 1.0)
+                    .ok_or_else(|| CoinCrabError::Config("--max-jump expects a ratio greater than 1".to_string()))?;
+            }
+            other => return Err(CoinCrabError::Config(format!("Unknown verify-history argument '{}'", other))),
+        }
+    }
+    Ok(options)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    // Earlier than the point before it
+    OutOfOrder { index: usize, timestamp: f64 },
+    Duplicate { index: usize, timestamp: f64 },
+    // Zero, negative or not a number
+    InvalidPrice { index: usize, price: f64 },
+    // Price moved by more than the allowed factor from the previous point
+    Jump { index: usize, from: f64, to: f64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct SeriesReport {
+    pub symbol: String,
+    pub timeframe: String,
+    pub points: usize,
+    pub issues: Vec<Issue>,
+    // Set when --repair replaced the series with a clean refetch
+    pub repaired: bool,
+}
+
+pub fn check_series(points: &[HistoricalDataPoint], max_jump_ratio: f64) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut last_valid_price: Option<f64> = None;
+    for (index, point) in points.iter().enumerate() {
+        if let Some(previous) = index.checked_sub(1).map(|i| points[i].timestamp) {
+            if point.timestamp < previous {
+                issues.push(Issue::OutOfOrder { index, timestamp: point.timestamp });
+            } else if point.timestamp == previous {
+                issues.push(Issue::Duplicate { index, timestamp: point.timestamp });
+            }
+        }
+        if !point.price.is_finite() || point.price <= 0.0 {
+            issues.push(Issue::InvalidPrice { index, price: point.price });
+            continue;
+        }
+        if let Some(from) = last_valid_price {
+            if point.price / from > max_jump_ratio || from / point.price > max_jump_ratio {
+                issues.push(Issue::Jump { index, from, to: point.price });
+            }
+        }
+        last_valid_price = Some(point.price);
+    }
+    issues
+}
+
+fn describe(issue: &Issue) -> String {
+    match issue {
+        Issue::OutOfOrder { index, timestamp } => format!("point {} is out of order (timestamp {})", index, timestamp),
+        Issue::Duplicate { index, timestamp } => format!("point {} repeats timestamp {}", index, timestamp),
+        Issue::InvalidPrice { index, price } => format!("point {} has invalid price {}", index, price),
+        Issue::Jump { index, from, to } => format!("point {} jumps from {} to {}", index, from, to),
+    }
+}
+
+// Runs the scan and prints the report; returns whether any series is still broken
+pub async fn run_verify_history(config: &ServerConfig, options: &VerifyOptions) -> CoinCrabResult<bool> {
+    let store = open_store(&config.storage)
+        .await?
+        .ok_or_else(|| CoinCrabError::Config("No history store configured (set DATABASE_URL or HISTORY_SQLITE_PATH)".to_string()))?;
+    println!("Verifying stored history in {}", store.name());
+    let storage = Storage::new(Some(store));
+    let stored = storage.load_all().await?;
+
+    let market_data = if options.repair {
+        Some(repair_provider(config, &storage).await?)
+    } else {
+        None
+    };
+
+    let mut reports = Vec::new();
+    for history in stored {
+        let mut report = SeriesReport {
+            issues: check_series(&history.points, options.max_jump_ratio),
+            symbol: history.symbol.clone(),
+            timeframe: history.timeframe.clone(),
+            points: history.points.len(),
+            repaired: false,
+        };
+        if let (Some(market_data), false) = (&market_data, report.issues.is_empty()) {
+            report.repaired = repair_series(market_data, &storage, &history, options.max_jump_ratio).await;
+        }
+        reports.push(report);
+    }
+
+    let broken: Vec<&SeriesReport> = reports.iter().filter(|report| !report.issues.is_empty()).collect();
+    for report in &broken {
+        let status = if report.repaired { " - repaired" } else { "" };
+        println!("{} {}: {} issues in {} points{}", report.symbol, report.timeframe, report.issues.len(), report.points, status);
+        for issue in &report.issues {
+            println!("  {}", describe(issue));
+        }
+    }
+    let unrepaired = broken.iter().filter(|report| !report.repaired).count();
+    println!("Checked {} series: {} with issues, {} repaired", reports.len(), broken.len(), broken.len() - unrepaired);
+    Ok(unrepaired > 0)
+}
+
+// The stored coin mapping lets CoinGecko resolve symbols without fetching the mapping first
+async fn repair_provider(config: &ServerConfig, storage: &Storage) -> CoinCrabResult<MarketData> {
+    let client = build_http_client(&config.http_client)?;
+    let identities = Arc::new(Mutex::new(IdentityMap::from_identities(storage.load_identities().await?)));
+    let retry_policy = RetryPolicy::from_config(&config.http_client);
+    Ok(MarketData::from_config(&config.market_data, &config.api_key, &client, retry_policy, identities))
+}
+
+// Only a refetch that passes the same checks replaces the stored series
+async fn repair_series(market_data: &MarketData, storage: &Storage, history: &StoredHistory, max_jump_ratio: f64) -> bool {
+    let result = fetch_historical_data_server(market_data, &history.symbol, &history.timeframe).await;
+    if !result.success {
+        println!("Refetching {} {} failed: {}", history.symbol, history.timeframe, result.error.unwrap_or_default());
+        return false;
+    }
+    let remaining = check_series(&result.data, max_jump_ratio);
+    if !remaining.is_empty() {
+        println!("Refetched {} {} still has {} issues - keeping the stored copy", history.symbol, history.timeframe, remaining.len());
+        return false;
+    }
+    storage.save(&result, SystemTime::now()).await;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: f64, price: f64) -> HistoricalDataPoint {
+        HistoricalDataPoint { timestamp, price, volume: None }
+    }
+
+    #[test]
+    fn test_check_series() {
+        let clean = [point(0.0, 100.0), point(60.0, 105.0), point(120.0, 98.0)];
+        assert!(check_series(&clean, DEFAULT_MAX_JUMP_RATIO).is_empty());
+
+        let broken = [
+            point(0.0, 100.0),
+            point(60.0, 101.0),
+            point(60.0, 102.0),
+            point(30.0, -1.0),
+            point(90.0, 5000.0),
+        ];
+        assert_eq!(check_series(&broken, DEFAULT_MAX_JUMP_RATIO), vec![
+            Issue::Duplicate { index: 2, timestamp: 60.0 },
+            Issue::OutOfOrder { index: 3, timestamp: 30.0 },
+            Issue::InvalidPrice { index: 3, price: -1.0 },
+            // Measured from the last valid price, skipping the invalid point
+            Issue::Jump { index: 4, from: 102.0, to: 5000.0 },
+        ]);
+        assert!(check_series(&broken[4..], DEFAULT_MAX_JUMP_RATIO).is_empty());
+    }
+
+    #[test]
+    fn test_parse_verify_args() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_verify_args(&[]).unwrap(), VerifyOptions { repair: false, max_jump_ratio: DEFAULT_MAX_JUMP_RATIO });
+        assert_eq!(parse_verify_args(&args(&["--repair", "--max-jump", "4"])).unwrap(), VerifyOptions { repair: true, max_jump_ratio: 4.0 });
+        assert!(parse_verify_args(&args(&["--max-jump", "0.5"])).is_err());
+        assert!(parse_verify_args(&args(&["--max-jump"])).is_err());
+        assert!(parse_verify_args(&args(&["--fix"])).is_err());
+    }
+}
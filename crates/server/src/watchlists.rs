@@ -0,0 +1,136 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use crate::types::CryptoCurrency;
+use shared::{CoinCrabError, CoinCrabResult};
+
+// Symbols each device follows, registered on crypto/watchlists/register/{device_id} or PUT
+// /api/watchlists/{device_id}. Devices get a bundle of just those coins on
+// crypto/watchlists/{device_id}/prices instead of all of crypto/prices/latest. Kept in memory;
+// devices register again when they connect.
+pub struct WatchlistRegistry {
+    lists: Mutex<HashMap<String, Vec<String>>>,
+    max_symbols: usize,
+}
+
+impl WatchlistRegistry {
+    pub fn new(max_symbols: usize) -> Self {
+        WatchlistRegistry { lists: Mutex::new(HashMap::new()), max_symbols }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lists.lock().unwrap().len()
+    }
+
+    // Replaces the device's watchlist; an empty list removes it. Symbols are uppercased and
+    // deduplicated, keeping the device's order. Returns the stored list.
+    pub fn register(&self, device_id: &str, symbols: Vec<String>) -> CoinCrabResult<Vec<String>> {
+        let mut seen = HashSet::new();
+        let symbols: Vec<String> = symbols
+            .into_iter()
+            .map(|symbol| symbol.trim().to_uppercase())
+            .filter(|symbol| !symbol.is_empty() && seen.insert(symbol.clone()))
+            .collect();
+        if symbols.len() > self.max_symbols {
+            return Err(CoinCrabError::Config(format!(
+                "{} symbols exceed the watchlist limit of {}", symbols.len(), self.max_symbols
+            )));
+        }
+        let mut lists = self.lists.lock().unwrap();
+        if symbols.is_empty() {
+            lists.remove(device_id);
+        } else {
+            lists.insert(device_id.to_string(), symbols.clone());
+        }
+        Ok(symbols)
+    }
+
+    pub fn symbols(&self, device_id: &str) -> Option<Vec<String>> {
+        self.lists.lock().unwrap().get(device_id).cloned()
+    }
+
+    // Watchlists containing any of the changed coins, so unaffected bundles aren't republished
+    pub fn affected_by(&self, changed: &[CryptoCurrency]) -> Vec<(String, Vec<String>)> {
+        let changed: HashSet<String> = changed.iter().map(|coin| coin.symbol.to_uppercase()).collect();
+        self.lists
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, symbols)| symbols.iter().any(|symbol| changed.contains(symbol)))
+            .map(|(device_id, symbols)| (device_id.clone(), symbols.clone()))
+            .collect()
+    }
+}
+
+// The watchlist's coins from the listings, in watchlist order; unknown symbols are skipped
+pub fn bundle(symbols: &[String], listings: &[CryptoCurrency]) -> Vec<CryptoCurrency> {
+    let by_symbol: HashMap<String, &CryptoCurrency> = listings.iter().map(|coin| (coin.symbol.to_uppercase(), coin)).collect();
+    symbols.iter().filter_map(|symbol| by_symbol.get(symbol).map(|coin| (*coin).clone())).collect()
+}
+
+// A JSON array of symbols, or a plain comma-separated list
+pub fn parse_watchlist_payload(payload: &[u8]) -> CoinCrabResult<Vec<String>> {
+    if let Ok(symbols) = serde_json::from_slice::<Vec<String>>(payload) {
+        return Ok(symbols);
+    }
+    let text = std::str::from_utf8(payload).map_err(|_| CoinCrabError::Parse("Watchlist is not UTF-8".to_string()))?;
+    if text.trim_start().starts_with('[') {
+        return Err(CoinCrabError::Parse(format!("Invalid watchlist: {}", text)));
+    }
+    Ok(text.split(',').map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{Quote, UsdQuote};
+
+    fn coin(symbol: &str) -> CryptoCurrency {
+        CryptoCurrency {
+            id: 1,
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            quote: Quote {
+                usd: UsdQuote {
+                    price: 1.0,
+                    percent_change_1h: 0.0,
+                    percent_change_24h: 0.0,
+                    percent_change_7d: 0.0,
+                    market_cap: 0.0,
+                    volume_24h: 0.0,
+                    last_updated: "2024-01-01T00:00:00Z".to_string(),
+                },
+                converted: Default::default(),
+            },
+        }
+    }
+
+    fn symbols(list: &[&str]) -> Vec<String> {
+        list.iter().map(|symbol| symbol.to_string()).collect()
+    }
+
+    #[test]
+    fn test_register_and_bundle() {
+        let registry = WatchlistRegistry::new(3);
+        assert_eq!(registry.register("phone", symbols(&["sol", "BTC", " btc ", ""])).unwrap(), symbols(&["SOL", "BTC"]));
+        assert!(registry.register("phone", symbols(&["A", "B", "C", "D"])).is_err());
+        assert_eq!(registry.symbols("phone"), Some(symbols(&["SOL", "BTC"])));
+
+        let listings = vec![coin("BTC"), coin("ETH"), coin("SOL")];
+        let coins: Vec<String> = bundle(&symbols(&["SOL", "BTC", "DOGE"]), &listings).into_iter().map(|coin| coin.symbol).collect();
+        assert_eq!(coins, symbols(&["SOL", "BTC"]));
+
+        registry.register("tablet", symbols(&["ETH"])).unwrap();
+        let affected = registry.affected_by(&[coin("BTC")]);
+        assert_eq!(affected, vec![("phone".to_string(), symbols(&["SOL", "BTC"]))]);
+
+        registry.register("phone", Vec::new()).unwrap();
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_watchlist_payload() {
+        assert_eq!(parse_watchlist_payload(br#"["BTC","ETH"]"#).unwrap(), symbols(&["BTC", "ETH"]));
+        assert_eq!(parse_watchlist_payload(b"BTC,ETH").unwrap(), symbols(&["BTC", "ETH"]));
+        assert!(parse_watchlist_payload(br#"["BTC", 1]"#).is_err());
+    }
+}
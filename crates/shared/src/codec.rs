@@ -0,0 +1,128 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::error::{CoinCrabError, CoinCrabResult};
+
+// Wire encodings for MQTT payloads. JSON stays on the plain topic for existing clients;
+// binary encodings are published alongside it on the same topic with a codec suffix
+// ("crypto/prices/latest/msgpack"), so a client picks one by what it subscribes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadCodec {
+    #[default]
+    Json,
+    MsgPack,
+}
+
+impl PayloadCodec {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "json" => Some(PayloadCodec::Json),
+            "msgpack" | "messagepack" => Some(PayloadCodec::MsgPack),
+            _ => None,
+        }
+    }
+
+    // Topic suffix selecting this encoding; JSON has none
+    pub fn suffix(&self) -> Option<&'static str> {
+        match self {
+            PayloadCodec::Json => None,
+            PayloadCodec::MsgPack => Some("msgpack"),
+        }
+    }
+
+    pub fn topic(&self, topic: &str) -> String {
+        match self.suffix() {
+            Some(suffix) => format!("{}/{}", topic, suffix),
+            None => topic.to_string(),
+        }
+    }
+
+    // Split an incoming topic into the base topic and the encoding of its payload
+    pub fn from_topic(topic: &str) -> (&str, PayloadCodec) {
+        match topic.strip_suffix("/msgpack") {
+            Some(base) => (base, PayloadCodec::MsgPack),
+            None => (topic, PayloadCodec::Json),
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> CoinCrabResult<Vec<u8>> {
+        match self {
+            PayloadCodec::Json => serde_json::to_vec(value).map_err(|e| CoinCrabError::Parse(e.to_string())),
+            // Field names are kept so either side can add optional fields, as with JSON
+            PayloadCodec::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| CoinCrabError::Parse(e.to_string())),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> CoinCrabResult<T> {
+        match self {
+            PayloadCodec::Json => serde_json::from_slice(bytes).map_err(|e| CoinCrabError::Parse(e.to_string())),
+            PayloadCodec::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| CoinCrabError::Parse(e.to_string())),
+        }
+    }
+
+    // Re-encode a payload as JSON text, so receivers can keep one JSON parsing path
+    pub fn to_json(&self, bytes: &[u8]) -> CoinCrabResult<String> {
+        match self {
+            PayloadCodec::Json => String::from_utf8(bytes.to_vec()).map_err(|e| CoinCrabError::Parse(e.to_string())),
+            PayloadCodec::MsgPack => {
+                let value: serde_json::Value = self.decode(bytes)?;
+                serde_json::to_string(&value).map_err(|e| CoinCrabError::Parse(e.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CryptoCurrency, PriceEnvelope, Quote, UsdQuote};
+
+    fn listing() -> Vec<CryptoCurrency> {
+        (0..100)
+            .map(|i| CryptoCurrency {
+                id: i,
+                name: format!("Coin {}", i),
+                symbol: format!("C{}", i),
+                quote: Quote {
+                    usd: UsdQuote {
+                        price: 1000.0 / (i + 1) as f64,
+                        percent_change_1h: 0.25,
+                        percent_change_24h: -1.5,
+                        percent_change_7d: 4.0,
+                        market_cap: 1.0e9,
+                        volume_24h: 2.5e8,
+                        last_updated: "2024-01-01T00:00:00.000Z".to_string(),
+                    },
+                    converted: Default::default(),
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_topic_suffixes() {
+        assert_eq!(PayloadCodec::Json.topic("crypto/prices/latest"), "crypto/prices/latest");
+        assert_eq!(PayloadCodec::MsgPack.topic("crypto/prices/latest"), "crypto/prices/latest/msgpack");
+        assert_eq!(PayloadCodec::from_topic("crypto/prices/delta/msgpack"), ("crypto/prices/delta", PayloadCodec::MsgPack));
+        assert_eq!(PayloadCodec::from_topic("crypto/prices/BTC"), ("crypto/prices/BTC", PayloadCodec::Json));
+        assert_eq!(PayloadCodec::parse(" MsgPack "), Some(PayloadCodec::MsgPack));
+        assert_eq!(PayloadCodec::parse("cbor"), None);
+    }
+
+    #[test]
+    fn test_msgpack_round_trip_is_smaller() {
+        let envelope = PriceEnvelope::new(listing(), 60).with_source(1_700_000_000, "CoinMarketCap");
+        let json = PayloadCodec::Json.encode(&envelope).unwrap();
+        let packed = PayloadCodec::MsgPack.encode(&envelope).unwrap();
+        assert!(packed.len() < json.len());
+
+        let decoded: PriceEnvelope<Vec<CryptoCurrency>> = PayloadCodec::MsgPack.decode(&packed).unwrap();
+        assert_eq!(decoded, envelope);
+
+        let transcoded = PayloadCodec::MsgPack.to_json(&packed).unwrap();
+        let reparsed: PriceEnvelope<Vec<CryptoCurrency>> = serde_json::from_str(&transcoded).unwrap();
+        // Same result as receiving the JSON variant (float parsing included)
+        let from_json: PriceEnvelope<Vec<CryptoCurrency>> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(reparsed, from_json);
+        assert!(PayloadCodec::MsgPack.to_json(b"\xc1").is_err());
+    }
+}
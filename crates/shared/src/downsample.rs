@@ -0,0 +1,153 @@
+use std::ops::Range;
+use crate::types::{HistoricalBucket, HistoricalDataPoint, HistoricalDataResult};
+
+// Fewest points a series can be downsampled to: the first, the last and one in between
+pub const MIN_SAMPLED_POINTS: usize = 3;
+
+fn needs_sampling(len: usize, threshold: usize) -> bool {
+    threshold >= MIN_SAMPLED_POINTS && len > threshold
+}
+
+// The `threshold` buckets a series of `len` points is reduced to: the first point, the rest
+// split evenly, and the last point
+fn buckets(len: usize, threshold: usize) -> Vec<Range<usize>> {
+    let bucket_size = (len - 2) as f64 / (threshold - 2) as f64;
+    let bucket_start = |bucket: usize| ((bucket as f64 * bucket_size) as usize + 1).min(len - 1);
+    let mut ranges = Vec::with_capacity(threshold);
+    ranges.push(0..1);
+    for bucket in 0..threshold - 2 {
+        let end = if bucket + 1 < threshold - 2 { bucket_start(bucket + 1) } else { len - 1 };
+        ranges.push(bucket_start(bucket)..end);
+    }
+    ranges.push(len - 1..len);
+    ranges
+}
+
+// Largest-Triangle-Three-Buckets (Steinarsson, 2013): reduces a series sorted by timestamp to
+// `threshold` points that keep its visual shape. The first and last points are always kept;
+// every bucket in between contributes the point forming the largest triangle with the point
+// kept from the previous bucket and the average of the next one. Series already within the
+// threshold, and thresholds below MIN_SAMPLED_POINTS, are returned unchanged.
+pub fn lttb(points: &[HistoricalDataPoint], threshold: usize) -> Vec<HistoricalDataPoint> {
+    if !needs_sampling(points.len(), threshold) {
+        return points.to_vec();
+    }
+
+    let ranges = buckets(points.len(), threshold);
+    let mut sampled = Vec::with_capacity(threshold);
+    sampled.push(points[0].clone());
+    let mut previous = &points[0];
+
+    for pair in ranges[1..].windows(2) {
+        let (start, end) = (pair[0].start, pair[0].end);
+        // The next bucket's average; the last bucket is the last point
+        let next = &points[pair[1].clone()];
+        let count = next.len() as f64;
+        let average_timestamp = next.iter().map(|point| point.timestamp).sum::<f64>() / count;
+        let average_price = next.iter().map(|point| point.price).sum::<f64>() / count;
+
+        let area = |point: &HistoricalDataPoint| {
+            ((previous.timestamp - average_timestamp) * (point.price - previous.price)
+                - (previous.timestamp - point.timestamp) * (average_price - previous.price))
+                .abs()
+        };
+        let chosen = points[start..end]
+            .iter()
+            .max_by(|a, b| area(a).total_cmp(&area(b)))
+            .unwrap_or(&points[start]);
+        sampled.push(chosen.clone());
+        previous = chosen;
+    }
+
+    sampled.push(points[points.len() - 1].clone());
+    sampled
+}
+
+// Open, high, low and close of the points each sampled point stands for, one bucket per point
+// lttb keeps. Empty when the series isn't downsampled.
+pub fn bucket_stats(points: &[HistoricalDataPoint], threshold: usize) -> Vec<HistoricalBucket> {
+    if !needs_sampling(points.len(), threshold) {
+        return Vec::new();
+    }
+    buckets(points.len(), threshold)
+        .into_iter()
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let bucket = &points[range];
+            let volumes: Vec<f64> = bucket.iter().filter_map(|point| point.volume).collect();
+            HistoricalBucket {
+                start: bucket[0].timestamp,
+                end: bucket[bucket.len() - 1].timestamp,
+                open: bucket[0].price,
+                high: bucket.iter().map(|point| point.price).fold(f64::MIN, f64::max),
+                low: bucket.iter().map(|point| point.price).fold(f64::MAX, f64::min),
+                close: bucket[bucket.len() - 1].price,
+                average_volume: (!volumes.is_empty()).then(|| volumes.iter().sum::<f64>() / volumes.len() as f64),
+                points: bucket.len(),
+            }
+        })
+        .collect()
+}
+
+// A series reduced to at most `threshold` points, with the statistics of each point's bucket
+pub fn downsample(result: HistoricalDataResult, threshold: usize) -> HistoricalDataResult {
+    HistoricalDataResult {
+        data: lttb(&result.data, threshold),
+        buckets: bucket_stats(&result.data, threshold),
+        ..result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(prices: &[f64]) -> Vec<HistoricalDataPoint> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| HistoricalDataPoint { timestamp: i as f64 * 3600.0, price, volume: None })
+            .collect()
+    }
+
+    #[test]
+    fn test_lttb_keeps_shape() {
+        // Flat with one spike: the spike survives, and the ends are kept
+        let mut prices = vec![10.0; 1000];
+        prices[437] = 50.0;
+        let sampled = lttb(&series(&prices), 20);
+        assert_eq!(sampled.len(), 20);
+        assert_eq!(sampled[0].timestamp, 0.0);
+        assert_eq!(sampled[19].timestamp, 999.0 * 3600.0);
+        assert!(sampled.iter().any(|point| point.price == 50.0));
+        assert!(sampled.windows(2).all(|pair| pair[0].timestamp < pair[1].timestamp));
+    }
+
+    #[test]
+    fn test_lttb_small_series_and_thresholds() {
+        let points = series(&[1.0, 5.0, 2.0, 4.0]);
+        assert_eq!(lttb(&points, 10).len(), 4);
+        assert_eq!(lttb(&points, 2).len(), 4);
+        let sampled = lttb(&points, 3);
+        assert_eq!(sampled.iter().map(|point| point.price).collect::<Vec<_>>(), vec![1.0, 5.0, 4.0]);
+        assert!(lttb(&[], 300).is_empty());
+    }
+
+    #[test]
+    fn test_bucket_stats_cover_the_series() {
+        let mut points = series(&[1.0, 5.0, 2.0, 4.0, 3.0, 6.0, 0.5, 2.5]);
+        points[2].volume = Some(10.0);
+        points[3].volume = Some(20.0);
+        let buckets = bucket_stats(&points, 4);
+        assert_eq!(buckets.len(), 4);
+        // Every point lands in exactly one bucket
+        assert_eq!(buckets.iter().map(|bucket| bucket.points).sum::<usize>(), points.len());
+        assert_eq!((buckets[0].open, buckets[0].close, buckets[0].points), (1.0, 1.0, 1));
+        assert_eq!((buckets[1].start, buckets[1].end), (3600.0, 3.0 * 3600.0));
+        assert_eq!((buckets[1].open, buckets[1].high, buckets[1].low, buckets[1].close), (5.0, 5.0, 2.0, 4.0));
+        assert_eq!(buckets[1].average_volume, Some(15.0));
+        assert_eq!(buckets[2].average_volume, None);
+        assert_eq!((buckets[2].high, buckets[2].low), (6.0, 0.5));
+        assert!(bucket_stats(&points, 10).is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+// Error type shared by the server and the iOS library, so callers can match on the
+// failure category instead of inspecting message strings
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoinCrabError {
+    // Broker setup, connection, subscribe or publish failures
+    Mqtt(String),
+    // Outbound HTTP client setup or provider request failures
+    Http(String),
+    // Payloads or files that could not be (de)serialized
+    Parse(String),
+    // Missing or invalid configuration
+    Config(String),
+    // An operation did not complete in time
+    Timeout(String),
+    // Local file system failures (caches, lease files)
+    Io(String),
+    // The server throttled this client; retry later
+    RateLimited(String),
+}
+
+pub type CoinCrabResult<T> = Result<T, CoinCrabError>;
+
+impl CoinCrabError {
+    // Stable category name, e.g. for JSON error payloads returned over FFI
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CoinCrabError::Mqtt(_) => "mqtt",
+            CoinCrabError::Http(_) => "http",
+            CoinCrabError::Parse(_) => "parse",
+            CoinCrabError::Config(_) => "config",
+            CoinCrabError::Timeout(_) => "timeout",
+            CoinCrabError::Io(_) => "io",
+            CoinCrabError::RateLimited(_) => "rate_limited",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            CoinCrabError::Mqtt(msg)
+            | CoinCrabError::Http(msg)
+            | CoinCrabError::Parse(msg)
+            | CoinCrabError::Config(msg)
+            | CoinCrabError::Timeout(msg)
+            | CoinCrabError::Io(msg)
+            | CoinCrabError::RateLimited(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for CoinCrabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let category = match self {
+            CoinCrabError::Mqtt(_) => "MQTT error",
+            CoinCrabError::Http(_) => "HTTP error",
+            CoinCrabError::Parse(_) => "Parse error",
+            CoinCrabError::Config(_) => "Configuration error",
+            CoinCrabError::Timeout(_) => "Timeout",
+            CoinCrabError::Io(_) => "I/O error",
+            CoinCrabError::RateLimited(_) => "Rate limited",
+        };
+        write!(f, "{}: {}", category, self.message())
+    }
+}
+
+impl std::error::Error for CoinCrabError {}
+
+impl From<serde_json::Error> for CoinCrabError {
+    fn from(e: serde_json::Error) -> Self {
+        CoinCrabError::Parse(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for CoinCrabError {
+    fn from(e: std::io::Error) -> Self {
+        CoinCrabError::Io(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_includes_category() {
+        let err = CoinCrabError::Mqtt("connection refused".to_string());
+        assert_eq!(err.to_string(), "MQTT error: connection refused");
+        assert_eq!(err.kind(), "mqtt");
+        assert_eq!(err.message(), "connection refused");
+    }
+
+    #[test]
+    fn test_conversions() {
+        let parse: CoinCrabError = serde_json::from_str::<u32>("nope").unwrap_err().into();
+        assert_eq!(parse.kind(), "parse");
+
+        let io: CoinCrabError = std::io::Error::new(std::io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(io, CoinCrabError::Io("missing".to_string()));
+    }
+
+    #[test]
+    fn test_is_std_error() {
+        fn boxed(err: CoinCrabError) -> Box<dyn std::error::Error> {
+            Box::new(err)
+        }
+        assert_eq!(boxed(CoinCrabError::Timeout("3s".to_string())).to_string(), "Timeout: 3s");
+    }
+}
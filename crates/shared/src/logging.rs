@@ -1,233 +1,273 @@
-use std::io::Write;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
-// Utility functions for logging across crates
+// Logging for all crates goes through tracing. Records from dependencies still using the log
+// crate (rumqttc, rumqttd, actix) are forwarded to the same subscriber by try_init.
 
-pub fn debug_log(message: &str) {
-    // Log to console for debugging
-    println!("{}", message);
+// LOG_LEVEL values; anything unrecognized logs at INFO
+pub fn level_filter(level: &str) -> LevelFilter {
+    match level.trim().to_uppercase().as_str() {
+        "OFF" => LevelFilter::OFF,
+        "ERROR" => LevelFilter::ERROR,
+        "WARN" => LevelFilter::WARN,
+        "INFO" => LevelFilter::INFO,
+        "DEBUG" => LevelFilter::DEBUG,
+        "TRACE" => LevelFilter::TRACE,
+        _ => LevelFilter::INFO,
+    }
+}
 
-    // Write to file for debugging
-    let log_path = if cfg!(target_os = "ios") {
-        // Try to get iOS Documents directory
+// The level applies to every target without a directive of its own. Directives use RUST_LOG
+// syntax ("rumqttd=off,coin_crab_server::data=debug"), comma separated; a later directive for
+// the same target replaces an earlier one and invalid ones are skipped.
+pub fn env_filter(level: &str, directives: &[&str]) -> EnvFilter {
+    // The level goes in as a directive of its own: a builder default only applies when no
+    // other directives are given
+    let mut all = vec![level_filter(level).to_string()];
+    all.extend(
+        directives
+            .iter()
+            .flat_map(|list| list.split(','))
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(str::to_string),
+    );
+    EnvFilter::builder().parse_lossy(all.join(","))
+}
+
+// Where the client's log file goes: the app's Documents directory on iOS, so it can be pulled
+// off the device, and the working directory elsewhere
+pub fn log_file_path() -> String {
+    if cfg!(target_os = "ios") {
         let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
         format!("{}/Documents/debug.log", home)
     } else {
         "debug.log".to_string()
-    };
+    }
+}
+
+// The client's log file is off unless enabled, and never grows past max_bytes: a full file
+// becomes debug.log.1, the previous debug.log.1 becomes debug.log.2 and so on, keeping
+// `rotations` old files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogFileConfig {
+    pub enabled: bool,
+    pub max_bytes: u64,
+    pub rotations: u32,
+}
 
-    if let Ok(mut file) = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)
-    {
-        let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-        writeln!(file, "[{}] {}", timestamp, message).ok();
+impl Default for LogFileConfig {
+    fn default() -> Self {
+        LogFileConfig {
+            enabled: false,
+            max_bytes: 1024 * 1024,
+            rotations: 3,
+        }
     }
 }
 
-pub fn init_logging() {
-    // Initialize logging based on environment variables
-    let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "INFO".to_string());
-
-    let level_filter = match log_level.to_uppercase().as_str() {
-        "OFF" => log::LevelFilter::Off,
-        "ERROR" => log::LevelFilter::Error,
-        "WARN" => log::LevelFilter::Warn,
-        "INFO" => log::LevelFilter::Info,
-        "DEBUG" => log::LevelFilter::Debug,
-        "TRACE" => log::LevelFilter::Trace,
-        _ => log::LevelFilter::Info,
-    };
-
-    env_logger::Builder::from_default_env()
-        .filter_level(level_filter)
-        .init();
-
-    // Print log path once at startup
-    if cfg!(target_os = "ios") {
-        let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-        let log_path = format!("{}/Documents/debug.log", home);
-        println!("Debug log: {}", log_path);
+struct RotatingLogFile {
+    path: PathBuf,
+    enabled: AtomicBool,
+    max_bytes: u64,
+    rotations: u32,
+    // Open file and its size; closed while disabled
+    file: Mutex<Option<(File, u64)>>,
+}
+
+impl RotatingLogFile {
+    fn new(path: PathBuf, config: &LogFileConfig) -> Self {
+        RotatingLogFile {
+            path,
+            enabled: AtomicBool::new(config.enabled),
+            max_bytes: config.max_bytes.max(1),
+            rotations: config.rotations,
+            file: Mutex::new(None),
+        }
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            *self.file.lock().unwrap() = None;
+        }
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.rotations == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        for index in (1..self.rotations).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    fn open(&self) -> io::Result<(File, u64)> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    // One formatted event; an event larger than max_bytes still gets a file of its own
+    fn write_event(&self, event: &[u8]) -> io::Result<()> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut state = self.file.lock().unwrap();
+        let (file, size) = match state.take() {
+            Some(open) => open,
+            None => self.open()?,
+        };
+        let (mut file, mut size) = if size > 0 && size + event.len() as u64 > self.max_bytes {
+            drop(file);
+            self.rotate()?;
+            self.open()?
+        } else {
+            (file, size)
+        };
+        file.write_all(event)?;
+        size += event.len() as u64;
+        *state = Some((file, size));
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env;
+struct LogFileWriter(&'static RotatingLogFile);
 
-    #[test]
-    fn test_debug_log_basic_functionality() {
-        // Test that debug_log doesn't panic and handles basic input
-        debug_log("Test message");
-        debug_log("Another test message with numbers: 123");
-        debug_log("");
-        
-        // If we reach here, debug_log works without panicking
-        assert!(true);
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_event(buf)?;
+        Ok(buf.len())
     }
 
-    #[test]
-    fn test_debug_log_special_characters() {
-        // Test that debug_log handles special characters
-        debug_log("Test with special chars: !@#$%^&*()");
-        debug_log("Unicode test: 🦀🔥💻");
-        debug_log("Multiline\ntest\nmessage");
-        
-        assert!(true);
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
+}
+
+static LOG_FILE: OnceLock<RotatingLogFile> = OnceLock::new();
+
+// Client logging: stdout plus the log file when enabled. Only the first call installs the
+// subscriber, so every client constructor can call it.
+pub fn init_logging(level: &str, directives: &str, log_file: &LogFileConfig) {
+    let log_path = log_file_path();
+    let file = LOG_FILE.get_or_init(|| RotatingLogFile::new(PathBuf::from(&log_path), log_file));
+    let file_layer = fmt::layer().with_ansi(false).with_writer(move || LogFileWriter(file));
+
+    let installed = tracing_subscriber::registry()
+        .with(env_filter(level, &[directives]))
+        .with(fmt::layer())
+        .with(file_layer)
+        .try_init()
+        .is_ok();
+
+    if installed {
+        tracing::info!(path = %log_path, level, directives, file_enabled = log_file.enabled, "Client logging initialized");
+    }
+}
+
+// Switch the log file on or off at runtime. Returns false before init_logging has run.
+pub fn set_file_logging(enabled: bool) -> bool {
+    match LOG_FILE.get() {
+        Some(file) => {
+            file.set_enabled(enabled);
+            tracing::info!(enabled, path = %file.path.display(), "File logging toggled");
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
     fn test_log_level_mapping() {
-        // Test the log level mapping logic from init_logging
         let test_cases = vec![
-            ("OFF", log::LevelFilter::Off),
-            ("ERROR", log::LevelFilter::Error),
-            ("WARN", log::LevelFilter::Warn),
-            ("INFO", log::LevelFilter::Info),
-            ("DEBUG", log::LevelFilter::Debug),
-            ("TRACE", log::LevelFilter::Trace),
-            ("invalid", log::LevelFilter::Info), // Default case
-            ("", log::LevelFilter::Info), // Empty string should default to Info
+            ("OFF", LevelFilter::OFF),
+            ("ERROR", LevelFilter::ERROR),
+            ("WARN", LevelFilter::WARN),
+            ("INFO", LevelFilter::INFO),
+            ("DEBUG", LevelFilter::DEBUG),
+            ("TRACE", LevelFilter::TRACE),
+            ("invalid", LevelFilter::INFO), // Default case
+            ("", LevelFilter::INFO), // Empty string should default to Info
         ];
 
         for (input, expected) in test_cases {
-            let level_filter = match input.to_uppercase().as_str() {
-                "OFF" => log::LevelFilter::Off,
-                "ERROR" => log::LevelFilter::Error,
-                "WARN" => log::LevelFilter::Warn,
-                "INFO" => log::LevelFilter::Info,
-                "DEBUG" => log::LevelFilter::Debug,
-                "TRACE" => log::LevelFilter::Trace,
-                _ => log::LevelFilter::Info,
-            };
-            
-            assert_eq!(level_filter, expected, "Failed for input: '{}'", input);
+            assert_eq!(level_filter(input), expected, "Failed for input: '{}'", input);
         }
-    }
 
-    #[test]
-    fn test_environment_variable_handling() {
-        // Test default behavior when LOG_LEVEL environment variable is not set
-        let default_level = std::env::var("NONEXISTENT_LOG_LEVEL").unwrap_or_else(|_| "INFO".to_string());
-        assert_eq!(default_level, "INFO");
-        
-        // Test case sensitivity handling
-        let test_levels = vec!["info", "INFO", "Info", "iNfO"];
-        for level in test_levels {
-            let normalized = level.to_uppercase();
-            assert_eq!(normalized, "INFO");
+        // Case insensitive
+        for level in ["info", "INFO", "Info", " iNfO "] {
+            assert_eq!(level_filter(level), LevelFilter::INFO);
         }
     }
 
     #[test]
-    fn test_log_file_path_logic() {
-        // Test the log file path logic for different platforms
-        let ios_path = {
-            let home = "/Users/testuser";
-            format!("{}/Documents/debug.log", home)
-        };
-        assert_eq!(ios_path, "/Users/testuser/Documents/debug.log");
-        
-        let desktop_path = "debug.log";
-        assert_eq!(desktop_path, "debug.log");
-        
-        // Test path with different home directories
-        let tmp_path = {
-            let home = "/tmp";
-            format!("{}/Documents/debug.log", home)
-        };
-        assert_eq!(tmp_path, "/tmp/Documents/debug.log");
-    }
+    fn test_env_filter_directives() {
+        let filter = env_filter("warn", &["rumqttd=off, rust_ios_lib::mqtt=debug", "", "rumqttd=info"]);
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::DEBUG));
+        let rendered = filter.to_string();
+        assert!(rendered.contains("rumqttd=info"), "{}", rendered);
+        assert!(!rendered.contains("rumqttd=off"), "{}", rendered);
+        assert!(rendered.contains("rust_ios_lib::mqtt=debug"), "{}", rendered);
+        assert!(rendered.contains("warn"), "{}", rendered);
 
-    #[test]
-    fn test_timestamp_format() {
-        // Test that we can create timestamps (indirectly testing chrono usage)
-        let now = chrono::Utc::now();
-        let timestamp = now.format("%Y-%m-%d %H:%M:%S UTC").to_string();
-        
-        // Basic format validation
-        assert!(timestamp.contains("-"));
-        assert!(timestamp.contains(":"));
-        assert!(timestamp.contains("UTC"));
-        assert!(timestamp.len() > 15); // Should be at least "YYYY-MM-DD HH:MM:SS UTC"
+        // Invalid directives don't take the rest down with them
+        let filter = env_filter("info", &["coin_crab_server=[", "rumqttd=off"]);
+        assert!(filter.to_string().contains("rumqttd=off"));
+        assert_eq!(env_filter("off", &[]).max_level_hint(), Some(LevelFilter::OFF));
     }
 
     #[test]
-    fn test_init_logging_doesnt_panic() {
-        // We can't really test init_logging fully without side effects,
-        // but we can at least verify it doesn't panic when called
-        // Note: This might initialize the global logger, but that's okay for tests
-        
-        // Save current LOG_LEVEL if it exists
-        let original_level = env::var("LOG_LEVEL").ok();
-        
-        // Test with different log levels
-        let test_levels = vec!["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
-        
-        for level in test_levels {
-            env::set_var("LOG_LEVEL", level);
-            
-            // init_logging might panic or fail if called multiple times
-            // but we can test the level parsing logic at least
-            let level_filter = match level.to_uppercase().as_str() {
-                "OFF" => log::LevelFilter::Off,
-                "ERROR" => log::LevelFilter::Error,
-                "WARN" => log::LevelFilter::Warn,
-                "INFO" => log::LevelFilter::Info,
-                "DEBUG" => log::LevelFilter::Debug,
-                "TRACE" => log::LevelFilter::Trace,
-                _ => log::LevelFilter::Info,
-            };
-            
-            // Just verify the mapping works - test that it's a valid level filter
-            match level {
-                "ERROR" => assert_eq!(level_filter, log::LevelFilter::Error),
-                "WARN" => assert_eq!(level_filter, log::LevelFilter::Warn),
-                "INFO" => assert_eq!(level_filter, log::LevelFilter::Info),
-                "DEBUG" => assert_eq!(level_filter, log::LevelFilter::Debug),
-                "TRACE" => assert_eq!(level_filter, log::LevelFilter::Trace),
-                "OFF" => assert_eq!(level_filter, log::LevelFilter::Off),
-                _ => assert_eq!(level_filter, log::LevelFilter::Info),
-            }
-        }
-        
-        // Restore original LOG_LEVEL
-        match original_level {
-            Some(level) => env::set_var("LOG_LEVEL", level),
-            None => env::remove_var("LOG_LEVEL"),
-        }
-        
-        assert!(true);
-    }
+    fn test_log_file_rotation() {
+        let dir = std::env::temp_dir().join(format!("coin-crab-log-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = RotatingLogFile::new(dir.join("debug.log"), &LogFileConfig { enabled: false, max_bytes: 10, rotations: 2 });
 
-    #[test] 
-    fn test_message_length_handling() {
-        // Test that debug_log handles messages of different lengths
-        debug_log("Short");
-        
-        let medium_message = "This is a medium length message that contains some more text to test handling.";
-        debug_log(medium_message);
-        
-        let long_message = "This is a very long message ".repeat(100);
-        debug_log(&long_message);
-        
-        assert!(true);
+        log.write_event(b"dropped\n").unwrap();
+        assert!(!log.path.exists());
+
+        log.set_enabled(true);
+        for event in ["one\n", "two\n", "three\n", "four\n", "five\n", "six\n"] {
+            log.write_event(event.as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(log.path.clone()), "six\n");
+        assert_eq!(read(log.rotated_path(1)), "four\nfive\n");
+        assert_eq!(read(log.rotated_path(2)), "three\n");
+        // "one" and "two" shared the first file, which fell off the end
+        assert!(!log.rotated_path(3).exists());
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_concurrent_logging() {
-        // Test that multiple debug_log calls work (basic concurrency test)
-        let messages = vec![
-            "Concurrent message 1",
-            "Concurrent message 2", 
-            "Concurrent message 3",
-        ];
-        
-        for message in messages {
-            debug_log(message);
+    fn test_log_file_path() {
+        let path = log_file_path();
+        if cfg!(target_os = "ios") {
+            assert!(path.ends_with("/Documents/debug.log"));
+        } else {
+            assert_eq!(path, "debug.log");
         }
-        
-        assert!(true);
     }
-}
\ No newline at end of file
+}
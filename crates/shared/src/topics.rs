@@ -0,0 +1,204 @@
+// MQTT topic namespacing shared by the server and the iOS client.
+// A prefix such as "staging" turns "crypto/prices/latest" into "staging/crypto/prices/latest",
+// so several deployments can share one broker without seeing each other's messages.
+
+// Normalise a configured prefix: surrounding whitespace and slashes are ignored
+pub fn normalize_topic_prefix(prefix: &str) -> String {
+    prefix.trim().trim_matches('/').to_string()
+}
+
+// Apply a prefix to a topic (or topic filter). An empty prefix leaves the topic unchanged.
+pub fn with_topic_prefix(prefix: &str, topic: &str) -> String {
+    let prefix = normalize_topic_prefix(prefix);
+    if prefix.is_empty() {
+        topic.to_string()
+    } else {
+        format!("{}/{}", prefix, topic)
+    }
+}
+
+// Remove a prefix from an incoming topic. Returns None if the topic is outside the namespace.
+pub fn strip_topic_prefix<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
+    let prefix = normalize_topic_prefix(prefix);
+    if prefix.is_empty() {
+        return Some(topic);
+    }
+    topic.strip_prefix(prefix.as_str())?.strip_prefix('/')
+}
+
+// Per-client topic, e.g. "crypto/clients/{client_id}/requests/historical". Requests carry the
+// client id in the topic because MQTT 3.1.1 publishes don't identify their sender.
+pub fn client_topic(client_id: &str, topic: &str) -> String {
+    format!("crypto/clients/{}/{}", client_id, topic)
+}
+
+// Split a per-client topic into the client id and the rest of the topic
+pub fn split_client_topic(topic: &str) -> Option<(&str, &str)> {
+    let (client_id, rest) = topic.strip_prefix("crypto/clients/")?.split_once('/')?;
+    (!client_id.is_empty()).then_some((client_id, rest))
+}
+
+// Where the server answers one correlated request, e.g. "crypto/responses/{client_id}/{request_id}"
+pub fn response_topic(client_id: &str, request_id: &str) -> String {
+    format!("crypto/responses/{}/{}", client_id, request_id)
+}
+
+// Split a response topic into the client id and request id
+pub fn split_response_topic(topic: &str) -> Option<(&str, &str)> {
+    let (client_id, request_id) = topic.strip_prefix("crypto/responses/")?.split_once('/')?;
+    (!client_id.is_empty() && !request_id.is_empty() && !request_id.contains('/')).then_some((client_id, request_id))
+}
+
+// Failures fetching one series, e.g. "crypto/errors/historical/BTC/24h"
+pub fn historical_error_topic(symbol: &str, timeframe: &str) -> String {
+    format!("crypto/errors/historical/{}/{}", symbol.to_uppercase(), timeframe)
+}
+
+// The series topic ("crypto/historical/{SYMBOL}/{timeframe}") an error topic is about
+pub fn split_historical_error_topic(topic: &str) -> Option<String> {
+    let (symbol, timeframe) = topic.strip_prefix("crypto/errors/historical/")?.split_once('/')?;
+    (!symbol.is_empty() && !timeframe.is_empty() && !timeframe.contains('/'))
+        .then(|| format!("crypto/historical/{}/{}", symbol, timeframe))
+}
+
+// Chunk topic for a series topic, e.g. "crypto/historical/BTC/365d/chunk/0/3"
+pub fn historical_chunk_topic(series_topic: &str, index: usize, total: usize) -> String {
+    format!("{}/chunk/{}/{}", series_topic, index, total)
+}
+
+// Split a chunk topic into the series topic, chunk index and chunk count. Out-of-range
+// indexes are rejected here so receivers can trust them.
+pub fn split_historical_chunk_topic(topic: &str) -> Option<(&str, usize, usize)> {
+    let (series_topic, position) = topic.split_once("/chunk/")?;
+    let (index, total) = position.split_once('/')?;
+    let (index, total): (usize, usize) = (index.parse().ok()?, total.parse().ok()?);
+    (series_topic.starts_with("crypto/historical/") && index < total).then_some((series_topic, index, total))
+}
+
+// Downsampled copy of a series for the client that asked for it, e.g.
+// "crypto/historical/BTC/365d/sampled/ios-1a2b"
+pub fn sampled_historical_topic(symbol: &str, timeframe: &str, client_id: &str) -> String {
+    format!("crypto/historical/{}/{}/sampled/{}", symbol.to_uppercase(), timeframe, client_id)
+}
+
+// The series topic of a downsampled copy
+pub fn split_sampled_historical_topic(topic: &str) -> Option<&str> {
+    let (series_topic, client_id) = topic.split_once("/sampled/")?;
+    (series_topic.starts_with("crypto/historical/") && !client_id.is_empty() && !client_id.contains('/')).then_some(series_topic)
+}
+
+// A custom window of history for the client that asked for it, e.g.
+// "crypto/historical/BTC/range/ios-1a2b/{request_id}"
+pub fn historical_range_topic(symbol: &str, client_id: &str, request_id: &str) -> String {
+    format!("crypto/historical/{}/range/{}/{}", symbol.to_uppercase(), client_id, request_id)
+}
+
+// Split a range topic into the client id and request id
+pub fn split_historical_range_topic(topic: &str) -> Option<(&str, &str)> {
+    let (symbol, rest) = topic.strip_prefix("crypto/historical/")?.split_once('/')?;
+    let (client_id, request_id) = rest.strip_prefix("range/")?.split_once('/')?;
+    (!symbol.is_empty() && !client_id.is_empty() && !request_id.is_empty() && !request_id.contains('/'))
+        .then_some((client_id, request_id))
+}
+
+// Retained logo image of one coin, e.g. "crypto/assets/logos/BTC"
+pub fn logo_topic(symbol: &str) -> String {
+    format!("crypto/assets/logos/{}", symbol.to_uppercase())
+}
+
+// Symbol of a logo topic
+pub fn split_logo_topic(topic: &str) -> Option<&str> {
+    topic
+        .strip_prefix("crypto/assets/logos/")
+        .filter(|symbol| !symbol.is_empty() && !symbol.contains('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_prefix_is_noop() {
+        assert_eq!(with_topic_prefix("", "crypto/prices/latest"), "crypto/prices/latest");
+        assert_eq!(strip_topic_prefix("", "crypto/prices/latest"), Some("crypto/prices/latest"));
+    }
+
+    #[test]
+    fn test_prefix_round_trip() {
+        let topic = with_topic_prefix(" /staging/ ", "crypto/historical/+/+");
+        assert_eq!(topic, "staging/crypto/historical/+/+");
+        assert_eq!(strip_topic_prefix("staging", "staging/crypto/prices/latest"), Some("crypto/prices/latest"));
+    }
+
+    #[test]
+    fn test_strip_rejects_other_namespaces() {
+        assert_eq!(strip_topic_prefix("staging", "crypto/prices/latest"), None);
+        assert_eq!(strip_topic_prefix("staging", "staging2/crypto/prices/latest"), None);
+        assert_eq!(strip_topic_prefix("prod", "staging/crypto/prices/latest"), None);
+    }
+
+    #[test]
+    fn test_client_topics() {
+        let topic = client_topic("ios-1a2b", "requests/historical");
+        assert_eq!(topic, "crypto/clients/ios-1a2b/requests/historical");
+        assert_eq!(split_client_topic(&topic), Some(("ios-1a2b", "requests/historical")));
+        assert_eq!(split_client_topic("crypto/clients//errors"), None);
+        assert_eq!(split_client_topic("crypto/requests/historical"), None);
+    }
+
+    #[test]
+    fn test_response_topics() {
+        let topic = response_topic("ios-1a2b", "42");
+        assert_eq!(topic, "crypto/responses/ios-1a2b/42");
+        assert_eq!(split_response_topic(&topic), Some(("ios-1a2b", "42")));
+        assert_eq!(split_response_topic("crypto/responses/ios-1a2b/"), None);
+        assert_eq!(split_response_topic("crypto/responses/ios-1a2b/42/extra"), None);
+        assert_eq!(split_response_topic("crypto/clients/ios-1a2b/errors"), None);
+    }
+
+    #[test]
+    fn test_historical_error_topics() {
+        let topic = historical_error_topic("btc", "24h");
+        assert_eq!(topic, "crypto/errors/historical/BTC/24h");
+        assert_eq!(split_historical_error_topic(&topic).as_deref(), Some("crypto/historical/BTC/24h"));
+        assert_eq!(split_historical_error_topic("crypto/errors/historical/BTC"), None);
+        assert_eq!(split_historical_error_topic("crypto/errors/historical/BTC/24h/x"), None);
+    }
+
+    #[test]
+    fn test_historical_chunk_topics() {
+        let topic = historical_chunk_topic("crypto/historical/BTC/365d", 2, 3);
+        assert_eq!(topic, "crypto/historical/BTC/365d/chunk/2/3");
+        assert_eq!(split_historical_chunk_topic(&topic), Some(("crypto/historical/BTC/365d", 2, 3)));
+        assert_eq!(split_historical_chunk_topic("crypto/historical/BTC/365d/chunk/3/3"), None);
+        assert_eq!(split_historical_chunk_topic("crypto/historical/BTC/365d/chunk/x/3"), None);
+        assert_eq!(split_historical_chunk_topic("crypto/historical/BTC/365d"), None);
+    }
+
+    #[test]
+    fn test_logo_topics() {
+        let topic = logo_topic("btc");
+        assert_eq!(topic, "crypto/assets/logos/BTC");
+        assert_eq!(split_logo_topic(&topic), Some("BTC"));
+        assert_eq!(split_logo_topic("crypto/assets/logos/"), None);
+        assert_eq!(split_logo_topic("crypto/assets/logos/BTC/128"), None);
+    }
+
+    #[test]
+    fn test_historical_range_topics() {
+        let topic = historical_range_topic("btc", "ios-1a2b", "r1");
+        assert_eq!(topic, "crypto/historical/BTC/range/ios-1a2b/r1");
+        assert_eq!(split_historical_range_topic(&topic), Some(("ios-1a2b", "r1")));
+        assert_eq!(split_historical_range_topic("crypto/historical/BTC/range/ios-1a2b/r1/chunk/0/2"), None);
+        assert_eq!(split_historical_range_topic("crypto/historical/BTC/24h"), None);
+    }
+
+    #[test]
+    fn test_sampled_historical_topics() {
+        let topic = sampled_historical_topic("btc", "365d", "ios-1a2b");
+        assert_eq!(topic, "crypto/historical/BTC/365d/sampled/ios-1a2b");
+        assert_eq!(split_sampled_historical_topic(&topic), Some("crypto/historical/BTC/365d"));
+        assert_eq!(split_sampled_historical_topic("crypto/historical/BTC/365d/sampled/"), None);
+        assert_eq!(split_sampled_historical_topic("crypto/prices/BTC/sampled/ios-1a2b"), None);
+    }
+}
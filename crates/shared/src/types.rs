@@ -1,8 +1,15 @@
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 // Shared data structures used by both server and iOS library
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Version of the published payloads (PriceEnvelope, HistoricalDataResult) and the types inside
+// them. Bumped when a field changes meaning or a required field is added or removed, so older
+// app builds can tell a payload they can't read from a corrupt one. Payloads from servers that
+// predate versioning deserialize as version 0.
+pub const PAYLOAD_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CryptoCurrency {
     pub id: i32,
     pub name: String,
@@ -10,20 +17,32 @@ pub struct CryptoCurrency {
     pub quote: Quote,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Quote {
     #[serde(rename = "USD")]
     pub usd: UsdQuote,
+    // Other fiat currencies keyed by ISO code ("EUR"), derived from USD with the server's
+    // exchange rates; see FxMetadata on the envelope
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub converted: BTreeMap<String, UsdQuote>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// The server can be configured to leave out any field but price (PAYLOAD_FIELDS or the
+// fields= query parameter); omitted fields parse as zero / empty.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UsdQuote {
     pub price: f64,
+    #[serde(default)]
     pub percent_change_1h: f64,
+    #[serde(default)]
     pub percent_change_24h: f64,
+    #[serde(default)]
     pub percent_change_7d: f64,
+    #[serde(default)]
     pub market_cap: f64,
+    #[serde(default)]
     pub volume_24h: f64,
+    #[serde(default)]
     pub last_updated: String,
 }
 
@@ -41,6 +60,264 @@ pub struct HistoricalDataResult {
     pub error: Option<String>,
     pub symbol: Option<String>,
     pub timeframe: Option<String>,
+    // Stretches of the series the provider returned no samples for (e.g. during an outage);
+    // the server re-fetches them and publishes the series again once they are filled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gaps: Vec<HistoricalGap>,
+    // For a downsampled series, the statistics of the samples behind each point, in the same order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<HistoricalBucket>,
+    #[serde(default)]
+    pub schema_version: u32,
+}
+
+// What one point of a downsampled series stands for: the price range of the samples merged
+// into it, so charts can draw ranges without the full series
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoricalBucket {
+    // Timestamps of the first and last sample in the bucket (Unix seconds)
+    pub start: f64,
+    pub end: f64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    // Mean of the samples that have a volume
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub average_volume: Option<f64>,
+    pub points: usize,
+}
+
+// Missing samples between two points of a series, as the timestamps of those points (Unix
+// seconds); every sample strictly between them is missing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HistoricalGap {
+    pub start: f64,
+    pub end: f64,
+}
+
+// Coins whose price moved beyond the server's threshold since they were last published,
+// sent on crypto/prices/delta between full crypto/prices/latest snapshots. seq increases by
+// one per delta within an epoch; the epoch changes whenever the server restarts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceDelta {
+    pub epoch: u64,
+    pub seq: u64,
+    pub coins: Vec<CryptoCurrency>,
+}
+
+// Wrapper for price publishes (crypto/prices/latest, crypto/prices/{SYMBOL} and
+// crypto/prices/delta). Retained messages outlive the server that sent them, so receivers
+// drop anything past expires_at rather than showing it as current.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceEnvelope<T> {
+    pub expires_at: i64, // Unix timestamp (seconds)
+    pub data: T,
+    // When the server fetched the listings and from which provider; snapshots only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<i64>, // Unix timestamp (seconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    // How the non-USD quotes were derived; absent when there are none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fx: Option<FxMetadata>,
+    #[serde(default)]
+    pub schema_version: u32,
+}
+
+// Exchange rates the server applied to the USD quotes: price, market cap and volume are
+// multiplied by the rate, percent changes are the USD ones
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FxMetadata {
+    pub base: String,
+    // Units of each currency per base unit
+    pub rates: BTreeMap<String, f64>,
+    // Where the rates came from and when they were fetched (Unix seconds)
+    pub provider: String,
+    pub fetched_at: i64,
+}
+
+impl<T> PriceEnvelope<T> {
+    pub fn new(data: T, ttl_seconds: u64) -> Self {
+        let ttl = i64::try_from(ttl_seconds).unwrap_or(i64::MAX);
+        PriceEnvelope {
+            expires_at: chrono::Utc::now().timestamp().saturating_add(ttl),
+            data,
+            fetched_at: None,
+            source: None,
+            fx: None,
+            schema_version: PAYLOAD_SCHEMA_VERSION,
+        }
+    }
+
+    pub fn with_source(self, fetched_at: i64, source: &str) -> Self {
+        PriceEnvelope { fetched_at: Some(fetched_at), source: Some(source.to_string()), ..self }
+    }
+
+    pub fn with_fx(self, fx: Option<FxMetadata>) -> Self {
+        PriceEnvelope { fx, ..self }
+    }
+
+    pub fn is_expired_at(&self, now: i64) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(chrono::Utc::now().timestamp())
+    }
+}
+
+// Sent to crypto/clients/{client_id}/errors when the server rejects one of that client's
+// requests, e.g. because it is sending them faster than its rate limit allows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestError {
+    // The request payload that was rejected, e.g. "BTC:24h"
+    pub request: String,
+    pub error: String,
+    // Seconds until the client may send requests again
+    pub retry_after_seconds: Option<u64>,
+}
+
+// JSON body of a historical request. The server answers with a RequestResponse carrying the
+// same request_id on reply_to, crypto/responses/{client_id}/{request_id}, once the series has
+// been published or the request has failed. Plain "SYMBOL:timeframe[:after]" text is still
+// accepted from older clients, which get no response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoricalRequestEnvelope {
+    pub request_id: String,
+    pub symbol: String,
+    // Empty for a custom window
+    #[serde(default)]
+    pub timeframe: String,
+    // Incremental refresh: only points newer than this unix timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<f64>,
+    // Downsample the series to at most this many points; it is then sent to the requester on
+    // crypto/historical/{SYMBOL}/{timeframe}/sampled instead of the retained series topic.
+    // Ignored for incremental refreshes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_points: Option<usize>,
+    // A custom window instead of the timeframe, as unix seconds or RFC 3339. end defaults to now
+    // and interval to one that fits the window. The series is sent to the requester on
+    // crypto/historical/{SYMBOL}/range/{client_id}/{request_id} and not cached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    pub reply_to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestResponse {
+    pub request_id: String,
+    // The series was published on its crypto/historical topic before this was sent
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Set when the request was rejected by the rate limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_seconds: Option<u64>,
+}
+
+impl RequestResponse {
+    pub fn succeeded(request_id: &str) -> Self {
+        RequestResponse { request_id: request_id.to_string(), success: true, error: None, retry_after_seconds: None }
+    }
+
+    pub fn failed(request_id: &str, error: &str) -> Self {
+        RequestResponse { request_id: request_id.to_string(), success: false, error: Some(error.to_string()), retry_after_seconds: None }
+    }
+}
+
+// Published on crypto/errors/historical/{SYMBOL}/{timeframe} when the server fails to fetch a
+// requested series, so every client waiting on it can say why instead of timing out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoricalFetchError {
+    pub symbol: String,
+    pub timeframe: String,
+    pub error: String,
+    // Set while the provider is rate limiting the server: seconds until it is asked again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_seconds: Option<u64>,
+    // Unix timestamp (seconds)
+    pub failed_at: i64,
+}
+
+impl HistoricalFetchError {
+    // For showing to the user, e.g. "CoinMarketCap circuit open for another 42s - try again in 42s"
+    pub fn describe(&self) -> String {
+        describe_failure(&self.error, self.retry_after_seconds)
+    }
+}
+
+pub fn describe_failure(error: &str, retry_after_seconds: Option<u64>) -> String {
+    match retry_after_seconds {
+        Some(seconds) => format!("{} - try again in {}s", error, seconds),
+        None => error.to_string(),
+    }
+}
+
+// Retained on crypto/server/status. The server publishes "online" when its publisher connects;
+// the broker publishes "offline" (the publisher's Last Will) when that connection is lost
+// without a clean disconnect, and the server sends it itself when shutting down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    Online,
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub status: ServerState,
+    pub version: String,
+    // When the server process started; Unix timestamp (seconds)
+    pub started_at: i64,
+}
+
+// One piece of a historical series too large for a single MQTT packet, published on
+// crypto/historical/{SYMBOL}/{timeframe}/chunk/{index}/{total}. The pieces' data, joined in
+// index order, is the series' HistoricalDataResult JSON. transfer_id keeps pieces of two
+// publishes of the same series from being mixed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoricalChunk {
+    pub transfer_id: u64,
+    pub data: String,
+}
+
+// Published by a client on crypto/clients/{client_id}/capabilities after each connect, so the
+// server knows which payload schema the builds it talks to can read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientCapabilities {
+    // Newest PAYLOAD_SCHEMA_VERSION the client understands
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+    // Encodings it subscribes to besides JSON, e.g. "msgpack"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<String>,
+}
+
+// Reads just the schema_version of a JSON payload, for payloads that no longer parse as the
+// expected type. None if the payload isn't a JSON object.
+pub fn payload_schema_version(payload: &str) -> Option<u32> {
+    #[derive(Deserialize)]
+    struct Versioned {
+        #[serde(default)]
+        schema_version: u32,
+    }
+    serde_json::from_str::<Versioned>(payload).ok().map(|versioned| versioned.schema_version)
+}
+
+// A symbol/timeframe series the server has freshly cached, published on
+// crypto/prefetch/popular so clients can warm their own cache while idle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefetchHint {
+    pub symbol: String,
+    pub timeframe: String,
+    pub cached_at: i64, // Unix timestamp (seconds)
 }
 
 #[cfg(test)]
@@ -66,10 +343,58 @@ mod tests {
             symbol: "BTC".to_string(),
             quote: Quote {
                 usd: create_test_usd_quote(),
+                converted: Default::default(),
             },
         }
     }
 
+    #[test]
+    fn test_price_envelope_expiry() {
+        let envelope = PriceEnvelope::new(vec![create_test_crypto()], 300);
+        assert!(!envelope.is_expired());
+        assert!(envelope.is_expired_at(envelope.expires_at));
+
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert!(json.contains("\"expires_at\""));
+        let parsed: PriceEnvelope<Vec<CryptoCurrency>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, envelope);
+
+        // Source metadata is left out unless set, so older receivers see the same payload
+        assert!(!json.contains("\"source\""));
+        let sourced = envelope.with_source(1_700_000_000, "CoinMarketCap");
+        let json = serde_json::to_string(&sourced).unwrap();
+        let parsed: PriceEnvelope<Vec<CryptoCurrency>> = serde_json::from_str(&json).unwrap();
+        assert_eq!((parsed.fetched_at, parsed.source.as_deref()), (Some(1_700_000_000), Some("CoinMarketCap")));
+    }
+
+    #[test]
+    fn test_payload_schema_version() {
+        let envelope = PriceEnvelope::new(Vec::<CryptoCurrency>::new(), 300);
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert_eq!(payload_schema_version(&json), Some(PAYLOAD_SCHEMA_VERSION));
+
+        // Payloads from servers that predate versioning still parse, as version 0
+        let legacy = r#"{"success":true,"data":[],"error":null,"symbol":"BTC","timeframe":"24h"}"#;
+        assert_eq!(serde_json::from_str::<HistoricalDataResult>(legacy).unwrap().schema_version, 0);
+        assert_eq!(payload_schema_version(legacy), Some(0));
+        assert_eq!(payload_schema_version("[1,2]"), None);
+    }
+
+    #[test]
+    fn test_converted_quotes_sit_beside_usd() {
+        let mut crypto = create_test_crypto();
+        let json = serde_json::to_value(&crypto).unwrap();
+        assert_eq!(json["quote"].as_object().unwrap().len(), 1);
+
+        crypto.quote.converted.insert("EUR".to_string(), UsdQuote { price: 46000.0, ..create_test_usd_quote() });
+        let json = serde_json::to_value(&crypto).unwrap();
+        assert_eq!(json["quote"]["EUR"]["price"], 46000.0);
+        let parsed: CryptoCurrency = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, crypto);
+        let msgpack = crate::PayloadCodec::MsgPack.encode(&crypto).unwrap();
+        assert_eq!(crate::PayloadCodec::MsgPack.decode::<CryptoCurrency>(&msgpack).unwrap(), crypto);
+    }
+
     #[test]
     fn test_crypto_currency_creation() {
         let crypto = create_test_crypto();
@@ -122,6 +447,17 @@ mod tests {
         assert_eq!(crypto.quote.usd.last_updated, "2024-01-01T00:00:00Z");
     }
 
+    #[test]
+    fn test_usd_quote_omitted_fields() {
+        let quote: UsdQuote = serde_json::from_str(r#"{"price":50000.0,"percent_change_24h":2.5}"#).unwrap();
+        assert_eq!(quote.price, 50000.0);
+        assert_eq!(quote.percent_change_24h, 2.5);
+        assert_eq!(quote.percent_change_7d, 0.0);
+        assert_eq!(quote.volume_24h, 0.0);
+        assert!(quote.last_updated.is_empty());
+        assert!(serde_json::from_str::<UsdQuote>(r#"{"market_cap":1.0}"#).is_err());
+    }
+
     #[test]
     fn test_usd_quote_all_fields() {
         let usd_quote = create_test_usd_quote();
@@ -175,6 +511,9 @@ mod tests {
             error: None,
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: PAYLOAD_SCHEMA_VERSION,
         };
         
         assert!(result.success);
@@ -192,6 +531,9 @@ mod tests {
             error: Some("API rate limit exceeded".to_string()),
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: PAYLOAD_SCHEMA_VERSION,
         };
         
         assert!(!result.success);
@@ -215,6 +557,9 @@ mod tests {
             error: None,
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: PAYLOAD_SCHEMA_VERSION,
         };
         
         let json = serde_json::to_string(&result).unwrap();
@@ -224,6 +569,36 @@ mod tests {
         assert!(json.contains("\"price\":45000.0"));
         assert!(json.contains("\"symbol\":\"BTC\""));
         assert!(json.contains("\"timeframe\":\"24h\""));
+        // Gaps are only sent when there are any, and older payloads without them still parse
+        assert!(!json.contains("gaps"));
+        let with_gaps = HistoricalDataResult { gaps: vec![HistoricalGap { start: 1704067200.0, end: 1704078000.0 }], ..result };
+        let parsed: HistoricalDataResult = serde_json::from_str(&serde_json::to_string(&with_gaps).unwrap()).unwrap();
+        assert_eq!(parsed.gaps, with_gaps.gaps);
+        let parsed: HistoricalDataResult = serde_json::from_str(&json).unwrap();
+        assert!(parsed.gaps.is_empty());
+    }
+
+    #[test]
+    fn test_prefetch_hint_round_trip() {
+        let hints = vec![
+            PrefetchHint {
+                symbol: "BTC".to_string(),
+                timeframe: "24h".to_string(),
+                cached_at: 1704067200,
+            },
+            PrefetchHint {
+                symbol: "ETH".to_string(),
+                timeframe: "7d".to_string(),
+                cached_at: 1704067100,
+            },
+        ];
+
+        let json = serde_json::to_string(&hints).unwrap();
+        assert!(json.contains("\"symbol\":\"BTC\""));
+        assert!(json.contains("\"cached_at\":1704067200"));
+
+        let parsed: Vec<PrefetchHint> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, hints);
     }
 
     #[test]
@@ -244,10 +619,12 @@ mod tests {
             error: None,
             symbol: Some("BTC".to_string()),
             timeframe: Some("24h".to_string()),
+            gaps: Vec::new(),
+            buckets: Vec::new(),
+            schema_version: PAYLOAD_SCHEMA_VERSION,
         };
         let _result_clone = result.clone();
         
-        assert!(true); // If we reach here, all types are cloneable
     }
 
     #[test]
@@ -273,7 +650,7 @@ mod tests {
             id: 1,
             name: "Bitcoin".to_string(),
             symbol: "BTC".to_string(),
-            quote: Quote { usd: create_test_usd_quote() },
+            quote: Quote { usd: create_test_usd_quote(), converted: Default::default() },
         };
         
         let eth = CryptoCurrency {
@@ -290,13 +667,50 @@ mod tests {
                     volume_24h: 25000000000.0,
                     last_updated: "2024-01-01T00:00:00Z".to_string(),
                 },
+                converted: Default::default(),
             },
         };
         
-        let currencies = vec![btc, eth];
+        let currencies = [btc, eth];
         assert_eq!(currencies.len(), 2);
         assert_eq!(currencies[0].symbol, "BTC");
         assert_eq!(currencies[1].symbol, "ETH");
         assert_eq!(currencies[1].quote.usd.price, 3000.0);
     }
+
+    #[test]
+    fn test_historical_request_envelope() {
+        let envelope: HistoricalRequestEnvelope = serde_json::from_str(
+            r#"{"request_id":"7f3c","symbol":"BTC","timeframe":"24h","reply_to":"crypto/responses/ios-1/7f3c"}"#
+        ).unwrap();
+        assert_eq!(envelope.after, None);
+        assert!(!serde_json::to_string(&envelope).unwrap().contains("after"));
+
+        // A custom window has no timeframe
+        let envelope: HistoricalRequestEnvelope = serde_json::from_str(
+            r#"{"request_id":"7f3d","symbol":"BTC","start":"2024-03-01T00:00:00Z","end":"1711929600","reply_to":"crypto/responses/ios-1/7f3d"}"#
+        ).unwrap();
+        assert!(envelope.timeframe.is_empty());
+        assert_eq!(envelope.start.as_deref(), Some("2024-03-01T00:00:00Z"));
+        assert_eq!(envelope.interval, None);
+
+        let json = serde_json::to_value(RequestResponse::failed("7f3c", "unknown symbol")).unwrap();
+        assert_eq!(json["success"], false);
+        assert_eq!(json["error"], "unknown symbol");
+        assert!(json.get("retry_after_seconds").is_none());
+    }
+
+    #[test]
+    fn test_historical_fetch_error_describe() {
+        let mut error = HistoricalFetchError {
+            symbol: "BTC".to_string(),
+            timeframe: "24h".to_string(),
+            error: "CoinMarketCap rate limit reached".to_string(),
+            retry_after_seconds: Some(42),
+            failed_at: 1_700_000_000,
+        };
+        assert_eq!(error.describe(), "CoinMarketCap rate limit reached - try again in 42s");
+        error.retry_after_seconds = None;
+        assert_eq!(error.describe(), "CoinMarketCap rate limit reached");
+    }
 }
\ No newline at end of file
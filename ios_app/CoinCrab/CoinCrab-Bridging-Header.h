@@ -1,5 +1,2 @@
-#import "rust_ios_lib.h"
-
-// Explicit function declarations to ensure linking works
-typedef void (*PriceUpdateCallback)(const void* context);
-void register_price_update_callback(PriceUpdateCallback callback);
\ No newline at end of file
+// Import the header maintained next to the Rust FFI so Swift always sees its current declarations
+#import "../../crates/ios_lib/rust_ios_lib.h"
@@ -38,6 +38,25 @@ struct UsdQuote: Codable {
         case volume_24h
         case last_updated
     }
+    
+    // The server may be configured to leave out everything but price
+    init(from decoder: Decoder) throws {
+        let container = try decoder.container(keyedBy: CodingKeys.self)
+        price = try container.decode(Double.self, forKey: .price)
+        percent_change_1h = try container.decodeIfPresent(Double.self, forKey: .percent_change_1h) ?? 0
+        percent_change_24h = try container.decodeIfPresent(Double.self, forKey: .percent_change_24h) ?? 0
+        percent_change_7d = try container.decodeIfPresent(Double.self, forKey: .percent_change_7d) ?? 0
+        market_cap = try container.decodeIfPresent(Double.self, forKey: .market_cap) ?? 0
+        volume_24h = try container.decodeIfPresent(Double.self, forKey: .volume_24h) ?? 0
+        last_updated = try container.decodeIfPresent(String.self, forKey: .last_updated) ?? ""
+    }
+}
+
+// Changes delivered by the Rust price update callback
+struct PriceUpdate: Codable {
+    let updated: [CryptoCurrency]
+    let removed: [String]
+    let total: Int
 }
 
 struct CryptoClientResult: Codable {
@@ -45,7 +64,14 @@ struct CryptoClientResult: Codable {
     let data: [CryptoCurrency]?
     let error: String?
     let last_updated: String?
+    // Set when the prices come from the on-device copy because the server was unreachable
     let cached: Bool
+    let age_seconds: Int?
+    // Server fetch time (RFC 3339) and market data provider of the prices
+    let data_timestamp: String?
+    let source: String?
+    let coin_count: Int?
+    let schema_version: Int?
 }
 
 // MARK: - Simplified Data Manager using Rust FFI delegation
@@ -107,8 +133,20 @@ class CryptoDataManager: ObservableObject {
                         self.isDataCached = result.cached
                         self.errorMessage = nil
                         self.isLoading = false
-                        self.connectionStatus = "Connected"
-                        self.isConnected = true
+                        if result.cached {
+                            let minutes = (result.age_seconds ?? 0) / 60
+                            self.connectionStatus = "Offline - prices from \(minutes) min ago"
+                            self.isConnected = false
+                        } else if let timestamp = result.data_timestamp,
+                                  let fetched = ISO8601DateFormatter().date(from: timestamp),
+                                  let source = result.source {
+                            let time = DateFormatter.localizedString(from: fetched, dateStyle: .none, timeStyle: .short)
+                            self.connectionStatus = "Data as of \(time) via \(source)"
+                            self.isConnected = true
+                        } else {
+                            self.connectionStatus = "Connected"
+                            self.isConnected = true
+                        }
                         print("SUCCESS: Updated \(data.count) cryptocurrencies from Rust")
                     } else {
                         // Handle error with retry logic
@@ -150,6 +188,39 @@ class CryptoDataManager: ObservableObject {
         }
     }
     
+    // Pull-to-refresh: ask the server for a fresh fetch; changed prices arrive through the MQTT callback
+    @MainActor
+    func requestPriceRefresh() async {
+        let sent = await Task.detached(priority: .userInitiated) { request_price_refresh() }.value
+        if !sent {
+            print("requestPriceRefresh: Refresh request not sent, re-reading cached prices")
+            fetchCryptoPrices()
+        }
+    }
+    
+    // Merge changed coins into the current list; fall back to a full fetch if the lists diverged
+    private func applyPriceUpdate(_ update: PriceUpdate) {
+        guard !cryptocurrencies.isEmpty else {
+            fetchCryptoPrices()
+            return
+        }
+        var coins = cryptocurrencies.filter { !update.removed.contains($0.symbol) }
+        for coin in update.updated {
+            if let index = coins.firstIndex(where: { $0.id == coin.id }) {
+                coins[index] = coin
+            } else {
+                coins.append(coin)
+            }
+        }
+        guard coins.count == update.total else {
+            fetchCryptoPrices()
+            return
+        }
+        cryptocurrencies = coins
+        lastUpdated = DateFormatter.localizedString(from: Date(), dateStyle: .none, timeStyle: .medium)
+        print("CryptoDataManager: Applied \(update.updated.count) price changes without refetching")
+    }
+    
     private func setupRealTimeUpdates() {
         print("CryptoDataManager: Setting up real-time MQTT push notifications - NO POLLING")
         
@@ -169,17 +240,19 @@ class CryptoDataManager: ObservableObject {
     private func setupMQTTCallback() {
         print("CryptoDataManager: Registering MQTT callback for real-time updates")
         
-        // Simple callback that triggers data fetch when MQTT receives new data
-        let callback: @convention(c) (UnsafeRawPointer?) -> Void = { context in
+        // The callback owns the payload: copy it, release the Rust buffer, then apply it on the main queue
+        let callback: @convention(c) (UnsafeMutablePointer<UInt8>?, Int) -> Void = { data, len in
+            guard let data = data else { return }
+            let payload = Data(bytes: data, count: len)
+            free_price_update(data, len)
             DispatchQueue.main.async {
-                print("MQTT Callback: Received real-time price update - fetching new data")
-                // Post notification to trigger data fetch
-                NotificationCenter.default.post(name: .mqttPriceUpdate, object: nil)
+                print("MQTT Callback: Received real-time price update (\(len) bytes)")
+                NotificationCenter.default.post(name: .mqttPriceUpdate, object: payload)
             }
         }
         
         // Register the callback with Rust FFI - MUST work for real-time updates
-        register_price_update_callback(callback)
+        register_price_payload_callback(callback)
         print("CryptoDataManager: Real-time MQTT callback registered - NO POLLING!")
         
         // Listen for MQTT notifications
@@ -187,9 +260,14 @@ class CryptoDataManager: ObservableObject {
             forName: .mqttPriceUpdate,
             object: nil,
             queue: .main
-        ) { [weak self] _ in
+        ) { [weak self] notification in
             print("CryptoDataManager: Processing MQTT price update notification")
-            self?.fetchCryptoPrices()
+            guard let payload = notification.object as? Data,
+                  let update = try? JSONDecoder().decode(PriceUpdate.self, from: payload) else {
+                self?.fetchCryptoPrices()
+                return
+            }
+            self?.applyPriceUpdate(update)
         }
         
         print("CryptoDataManager: MQTT callback system registered successfully")
@@ -412,6 +490,9 @@ struct ModernCryptoListView: View {
                 }
             }
         }
+        .refreshable {
+            await cryptoManager.requestPriceRefresh()
+        }
     }
 }
 